@@ -0,0 +1,24 @@
+//! Records the commit the binary is built from, reported by `GET /status/runtime`, and generates
+//! the protobuf messages of the binary wire format from `proto/aggregator.proto`.
+use std::{env, error::Error, process::Command};
+
+fn main() -> Result<(), Box<dyn Error>> {
+	// Builds outside of a checkout, e.g. in a container, pass the commit through `GIT_COMMIT`
+	println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+	println!("cargo:rerun-if-changed=.git/HEAD");
+	println!("cargo:rerun-if-changed=.git/refs");
+	let commit = env::var("GIT_COMMIT").ok().or_else(|| {
+		let output = Command::new("git").args(["rev-parse", "HEAD"]).output().ok()?;
+		if !output.status.success() {
+			return None
+		}
+		Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+	});
+	println!("cargo:rustc-env=GIT_COMMIT={}", commit.unwrap_or_else(|| "unknown".to_string()));
+
+	// The vendored `protoc` spares the builds from installing one
+	println!("cargo:rerun-if-changed=proto/aggregator.proto");
+	env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+	prost_build::compile_protos(&["proto/aggregator.proto"], &["proto"])?;
+	Ok(())
+}
@@ -0,0 +1,231 @@
+//! Targeted backfill of the full history of a single account.
+//!
+//! Instead of re-indexing whole epochs, the signatures of the account are paged through with
+//! `getSignaturesForAddress` and only the referenced transactions are fetched and ingested.
+use serde::Serialize;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::{error::Error, str::FromStr, sync::Arc};
+use tracing::{error, info};
+
+use super::{
+	processor::{parse_transaction_with_meta, ParsedTransaction},
+	retrieval::{find_transaction, get_signatures_for_address, get_transaction},
+};
+use crate::{
+	db::{
+		self,
+		pool::{with_connection, DbPool},
+		writer::DbWriter,
+	},
+	rpc::RpcGateway,
+};
+
+/// The outcome of an account backfill.
+#[derive(Debug, Default, Serialize)]
+pub struct BackfillSummary {
+	/// Signatures returned by the RPC node for the account.
+	pub signatures: usize,
+	/// Signatures skipped because the transaction was already stored.
+	pub already_stored: usize,
+	/// Transactions fetched and handed to the writer.
+	pub ingested: usize,
+	/// Transactions that could not be fetched or parsed.
+	pub failed: usize,
+}
+
+/// The outcome of a transaction re-fetch.
+#[derive(Debug, Serialize)]
+pub struct RefetchSummary {
+	pub signature: String,
+	pub slot: u64,
+	/// Whether a stored copy of the transaction was replaced.
+	pub replaced: bool,
+}
+
+/// Backfills the history of accounts on demand.
+#[derive(Clone)]
+pub struct Backfiller {
+	gateway: Arc<RpcGateway>,
+	pool: DbPool,
+	writer: DbWriter,
+}
+
+impl Backfiller {
+	pub fn new(gateway: Arc<RpcGateway>, pool: DbPool, writer: DbWriter) -> Self {
+		Self { gateway, pool, writer }
+	}
+
+	/// Ingests every transaction of an account known to the RPC node that is not stored yet.
+	///
+	/// The transactions are written per slot in block order. Their exact position within the
+	/// block is unknown, so only their relative order is kept; ingesting the whole block later
+	/// overwrites them with the exact positions.
+	///
+	/// # Arguments
+	///
+	/// * `address` - The account to backfill.
+	///
+	/// # Returns
+	///
+	/// This function returns a `BackfillSummary` of the run.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the signatures cannot be listed, the database cannot be
+	/// read, or the writer has stopped. Individual transactions failing to be fetched or parsed
+	/// are logged and counted instead.
+	pub async fn backfill_account(
+		&self,
+		address: &Pubkey,
+	) -> Result<BackfillSummary, Box<dyn Error + Send + Sync>> {
+		let mut summary = BackfillSummary::default();
+
+		// Page through the history, newest first
+		let mut statuses = Vec::new();
+		let mut before = None;
+		loop {
+			let page = get_signatures_for_address(&self.gateway, address, before).await?;
+			let Some(last) = page.last() else { break };
+			before = Some(Signature::from_str(&last.signature)?);
+			statuses.extend(page);
+		}
+		summary.signatures = statuses.len();
+		info!("Backfilling {} signatures of {}", statuses.len(), address);
+
+		// Oldest first, so that each slot's transactions come out in block order
+		statuses.reverse();
+		let mut pending: Vec<ParsedTransaction> = Vec::new();
+		let mut pending_slot = None;
+		let mut pending_block_time = None;
+		for status in statuses {
+			if pending_slot != Some(status.slot) {
+				self.flush(pending_slot, pending_block_time, &mut pending).await?;
+				pending_slot = Some(status.slot);
+				pending_block_time = status.block_time;
+			}
+
+			let signature = status.signature.clone();
+			let stored = with_connection(&self.pool, move |conn| {
+				db::get_transaction(conn, &signature).map(|stored| stored.is_some())
+			})
+			.await??;
+			if stored {
+				summary.already_stored += 1;
+				continue
+			}
+
+			match self.fetch(&status.signature).await {
+				Ok(parsed) => {
+					summary.ingested += 1;
+					pending.push(parsed);
+				},
+				Err(err) => {
+					summary.failed += 1;
+					error!("Failed to backfill transaction {}: {:?}", status.signature, err);
+				},
+			}
+		}
+		self.flush(pending_slot, pending_block_time, &mut pending).await?;
+
+		Ok(summary)
+	}
+
+	/// Fetches a single transaction from the RPC node, parses it again and replaces the stored
+	/// copy.
+	///
+	/// This repairs records written by an older or buggy parser. Stored transactions keep their
+	/// position within the block.
+	///
+	/// # Arguments
+	///
+	/// * `signature` - The signature of the transaction.
+	///
+	/// # Returns
+	///
+	/// This function returns a `RefetchSummary` once the transaction has been written.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the transaction cannot be fetched or parsed, the database
+	/// cannot be read, or the writer has stopped.
+	pub async fn refetch_transaction(
+		&self,
+		signature: &Signature,
+	) -> Result<RefetchSummary, Box<dyn Error + Send + Sync>> {
+		let confirmed = get_transaction(&self.gateway, signature).await?;
+		let parsed = parse_transaction_with_meta(&confirmed.transaction, confirmed.block_time)?;
+		let signature = parsed.signature.clone();
+		let replaced = with_connection(&self.pool, move |conn| {
+			db::get_transaction(conn, &signature).map(|stored| stored.is_some())
+		})
+		.await??;
+
+		let summary =
+			RefetchSummary { signature: parsed.signature.clone(), slot: confirmed.slot, replaced };
+		self.writer
+			.replace_transaction(confirmed.slot, confirmed.block_time, parsed)
+			.await?;
+		self.writer.checkpoint().await?;
+		Ok(summary)
+	}
+
+	/// Fetches a transaction missing from the database from the RPC node and stores it, so lookups
+	/// of history not backfilled yet are answered.
+	///
+	/// Like the backfilled ones, the transaction is stored first within its block, its exact
+	/// position being unknown; ingesting the whole block later overwrites it with the exact
+	/// position. The storage policy and program filters of the writer still apply.
+	///
+	/// # Arguments
+	///
+	/// * `signature` - The signature of the transaction.
+	///
+	/// # Returns
+	///
+	/// This function returns `false` if the RPC node does not know the transaction, `true` once it
+	/// has been written.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the transaction cannot be fetched or parsed, or the writer
+	/// has stopped.
+	pub async fn fetch_missing_transaction(
+		&self,
+		signature: &Signature,
+	) -> Result<bool, Box<dyn Error + Send + Sync>> {
+		let Some(confirmed) = find_transaction(&self.gateway, signature).await? else {
+			return Ok(false)
+		};
+		let parsed = parse_transaction_with_meta(&confirmed.transaction, confirmed.block_time)?;
+		self.writer
+			.write_transactions(confirmed.slot, confirmed.block_time, vec![parsed])
+			.await?;
+		self.writer.checkpoint().await?;
+		Ok(true)
+	}
+
+	/// Fetches and parses a single transaction.
+	async fn fetch(
+		&self,
+		signature: &str,
+	) -> Result<ParsedTransaction, Box<dyn Error + Send + Sync>> {
+		let signature = Signature::from_str(signature)?;
+		let confirmed = get_transaction(&self.gateway, &signature).await?;
+		parse_transaction_with_meta(&confirmed.transaction, confirmed.block_time)
+	}
+
+	/// Hands the transactions collected for a slot to the writer.
+	async fn flush(
+		&self,
+		slot: Option<u64>,
+		block_time: Option<i64>,
+		pending: &mut Vec<ParsedTransaction>,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		match slot {
+			Some(slot) if !pending.is_empty() =>
+				self.writer.write_transactions(slot, block_time, std::mem::take(pending)).await,
+			_ => Ok(()),
+		}
+	}
+}
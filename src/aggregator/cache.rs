@@ -0,0 +1,188 @@
+//! On-disk cache of the raw blocks fetched from the RPC node.
+//!
+//! Re-running the ingestion over a slot range, after a parser change or a crash, reads the blocks
+//! from the cache instead of downloading them again. Blocks are stored as gzipped JSON, one file
+//! per slot, and the oldest files are evicted once the cache outgrows its size limit or their TTL.
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use solana_transaction_status::UiConfirmedBlock;
+use std::{
+	collections::{BTreeSet, HashMap},
+	error::Error,
+	fs,
+	io::Write,
+	path::PathBuf,
+	sync::Mutex,
+	time::{Duration, SystemTime},
+};
+use tracing::warn;
+
+use crate::{server::stats::parse_window, types::BlockCacheConfig};
+
+const EXTENSION: &str = "json.gz";
+
+/// The files of the cache, by slot and by age.
+#[derive(Default)]
+struct CacheIndex {
+	/// Size and modification time of the file of each slot.
+	entries: HashMap<u64, (u64, SystemTime)>,
+	/// The cached slots, oldest file first.
+	by_age: BTreeSet<(SystemTime, u64)>,
+	total_size: u64,
+}
+
+impl CacheIndex {
+	fn insert(&mut self, slot: u64, size: u64, modified: SystemTime) {
+		self.remove(slot);
+		self.entries.insert(slot, (size, modified));
+		self.by_age.insert((modified, slot));
+		self.total_size += size;
+	}
+
+	fn remove(&mut self, slot: u64) {
+		if let Some((size, modified)) = self.entries.remove(&slot) {
+			self.by_age.remove(&(modified, slot));
+			self.total_size -= size;
+		}
+	}
+}
+
+/// A size- and age-bounded directory of raw blocks.
+pub struct BlockCache {
+	path: PathBuf,
+	max_size: u64,
+	ttl: Option<Duration>,
+	index: Mutex<CacheIndex>,
+}
+
+impl BlockCache {
+	/// Opens the cache directory, creating it if needed, and indexes the blocks it holds.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the TTL is invalid or the directory cannot be read.
+	pub fn open(config: &BlockCacheConfig) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let ttl = config
+			.ttl
+			.as_deref()
+			.map(parse_window)
+			.transpose()?
+			.map(|ttl| Duration::from_secs(ttl.max(0) as u64));
+		let path = PathBuf::from(&config.path);
+		fs::create_dir_all(&path)?;
+
+		let mut index = CacheIndex::default();
+		for entry in fs::read_dir(&path)? {
+			let entry = entry?;
+			let name = entry.file_name();
+			let Some(slot) = name
+				.to_str()
+				.and_then(|name| name.strip_suffix(EXTENSION))
+				.and_then(|name| name.strip_suffix('.'))
+				.and_then(|slot| slot.parse().ok())
+			else {
+				continue
+			};
+			let metadata = entry.metadata()?;
+			index.insert(slot, metadata.len(), metadata.modified()?);
+		}
+
+		let cache = Self {
+			path,
+			max_size: config.max_size_mb.saturating_mul(1024 * 1024),
+			ttl,
+			index: Mutex::new(index),
+		};
+		cache.evict(SystemTime::now());
+		Ok(cache)
+	}
+
+	/// Returns the cached block of a slot, if any.
+	///
+	/// Unreadable or expired files are removed and reported as a miss.
+	pub fn get(&self, slot: u64) -> Option<UiConfirmedBlock> {
+		let modified = self.index.lock().expect("block cache lock poisoned").entries.get(&slot)?.1;
+		if self.is_expired(modified, SystemTime::now()) {
+			self.remove(slot);
+			return None
+		}
+		match fs::File::open(self.file(slot))
+			.map_err(Box::<dyn Error + Send + Sync>::from)
+			.and_then(|file| Ok(serde_json::from_reader(GzDecoder::new(file))?))
+		{
+			Ok(block) => Some(block),
+			Err(err) => {
+				warn!("Failed to read cached block at slot {}: {:?}", slot, err);
+				self.remove(slot);
+				None
+			},
+		}
+	}
+
+	/// Stores the block of a slot, evicting the oldest blocks beyond the size limit.
+	///
+	/// Failures are logged, the cache being an optimization only.
+	pub fn put(&self, slot: u64, block: &UiConfirmedBlock) {
+		if let Err(err) = self.write(slot, block) {
+			warn!("Failed to cache block at slot {}: {:?}", slot, err);
+		}
+		self.evict(SystemTime::now());
+	}
+
+	fn write(
+		&self,
+		slot: u64,
+		block: &UiConfirmedBlock,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+		serde_json::to_writer(&mut encoder, block)?;
+		let body = encoder.finish()?;
+
+		// Written aside and renamed, so a crash never leaves a truncated block behind
+		let file = self.file(slot);
+		let partial = file.with_extension("partial");
+		fs::File::create(&partial)?.write_all(&body)?;
+		fs::rename(&partial, &file)?;
+
+		let modified = fs::metadata(&file)?.modified()?;
+		self.index.lock().expect("block cache lock poisoned").insert(
+			slot,
+			body.len() as u64,
+			modified,
+		);
+		Ok(())
+	}
+
+	/// Removes the expired blocks, then the oldest ones until the cache fits its size limit.
+	fn evict(&self, now: SystemTime) {
+		let mut evicted = Vec::new();
+		{
+			let mut index = self.index.lock().expect("block cache lock poisoned");
+			while let Some(&(modified, slot)) = index.by_age.first() {
+				if index.total_size <= self.max_size && !self.is_expired(modified, now) {
+					break
+				}
+				index.remove(slot);
+				evicted.push(slot);
+			}
+		}
+		for slot in evicted {
+			if let Err(err) = fs::remove_file(self.file(slot)) {
+				warn!("Failed to evict cached block at slot {}: {:?}", slot, err);
+			}
+		}
+	}
+
+	fn remove(&self, slot: u64) {
+		self.index.lock().expect("block cache lock poisoned").remove(slot);
+		let _ = fs::remove_file(self.file(slot));
+	}
+
+	fn is_expired(&self, modified: SystemTime, now: SystemTime) -> bool {
+		self.ttl
+			.is_some_and(|ttl| now.duration_since(modified).unwrap_or_default() > ttl)
+	}
+
+	fn file(&self, slot: u64) -> PathBuf {
+		self.path.join(format!("{:012}.{}", slot, EXTENSION))
+	}
+}
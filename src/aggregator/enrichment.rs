@@ -0,0 +1,125 @@
+//! Enrichment of the discovered accounts with their on-chain metadata.
+//!
+//! The accounts stored without metadata are looked up in batches with `getMultipleAccounts`, so
+//! the account endpoints can tell wallets, token accounts, mints and programs apart. Each account
+//! is looked up once; accounts that do not exist are recorded as closed.
+use solana_program::pubkey::Pubkey;
+use std::{collections::HashMap, error::Error, str::FromStr, sync::Arc, time::Duration};
+use tracing::{error, info};
+
+use super::retrieval::{get_account_metadata, AccountMetadata};
+use crate::{
+	db::{
+		account_info::{self, AccountInfoRecord, AccountKind},
+		pool::{with_connection, DbPool},
+	},
+	rpc::RpcGateway,
+};
+
+/// Maximum number of accounts `getMultipleAccounts` accepts in a single call.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// Fetches and stores the metadata of a batch of accounts stored without it.
+///
+/// # Arguments
+///
+/// * `gateway` - The gateway to the RPC node.
+/// * `pool` - The `DbPool` the accounts are read from and their metadata written to.
+/// * `batch_size` - The number of accounts looked up, capped at `MAX_BATCH_SIZE`.
+///
+/// # Returns
+///
+/// This function returns the number of accounts whose metadata was stored.
+///
+/// # Errors
+///
+/// This function returns an error if the database cannot be read or written, or if the RPC call
+/// fails.
+pub async fn enrich_accounts(
+	gateway: &RpcGateway,
+	pool: &DbPool,
+	batch_size: usize,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+	let limit = batch_size.clamp(1, MAX_BATCH_SIZE);
+	let accounts =
+		with_connection(pool, move |conn| account_info::get_accounts_without_info(conn, limit))
+			.await??;
+	if accounts.is_empty() {
+		return Ok(0)
+	}
+
+	// Malformed keys cannot exist on-chain, they are recorded as closed without a lookup
+	let pubkeys: Vec<Pubkey> =
+		accounts.iter().filter_map(|account| Pubkey::from_str(account).ok()).collect();
+	let (slot, metadata) = if pubkeys.is_empty() {
+		(0, Vec::new())
+	} else {
+		get_account_metadata(gateway, &pubkeys).await?
+	};
+	let mut metadata: HashMap<String, AccountMetadata> = pubkeys
+		.iter()
+		.map(Pubkey::to_string)
+		.zip(metadata)
+		.filter_map(|(pubkey, metadata)| Some((pubkey, metadata?)))
+		.collect();
+
+	let fetched_at = chrono::Utc::now().timestamp();
+	let records: Vec<(String, AccountInfoRecord)> = accounts
+		.into_iter()
+		.map(|account| {
+			let (owner, executable, data_length) = match metadata.remove(&account) {
+				Some(metadata) => (Some(metadata.owner), metadata.executable, metadata.space),
+				None => (None, false, None),
+			};
+			let record = AccountInfoRecord {
+				kind: AccountKind::classify(owner.as_deref(), executable, data_length),
+				owner,
+				executable,
+				data_length,
+				fetched_slot: slot,
+				fetched_at,
+			};
+			(account, record)
+		})
+		.collect();
+
+	let stored = records.len();
+	with_connection(pool, move |conn| -> Result<(), Box<dyn Error + Send + Sync>> {
+		let tx = conn.unchecked_transaction()?;
+		for (account, record) in &records {
+			account_info::insert_or_update_account_info(&tx, account, record)?;
+		}
+		tx.commit()?;
+		Ok(())
+	})
+	.await??;
+	Ok(stored)
+}
+
+/// Keeps enriching the discovered accounts, pausing for `interval` once every account is done.
+///
+/// # Arguments
+///
+/// * `gateway` - The gateway to the RPC node.
+/// * `pool` - The `DbPool` the accounts are read from and their metadata written to.
+/// * `batch_size` - The number of accounts looked up per call.
+/// * `interval` - The pause between two passes once no account is left, or after a failure.
+pub async fn run_account_enrichment(
+	gateway: Arc<RpcGateway>,
+	pool: DbPool,
+	batch_size: usize,
+	interval: Duration,
+) {
+	loop {
+		match enrich_accounts(&gateway, &pool, batch_size).await {
+			Ok(stored) if stored >= batch_size.clamp(1, MAX_BATCH_SIZE) => {
+				info!("Stored the on-chain metadata of {} accounts", stored);
+				continue
+			},
+			Ok(0) => {},
+			Ok(stored) => info!("Stored the on-chain metadata of {} accounts", stored),
+			Err(err) => error!("Failed to enrich accounts: {:?}", err),
+		}
+		tokio::time::sleep(interval).await;
+	}
+}
@@ -0,0 +1,161 @@
+//! Maintenance of the epoch catalog (`db::epochs`).
+//!
+//! On startup, the epoch schedule of the cluster is fetched once, and the current epoch of the
+//! node is catalogued along with the epochs of the stored blocks. The catalog then follows the
+//! `BlockIngested` events of the writer: a block extends the times of its epoch, and the block of
+//! an epoch not catalogued yet adds it, with the slots of the schedule. When events are missed, the
+//! epochs since the last block seen are catalogued again from the stored blocks.
+use rusqlite::Connection;
+use solana_sdk::epoch_schedule::EpochSchedule;
+use std::{error::Error, sync::Arc, time::Duration};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::{error, info, warn};
+
+use super::retrieval::{get_epoch_info, get_epoch_schedule};
+use crate::{
+	db::{
+		blocks, epochs,
+		pool::{with_connection, DbPool},
+	},
+	events::Event,
+	rpc::RpcGateway,
+};
+
+/// Delay before fetching the epoch schedule again after a failure.
+const SCHEDULE_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Catalogues the epochs spanned by the stored blocks, with their times.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `schedule` - The epoch schedule of the cluster.
+/// * `since_slot` - Only the epochs from the one holding this slot on are catalogued.
+///
+/// # Returns
+///
+/// This function returns the number of epochs catalogued.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn catalog_stored_epochs(
+	conn: &Connection,
+	schedule: &EpochSchedule,
+	since_slot: u64,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+	let Some((first_slot, last_slot)) = blocks::get_stored_slot_range(conn)? else { return Ok(0) };
+	let first_epoch = schedule.get_epoch(first_slot.max(since_slot));
+	let last_epoch = schedule.get_epoch(last_slot);
+	let tx = conn.unchecked_transaction()?;
+	for epoch in first_epoch..=last_epoch {
+		let (first_slot, last_slot) =
+			(schedule.get_first_slot_in_epoch(epoch), schedule.get_last_slot_in_epoch(epoch));
+		epochs::insert_or_update_epoch(&tx, epoch, first_slot, last_slot)?;
+	}
+	tx.commit()?;
+	Ok((last_epoch + 1).saturating_sub(first_epoch))
+}
+
+/// Records a stored block in the catalog, cataloguing its epoch if it is new.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn catalog_block(
+	conn: &Connection,
+	schedule: &EpochSchedule,
+	slot: u64,
+	block_time: Option<i64>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	if !epochs::record_block_time(conn, slot, block_time)? {
+		let epoch = schedule.get_epoch(slot);
+		let (first_slot, last_slot) =
+			(schedule.get_first_slot_in_epoch(epoch), schedule.get_last_slot_in_epoch(epoch));
+		epochs::insert_or_update_epoch(conn, epoch, first_slot, last_slot)?;
+	}
+	Ok(())
+}
+
+/// Catalogues the current epoch of the node and the epochs of the stored blocks, then keeps the
+/// catalog up to date with the blocks ingested, until the event bus is closed.
+///
+/// # Arguments
+///
+/// * `gateway` - The gateway to the RPC node.
+/// * `pool` - The `DbPool` the catalog is written to.
+/// * `events` - A subscription to the event bus, taken before the ingestion starts.
+pub async fn run_epoch_catalog(
+	gateway: Arc<RpcGateway>,
+	pool: DbPool,
+	mut events: Receiver<Event>,
+) {
+	let schedule = loop {
+		match get_epoch_schedule(&gateway).await {
+			Ok(schedule) => break schedule,
+			Err(err) => {
+				error!("Failed to fetch the epoch schedule: {:?}", err);
+				tokio::time::sleep(SCHEDULE_RETRY_DELAY).await;
+			},
+		}
+	};
+	match get_epoch_info(&gateway).await {
+		Ok(epoch_info) => {
+			let first_slot = epoch_info.absolute_slot - epoch_info.slot_index;
+			let last_slot = first_slot + epoch_info.slots_in_epoch - 1;
+			let result = update_catalog(&pool, move |conn| {
+				epochs::insert_or_update_epoch(conn, epoch_info.epoch, first_slot, last_slot)
+			})
+			.await;
+			if let Err(err) = result {
+				error!("Failed to catalog the current epoch: {:?}", err);
+			}
+		},
+		Err(err) => warn!("Failed to fetch the current epoch: {:?}", err),
+	}
+
+	// The epochs from this slot on are catalogued again from the stored blocks, if set
+	let mut rebuild_from = Some(0);
+	let mut last_seen_slot = None;
+	loop {
+		if let Some(since_slot) = rebuild_from.take() {
+			let schedule = schedule.clone();
+			let result = update_catalog(&pool, move |conn| {
+				catalog_stored_epochs(conn, &schedule, since_slot)
+			})
+			.await;
+			match result {
+				Ok(count) => info!("Catalogued {} epochs from the stored blocks", count),
+				Err(err) => error!("Failed to catalog the epochs of the stored blocks: {:?}", err),
+			}
+		}
+		match events.recv().await {
+			Ok(Event::BlockIngested { slot, block_time, .. }) => {
+				last_seen_slot = Some(slot);
+				let schedule = schedule.clone();
+				let result = update_catalog(&pool, move |conn| {
+					catalog_block(conn, &schedule, slot, block_time)
+				})
+				.await;
+				if let Err(err) = result {
+					error!("Failed to catalog the block of slot {}: {:?}", slot, err);
+				}
+			},
+			Ok(_) => {},
+			Err(RecvError::Lagged(missed)) => {
+				warn!("Epoch catalog lagged, {} events skipped", missed);
+				rebuild_from = Some(last_seen_slot.unwrap_or_default());
+			},
+			Err(RecvError::Closed) => break,
+		}
+	}
+}
+
+/// Runs an update of the catalog on a pooled connection.
+async fn update_catalog<T, F>(pool: &DbPool, update: F) -> Result<T, Box<dyn Error + Send + Sync>>
+where
+	F: FnOnce(&Connection) -> Result<T, Box<dyn Error + Send + Sync>> + Send + 'static,
+	T: Send + 'static,
+{
+	with_connection(pool, update).await?
+}
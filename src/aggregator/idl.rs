@@ -0,0 +1,202 @@
+//! Decoding of program accounts with an Anchor IDL.
+//!
+//! An Anchor account starts with an 8 byte discriminator naming its type, followed by its fields
+//! serialized with Borsh. The discriminator is listed in the IDL by Anchor 0.30 and later, and is
+//! otherwise the first 8 bytes of `sha256("account:<Name>")`. Both the older and newer IDL formats
+//! are read. The decoded fields are JSON: integers wider than 64 bits are decimal strings, public
+//! keys base58 strings and byte arrays lists of numbers. Accounts of an unknown type, or using a
+//! type the decoder does not support, are left undecoded.
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use solana_program::pubkey::Pubkey;
+use std::{collections::HashMap, error::Error, fs};
+
+/// Size of the discriminator of an Anchor account.
+const DISCRIMINATOR_SIZE: usize = 8;
+
+/// The account types of an IDL, by discriminator.
+#[derive(Debug, Clone)]
+pub struct Idl {
+	accounts: Vec<([u8; DISCRIMINATOR_SIZE], String)>,
+	/// The definitions of the named types, account types included.
+	types: HashMap<String, Value>,
+}
+
+impl Idl {
+	/// Reads an IDL from a JSON file.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the file cannot be read or is not an IDL.
+	pub fn load(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let idl: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+		Self::parse(&idl).ok_or_else(|| format!("{} is not an Anchor IDL", path).into())
+	}
+
+	/// Reads an IDL from its JSON, `None` if it lists no accounts.
+	pub fn parse(idl: &Value) -> Option<Self> {
+		let mut types: HashMap<String, Value> = idl["types"]
+			.as_array()
+			.into_iter()
+			.flatten()
+			.filter_map(|definition| {
+				Some((definition["name"].as_str()?.to_string(), definition["type"].clone()))
+			})
+			.collect();
+		let mut accounts = Vec::new();
+		for account in idl["accounts"].as_array()? {
+			let name = account["name"].as_str()?.to_string();
+			// Before Anchor 0.30, the account lists its own type
+			if account["type"].is_object() {
+				types.insert(name.clone(), account["type"].clone());
+			}
+			let discriminator = match account["discriminator"].as_array() {
+				Some(bytes) => bytes
+					.iter()
+					.map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+					.collect::<Option<Vec<u8>>>()?
+					.try_into()
+					.ok()?,
+				None => account_discriminator(&name),
+			};
+			accounts.push((discriminator, name));
+		}
+		Some(Self { accounts, types })
+	}
+
+	/// Decodes the data of an account, returning its type and fields.
+	///
+	/// # Returns
+	///
+	/// This function returns `None` if the type of the account is not in the IDL, if the data does
+	/// not match it, or if the type uses a type the decoder does not support.
+	pub fn decode_account(&self, data: &[u8]) -> Option<(String, Value)> {
+		let discriminator = data.get(..DISCRIMINATOR_SIZE)?;
+		let (_, name) = self.accounts.iter().find(|(known, _)| known == discriminator)?;
+		let mut reader = Reader(&data[DISCRIMINATOR_SIZE..]);
+		let fields = self.decode_defined(name, &mut reader)?;
+		Some((name.clone(), fields))
+	}
+
+	/// Decodes a value of a named struct or enum.
+	fn decode_defined(&self, name: &str, reader: &mut Reader) -> Option<Value> {
+		let definition = self.types.get(name)?;
+		match definition["kind"].as_str()? {
+			"struct" => self.decode_fields(&definition["fields"], reader),
+			"enum" => {
+				let variant =
+					definition["variants"].as_array()?.get(reader.take(1)?[0] as usize)?;
+				let name = variant["name"].as_str()?.to_string();
+				match variant.get("fields") {
+					None | Some(Value::Null) => Some(Value::String(name)),
+					Some(fields) => {
+						let fields = self.decode_fields(fields, reader)?;
+						Some(Value::Object(Map::from_iter([(name, fields)])))
+					},
+				}
+			},
+			_ => None,
+		}
+	}
+
+	/// Decodes named fields into an object, or unnamed ones into an array.
+	fn decode_fields(&self, fields: &Value, reader: &mut Reader) -> Option<Value> {
+		let fields = fields.as_array()?;
+		if fields.iter().all(|field| field["name"].is_string()) {
+			let mut object = Map::new();
+			for field in fields {
+				let value = self.decode(&field["type"], reader)?;
+				object.insert(field["name"].as_str()?.to_string(), value);
+			}
+			Some(Value::Object(object))
+		} else {
+			let values = fields.iter().map(|field| self.decode(field, reader));
+			Some(Value::Array(values.collect::<Option<_>>()?))
+		}
+	}
+
+	/// Decodes a value of an IDL type.
+	fn decode(&self, idl_type: &Value, reader: &mut Reader) -> Option<Value> {
+		if let Some(name) = idl_type.as_str() {
+			return decode_primitive(name, reader)
+		}
+		if let Some(inner) = idl_type.get("option") {
+			return match reader.take(1)?[0] {
+				0 => Some(Value::Null),
+				_ => self.decode(inner, reader),
+			};
+		}
+		if let Some(inner) = idl_type.get("vec") {
+			let length = u32::from_le_bytes(reader.take(4)?.try_into().ok()?) as usize;
+			// Each element takes a byte at least, which bounds a corrupted length
+			if length > reader.0.len() {
+				return None
+			}
+			return (0..length).map(|_| self.decode(inner, reader)).collect()
+		}
+		if let Some([inner, length]) =
+			idl_type.get("array").and_then(Value::as_array).map(Vec::as_slice)
+		{
+			return (0..length.as_u64()?).map(|_| self.decode(inner, reader)).collect()
+		}
+		match idl_type.get("defined")? {
+			Value::String(name) => self.decode_defined(name, reader),
+			defined => self.decode_defined(defined["name"].as_str()?, reader),
+		}
+	}
+}
+
+/// Returns the discriminator Anchor derives from the name of an account type.
+pub fn account_discriminator(name: &str) -> [u8; DISCRIMINATOR_SIZE] {
+	let hash = Sha256::digest(format!("account:{}", name).as_bytes());
+	hash[..DISCRIMINATOR_SIZE].try_into().expect("a SHA-256 hash is 32 bytes")
+}
+
+/// Decodes a value of a primitive IDL type.
+fn decode_primitive(name: &str, reader: &mut Reader) -> Option<Value> {
+	macro_rules! integer {
+		($type:ty) => {
+			<$type>::from_le_bytes(reader.take(std::mem::size_of::<$type>())?.try_into().ok()?)
+		};
+	}
+	Some(match name {
+		"bool" => Value::Bool(reader.take(1)?[0] != 0),
+		"u8" => integer!(u8).into(),
+		"i8" => integer!(i8).into(),
+		"u16" => integer!(u16).into(),
+		"i16" => integer!(i16).into(),
+		"u32" => integer!(u32).into(),
+		"i32" => integer!(i32).into(),
+		"u64" => integer!(u64).into(),
+		"i64" => integer!(i64).into(),
+		"u128" => integer!(u128).to_string().into(),
+		"i128" => integer!(i128).to_string().into(),
+		"f32" => integer!(f32).into(),
+		"f64" => integer!(f64).into(),
+		"pubkey" | "publicKey" =>
+			Pubkey::new_from_array(reader.take(32)?.try_into().ok()?).to_string().into(),
+		"string" => {
+			let length = integer!(u32) as usize;
+			String::from_utf8(reader.take(length)?.to_vec()).ok()?.into()
+		},
+		"bytes" => {
+			let length = integer!(u32) as usize;
+			reader.take(length)?.to_vec().into()
+		},
+		_ => return None,
+	})
+}
+
+/// Reads the bytes of a Borsh serialized value in order.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+		if length > self.0.len() {
+			return None
+		}
+		let (taken, rest) = self.0.split_at(length);
+		self.0 = rest;
+		Some(taken)
+	}
+}
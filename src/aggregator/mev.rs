@@ -0,0 +1,52 @@
+//! Analysis passes flagging MEV patterns in the ordered swaps of a block.
+use crate::db::{SandwichRecord, SwapRecord};
+
+/// Flags probable sandwiches among the swaps of a block.
+///
+/// A sandwich is flagged when an attacker swaps on a pool (front-run), a different trader then
+/// swaps in the same direction on the same pool (victim), and the attacker swaps back in the
+/// opposite direction on that pool (back-run), all within the block. Each front-run is paired with
+/// the attacker's first matching back-run.
+///
+/// # Arguments
+///
+/// * `swaps` - The swaps of a single block, ordered by their position in the block.
+///
+/// # Returns
+///
+/// This function returns a `Vec<SandwichRecord>` with one record per flagged victim swap.
+pub fn detect_sandwiches(swaps: &[SwapRecord]) -> Vec<SandwichRecord> {
+	let mut sandwiches = Vec::new();
+
+	for (front_index, front) in swaps.iter().enumerate() {
+		let Some(back_offset) = swaps[front_index + 1..].iter().position(|back| {
+			back.trader == front.trader &&
+				back.pool == front.pool &&
+				back.mint_in == front.mint_out &&
+				back.mint_out == front.mint_in
+		}) else {
+			continue
+		};
+		let back = &swaps[front_index + 1 + back_offset];
+
+		for victim in &swaps[front_index + 1..front_index + 1 + back_offset] {
+			if victim.trader != front.trader &&
+				victim.pool == front.pool &&
+				victim.mint_in == front.mint_in &&
+				victim.mint_out == front.mint_out
+			{
+				sandwiches.push(SandwichRecord {
+					block_height: front.block_height,
+					attacker: front.trader.clone(),
+					victim: victim.trader.clone(),
+					pool: front.pool.clone(),
+					front_transaction_id: front.transaction_id.clone(),
+					victim_transaction_id: victim.transaction_id.clone(),
+					back_transaction_id: back.transaction_id.clone(),
+				});
+			}
+		}
+	}
+
+	sandwiches
+}
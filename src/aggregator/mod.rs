@@ -1,34 +1,98 @@
 //! Module for fetching and processing block data from Solana
-use crate::db::{
-	insert_or_update_account, insert_or_update_transaction, AccountRecord, TransactionRecord,
+use crate::{
+	archive::Archiver,
+	db::{
+		account_has_transactions, blocks, delete_transaction_details,
+		filters::ProgramFilters,
+		get_transaction_index, insert_or_update_account_transaction,
+		insert_or_update_balance_change, insert_or_update_sandwich, insert_or_update_swap,
+		insert_or_update_transaction, insert_or_update_transaction_error,
+		insert_or_update_transfer,
+		instructions::insert_or_update_program_instruction,
+		is_transaction_stored, merge_account,
+		pool::{self, DbPool},
+		recent_writes::RecentWrites,
+		rollups::{self, DailyStats},
+		submissions, sync_state,
+		writer::DbWriter,
+		AccountTransactionRecord, BalanceChangeRecord, SwapRecord, TransactionErrorRecord,
+		TransactionRecord, TransferRecord,
+	},
 };
-use log::{error, info};
+use tracing::{error, info, info_span, warn, Instrument};
 
 use rusqlite::Connection;
 
-use solana_client::rpc_client::RpcClient;
-use solana_transaction_status::UiConfirmedBlock;
-use std::{error::Error, sync::Arc};
+use std::{
+	collections::{BTreeMap, HashSet},
+	error::Error,
+	ops::RangeInclusive,
+	sync::Arc,
+	time::{Duration, Instant},
+};
 
-use crate::types::Config;
-use tokio::sync::Mutex;
+use crate::{
+	pubsub::pubsub_url,
+	rpc::RpcGateway,
+	shutdown::Shutdown,
+	types::{Config, EpochInfo, StartFrom, StoragePolicy},
+};
+use solana_transaction_status::UiConfirmedBlock;
 
+pub mod backfill;
+pub mod cache;
+pub mod enrichment;
+pub mod epochs;
+pub mod idl;
+pub mod mev;
 pub mod processor;
+pub mod reorgs;
 pub mod retrieval;
+pub mod retry;
+pub mod snapshots;
+pub mod subscription;
+pub mod tagging;
 
-use processor::parse_block;
-use retrieval::{get_block, get_epoch_info};
+use cache::BlockCache;
+use processor::{
+	discover_accounts, get_account_keys, get_block_record, get_invoked_programs, parse_block,
+	redact_raw_transaction, ParsedTransaction,
+};
+use reorgs::ChainTip;
+use retrieval::{
+	get_block, get_blocks_batch, get_epoch_info, get_first_available_slot, get_tip_slot,
+	is_skipped_slot, BlockResult,
+};
 
-/// Fetches and processes blocks for the current epoch.
+/// Fetches and processes blocks up to the end of the current epoch, or indefinitely in continuous
+/// mode.
+///
+/// This function retrieves the current epoch info, resolves the start slot from the `start_from`
+/// strategy of the configuration, clamped to the first slot the RPC node still has (the skipped
+/// range is recorded as unavailable), and iterates through each slot up to the end of the epoch,
+/// or the tip if earlier, to fetch and parse the block data. The progress is recorded in
+/// `sync_state` so the next run can resume from it. The transactions within each block are
+/// processed and handed to the database writer. When archival is configured, the raw block is also
+/// written to object storage; archival failures are logged and do not stop the ingestion.
+///
+/// The tip is the latest slot at the `commitment` of the configuration, which is also the
+/// commitment the epoch information and the blocks are read at.
 ///
-/// This function retrieves the current epoch info, calculates the start and end slots for the
-/// epoch, and iterates through each slot to fetch and parse the block data. The transactions within
-/// each block are processed and stored in the SQLite database.
+/// When `continuous` is set, the ingestion instead catches up to the tip, then polls it every
+/// `poll_interval_ms` and ingests the blocks produced since the previous poll, or receives them
+/// through a block subscription when `block_subscription` is set, so the function only returns on
+/// error or shutdown.
+///
+/// Once the shutdown is requested, the ingestion stops after the block it is writing: the progress
+/// is recorded up to that block, and the function returns once the queued blocks are committed.
 ///
 /// # Arguments
-/// * `client` - A shared reference to the `RpcClient` for communicating with the Solana blockchain.
-/// * `conn` - A shared, thread-safe reference to the SQLite database connection.
+/// * `gateway` - A shared reference to the `RpcGateway` for communicating with the Solana
+///   blockchain.
+/// * `pool` - The `DbPool` of the database, used to find the last processed slot.
+/// * `writer` - The handle of the database writer actor.
 /// * `config` - Configuration parameters for the block aggregation process.
+/// * `shutdown` - The shutdown of the service, which stops the ingestion at a block boundary.
 ///
 /// # Returns
 /// * `Ok(())` on success.
@@ -36,103 +100,746 @@ use retrieval::{get_block, get_epoch_info};
 ///
 /// # Errors
 /// This function returns an error if:
-/// - The epoch information cannot be fetched.
+/// - The epoch information or the tip cannot be fetched.
+/// - The archive configuration is invalid.
 /// - A block cannot be fetched after the specified number of retry attempts.
 /// - The block data cannot be parsed.
-/// - A transaction or account record cannot be inserted or updated in the SQLite database.
+/// - The database writer has stopped.
 pub async fn aggregate_blocks(
-	client: Arc<RpcClient>,
-	conn: Arc<Mutex<Connection>>,
+	gateway: Arc<RpcGateway>,
+	pool: DbPool,
+	writer: DbWriter,
 	config: Config,
+	shutdown: Shutdown,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
 	// Fetch the current epoch info
-	let epoch_info = get_epoch_info(&client)?;
+	let epoch_info = get_epoch_info(&gateway).await?;
 	info!("Epoch Info: {:?}", epoch_info);
 
-	// Calculate the start and end slots for the current epoch
-	let start_slot = epoch_info.absolute_slot - epoch_info.slot_index;
-	let end_slot = start_slot + epoch_info.slots_in_epoch;
+	let archiver = config.archive.clone().map(Archiver::new).transpose()?;
+	let cache = config.block_cache.as_ref().map(BlockCache::open).transpose()?;
+	let ingestion = Ingestion {
+		gateway: &gateway,
+		writer: &writer,
+		archiver: archiver.as_ref(),
+		cache: cache.as_ref(),
+		shutdown: Some(&shutdown),
+		chain: ChainTip::default(),
+	};
+
+	let last_slot = match config.start_from {
+		StartFrom::Checkpoint =>
+			pool::with_connection(&pool, sync_state::get_last_processed_slot).await??,
+		_ => None,
+	};
+	let mut start_slot = start_slot(config.start_from, &epoch_info, last_slot);
+	// Slots past the tip are not produced yet, and must not be recorded as processed
+	let tip_slot = get_tip_slot(&gateway).await?;
+	let end_slot = if config.continuous {
+		tip_slot
+	} else {
+		(epoch_info.absolute_slot - epoch_info.slot_index + epoch_info.slots_in_epoch).min(tip_slot)
+	};
+
+	// Skip the slots the node has already purged instead of burning retries on them
+	match get_first_available_slot(&gateway).await {
+		Ok(first_available) if first_available > start_slot => {
+			let unavailable_end = first_available.min(end_slot + 1) - 1;
+			warn!(
+				first_slot = start_slot,
+				last_slot = unavailable_end,
+				first_available,
+				"Slots are no longer available on the RPC node, skipping them"
+			);
+			writer
+				.mark_unavailable(start_slot, unavailable_end, "Purged from the RPC node ledger")
+				.await?;
+			start_slot = first_available;
+		},
+		Ok(_) => {},
+		Err(err) => warn!("Failed to fetch the first available slot: {:?}", err),
+	}
 
 	info!("Fetching blocks from slot {} to {}", start_slot, end_slot);
+	ingestion.ingest_slots(start_slot..=end_slot).await?;
 
-	for slot in start_slot..=end_slot {
-		match get_block_with_retry(&client, slot, config.retry_attempts).await {
-			Ok(block) => {
-				match parse_block(&block) {
-					Ok(parsed_response) => {
-						info!("Finished parsing block at slot {:?}", slot);
-
-						let conn = conn.lock().await;
-						for transaction in &parsed_response {
-							let record = TransactionRecord {
-								transaction_id: transaction.0.clone(),
-								timestamp: block.block_time.unwrap_or_default(),
-								block_height: slot,
-								raw_transaction: serde_json::to_string(&transaction.1)?,
-							};
-							insert_or_update_transaction(&conn, &record)?;
-
-							if let Some(transfer_info) = &transaction.2 {
-								// Assuming each transaction has sender and receiver
-								for account_id in
-									&[transfer_info.sender.clone(), transfer_info.receiver.clone()]
-								{
-									let account_record = AccountRecord {
-										account_id: account_id.clone(),
-										estimated_balance: 0,
-										related_transactions: vec![transaction.0.clone()],
-									};
-									insert_or_update_account(&conn, &account_record)?;
-								}
-							}
-						}
-					},
-					Err(err) => {
-						error!("Failed to parse block at slot {}: {:?}", slot, err);
+	if config.continuous && !ingestion.is_stopping() {
+		let next_slot = start_slot.max(end_slot + 1);
+		info!("Caught up to slot {}, following the tip", end_slot);
+		follow_tip(&ingestion, &config, next_slot).await?;
+	}
+
+	// Wait for the queued blocks to be committed before reporting completion
+	writer.checkpoint().await?;
+
+	Ok(())
+}
+
+/// Ingests the blocks of a historical range of slots and returns once they are committed, without
+/// moving the checkpoint of the live ingestion.
+///
+/// The blocks go through the same path as the live ingestion: the block cache, the archive, and
+/// the upserts of the writer, so slots already stored are written again without duplicating their
+/// records. Slots whose block cannot be fetched or parsed are queued for a later attempt.
+///
+/// # Errors
+/// This function returns an error if the archive or block cache configuration is invalid, or if the
+/// database writer has stopped.
+pub async fn backfill_range(
+	gateway: Arc<RpcGateway>,
+	writer: DbWriter,
+	config: &Config,
+	slots: RangeInclusive<u64>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let archiver = config.archive.clone().map(Archiver::new).transpose()?;
+	let cache = config.block_cache.as_ref().map(BlockCache::open).transpose()?;
+	let ingestion = Ingestion {
+		gateway: &gateway,
+		writer: &writer,
+		archiver: archiver.as_ref(),
+		cache: cache.as_ref(),
+		shutdown: None,
+		chain: ChainTip::default(),
+	};
+	info!("Backfilling blocks from slot {} to {}", slots.start(), slots.end());
+	ingestion.backfill_slots(slots).await?;
+	writer.checkpoint().await?;
+	Ok(())
+}
+
+/// Ingests the blocks from `next_slot` on as the tip advances, until the writer stops or the
+/// shutdown is requested.
+///
+/// With `block_subscription` set, the blocks are received through a `blockSubscribe` subscription.
+/// While the subscription is down, the tip is polled every `poll_interval_ms` instead,
+/// and the subscription is re-established with an exponential backoff.
+///
+/// # Errors
+/// This function returns an error if the database writer has stopped.
+async fn follow_tip(
+	ingestion: &Ingestion<'_>,
+	config: &Config,
+	mut next_slot: u64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let poll_interval = Duration::from_millis(config.poll_interval_ms);
+	let pubsub_url = config.pubsub_url.clone().unwrap_or_else(|| pubsub_url(&config.rpc_url));
+	let mut reconnect_delay = subscription::MIN_RECONNECT_DELAY;
+	let mut reconnect_at = Instant::now();
+	while !ingestion.is_stopping() {
+		if config.block_subscription && Instant::now() >= reconnect_at {
+			let connected =
+				subscription::ingest_subscribed_blocks(&pubsub_url, ingestion, &mut next_slot)
+					.await?;
+			reconnect_delay = if connected {
+				subscription::MIN_RECONNECT_DELAY
+			} else {
+				(reconnect_delay * 2).min(subscription::MAX_RECONNECT_DELAY)
+			};
+			reconnect_at = Instant::now() + reconnect_delay;
+			warn!(
+				"Block subscription unavailable, polling the tip for the next {:?}",
+				reconnect_delay
+			);
+		}
+
+		tokio::select! {
+			_ = tokio::time::sleep(poll_interval) => {},
+			_ = ingestion.stopped() => break,
+		}
+		let tip_slot = match get_tip_slot(ingestion.gateway).await {
+			Ok(slot) => slot,
+			Err(err) => {
+				warn!("Failed to fetch the tip slot: {:?}", err);
+				continue
+			},
+		};
+		if tip_slot < next_slot {
+			continue
+		}
+		ingestion.ingest_slots(next_slot..=tip_slot).await?;
+		next_slot = tip_slot + 1;
+	}
+	Ok(())
+}
+
+/// The path of the fetched blocks: from the RPC node or the block cache, through the archive, to
+/// the database writer.
+pub struct Ingestion<'a> {
+	pub gateway: &'a RpcGateway,
+	pub writer: &'a DbWriter,
+	pub archiver: Option<&'a Archiver>,
+	pub cache: Option<&'a BlockCache>,
+	/// The shutdown of the service, at which the ingestion stops after the block it is writing.
+	pub shutdown: Option<&'a Shutdown>,
+	/// The last block handed to the writer, which the next one is checked to descend from.
+	pub chain: ChainTip,
+}
+
+impl Ingestion<'_> {
+	/// Returns whether the shutdown is requested, so the ingestion must stop at the next block
+	/// boundary.
+	pub fn is_stopping(&self) -> bool {
+		self.shutdown.is_some_and(Shutdown::is_requested)
+	}
+
+	/// Waits until the shutdown is requested, forever without a shutdown handle.
+	pub async fn stopped(&self) {
+		match self.shutdown {
+			Some(shutdown) => shutdown.requested().await,
+			None => std::future::pending().await,
+		}
+	}
+
+	/// Fetches, archives and parses the blocks of a range of slots and hands them to the writer,
+	/// along with the ingestion progress.
+	///
+	/// Blocks held by the block cache are read from it. When the gateway batches calls, the other
+	/// blocks are fetched `batch_size` slots per JSON-RPC request, falling back to one request per
+	/// slot for a batch that fails. Blocks that cannot be fetched or parsed are logged and queued
+	/// for a later attempt. Once the shutdown is requested, the range stops after the block being
+	/// written, and the progress is recorded up to it.
+	///
+	/// # Errors
+	/// This function returns an error if the database writer has stopped.
+	pub async fn ingest_slots(
+		&self,
+		slots: RangeInclusive<u64>,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.ingest_range(slots, true).await
+	}
+
+	/// Fetches, archives and parses the blocks of a historical range of slots and hands them to
+	/// the writer, like `ingest_slots`, but leaves the ingestion progress alone, so the live
+	/// ingestion neither skips nor repeats slots because of the range.
+	///
+	/// # Errors
+	/// This function returns an error if the database writer has stopped.
+	pub async fn backfill_slots(
+		&self,
+		slots: RangeInclusive<u64>,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.ingest_range(slots, false).await
+	}
+
+	async fn ingest_range(
+		&self,
+		slots: RangeInclusive<u64>,
+		record_progress: bool,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		let batch_size = self.gateway.batch_size() as u64;
+		let mut start = *slots.start();
+		while start <= *slots.end() && !self.is_stopping() {
+			let end = start.saturating_add(batch_size - 1).min(*slots.end());
+
+			let mut cached = BTreeMap::new();
+			let mut missing = Vec::new();
+			for slot in start..=end {
+				match self.cache.and_then(|cache| cache.get(slot)) {
+					Some(block) => {
+						cached.insert(slot, block);
 					},
+					None => missing.push(slot),
 				}
+			}
+			let mut fetched: BTreeMap<u64, BlockResult> =
+				self.fetch_blocks(&missing).await.into_iter().collect();
+
+			// Blocks are written in slot order, whichever source they come from
+			let mut written = end;
+			for slot in start..=end {
+				let (cached, fetched) = (cached.remove(&slot), fetched.remove(&slot));
+				async {
+					if let Some(block) = cached {
+						info!("Read block at slot {} from the block cache", slot);
+						self.write_block(slot, &block).await
+					} else {
+						match fetched {
+							Some(Ok(block)) => self.ingest_block(slot, &block).await,
+							Some(Err(err)) => self.fail_slot(slot, "fetch", err.as_ref()).await,
+							None => Ok(()),
+						}
+					}
+				}
+				.instrument(info_span!("slot", slot))
+				.await?;
+				if slot < end && self.is_stopping() {
+					info!("Stopping the ingestion after slot {}", slot);
+					written = slot;
+					break
+				}
+			}
+			if record_progress {
+				self.writer.advance_sync_state(written).await?;
+			}
+			match end.checked_add(1) {
+				Some(next) => start = next,
+				None => break,
+			}
+		}
+		Ok(())
+	}
+
+	/// Fetches the blocks of some slots from the RPC node, in a batch when enabled.
+	async fn fetch_blocks(&self, slots: &[u64]) -> Vec<(u64, BlockResult)> {
+		if slots.len() > 1 && self.gateway.batch_size() > 1 {
+			let span = info_span!("slots", first = slots[0], last = slots[slots.len() - 1]);
+			match get_blocks_batch(self.gateway, slots).instrument(span).await {
+				Ok(blocks) => return blocks,
+				Err(err) => warn!(
+					first_slot = slots[0],
+					last_slot = slots[slots.len() - 1],
+					error_kind = "fetch",
+					error = %err,
+					"Failed to fetch blocks in a batch"
+				),
+			}
+		}
+		let mut blocks = Vec::new();
+		for slot in slots {
+			let block = get_block(self.gateway, *slot).instrument(info_span!("slot", slot)).await;
+			blocks.push((*slot, block));
+		}
+		blocks
+	}
+
+	/// Caches, archives and parses a block fetched from the RPC node and hands its transactions
+	/// to the writer, once the stored chain leads to it.
+	///
+	/// Archival and parsing failures are logged, and a block that cannot be parsed is queued for a
+	/// later attempt.
+	///
+	/// # Errors
+	/// This function returns an error if the database writer has stopped.
+	pub async fn ingest_block(
+		&self,
+		slot: u64,
+		block: &UiConfirmedBlock,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.keep_block(slot, block).await;
+		self.write_block(slot, block).await
+	}
+
+	/// Writes a block to the block cache and the archive, logging the archival failures.
+	async fn keep_block(&self, slot: u64, block: &UiConfirmedBlock) {
+		if let Some(cache) = self.cache {
+			cache.put(slot, block);
+		}
+		if let Some(archiver) = self.archiver {
+			if let Err(err) = archiver.archive_block(slot, block).await {
+				error!(slot, error_kind = "archive", error = %err, "Failed to archive block");
+			}
+		}
+	}
+
+	/// Rolls back the fork the stored chain is left on, if the block does not descend from it,
+	/// then parses the block and hands its transactions to the writer.
+	async fn write_block(
+		&self,
+		slot: u64,
+		block: &UiConfirmedBlock,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		reorgs::follow_chain(self, slot, block).await?;
+		self.hand_over(slot, block).await
+	}
+
+	/// Parses a block and hands its transactions to the writer, queueing a block that cannot be
+	/// parsed for a later attempt.
+	async fn hand_over(
+		&self,
+		slot: u64,
+		block: &UiConfirmedBlock,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		match parse_block(block) {
+			Ok(parsed_response) => {
+				info!("Finished parsing block at slot {:?}", slot);
+
+				self.writer.write_block(get_block_record(slot, block), parsed_response).await?;
+				self.chain.advance(slot, &block.blockhash);
 			},
-			Err(err) => {
-				error!("Failed to fetch block at slot {}: {:?}", slot, err);
-			},
+			Err(err) => self.fail_slot(slot, "parse", err.as_ref()).await?,
+		}
+		Ok(())
+	}
+
+	/// Logs the failure to ingest a slot, and queues the slot for a later attempt unless the
+	/// leader skipped it, so it has no block to fetch.
+	///
+	/// # Errors
+	/// This function returns an error if the database writer has stopped.
+	pub async fn fail_slot(
+		&self,
+		slot: u64,
+		stage: &str,
+		err: &(dyn Error + Send + Sync),
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		if is_skipped_slot(err) {
+			info!(slot, error_kind = "skipped_slot", "Slot was skipped, no block to ingest");
+			return Ok(())
 		}
+		error!(slot, error_kind = stage, error = %err, "Failed to {} block", stage);
+		self.writer.mark_failed(slot, &err.to_string()).await
+	}
+}
+
+/// Resolves the first slot to ingest from a `StartFrom` strategy.
+///
+/// # Arguments
+/// * `start_from` - The strategy from the configuration.
+/// * `epoch_info` - The current epoch info.
+/// * `last_slot` - The last slot processed by a previous run, used by `StartFrom::Checkpoint`.
+///
+/// # Returns
+/// The first slot to ingest.
+pub fn start_slot(start_from: StartFrom, epoch_info: &EpochInfo, last_slot: Option<u64>) -> u64 {
+	let epoch_start = epoch_info.absolute_slot - epoch_info.slot_index;
+	match start_from {
+		StartFrom::Latest => epoch_info.absolute_slot,
+		StartFrom::EpochStart => epoch_start,
+		StartFrom::Checkpoint => last_slot.map_or(epoch_start, |slot| slot + 1),
+		StartFrom::Slot(slot) => slot,
+	}
+}
+
+/// Stores the transactions parsed from a block in the SQLite database.
+///
+/// This function records the time of the block and writes each transaction along with the records
+/// derived from it (the accounts it touches, errors, balance changes, transfers, swaps) and updates
+/// the status of the transactions submitted through the service. The changes to the accounts
+/// selected by the discovery policy are accumulated over the block and merged into each account
+/// once, and the transactions stored for the first time are added to the rollup of their day. It
+/// then runs the MEV analysis over the block's ordered swaps and stores the flagged sandwiches.
+///
+/// The rows are written through cached prepared statements. Callers run this function within a
+/// transaction, as the database writer does, so the writes of a block are committed at once rather
+/// than one statement at a time.
+///
+/// # Arguments
+/// * `conn` - A reference to the SQLite database connection.
+/// * `slot` - The slot of the block.
+/// * `block_time` - The optional timestamp of the block.
+/// * `transactions` - The transactions parsed from the block, in block order.
+/// * `policy` - Which of the accounts touched by the transactions get an account row, and how much
+///   of the raw transactions is stored.
+/// * `filters` - The program filters; the transactions they drop, like those the watched addresses
+///   and indexed programs of the policy leave out, are skipped, and the others keep their position
+///   within the block.
+/// * `recent` - The states recently written for the accounts, whose upserts are skipped when the
+///   block leaves them unchanged. The caller clears them when the writes are rolled back.
+///
+/// # Returns
+/// * `Ok(())` on success.
+/// * `Err(Box<dyn Error + Send + Sync>)` if a record cannot be serialized or written.
+pub fn store_parsed_block(
+	conn: &Connection,
+	slot: u64,
+	block_time: Option<i64>,
+	transactions: &[ParsedTransaction],
+	policy: &StoragePolicy,
+	filters: &ProgramFilters,
+	recent: &mut RecentWrites,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let swaps = store_transactions(
+		conn,
+		slot,
+		block_time,
+		transactions
+			.iter()
+			.enumerate()
+			.filter(|(_, transaction)| passes_filters(policy, filters, transaction))
+			.map(|(index, transaction)| (index as u32, transaction)),
+		policy,
+		recent,
+	)?;
+
+	for sandwich in mev::detect_sandwiches(&swaps) {
+		info!(
+			"Probable sandwich at slot {} by {} on pool {}",
+			slot, sandwich.attacker, sandwich.pool
+		);
+		insert_or_update_sandwich(conn, &sandwich)?;
 	}
 
 	Ok(())
 }
 
-/// Fetches a block with retry logic in case of failures.
+/// Returns whether a transaction is stored: it must touch one of the watched addresses of the
+/// policy, if any, invoke one of its indexed programs, if any, and the program filters must let it
+/// through. Programs are judged by the top level instructions of the transaction.
+pub fn passes_filters(
+	policy: &StoragePolicy,
+	filters: &ProgramFilters,
+	transaction: &ParsedTransaction,
+) -> bool {
+	if !touches_watched_addresses(&policy.watched_addresses, transaction) {
+		return false
+	}
+	if policy.index_programs.is_empty() && filters.is_empty() {
+		return true
+	}
+	let programs = get_invoked_programs(&transaction.transaction);
+	let indexed = policy.index_programs.is_empty() ||
+		programs.iter().any(|program| policy.index_programs.contains(program));
+	indexed && filters.allows(&programs)
+}
+
+/// Returns whether a transaction touches one of the watched addresses, through an account of its
+/// message, loaded ones included, or a wallet owning one of its token accounts. Every transaction
+/// does when no address is watched.
+pub fn touches_watched_addresses(
+	watched: &HashSet<String>,
+	transaction: &ParsedTransaction,
+) -> bool {
+	watched.is_empty() ||
+		get_account_keys(&transaction.transaction, transaction.loaded_addresses.as_ref())
+			.iter()
+			.chain(transaction.token_owners.values())
+			.any(|address| watched.contains(address))
+}
+
+/// Replaces a stored transaction with a freshly parsed copy.
 ///
-/// This function attempts to fetch a block from the Solana blockchain. If the fetch fails, it will
-/// retry up to the specified number of times with an exponential backoff.
+/// The records derived from the previous parse (errors, balance changes, transfers, swaps) are
+/// deleted before the transaction is stored again, so records the new parse no longer produces do
+/// not linger. The transaction keeps its stored position within the block, or is placed first when
+/// it was not stored yet. Transfers already merged into an account are not applied twice.
 ///
 /// # Arguments
-/// * `client` - A reference to the `RpcClient` for communicating with the Solana blockchain.
-/// * `slot` - The slot number of the block to fetch.
-/// * `retries` - The maximum number of retry attempts.
+/// * `conn` - A reference to the SQLite database connection.
+/// * `slot` - The slot of the transaction.
+/// * `block_time` - The optional timestamp of the block.
+/// * `transaction` - The parsed transaction.
+/// * `policy` - Which of the accounts touched by the transaction get an account row, and how much
+///   of the raw transaction is stored.
 ///
 /// # Returns
-/// * `Ok(UiConfirmedBlock)` on success, containing the fetched block data.
-/// * `Err(Box<dyn Error + Send + Sync>)` on failure, containing the error encountered during the
-///   fetch process.
-async fn get_block_with_retry(
-	client: &RpcClient,
+/// * `Ok(())` on success.
+/// * `Err(Box<dyn Error + Send + Sync>)` if a record cannot be serialized or written.
+pub fn replace_transaction(
+	conn: &Connection,
+	slot: u64,
+	block_time: Option<i64>,
+	transaction: &ParsedTransaction,
+	policy: &StoragePolicy,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let index = get_transaction_index(conn, &transaction.signature)?.unwrap_or_default();
+	delete_transaction_details(conn, &transaction.signature)?;
+	store_transactions(
+		conn,
+		slot,
+		block_time,
+		[(index, transaction)],
+		policy,
+		&mut RecentWrites::default(),
+	)?;
+	Ok(())
+}
+
+/// Writes transactions of a block along with the records derived from them, given their position
+/// within the block, and returns their swaps in order.
+fn store_transactions<'a>(
+	conn: &Connection,
 	slot: u64,
-	retries: u8,
-) -> Result<UiConfirmedBlock, Box<dyn Error + Send + Sync>> {
-	let mut attempts = 0;
-	let mut wait_time = 2; // initial wait time in seconds
-
-	loop {
-		match get_block(client, slot) {
-			Ok(block) => return Ok(block),
-			Err(err) if attempts < retries => {
-				attempts += 1;
-				info!("Retry {}/{} for slot {}: {:?}", attempts, retries, slot, err);
-				tokio::time::sleep(tokio::time::Duration::from_secs(wait_time)).await;
-				wait_time *= 2; // exponential backoff
+	block_time: Option<i64>,
+	transactions: impl IntoIterator<Item = (u32, &'a ParsedTransaction)>,
+	policy: &StoragePolicy,
+	recent: &mut RecentWrites,
+) -> Result<Vec<SwapRecord>, Box<dyn Error + Send + Sync>> {
+	if let Some(block_time) = block_time {
+		blocks::insert_or_update_block(conn, slot, block_time)?;
+	}
+
+	let mut swaps = Vec::new();
+	// The transfers of each account over the block, as (transaction, lamport change) pairs
+	let mut account_changes: BTreeMap<String, Vec<(String, i128)>> = BTreeMap::new();
+	// The balance of each account after its last transaction, as (transaction index, lamports)
+	let mut account_balances: BTreeMap<String, (u32, u64)> = BTreeMap::new();
+
+	let mut daily = block_time
+		.and_then(rollups::day_of)
+		.map(|day| DailyStats { day, ..Default::default() });
+
+	for (index, transaction) in transactions {
+		let _span = info_span!("transaction", index, signature = %transaction.signature).entered();
+		// Only transactions stored for the first time, in either database, count towards the daily
+		// rollup
+		let mut rollup = match daily.as_mut() {
+			Some(daily) if !is_transaction_stored(conn, &transaction.signature)? => {
+				daily.transaction_count += 1;
+				daily.total_fee += transaction.fee;
+				Some(daily)
 			},
-			Err(err) => return Err(err),
+			_ => None,
+		};
+
+		let discovered = discover_accounts(transaction, policy.account_discovery);
+		let record = TransactionRecord {
+			transaction_id: transaction.signature.clone(),
+			timestamp: block_time.unwrap_or_default(),
+			block_height: slot,
+			raw_transaction: redact_raw_transaction(
+				&transaction.transaction,
+				policy.raw_transactions,
+			)?,
+			fee: transaction.fee,
+			fee_payer: transaction.fee_payer.clone(),
+			compute_unit_limit: transaction.compute_unit_limit,
+			compute_unit_price: transaction.compute_unit_price,
+		};
+		insert_or_update_transaction(conn, &record)?;
+		submissions::mark_submission_landed(
+			conn,
+			&transaction.signature,
+			slot,
+			transaction.error.as_ref().map(|failure| failure.error.as_str()),
+		)?;
+
+		// The wallets owning token accounts are linked to the transaction along with its accounts
+		let mut account_keys =
+			get_account_keys(&transaction.transaction, transaction.loaded_addresses.as_ref());
+		account_keys.extend(transaction.token_owners.values().cloned());
+		account_keys.sort();
+		account_keys.dedup();
+		for account_id in account_keys {
+			if let Some(daily) = rollup.as_deref_mut() {
+				if !account_has_transactions(conn, &account_id)? {
+					daily.new_accounts += 1;
+				}
+				if rollups::mark_active_account(conn, &daily.day, &account_id)? {
+					daily.active_accounts += 1;
+				}
+			}
+			let account_transaction_record = AccountTransactionRecord {
+				account_id,
+				transaction_id: transaction.signature.clone(),
+				block_height: slot,
+				transaction_index: index,
+			};
+			insert_or_update_account_transaction(conn, &account_transaction_record)?;
+		}
+
+		if let Some(failure) = &transaction.error {
+			let error_record = TransactionErrorRecord {
+				transaction_id: transaction.signature.clone(),
+				program_id: failure.program_id.clone(),
+				error: failure.error.clone(),
+				timestamp: record.timestamp,
+				block_height: slot,
+			};
+			insert_or_update_transaction_error(conn, &error_record)?;
+		}
+
+		for change in &transaction.balance_changes {
+			let balance_change_record = BalanceChangeRecord {
+				account_id: change.account.clone(),
+				transaction_id: transaction.signature.clone(),
+				block_height: slot,
+				transaction_index: index,
+				pre_balance: change.pre_balance,
+				post_balance: change.post_balance,
+			};
+			insert_or_update_balance_change(conn, &balance_change_record)?;
+		}
+
+		for instruction in &transaction.program_instructions {
+			insert_or_update_program_instruction(conn, &transaction.signature, slot, instruction)?;
+		}
+
+		if let Some(swap) = &transaction.swap {
+			let swap_record = SwapRecord {
+				transaction_id: transaction.signature.clone(),
+				block_height: slot,
+				transaction_index: index,
+				trader: swap.trader.clone(),
+				pool: swap.pool.clone(),
+				mint_in: swap.mint_in.clone(),
+				amount_in: swap.amount_in,
+				mint_out: swap.mint_out.clone(),
+				amount_out: swap.amount_out,
+			};
+			insert_or_update_swap(conn, &swap_record)?;
+			swaps.push(swap_record);
+		}
+
+		for transfer_record in transfer_records(slot, record.timestamp, transaction) {
+			insert_or_update_transfer(conn, &transfer_record)?;
+			if let Some(daily) = rollup.as_deref_mut() {
+				daily.volume += transfer_record.amount as u128;
+			}
+
+			// Coalesce the account writes of the block, so busy accounts are upserted once
+			let amount = transfer_record.amount as i128;
+			for (account_id, delta) in
+				[(&transfer_record.sender, -amount), (&transfer_record.receiver, amount)]
+			{
+				if discovered.contains(account_id) {
+					account_changes
+						.entry(account_id.clone())
+						.or_default()
+						.push((transaction.signature.clone(), delta));
+				}
+			}
 		}
+
+		for (account_id, lamports) in &transaction.post_balances {
+			if discovered.contains(account_id) {
+				let balance =
+					account_balances.entry(account_id.clone()).or_insert((index, *lamports));
+				if index >= balance.0 {
+					*balance = (index, *lamports);
+				}
+			}
+		}
+
+		// The other discovered accounts are linked to the transaction without a lamport change
+		for account_id in discovered {
+			let changes = account_changes.entry(account_id).or_default();
+			if !changes
+				.iter()
+				.any(|(transaction_id, _)| *transaction_id == transaction.signature)
+			{
+				changes.push((transaction.signature.clone(), 0));
+			}
+		}
+	}
+
+	for (account_id, changes) in &account_changes {
+		let balance = account_balances.get(account_id).copied();
+		merge_account(conn, account_id, slot, changes, balance, recent)?;
+	}
+
+	if let Some(daily) = daily.filter(|daily| daily.transaction_count > 0) {
+		rollups::add_daily_stats(conn, &daily)?;
 	}
+
+	Ok(swaps)
+}
+
+/// Returns the records of the transfers of a parsed transaction, in transfer order.
+///
+/// # Arguments
+/// * `slot` - The slot of the transaction.
+/// * `timestamp` - The timestamp of the block, 0 when unknown.
+/// * `transaction` - The parsed transaction.
+pub fn transfer_records(
+	slot: u64,
+	timestamp: i64,
+	transaction: &ParsedTransaction,
+) -> impl Iterator<Item = TransferRecord> + '_ {
+	transaction
+		.transfers()
+		.enumerate()
+		.map(move |(transfer_index, transfer_info)| TransferRecord {
+			transaction_id: transaction.signature.clone(),
+			transfer_index: transfer_index as u32,
+			sender: transfer_info.sender.clone(),
+			receiver: transfer_info.receiver.clone(),
+			amount: transfer_info.amount,
+			timestamp,
+			block_height: slot,
+			kind: transfer_info.kind,
+			sender_owner: transfer_info.sender_owner.clone(),
+			receiver_owner: transfer_info.receiver_owner.clone(),
+			invoked_by: transfer_info.invoked_by.clone(),
+			tags: transfer_info.tags.clone(),
+		})
 }
@@ -1,20 +1,108 @@
-use crate::types::{ParsedInstruction, TransactionDetails};
-use log::{debug, error};
+use crate::{
+	db::blocks::BlockRecord,
+	types::{
+		AccountDiscovery, BalanceChange, CloseAccountInfo, CreateAccountInfo, ParsedInstruction,
+		ProgramInstruction, RawTransactionPolicy, Swap, TransactionDetails, TransactionFailure,
+		TransferInfo, TransferKind,
+	},
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::{debug, error, info_span};
 
 use solana_transaction_status::{
-	EncodedTransaction, UiConfirmedBlock, UiInstruction, UiMessage, UiParsedInstruction,
+	option_serializer::OptionSerializer, EncodedTransaction, EncodedTransactionWithStatusMeta,
+	UiConfirmedBlock, UiInstruction, UiLoadedAddresses, UiMessage, UiParsedInstruction,
+	UiParsedMessage, UiTransactionStatusMeta,
 };
-use std::error::Error;
+use std::{collections::BTreeMap, error::Error};
+
+pub const SYSTEM_PROGRAM_ID: &str = "11111111111111111111111111111111";
+pub const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+pub const TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+pub const COMPUTE_BUDGET_PROGRAM_ID: &str = "ComputeBudget111111111111111111111111111111";
+pub const WRAPPED_SOL_MINT: &str = "So11111111111111111111111111111111111111112";
+pub const MEMO_PROGRAM_IDS: [&str; 2] =
+	["MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr", "Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo"];
+
+/// The instructions the parser derives transfers and fees from, by program. Swaps are derived from
+/// the balance changes of the fee payer, whichever program made them.
+///
+/// Transfers are lamport movements, so the token `transfer` and `transferChecked` instructions,
+/// whose amounts are in units of their mint, are not parsed into transfers. Their transactions
+/// still reach the wallets owning the token accounts through `token_owners`.
+pub const PARSED_INSTRUCTIONS: [(&str, &[&str]); 4] = [
+	(SYSTEM_PROGRAM_ID, &["transfer", "transferWithSeed", "createAccount", "createAccountWithSeed"]),
+	(TOKEN_PROGRAM_ID, &["closeAccount"]),
+	(TOKEN_2022_PROGRAM_ID, &["closeAccount"]),
+	(COMPUTE_BUDGET_PROGRAM_ID, &["setComputeUnitLimit", "setComputeUnitPrice"]),
+];
+
+/// Discriminator of the ComputeBudget `SetComputeUnitLimit` instruction.
+const SET_COMPUTE_UNIT_LIMIT: u8 = 2;
+/// Discriminator of the ComputeBudget `SetComputeUnitPrice` instruction.
+const SET_COMPUTE_UNIT_PRICE: u8 = 3;
+
+/// A transaction extracted from a block, along with the details parsed from it.
+#[derive(Debug)]
+pub struct ParsedTransaction {
+	/// The transaction signature.
+	pub signature: String,
+	/// The encoded transaction itself.
+	pub transaction: EncodedTransaction,
+	/// The transfers of the supported top level instructions, in instruction order, empty if the
+	/// transaction has none or failed.
+	pub details: Vec<TransactionDetails>,
+	/// The fee paid for the transaction, in lamports.
+	pub fee: u64,
+	/// The account that paid the fee (the first account of the message).
+	pub fee_payer: String,
+	/// The error the transaction failed with, or `None` if it succeeded.
+	pub error: Option<TransactionFailure>,
+	/// The compute unit limit requested through the ComputeBudget program, if any.
+	pub compute_unit_limit: Option<u32>,
+	/// The compute unit price (priority fee) in micro-lamports set through the ComputeBudget
+	/// program, if any.
+	pub compute_unit_price: Option<u64>,
+	/// The token swap performed by the fee payer, if the transaction is a swap.
+	pub swap: Option<Swap>,
+	/// The lamport balance changes of the accounts of the transaction.
+	pub balance_changes: Vec<BalanceChange>,
+	/// The lamport balances of the accounts of the transaction once it executed.
+	pub post_balances: Vec<(String, u64)>,
+	/// The wallets owning the token accounts of the transaction, by token account.
+	pub token_owners: BTreeMap<String, String>,
+	/// The transfers made through cross-program invocations.
+	pub inner_transfers: Vec<TransactionDetails>,
+	/// The accounts a versioned transaction loaded from address lookup tables, if any.
+	pub loaded_addresses: Option<UiLoadedAddresses>,
+	/// The top level instructions of the programs the RPC node does not parse.
+	pub program_instructions: Vec<ProgramInstruction>,
+}
+
+impl ParsedTransaction {
+	/// Returns the transfers of the transaction: the top level ones first, then the inner ones.
+	pub fn transfers(&self) -> impl Iterator<Item = &TransactionDetails> {
+		self.details.iter().chain(&self.inner_transfers)
+	}
+
+	/// Returns the transfers of the transaction mutably, in the order of `transfers`.
+	pub fn transfers_mut(&mut self) -> impl Iterator<Item = &mut TransactionDetails> {
+		self.details.iter_mut().chain(&mut self.inner_transfers)
+	}
+}
 
 /// Parses a confirmed block and extracts transaction details.
 ///
 /// This function takes a reference to a `UiConfirmedBlock` and attempts to parse
-/// each transaction within the block. It returns a vector of tuples, where each
-/// tuple contains:
+/// each transaction within the block. It returns a vector of `ParsedTransaction`, where each
+/// entry contains:
 /// - A `String` representing the transaction signature.
 /// - An `EncodedTransaction` which is the transaction itself.
-/// - An `Option<TransactionDetails>` which contains parsed transaction details if the transaction
-///   was successfully parsed, or `None` if the transaction was unsupported or failed to parse.
+/// - A `Vec<TransactionDetails>` which contains the transfers parsed from the supported top level
+///   instructions, empty if the transaction has none or failed.
+/// - The fee paid for the transaction and the account that paid it.
 ///
 /// # Arguments
 ///
@@ -23,37 +111,24 @@ use std::error::Error;
 /// # Returns
 ///
 /// This function returns a `Result` containing:
-/// - `Ok(Vec<(String, EncodedTransaction, Option<TransactionDetails>)>)` on success.
+/// - `Ok(Vec<ParsedTransaction>)` on success.
 /// - `Err(Box<dyn Error + Send + Sync>)` if there was an error during the parsing process.
 pub fn parse_block(
 	block: &UiConfirmedBlock,
-) -> Result<
-	Vec<(String, EncodedTransaction, Option<TransactionDetails>)>,
-	Box<dyn Error + Send + Sync>,
-> {
+) -> Result<Vec<ParsedTransaction>, Box<dyn Error + Send + Sync>> {
 	let mut transaction_details = Vec::new();
 
 	if let Some(transactions) = &block.transactions {
-		for transaction_with_meta in transactions {
-			let tx_signature = get_transaction_signature(&transaction_with_meta.transaction)?;
-			match parse_transaction(&transaction_with_meta.transaction, block.block_time) {
-				Ok(Some(parsed_transaction)) => {
-					transaction_details.push((
-						tx_signature,
-						transaction_with_meta.transaction.clone(),
-						Some(parsed_transaction),
-					));
-				},
-				Ok(None) => {
-					debug!("Parsed and not supported tx found");
-					transaction_details.push((
-						tx_signature,
-						transaction_with_meta.transaction.clone(),
-						None,
-					));
-				},
+		for (index, transaction_with_meta) in transactions.iter().enumerate() {
+			let _span = info_span!("transaction", index).entered();
+			match parse_transaction_with_meta(transaction_with_meta, block.block_time) {
+				Ok(parsed_transaction) => transaction_details.push(parsed_transaction),
 				Err(err) => {
-					error!("Failed to parse transaction: {:?}", err);
+					error!(
+						error_kind = "transaction_parse",
+						error = %err,
+						"Failed to parse transaction"
+					);
 				},
 			}
 		}
@@ -62,6 +137,785 @@ pub fn parse_block(
 	Ok(transaction_details)
 }
 
+/// Returns the header of a block, to store along with its transactions.
+///
+/// # Arguments
+///
+/// * `slot` - The slot of the block.
+/// * `block` - A reference to the `UiConfirmedBlock`.
+pub fn get_block_record(slot: u64, block: &UiConfirmedBlock) -> BlockRecord {
+	BlockRecord {
+		slot,
+		block_time: block.block_time,
+		blockhash: Some(block.blockhash.clone()),
+		previous_blockhash: Some(block.previous_blockhash.clone()),
+		parent_slot: Some(block.parent_slot),
+		block_height: block.block_height,
+		transaction_count: Some(block.transactions.as_ref().map_or(0, Vec::len) as u32),
+	}
+}
+
+/// Parses a single transaction along with its status meta.
+///
+/// # Arguments
+///
+/// * `transaction_with_meta` - A reference to the transaction and its status meta.
+/// * `block_time` - The optional timestamp of the block containing the transaction.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing:
+/// - `Ok(ParsedTransaction)` on success.
+/// - `Err(Box<dyn Error + Send + Sync>)` if the signature or a supported instruction cannot be
+///   parsed.
+pub fn parse_transaction_with_meta(
+	transaction_with_meta: &EncodedTransactionWithStatusMeta,
+	block_time: Option<i64>,
+) -> Result<ParsedTransaction, Box<dyn Error + Send + Sync>> {
+	let tx_signature = get_transaction_signature(&transaction_with_meta.transaction)?;
+	let details = parse_transaction(
+		&transaction_with_meta.transaction,
+		transaction_with_meta.meta.as_ref(),
+		block_time,
+	)?;
+	if details.is_empty() {
+		debug!("Parsed and not supported tx found");
+	}
+	let (compute_unit_limit, compute_unit_price) =
+		get_compute_budget(&transaction_with_meta.transaction);
+	Ok(ParsedTransaction {
+		signature: tx_signature,
+		transaction: transaction_with_meta.transaction.clone(),
+		details,
+		fee: transaction_with_meta.meta.as_ref().map(|meta| meta.fee).unwrap_or_default(),
+		fee_payer: get_fee_payer(&transaction_with_meta.transaction).unwrap_or_default(),
+		error: transaction_with_meta
+			.meta
+			.as_ref()
+			.and_then(|meta| get_transaction_failure(&transaction_with_meta.transaction, meta)),
+		compute_unit_limit,
+		compute_unit_price,
+		swap: transaction_with_meta
+			.meta
+			.as_ref()
+			.and_then(|meta| get_swap(&transaction_with_meta.transaction, meta)),
+		balance_changes: transaction_with_meta
+			.meta
+			.as_ref()
+			.map(|meta| get_balance_changes(&transaction_with_meta.transaction, meta))
+			.unwrap_or_default(),
+		post_balances: transaction_with_meta
+			.meta
+			.as_ref()
+			.map(|meta| get_post_balances(&transaction_with_meta.transaction, meta))
+			.unwrap_or_default(),
+		token_owners: transaction_with_meta
+			.meta
+			.as_ref()
+			.map(|meta| get_token_account_owners(&transaction_with_meta.transaction, meta))
+			.unwrap_or_default(),
+		inner_transfers: parse_inner_transfers(
+			&transaction_with_meta.transaction,
+			transaction_with_meta.meta.as_ref(),
+			block_time,
+		)?,
+		loaded_addresses: transaction_with_meta
+			.meta
+			.as_ref()
+			.and_then(get_loaded_addresses)
+			.cloned(),
+		program_instructions: get_program_instructions(
+			&transaction_with_meta.transaction,
+			transaction_with_meta.meta.as_ref().and_then(get_loaded_addresses),
+		),
+	})
+}
+
+/// Returns the fee payer of an encoded transaction, which is the first account of its message.
+pub fn get_fee_payer(transaction: &EncodedTransaction) -> Option<String> {
+	match transaction {
+		EncodedTransaction::Json(ui_transaction) => match &ui_transaction.message {
+			UiMessage::Parsed(message) =>
+				message.account_keys.first().map(|key| key.pubkey.clone()),
+			UiMessage::Raw(message) => message.account_keys.first().cloned(),
+		},
+		_ => None,
+	}
+}
+
+/// Extracts the compute unit limit and price set through ComputeBudget instructions.
+///
+/// ComputeBudget instructions are not parsed by the RPC node, so their base58 encoded data is
+/// decoded here: `SetComputeUnitLimit` carries a little endian `u32` and `SetComputeUnitPrice` a
+/// little endian `u64` in micro-lamports per compute unit.
+///
+/// # Arguments
+///
+/// * `transaction` - A reference to an `EncodedTransaction` to extract the compute budget from.
+///
+/// # Returns
+///
+/// This function returns a tuple of the optional compute unit limit and compute unit price.
+pub fn get_compute_budget(transaction: &EncodedTransaction) -> (Option<u32>, Option<u64>) {
+	let mut compute_unit_limit = None;
+	let mut compute_unit_price = None;
+
+	let EncodedTransaction::Json(ui_transaction) = transaction else {
+		return (compute_unit_limit, compute_unit_price)
+	};
+	let instructions: Vec<(String, &str)> = match &ui_transaction.message {
+		UiMessage::Parsed(message) => message
+			.instructions
+			.iter()
+			.filter_map(|instruction| match instruction {
+				UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(instruction)) =>
+					Some((instruction.program_id.clone(), instruction.data.as_str())),
+				UiInstruction::Compiled(instruction) => message
+					.account_keys
+					.get(instruction.program_id_index as usize)
+					.map(|key| (key.pubkey.clone(), instruction.data.as_str())),
+				_ => None,
+			})
+			.collect(),
+		UiMessage::Raw(message) => message
+			.instructions
+			.iter()
+			.filter_map(|instruction| {
+				message
+					.account_keys
+					.get(instruction.program_id_index as usize)
+					.map(|key| (key.clone(), instruction.data.as_str()))
+			})
+			.collect(),
+	};
+
+	for (program_id, data) in instructions {
+		if program_id != COMPUTE_BUDGET_PROGRAM_ID {
+			continue
+		}
+		let Ok(data) = bs58::decode(data).into_vec() else { continue };
+		match data.split_first() {
+			Some((&SET_COMPUTE_UNIT_LIMIT, value)) =>
+				if let Ok(value) = value.try_into() {
+					compute_unit_limit = Some(u32::from_le_bytes(value));
+				},
+			Some((&SET_COMPUTE_UNIT_PRICE, value)) =>
+				if let Ok(value) = value.try_into() {
+					compute_unit_price = Some(u64::from_le_bytes(value));
+				},
+			_ => {},
+		}
+	}
+
+	(compute_unit_limit, compute_unit_price)
+}
+
+/// Serializes the raw transaction stored for a transaction under a redaction policy.
+///
+/// Memos are stripped from the top-level instructions of JSON-encoded transactions; binary
+/// encodings cannot be inspected, so only their hash is stored when memos must be stripped.
+///
+/// # Arguments
+///
+/// * `transaction` - A reference to the `EncodedTransaction`.
+/// * `policy` - How much of the transaction is stored.
+///
+/// # Returns
+///
+/// This function returns the JSON of the transaction, or `sha256:` followed by the hex hash of
+/// that JSON.
+///
+/// # Errors
+///
+/// This function returns an error if the transaction cannot be serialized.
+pub fn redact_raw_transaction(
+	transaction: &EncodedTransaction,
+	policy: RawTransactionPolicy,
+) -> Result<String, serde_json::Error> {
+	let raw = match (policy, transaction) {
+		(RawTransactionPolicy::Full, _) => return serde_json::to_string(transaction),
+		(RawTransactionPolicy::StripMemos, EncodedTransaction::Json(ui_transaction)) => {
+			let mut ui_transaction = ui_transaction.clone();
+			strip_memos(&mut ui_transaction.message);
+			return serde_json::to_string(&EncodedTransaction::Json(ui_transaction))
+		},
+		_ => serde_json::to_string(transaction)?,
+	};
+	let hash: String = Sha256::digest(raw.as_bytes())
+		.iter()
+		.map(|byte| format!("{:02x}", byte))
+		.collect();
+	Ok(format!("sha256:{}", hash))
+}
+
+/// Empties the data of the memo instructions of a message.
+fn strip_memos(message: &mut UiMessage) {
+	let is_memo = |program_id: &str| MEMO_PROGRAM_IDS.contains(&program_id);
+	match message {
+		UiMessage::Parsed(message) => {
+			let account_keys: Vec<&str> =
+				message.account_keys.iter().map(|key| key.pubkey.as_str()).collect();
+			for instruction in &mut message.instructions {
+				match instruction {
+					UiInstruction::Parsed(UiParsedInstruction::Parsed(instruction))
+						if is_memo(&instruction.program_id) =>
+						instruction.parsed = Value::Null,
+					UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(instruction))
+						if is_memo(&instruction.program_id) =>
+						instruction.data.clear(),
+					UiInstruction::Compiled(instruction)
+						if account_keys
+							.get(instruction.program_id_index as usize)
+							.is_some_and(|program_id| is_memo(program_id)) =>
+						instruction.data.clear(),
+					_ => {},
+				}
+			}
+		},
+		UiMessage::Raw(message) =>
+			for instruction in &mut message.instructions {
+				if message
+					.account_keys
+					.get(instruction.program_id_index as usize)
+					.is_some_and(|program_id| is_memo(program_id))
+				{
+					instruction.data.clear();
+				}
+			},
+	}
+}
+
+/// Returns the text of the top level memo instructions of a transaction, in execution order.
+///
+/// The memos the node parsed are read as is, and the data of the others is decoded from base58,
+/// invalid UTF-8 being replaced. Binary encodings cannot be inspected and have no memos.
+pub fn get_memos(transaction: &EncodedTransaction) -> Vec<String> {
+	let EncodedTransaction::Json(ui_transaction) = transaction else { return Vec::new() };
+	let is_memo = |program_id: &str| MEMO_PROGRAM_IDS.contains(&program_id);
+	let decode = |data: &str| {
+		bs58::decode(data)
+			.into_vec()
+			.ok()
+			.map(|data| String::from_utf8_lossy(&data).into_owned())
+	};
+	match &ui_transaction.message {
+		UiMessage::Parsed(message) => message
+			.instructions
+			.iter()
+			.filter_map(|instruction| match instruction {
+				UiInstruction::Parsed(UiParsedInstruction::Parsed(instruction))
+					if is_memo(&instruction.program_id) =>
+					instruction.parsed.as_str().map(str::to_string),
+				UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(instruction))
+					if is_memo(&instruction.program_id) =>
+					decode(&instruction.data),
+				UiInstruction::Compiled(instruction)
+					if message
+						.account_keys
+						.get(instruction.program_id_index as usize)
+						.is_some_and(|program_id| is_memo(&program_id.pubkey)) =>
+					decode(&instruction.data),
+				_ => None,
+			})
+			.collect(),
+		UiMessage::Raw(message) => message
+			.instructions
+			.iter()
+			.filter(|instruction| {
+				message
+					.account_keys
+					.get(instruction.program_id_index as usize)
+					.is_some_and(|program_id| is_memo(program_id))
+			})
+			.filter_map(|instruction| decode(&instruction.data))
+			.collect(),
+	}
+}
+
+/// Returns the accounts a versioned transaction loaded from address lookup tables, as resolved by
+/// the RPC node in its status meta.
+pub fn get_loaded_addresses(meta: &UiTransactionStatusMeta) -> Option<&UiLoadedAddresses> {
+	match &meta.loaded_addresses {
+		OptionSerializer::Some(loaded) => Some(loaded),
+		_ => None,
+	}
+}
+
+/// Returns the account keys of an encoded transaction, in the order the balances of its status
+/// meta are reported in.
+///
+/// Parsed messages already list the accounts loaded from address lookup tables. Raw messages of
+/// versioned transactions only list their static keys, which are followed by the loaded writable
+/// then readonly accounts.
+///
+/// # Arguments
+///
+/// * `transaction` - A reference to the `EncodedTransaction`.
+/// * `loaded` - The accounts the transaction loaded from address lookup tables, if any.
+pub fn get_account_keys(
+	transaction: &EncodedTransaction,
+	loaded: Option<&UiLoadedAddresses>,
+) -> Vec<String> {
+	match transaction {
+		EncodedTransaction::Json(ui_transaction) => match &ui_transaction.message {
+			UiMessage::Parsed(message) =>
+				message.account_keys.iter().map(|key| key.pubkey.clone()).collect(),
+			UiMessage::Raw(message) => {
+				let mut keys = message.account_keys.clone();
+				if let Some(loaded) = loaded {
+					keys.extend(loaded.writable.iter().chain(&loaded.readonly).cloned());
+				}
+				keys
+			},
+		},
+		_ => Vec::new(),
+	}
+}
+
+/// Extracts the top level instructions the dedicated parsers do not decode.
+///
+/// The node hands the instructions of the programs it does not parse over partially decoded, with
+/// their program id, accounts and base58 encoded data; the instructions of raw messages are all
+/// compiled, and their program id and accounts are resolved from the account keys, loaded ones
+/// included. The instructions the node parsed are kept with the JSON it returned, verbatim and
+/// without accounts or data, unless the dedicated parsers decode them (see
+/// `is_decoded_instruction`). The ComputeBudget instructions are left out, as they are decoded by
+/// `get_compute_budget`, and so are the memos, whose content is kept in the raw transaction under
+/// the `raw_transactions` policy only.
+///
+/// # Arguments
+///
+/// * `transaction` - A reference to the `EncodedTransaction`.
+/// * `loaded` - The accounts the transaction loaded from address lookup tables, if any.
+///
+/// # Returns
+///
+/// This function returns the instructions in execution order.
+pub fn get_program_instructions(
+	transaction: &EncodedTransaction,
+	loaded: Option<&UiLoadedAddresses>,
+) -> Vec<ProgramInstruction> {
+	let EncodedTransaction::Json(ui_transaction) = transaction else { return Vec::new() };
+	let account_keys = get_account_keys(transaction, loaded);
+	let compiled = |index: usize, program_id_index: u8, accounts: &[u8], data: &str| {
+		let key = |index: u8| account_keys.get(index as usize).cloned();
+		Some(ProgramInstruction {
+			index: index as u32,
+			program_id: key(program_id_index)?,
+			accounts: accounts.iter().map(|index| key(*index)).collect::<Option<_>>()?,
+			data: data.to_string(),
+			parsed: None,
+		})
+	};
+	let mut instructions: Vec<ProgramInstruction> = match &ui_transaction.message {
+		UiMessage::Parsed(message) => message
+			.instructions
+			.iter()
+			.enumerate()
+			.filter_map(|(index, instruction)| match instruction {
+				UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(instruction)) =>
+					Some(ProgramInstruction {
+						index: index as u32,
+						program_id: instruction.program_id.clone(),
+						accounts: instruction.accounts.clone(),
+						data: instruction.data.clone(),
+						parsed: None,
+					}),
+				UiInstruction::Compiled(instruction) => compiled(
+					index,
+					instruction.program_id_index,
+					&instruction.accounts,
+					&instruction.data,
+				),
+				UiInstruction::Parsed(UiParsedInstruction::Parsed(instruction))
+					if !is_decoded_instruction(&instruction.program_id, &instruction.parsed) =>
+					Some(ProgramInstruction {
+						index: index as u32,
+						program_id: instruction.program_id.clone(),
+						accounts: Vec::new(),
+						data: String::new(),
+						parsed: Some(instruction.parsed.clone()),
+					}),
+				UiInstruction::Parsed(UiParsedInstruction::Parsed(_)) => None,
+			})
+			.collect(),
+		UiMessage::Raw(message) => message
+			.instructions
+			.iter()
+			.enumerate()
+			.filter_map(|(index, instruction)| {
+				compiled(
+					index,
+					instruction.program_id_index,
+					&instruction.accounts,
+					&instruction.data,
+				)
+			})
+			.collect(),
+	};
+	instructions.retain(|instruction| {
+		instruction.program_id != COMPUTE_BUDGET_PROGRAM_ID &&
+			!MEMO_PROGRAM_IDS.contains(&instruction.program_id.as_str())
+	});
+	instructions
+}
+
+/// Returns whether the dedicated parsers decode an instruction the node parsed, i.e. whether
+/// `parse_transfer_instruction` supports it.
+fn is_decoded_instruction(program_id: &str, parsed: &Value) -> bool {
+	let instruction_type = parsed.get("type").and_then(Value::as_str).unwrap_or("");
+	matches!(
+		(program_id, instruction_type),
+		(
+			SYSTEM_PROGRAM_ID,
+			"transfer" | "transferWithSeed" | "createAccount" | "createAccountWithSeed"
+		) | (TOKEN_PROGRAM_ID | TOKEN_2022_PROGRAM_ID, "closeAccount")
+	)
+}
+
+/// Returns the accounts of a transaction that get an account row under a discovery policy.
+///
+/// Signer and writable flags are read from the parsed message, or derived from the message header
+/// of raw messages, whose accounts loaded from address lookup tables are never signers and are
+/// writable when loaded as such.
+///
+/// # Arguments
+///
+/// * `transaction` - A reference to the `ParsedTransaction`.
+/// * `discovery` - The account discovery policy.
+///
+/// # Returns
+///
+/// This function returns the sorted, deduplicated accounts.
+pub fn discover_accounts(
+	transaction: &ParsedTransaction,
+	discovery: AccountDiscovery,
+) -> Vec<String> {
+	let mut accounts: Vec<String> = match (discovery, &transaction.transaction) {
+		(AccountDiscovery::TransferParties, _) => transaction
+			.transfers()
+			.flat_map(|details| [details.sender.clone(), details.receiver.clone()])
+			.collect(),
+		(AccountDiscovery::All, encoded) =>
+			get_account_keys(encoded, transaction.loaded_addresses.as_ref()),
+		(_, EncodedTransaction::Json(ui_transaction)) => {
+			let signers_only = discovery == AccountDiscovery::Signers;
+			match &ui_transaction.message {
+				UiMessage::Parsed(message) => message
+					.account_keys
+					.iter()
+					.filter(|key| if signers_only { key.signer } else { key.writable })
+					.map(|key| key.pubkey.clone())
+					.collect(),
+				UiMessage::Raw(message) => {
+					let header = &message.header;
+					let signers = header.num_required_signatures as usize;
+					let writable_signers =
+						signers.saturating_sub(header.num_readonly_signed_accounts as usize);
+					let writable_unsigned = message
+						.account_keys
+						.len()
+						.saturating_sub(header.num_readonly_unsigned_accounts as usize);
+					let loaded_writable = transaction
+						.loaded_addresses
+						.iter()
+						.flat_map(|loaded| &loaded.writable)
+						.filter(|_| !signers_only);
+					message
+						.account_keys
+						.iter()
+						.enumerate()
+						.filter(|(index, _)| {
+							let writable = *index < writable_signers ||
+								(*index >= signers && *index < writable_unsigned);
+							if signers_only {
+								*index < signers
+							} else {
+								writable
+							}
+						})
+						.map(|(_, key)| key)
+						.chain(loaded_writable)
+						.cloned()
+						.collect()
+				},
+			}
+		},
+		_ => Vec::new(),
+	};
+	accounts.sort();
+	accounts.dedup();
+	accounts
+}
+
+/// Extracts the lamport balance changes of the accounts of a transaction from its status meta.
+///
+/// Accounts whose balance did not change are omitted.
+///
+/// # Arguments
+///
+/// * `transaction` - A reference to the `EncodedTransaction`, used to resolve the account keys.
+/// * `meta` - A reference to the status meta holding the pre and post balances and the accounts
+///   loaded from address lookup tables.
+///
+/// # Returns
+///
+/// This function returns a `Vec<BalanceChange>` in account key order.
+pub fn get_balance_changes(
+	transaction: &EncodedTransaction,
+	meta: &UiTransactionStatusMeta,
+) -> Vec<BalanceChange> {
+	get_account_keys(transaction, get_loaded_addresses(meta))
+		.into_iter()
+		.zip(meta.pre_balances.iter().zip(&meta.post_balances))
+		.filter(|(_, (pre, post))| pre != post)
+		.map(|(account, (pre, post))| BalanceChange {
+			account,
+			pre_balance: *pre,
+			post_balance: *post,
+		})
+		.collect()
+}
+
+/// Extracts the lamport balances of the accounts of a transaction once it executed.
+///
+/// # Arguments
+///
+/// * `transaction` - A reference to the `EncodedTransaction`, used to resolve the account keys.
+/// * `meta` - A reference to the status meta holding the post balances.
+///
+/// # Returns
+///
+/// This function returns the `(account, balance)` pairs in account key order.
+pub fn get_post_balances(
+	transaction: &EncodedTransaction,
+	meta: &UiTransactionStatusMeta,
+) -> Vec<(String, u64)> {
+	get_account_keys(transaction, get_loaded_addresses(meta))
+		.into_iter()
+		.zip(meta.post_balances.iter().copied())
+		.collect()
+}
+
+/// Maps the token accounts of a transaction to the wallets owning them.
+///
+/// The owners are read from the token balances of the status meta, which the RPC node decodes from
+/// the token account data before and after the transaction.
+///
+/// # Arguments
+///
+/// * `transaction` - A reference to the `EncodedTransaction`, used to resolve the account indexes.
+/// * `meta` - A reference to the status meta holding the token balances.
+///
+/// # Returns
+///
+/// This function returns the owning wallet of each token account with a token balance.
+pub fn get_token_account_owners(
+	transaction: &EncodedTransaction,
+	meta: &UiTransactionStatusMeta,
+) -> BTreeMap<String, String> {
+	let account_keys = get_account_keys(transaction, get_loaded_addresses(meta));
+	let mut owners = BTreeMap::new();
+	for balances in [&meta.pre_token_balances, &meta.post_token_balances] {
+		let OptionSerializer::Some(balances) = balances else { continue };
+		for balance in balances {
+			if let (Some(account), OptionSerializer::Some(owner)) =
+				(account_keys.get(balance.account_index as usize), &balance.owner)
+			{
+				owners.insert(account.clone(), owner.clone());
+			}
+		}
+	}
+	owners
+}
+
+/// Derives the token swap performed by the fee payer of a transaction from its balance changes.
+///
+/// A successful transaction is considered a swap when the fee payer's balance decreased for exactly
+/// one mint and increased for exactly one other mint. Native SOL balance changes (excluding the
+/// fee) are accounted as wrapped SOL when only one token balance of the fee payer changed. The pool
+/// is identified by the owner of the vault that received the sold mint (or, failing that, sent the
+/// bought mint).
+///
+/// # Arguments
+///
+/// * `transaction` - A reference to the `EncodedTransaction`, used to find the fee payer.
+/// * `meta` - A reference to the status meta holding the balance changes.
+///
+/// # Returns
+///
+/// This function returns `Some(Swap)` if the transaction is a swap, `None` otherwise.
+pub fn get_swap(transaction: &EncodedTransaction, meta: &UiTransactionStatusMeta) -> Option<Swap> {
+	if meta.err.is_some() {
+		return None
+	}
+	let trader = get_fee_payer(transaction)?;
+	let (OptionSerializer::Some(pre_balances), OptionSerializer::Some(post_balances)) =
+		(&meta.pre_token_balances, &meta.post_token_balances)
+	else {
+		return None
+	};
+
+	// Token balance changes per account: (owner, mint, delta)
+	let mut changes: BTreeMap<u8, (String, String, i128)> = BTreeMap::new();
+	for (balances, sign) in [(pre_balances, -1), (post_balances, 1)] {
+		for balance in balances {
+			let amount: i128 = balance.ui_token_amount.amount.parse().ok()?;
+			let owner =
+				Option::<&String>::from(balance.owner.as_ref()).cloned().unwrap_or_default();
+			let entry = changes
+				.entry(balance.account_index)
+				.or_insert_with(|| (owner, balance.mint.clone(), 0));
+			entry.2 = entry.2.checked_add(sign * amount)?;
+		}
+	}
+
+	let mut trader_deltas: BTreeMap<String, i128> = BTreeMap::new();
+	for (owner, mint, delta) in changes.values() {
+		if *owner == trader && *delta != 0 {
+			let total = trader_deltas.entry(mint.clone()).or_default();
+			*total = total.checked_add(*delta)?;
+		}
+	}
+	if trader_deltas.len() == 1 && !trader_deltas.contains_key(WRAPPED_SOL_MINT) {
+		let pre = *meta.pre_balances.first()? as i128;
+		let post = *meta.post_balances.first()? as i128;
+		let delta = post.checked_sub(pre)?.checked_add(meta.fee as i128)?;
+		if delta != 0 {
+			trader_deltas.insert(WRAPPED_SOL_MINT.to_string(), delta);
+		}
+	}
+
+	let mut sold = trader_deltas.iter().filter(|(_, delta)| **delta < 0);
+	let mut bought = trader_deltas.iter().filter(|(_, delta)| **delta > 0);
+	let ((mint_in, amount_in), (mint_out, amount_out)) = match (sold.next(), bought.next()) {
+		(Some(sold_entry), Some(bought_entry))
+			if sold.next().is_none() && bought.next().is_none() =>
+			(sold_entry, bought_entry),
+		_ => return None,
+	};
+
+	let pool = changes
+		.values()
+		.find(|(owner, mint, delta)| *owner != trader && mint == mint_in && *delta > 0)
+		.or_else(|| {
+			changes
+				.values()
+				.find(|(owner, mint, delta)| *owner != trader && mint == mint_out && *delta < 0)
+		})
+		.map(|(owner, _, _)| owner.clone())
+		.filter(|owner| !owner.is_empty())?;
+
+	Some(Swap {
+		trader,
+		pool,
+		mint_in: mint_in.clone(),
+		amount_in: u128::try_from(amount_in.checked_neg()?).ok()?,
+		mint_out: mint_out.clone(),
+		amount_out: u128::try_from(*amount_out).ok()?,
+	})
+}
+
+/// Extracts the error a transaction failed with from its status meta.
+///
+/// Instruction errors are attributed to the program of the failing instruction, and custom program
+/// errors are reported as `Custom(<code>)`. Transaction-level errors (e.g. `AccountInUse`) are
+/// reported without a program.
+///
+/// # Arguments
+///
+/// * `transaction` - A reference to the `EncodedTransaction`, used to resolve the failing program.
+/// * `meta` - A reference to the status meta of the transaction.
+///
+/// # Returns
+///
+/// This function returns `Some(TransactionFailure)` if the transaction failed, `None` otherwise.
+pub fn get_transaction_failure(
+	transaction: &EncodedTransaction,
+	meta: &UiTransactionStatusMeta,
+) -> Option<TransactionFailure> {
+	let err = serde_json::to_value(meta.err.as_ref()?).ok()?;
+	if let Some([index, instruction_error]) =
+		err.get("InstructionError").and_then(|e| e.as_array()).map(Vec::as_slice)
+	{
+		let program_id = index
+			.as_u64()
+			.and_then(|index| get_instruction_program_id(transaction, index as usize));
+		return Some(TransactionFailure { program_id, error: error_code(instruction_error) })
+	}
+	Some(TransactionFailure { program_id: None, error: error_code(&err) })
+}
+
+/// Formats a JSON encoded error as an error code, e.g. `{"Custom": 1}` as `Custom(1)`.
+fn error_code(err: &serde_json::Value) -> String {
+	match err {
+		serde_json::Value::String(code) => code.clone(),
+		serde_json::Value::Object(map) if map.len() == 1 => {
+			let (name, value) = map.iter().next().expect("map has exactly one entry");
+			match value {
+				serde_json::Value::Object(_) => name.clone(),
+				value => format!("{}({})", name, value),
+			}
+		},
+		other => other.to_string(),
+	}
+}
+
+/// Returns the program id of the top level instruction at the given index.
+pub fn get_instruction_program_id(
+	transaction: &EncodedTransaction,
+	index: usize,
+) -> Option<String> {
+	let EncodedTransaction::Json(ui_transaction) = transaction else { return None };
+	match &ui_transaction.message {
+		UiMessage::Parsed(message) =>
+			parsed_instruction_program_id(message, message.instructions.get(index)?),
+		UiMessage::Raw(message) => {
+			let instruction = message.instructions.get(index)?;
+			message.account_keys.get(instruction.program_id_index as usize).cloned()
+		},
+	}
+}
+
+/// Returns the programs of the top level instructions of a transaction, deduplicated.
+pub fn get_invoked_programs(transaction: &EncodedTransaction) -> Vec<String> {
+	let EncodedTransaction::Json(ui_transaction) = transaction else { return Vec::new() };
+	let mut programs: Vec<String> = match &ui_transaction.message {
+		UiMessage::Parsed(message) => message
+			.instructions
+			.iter()
+			.filter_map(|instruction| parsed_instruction_program_id(message, instruction))
+			.collect(),
+		UiMessage::Raw(message) => message
+			.instructions
+			.iter()
+			.filter_map(|instruction| {
+				message.account_keys.get(instruction.program_id_index as usize).cloned()
+			})
+			.collect(),
+	};
+	programs.sort();
+	programs.dedup();
+	programs
+}
+
+/// Returns the program id of an instruction of a parsed message.
+fn parsed_instruction_program_id(
+	message: &UiParsedMessage,
+	instruction: &UiInstruction,
+) -> Option<String> {
+	match instruction {
+		UiInstruction::Parsed(UiParsedInstruction::Parsed(instruction)) =>
+			Some(instruction.program_id.clone()),
+		UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(instruction)) =>
+			Some(instruction.program_id.clone()),
+		UiInstruction::Compiled(instruction) => message
+			.account_keys
+			.get(instruction.program_id_index as usize)
+			.map(|key| key.pubkey.clone()),
+	}
+}
+
 /// Extracts the transaction signature from an encoded transaction.
 ///
 /// This function takes a reference to an `EncodedTransaction` and attempts to retrieve
@@ -91,65 +945,239 @@ pub fn get_transaction_signature(
 	}
 }
 
-/// Parses an encoded transaction and extracts transaction details if supported.
+/// Parses the transfers of the supported top level instructions of an encoded transaction.
+///
+/// This function takes a reference to an `EncodedTransaction`, its optional status meta and an
+/// optional timestamp, and parses each supported top level instruction into details such as
+/// sender, receiver, amount, kind and timestamp. It supports JSON encoded transactions; the
+/// instructions of raw messages are not decoded, so they yield no details. As for the inner
+/// instructions in `parse_inner_transfers`, a transaction the meta reports as failed made no
+/// transfer, its effects being rolled back, and yields no details.
+///
+/// The supported instructions are classified as follows:
+/// - System program `transfer`/`transferWithSeed` instructions are classified as
+///   `TransferKind::Transfer`.
+/// - System program `createAccount`/`createAccountWithSeed` instructions are classified as
+///   `TransferKind::RentDeposit`.
+/// - Token program `closeAccount` instructions are classified as `TransferKind::AccountClose`, with
+///   the reclaimed lamports taken from the pre/post balances in the meta.
 ///
-/// This function takes a reference to an `EncodedTransaction` and an optional timestamp,
-/// and attempts to parse the transaction to extract details such as sender, receiver,
-/// amount, and timestamp. It supports JSON encoded transactions with parsed messages.
+/// Senders and receivers that are token accounts are resolved to their owning wallet from the token
+/// balances in the meta, or from the instruction for closed accounts. Partially decoded
+/// instructions carry no transfer the service can parse; they are extracted by
+/// `get_program_instructions` instead.
 ///
 /// # Arguments
 ///
 /// * `transaction` - A reference to an `EncodedTransaction` to be parsed.
+/// * `meta` - The optional status meta of the transaction, used to compute reclaimed lamports.
 /// * `timestamp` - An optional `i64` timestamp associated with the transaction.
 ///
 /// # Returns
 ///
 /// This function returns a `Result` containing:
-/// - `Ok(Some(TransactionDetails))` with the parsed transaction details on success.
-/// - `Ok(None)` if the transaction format is supported but no relevant details were found.
-/// - `Err(Box<dyn Error + Send + Sync>)` if the transaction encoding or format is unsupported, or
-///   if an error occurs during parsing.
+/// - `Ok(Vec<TransactionDetails>)` with the parsed transfers in instruction order on success,
+///   empty if the transaction format is supported but no relevant details were found.
+/// - `Err(Box<dyn Error + Send + Sync>)` if the transaction encoding is unsupported, or if an error
+///   occurs during parsing.
 ///
 /// # Errors
 ///
 /// This function will return an error if:
 /// - The transaction encoding is unsupported.
-/// - The transaction message format is unsupported.
 /// - Deserialization of transfer information fails.
 pub fn parse_transaction(
 	transaction: &EncodedTransaction,
+	meta: Option<&UiTransactionStatusMeta>,
 	timestamp: Option<i64>,
-) -> Result<Option<TransactionDetails>, Box<dyn Error + Send + Sync>> {
-	match transaction {
-		EncodedTransaction::Json(ui_transaction) => {
-			if let UiMessage::Parsed(parsed_message) = &ui_transaction.message {
-				for instruction in &parsed_message.instructions {
-					if let UiInstruction::Parsed(parsed_instruction) = instruction {
-						if let UiParsedInstruction::Parsed(ref parsed_inst) = parsed_instruction {
-							if parsed_inst.program_id == "11111111111111111111111111111111" {
-								let transfer_info: ParsedInstruction =
-									serde_json::from_value(parsed_inst.parsed.clone()).map_err(
-										|e| format!("Failed to deserialize transfer info: {}", e),
-									)?;
-								return Ok(Some(TransactionDetails {
-									sender: transfer_info.info.source,
-									receiver: transfer_info.info.destination,
-									amount: transfer_info.info.lamports,
-									timestamp,
-								}))
-							}
-						}
+) -> Result<Vec<TransactionDetails>, Box<dyn Error + Send + Sync>> {
+	let EncodedTransaction::Json(ui_transaction) = transaction else {
+		return Err("Unsupported transaction encoding".into())
+	};
+	if meta.is_some_and(|meta| meta.err.is_some()) {
+		return Ok(Vec::new())
+	}
+	let UiMessage::Parsed(message) = &ui_transaction.message else { return Ok(Vec::new()) };
 
-						// if let UiParsedInstruction::PartiallyDecoded(ref parsed_inst) =
-						// parsed_instruction {     todo!();
-						// }
-					}
-				}
-			} else {
-				return Err("Unsupported transaction message format".into())
+	let owners = meta.map(|meta| get_token_account_owners(transaction, meta)).unwrap_or_default();
+	let mut transfers = Vec::new();
+	for instruction in &message.instructions {
+		if let Some(mut details) =
+			parse_transfer_instruction(instruction, message, meta, timestamp)?
+		{
+			resolve_token_owners(&mut details, &owners);
+			transfers.push(details);
+		}
+	}
+	Ok(transfers)
+}
+
+/// Parses the transfers a transaction made through cross-program invocations (CPI).
+///
+/// The supported instructions of `meta.innerInstructions` are parsed as in `parse_transaction`,
+/// each yielding a transfer tagged with the program that invoked it. Like the top level ones, the
+/// instructions of failed transactions made no transfer, as their effects were rolled back.
+///
+/// # Arguments
+///
+/// * `transaction` - A reference to an `EncodedTransaction` to be parsed.
+/// * `meta` - The optional status meta of the transaction, holding its inner instructions.
+/// * `timestamp` - An optional `i64` timestamp associated with the transaction.
+///
+/// # Returns
+///
+/// This function returns the inner transfers in execution order.
+///
+/// # Errors
+///
+/// This function returns an error if the transaction is not JSON encoded, or if the
+/// deserialization of transfer information fails. The inner instructions of raw messages are not
+/// decoded, so they yield no transfers.
+pub fn parse_inner_transfers(
+	transaction: &EncodedTransaction,
+	meta: Option<&UiTransactionStatusMeta>,
+	timestamp: Option<i64>,
+) -> Result<Vec<TransactionDetails>, Box<dyn Error + Send + Sync>> {
+	let Some(meta) = meta.filter(|meta| meta.err.is_none()) else { return Ok(Vec::new()) };
+	let OptionSerializer::Some(inner_instructions) = &meta.inner_instructions else {
+		return Ok(Vec::new())
+	};
+	let EncodedTransaction::Json(ui_transaction) = transaction else {
+		return Err("Unsupported transaction encoding".into())
+	};
+	let UiMessage::Parsed(message) = &ui_transaction.message else { return Ok(Vec::new()) };
+
+	let owners = get_token_account_owners(transaction, meta);
+	let mut transfers = Vec::new();
+	for inner in inner_instructions {
+		// The programs of the call stack, by stack height minus one
+		let mut callers: Vec<Option<String>> =
+			vec![get_instruction_program_id(transaction, inner.index as usize)];
+		for instruction in &inner.instructions {
+			let height = instruction_stack_height(instruction).unwrap_or(2).max(2) as usize;
+			callers.truncate(height - 1);
+			let invoked_by = callers.last().cloned().flatten();
+			callers.push(parsed_instruction_program_id(message, instruction));
+
+			if let Some(mut details) =
+				parse_transfer_instruction(instruction, message, Some(meta), timestamp)?
+			{
+				details.invoked_by = invoked_by;
+				resolve_token_owners(&mut details, &owners);
+				transfers.push(details);
 			}
+		}
+	}
+	Ok(transfers)
+}
+
+/// Parses a supported transfer instruction, returning `None` for other instructions.
+///
+/// The instructions supported here are listed in `is_decoded_instruction` too.
+fn parse_transfer_instruction(
+	instruction: &UiInstruction,
+	message: &UiParsedMessage,
+	meta: Option<&UiTransactionStatusMeta>,
+	timestamp: Option<i64>,
+) -> Result<Option<TransactionDetails>, Box<dyn Error + Send + Sync>> {
+	let UiInstruction::Parsed(UiParsedInstruction::Parsed(parsed_inst)) = instruction else {
+		return Ok(None)
+	};
+	let instruction_type = parsed_inst.parsed.get("type").and_then(|t| t.as_str()).unwrap_or("");
+	let details = match (parsed_inst.program_id.as_str(), instruction_type) {
+		(SYSTEM_PROGRAM_ID, "transfer" | "transferWithSeed") => {
+			let transfer_info = deserialize_instruction::<TransferInfo>(&parsed_inst.parsed)?;
+			Some(TransactionDetails {
+				sender: transfer_info.info.source,
+				receiver: transfer_info.info.destination,
+				amount: transfer_info.info.lamports,
+				timestamp,
+				kind: TransferKind::Transfer,
+				sender_owner: None,
+				receiver_owner: None,
+				invoked_by: None,
+				tags: Vec::new(),
+			})
+		},
+		(SYSTEM_PROGRAM_ID, "createAccount" | "createAccountWithSeed") => {
+			let create_info = deserialize_instruction::<CreateAccountInfo>(&parsed_inst.parsed)?;
+			Some(TransactionDetails {
+				sender: create_info.info.source,
+				receiver: create_info.info.new_account,
+				amount: create_info.info.lamports,
+				timestamp,
+				kind: TransferKind::RentDeposit,
+				sender_owner: None,
+				receiver_owner: None,
+				invoked_by: None,
+				tags: Vec::new(),
+			})
+		},
+		(TOKEN_PROGRAM_ID | TOKEN_2022_PROGRAM_ID, "closeAccount") => {
+			let close_info = deserialize_instruction::<CloseAccountInfo>(&parsed_inst.parsed)?;
+			let amount = reclaimed_lamports(message, meta, &close_info.info.account);
+			Some(TransactionDetails {
+				sender: close_info.info.account,
+				receiver: close_info.info.destination,
+				amount,
+				timestamp,
+				kind: TransferKind::AccountClose,
+				sender_owner: close_info.info.owner,
+				receiver_owner: None,
+				invoked_by: None,
+				tags: Vec::new(),
+			})
 		},
-		_ => return Err("Unsupported transaction encoding".into()),
+		_ => None,
+	};
+	Ok(details)
+}
+
+/// Sets the owners of the sender and receiver of a transfer when they are known token accounts.
+fn resolve_token_owners(details: &mut TransactionDetails, owners: &BTreeMap<String, String>) {
+	if let Some(owner) = owners.get(&details.sender) {
+		details.sender_owner = Some(owner.clone());
 	}
-	Ok(None)
+	if let Some(owner) = owners.get(&details.receiver) {
+		details.receiver_owner = Some(owner.clone());
+	}
+}
+
+/// Returns the stack height of an inner instruction, if the RPC node reported it.
+fn instruction_stack_height(instruction: &UiInstruction) -> Option<u32> {
+	match instruction {
+		UiInstruction::Parsed(UiParsedInstruction::Parsed(instruction)) => instruction.stack_height,
+		UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(instruction)) =>
+			instruction.stack_height,
+		UiInstruction::Compiled(instruction) => instruction.stack_height,
+	}
+}
+
+/// Deserializes the `parsed` JSON of an instruction into a typed `ParsedInstruction`.
+fn deserialize_instruction<T: DeserializeOwned>(
+	parsed: &serde_json::Value,
+) -> Result<ParsedInstruction<T>, Box<dyn Error + Send + Sync>> {
+	serde_json::from_value(parsed.clone())
+		.map_err(|e| format!("Failed to deserialize transfer info: {}", e).into())
+}
+
+/// Computes the lamports reclaimed from a closed account using the balances in the meta.
+///
+/// Returns 0 if the meta is unavailable or the account is not part of the message.
+fn reclaimed_lamports(
+	message: &UiParsedMessage,
+	meta: Option<&UiTransactionStatusMeta>,
+	account: &str,
+) -> u64 {
+	let Some(meta) = meta else { return 0 };
+	message
+		.account_keys
+		.iter()
+		.position(|key| key.pubkey == account)
+		.and_then(|index| {
+			let pre = meta.pre_balances.get(index)?;
+			let post = meta.post_balances.get(index)?;
+			Some(pre.saturating_sub(*post))
+		})
+		.unwrap_or(0)
 }
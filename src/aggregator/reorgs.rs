@@ -0,0 +1,124 @@
+//! Detection of the reorgs through the chain of parent blockhashes.
+//!
+//! Every block names its parent slot and the blockhash of its parent. Before a block is written,
+//! its parent is compared to the block stored at that slot: a different blockhash means the
+//! stored block belongs to a fork the cluster abandoned, and so do the blocks stored between the
+//! parent and the block, at slots the canonical chain skips. The canonical parent is then fetched
+//! from the RPC node and checked in turn, walking back until a block descends from the stored
+//! chain, at most `MAX_REORG_DEPTH` blocks. The stored blocks of the fork are rolled back along
+//! with their transactions, then the canonical branch is written, oldest first, before the block
+//! itself. A canonical block that cannot be fetched, or lies deeper than the walk, is queued as a
+//! failed slot, and its own parent is checked once it is ingested.
+//!
+//! The stored chain is read through the database writer, so the blocks still queued are part of
+//! it. An ingestion remembers the last block it handed to the writer, and a block descending from
+//! it, as every block does until a reorg, is written without reading the chain.
+use solana_transaction_status::UiConfirmedBlock;
+use std::{error::Error, sync::Mutex};
+use tracing::{info_span, warn, Instrument};
+
+use super::{retrieval::get_block, Ingestion};
+
+/// Maximum number of canonical blocks fetched to find where a fork left the stored chain.
+pub const MAX_REORG_DEPTH: usize = 32;
+
+/// The slot and blockhash of the last block an ingestion handed to the writer.
+#[derive(Debug, Default)]
+pub struct ChainTip(Mutex<Option<(u64, String)>>);
+
+impl ChainTip {
+	/// Returns whether a block is the child of the tip.
+	pub fn is_extended_by(&self, block: &UiConfirmedBlock) -> bool {
+		let tip = self.0.lock().expect("chain tip lock poisoned");
+		tip.as_ref().is_some_and(|(slot, blockhash)| {
+			*slot == block.parent_slot && *blockhash == block.previous_blockhash
+		})
+	}
+
+	/// Moves the tip to a block handed to the writer.
+	pub fn advance(&self, slot: u64, blockhash: &str) {
+		*self.0.lock().expect("chain tip lock poisoned") = Some((slot, blockhash.to_string()));
+	}
+}
+
+/// Makes the stored chain lead to a block about to be written at `slot`, rolling back the blocks
+/// of an abandoned fork and writing the canonical branch in their place.
+///
+/// # Errors
+///
+/// This function returns an error if the database writer has stopped or the stored chain cannot be
+/// read.
+pub async fn follow_chain(
+	ingestion: &Ingestion<'_>,
+	slot: u64,
+	block: &UiConfirmedBlock,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	if ingestion.chain.is_extended_by(block) {
+		return Ok(())
+	}
+
+	let mut forked = Vec::new();
+	let mut canonical: Vec<(u64, UiConfirmedBlock)> = Vec::new();
+	let mut unresolved = None;
+	let (mut child_slot, mut parent_slot) = (slot, block.parent_slot);
+	let mut previous_blockhash = block.previous_blockhash.clone();
+	// The genesis block is its own parent
+	while parent_slot < child_slot {
+		let stored = ingestion.writer.stored_blockhashes(parent_slot..=child_slot - 1).await?;
+		let skipped = stored.iter().map(|(stored_slot, _)| *stored_slot);
+		forked.extend(skipped.filter(|stored_slot| *stored_slot > parent_slot));
+		match stored.first() {
+			Some((stored_slot, blockhash))
+				if *stored_slot == parent_slot && *blockhash != previous_blockhash =>
+				forked.push(parent_slot),
+			_ => break,
+		}
+		if canonical.len() == MAX_REORG_DEPTH {
+			unresolved = Some((parent_slot, "The fork is deeper than the reorg walk".to_string()));
+			break
+		}
+		let fetched = get_block(ingestion.gateway, parent_slot);
+		match fetched.instrument(info_span!("slot", slot = parent_slot)).await {
+			Ok(parent) => {
+				child_slot = parent_slot;
+				parent_slot = parent.parent_slot;
+				previous_blockhash = parent.previous_blockhash.clone();
+				canonical.push((child_slot, parent));
+			},
+			Err(err) => {
+				unresolved = Some((parent_slot, err.to_string()));
+				break
+			},
+		}
+	}
+	if forked.is_empty() {
+		return Ok(())
+	}
+
+	forked.sort_unstable();
+	warn!(
+		slot,
+		fork_slots = ?forked,
+		error_kind = "reorg",
+		"Block does not descend from the stored chain, rolling back the abandoned fork"
+	);
+	ingestion.writer.roll_back(forked).await?;
+	if let Some((slot, error)) = unresolved {
+		warn!(
+			slot,
+			error_kind = "reorg",
+			error = %error,
+			"Canonical block left to the failed slots"
+		);
+		ingestion.writer.mark_failed(slot, &error).await?;
+	}
+	for (slot, block) in canonical.into_iter().rev() {
+		async {
+			ingestion.keep_block(slot, &block).await;
+			ingestion.hand_over(slot, &block).await
+		}
+		.instrument(info_span!("slot", slot))
+		.await?;
+	}
+	Ok(())
+}
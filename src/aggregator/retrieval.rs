@@ -1,41 +1,313 @@
-use crate::types::EpochInfo;
-use log::error;
-use solana_client::{rpc_client::RpcClient, rpc_config::RpcBlockConfig};
-use solana_transaction_status::{UiConfirmedBlock, UiTransactionEncoding};
+// The solana client error is large, but boxing it at every call site would only add noise.
+#![allow(clippy::result_large_err)]
+use crate::{
+	rpc::RpcGateway,
+	types::{Commitment, EpochInfo},
+};
+use serde_json::{json, Value};
+use solana_client::{
+	rpc_client::GetConfirmedSignaturesForAddress2Config,
+	rpc_config::{RpcBlockConfig, RpcTransactionConfig},
+	rpc_request::RpcRequest,
+	rpc_response::{OptionalContext, RpcConfirmedTransactionStatusWithSignature, RpcKeyedAccount},
+};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::{account::Account, epoch_schedule::EpochSchedule, signature::Signature};
+use solana_transaction_status::{
+	EncodedConfirmedTransactionWithStatusMeta, UiConfirmedBlock, UiTransactionEncoding,
+};
 use std::error::Error;
 
-/// Retrieves epoch information from the RPC client.
-pub fn get_epoch_info(client: &RpcClient) -> Result<EpochInfo, Box<dyn Error + Send + Sync>> {
-	let epoch_info = client.get_epoch_info().map_err(|e| {
-		error!("Failed to get epoch info: {}", e);
-		format!("Failed to get epoch info: {}", e)
-	})?;
+/// Retrieves epoch information from the RPC node.
+pub async fn get_epoch_info(
+	gateway: &RpcGateway,
+) -> Result<EpochInfo, Box<dyn Error + Send + Sync>> {
+	let epoch_info = gateway.call("getEpochInfo", |client| client.get_epoch_info()).await?;
 	Ok(EpochInfo {
+		epoch: epoch_info.epoch,
 		absolute_slot: epoch_info.absolute_slot,
 		slot_index: epoch_info.slot_index,
 		slots_in_epoch: epoch_info.slots_in_epoch,
 	})
 }
 
-/// Retrieves a confirmed block from the RPC client for a given slot.
-pub fn get_block(
-	client: &RpcClient,
+/// Retrieves the epoch schedule of the cluster from the RPC node.
+pub async fn get_epoch_schedule(
+	gateway: &RpcGateway,
+) -> Result<EpochSchedule, Box<dyn Error + Send + Sync>> {
+	gateway.call("getEpochSchedule", |client| client.get_epoch_schedule()).await
+}
+
+/// Retrieves the tip of the ingestion from the RPC node: the latest slot at the commitment of the
+/// blocks.
+pub async fn get_tip_slot(gateway: &RpcGateway) -> Result<u64, Box<dyn Error + Send + Sync>> {
+	let commitment = gateway.commitment().block_config();
+	gateway
+		.call("getSlot", |client| client.get_slot_with_commitment(commitment))
+		.await
+}
+
+/// Retrieves the slots of the confirmed blocks within a range, skipping the slots without a block.
+pub async fn get_blocks(
+	gateway: &RpcGateway,
+	start_slot: u64,
+	end_slot: u64,
+) -> Result<Vec<u64>, Box<dyn Error + Send + Sync>> {
+	let commitment = gateway.commitment().block_config();
+	gateway
+		.call("getBlocks", |client| {
+			client.get_blocks_with_commitment(start_slot, Some(end_slot), commitment)
+		})
+		.await
+}
+
+/// Retrieves the first slot the RPC node can still serve blocks for.
+///
+/// This is the highest of `minimumLedgerSlot` and `getFirstAvailableBlock`, since a node may
+/// still hold ledger data for slots whose blocks are gone from its storage.
+pub async fn get_first_available_slot(
+	gateway: &RpcGateway,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+	let minimum_ledger_slot =
+		gateway.call("minimumLedgerSlot", |client| client.minimum_ledger_slot()).await?;
+	let first_available_block = gateway
+		.call("getFirstAvailableBlock", |client| client.get_first_available_block())
+		.await?;
+	Ok(minimum_ledger_slot.max(first_available_block))
+}
+
+/// Retrieves the genesis hash of the network of the RPC node.
+pub async fn get_genesis_hash(
+	gateway: &RpcGateway,
+) -> Result<String, Box<dyn Error + Send + Sync>> {
+	let hash = gateway.call("getGenesisHash", |client| client.get_genesis_hash()).await?;
+	Ok(hash.to_string())
+}
+
+/// The outcome of fetching the block of a slot.
+pub type BlockResult = Result<UiConfirmedBlock, Box<dyn Error + Send + Sync>>;
+
+/// The configuration of the `getBlock` calls: fully parsed transactions, without rewards, at the
+/// commitment of the ingestion.
+///
+/// Version 0 transactions are included along with the legacy ones; the node resolves the accounts
+/// they load from address lookup tables into their parsed messages.
+fn block_config(commitment: Commitment) -> RpcBlockConfig {
+	RpcBlockConfig {
+		encoding: Some(UiTransactionEncoding::JsonParsed),
+		transaction_details: Some(solana_transaction_status::TransactionDetails::Full),
+		rewards: Some(false),
+		commitment: Some(commitment.block_config()),
+		max_supported_transaction_version: Some(0),
+	}
+}
+
+/// Retrieves a confirmed block from the RPC node for a given slot.
+pub async fn get_block(
+	gateway: &RpcGateway,
 	slot: u64,
 ) -> Result<UiConfirmedBlock, Box<dyn Error + Send + Sync>> {
-	let block = client
-		.get_block_with_config(
-			slot,
-			RpcBlockConfig {
-				encoding: Some(UiTransactionEncoding::JsonParsed),
-				transaction_details: Some(solana_transaction_status::TransactionDetails::Full),
-				rewards: Some(false),
-				commitment: None,
-				max_supported_transaction_version: Some(1),
-			},
-		)
-		.map_err(|e| {
-			error!("Failed to get block for slot {}: {}", slot, e);
-			format!("Failed to get block for slot {}: {}", slot, e)
-		})?;
-	Ok(block)
+	let config = block_config(gateway.commitment());
+	gateway
+		.call("getBlock", |client| client.get_block_with_config(slot, config))
+		.await
+}
+
+/// Returns whether a `getBlock` error reports a slot the leader skipped, or one missing from the
+/// ledger of the node, rather than a failure worth retrying.
+pub fn is_skipped_slot(err: &(dyn Error + Send + Sync)) -> bool {
+	err.to_string().contains("was skipped")
+}
+
+/// Retrieves the confirmed blocks of several slots in a single JSON-RPC batch request.
+///
+/// Slots whose call failed with a transient error (block not available yet, node unhealthy, ...)
+/// are fetched again one by one with `get_block`, which retries them.
+///
+/// # Returns
+///
+/// This function returns the result of each slot, in the order of `slots`.
+///
+/// # Errors
+///
+/// This function returns an error if the batch request itself fails.
+pub async fn get_blocks_batch(
+	gateway: &RpcGateway,
+	slots: &[u64],
+) -> Result<Vec<(u64, BlockResult)>, Box<dyn Error + Send + Sync>> {
+	let config = block_config(gateway.commitment());
+	let params: Vec<Value> = slots.iter().map(|slot| json!([slot, config])).collect();
+	let results = gateway.batch_call("getBlock", &params).await?;
+
+	let mut blocks = Vec::with_capacity(slots.len());
+	for (slot, result) in slots.iter().copied().zip(results) {
+		let block = match result {
+			Ok(Value::Null) => Err(format!("Block at slot {} is not available", slot).into()),
+			Ok(block) => serde_json::from_value(block).map_err(Into::into),
+			Err(err) if err.is_transient() => get_block(gateway, slot).await,
+			Err(err) => Err(format!("getBlock failed: {}", err).into()),
+		};
+		blocks.push((slot, block));
+	}
+	Ok(blocks)
+}
+
+/// Retrieves a page of the transaction signatures of an address, newest first.
+pub async fn get_signatures_for_address(
+	gateway: &RpcGateway,
+	address: &Pubkey,
+	before: Option<Signature>,
+) -> Result<Vec<RpcConfirmedTransactionStatusWithSignature>, Box<dyn Error + Send + Sync>> {
+	gateway
+		.call("getSignaturesForAddress", |client| {
+			client.get_signatures_for_address_with_config(
+				address,
+				GetConfirmedSignaturesForAddress2Config {
+					before,
+					until: None,
+					limit: None,
+					commitment: None,
+				},
+			)
+		})
+		.await
+}
+
+/// The metadata of an existing account, without its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountMetadata {
+	pub owner: String,
+	pub executable: bool,
+	/// The size of the account data, `None` if the RPC node does not report it.
+	pub space: Option<u64>,
+}
+
+/// Retrieves the metadata of several accounts with `getMultipleAccounts`, at most 100 at once.
+///
+/// The data of the accounts is sliced away, so only their size is transferred.
+///
+/// # Returns
+///
+/// This function returns the slot the accounts were read at, and the metadata of each account in
+/// the order of `pubkeys`, `None` for the accounts that do not exist.
+pub async fn get_account_metadata(
+	gateway: &RpcGateway,
+	pubkeys: &[Pubkey],
+) -> Result<(u64, Vec<Option<AccountMetadata>>), Box<dyn Error + Send + Sync>> {
+	let params = json!([
+		pubkeys.iter().map(Pubkey::to_string).collect::<Vec<_>>(),
+		{
+			"encoding": "base64",
+			"dataSlice": { "offset": 0, "length": 0 },
+			"commitment": "finalized",
+		},
+	]);
+	let response: Value = gateway
+		.call("getMultipleAccounts", |client| {
+			client.send(RpcRequest::GetMultipleAccounts, params.clone())
+		})
+		.await?;
+
+	let slot = response["context"]["slot"].as_u64().unwrap_or_default();
+	let accounts = response["value"]
+		.as_array()
+		.filter(|accounts| accounts.len() == pubkeys.len())
+		.ok_or_else(|| format!("Unexpected getMultipleAccounts response: {}", response))?;
+	let metadata = accounts
+		.iter()
+		.map(|account| {
+			let owner = account["owner"].as_str()?;
+			Some(AccountMetadata {
+				owner: owner.to_string(),
+				executable: account["executable"].as_bool().unwrap_or_default(),
+				space: account["space"].as_u64(),
+			})
+		})
+		.collect();
+	Ok((slot, metadata))
+}
+
+/// An account owned by a program, with its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramAccount {
+	pub pubkey: String,
+	pub lamports: u64,
+	pub data: Vec<u8>,
+}
+
+/// Retrieves every account owned by a program with `getProgramAccounts`.
+///
+/// # Returns
+///
+/// This function returns the slot the accounts were read at, and the accounts.
+pub async fn get_program_accounts(
+	gateway: &RpcGateway,
+	program_id: &Pubkey,
+) -> Result<(u64, Vec<ProgramAccount>), Box<dyn Error + Send + Sync>> {
+	let params = json!([
+		program_id.to_string(),
+		{ "encoding": "base64", "commitment": "finalized", "withContext": true },
+	]);
+	let response: OptionalContext<Vec<RpcKeyedAccount>> = gateway
+		.call("getProgramAccounts", |client| {
+			client.send(RpcRequest::GetProgramAccounts, params.clone())
+		})
+		.await?;
+	let (slot, accounts) = match response {
+		OptionalContext::Context(response) => (response.context.slot, response.value),
+		OptionalContext::NoContext(accounts) => (0, accounts),
+	};
+	let accounts = accounts
+		.into_iter()
+		.map(|keyed| {
+			let account: Account = keyed
+				.account
+				.decode()
+				.ok_or_else(|| format!("Undecodable data of account {}", keyed.pubkey))?;
+			Ok(ProgramAccount {
+				pubkey: keyed.pubkey,
+				lamports: account.lamports,
+				data: account.data,
+			})
+		})
+		.collect::<Result<_, Box<dyn Error + Send + Sync>>>()?;
+	Ok((slot, accounts))
+}
+
+/// Retrieves a confirmed transaction from the RPC node by signature.
+pub async fn get_transaction(
+	gateway: &RpcGateway,
+	signature: &Signature,
+) -> Result<EncodedConfirmedTransactionWithStatusMeta, Box<dyn Error + Send + Sync>> {
+	gateway
+		.call("getTransaction", |client| {
+			client.get_transaction_with_config(
+				signature,
+				RpcTransactionConfig {
+					encoding: Some(UiTransactionEncoding::JsonParsed),
+					commitment: None,
+					max_supported_transaction_version: Some(0),
+				},
+			)
+		})
+		.await
+}
+
+/// Looks a confirmed transaction up on the RPC node by signature.
+///
+/// Unlike `get_transaction`, a signature the node does not know is reported as `None` right away
+/// rather than failing the call after its retries.
+pub async fn find_transaction(
+	gateway: &RpcGateway,
+	signature: &Signature,
+) -> Result<Option<EncodedConfirmedTransactionWithStatusMeta>, Box<dyn Error + Send + Sync>> {
+	let config = RpcTransactionConfig {
+		encoding: Some(UiTransactionEncoding::JsonParsed),
+		commitment: None,
+		max_supported_transaction_version: Some(0),
+	};
+	let params = json!([signature.to_string(), config]);
+	gateway
+		.call("getTransaction", |client| client.send(RpcRequest::GetTransaction, params.clone()))
+		.await
 }
@@ -0,0 +1,109 @@
+//! Scheduled re-attempts of the slots whose block could not be fetched or parsed.
+//!
+//! The ingestion queues such slots in `failed_slots` instead of abandoning them. A background
+//! task fetches the due ones again through the regular ingestion path, so a recovered block is
+//! archived, cached and written like any other; the writer removes a slot from the queue once its
+//! block is stored, and a new failure schedules the next attempt further away.
+use std::{error::Error, sync::Arc, time::Duration};
+use tracing::{error, info, info_span, Instrument};
+
+use super::{reorgs::ChainTip, retrieval::get_block, Ingestion};
+use crate::{
+	archive::Archiver,
+	db::{
+		failed_slots,
+		pool::{with_connection, DbPool},
+		writer::DbWriter,
+	},
+	rpc::RpcGateway,
+	types::ArchiveConfig,
+};
+
+/// Pause between two scans of the queue for due slots.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum number of slots attempted per scan.
+const BATCH_SIZE: usize = 32;
+
+/// Attempts the ingestion of the queued slots whose next attempt is due.
+///
+/// # Arguments
+///
+/// * `ingestion` - The ingestion path the recovered blocks are written through.
+/// * `pool` - The `DbPool` the queue is read from.
+///
+/// # Returns
+///
+/// This function returns the number of slots attempted.
+///
+/// # Errors
+///
+/// This function returns an error if the queue cannot be read or the database writer has stopped.
+pub async fn retry_failed_slots(
+	ingestion: &Ingestion<'_>,
+	pool: &DbPool,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+	let now = chrono::Utc::now().timestamp();
+	let slots = with_connection(pool, move |conn| {
+		failed_slots::get_due_failed_slots(conn, now, BATCH_SIZE)
+	})
+	.await??;
+	for slot in &slots {
+		async {
+			info!("Re-attempting the ingestion of slot {}", slot);
+			match get_block(ingestion.gateway, *slot).await {
+				Ok(block) => ingestion.ingest_block(*slot, &block).await,
+				Err(err) => ingestion.fail_slot(*slot, "fetch", err.as_ref()).await,
+			}
+		}
+		.instrument(info_span!("slot", slot))
+		.await?;
+	}
+	// The next scan must see the outcome of these attempts
+	ingestion.writer.checkpoint().await?;
+	Ok(slots.len())
+}
+
+/// Keeps re-attempting the queued slots as they become due, scanning the queue every
+/// `POLL_INTERVAL`, or right away while more slots are due than a scan attempts.
+///
+/// # Arguments
+///
+/// * `gateway` - The gateway to the RPC node.
+/// * `pool` - The `DbPool` the queue is read from.
+/// * `writer` - The handle of the database writer actor.
+/// * `archive` - The optional archival configuration, so recovered blocks are archived too.
+pub async fn run_failed_slot_retries(
+	gateway: Arc<RpcGateway>,
+	pool: DbPool,
+	writer: DbWriter,
+	archive: Option<ArchiveConfig>,
+) {
+	let archiver = match archive.map(Archiver::new).transpose() {
+		Ok(archiver) => archiver,
+		Err(err) => {
+			error!("Failed to set up the archival of retried slots: {:?}", err);
+			None
+		},
+	};
+	let ingestion = Ingestion {
+		gateway: &gateway,
+		writer: &writer,
+		archiver: archiver.as_ref(),
+		cache: None,
+		shutdown: None,
+		chain: ChainTip::default(),
+	};
+	loop {
+		match retry_failed_slots(&ingestion, &pool).await {
+			Ok(attempted) if attempted >= BATCH_SIZE => {
+				info!("Re-attempted the ingestion of {} failed slots", attempted);
+				continue
+			},
+			Ok(0) => {},
+			Ok(attempted) => info!("Re-attempted the ingestion of {} failed slots", attempted),
+			Err(err) => error!("Failed to retry failed slots: {:?}", err),
+		}
+		tokio::time::sleep(POLL_INTERVAL).await;
+	}
+}
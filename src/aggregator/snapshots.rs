@@ -0,0 +1,127 @@
+//! Periodic snapshots of the accounts of tracked programs.
+//!
+//! Every interval, the accounts owned by each configured program are read with
+//! `getProgramAccounts` and the changed ones are stored along with the slot they were read at. With
+//! an Anchor IDL configured, the fields of the accounts are decoded and stored too.
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use solana_program::pubkey::Pubkey;
+use std::{error::Error, str::FromStr, sync::Arc, time::Duration};
+use tracing::{error, info};
+
+use super::{
+	idl::Idl,
+	retrieval::{get_program_accounts, ProgramAccount},
+};
+use crate::{
+	db::{
+		account_snapshots::{self, AccountSnapshotRecord},
+		pool::{with_connection, DbPool},
+	},
+	rpc::RpcGateway,
+	server::stats::parse_window,
+	types::AccountSnapshotConfig,
+};
+
+/// Builds the snapshot of an account read at a slot, decoding its data with the IDL if any.
+pub fn snapshot_account(
+	program_id: &str,
+	slot: u64,
+	account: &ProgramAccount,
+	idl: Option<&Idl>,
+	taken_at: i64,
+) -> AccountSnapshotRecord {
+	let data_hash = Sha256::digest(&account.data)
+		.iter()
+		.map(|byte| format!("{:02x}", byte))
+		.collect();
+	let (account_type, decoded) = idl
+		.and_then(|idl| idl.decode_account(&account.data))
+		.map_or((None, None), |(account_type, decoded)| (Some(account_type), Some(decoded)));
+	AccountSnapshotRecord {
+		pubkey: account.pubkey.clone(),
+		slot,
+		program_id: program_id.to_string(),
+		lamports: account.lamports,
+		data_length: account.data.len() as u64,
+		data_hash,
+		account_type,
+		decoded,
+		taken_at,
+	}
+}
+
+/// The snapshots of the accounts of a program, as configured.
+pub struct AccountSnapshotter {
+	program_id: Pubkey,
+	idl: Option<Arc<Idl>>,
+	interval: Duration,
+}
+
+impl AccountSnapshotter {
+	/// Validates the configuration of the snapshots of a program, reading its IDL.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the program ID or the interval is invalid, or if the IDL
+	/// cannot be read.
+	pub fn new(config: &AccountSnapshotConfig) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let program_id = Pubkey::from_str(&config.program_id)
+			.map_err(|_| format!("Invalid program ID: {}", config.program_id))?;
+		let interval = parse_window(&config.interval)?;
+		let idl = config.idl.as_deref().map(Idl::load).transpose()?.map(Arc::new);
+		Ok(Self { program_id, idl, interval: Duration::from_secs(interval.max(1) as u64) })
+	}
+
+	/// Reads the accounts of the program and stores the snapshots of those changed since their
+	/// latest snapshot.
+	///
+	/// # Returns
+	///
+	/// This function returns the number of accounts read and the number of snapshots stored.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the RPC call fails or if the database cannot be written.
+	pub async fn snapshot(
+		&self,
+		gateway: &RpcGateway,
+		pool: &DbPool,
+	) -> Result<(usize, usize), Box<dyn Error + Send + Sync>> {
+		let (slot, accounts) = get_program_accounts(gateway, &self.program_id).await?;
+		let program_id = self.program_id.to_string();
+		let idl = self.idl.clone();
+		let taken_at = Utc::now().timestamp();
+		with_connection(pool, move |conn| -> Result<_, Box<dyn Error + Send + Sync>> {
+			let tx = conn.unchecked_transaction()?;
+			let mut stored = 0;
+			for account in &accounts {
+				let snapshot =
+					snapshot_account(&program_id, slot, account, idl.as_deref(), taken_at);
+				if account_snapshots::insert_account_snapshot(&tx, &snapshot)? {
+					stored += 1;
+				}
+			}
+			tx.commit()?;
+			Ok((accounts.len(), stored))
+		})
+		.await?
+	}
+
+	/// Keeps snapshotting the accounts of the program, pausing for the interval between passes.
+	pub async fn run(self, gateway: Arc<RpcGateway>, pool: DbPool) {
+		loop {
+			match self.snapshot(&gateway, &pool).await {
+				Ok((read, stored)) => info!(
+					"Snapshotted {} of the {} accounts of program {}",
+					stored, read, self.program_id
+				),
+				Err(err) => error!(
+					"Failed to snapshot the accounts of program {}: {:?}",
+					self.program_id, err
+				),
+			}
+			tokio::time::sleep(self.interval).await;
+		}
+	}
+}
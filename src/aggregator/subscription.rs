@@ -0,0 +1,123 @@
+//! Ingestion of the blocks pushed by a `blockSubscribe` subscription, at the commitment of the
+//! ingestion.
+//!
+//! The subscription saves a `getBlock` request per slot while following the tip. Blocks missed
+//! while the connection was down, or never notified, are listed with `getBlocks` and fetched before
+//! the next notified block is written, so the blocks reach the writer in slot order.
+use futures::StreamExt;
+use serde_json::json;
+use solana_client::{
+	nonblocking::pubsub_client::PubsubClient,
+	rpc_config::{RpcBlockSubscribeConfig, RpcBlockSubscribeFilter},
+};
+use std::{error::Error, time::Duration};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use super::{retrieval::get_blocks, Ingestion};
+
+/// Delay before the first attempt to re-establish a dropped subscription.
+pub const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Upper bound of the delay between two subscription attempts.
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Ingests the blocks notified by a block subscription until the connection drops or the shutdown
+/// is requested.
+///
+/// # Arguments
+///
+/// * `url` - The PubSub endpoint of the RPC node.
+/// * `ingestion` - The ingestion path of the blocks; its gateway fetches the slots that were not
+///   notified.
+/// * `next_slot` - The first slot not ingested yet, advanced as blocks are ingested.
+///
+/// # Returns
+///
+/// This function returns whether the subscription was established, so the caller can back off
+/// from an endpoint that does not support block subscriptions.
+///
+/// # Errors
+///
+/// This function returns an error if the database writer has stopped.
+pub async fn ingest_subscribed_blocks(
+	url: &str,
+	ingestion: &Ingestion<'_>,
+	next_slot: &mut u64,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	let client = match PubsubClient::new(url).await {
+		Ok(client) => client,
+		Err(err) => {
+			error!("Failed to connect to PubSub endpoint {}: {:?}", url, err);
+			return Ok(false)
+		},
+	};
+	let config: RpcBlockSubscribeConfig = serde_json::from_value(json!({
+		"commitment": ingestion.gateway.commitment().block_config().commitment,
+		"encoding": "jsonParsed",
+		"transactionDetails": "full",
+		"showRewards": false,
+		"maxSupportedTransactionVersion": 1,
+	}))
+	.expect("valid block subscription config");
+	let (mut updates, _unsubscribe) =
+		match client.block_subscribe(RpcBlockSubscribeFilter::All, Some(config)).await {
+			Ok(subscription) => subscription,
+			Err(err) => {
+				error!("Failed to subscribe to blocks on {}: {:?}", url, err);
+				return Ok(false)
+			},
+		};
+	info!("Subscribed to blocks on {}", url);
+
+	loop {
+		let update = tokio::select! {
+			update = updates.next() => update,
+			_ = ingestion.stopped() => return Ok(true),
+		};
+		let Some(update) = update.map(|update| update.value) else { break };
+		if update.slot < *next_slot {
+			continue
+		}
+		if update.slot > *next_slot {
+			ingest_gap(ingestion, *next_slot, update.slot - 1).await?;
+			// On shutdown the gap stops at a block boundary, and the notified block is left to the
+			// next run
+			if ingestion.is_stopping() {
+				return Ok(true)
+			}
+		}
+		match (update.block, update.err) {
+			(Some(block), _) => {
+				let span = info_span!("slot", slot = update.slot);
+				ingestion.ingest_block(update.slot, &block).instrument(span).await?
+			},
+			(None, err) =>
+				warn!("Block notification without a block at slot {}: {:?}", update.slot, err),
+		}
+		ingestion.writer.advance_sync_state(update.slot).await?;
+		*next_slot = update.slot + 1;
+	}
+
+	warn!("Block subscription on {} ended", url);
+	Ok(true)
+}
+
+/// Fetches the blocks of the slots that were not notified, most of which are usually skipped
+/// slots without a block.
+async fn ingest_gap(
+	ingestion: &Ingestion<'_>,
+	start_slot: u64,
+	end_slot: u64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	match get_blocks(ingestion.gateway, start_slot, end_slot).await {
+		Ok(slots) =>
+			for slot in slots {
+				ingestion.ingest_slots(slot..=slot).await?;
+			},
+		Err(err) => {
+			warn!("Failed to list the blocks from slot {} to {}: {:?}", start_slot, end_slot, err);
+			ingestion.ingest_slots(start_slot..=end_slot).await?;
+		},
+	}
+	Ok(())
+}
@@ -0,0 +1,112 @@
+//! Tagging of the transfers with the configured rules, as they are stored.
+//!
+//! A `[[tagging_rules]]` entry gives its tag to the transfers meeting all of its conditions: an
+//! amount range, a kind, a label of the sender or of the receiver, and a regular expression one of
+//! the memos of the transaction must match. The writer tags the transfers of each block before
+//! storing them, so the stored rows and the published events carry the same tags. The labels are
+//! read once per account and batch, so labels imported through the API apply from the next batch
+//! on; the transfers already stored keep their tags. A transfer carries the tags of the rules it
+//! meets in the order of the rules, each once.
+use rusqlite::Connection;
+use std::{collections::HashMap, error::Error};
+
+use super::processor::{get_memos, ParsedTransaction};
+use crate::{
+	db::labels,
+	types::{TaggingRule, TransactionDetails},
+};
+
+/// Tags the transfers of the transactions written in a batch.
+pub struct TransferTagger<'a> {
+	rules: &'a [TaggingRule],
+	/// The label and the label tags of the accounts looked up so far.
+	labels: HashMap<String, Vec<String>>,
+}
+
+impl<'a> TransferTagger<'a> {
+	pub fn new(rules: &'a [TaggingRule]) -> Self {
+		Self { rules, labels: HashMap::new() }
+	}
+
+	/// Sets the tags of the transfers of a transaction.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the label of an account cannot be read.
+	pub fn tag(
+		&mut self,
+		conn: &Connection,
+		transaction: &mut ParsedTransaction,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		if self.rules.is_empty() {
+			return Ok(())
+		}
+		let rules = self.rules;
+		let memos = if rules.iter().any(|rule| rule.memo.is_some()) {
+			get_memos(&transaction.transaction)
+		} else {
+			Vec::new()
+		};
+		for transfer in transaction.transfers_mut() {
+			let mut tags: Vec<String> = Vec::new();
+			for rule in rules {
+				if !tags.contains(&rule.tag) && self.matches(conn, rule, transfer, &memos)? {
+					tags.push(rule.tag.clone());
+				}
+			}
+			transfer.tags = tags;
+		}
+		Ok(())
+	}
+
+	/// Returns whether a transfer meets the conditions of a rule, looking the labels up last.
+	fn matches(
+		&mut self,
+		conn: &Connection,
+		rule: &TaggingRule,
+		transfer: &TransactionDetails,
+		memos: &[String],
+	) -> Result<bool, Box<dyn Error + Send + Sync>> {
+		let meets = rule.min_amount.is_none_or(|min_amount| transfer.amount >= min_amount) &&
+			rule.max_amount.is_none_or(|max_amount| transfer.amount <= max_amount) &&
+			rule.kind.is_none_or(|kind| kind == transfer.kind) &&
+			rule.memo
+				.as_ref()
+				.is_none_or(|pattern| memos.iter().any(|memo| pattern.0.is_match(memo)));
+		if !meets {
+			return Ok(false)
+		}
+		let sides = [
+			(&rule.sender_label, &transfer.sender, &transfer.sender_owner),
+			(&rule.receiver_label, &transfer.receiver, &transfer.receiver_owner),
+		];
+		for (label, account, owner) in sides {
+			let Some(label) = label else { continue };
+			let labeled = self.has_label(conn, account, label)? ||
+				match owner {
+					Some(owner) => self.has_label(conn, owner, label)?,
+					None => false,
+				};
+			if !labeled {
+				return Ok(false)
+			}
+		}
+		Ok(true)
+	}
+
+	/// Returns whether an account has a label, or a label tag, reading it on its first lookup.
+	fn has_label(
+		&mut self,
+		conn: &Connection,
+		account: &str,
+		label: &str,
+	) -> Result<bool, Box<dyn Error + Send + Sync>> {
+		if !self.labels.contains_key(account) {
+			let names = labels::get_label(conn, account)?
+				.map(|record| [record.label].into_iter().chain(record.tags).collect())
+				.unwrap_or_default();
+			self.labels.insert(account.to_string(), names);
+		}
+		Ok(self.labels[account].iter().any(|name| name == label))
+	}
+}
@@ -0,0 +1,201 @@
+//! Detection of unusual spikes in the transfer volume of the accounts and the failure rate of the
+//! programs.
+//!
+//! The detector follows the event bus and aggregates the stored transactions into samples of
+//! `bucket_slots` consecutive slots: the lamports each account sent and received, and the share of
+//! the transactions that failed in each program. When a sample is complete, each value is compared
+//! with the mean and standard deviation of the last `window` samples of its account or program,
+//! and a value more than `threshold` standard deviations above the mean is published back on the
+//! bus as an `AnomalyDetected` event. Subjects whose past samples are all equal are not judged,
+//! and a subject is forgotten once its window holds only zeros. The events of slots before the
+//! current sample, e.g. from an account backfill, are ignored.
+use serde::Serialize;
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use crate::{
+	events::{Event, EventBus},
+	types::AnomalyConfig,
+};
+
+/// The measure an anomaly was detected on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyMetric {
+	/// The lamports an account sent and received.
+	TransferVolume,
+	/// The share of the stored transactions that failed in a program.
+	FailureRate,
+}
+
+/// A sample far above the recent samples of its account or program.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Anomaly {
+	pub metric: AnomalyMetric,
+	/// The account of a transfer volume, or the program of a failure rate.
+	pub subject: String,
+	/// The first slot of the sample.
+	pub start_slot: u64,
+	/// The last slot of the sample.
+	pub end_slot: u64,
+	pub value: f64,
+	/// The mean of the previous samples.
+	pub mean: f64,
+	/// The standard deviation of the previous samples.
+	pub stddev: f64,
+}
+
+/// Rolling statistics of the accounts and programs, fed with the events of the ingestion.
+pub struct AnomalyDetector {
+	config: AnomalyConfig,
+	/// The index of the sample being aggregated, i.e. its first slot divided by `bucket_slots`.
+	bucket: Option<u64>,
+	transactions: u64,
+	volumes: HashMap<String, u128>,
+	failures: HashMap<String, u64>,
+	/// The last samples of each subject, oldest first.
+	history: HashMap<(AnomalyMetric, String), VecDeque<f64>>,
+}
+
+impl AnomalyDetector {
+	pub fn new(config: AnomalyConfig) -> Self {
+		Self {
+			config,
+			bucket: None,
+			transactions: 0,
+			volumes: HashMap::new(),
+			failures: HashMap::new(),
+			history: HashMap::new(),
+		}
+	}
+
+	/// Adds an event to the current sample.
+	///
+	/// # Returns
+	///
+	/// This function returns the anomalies of the sample the event completes, if any.
+	pub fn observe(&mut self, event: &Event) -> Vec<Anomaly> {
+		let slot = match event {
+			Event::BlockIngested { slot, .. } | Event::TransactionStored { slot, .. } => *slot,
+			Event::TransferStored(transfer) => transfer.block_height,
+			Event::ReorgDetected { .. } | Event::AnomalyDetected(_) => return Vec::new(),
+		};
+		let bucket = slot / self.config.bucket_slots.max(1);
+		let anomalies = match self.bucket {
+			Some(current) if bucket < current => return Vec::new(),
+			Some(current) if bucket > current => self.close_sample(current),
+			_ => Vec::new(),
+		};
+		self.bucket = Some(bucket);
+
+		match event {
+			Event::TransactionStored { error, .. } => {
+				self.transactions += 1;
+				if let Some(program_id) = error.as_ref().and_then(|error| error.program_id.as_ref())
+				{
+					*self.failures.entry(program_id.clone()).or_default() += 1;
+				}
+			},
+			Event::TransferStored(transfer) =>
+				for account in [&transfer.sender, &transfer.receiver] {
+					*self.volumes.entry(account.clone()).or_default() +=
+						u128::from(transfer.amount);
+				},
+			_ => {},
+		}
+		anomalies
+	}
+
+	/// Compares the values of a complete sample with the history of their subjects, then adds them
+	/// to it.
+	fn close_sample(&mut self, bucket: u64) -> Vec<Anomaly> {
+		let bucket_slots = self.config.bucket_slots.max(1);
+		let (start_slot, end_slot) = (bucket * bucket_slots, (bucket + 1) * bucket_slots - 1);
+		let mut values: HashMap<(AnomalyMetric, String), f64> = HashMap::new();
+		for (account, volume) in self.volumes.drain() {
+			values.insert((AnomalyMetric::TransferVolume, account), volume as f64);
+		}
+		let transactions = std::mem::take(&mut self.transactions);
+		for (program_id, failures) in self.failures.drain() {
+			if transactions > 0 {
+				let rate = failures as f64 / transactions as f64;
+				values.insert((AnomalyMetric::FailureRate, program_id), rate);
+			}
+		}
+
+		let mut anomalies = Vec::new();
+		// The tracked subjects absent from the sample had a value of zero
+		self.history.retain(|key, samples| {
+			let value = values.remove(key).unwrap_or_default();
+			if let Some((mean, stddev)) = check_sample(&self.config, samples, value) {
+				let (metric, subject) = (key.0, key.1.clone());
+				anomalies.push(Anomaly {
+					metric,
+					subject,
+					start_slot,
+					end_slot,
+					value,
+					mean,
+					stddev,
+				});
+			}
+			samples.push_back(value);
+			if samples.len() > self.config.window {
+				samples.pop_front();
+			}
+			samples.iter().any(|sample| *sample != 0.0)
+		});
+		// New subjects start being tracked while there is room for them
+		for (key, value) in values {
+			if self.history.len() >= self.config.max_subjects {
+				break
+			}
+			if let Entry::Vacant(entry) = self.history.entry(key) {
+				entry.insert(VecDeque::from([value]));
+			}
+		}
+		anomalies
+	}
+}
+
+/// Returns the mean and standard deviation of the previous samples if a value is an anomaly.
+fn check_sample(config: &AnomalyConfig, samples: &VecDeque<f64>, value: f64) -> Option<(f64, f64)> {
+	if samples.len() < config.min_samples.max(1) {
+		return None
+	}
+	let count = samples.len() as f64;
+	let mean = samples.iter().sum::<f64>() / count;
+	let stddev = (samples.iter().map(|sample| (sample - mean).powi(2)).sum::<f64>() / count).sqrt();
+	(stddev > 0.0 && value > mean + config.threshold * stddev).then_some((mean, stddev))
+}
+
+/// Runs the detector over the events of the bus until it is closed, publishing the anomalies it
+/// finds on the bus.
+pub async fn run_anomaly_detection(config: AnomalyConfig, events: EventBus) {
+	let mut receiver = events.subscribe();
+	let mut detector = AnomalyDetector::new(config);
+	loop {
+		let event = match receiver.recv().await {
+			Ok(event) => event,
+			Err(RecvError::Lagged(missed)) => {
+				warn!("Anomaly detection lagged, {} events skipped", missed);
+				continue
+			},
+			Err(RecvError::Closed) => break,
+		};
+		for anomaly in detector.observe(&event) {
+			warn!(
+				"Anomaly in the {:?} of {} over slots {}-{}: {} against a mean of {} (stddev {})",
+				anomaly.metric,
+				anomaly.subject,
+				anomaly.start_slot,
+				anomaly.end_slot,
+				anomaly.value,
+				anomaly.mean,
+				anomaly.stddev
+			);
+			events.publish(Event::AnomalyDetected(anomaly));
+		}
+	}
+}
@@ -0,0 +1,89 @@
+//! Archival of the raw ingested blocks to S3-compatible object storage.
+//!
+//! Archived blocks allow reprocessing a slot range later (e.g. after a parser change) without
+//! keeping the raw payloads in SQLite or downloading them again from the RPC node.
+use crate::types::{ArchiveCompression, ArchiveConfig, ArchiveLayout};
+use flate2::{write::GzEncoder, Compression};
+use solana_transaction_status::UiConfirmedBlock;
+use std::{error::Error, io::Write};
+
+pub mod s3;
+
+use s3::{S3Client, S3Credentials};
+
+/// Number of slots grouped under a common key prefix with the partitioned layout.
+const SLOTS_PER_PARTITION: u64 = 100_000;
+
+/// Writes the raw JSON of ingested blocks to an S3-compatible bucket.
+pub struct Archiver {
+	client: S3Client,
+	config: ArchiveConfig,
+}
+
+impl Archiver {
+	/// Creates an archiver from its configuration.
+	///
+	/// Credentials missing from the configuration are read from the `AWS_ACCESS_KEY_ID` and
+	/// `AWS_SECRET_ACCESS_KEY` environment variables.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the endpoint is invalid or credentials are missing.
+	pub fn new(config: ArchiveConfig) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let credentials =
+			S3Credentials::resolve(config.access_key_id.clone(), config.secret_access_key.clone())?;
+		let client = S3Client::new(&config.endpoint, &config.bucket, &config.region, credentials)?;
+		Ok(Self { client, config })
+	}
+
+	/// Archives the raw JSON of a block.
+	///
+	/// # Arguments
+	///
+	/// * `slot` - The slot of the block.
+	/// * `block` - The block to archive.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the block cannot be serialized, compressed or uploaded.
+	pub async fn archive_block(
+		&self,
+		slot: u64,
+		block: &UiConfirmedBlock,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		let json = serde_json::to_vec(block)?;
+		let (body, content_encoding) = match self.config.compression {
+			ArchiveCompression::None => (json, None),
+			ArchiveCompression::Gzip => {
+				let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+				encoder.write_all(&json)?;
+				(encoder.finish()?, Some("gzip"))
+			},
+		};
+		let key = object_key(&self.config, slot);
+		self.client.put_object(&key, body, "application/json", content_encoding).await
+	}
+}
+
+/// Returns the key a block is archived under, following the configured prefix and layout.
+pub fn object_key(config: &ArchiveConfig, slot: u64) -> String {
+	let extension = match config.compression {
+		ArchiveCompression::None => "json",
+		ArchiveCompression::Gzip => "json.gz",
+	};
+	let name = match config.layout {
+		ArchiveLayout::Flat => format!("{:012}.{}", slot, extension),
+		ArchiveLayout::Partitioned => format!(
+			"{:012}/{:012}.{}",
+			slot / SLOTS_PER_PARTITION * SLOTS_PER_PARTITION,
+			slot,
+			extension
+		),
+	};
+	let prefix = config.prefix.trim_matches('/');
+	if prefix.is_empty() {
+		name
+	} else {
+		format!("{}/{}", prefix, name)
+	}
+}
@@ -0,0 +1,215 @@
+//! Minimal S3-compatible client uploading objects with AWS Signature Version 4.
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, env, error::Error};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Credentials used to sign the requests.
+#[derive(Clone)]
+pub struct S3Credentials {
+	pub access_key_id: String,
+	pub secret_access_key: String,
+}
+
+impl S3Credentials {
+	/// Returns the configured credentials, the missing ones read from the `AWS_ACCESS_KEY_ID` and
+	/// `AWS_SECRET_ACCESS_KEY` environment variables.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if a credential is neither configured nor in the environment.
+	pub fn resolve(
+		access_key_id: Option<String>,
+		secret_access_key: Option<String>,
+	) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let access_key_id = access_key_id
+			.or_else(|| env::var("AWS_ACCESS_KEY_ID").ok())
+			.ok_or("Missing S3 access key id")?;
+		let secret_access_key = secret_access_key
+			.or_else(|| env::var("AWS_SECRET_ACCESS_KEY").ok())
+			.ok_or("Missing S3 secret access key")?;
+		Ok(Self { access_key_id, secret_access_key })
+	}
+}
+
+/// A client writing objects to a bucket of an S3-compatible endpoint, using path-style URLs.
+pub struct S3Client {
+	http: reqwest::Client,
+	endpoint: reqwest::Url,
+	bucket: String,
+	region: String,
+	credentials: S3Credentials,
+}
+
+impl S3Client {
+	pub fn new(
+		endpoint: &str,
+		bucket: &str,
+		region: &str,
+		credentials: S3Credentials,
+	) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let endpoint = reqwest::Url::parse(endpoint)
+			.map_err(|e| format!("Invalid S3 endpoint {}: {}", endpoint, e))?;
+		if endpoint.host_str().is_none() {
+			return Err(format!("S3 endpoint {} has no host", endpoint).into())
+		}
+		Ok(Self {
+			http: reqwest::Client::new(),
+			endpoint,
+			bucket: bucket.to_string(),
+			region: region.to_string(),
+			credentials,
+		})
+	}
+
+	/// Uploads an object to the bucket.
+	///
+	/// # Arguments
+	///
+	/// * `key` - The key of the object within the bucket.
+	/// * `body` - The content of the object.
+	/// * `content_type` - The `Content-Type` stored with the object.
+	/// * `content_encoding` - The optional `Content-Encoding` stored with the object.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the request fails or the endpoint rejects the upload.
+	pub async fn put_object(
+		&self,
+		key: &str,
+		body: Vec<u8>,
+		content_type: &str,
+		content_encoding: Option<&str>,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		let path = format!(
+			"{}/{}/{}",
+			self.endpoint.path().trim_end_matches('/'),
+			uri_encode(&self.bucket, false),
+			uri_encode(key, true)
+		);
+		let host = match self.endpoint.port() {
+			Some(port) => format!("{}:{}", self.endpoint.host_str().unwrap_or_default(), port),
+			None => self.endpoint.host_str().unwrap_or_default().to_string(),
+		};
+		let payload_hash = hex(&Sha256::digest(&body));
+		let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+		let mut headers = BTreeMap::new();
+		headers.insert("content-type".to_string(), content_type.to_string());
+		if let Some(content_encoding) = content_encoding {
+			headers.insert("content-encoding".to_string(), content_encoding.to_string());
+		}
+		headers.insert("x-amz-content-sha256".to_string(), payload_hash.clone());
+		headers.insert("x-amz-date".to_string(), amz_date.clone());
+
+		let authorization = authorization_header(
+			&self.credentials,
+			&self.region,
+			"PUT",
+			&host,
+			&path,
+			&headers,
+			&payload_hash,
+			&amz_date,
+		);
+
+		let mut url = self.endpoint.clone();
+		url.set_path(&path);
+		let mut request = self.http.put(url).header("authorization", authorization).body(body);
+		for (name, value) in &headers {
+			request = request.header(name.as_str(), value.as_str());
+		}
+
+		let response = request.send().await?;
+		if !response.status().is_success() {
+			let status = response.status();
+			let message = response.text().await.unwrap_or_default();
+			return Err(format!("Failed to upload {}: {} {}", key, status, message).into())
+		}
+		Ok(())
+	}
+}
+
+/// Computes the AWS Signature Version 4 `Authorization` header of an S3 request.
+///
+/// # Arguments
+///
+/// * `credentials` - The credentials to sign the request with.
+/// * `region` - The region of the endpoint.
+/// * `method` - The HTTP method of the request.
+/// * `host` - The `Host` header of the request.
+/// * `path` - The URI-encoded path of the request.
+/// * `headers` - The other signed headers, with lowercase names.
+/// * `payload_hash` - The hex encoded SHA-256 of the payload.
+/// * `amz_date` - The request time, formatted as `YYYYMMDD'T'HHMMSS'Z'`.
+///
+/// # Returns
+///
+/// This function returns the value of the `Authorization` header.
+#[allow(clippy::too_many_arguments)]
+pub fn authorization_header(
+	credentials: &S3Credentials,
+	region: &str,
+	method: &str,
+	host: &str,
+	path: &str,
+	headers: &BTreeMap<String, String>,
+	payload_hash: &str,
+	amz_date: &str,
+) -> String {
+	let mut signed = headers.clone();
+	signed.insert("host".to_string(), host.to_string());
+	let canonical_headers: String = signed
+		.iter()
+		.map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+		.collect();
+	let signed_headers = signed.keys().cloned().collect::<Vec<_>>().join(";");
+
+	let canonical_request = format!(
+		"{}\n{}\n\n{}\n{}\n{}",
+		method, path, canonical_headers, signed_headers, payload_hash
+	);
+	let date = &amz_date[..8];
+	let scope = format!("{}/{}/s3/aws4_request", date, region);
+	let string_to_sign = format!(
+		"AWS4-HMAC-SHA256\n{}\n{}\n{}",
+		amz_date,
+		scope,
+		hex(&Sha256::digest(canonical_request.as_bytes()))
+	);
+
+	let secret = format!("AWS4{}", credentials.secret_access_key);
+	let signing_key = [date, region, "s3", "aws4_request"]
+		.iter()
+		.fold(secret.into_bytes(), |key, part| hmac_sha256(&key, part.as_bytes()));
+	let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+	format!(
+		"AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+		credentials.access_key_id, scope, signed_headers, signature
+	)
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+	let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+	mac.update(data);
+	mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// URI-encodes a string as required by Signature Version 4, optionally keeping `/` as is.
+fn uri_encode(value: &str, keep_slash: bool) -> String {
+	value
+		.bytes()
+		.map(|byte| match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' =>
+				(byte as char).to_string(),
+			b'/' if keep_slash => "/".to_string(),
+			byte => format!("%{:02X}", byte),
+		})
+		.collect()
+}
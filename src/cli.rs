@@ -0,0 +1,289 @@
+//! Command line of the service.
+//!
+//! The configuration is read from `config.toml`, or the file given with `--config`. Its settings
+//! are overridden by the `AGG_` environment variables, themselves overridden by the flags, so a
+//! containerized deployment changes them without baking its own configuration file into the image.
+//!
+//! The `backfill` subcommand ingests a range of past slots into the database and exits, instead of
+//! running the service. The `export` subcommand writes stored tables to CSV or Parquet files and
+//! exits. The `export-snapshot` and `import-snapshot` subcommands write a snapshot of the database
+//! to a file and read it back into another database, to seed a new instance.
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command, ValueSource};
+use std::{error::Error, ops::RangeInclusive};
+use toml::{value::Table, Value};
+
+use crate::{
+	db::TransactionFilter,
+	exports::{
+		snapshot::{SnapshotExport, SnapshotImport},
+		tables::{ExportTable, TableExport},
+	},
+	types::Config,
+};
+
+/// Describes the arguments of the service.
+pub fn command() -> Command<'static> {
+	Command::new("solana_data_aggregator")
+		.version(env!("CARGO_PKG_VERSION"))
+		.about("Aggregates the transactions of a Solana cluster and serves them over HTTP")
+		.arg(
+			Arg::new("config")
+				.long("config")
+				.value_name("PATH")
+				.takes_value(true)
+				.default_value("config.toml")
+				.help("Path of the configuration file"),
+		)
+		.arg(
+			Arg::new("rpc-url")
+				.long("rpc-url")
+				.value_name("URL")
+				.takes_value(true)
+				.help("Overrides the rpc_url of the configuration"),
+		)
+		.arg(
+			Arg::new("server-address")
+				.long("server-address")
+				.value_name("ADDRESS")
+				.takes_value(true)
+				.help("Overrides the server_address of the configuration"),
+		)
+		.arg(
+			Arg::new("db-path")
+				.long("db-path")
+				.value_name("PATH")
+				.takes_value(true)
+				.help("Overrides the db_path of the configuration"),
+		)
+		.arg(
+			Arg::new("retry-attempts")
+				.long("retry-attempts")
+				.value_name("COUNT")
+				.takes_value(true)
+				.value_parser(value_parser!(u8))
+				.help("Overrides the retry_attempts of the configuration"),
+		)
+		.arg(
+			Arg::new("serve-only")
+				.long("serve-only")
+				.action(ArgAction::SetTrue)
+				.conflicts_with("ingest-only")
+				.help("Serves the API over the database without ingesting blocks"),
+		)
+		.arg(
+			Arg::new("ingest-only")
+				.long("ingest-only")
+				.action(ArgAction::SetTrue)
+				.help("Ingests blocks without serving the API"),
+		)
+		.subcommand(
+			Command::new("backfill")
+				.about("Ingests a range of past slots, then exits")
+				.arg(
+					Arg::new("from-slot")
+						.long("from-slot")
+						.value_name("SLOT")
+						.required(true)
+						.value_parser(value_parser!(u64))
+						.help("First slot of the range"),
+				)
+				.arg(
+					Arg::new("to-slot")
+						.long("to-slot")
+						.value_name("SLOT")
+						.required(true)
+						.value_parser(value_parser!(u64))
+						.help("Last slot of the range, inclusive"),
+				),
+		)
+		.subcommand(
+			Command::new("export")
+				.about(
+					"Writes the stored transactions, transfers and accounts to files, then exits",
+				)
+				.arg(
+					Arg::new("output")
+						.long("output")
+						.value_name("DIRECTORY")
+						.required(true)
+						.takes_value(true)
+						.help("Directory the files are written to"),
+				)
+				.arg(
+					Arg::new("format")
+						.long("format")
+						.value_name("FORMAT")
+						.takes_value(true)
+						.value_parser(["csv", "parquet"])
+						.default_value("csv")
+						.help("Format of the files"),
+				)
+				.arg(
+					Arg::new("tables")
+						.long("tables")
+						.value_name("TABLES")
+						.takes_value(true)
+						.use_value_delimiter(true)
+						.value_parser(["transactions", "transfers", "accounts"])
+						.help("Comma-separated tables to export, all by default"),
+				)
+				.arg(
+					Arg::new("from-slot")
+						.long("from-slot")
+						.value_name("SLOT")
+						.value_parser(value_parser!(u64))
+						.help("First slot exported"),
+				)
+				.arg(
+					Arg::new("to-slot")
+						.long("to-slot")
+						.value_name("SLOT")
+						.value_parser(value_parser!(u64))
+						.help("Last slot exported, inclusive"),
+				)
+				.arg(
+					Arg::new("start-time")
+						.long("start-time")
+						.value_name("TIMESTAMP")
+						.value_parser(value_parser!(i64))
+						.help("Unix timestamp from which blocks are exported"),
+				)
+				.arg(
+					Arg::new("end-time")
+						.long("end-time")
+						.value_name("TIMESTAMP")
+						.value_parser(value_parser!(i64))
+						.help("Unix timestamp before which blocks are exported"),
+				),
+		)
+		.subcommand(
+			Command::new("export-snapshot")
+				.about("Writes a snapshot of the database for a range of slots to a file, then exits")
+				.arg(
+					Arg::new("output")
+						.long("output")
+						.value_name("FILE")
+						.required(true)
+						.takes_value(true)
+						.help("File the snapshot is written to"),
+				)
+				.arg(
+					Arg::new("from-slot")
+						.long("from-slot")
+						.value_name("SLOT")
+						.value_parser(value_parser!(u64))
+						.help("First slot of the snapshot, the first stored one by default"),
+				)
+				.arg(
+					Arg::new("to-slot")
+						.long("to-slot")
+						.value_name("SLOT")
+						.value_parser(value_parser!(u64))
+						.help("Last slot of the snapshot, inclusive, the last processed one by default"),
+				),
+		)
+		.subcommand(
+			Command::new("import-snapshot")
+				.about("Writes the rows of a snapshot file to the database, then exits")
+				.arg(
+					Arg::new("input")
+						.long("input")
+						.value_name("FILE")
+						.required(true)
+						.takes_value(true)
+						.help("File the snapshot is read from"),
+				),
+		)
+}
+
+/// Reads the configuration file named on the command line, overridden by the environment
+/// variables and the flags.
+///
+/// The file is optional when it is not named on the command line.
+///
+/// # Errors
+///
+/// This function returns an error if the configuration file cannot be read or parsed, or if a
+/// setting is missing or invalid.
+pub fn load_config(
+	matches: &ArgMatches,
+	vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Config, Box<dyn Error>> {
+	let path = matches.get_one::<String>("config").expect("the config path has a default");
+	let required = matches.value_source("config") != Some(ValueSource::DefaultValue);
+	Config::load(path, required, vars, overrides(matches))
+}
+
+/// Returns the range of slots of the `backfill` subcommand, `None` when the service runs instead.
+///
+/// # Errors
+///
+/// This function returns an error if the range is empty.
+pub fn backfill_range(matches: &ArgMatches) -> Result<Option<RangeInclusive<u64>>, Box<dyn Error>> {
+	let Some(backfill) = matches.subcommand_matches("backfill") else { return Ok(None) };
+	let from_slot = *backfill.get_one::<u64>("from-slot").expect("from-slot is required");
+	let to_slot = *backfill.get_one::<u64>("to-slot").expect("to-slot is required");
+	if from_slot > to_slot {
+		return Err(format!("--from-slot {} is after --to-slot {}", from_slot, to_slot).into())
+	}
+	Ok(Some(from_slot..=to_slot))
+}
+
+/// Returns the export of the `export` subcommand, `None` when the service runs instead.
+pub fn table_export(matches: &ArgMatches) -> Option<TableExport> {
+	let export = matches.subcommand_matches("export")?;
+	let format = export.get_one::<String>("format").expect("the format has a default");
+	let tables = match export.get_many::<String>("tables") {
+		Some(tables) =>
+			tables.map(|table| table.parse().expect("the tables are validated")).collect(),
+		None => ExportTable::ALL.to_vec(),
+	};
+	Some(TableExport {
+		directory: export.get_one::<String>("output").expect("the output is required").into(),
+		format: format.parse().expect("the formats are validated"),
+		tables,
+		filter: TransactionFilter {
+			from_slot: export.get_one::<u64>("from-slot").copied(),
+			to_slot: export.get_one::<u64>("to-slot").copied(),
+			since: export.get_one::<i64>("start-time").copied(),
+			until: export.get_one::<i64>("end-time").copied(),
+		},
+	})
+}
+
+/// Returns the export of the `export-snapshot` subcommand, `None` when the service runs instead.
+pub fn snapshot_export(matches: &ArgMatches) -> Option<SnapshotExport> {
+	let export = matches.subcommand_matches("export-snapshot")?;
+	Some(SnapshotExport {
+		path: export.get_one::<String>("output").expect("the output is required").into(),
+		from_slot: export.get_one::<u64>("from-slot").copied(),
+		to_slot: export.get_one::<u64>("to-slot").copied(),
+	})
+}
+
+/// Returns the import of the `import-snapshot` subcommand, `None` when the service runs instead.
+pub fn snapshot_import(matches: &ArgMatches) -> Option<SnapshotImport> {
+	let import = matches.subcommand_matches("import-snapshot")?;
+	Some(SnapshotImport {
+		path: import.get_one::<String>("input").expect("the input is required").into(),
+	})
+}
+
+/// Returns the settings overridden by the flags of the command line.
+pub fn overrides(matches: &ArgMatches) -> Table {
+	let mut settings = Table::new();
+	for name in ["rpc-url", "server-address", "db-path"] {
+		if let Some(value) = matches.get_one::<String>(name) {
+			settings.insert(name.replace('-', "_"), Value::String(value.clone()));
+		}
+	}
+	if let Some(retry_attempts) = matches.get_one::<u8>("retry-attempts") {
+		settings.insert("retry_attempts".to_string(), Value::Integer((*retry_attempts).into()));
+	}
+	for (name, mode) in [("serve-only", "serve_only"), ("ingest-only", "ingest_only")] {
+		if matches.get_one::<bool>(name).copied().unwrap_or_default() {
+			settings.insert("mode".to_string(), Value::String(mode.to_string()));
+		}
+	}
+	settings
+}
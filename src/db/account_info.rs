@@ -0,0 +1,203 @@
+//! On-chain metadata of the discovered accounts, fetched with `getMultipleAccounts`.
+//!
+//! The owner program, executable flag and data length tell wallets apart from token accounts,
+//! mints and programs, which the transactions alone do not.
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, str::FromStr};
+
+use crate::aggregator::processor::{SYSTEM_PROGRAM_ID, TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID};
+
+/// Size of the data of an SPL Token mint.
+const MINT_DATA_LENGTH: u64 = 82;
+
+/// What an account is, as told by its on-chain metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountKind {
+	/// A system account without data, usually a wallet.
+	Wallet,
+	/// An SPL Token account holding tokens of a mint.
+	TokenAccount,
+	/// An SPL Token mint.
+	Mint,
+	/// An executable program.
+	Program,
+	/// A data account owned by another program.
+	Other,
+	/// The account does not exist on-chain, e.g. it was closed.
+	Closed,
+}
+
+impl AccountKind {
+	/// Classifies an account from its metadata, `None` for an account that does not exist.
+	pub fn classify(owner: Option<&str>, executable: bool, data_length: Option<u64>) -> Self {
+		match owner {
+			None => AccountKind::Closed,
+			Some(_) if executable => AccountKind::Program,
+			Some(SYSTEM_PROGRAM_ID) if data_length.unwrap_or_default() == 0 => AccountKind::Wallet,
+			Some(TOKEN_PROGRAM_ID | TOKEN_2022_PROGRAM_ID) =>
+				if data_length == Some(MINT_DATA_LENGTH) {
+					AccountKind::Mint
+				} else {
+					AccountKind::TokenAccount
+				},
+			Some(_) => AccountKind::Other,
+		}
+	}
+
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			AccountKind::Wallet => "wallet",
+			AccountKind::TokenAccount => "token_account",
+			AccountKind::Mint => "mint",
+			AccountKind::Program => "program",
+			AccountKind::Other => "other",
+			AccountKind::Closed => "closed",
+		}
+	}
+}
+
+impl FromStr for AccountKind {
+	type Err = String;
+
+	fn from_str(kind: &str) -> Result<Self, Self::Err> {
+		match kind {
+			"wallet" => Ok(AccountKind::Wallet),
+			"token_account" => Ok(AccountKind::TokenAccount),
+			"mint" => Ok(AccountKind::Mint),
+			"program" => Ok(AccountKind::Program),
+			"other" => Ok(AccountKind::Other),
+			"closed" => Ok(AccountKind::Closed),
+			_ => Err(format!("Unknown account kind: {}", kind)),
+		}
+	}
+}
+
+/// A record representing the on-chain metadata of an account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountInfoRecord {
+	pub kind: AccountKind,
+	/// The program owning the account, `None` if the account does not exist.
+	pub owner: Option<String>,
+	pub executable: bool,
+	/// The size of the account data, `None` if the RPC node did not report it.
+	pub data_length: Option<u64>,
+	/// The slot the metadata was read at.
+	pub fetched_slot: u64,
+	/// The unix timestamp the metadata was fetched at.
+	pub fetched_at: i64,
+}
+
+/// Initializes the `account_info` table.
+pub fn initialize_account_info(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS account_info (
+            account_id TEXT PRIMARY KEY,
+            kind TEXT NOT NULL,
+            owner TEXT,
+            executable INTEGER NOT NULL,
+            data_length INTEGER,
+            fetched_slot INTEGER NOT NULL,
+            fetched_at INTEGER NOT NULL
+        )",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Inserts or updates the metadata of an account.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `account_id` - A string slice containing the account ID.
+/// * `record` - A reference to the `AccountInfoRecord` of the account.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_account_info(
+	conn: &Connection,
+	account_id: &str,
+	record: &AccountInfoRecord,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	conn.execute(
+		"INSERT OR REPLACE INTO account_info (account_id, kind, owner, executable, data_length, fetched_slot, fetched_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+		params![
+			account_id,
+			record.kind.as_str(),
+			record.owner,
+			record.executable,
+			record.data_length,
+			record.fetched_slot,
+			record.fetched_at
+		],
+	)?;
+	Ok(())
+}
+
+/// Retrieves the metadata of an account.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `account_id` - A string slice containing the account ID.
+///
+/// # Returns
+///
+/// This function returns `None` if the metadata of the account was not fetched yet.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if a stored kind is invalid.
+pub fn get_account_info(
+	conn: &Connection,
+	account_id: &str,
+) -> Result<Option<AccountInfoRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT kind, owner, executable, data_length, fetched_slot, fetched_at
+         FROM account_info WHERE account_id = ?1",
+	)?;
+	let mut rows = stmt.query(params![account_id])?;
+
+	if let Some(row) = rows.next()? {
+		let kind: String = row.get(0)?;
+		Ok(Some(AccountInfoRecord {
+			kind: kind.parse()?,
+			owner: row.get(1)?,
+			executable: row.get(2)?,
+			data_length: row.get(3)?,
+			fetched_slot: row.get(4)?,
+			fetched_at: row.get(5)?,
+		}))
+	} else {
+		Ok(None)
+	}
+}
+
+/// Retrieves the stored accounts whose metadata was not fetched yet, in discovery order.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `limit` - The maximum number of accounts to return.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_accounts_without_info(
+	conn: &Connection,
+	limit: usize,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT account_id FROM accounts
+         WHERE NOT EXISTS (SELECT 1 FROM account_info i WHERE i.account_id = accounts.account_id)
+         ORDER BY first_seen_slot, account_id LIMIT ?1",
+	)?;
+	let accounts = stmt
+		.query_map(params![limit], |row| row.get(0))?
+		.collect::<rusqlite::Result<Vec<String>>>()?;
+	Ok(accounts)
+}
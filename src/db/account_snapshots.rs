@@ -0,0 +1,144 @@
+//! Snapshots of the accounts of tracked programs, giving the state of an account over time rather
+//! than only the transactions touching it.
+//!
+//! A snapshot is stored when the lamports or the data of an account changed since its previous
+//! snapshot, so an account left untouched between two passes takes no extra space.
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::error::Error;
+
+/// A record representing the state of an account at a slot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountSnapshotRecord {
+	pub pubkey: String,
+	/// The slot the account was read at.
+	pub slot: u64,
+	/// The program owning the account.
+	pub program_id: String,
+	pub lamports: u64,
+	pub data_length: u64,
+	/// The SHA-256 hash of the account data, in hex.
+	pub data_hash: String,
+	/// The type of the account in the IDL of the program, `None` without an IDL or if the data
+	/// could not be decoded.
+	pub account_type: Option<String>,
+	/// The fields of the account decoded with the IDL of the program.
+	pub decoded: Option<Value>,
+	/// The unix timestamp the snapshot was taken at.
+	pub taken_at: i64,
+}
+
+/// Initializes the `account_snapshots` table.
+pub fn initialize_account_snapshots(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS account_snapshots (
+            pubkey TEXT NOT NULL,
+            slot INTEGER NOT NULL,
+            program_id TEXT NOT NULL,
+            lamports INTEGER NOT NULL,
+            data_length INTEGER NOT NULL,
+            data_hash TEXT NOT NULL,
+            account_type TEXT,
+            decoded TEXT,
+            taken_at INTEGER NOT NULL,
+            PRIMARY KEY (pubkey, slot)
+        )",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Stores the snapshot of an account, unless the account is unchanged since its latest snapshot.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `snapshot` - A reference to the `AccountSnapshotRecord` to store.
+///
+/// # Returns
+///
+/// This function returns `true` if the snapshot was stored, `false` if the account is unchanged.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_account_snapshot(
+	conn: &Connection,
+	snapshot: &AccountSnapshotRecord,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	let latest: Option<(u64, String)> = conn
+		.prepare_cached(
+			"SELECT lamports, data_hash FROM account_snapshots WHERE pubkey = ?1
+             ORDER BY slot DESC LIMIT 1",
+		)?
+		.query_row(params![snapshot.pubkey], |row| Ok((row.get(0)?, row.get(1)?)))
+		.optional()?;
+	if latest.as_ref() == Some(&(snapshot.lamports, snapshot.data_hash.clone())) {
+		return Ok(false)
+	}
+	let decoded = snapshot.decoded.as_ref().map(Value::to_string);
+	let inserted = conn
+		.prepare_cached(
+			"INSERT OR IGNORE INTO account_snapshots (pubkey, slot, program_id, lamports, data_length, data_hash, account_type, decoded, taken_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+		)?
+		.execute(params![
+			snapshot.pubkey,
+			snapshot.slot,
+			snapshot.program_id,
+			snapshot.lamports,
+			snapshot.data_length,
+			snapshot.data_hash,
+			snapshot.account_type,
+			decoded,
+			snapshot.taken_at,
+		])?;
+	Ok(inserted > 0)
+}
+
+/// Retrieves the snapshots of an account, newest first.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `pubkey` - The account.
+/// * `from_slot` - Only the snapshots taken at or after this slot, if set.
+/// * `to_slot` - Only the snapshots taken at or before this slot, if set.
+/// * `limit` - The maximum number of snapshots returned.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if stored decoded fields are
+/// not JSON.
+pub fn get_account_snapshots(
+	conn: &Connection,
+	pubkey: &str,
+	from_slot: Option<u64>,
+	to_slot: Option<u64>,
+	limit: usize,
+) -> Result<Vec<AccountSnapshotRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT pubkey, slot, program_id, lamports, data_length, data_hash, account_type, decoded, taken_at
+         FROM account_snapshots
+         WHERE pubkey = ?1 AND (?2 IS NULL OR slot >= ?2) AND (?3 IS NULL OR slot <= ?3)
+         ORDER BY slot DESC LIMIT ?4",
+	)?;
+	let mut rows = stmt.query(params![pubkey, from_slot, to_slot, limit as i64])?;
+	let mut snapshots = Vec::new();
+	while let Some(row) = rows.next()? {
+		let decoded: Option<String> = row.get(7)?;
+		snapshots.push(AccountSnapshotRecord {
+			pubkey: row.get(0)?,
+			slot: row.get(1)?,
+			program_id: row.get(2)?,
+			lamports: row.get(3)?,
+			data_length: row.get(4)?,
+			data_hash: row.get(5)?,
+			account_type: row.get(6)?,
+			decoded: decoded.map(|decoded| serde_json::from_str(&decoded)).transpose()?,
+			taken_at: row.get(8)?,
+		});
+	}
+	Ok(snapshots)
+}
@@ -0,0 +1,156 @@
+//! Storage of token and lamport amounts.
+//!
+//! SQLite integers are signed 64-bit, so raw token amounts above `i64::MAX` cannot be stored in an
+//! `INTEGER` column. Amounts are stored as decimal `TEXT` instead, through the `Amount` wrapper,
+//! and read back with `get_amount`. Rows written as integers by older versions are still readable.
+use rusqlite::{
+	types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Type, Value, ValueRef},
+	Connection, Error, OptionalExtension, Result, Row, ToSql,
+};
+
+/// Tables holding amount columns, which used to be declared as `INTEGER`, along with one of these
+/// columns.
+const AMOUNT_TABLES: [(&str, &str); 4] = [
+	("accounts", "estimated_balance"),
+	("transfers", "amount"),
+	("swaps", "amount_in"),
+	("balance_changes", "pre_balance"),
+];
+
+/// An amount of lamports or token base units, stored as decimal text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Amount(pub u128);
+
+impl From<u64> for Amount {
+	fn from(value: u64) -> Self {
+		Amount(value as u128)
+	}
+}
+
+impl From<u128> for Amount {
+	fn from(value: u128) -> Self {
+		Amount(value)
+	}
+}
+
+impl ToSql for Amount {
+	fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
+		Ok(ToSqlOutput::Owned(Value::Text(self.0.to_string())))
+	}
+}
+
+impl FromSql for Amount {
+	fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+		match value {
+			ValueRef::Integer(value) =>
+				u128::try_from(value).map(Amount).map_err(|_| FromSqlError::OutOfRange(value)),
+			ValueRef::Text(text) => std::str::from_utf8(text)
+				.ok()
+				.and_then(|text| text.parse().ok())
+				.map(Amount)
+				.ok_or(FromSqlError::InvalidType),
+			_ => Err(FromSqlError::InvalidType),
+		}
+	}
+}
+
+/// Reads an amount column into an integer type, failing if the amount does not fit.
+///
+/// # Arguments
+///
+/// * `row` - The row to read from.
+/// * `index` - The index of the amount column.
+///
+/// # Errors
+///
+/// This function returns an error if the column is not an amount or if the amount overflows `T`.
+pub fn get_amount<T>(row: &Row, index: usize) -> Result<T>
+where
+	T: TryFrom<u128>,
+	T::Error: std::error::Error + Send + Sync + 'static,
+{
+	let Amount(amount) = row.get(index)?;
+	T::try_from(amount)
+		.map_err(|err| Error::FromSqlConversionFailure(index, Type::Text, Box::new(err)))
+}
+
+/// Moves the tables with legacy `INTEGER` amount columns out of the way.
+///
+/// The tables are renamed to `<table>_legacy` and their indexes dropped, so that the tables are
+/// created again with `TEXT` amount columns. `restore_legacy_tables` then copies the rows back.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn rename_legacy_tables(conn: &Connection) -> Result<()> {
+	for (table, column) in AMOUNT_TABLES {
+		let column_type: Option<String> = conn
+			.prepare(&format!("SELECT type FROM pragma_table_info('{}') WHERE name = ?1", table))?
+			.query_row([column], |row| row.get(0))
+			.optional()?;
+		if !column_type.is_some_and(|column_type| column_type.eq_ignore_ascii_case("INTEGER")) {
+			continue
+		}
+
+		let legacy = format!("{}_legacy", table);
+		conn.execute(&format!("ALTER TABLE {} RENAME TO {}", table, legacy), [])?;
+		let indexes = conn
+			.prepare(
+				"SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = ?1 AND sql IS NOT NULL",
+			)?
+			.query_map([&legacy], |row| row.get::<_, String>(0))?
+			.collect::<Result<Vec<_>>>()?;
+		for index in indexes {
+			conn.execute(&format!("DROP INDEX {}", index), [])?;
+		}
+	}
+	Ok(())
+}
+
+/// Copies the rows of the tables renamed by `rename_legacy_tables` into the new tables.
+///
+/// Integer amounts are converted to text by the column affinity. The legacy tables are dropped
+/// once copied.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn restore_legacy_tables(conn: &Connection) -> Result<()> {
+	for (table, _) in AMOUNT_TABLES {
+		let legacy = format!("{}_legacy", table);
+		let exists: bool = conn.query_row(
+			"SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+			[&legacy],
+			|row| row.get(0),
+		)?;
+		if !exists {
+			continue
+		}
+
+		// Columns added since then are left to their defaults
+		let columns = conn
+			.prepare(&format!("SELECT name FROM pragma_table_info('{}')", legacy))?
+			.query_map([], |row| row.get::<_, String>(0))?
+			.collect::<Result<Vec<_>>>()?
+			.join(", ");
+		let tx = conn.unchecked_transaction()?;
+		tx.execute(
+			&format!(
+				"INSERT OR IGNORE INTO {} ({}) SELECT {} FROM {}",
+				table, columns, columns, legacy
+			),
+			[],
+		)?;
+		tx.execute(&format!("DROP TABLE {}", legacy), [])?;
+		tx.commit()?;
+	}
+	Ok(())
+}
@@ -0,0 +1,408 @@
+//! Storage of the ingested blocks, used to map between slots and wall-clock time and to serve the
+//! header of a block without the RPC node.
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use std::{error::Error, ops::RangeInclusive};
+
+/// A slot along with the time its block was produced.
+pub type BlockTime = (u64, i64);
+
+/// A record representing an ingested block.
+///
+/// The header fields are `None` for the blocks stored before headers were recorded, and for the
+/// slots only written through a backfill, which does not fetch whole blocks.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRecord {
+	pub slot: u64,
+	pub block_time: Option<i64>,
+	pub blockhash: Option<String>,
+	pub previous_blockhash: Option<String>,
+	pub parent_slot: Option<u64>,
+	/// The number of blocks beneath the block in the chain.
+	pub block_height: Option<u64>,
+	/// The number of transactions of the block, parsed or not.
+	pub transaction_count: Option<u32>,
+}
+
+/// A range of slots that could not be ingested because the RPC node no longer has them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnavailableSlotsRecord {
+	pub start_slot: u64,
+	/// The last unavailable slot, inclusive.
+	pub end_slot: u64,
+	pub reason: String,
+	/// The unix timestamp the range was recorded at.
+	pub recorded_at: i64,
+}
+
+/// Initializes the `blocks` and `unavailable_slots` tables.
+///
+/// When the `blocks` table is created on an existing database, it is seeded with the block times
+/// of the stored transactions.
+pub fn initialize_blocks(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS unavailable_slots (
+            start_slot INTEGER,
+            end_slot INTEGER,
+            reason TEXT,
+            recorded_at INTEGER,
+            PRIMARY KEY (start_slot, end_slot)
+        )",
+		[],
+	)?;
+
+	let exists: bool = conn.query_row(
+		"SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'blocks')",
+		[],
+		|row| row.get(0),
+	)?;
+	if exists {
+		return Ok(())
+	}
+
+	conn.execute(
+		"CREATE TABLE blocks (
+            slot INTEGER PRIMARY KEY,
+            block_time INTEGER NOT NULL
+        )",
+		[],
+	)?;
+	conn.execute("CREATE INDEX blocks_by_time ON blocks (block_time)", [])?;
+	conn.execute(
+		"INSERT OR IGNORE INTO blocks (slot, block_time)
+         SELECT block_height, MAX(timestamp) FROM transactions WHERE timestamp > 0 GROUP BY block_height",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Records the header of the ingested blocks, and allows blocks without a time.
+pub(crate) fn add_block_headers(conn: &Connection) -> rusqlite::Result<()> {
+	if super::has_column(conn, "blocks", "blockhash")? {
+		return Ok(())
+	}
+	conn.execute_batch(
+		"CREATE TABLE blocks_with_headers (
+            slot INTEGER PRIMARY KEY,
+            block_time INTEGER,
+            blockhash TEXT,
+            previous_blockhash TEXT,
+            parent_slot INTEGER,
+            block_height INTEGER,
+            transaction_count INTEGER
+        );
+        INSERT INTO blocks_with_headers (slot, block_time) SELECT slot, block_time FROM blocks;
+        DROP TABLE blocks;
+        ALTER TABLE blocks_with_headers RENAME TO blocks;
+        CREATE INDEX blocks_by_time ON blocks (block_time);",
+	)
+}
+
+/// Inserts or updates the time of a block, keeping its stored header.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slot` - The slot of the block.
+/// * `block_time` - The unix timestamp the block was produced at.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_block(
+	conn: &Connection,
+	slot: u64,
+	block_time: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"INSERT INTO blocks (slot, block_time) VALUES (?1, ?2)
+         ON CONFLICT (slot) DO UPDATE SET block_time = excluded.block_time",
+	)?;
+	stmt.execute(params![slot, block_time])?;
+	Ok(())
+}
+
+/// Inserts or updates a block along with its header.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `record` - A reference to the `BlockRecord` to store.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_block_record(
+	conn: &Connection,
+	record: &BlockRecord,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"INSERT OR REPLACE INTO blocks (slot, block_time, blockhash, previous_blockhash, parent_slot, block_height, transaction_count)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+	)?;
+	stmt.execute(params![
+		record.slot,
+		record.block_time,
+		record.blockhash,
+		record.previous_blockhash,
+		record.parent_slot,
+		record.block_height,
+		record.transaction_count
+	])?;
+	Ok(())
+}
+
+/// Retrieves a stored block.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slot` - The slot of the block.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_block(
+	conn: &Connection,
+	slot: u64,
+) -> Result<Option<BlockRecord>, Box<dyn Error + Send + Sync>> {
+	let record = conn
+		.query_row(
+			"SELECT slot, block_time, blockhash, previous_blockhash, parent_slot, block_height, transaction_count
+             FROM blocks WHERE slot = ?1",
+			params![slot],
+			|row| {
+				Ok(BlockRecord {
+					slot: row.get(0)?,
+					block_time: row.get(1)?,
+					blockhash: row.get(2)?,
+					previous_blockhash: row.get(3)?,
+					parent_slot: row.get(4)?,
+					block_height: row.get(5)?,
+					transaction_count: row.get(6)?,
+				})
+			},
+		)
+		.optional()?;
+	Ok(record)
+}
+
+/// Retrieves the blockhashes of the blocks stored within a range of slots, in slot order.
+///
+/// The slots only written through a backfill, which hold no whole block, are left out.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slots` - The range of slots, inclusive.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_blockhashes(
+	conn: &Connection,
+	slots: RangeInclusive<u64>,
+) -> Result<Vec<(u64, String)>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"SELECT slot, blockhash FROM blocks
+         WHERE slot BETWEEN ?1 AND ?2 AND blockhash IS NOT NULL ORDER BY slot",
+	)?;
+	let blockhashes = stmt
+		.query_map(params![slots.start(), slots.end()], |row| Ok((row.get(0)?, row.get(1)?)))?
+		.collect::<rusqlite::Result<Vec<_>>>()?;
+	Ok(blockhashes)
+}
+
+/// Retrieves a page of the signatures of the transactions stored at a slot, in block order.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slot` - The slot of the block.
+/// * `after` - The position and signature of the last transaction of the previous page, if any.
+/// * `limit` - The maximum number of signatures returned.
+///
+/// # Returns
+///
+/// This function returns the signatures along with their position in the block, the index of the
+/// transaction or -1 for the transactions stored without one.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_block_signatures(
+	conn: &Connection,
+	slot: u64,
+	after: Option<(i64, &str)>,
+	limit: usize,
+) -> Result<Vec<(i64, String)>, Box<dyn Error + Send + Sync>> {
+	let (after_position, after_signature) = after.unzip();
+	let mut stmt = conn.prepare(
+		"SELECT position, transaction_id FROM (
+            SELECT t.transaction_id, COALESCE((SELECT MIN(a.transaction_index) FROM account_transactions a WHERE a.transaction_id = t.transaction_id), -1) AS position
+            FROM transactions t WHERE t.block_height = ?1
+         )
+         WHERE ?2 IS NULL OR position > ?2 OR (position = ?2 AND transaction_id > ?3)
+         ORDER BY position, transaction_id LIMIT ?4",
+	)?;
+	let signatures = stmt
+		.query_map(params![slot, after_position, after_signature, limit as i64], |row| {
+			Ok((row.get(0)?, row.get(1)?))
+		})?
+		.collect::<rusqlite::Result<Vec<_>>>()?;
+	Ok(signatures)
+}
+
+/// Counts the transactions stored at a slot.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn count_block_transactions(
+	conn: &Connection,
+	slot: u64,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+	let count = conn.query_row(
+		"SELECT COUNT(*) FROM transactions WHERE block_height = ?1",
+		[slot],
+		|row| row.get(0),
+	)?;
+	Ok(count)
+}
+
+/// Retrieves the first and last slots of the stored blocks, if any.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_stored_slot_range(
+	conn: &Connection,
+) -> Result<Option<(u64, u64)>, Box<dyn Error + Send + Sync>> {
+	let range: (Option<u64>, Option<u64>) =
+		conn.query_row("SELECT MIN(slot), MAX(slot) FROM blocks", [], |row| {
+			Ok((row.get(0)?, row.get(1)?))
+		})?;
+	Ok(range.0.zip(range.1))
+}
+
+/// Retrieves the stored blocks closest to a slot.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slot` - The slot to look around.
+///
+/// # Returns
+///
+/// This function returns the last block at or before the slot and the first block after it, if
+/// any.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_blocks_around_slot(
+	conn: &Connection,
+	slot: u64,
+) -> Result<(Option<BlockTime>, Option<BlockTime>), Box<dyn Error + Send + Sync>> {
+	let before = conn
+		.query_row(
+			"SELECT slot, block_time FROM blocks WHERE slot <= ?1 AND block_time IS NOT NULL ORDER BY slot DESC LIMIT 1",
+			params![slot],
+			|row| Ok((row.get(0)?, row.get(1)?)),
+		)
+		.optional()?;
+	let after = conn
+		.query_row(
+			"SELECT slot, block_time FROM blocks WHERE slot > ?1 AND block_time IS NOT NULL ORDER BY slot LIMIT 1",
+			params![slot],
+			|row| Ok((row.get(0)?, row.get(1)?)),
+		)
+		.optional()?;
+	Ok((before, after))
+}
+
+/// Retrieves the stored blocks closest to a timestamp.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `timestamp` - The unix timestamp to look around.
+///
+/// # Returns
+///
+/// This function returns the last block produced before the timestamp and the first block
+/// produced at or after it, if any.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_blocks_around_time(
+	conn: &Connection,
+	timestamp: i64,
+) -> Result<(Option<BlockTime>, Option<BlockTime>), Box<dyn Error + Send + Sync>> {
+	let before = conn
+		.query_row(
+			"SELECT slot, block_time FROM blocks WHERE block_time < ?1 ORDER BY block_time DESC, slot DESC LIMIT 1",
+			params![timestamp],
+			|row| Ok((row.get(0)?, row.get(1)?)),
+		)
+		.optional()?;
+	let after = conn
+		.query_row(
+			"SELECT slot, block_time FROM blocks WHERE block_time >= ?1 ORDER BY block_time, slot LIMIT 1",
+			params![timestamp],
+			|row| Ok((row.get(0)?, row.get(1)?)),
+		)
+		.optional()?;
+	Ok((before, after))
+}
+
+/// Records a range of slots that could not be ingested.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `record` - A reference to the `UnavailableSlotsRecord` to store.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_unavailable_slots(
+	conn: &Connection,
+	record: &UnavailableSlotsRecord,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	conn.execute(
+		"INSERT OR REPLACE INTO unavailable_slots (start_slot, end_slot, reason, recorded_at) VALUES (?1, ?2, ?3, ?4)",
+		params![record.start_slot, record.end_slot, record.reason, record.recorded_at],
+	)?;
+	Ok(())
+}
+
+/// Retrieves the recorded ranges of unavailable slots, ordered by start slot.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_unavailable_slots(
+	conn: &Connection,
+) -> Result<Vec<UnavailableSlotsRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT start_slot, end_slot, reason, recorded_at FROM unavailable_slots ORDER BY start_slot",
+	)?;
+	let ranges = stmt
+		.query_map([], |row| {
+			Ok(UnavailableSlotsRecord {
+				start_slot: row.get(0)?,
+				end_slot: row.get(1)?,
+				reason: row.get(2)?,
+				recorded_at: row.get(3)?,
+			})
+		})?
+		.collect::<rusqlite::Result<Vec<_>>>()?;
+	Ok(ranges)
+}
@@ -0,0 +1,148 @@
+//! Startup guard against running the service over an incompatible database.
+//!
+//! The database records the schema version it was last initialized with and the genesis hash of
+//! the network it indexes. An older binary must not write to a database upgraded by a newer one,
+//! and a database must not mix the transactions of several networks.
+use rusqlite::{params, Connection, OptionalExtension};
+use std::error::Error;
+
+/// Version of the schema created by `initialize_db`, that of its last migration.
+pub const SCHEMA_VERSION: u32 = super::migrations::LATEST_VERSION;
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+const GENESIS_HASH_KEY: &str = "genesis_hash";
+
+/// Initializes the `metadata` table.
+fn initialize_metadata(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS metadata (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+		[],
+	)?;
+	Ok(())
+}
+
+fn get_metadata(conn: &Connection, key: &str) -> rusqlite::Result<Option<String>> {
+	let exists: bool = conn.query_row(
+		"SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'metadata')",
+		[],
+		|row| row.get(0),
+	)?;
+	if !exists {
+		return Ok(None)
+	}
+	conn.query_row("SELECT value FROM metadata WHERE key = ?1", params![key], |row| row.get(0))
+		.optional()
+}
+
+fn set_metadata(conn: &Connection, key: &str, value: &str) -> rusqlite::Result<()> {
+	initialize_metadata(conn)?;
+	conn.execute(
+		"INSERT OR REPLACE INTO metadata (key, value) VALUES (?1, ?2)",
+		params![key, value],
+	)?;
+	Ok(())
+}
+
+/// Checks that the database was not initialized by a newer version of the service.
+///
+/// This must run before `initialize_db`, which would otherwise alter a newer schema.
+///
+/// # Errors
+///
+/// This function returns an error explaining how to proceed if the database schema is newer than
+/// `SCHEMA_VERSION`, or if the database cannot be read.
+pub fn check_schema_version(conn: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let Some(version) = get_metadata(conn, SCHEMA_VERSION_KEY)? else { return Ok(()) };
+	let version: u32 = version
+		.parse()
+		.map_err(|_| format!("Invalid database schema version: {}", version))?;
+	if version > SCHEMA_VERSION {
+		return Err(format!(
+			"The database schema (version {}) is newer than this binary supports (version {}). \
+			 Upgrade the service, or point it at a database it created.",
+			version, SCHEMA_VERSION
+		)
+		.into())
+	}
+	Ok(())
+}
+
+/// Checks that the database was initialized with the schema of this version of the service.
+///
+/// A serve-only replica runs no migration, so it refuses a database that is not yet upgraded as
+/// well as one upgraded by a newer version.
+///
+/// # Errors
+///
+/// This function returns an error explaining how to proceed if the database schema is missing,
+/// older or newer than `SCHEMA_VERSION`, or if the database cannot be read.
+pub fn check_schema_is_current(conn: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+	check_schema_version(conn)?;
+	let version = get_metadata(conn, SCHEMA_VERSION_KEY)?;
+	if version != Some(SCHEMA_VERSION.to_string()) {
+		return Err(format!(
+			"The database schema (version {}) is older than this binary expects (version {}). \
+			 Run an ingesting instance of this version over the database first.",
+			version.as_deref().unwrap_or("unknown"),
+			SCHEMA_VERSION
+		)
+		.into())
+	}
+	Ok(())
+}
+
+/// Records the schema version of the running binary, once `initialize_db` has upgraded the
+/// database.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn record_schema_version(conn: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+	set_metadata(conn, SCHEMA_VERSION_KEY, &SCHEMA_VERSION.to_string())?;
+	Ok(())
+}
+
+/// Returns the genesis hash of the network the database indexes, `None` before the first check.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn recorded_genesis_hash(
+	conn: &Connection,
+) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+	Ok(get_metadata(conn, GENESIS_HASH_KEY)?)
+}
+
+/// Checks that the database indexes the network of the RPC node, recording its genesis hash on
+/// first use.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `genesis_hash` - The genesis hash reported by the RPC node.
+///
+/// # Errors
+///
+/// This function returns an error explaining how to proceed if the database was indexed from
+/// another network, or if the database operation fails.
+pub fn check_genesis_hash(
+	conn: &Connection,
+	genesis_hash: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	match get_metadata(conn, GENESIS_HASH_KEY)? {
+		Some(recorded) if recorded != genesis_hash => Err(format!(
+			"The database indexes the network with genesis hash {}, but the RPC node reports {}. \
+			 Check `rpc_url`, or use a separate database for each network.",
+			recorded, genesis_hash
+		)
+		.into()),
+		Some(_) => Ok(()),
+		None => {
+			set_metadata(conn, GENESIS_HASH_KEY, genesis_hash)?;
+			Ok(())
+		},
+	}
+}
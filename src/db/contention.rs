@@ -0,0 +1,136 @@
+//! Retries of the statements and transactions failing on database contention.
+//!
+//! Even in WAL mode, the connections contend for the single write lock. A statement waiting for it
+//! fails with `SQLITE_BUSY`, and a transaction that read before writing fails with `SQLITE_BUSY`
+//! right away when another connection committed in between, as waiting cannot refresh its
+//! snapshot. `SQLITE_LOCKED` reports the same kind of conflict on a table of the connection itself.
+//! Rather than surfacing those errors as `500`s, the busy handler installed on every connection
+//! retries a statement waiting for the lock with a jittered backoff, and the write transactions of
+//! the db functions run through `with_retry`, which starts the whole transaction over.
+//!
+//! Both are bounded, after which the error is returned as before. The busy handler cannot carry
+//! state, so the contention is counted process-wide, and served by `/stats/db`.
+use rusqlite::{Connection, ErrorCode};
+use serde::Serialize;
+use std::{
+	collections::hash_map::RandomState,
+	error::Error,
+	hash::{BuildHasher, Hasher},
+	sync::atomic::{AtomicU64, Ordering},
+	thread,
+	time::Duration,
+};
+
+/// Number of times the busy handler lets a statement wait for the lock, about 5 seconds.
+const MAX_BUSY_RETRIES: i32 = 100;
+
+/// Longest wait of the busy handler between two attempts of a statement.
+const MAX_BUSY_DELAY: Duration = Duration::from_millis(50);
+
+/// Number of attempts of a transaction failing on contention.
+const MAX_TRANSACTION_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry of a transaction, doubled on each of the next ones.
+const TRANSACTION_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+static BUSY_WAITS: AtomicU64 = AtomicU64::new(0);
+static TRANSACTION_RETRIES: AtomicU64 = AtomicU64::new(0);
+static EXHAUSTED: AtomicU64 = AtomicU64::new(0);
+
+/// Counters of the contention on the database since the process started.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ContentionStats {
+	/// Statements that found the database locked and waited for it.
+	pub busy_waits: u64,
+	/// Transactions started over after failing on contention.
+	pub transaction_retries: u64,
+	/// Statements and transactions still failing on contention once their retries ran out.
+	pub exhausted: u64,
+}
+
+/// Returns the counters of the contention on the database.
+pub fn contention_stats() -> ContentionStats {
+	ContentionStats {
+		busy_waits: BUSY_WAITS.load(Ordering::Relaxed),
+		transaction_retries: TRANSACTION_RETRIES.load(Ordering::Relaxed),
+		exhausted: EXHAUSTED.load(Ordering::Relaxed),
+	}
+}
+
+/// Installs the busy handler on a connection, in place of a busy timeout.
+pub fn install_busy_handler(conn: &Connection) -> rusqlite::Result<()> {
+	conn.busy_handler(Some(busy_handler))
+}
+
+/// Called by SQLite while a statement finds the database locked, with the number of times it was
+/// called for that statement; the statement is attempted again as long as it returns `true`.
+fn busy_handler(attempts: i32) -> bool {
+	if attempts == 0 {
+		BUSY_WAITS.fetch_add(1, Ordering::Relaxed);
+	}
+	if attempts >= MAX_BUSY_RETRIES {
+		EXHAUSTED.fetch_add(1, Ordering::Relaxed);
+		return false
+	}
+	let delay = Duration::from_millis(1 << attempts.min(6)).min(MAX_BUSY_DELAY);
+	thread::sleep(jittered(delay));
+	true
+}
+
+/// Scales a delay by a random factor between 0.5 and 1.5, so the connections retrying together do
+/// not collide again.
+fn jittered(delay: Duration) -> Duration {
+	let random = RandomState::new().build_hasher().finish();
+	delay.mul_f64(0.5 + (random % 1000) as f64 / 1000.0)
+}
+
+/// An error that may report contention on the database.
+pub trait Contention {
+	/// Returns whether the error is `SQLITE_BUSY` or `SQLITE_LOCKED`.
+	fn is_contention(&self) -> bool;
+}
+
+impl Contention for rusqlite::Error {
+	fn is_contention(&self) -> bool {
+		matches!(
+			self,
+			rusqlite::Error::SqliteFailure(err, _)
+				if matches!(err.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+		)
+	}
+}
+
+impl Contention for Box<dyn Error + Send + Sync> {
+	fn is_contention(&self) -> bool {
+		self.downcast_ref::<rusqlite::Error>().is_some_and(Contention::is_contention)
+	}
+}
+
+/// Runs a transaction, starting it over with a jittered backoff while it fails on contention.
+///
+/// The closure must open and commit the transaction itself, so a failed attempt is rolled back
+/// before the next one starts.
+///
+/// # Errors
+///
+/// This function returns the error of the closure if it is not contention, or if the transaction
+/// still fails on contention after `MAX_TRANSACTION_ATTEMPTS` attempts.
+pub fn with_retry<T>(
+	mut transaction: impl FnMut() -> Result<T, Box<dyn Error + Send + Sync>>,
+) -> Result<T, Box<dyn Error + Send + Sync>> {
+	let mut attempts = 1;
+	loop {
+		match transaction() {
+			Err(err) if err.is_contention() => {
+				if attempts >= MAX_TRANSACTION_ATTEMPTS {
+					EXHAUSTED.fetch_add(1, Ordering::Relaxed);
+					return Err(err)
+				}
+				TRANSACTION_RETRIES.fetch_add(1, Ordering::Relaxed);
+				thread::sleep(jittered(TRANSACTION_RETRY_DELAY * 2u32.pow(attempts - 1)));
+				attempts += 1;
+			},
+			result => return result,
+		}
+	}
+}
@@ -0,0 +1,159 @@
+//! Catalog of the epoch boundaries, so conversions between slots, epochs and time are served from
+//! the database instead of extra RPC calls.
+//!
+//! The slots of an epoch come from the epoch information of the RPC node, and its times from the
+//! stored blocks: the times of the first and last stored blocks of the epoch, which are the times
+//! of its boundaries once the epoch is fully ingested.
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+/// A record representing an epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EpochRecord {
+	pub epoch: u64,
+	pub first_slot: u64,
+	/// The last slot of the epoch, inclusive.
+	pub last_slot: u64,
+	/// The time of the first stored block of the epoch, `None` until a block with a time is.
+	pub start_time: Option<i64>,
+	/// The time of the last stored block of the epoch.
+	pub end_time: Option<i64>,
+}
+
+/// Initializes the `epochs` table.
+pub fn initialize_epochs(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS epochs (
+            epoch INTEGER PRIMARY KEY,
+            first_slot INTEGER NOT NULL,
+            last_slot INTEGER NOT NULL,
+            start_time INTEGER,
+            end_time INTEGER
+        )",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Inserts or updates the slots of an epoch, and sets its times from the stored blocks.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `epoch` - The epoch number.
+/// * `first_slot` - The first slot of the epoch.
+/// * `last_slot` - The last slot of the epoch, inclusive.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_epoch(
+	conn: &Connection,
+	epoch: u64,
+	first_slot: u64,
+	last_slot: u64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	conn.execute(
+		"INSERT INTO epochs (epoch, first_slot, last_slot, start_time, end_time)
+         SELECT ?1, ?2, ?3, MIN(block_time), MAX(block_time) FROM blocks WHERE slot BETWEEN ?2 AND ?3
+         ON CONFLICT (epoch) DO UPDATE SET
+            first_slot = excluded.first_slot,
+            last_slot = excluded.last_slot,
+            start_time = excluded.start_time,
+            end_time = excluded.end_time",
+		params![epoch, first_slot, last_slot],
+	)?;
+	Ok(())
+}
+
+/// Extends the times of the epoch of a slot with the time of a block stored at the slot.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slot` - The slot of the block.
+/// * `block_time` - The time of the block, if the node reported it.
+///
+/// # Returns
+///
+/// This function returns `false` if the epoch of the slot is not catalogued.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn record_block_time(
+	conn: &Connection,
+	slot: u64,
+	block_time: Option<i64>,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	// `MIN` and `MAX` are NULL as soon as one of their arguments is
+	let updated = conn
+		.prepare_cached(
+			"UPDATE epochs SET
+                start_time = COALESCE(MIN(start_time, ?2), start_time, ?2),
+                end_time = COALESCE(MAX(end_time, ?2), end_time, ?2)
+             WHERE first_slot <= ?1 AND last_slot >= ?1",
+		)?
+		.execute(params![slot, block_time])?;
+	Ok(updated > 0)
+}
+
+/// Retrieves the catalogued epochs, newest first.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slot` - Only the epoch holding this slot, if set.
+/// * `timestamp` - Only the epochs whose stored blocks span this unix timestamp, if set.
+/// * `limit` - The maximum number of epochs returned.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_epochs(
+	conn: &Connection,
+	slot: Option<u64>,
+	timestamp: Option<i64>,
+	limit: usize,
+) -> Result<Vec<EpochRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT epoch, first_slot, last_slot, start_time, end_time FROM epochs
+         WHERE (?1 IS NULL OR ?1 BETWEEN first_slot AND last_slot)
+         AND (?2 IS NULL OR ?2 BETWEEN start_time AND end_time)
+         ORDER BY epoch DESC LIMIT ?3",
+	)?;
+	let epochs = stmt
+		.query_map(params![slot, timestamp, limit as i64], read_epoch)?
+		.collect::<rusqlite::Result<Vec<_>>>()?;
+	Ok(epochs)
+}
+
+/// Retrieves a catalogued epoch.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_epoch(
+	conn: &Connection,
+	epoch: u64,
+) -> Result<Option<EpochRecord>, Box<dyn Error + Send + Sync>> {
+	let record = conn
+		.query_row(
+			"SELECT epoch, first_slot, last_slot, start_time, end_time FROM epochs WHERE epoch = ?1",
+			params![epoch],
+			read_epoch,
+		)
+		.optional()?;
+	Ok(record)
+}
+
+fn read_epoch(row: &rusqlite::Row) -> rusqlite::Result<EpochRecord> {
+	Ok(EpochRecord {
+		epoch: row.get(0)?,
+		first_slot: row.get(1)?,
+		last_slot: row.get(2)?,
+		start_time: row.get(3)?,
+		end_time: row.get(4)?,
+	})
+}
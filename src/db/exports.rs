@@ -0,0 +1,246 @@
+//! History of the scheduled exports and the queries they run.
+//!
+//! Each run of an export is recorded when it starts and updated when it ends, with the number of
+//! exported transfers and where the file was written, or the error that stopped it.
+use rusqlite::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+use super::{read_transfer, TransferRecord};
+use crate::types::ExportFilter;
+
+/// The state of a run of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportRunStatus {
+	Running,
+	Succeeded,
+	Failed,
+}
+
+impl ExportRunStatus {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			ExportRunStatus::Running => "running",
+			ExportRunStatus::Succeeded => "succeeded",
+			ExportRunStatus::Failed => "failed",
+		}
+	}
+}
+
+impl std::str::FromStr for ExportRunStatus {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"running" => Ok(ExportRunStatus::Running),
+			"succeeded" => Ok(ExportRunStatus::Succeeded),
+			"failed" => Ok(ExportRunStatus::Failed),
+			_ => Err(format!("Unknown export run status: {}", s)),
+		}
+	}
+}
+
+/// A record representing a run of a scheduled export.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportRunRecord {
+	pub id: i64,
+	pub export_name: String,
+	/// The unix timestamp the run was scheduled at.
+	pub scheduled_at: i64,
+	pub started_at: i64,
+	/// The unix timestamp the run ended at, `None` while it is running.
+	pub finished_at: Option<i64>,
+	pub status: ExportRunStatus,
+	/// The number of exported transfers, once the run succeeded.
+	pub row_count: Option<u64>,
+	/// The path or object URL of the written file, once the run succeeded.
+	pub location: Option<String>,
+	/// The error that stopped the run, once it failed.
+	pub error: Option<String>,
+}
+
+/// Initializes the `export_runs` table.
+pub fn initialize_export_runs(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS export_runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            export_name TEXT NOT NULL,
+            scheduled_at INTEGER NOT NULL,
+            started_at INTEGER NOT NULL,
+            finished_at INTEGER,
+            status TEXT NOT NULL,
+            row_count INTEGER,
+            location TEXT,
+            error TEXT
+        )",
+		[],
+	)?;
+	conn.execute(
+		"CREATE INDEX IF NOT EXISTS idx_export_runs_export_name ON export_runs (export_name, id)",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Records the start of a run of an export.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `export_name` - The name of the export.
+/// * `scheduled_at` - The unix timestamp the run was scheduled at.
+/// * `started_at` - The unix timestamp the run started at.
+///
+/// # Returns
+///
+/// This function returns the id of the run.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn start_export_run(
+	conn: &Connection,
+	export_name: &str,
+	scheduled_at: i64,
+	started_at: i64,
+) -> Result<i64, Box<dyn Error + Send + Sync>> {
+	conn.execute(
+		"INSERT INTO export_runs (export_name, scheduled_at, started_at, status) VALUES (?1, ?2, ?3, ?4)",
+		params![export_name, scheduled_at, started_at, ExportRunStatus::Running.as_str()],
+	)?;
+	Ok(conn.last_insert_rowid())
+}
+
+/// Records the end of a run of an export.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `id` - The id of the run.
+/// * `finished_at` - The unix timestamp the run ended at.
+/// * `outcome` - The number of exported transfers and the location of the file, or the error that
+///   stopped the run.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn finish_export_run(
+	conn: &Connection,
+	id: i64,
+	finished_at: i64,
+	outcome: Result<(u64, String), String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let (status, row_count, location, error) = match outcome {
+		Ok((row_count, location)) =>
+			(ExportRunStatus::Succeeded, Some(row_count), Some(location), None),
+		Err(error) => (ExportRunStatus::Failed, None, None, Some(error)),
+	};
+	conn.execute(
+		"UPDATE export_runs SET finished_at = ?2, status = ?3, row_count = ?4, location = ?5, error = ?6
+         WHERE id = ?1",
+		params![id, finished_at, status.as_str(), row_count, location, error],
+	)?;
+	Ok(())
+}
+
+/// Marks the runs left running by a previous process as failed.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn fail_interrupted_export_runs(
+	conn: &Connection,
+	now: i64,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+	Ok(conn.execute(
+		"UPDATE export_runs SET finished_at = ?1, status = ?2, error = 'Interrupted' WHERE status = ?3",
+		params![now, ExportRunStatus::Failed.as_str(), ExportRunStatus::Running.as_str()],
+	)?)
+}
+
+/// Retrieves the runs of the exports, newest first.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `export_name` - The optional name of the export to list the runs of.
+/// * `limit` - The maximum number of runs to return.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if a stored status is
+/// invalid.
+pub fn get_export_runs(
+	conn: &Connection,
+	export_name: Option<&str>,
+	limit: usize,
+) -> Result<Vec<ExportRunRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT id, export_name, scheduled_at, started_at, finished_at, status, row_count, location, error
+         FROM export_runs WHERE ?1 IS NULL OR export_name = ?1 ORDER BY id DESC LIMIT ?2",
+	)?;
+	let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+	let mut rows = stmt.query(params![export_name, limit])?;
+	let mut runs = Vec::new();
+	while let Some(row) = rows.next()? {
+		runs.push(read_export_run(row)?);
+	}
+	Ok(runs)
+}
+
+fn read_export_run(row: &Row) -> Result<ExportRunRecord, Box<dyn Error + Send + Sync>> {
+	let status: String = row.get(5)?;
+	Ok(ExportRunRecord {
+		id: row.get(0)?,
+		export_name: row.get(1)?,
+		scheduled_at: row.get(2)?,
+		started_at: row.get(3)?,
+		finished_at: row.get(4)?,
+		status: status.parse()?,
+		row_count: row.get(6)?,
+		location: row.get(7)?,
+		error: row.get(8)?,
+	})
+}
+
+/// Reads the transfers matching the filter of an export one at a time, oldest first.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `filter` - The filter of the export.
+/// * `since` - The optional unix timestamp from which transfers are exported.
+/// * `until` - The unix timestamp before which transfers are exported.
+/// * `f` - Called with each transfer.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails, if a stored kind is invalid or
+/// if `f` fails.
+pub fn for_each_exported_transfer(
+	conn: &Connection,
+	filter: &ExportFilter,
+	since: Option<i64>,
+	until: i64,
+	mut f: impl FnMut(TransferRecord) -> Result<(), Box<dyn Error + Send + Sync>>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let (accounts, value) = match filter {
+		ExportFilter::Account(account) => ("SELECT ?1", account),
+		ExportFilter::Group(group) =>
+			("SELECT pubkey FROM group_members WHERE group_name = ?1", group),
+	};
+	let mut stmt = conn.prepare(&format!(
+		"SELECT transaction_id, transfer_index, sender, receiver, amount, timestamp, block_height, kind, sender_owner, receiver_owner, invoked_by, tags
+         FROM transfers
+         WHERE (sender IN ({accounts}) OR receiver IN ({accounts}) OR sender_owner IN ({accounts}) OR receiver_owner IN ({accounts}))
+         AND (?2 IS NULL OR timestamp >= ?2) AND timestamp < ?3
+         ORDER BY block_height, transaction_id, transfer_index",
+		accounts = accounts
+	))?;
+	let mut rows = stmt.query(params![value, since, until])?;
+	while let Some(row) = rows.next()? {
+		f(read_transfer(row)?)?;
+	}
+	Ok(())
+}
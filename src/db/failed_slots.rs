@@ -0,0 +1,234 @@
+//! Queue of the slots whose block could not be fetched or parsed.
+//!
+//! A slot failing after the retries of the RPC gateway is recorded here instead of being
+//! abandoned, and fetched again later with an exponential backoff starting at
+//! `MIN_RETRY_DELAY_SECS` and capped at `MAX_RETRY_DELAY_SECS`. After `MAX_ATTEMPTS` failures a
+//! slot is parked: it stays listed, but is only attempted again once requeued. A slot leaves the
+//! queue as soon as its block is stored.
+use rusqlite::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+/// Delay before the first re-attempt of a failed slot, in seconds.
+pub const MIN_RETRY_DELAY_SECS: i64 = 5 * 60;
+
+/// Maximum delay between two re-attempts of a failed slot, in seconds.
+pub const MAX_RETRY_DELAY_SECS: i64 = 6 * 60 * 60;
+
+/// Number of failed attempts after which a slot is parked until requeued.
+pub const MAX_ATTEMPTS: u32 = 10;
+
+/// A record representing a slot waiting to be ingested again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedSlotRecord {
+	pub slot: u64,
+	/// The error of the last attempt.
+	pub error: String,
+	/// The number of failed attempts, the first fetch included.
+	pub attempts: u32,
+	/// The unix timestamp of the first failure.
+	pub first_failed_at: i64,
+	/// The unix timestamp of the last failure.
+	pub last_failed_at: i64,
+	/// The unix timestamp of the next attempt, `None` once the slot is parked.
+	pub next_attempt_at: Option<i64>,
+}
+
+/// Initializes the `failed_slots` table.
+pub fn initialize_failed_slots(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS failed_slots (
+            slot INTEGER PRIMARY KEY,
+            error TEXT NOT NULL,
+            attempts INTEGER NOT NULL,
+            first_failed_at INTEGER NOT NULL,
+            last_failed_at INTEGER NOT NULL,
+            next_attempt_at INTEGER
+        )",
+		[],
+	)?;
+	conn.execute(
+		"CREATE INDEX IF NOT EXISTS idx_failed_slots_next_attempt_at ON failed_slots (next_attempt_at)",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Returns the delay before the next attempt of a slot that failed `attempts` times, `None` once
+/// the slot is to be parked.
+pub fn retry_delay(attempts: u32) -> Option<i64> {
+	if attempts >= MAX_ATTEMPTS {
+		return None
+	}
+	let exponent = attempts.saturating_sub(1).min(16);
+	Some((MIN_RETRY_DELAY_SECS << exponent).min(MAX_RETRY_DELAY_SECS))
+}
+
+/// Records a failed attempt at ingesting a slot, and schedules the next one.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slot` - The slot that failed.
+/// * `error` - The error of the attempt.
+/// * `failed_at` - The unix timestamp of the failure.
+///
+/// # Returns
+///
+/// This function returns the updated record of the slot.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn record_failed_slot(
+	conn: &Connection,
+	slot: u64,
+	error: &str,
+	failed_at: i64,
+) -> Result<FailedSlotRecord, Box<dyn Error + Send + Sync>> {
+	let previous = get_failed_slot(conn, slot)?;
+	let attempts = previous.as_ref().map_or(0, |record| record.attempts) + 1;
+	let record = FailedSlotRecord {
+		slot,
+		error: error.to_string(),
+		attempts,
+		first_failed_at: previous.map_or(failed_at, |record| record.first_failed_at),
+		last_failed_at: failed_at,
+		next_attempt_at: retry_delay(attempts).map(|delay| failed_at + delay),
+	};
+	conn.execute(
+		"INSERT OR REPLACE INTO failed_slots (slot, error, attempts, first_failed_at, last_failed_at, next_attempt_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+		params![
+			record.slot,
+			record.error,
+			record.attempts,
+			record.first_failed_at,
+			record.last_failed_at,
+			record.next_attempt_at
+		],
+	)?;
+	Ok(record)
+}
+
+/// Removes a slot from the queue, once its block is stored.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn remove_failed_slot(
+	conn: &Connection,
+	slot: u64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	conn.prepare_cached("DELETE FROM failed_slots WHERE slot = ?1")?
+		.execute(params![slot])?;
+	Ok(())
+}
+
+/// Retrieves the queued record of a slot, `None` if the slot is not queued.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_failed_slot(
+	conn: &Connection,
+	slot: u64,
+) -> Result<Option<FailedSlotRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT slot, error, attempts, first_failed_at, last_failed_at, next_attempt_at
+         FROM failed_slots WHERE slot = ?1",
+	)?;
+	let mut rows = stmt.query_map(params![slot], read_failed_slot)?;
+	Ok(rows.next().transpose()?)
+}
+
+/// Retrieves the queued slots, ordered by slot.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `limit` - The maximum number of slots to return.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_failed_slots(
+	conn: &Connection,
+	limit: usize,
+) -> Result<Vec<FailedSlotRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT slot, error, attempts, first_failed_at, last_failed_at, next_attempt_at
+         FROM failed_slots ORDER BY slot LIMIT ?1",
+	)?;
+	let slots = stmt
+		.query_map(params![limit], read_failed_slot)?
+		.collect::<rusqlite::Result<Vec<_>>>()?;
+	Ok(slots)
+}
+
+/// Retrieves the slots whose next attempt is due, oldest schedule first.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `now` - The current unix timestamp.
+/// * `limit` - The maximum number of slots to return.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_due_failed_slots(
+	conn: &Connection,
+	now: i64,
+	limit: usize,
+) -> Result<Vec<u64>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT slot FROM failed_slots WHERE next_attempt_at <= ?1
+         ORDER BY next_attempt_at, slot LIMIT ?2",
+	)?;
+	let slots = stmt
+		.query_map(params![now, limit], |row| row.get(0))?
+		.collect::<rusqlite::Result<Vec<u64>>>()?;
+	Ok(slots)
+}
+
+/// Schedules the next attempt of a queued slot right away, parked or not.
+///
+/// The attempts made so far are kept, so a parked slot is parked again if the attempt fails.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slot` - The slot to requeue.
+/// * `now` - The current unix timestamp.
+///
+/// # Returns
+///
+/// This function returns the updated record, `None` if the slot is not queued.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn requeue_failed_slot(
+	conn: &Connection,
+	slot: u64,
+	now: i64,
+) -> Result<Option<FailedSlotRecord>, Box<dyn Error + Send + Sync>> {
+	conn.execute(
+		"UPDATE failed_slots SET next_attempt_at = ?2 WHERE slot = ?1",
+		params![slot, now],
+	)?;
+	get_failed_slot(conn, slot)
+}
+
+fn read_failed_slot(row: &Row) -> rusqlite::Result<FailedSlotRecord> {
+	Ok(FailedSlotRecord {
+		slot: row.get(0)?,
+		error: row.get(1)?,
+		attempts: row.get(2)?,
+		first_failed_at: row.get(3)?,
+		last_failed_at: row.get(4)?,
+		next_attempt_at: row.get(5)?,
+	})
+}
@@ -0,0 +1,215 @@
+//! Program filters of the ingestion, managed through the admin API.
+//!
+//! A `deny` rule drops the transactions invoking a program, and `allow` rules restrict the stored
+//! transactions to those invoking at least one allowed program, so the filtering policy changes
+//! without a redeploy. A denied program wins over an allowed one. The writer reads the rules at the
+//! start of each batch, so a change applies to the blocks written from then on; the transactions
+//! already stored are kept.
+use rusqlite::{params, Connection, Params};
+use serde::{Deserialize, Serialize};
+
+use super::contention;
+use std::{collections::HashSet, error::Error};
+
+/// What a program filter does with the transactions invoking the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterRule {
+	Allow,
+	Deny,
+}
+
+impl FilterRule {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			FilterRule::Allow => "allow",
+			FilterRule::Deny => "deny",
+		}
+	}
+}
+
+impl std::str::FromStr for FilterRule {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"allow" => Ok(FilterRule::Allow),
+			"deny" => Ok(FilterRule::Deny),
+			_ => Err(format!("Unknown filter rule: {}", s)),
+		}
+	}
+}
+
+/// A record representing the filter of a program.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgramFilterRecord {
+	pub program_id: String,
+	pub rule: FilterRule,
+	/// The unix timestamp of the last change of the rule.
+	pub updated_at: i64,
+}
+
+/// The rules of the program filters, as read by the writer.
+#[derive(Debug, Clone, Default)]
+pub struct ProgramFilters {
+	allowed: HashSet<String>,
+	denied: HashSet<String>,
+}
+
+impl ProgramFilters {
+	/// Returns whether a transaction invoking these programs is stored.
+	pub fn allows<'a>(&self, programs: impl IntoIterator<Item = &'a String>) -> bool {
+		let mut allowed = self.allowed.is_empty();
+		for program in programs {
+			if self.denied.contains(program) {
+				return false
+			}
+			allowed |= self.allowed.contains(program);
+		}
+		allowed
+	}
+
+	/// Returns whether every transaction is stored.
+	pub fn is_empty(&self) -> bool {
+		self.allowed.is_empty() && self.denied.is_empty()
+	}
+}
+
+/// Initializes the `program_filters` table.
+pub fn initialize_program_filters(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS program_filters (
+            program_id TEXT PRIMARY KEY,
+            rule TEXT NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Creates the filter of a program or replaces its rule.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `filter` - A reference to the `ProgramFilterRecord` to store.
+///
+/// # Returns
+///
+/// This function returns `true` if the filter was created, `false` if it was replaced.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn put_program_filter(
+	conn: &Connection,
+	filter: &ProgramFilterRecord,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	contention::with_retry(|| {
+		let tx = conn.unchecked_transaction()?;
+		let created = get_program_filter(&tx, &filter.program_id)?.is_none();
+		tx.execute(
+			"INSERT INTO program_filters (program_id, rule, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (program_id) DO UPDATE SET
+                rule = excluded.rule,
+                updated_at = excluded.updated_at",
+			params![filter.program_id, filter.rule.as_str(), filter.updated_at],
+		)?;
+		tx.commit()?;
+		Ok(created)
+	})
+}
+
+/// Retrieves the filter of a program.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if the stored rule is
+/// invalid.
+pub fn get_program_filter(
+	conn: &Connection,
+	program_id: &str,
+) -> Result<Option<ProgramFilterRecord>, Box<dyn Error + Send + Sync>> {
+	query_program_filters(
+		conn,
+		"SELECT program_id, rule, updated_at FROM program_filters WHERE program_id = ?1",
+		params![program_id],
+	)
+	.map(|filters| filters.into_iter().next())
+}
+
+/// Retrieves the filters of all programs, ordered by program.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if a stored rule is invalid.
+pub fn list_program_filters(
+	conn: &Connection,
+) -> Result<Vec<ProgramFilterRecord>, Box<dyn Error + Send + Sync>> {
+	query_program_filters(
+		conn,
+		"SELECT program_id, rule, updated_at FROM program_filters ORDER BY program_id",
+		[],
+	)
+}
+
+/// Deletes the filter of a program.
+///
+/// # Returns
+///
+/// This function returns `true` if the filter was deleted, `false` if the program had none.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn delete_program_filter(
+	conn: &Connection,
+	program_id: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	let deleted =
+		conn.execute("DELETE FROM program_filters WHERE program_id = ?1", params![program_id])?;
+	Ok(deleted > 0)
+}
+
+/// Reads the rules of the program filters.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if a stored rule is invalid.
+pub fn load_program_filters(
+	conn: &Connection,
+) -> Result<ProgramFilters, Box<dyn Error + Send + Sync>> {
+	let mut filters = ProgramFilters::default();
+	let records = query_program_filters(
+		conn,
+		"SELECT program_id, rule, updated_at FROM program_filters",
+		[],
+	)?;
+	for record in records {
+		match record.rule {
+			FilterRule::Allow => filters.allowed.insert(record.program_id),
+			FilterRule::Deny => filters.denied.insert(record.program_id),
+		};
+	}
+	Ok(filters)
+}
+
+fn query_program_filters(
+	conn: &Connection,
+	sql: &str,
+	params: impl Params,
+) -> Result<Vec<ProgramFilterRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(sql)?;
+	let mut rows = stmt.query(params)?;
+	let mut filters = Vec::new();
+	while let Some(row) = rows.next()? {
+		let rule: String = row.get(1)?;
+		filters.push(ProgramFilterRecord {
+			program_id: row.get(0)?,
+			rule: rule.parse()?,
+			updated_at: row.get(2)?,
+		});
+	}
+	Ok(filters)
+}
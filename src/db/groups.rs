@@ -0,0 +1,230 @@
+//! Storage of named account groups, e.g. the wallets of a treasury.
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use super::{contention, query_signatures, SignatureInfo, TransactionFilter};
+use std::error::Error;
+
+/// A record representing a named group of accounts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupRecord {
+	pub name: String,
+	pub members: Vec<String>,
+}
+
+/// Fee and failure statistics of the transactions paid by the members of a group.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupStats {
+	pub name: String,
+	pub transaction_count: u64,
+	pub failed_transaction_count: u64,
+	pub total_fee: u64,
+	pub average_fee: f64,
+}
+
+/// Initializes the `groups` and `group_members` tables.
+pub fn initialize_groups(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS groups (
+            name TEXT PRIMARY KEY
+        )",
+		[],
+	)?;
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS group_members (
+            group_name TEXT,
+            pubkey TEXT,
+            PRIMARY KEY (group_name, pubkey)
+        )",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Creates a group or replaces the members of an existing one.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `group` - A reference to the `GroupRecord` to store.
+///
+/// # Returns
+///
+/// This function returns `true` if the group was created, `false` if it was replaced.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn put_group(
+	conn: &Connection,
+	group: &GroupRecord,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	contention::with_retry(|| {
+		let tx = conn.unchecked_transaction()?;
+		let created = tx
+			.execute("INSERT OR IGNORE INTO groups (name) VALUES (?1)", params![group.name])? ==
+			1;
+		tx.execute("DELETE FROM group_members WHERE group_name = ?1", params![group.name])?;
+		for member in &group.members {
+			tx.execute(
+				"INSERT OR IGNORE INTO group_members (group_name, pubkey) VALUES (?1, ?2)",
+				params![group.name, member],
+			)?;
+		}
+		tx.commit()?;
+		Ok(created)
+	})
+}
+
+/// Retrieves a group along with its members.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `name` - The name of the group.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing an `Option<GroupRecord>`, `None` if the group does
+/// not exist.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_group(
+	conn: &Connection,
+	name: &str,
+) -> Result<Option<GroupRecord>, Box<dyn Error + Send + Sync>> {
+	let exists: bool = conn.query_row(
+		"SELECT EXISTS (SELECT 1 FROM groups WHERE name = ?1)",
+		params![name],
+		|row| row.get(0),
+	)?;
+	if !exists {
+		return Ok(None)
+	}
+
+	let mut stmt =
+		conn.prepare("SELECT pubkey FROM group_members WHERE group_name = ?1 ORDER BY pubkey")?;
+	let members = stmt
+		.query_map(params![name], |row| row.get(0))?
+		.collect::<rusqlite::Result<Vec<String>>>()?;
+	Ok(Some(GroupRecord { name: name.to_string(), members }))
+}
+
+/// Retrieves all groups along with their members, ordered by name.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn list_groups(conn: &Connection) -> Result<Vec<GroupRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT g.name, m.pubkey FROM groups g
+         LEFT JOIN group_members m ON m.group_name = g.name
+         ORDER BY g.name, m.pubkey",
+	)?;
+	let mut rows = stmt.query([])?;
+
+	let mut groups: Vec<GroupRecord> = Vec::new();
+	while let Some(row) = rows.next()? {
+		let name: String = row.get(0)?;
+		let member: Option<String> = row.get(1)?;
+		match groups.last() {
+			Some(group) if group.name == name => {},
+			_ => groups.push(GroupRecord { name, members: Vec::new() }),
+		}
+		if let (Some(group), Some(member)) = (groups.last_mut(), member) {
+			group.members.push(member);
+		}
+	}
+	Ok(groups)
+}
+
+/// Deletes a group.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `name` - The name of the group.
+///
+/// # Returns
+///
+/// This function returns `true` if the group existed.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn delete_group(conn: &Connection, name: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	contention::with_retry(|| {
+		let tx = conn.unchecked_transaction()?;
+		tx.execute("DELETE FROM group_members WHERE group_name = ?1", params![name])?;
+		let deleted = tx.execute("DELETE FROM groups WHERE name = ?1", params![name])? == 1;
+		tx.commit()?;
+		Ok(deleted)
+	})
+}
+
+/// Retrieves a page of the signatures of the transactions involving any member of a group, newest
+/// first, with the same semantics as `get_account_signatures`.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_group_signatures(
+	conn: &Connection,
+	name: &str,
+	before: Option<&str>,
+	until: Option<&str>,
+	filter: &TransactionFilter,
+	limit: usize,
+) -> Result<Vec<SignatureInfo>, Box<dyn Error + Send + Sync>> {
+	query_signatures(
+		conn,
+		"a.account_id IN (SELECT pubkey FROM group_members WHERE group_name = ?1)",
+		name,
+		before,
+		until,
+		filter,
+		limit,
+	)
+}
+
+/// Retrieves the fee and failure statistics of the transactions paid by the members of a group
+/// since a given timestamp.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `name` - The name of the group.
+/// * `since` - The unix timestamp from which transactions are taken into account.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_group_stats(
+	conn: &Connection,
+	name: &str,
+	since: i64,
+) -> Result<GroupStats, Box<dyn Error + Send + Sync>> {
+	Ok(conn.query_row(
+		"SELECT COUNT(*), COUNT(e.transaction_id), COALESCE(SUM(t.fee), 0), COALESCE(AVG(t.fee), 0.0)
+         FROM transactions t
+         LEFT JOIN transaction_errors e ON e.transaction_id = t.transaction_id
+         WHERE t.timestamp >= ?2
+           AND t.fee_payer IN (SELECT pubkey FROM group_members WHERE group_name = ?1)",
+		params![name, since],
+		|row| {
+			Ok(GroupStats {
+				name: name.to_string(),
+				transaction_count: row.get(0)?,
+				failed_transaction_count: row.get(1)?,
+				total_fee: row.get(2)?,
+				average_fee: row.get(3)?,
+			})
+		},
+	)?)
+}
@@ -0,0 +1,127 @@
+//! Storage of the `Idempotency-Key` of the mutating requests and of the responses they got.
+use rusqlite::{params, Connection};
+use std::error::Error;
+
+/// Number of seconds a key is remembered for; a retry after that runs the request again.
+pub const IDEMPOTENCY_KEY_TTL_SECS: i64 = 24 * 60 * 60;
+
+/// The response recorded for an idempotency key, replayed to the retries of the request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredResponse {
+	pub status: u16,
+	pub content_type: Option<String>,
+	pub body: Vec<u8>,
+}
+
+/// Outcome of the reservation of an idempotency key.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reservation {
+	/// The key was not known; the request must run and its response be recorded.
+	Reserved,
+	/// The same request already completed with the given response.
+	Completed(StoredResponse),
+	/// The same request is still running.
+	InProgress,
+	/// The key was used for a different request.
+	Mismatch,
+}
+
+/// Initializes the `idempotency_keys` table.
+pub fn initialize_idempotency(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS idempotency_keys (
+            key TEXT PRIMARY KEY,
+            fingerprint TEXT NOT NULL,
+            status INTEGER,
+            content_type TEXT,
+            body BLOB,
+            created_at INTEGER NOT NULL
+        )",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Reserves an idempotency key for a request, unless it was already used.
+///
+/// Keys older than `IDEMPOTENCY_KEY_TTL_SECS` are forgotten first.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `key` - The `Idempotency-Key` sent by the client.
+/// * `fingerprint` - A digest of the method, path and body of the request.
+/// * `now` - The current unix timestamp.
+///
+/// # Returns
+///
+/// This function returns the `Reservation` telling whether the request must run.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn reserve_key(
+	conn: &Connection,
+	key: &str,
+	fingerprint: &str,
+	now: i64,
+) -> Result<Reservation, Box<dyn Error + Send + Sync>> {
+	conn.execute(
+		"DELETE FROM idempotency_keys WHERE created_at < ?1",
+		params![now - IDEMPOTENCY_KEY_TTL_SECS],
+	)?;
+	let inserted = conn.execute(
+		"INSERT OR IGNORE INTO idempotency_keys (key, fingerprint, created_at) VALUES (?1, ?2, ?3)",
+		params![key, fingerprint, now],
+	)?;
+	if inserted == 1 {
+		return Ok(Reservation::Reserved)
+	}
+
+	let (stored_fingerprint, status, content_type, body): (
+		String,
+		Option<u16>,
+		Option<String>,
+		Option<Vec<u8>>,
+	) = conn.query_row(
+		"SELECT fingerprint, status, content_type, body FROM idempotency_keys WHERE key = ?1",
+		params![key],
+		|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+	)?;
+	Ok(match status {
+		_ if stored_fingerprint != fingerprint => Reservation::Mismatch,
+		Some(status) => Reservation::Completed(StoredResponse {
+			status,
+			content_type,
+			body: body.unwrap_or_default(),
+		}),
+		None => Reservation::InProgress,
+	})
+}
+
+/// Records the response of a request holding an idempotency key.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn complete_key(
+	conn: &Connection,
+	key: &str,
+	response: &StoredResponse,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	conn.execute(
+		"UPDATE idempotency_keys SET status = ?2, content_type = ?3, body = ?4 WHERE key = ?1",
+		params![key, response.status, response.content_type, response.body],
+	)?;
+	Ok(())
+}
+
+/// Forgets an idempotency key, so a retry of the request runs again.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn release_key(conn: &Connection, key: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+	conn.execute("DELETE FROM idempotency_keys WHERE key = ?1", params![key])?;
+	Ok(())
+}
@@ -0,0 +1,118 @@
+//! Instructions the service does not decode.
+//!
+//! The node only decodes the instructions of well-known programs. For the others, the program id,
+//! the accounts and the base58 encoded data of each top level instruction are kept, so the
+//! transactions touching them can still be told apart. The instructions the node parsed but no
+//! parser of the service decodes keep the JSON of the node in the `parsed` column, verbatim, for
+//! future parsers and for queries with the SQLite JSON functions.
+use rusqlite::{params, Connection};
+use std::error::Error;
+
+use crate::types::ProgramInstruction;
+
+/// Initializes the `program_instructions` table.
+pub fn initialize_program_instructions(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS program_instructions (
+            transaction_id TEXT NOT NULL,
+            instruction_index INTEGER NOT NULL,
+            program_id TEXT NOT NULL,
+            accounts TEXT NOT NULL,
+            data TEXT NOT NULL,
+            block_height INTEGER NOT NULL,
+            PRIMARY KEY (transaction_id, instruction_index)
+        )",
+		[],
+	)?;
+	conn.execute(
+		"CREATE INDEX IF NOT EXISTS idx_program_instructions_program_id
+         ON program_instructions (program_id, block_height)",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Keeps the JSON of the instructions the node parsed.
+pub(crate) fn add_parsed_instructions(conn: &Connection) -> rusqlite::Result<()> {
+	if !super::has_column(conn, "program_instructions", "parsed")? {
+		conn.execute("ALTER TABLE program_instructions ADD COLUMN parsed TEXT", [])?;
+	}
+	Ok(())
+}
+
+/// Inserts or updates an instruction of a transaction.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `transaction_id` - The signature of the transaction.
+/// * `block_height` - The slot of the transaction.
+/// * `instruction` - A reference to the `ProgramInstruction` to store.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_program_instruction(
+	conn: &Connection,
+	transaction_id: &str,
+	block_height: u64,
+	instruction: &ProgramInstruction,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	conn.prepare_cached(
+		"INSERT OR REPLACE INTO program_instructions (transaction_id, instruction_index, program_id, accounts, data, block_height, parsed)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+	)?
+	.execute(params![
+		transaction_id,
+		instruction.index,
+		instruction.program_id,
+		serde_json::to_string(&instruction.accounts)?,
+		instruction.data,
+		block_height,
+		instruction.parsed.as_ref().map(serde_json::to_string).transpose()?
+	])?;
+	Ok(())
+}
+
+/// Retrieves the recorded instructions of a transaction, in execution order.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `transaction_id` - The signature of the transaction.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if stored accounts or JSON
+/// are invalid.
+pub fn get_program_instructions(
+	conn: &Connection,
+	transaction_id: &str,
+) -> Result<Vec<ProgramInstruction>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT instruction_index, program_id, accounts, data, parsed FROM program_instructions
+         WHERE transaction_id = ?1 ORDER BY instruction_index",
+	)?;
+	let rows = stmt
+		.query_map(params![transaction_id], |row| {
+			Ok((
+				row.get(0)?,
+				row.get(1)?,
+				row.get::<_, String>(2)?,
+				row.get(3)?,
+				row.get::<_, Option<String>>(4)?,
+			))
+		})?
+		.collect::<rusqlite::Result<Vec<_>>>()?;
+	rows.into_iter()
+		.map(|(index, program_id, accounts, data, parsed)| {
+			Ok(ProgramInstruction {
+				index,
+				program_id,
+				accounts: serde_json::from_str(&accounts)?,
+				data,
+				parsed: parsed.as_deref().map(serde_json::from_str).transpose()?,
+			})
+		})
+		.collect()
+}
@@ -0,0 +1,144 @@
+//! Storage of the labels attached to known accounts.
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use super::contention;
+use std::{collections::BTreeMap, error::Error};
+
+/// A record representing the label and tags attached to an account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabelRecord {
+	pub pubkey: String,
+	pub label: String,
+	#[serde(default)]
+	pub tags: Vec<String>,
+}
+
+/// Outcome of a bulk label import.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LabelImportSummary {
+	/// Number of rows received, before deduplication.
+	pub received: usize,
+	/// Number of accounts that had no label yet.
+	pub inserted: usize,
+	/// Number of accounts whose existing label was merged with the imported one.
+	pub updated: usize,
+}
+
+/// Initializes the `labels` table.
+pub fn initialize_labels(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS labels (
+            pubkey TEXT PRIMARY KEY,
+            label TEXT,
+            tags TEXT
+        )",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Retrieves the label of an account.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `pubkey` - A string slice containing the account ID.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing an `Option<LabelRecord>`.
+/// The `Option` is `None` if the account has no label.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if deserialization fails.
+pub fn get_label(
+	conn: &Connection,
+	pubkey: &str,
+) -> Result<Option<LabelRecord>, Box<dyn Error + Send + Sync>> {
+	let row = conn
+		.query_row(
+			"SELECT pubkey, label, tags FROM labels WHERE pubkey = ?1",
+			params![pubkey],
+			|row| {
+				Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+			},
+		)
+		.optional()?;
+
+	match row {
+		Some((pubkey, label, tags)) =>
+			Ok(Some(LabelRecord { pubkey, label, tags: serde_json::from_str(&tags)? })),
+		None => Ok(None),
+	}
+}
+
+/// Imports labels in bulk, merging them with the existing ones.
+///
+/// Rows are deduplicated by pubkey before being written: the last label wins and tags are merged.
+/// Imported rows are merged the same way with the labels already stored, so re-importing a
+/// dataset is idempotent. All rows are written within a single transaction.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `records` - The labels to import.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing a `LabelImportSummary`.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if (de)serialization fails.
+pub fn import_labels(
+	conn: &Connection,
+	records: Vec<LabelRecord>,
+) -> Result<LabelImportSummary, Box<dyn Error + Send + Sync>> {
+	let summary = LabelImportSummary { received: records.len(), ..Default::default() };
+
+	let mut deduplicated: BTreeMap<String, LabelRecord> = BTreeMap::new();
+	for record in records {
+		match deduplicated.get_mut(&record.pubkey) {
+			Some(existing) => merge_label(existing, record),
+			None => {
+				deduplicated.insert(record.pubkey.clone(), record);
+			},
+		}
+	}
+
+	contention::with_retry(|| {
+		let mut summary = summary.clone();
+		let tx = conn.unchecked_transaction()?;
+		for record in deduplicated.values() {
+			let record = match get_label(&tx, &record.pubkey)? {
+				Some(mut existing) => {
+					merge_label(&mut existing, record.clone());
+					summary.updated += 1;
+					existing
+				},
+				None => {
+					summary.inserted += 1;
+					record.clone()
+				},
+			};
+			tx.execute(
+				"INSERT OR REPLACE INTO labels (pubkey, label, tags) VALUES (?1, ?2, ?3)",
+				params![record.pubkey, record.label, serde_json::to_string(&record.tags)?],
+			)?;
+		}
+		tx.commit()?;
+		Ok(summary)
+	})
+}
+
+/// Merges a label into an existing one: the label is replaced and the tags are merged.
+fn merge_label(existing: &mut LabelRecord, other: LabelRecord) {
+	existing.label = other.label;
+	for tag in other.tags {
+		if !existing.tags.contains(&tag) {
+			existing.tags.push(tag);
+		}
+	}
+}
@@ -0,0 +1,152 @@
+//! Versioned schema migrations.
+//!
+//! The `schema_version` table records each migration applied to the database. On startup, the
+//! migrations newer than the last recorded one are applied in order, so a release can add columns
+//! and tables to an existing database instead of requiring it to be rebuilt.
+//!
+//! To change the schema, append a migration to `MIGRATIONS` with the next version; released
+//! migrations must never be edited, since the databases they ran on will not run them again.
+use rusqlite::{params, Connection, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// A step of the schema history.
+pub struct Migration {
+	/// The version the schema is at once the migration is applied, starting at 1.
+	pub version: u32,
+	pub description: &'static str,
+	pub apply: fn(&Connection) -> Result<()>,
+}
+
+/// The migrations of the schema, in version order.
+///
+/// The baseline creates the schema as it stood before migrations were versioned. It also upgrades
+/// the databases created by those older releases, so it is idempotent and runs outside of a
+/// transaction; the migrations after it run in a transaction each.
+pub const MIGRATIONS: &[Migration] = &[
+	Migration { version: 1, description: "baseline schema", apply: super::initialize_baseline },
+	Migration {
+		version: 2,
+		description: "account info",
+		apply: super::account_info::initialize_account_info,
+	},
+	Migration { version: 3, description: "account balances", apply: super::add_account_balances },
+	Migration { version: 4, description: "transfer owners", apply: super::add_transfer_owners },
+	Migration { version: 5, description: "inner transfers", apply: super::add_inner_transfers },
+	Migration {
+		version: 6,
+		description: "failed slots",
+		apply: super::failed_slots::initialize_failed_slots,
+	},
+	Migration {
+		version: 7,
+		description: "program instructions",
+		apply: super::instructions::initialize_program_instructions,
+	},
+	Migration {
+		version: 8,
+		description: "transactions by slot",
+		apply: super::add_transactions_by_slot,
+	},
+	Migration { version: 9, description: "block headers", apply: super::blocks::add_block_headers },
+	Migration {
+		version: 10,
+		description: "export runs",
+		apply: super::exports::initialize_export_runs,
+	},
+	Migration {
+		version: 11,
+		description: "parsed instructions",
+		apply: super::instructions::add_parsed_instructions,
+	},
+	Migration { version: 12, description: "epochs", apply: super::epochs::initialize_epochs },
+	Migration {
+		version: 13,
+		description: "program filters",
+		apply: super::filters::initialize_program_filters,
+	},
+	Migration {
+		version: 14,
+		description: "account snapshots",
+		apply: super::account_snapshots::initialize_account_snapshots,
+	},
+	Migration {
+		version: 15,
+		description: "legacy row repair",
+		apply: super::repair::apply_legacy_repair,
+	},
+	Migration { version: 16, description: "transfer tags", apply: super::add_transfer_tags },
+	Migration { version: 17, description: "webhooks", apply: super::webhooks::initialize_webhooks },
+];
+
+/// The version of the schema once every migration is applied.
+pub const LATEST_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// Initializes the `schema_version` table.
+fn initialize_schema_version(conn: &Connection) -> Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
+        )",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Retrieves the version of the last migration applied to the database, 0 if none was.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_schema_version(conn: &Connection) -> Result<u32> {
+	initialize_schema_version(conn)?;
+	conn.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))
+}
+
+fn record_migration(conn: &Connection, migration: &Migration) -> Result<()> {
+	let applied_at = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|now| now.as_secs())
+		.unwrap_or_default();
+	conn.execute(
+		"INSERT INTO schema_version (version, description, applied_at) VALUES (?1, ?2, ?3)",
+		params![migration.version, migration.description, applied_at],
+	)?;
+	Ok(())
+}
+
+/// Applies the migrations the database has not run yet, in order.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `migrations` - The migrations of the schema, in version order.
+///
+/// # Returns
+///
+/// This function returns the versions of the migrations it applied.
+///
+/// # Errors
+///
+/// This function returns an error if a migration fails. The migrations applied before it stay
+/// recorded, and the failed one is retried on the next run.
+pub fn apply_migrations(conn: &Connection, migrations: &[Migration]) -> Result<Vec<u32>> {
+	let current = get_schema_version(conn)?;
+	let mut applied = Vec::new();
+	for migration in migrations.iter().filter(|migration| migration.version > current) {
+		if migration.version == 1 {
+			(migration.apply)(conn)?;
+			record_migration(conn, migration)?;
+		} else {
+			let tx = conn.unchecked_transaction()?;
+			(migration.apply)(&tx)?;
+			record_migration(&tx, migration)?;
+			tx.commit()?;
+		}
+		info!("Applied schema migration {}: {}", migration.version, migration.description);
+		applied.push(migration.version);
+	}
+	Ok(applied)
+}
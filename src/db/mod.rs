@@ -1,7 +1,43 @@
+use crate::types::TransferKind;
+use amount::{get_amount, Amount};
+use recent_writes::RecentWrites;
 use rusqlite::{params, Connection, Result};
 use serde::{Deserialize, Serialize};
 
-use std::error::Error;
+use std::{
+	collections::{HashMap, HashSet},
+	error::Error,
+};
+
+pub mod account_info;
+pub mod account_snapshots;
+pub mod amount;
+pub mod blocks;
+pub mod compat;
+pub mod contention;
+pub mod epochs;
+pub mod exports;
+pub mod failed_slots;
+pub mod filters;
+pub mod groups;
+pub mod idempotency;
+pub mod instructions;
+pub mod labels;
+pub mod migrations;
+pub mod pool;
+pub mod recent_writes;
+pub mod reorgs;
+pub mod repair;
+pub mod rollups;
+pub mod scopes;
+pub mod slow_log;
+pub mod stats;
+pub mod storage;
+pub mod submissions;
+pub mod sync_state;
+pub mod tiering;
+pub mod webhooks;
+pub mod writer;
 
 /// A record representing a transaction.
 #[derive(Debug, Serialize, Deserialize)]
@@ -10,19 +46,139 @@ pub struct TransactionRecord {
 	pub timestamp: i64,
 	pub block_height: u64,
 	pub raw_transaction: String,
+	pub fee: u64,
+	pub fee_payer: String,
+	pub compute_unit_limit: Option<u32>,
+	pub compute_unit_price: Option<u64>,
 }
 
 /// A record representing an account.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Hash, Serialize, Deserialize)]
 pub struct AccountRecord {
 	pub account_id: String,
+	/// The lamports held by the account after its latest transaction, estimated from its transfers
+	/// while no transaction reported its balance.
 	pub estimated_balance: u64,
 	pub related_transactions: Vec<String>,
+	/// Lamports received through transfers.
+	pub total_received: u128,
+	/// Lamports sent through transfers.
+	pub total_sent: u128,
+	/// The earliest slot the account was seen in.
+	pub first_seen_slot: Option<u64>,
+	/// The latest slot the account was active in.
+	pub last_active_slot: Option<u64>,
+	/// The slot of the transaction the balance was read from, `None` while it is estimated.
+	pub balance_slot: Option<u64>,
+	/// The index within its block of the transaction the balance was read from.
+	pub balance_transaction_index: Option<u32>,
 }
 
-/// Initializes the database with the required tables.
-///
-/// This function creates the `transactions` and `accounts` tables if they do not already exist.
+/// A record representing a lamport movement extracted from a transaction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferRecord {
+	pub transaction_id: String,
+	/// The position of the transfer among the transfers of the transaction.
+	pub transfer_index: u32,
+	pub sender: String,
+	pub receiver: String,
+	pub amount: u64,
+	pub timestamp: i64,
+	pub block_height: u64,
+	pub kind: TransferKind,
+	/// The wallet owning the sender, if the sender is a token account.
+	pub sender_owner: Option<String>,
+	/// The wallet owning the receiver, if the receiver is a token account.
+	pub receiver_owner: Option<String>,
+	/// The program that made the transfer through a cross-program invocation, `None` for a top
+	/// level instruction.
+	pub invoked_by: Option<String>,
+	/// The tags of the tagging rules the transfer met when it was stored.
+	#[serde(default)]
+	pub tags: Vec<String>,
+}
+
+/// A record representing the error a failed transaction ended with.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransactionErrorRecord {
+	pub transaction_id: String,
+	pub program_id: Option<String>,
+	pub error: String,
+	pub timestamp: i64,
+	pub block_height: u64,
+}
+
+/// A record representing a token swap, along with its position in the block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapRecord {
+	pub transaction_id: String,
+	pub block_height: u64,
+	pub transaction_index: u32,
+	pub trader: String,
+	pub pool: String,
+	pub mint_in: String,
+	pub amount_in: u128,
+	pub mint_out: String,
+	pub amount_out: u128,
+}
+
+/// A record representing a probable sandwich: an attacker swapping on the same pool right before
+/// and after a victim's swap within a block.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SandwichRecord {
+	pub block_height: u64,
+	pub attacker: String,
+	pub victim: String,
+	pub pool: String,
+	pub front_transaction_id: String,
+	pub victim_transaction_id: String,
+	pub back_transaction_id: String,
+}
+
+/// A record representing the lamport balance change of an account within a transaction.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BalanceChangeRecord {
+	pub account_id: String,
+	pub transaction_id: String,
+	pub block_height: u64,
+	pub transaction_index: u32,
+	pub pre_balance: u64,
+	pub post_balance: u64,
+}
+
+/// A record linking an account to a transaction it appears in.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountTransactionRecord {
+	pub account_id: String,
+	pub transaction_id: String,
+	pub block_height: u64,
+	pub transaction_index: u32,
+}
+
+/// A transaction signature of an account, in the shape returned by `getSignaturesForAddress`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureInfo {
+	pub signature: String,
+	pub slot: u64,
+	/// The error the transaction failed with, `None` if it succeeded.
+	pub err: Option<String>,
+	/// Always `None`, memos are not indexed.
+	pub memo: Option<String>,
+	pub block_time: Option<i64>,
+	pub confirmation_status: Option<String>,
+}
+
+/// A summary of an ingested block, built from its stored transactions.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockSummary {
+	pub slot: u64,
+	pub block_time: i64,
+	pub transaction_count: u64,
+	pub total_fee: u64,
+}
+
+/// Initializes the database with the required tables, applying the pending schema migrations.
 ///
 /// # Arguments
 ///
@@ -32,12 +188,40 @@ pub struct AccountRecord {
 ///
 /// This function returns a `Result` indicating success or failure.
 pub fn initialize_db(conn: &Connection) -> Result<()> {
+	migrations::apply_migrations(conn, migrations::MIGRATIONS)?;
+	Ok(())
+}
+
+/// Creates the baseline schema, the first of the schema migrations.
+///
+/// This function creates the `transactions`, `accounts`, `transfers`, `transaction_errors`,
+/// `swaps`, `sandwiches`, `balance_changes`, `account_transactions`, `blocks`, `groups`,
+/// `group_members`, `labels`, `submissions`, `daily_stats` and `daily_active_accounts` tables if
+/// they do not already exist. Tables created by older versions with `INTEGER` amount columns are
+/// rebuilt with `TEXT` amount columns, see the `amount` module.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+///
+/// # Returns
+///
+/// This function returns a `Result` indicating success or failure.
+fn initialize_baseline(conn: &Connection) -> Result<()> {
+	let add_account_totals = !has_column(conn, "accounts", "total_received")?;
+	let add_account_slots = !has_column(conn, "accounts", "first_seen_slot")?;
+	amount::rename_legacy_tables(conn)?;
+
 	conn.execute(
 		"CREATE TABLE IF NOT EXISTS transactions (
             transaction_id TEXT PRIMARY KEY,
             timestamp INTEGER,
             block_height INTEGER,
-            raw_transaction TEXT
+            raw_transaction TEXT,
+            fee INTEGER,
+            fee_payer TEXT,
+            compute_unit_limit INTEGER,
+            compute_unit_price INTEGER
         )",
 		[],
 	)?;
@@ -45,15 +229,260 @@ pub fn initialize_db(conn: &Connection) -> Result<()> {
 	conn.execute(
 		"CREATE TABLE IF NOT EXISTS accounts (
             account_id TEXT PRIMARY KEY,
-            estimated_balance INTEGER,
-            related_transactions TEXT
+            estimated_balance TEXT,
+            related_transactions TEXT,
+            total_received TEXT NOT NULL DEFAULT '0',
+            total_sent TEXT NOT NULL DEFAULT '0',
+            first_seen_slot INTEGER,
+            last_active_slot INTEGER
+        )",
+		[],
+	)?;
+
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS transfers (
+            transaction_id TEXT PRIMARY KEY,
+            sender TEXT,
+            receiver TEXT,
+            amount TEXT,
+            timestamp INTEGER,
+            block_height INTEGER,
+            kind TEXT
+        )",
+		[],
+	)?;
+
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS transaction_errors (
+            transaction_id TEXT PRIMARY KEY,
+            program_id TEXT,
+            error TEXT,
+            timestamp INTEGER,
+            block_height INTEGER
+        )",
+		[],
+	)?;
+
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS swaps (
+            transaction_id TEXT PRIMARY KEY,
+            block_height INTEGER,
+            transaction_index INTEGER,
+            trader TEXT,
+            pool TEXT,
+            mint_in TEXT,
+            amount_in TEXT,
+            mint_out TEXT,
+            amount_out TEXT
+        )",
+		[],
+	)?;
+
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS sandwiches (
+            block_height INTEGER,
+            attacker TEXT,
+            victim TEXT,
+            pool TEXT,
+            front_transaction_id TEXT,
+            victim_transaction_id TEXT,
+            back_transaction_id TEXT,
+            PRIMARY KEY (front_transaction_id, victim_transaction_id, back_transaction_id)
+        )",
+		[],
+	)?;
+
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS balance_changes (
+            account_id TEXT,
+            transaction_id TEXT,
+            block_height INTEGER,
+            transaction_index INTEGER,
+            pre_balance TEXT,
+            post_balance TEXT,
+            PRIMARY KEY (account_id, transaction_id)
+        )",
+		[],
+	)?;
+
+	conn.execute(
+		"CREATE INDEX IF NOT EXISTS balance_changes_by_slot ON balance_changes (account_id, block_height, transaction_index)",
+		[],
+	)?;
+
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS account_transactions (
+            account_id TEXT,
+            transaction_id TEXT,
+            block_height INTEGER,
+            transaction_index INTEGER,
+            PRIMARY KEY (account_id, transaction_id)
         )",
 		[],
 	)?;
 
+	conn.execute(
+		"CREATE INDEX IF NOT EXISTS account_transactions_by_slot ON account_transactions (account_id, block_height, transaction_index)",
+		[],
+	)?;
+
+	conn.execute(
+		"CREATE INDEX IF NOT EXISTS account_transactions_by_transaction ON account_transactions (transaction_id)",
+		[],
+	)?;
+
+	blocks::initialize_blocks(conn)?;
+	groups::initialize_groups(conn)?;
+	idempotency::initialize_idempotency(conn)?;
+	labels::initialize_labels(conn)?;
+	submissions::initialize_submissions(conn)?;
+	sync_state::initialize_sync_state(conn)?;
+	amount::restore_legacy_tables(conn)?;
+	rollups::initialize_rollups(conn)?;
+
+	if add_account_totals {
+		if !has_column(conn, "accounts", "total_received")? {
+			conn.execute(
+				"ALTER TABLE accounts ADD COLUMN total_received TEXT NOT NULL DEFAULT '0'",
+				[],
+			)?;
+			conn.execute(
+				"ALTER TABLE accounts ADD COLUMN total_sent TEXT NOT NULL DEFAULT '0'",
+				[],
+			)?;
+		}
+		initialize_account_totals(conn)?;
+	}
+	if add_account_slots {
+		if !has_column(conn, "accounts", "first_seen_slot")? {
+			conn.execute("ALTER TABLE accounts ADD COLUMN first_seen_slot INTEGER", [])?;
+			conn.execute("ALTER TABLE accounts ADD COLUMN last_active_slot INTEGER", [])?;
+		}
+		conn.execute(
+			"UPDATE accounts SET
+                first_seen_slot = (SELECT MIN(block_height) FROM account_transactions a WHERE a.account_id = accounts.account_id),
+                last_active_slot = (SELECT MAX(block_height) FROM account_transactions a WHERE a.account_id = accounts.account_id)",
+			[],
+		)?;
+	}
+
+	Ok(())
+}
+
+/// Adds the position of the transaction the balance of each account was read from, and sets the
+/// balance of the accounts with recorded balance changes to the post balance of their latest one.
+pub(crate) fn add_account_balances(conn: &Connection) -> Result<()> {
+	if !has_column(conn, "accounts", "balance_slot")? {
+		conn.execute("ALTER TABLE accounts ADD COLUMN balance_slot INTEGER", [])?;
+		conn.execute("ALTER TABLE accounts ADD COLUMN balance_transaction_index INTEGER", [])?;
+	}
+	conn.execute(
+		"UPDATE accounts SET
+            estimated_balance = (SELECT post_balance FROM balance_changes b WHERE b.account_id = accounts.account_id ORDER BY block_height DESC, transaction_index DESC LIMIT 1),
+            balance_slot = (SELECT block_height FROM balance_changes b WHERE b.account_id = accounts.account_id ORDER BY block_height DESC, transaction_index DESC LIMIT 1),
+            balance_transaction_index = (SELECT transaction_index FROM balance_changes b WHERE b.account_id = accounts.account_id ORDER BY block_height DESC, transaction_index DESC LIMIT 1)
+         WHERE EXISTS (SELECT 1 FROM balance_changes b WHERE b.account_id = accounts.account_id)",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Adds the wallets owning the token accounts of the transfers.
+pub(crate) fn add_transfer_owners(conn: &Connection) -> Result<()> {
+	if !has_column(conn, "transfers", "sender_owner")? {
+		conn.execute("ALTER TABLE transfers ADD COLUMN sender_owner TEXT", [])?;
+		conn.execute("ALTER TABLE transfers ADD COLUMN receiver_owner TEXT", [])?;
+	}
+	conn.execute(
+		"CREATE INDEX IF NOT EXISTS transfers_by_sender_owner ON transfers (sender_owner)",
+		[],
+	)?;
+	conn.execute(
+		"CREATE INDEX IF NOT EXISTS transfers_by_receiver_owner ON transfers (receiver_owner)",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Keys the transfers by their position within the transaction, so a transaction can hold the
+/// transfers made through cross-program invocations along with its top level one, and records the
+/// program that invoked them.
+pub(crate) fn add_inner_transfers(conn: &Connection) -> Result<()> {
+	if has_column(conn, "transfers", "transfer_index")? {
+		return Ok(())
+	}
+	conn.execute_batch(
+		"CREATE TABLE transfers_by_index (
+            transaction_id TEXT NOT NULL,
+            transfer_index INTEGER NOT NULL,
+            sender TEXT,
+            receiver TEXT,
+            amount TEXT,
+            timestamp INTEGER,
+            block_height INTEGER,
+            kind TEXT,
+            sender_owner TEXT,
+            receiver_owner TEXT,
+            invoked_by TEXT,
+            PRIMARY KEY (transaction_id, transfer_index)
+        );
+        INSERT INTO transfers_by_index (transaction_id, transfer_index, sender, receiver, amount, timestamp, block_height, kind, sender_owner, receiver_owner)
+            SELECT transaction_id, 0, sender, receiver, amount, timestamp, block_height, kind, sender_owner, receiver_owner FROM transfers;
+        DROP TABLE transfers;
+        ALTER TABLE transfers_by_index RENAME TO transfers;
+        CREATE INDEX transfers_by_sender_owner ON transfers (sender_owner);
+        CREATE INDEX transfers_by_receiver_owner ON transfers (receiver_owner);",
+	)
+}
+
+/// Adds the tags the tagging rules give the transfers, as a JSON list.
+pub(crate) fn add_transfer_tags(conn: &Connection) -> Result<()> {
+	if !has_column(conn, "transfers", "tags")? {
+		conn.execute("ALTER TABLE transfers ADD COLUMN tags TEXT", [])?;
+	}
+	Ok(())
+}
+
+/// Indexes the transactions by slot, so the transactions of a block can be listed.
+pub(crate) fn add_transactions_by_slot(conn: &Connection) -> Result<()> {
+	conn.execute(
+		"CREATE INDEX IF NOT EXISTS transactions_by_slot ON transactions (block_height)",
+		[],
+	)?;
 	Ok(())
 }
 
+/// Returns whether a table has a column, `false` if the table does not exist.
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+	conn.query_row(
+		&format!("SELECT EXISTS (SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1)", table),
+		[column],
+		|row| row.get(0),
+	)
+}
+
+/// Computes the transfer totals of the accounts stored before the totals were tracked, from the
+/// stored transfers.
+fn initialize_account_totals(conn: &Connection) -> Result<()> {
+	let mut totals: HashMap<String, (u128, u128)> = HashMap::new();
+	let mut stmt = conn.prepare("SELECT sender, receiver, amount FROM transfers")?;
+	let mut rows = stmt.query([])?;
+	while let Some(row) = rows.next()? {
+		let amount: u128 = get_amount(row, 2)?;
+		totals.entry(row.get(0)?).or_default().1 += amount;
+		totals.entry(row.get(1)?).or_default().0 += amount;
+	}
+
+	let tx = conn.unchecked_transaction()?;
+	for (account_id, (received, sent)) in totals {
+		tx.execute(
+			"UPDATE accounts SET total_received = ?2, total_sent = ?3 WHERE account_id = ?1",
+			params![account_id, Amount::from(received), Amount::from(sent)],
+		)?;
+	}
+	tx.commit()
+}
+
 /// Inserts or updates a transaction record in the database.
 ///
 /// This function inserts a new transaction record or updates an existing record with the same
@@ -75,15 +504,19 @@ pub fn insert_or_update_transaction(
 	conn: &Connection,
 	record: &TransactionRecord,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-	conn.execute(
-        "INSERT OR REPLACE INTO transactions (transaction_id, timestamp, block_height, raw_transaction) VALUES (?1, ?2, ?3, ?4)",
-        params![
-            record.transaction_id,
-            record.timestamp,
-            record.block_height,
-            record.raw_transaction
-        ],
-    )?;
+	let mut stmt = conn.prepare_cached(
+		"INSERT OR REPLACE INTO transactions (transaction_id, timestamp, block_height, raw_transaction, fee, fee_payer, compute_unit_limit, compute_unit_price) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+	)?;
+	stmt.execute(params![
+		record.transaction_id,
+		record.timestamp,
+		record.block_height,
+		record.raw_transaction,
+		record.fee,
+		record.fee_payer,
+		record.compute_unit_limit,
+		record.compute_unit_price
+	])?;
 	Ok(())
 }
 
@@ -110,20 +543,27 @@ pub fn insert_or_update_account(
 	record: &AccountRecord,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
 	let transactions_json = serde_json::to_string(&record.related_transactions)?;
-	conn.execute(
-        "INSERT OR REPLACE INTO accounts (account_id, estimated_balance, related_transactions) VALUES (?1, ?2, ?3)",
-        params![
-            record.account_id,
-            record.estimated_balance,
-            transactions_json
-        ],
-    )?;
+	let mut stmt = conn.prepare_cached(
+		"INSERT OR REPLACE INTO accounts (account_id, estimated_balance, related_transactions, total_received, total_sent, first_seen_slot, last_active_slot, balance_slot, balance_transaction_index) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+	)?;
+	stmt.execute(params![
+		record.account_id,
+		Amount::from(record.estimated_balance),
+		transactions_json,
+		Amount::from(record.total_received),
+		Amount::from(record.total_sent),
+		record.first_seen_slot,
+		record.last_active_slot,
+		record.balance_slot,
+		record.balance_transaction_index
+	])?;
 	Ok(())
 }
 
 /// Retrieves a transaction record from the database by transaction ID.
 ///
-/// This function fetches a transaction record matching the given transaction ID.
+/// This function fetches a transaction record matching the given transaction ID. When a cold
+/// database is attached, transactions missing from the hot database are looked up there.
 ///
 /// # Arguments
 ///
@@ -142,21 +582,216 @@ pub fn get_transaction(
 	conn: &Connection,
 	tx_id: &str,
 ) -> Result<Option<TransactionRecord>, Box<dyn Error + Send + Sync>> {
-	let mut stmt = conn.prepare("SELECT transaction_id, timestamp, block_height, raw_transaction FROM transactions WHERE transaction_id = ?1")?;
+	if let Some(transaction) = query_transaction(conn, "main", tx_id)? {
+		return Ok(Some(transaction))
+	}
+	if tiering::is_cold_attached(conn)? {
+		return query_transaction(conn, tiering::COLD_SCHEMA, tx_id)
+	}
+	Ok(None)
+}
+
+/// Returns whether a transaction is stored, in the hot or, when attached, the cold database.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn is_transaction_stored(
+	conn: &Connection,
+	tx_id: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	let cold = if tiering::is_cold_attached(conn)? {
+		" OR EXISTS (SELECT 1 FROM cold.transactions WHERE transaction_id = ?1)"
+	} else {
+		""
+	};
+	let stored = conn.query_row(
+		&format!(
+			"SELECT EXISTS (SELECT 1 FROM main.transactions WHERE transaction_id = ?1){}",
+			cold
+		),
+		params![tx_id],
+		|row| row.get(0),
+	)?;
+	Ok(stored)
+}
+
+fn query_transaction(
+	conn: &Connection,
+	schema: &str,
+	tx_id: &str,
+) -> Result<Option<TransactionRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(&format!("SELECT transaction_id, timestamp, block_height, raw_transaction, fee, fee_payer, compute_unit_limit, compute_unit_price FROM {}.transactions WHERE transaction_id = ?1", schema))?;
 	let mut rows = stmt.query(params![tx_id])?;
 
 	if let Some(row) = rows.next()? {
-		Ok(Some(TransactionRecord {
-			transaction_id: row.get(0)?,
-			timestamp: row.get(1)?,
-			block_height: row.get(2)?,
-			raw_transaction: row.get(3)?,
-		}))
+		Ok(Some(read_transaction(row)?))
 	} else {
 		Ok(None)
 	}
 }
 
+fn read_transaction(row: &rusqlite::Row) -> Result<TransactionRecord> {
+	Ok(TransactionRecord {
+		transaction_id: row.get(0)?,
+		timestamp: row.get(1)?,
+		block_height: row.get(2)?,
+		raw_transaction: row.get(3)?,
+		fee: row.get(4)?,
+		fee_payer: row.get(5)?,
+		compute_unit_limit: row.get(6)?,
+		compute_unit_price: row.get(7)?,
+	})
+}
+
+/// The bounds of a listing of the stored transactions, all optional.
+#[derive(Debug, Default, Clone)]
+pub struct TransactionFilter {
+	/// The first slot listed.
+	pub from_slot: Option<u64>,
+	/// The last slot listed.
+	pub to_slot: Option<u64>,
+	/// The unix timestamp from which transactions are listed.
+	pub since: Option<i64>,
+	/// The unix timestamp before which transactions are listed.
+	pub until: Option<i64>,
+}
+
+/// Retrieves a page of the stored transactions, newest first.
+///
+/// Transactions are ordered by slot, then by ID within a slot, so a page can resume after the last
+/// transaction of the previous one.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `filter` - The bounds of the listing.
+/// * `after` - The slot and ID of the last transaction of the previous page, `None` for the first
+///   page.
+/// * `limit` - The maximum number of transactions to return.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_transactions_page(
+	conn: &Connection,
+	filter: &TransactionFilter,
+	after: Option<(u64, &str)>,
+	limit: usize,
+) -> Result<Vec<TransactionRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT transaction_id, timestamp, block_height, raw_transaction, fee, fee_payer, compute_unit_limit, compute_unit_price
+         FROM transactions
+         WHERE (?1 IS NULL OR block_height >= ?1)
+           AND (?2 IS NULL OR block_height <= ?2)
+           AND (?3 IS NULL OR timestamp >= ?3)
+           AND (?4 IS NULL OR timestamp < ?4)
+           AND (?5 IS NULL OR block_height < ?5 OR (block_height = ?5 AND transaction_id < ?6))
+         ORDER BY block_height DESC, transaction_id DESC
+         LIMIT ?7",
+	)?;
+	let transactions = stmt
+		.query_map(
+			params![
+				filter.from_slot,
+				filter.to_slot,
+				filter.since,
+				filter.until,
+				after.map(|(slot, _)| slot),
+				after.map(|(_, transaction_id)| transaction_id),
+				limit as i64
+			],
+			read_transaction,
+		)?
+		.collect::<Result<Vec<_>>>()?;
+	Ok(transactions)
+}
+
+/// Retrieves the position of a stored transaction within its block.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `tx_id` - A string slice containing the transaction ID.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing an `Option<u32>`, `None` if the transaction is not
+/// linked to any account.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_transaction_index(
+	conn: &Connection,
+	tx_id: &str,
+) -> Result<Option<u32>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"SELECT MIN(transaction_index) FROM account_transactions WHERE transaction_id = ?1",
+	)?;
+	Ok(stmt.query_row(params![tx_id], |row| row.get(0))?)
+}
+
+/// Retrieves the IDs of the transactions stored at a slot.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slot` - The slot of the block.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_block_transaction_ids(
+	conn: &Connection,
+	slot: u64,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+	let mut stmt =
+		conn.prepare_cached("SELECT transaction_id FROM transactions WHERE block_height = ?1")?;
+	let ids = stmt.query_map(params![slot], |row| row.get(0))?.collect::<Result<Vec<_>>>()?;
+	Ok(ids)
+}
+
+/// Returns whether an account is linked to any stored transaction.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn account_has_transactions(
+	conn: &Connection,
+	account_id: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"SELECT EXISTS (SELECT 1 FROM account_transactions WHERE account_id = ?1)",
+	)?;
+	Ok(stmt.query_row(params![account_id], |row| row.get(0))?)
+}
+
+/// Deletes the records derived from parsing a transaction: its error, balance changes, transfer,
+/// swap and program instructions.
+///
+/// The transaction itself and its account links are kept.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `tx_id` - A string slice containing the transaction ID.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn delete_transaction_details(
+	conn: &Connection,
+	tx_id: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	for table in
+		["transaction_errors", "balance_changes", "transfers", "swaps", "program_instructions"]
+	{
+		conn.execute(&format!("DELETE FROM {} WHERE transaction_id = ?1", table), params![tx_id])?;
+	}
+	Ok(())
+}
+
 /// Retrieves an account record from the database by account ID.
 ///
 /// This function fetches an account record matching the given account ID.
@@ -178,18 +813,711 @@ pub fn get_account(
 	conn: &Connection,
 	account_id: &str,
 ) -> Result<Option<AccountRecord>, Box<dyn Error + Send + Sync>> {
-	let mut stmt = conn.prepare("SELECT account_id, estimated_balance, related_transactions FROM accounts WHERE account_id = ?1")?;
+	let mut stmt = conn.prepare_cached(&format!(
+		"SELECT {} FROM accounts WHERE account_id = ?1",
+		ACCOUNT_COLUMNS
+	))?;
 	let mut rows = stmt.query(params![account_id])?;
 
 	if let Some(row) = rows.next()? {
-		let related_transactions: String = row.get(2)?;
-		let related_transactions: Vec<String> = serde_json::from_str(&related_transactions)?;
-		Ok(Some(AccountRecord {
-			account_id: row.get(0)?,
-			estimated_balance: row.get(1)?,
-			related_transactions,
-		}))
+		Ok(Some(read_account(row)?))
 	} else {
 		Ok(None)
 	}
 }
+
+/// Retrieves the account records of several account IDs in a single query.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `account_ids` - The account IDs, at most as many as the variables of a SQLite statement.
+///
+/// # Returns
+///
+/// This function returns the records of the accounts found, in no particular order.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if deserialization fails.
+pub fn get_accounts(
+	conn: &Connection,
+	account_ids: &[String],
+) -> Result<Vec<AccountRecord>, Box<dyn Error + Send + Sync>> {
+	if account_ids.is_empty() {
+		return Ok(Vec::new())
+	}
+	let placeholders = vec!["?"; account_ids.len()].join(", ");
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {} FROM accounts WHERE account_id IN ({})",
+		ACCOUNT_COLUMNS, placeholders
+	))?;
+	let mut rows = stmt.query(rusqlite::params_from_iter(account_ids))?;
+	let mut accounts = Vec::new();
+	while let Some(row) = rows.next()? {
+		accounts.push(read_account(row)?);
+	}
+	Ok(accounts)
+}
+
+/// The columns of the `accounts` table read by `read_account`.
+const ACCOUNT_COLUMNS: &str = "account_id, estimated_balance, related_transactions, total_received, total_sent, first_seen_slot, last_active_slot, balance_slot, balance_transaction_index";
+
+fn read_account(row: &rusqlite::Row) -> Result<AccountRecord, Box<dyn Error + Send + Sync>> {
+	let related_transactions: String = row.get(2)?;
+	Ok(AccountRecord {
+		account_id: row.get(0)?,
+		estimated_balance: get_amount(row, 1)?,
+		related_transactions: serde_json::from_str(&related_transactions)?,
+		total_received: get_amount(row, 3)?,
+		total_sent: get_amount(row, 4)?,
+		first_seen_slot: row.get(5)?,
+		last_active_slot: row.get(6)?,
+		balance_slot: row.get(7)?,
+		balance_transaction_index: row.get(8)?,
+	})
+}
+
+/// Inserts or updates a transfer record in the database.
+///
+/// This function inserts a new transfer record or updates the existing record at the same position
+/// of the same transaction.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `record` - A reference to a `TransferRecord` containing the transfer details.
+///
+/// # Returns
+///
+/// This function returns a `Result` indicating success or failure.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_transfer(
+	conn: &Connection,
+	record: &TransferRecord,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"INSERT OR REPLACE INTO transfers (transaction_id, transfer_index, sender, receiver, amount, timestamp, block_height, kind, sender_owner, receiver_owner, invoked_by, tags) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+	)?;
+	// Untagged transfers store no tags, rather than an empty list
+	let tags = (!record.tags.is_empty())
+		.then(|| serde_json::to_string(&record.tags))
+		.transpose()?;
+	stmt.execute(params![
+		record.transaction_id,
+		record.transfer_index,
+		record.sender,
+		record.receiver,
+		Amount::from(record.amount),
+		record.timestamp,
+		record.block_height,
+		record.kind.as_str(),
+		record.sender_owner,
+		record.receiver_owner,
+		record.invoked_by,
+		tags
+	])?;
+	Ok(())
+}
+
+/// Inserts or updates the error record of a failed transaction in the database.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `record` - A reference to a `TransactionErrorRecord` containing the error details.
+///
+/// # Returns
+///
+/// This function returns a `Result` indicating success or failure.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_transaction_error(
+	conn: &Connection,
+	record: &TransactionErrorRecord,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"INSERT OR REPLACE INTO transaction_errors (transaction_id, program_id, error, timestamp, block_height) VALUES (?1, ?2, ?3, ?4, ?5)",
+	)?;
+	stmt.execute(params![
+		record.transaction_id,
+		record.program_id,
+		record.error,
+		record.timestamp,
+		record.block_height
+	])?;
+	Ok(())
+}
+
+/// Inserts or updates a swap record in the database.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `record` - A reference to a `SwapRecord` containing the swap details.
+///
+/// # Returns
+///
+/// This function returns a `Result` indicating success or failure.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_swap(
+	conn: &Connection,
+	record: &SwapRecord,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"INSERT OR REPLACE INTO swaps (transaction_id, block_height, transaction_index, trader, pool, mint_in, amount_in, mint_out, amount_out) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+	)?;
+	stmt.execute(params![
+		record.transaction_id,
+		record.block_height,
+		record.transaction_index,
+		record.trader,
+		record.pool,
+		record.mint_in,
+		Amount::from(record.amount_in),
+		record.mint_out,
+		Amount::from(record.amount_out)
+	])?;
+	Ok(())
+}
+
+/// Inserts or updates a sandwich record in the database.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `record` - A reference to a `SandwichRecord` containing the flagged transactions.
+///
+/// # Returns
+///
+/// This function returns a `Result` indicating success or failure.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_sandwich(
+	conn: &Connection,
+	record: &SandwichRecord,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"INSERT OR REPLACE INTO sandwiches (block_height, attacker, victim, pool, front_transaction_id, victim_transaction_id, back_transaction_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+	)?;
+	stmt.execute(params![
+		record.block_height,
+		record.attacker,
+		record.victim,
+		record.pool,
+		record.front_transaction_id,
+		record.victim_transaction_id,
+		record.back_transaction_id
+	])?;
+	Ok(())
+}
+
+/// Retrieves the most recent flagged sandwiches.
+///
+/// This function fetches sandwich records ordered by block height in descending order, optionally
+/// restricted to a single attacker.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `attacker` - An optional attacker account ID to filter the sandwiches by.
+/// * `limit` - The maximum number of sandwiches to return.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing a `Vec<SandwichRecord>`.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_sandwiches(
+	conn: &Connection,
+	attacker: Option<&str>,
+	limit: u32,
+) -> Result<Vec<SandwichRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare("SELECT block_height, attacker, victim, pool, front_transaction_id, victim_transaction_id, back_transaction_id FROM sandwiches WHERE (?1 IS NULL OR attacker = ?1) ORDER BY block_height DESC LIMIT ?2")?;
+	let mut rows = stmt.query(params![attacker, limit])?;
+
+	let mut sandwiches = Vec::new();
+	while let Some(row) = rows.next()? {
+		sandwiches.push(SandwichRecord {
+			block_height: row.get(0)?,
+			attacker: row.get(1)?,
+			victim: row.get(2)?,
+			pool: row.get(3)?,
+			front_transaction_id: row.get(4)?,
+			victim_transaction_id: row.get(5)?,
+			back_transaction_id: row.get(6)?,
+		});
+	}
+	Ok(sandwiches)
+}
+
+/// Inserts or updates a balance change record in the database.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `record` - A reference to a `BalanceChangeRecord` containing the balance change.
+///
+/// # Returns
+///
+/// This function returns a `Result` indicating success or failure.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_balance_change(
+	conn: &Connection,
+	record: &BalanceChangeRecord,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"INSERT OR REPLACE INTO balance_changes (account_id, transaction_id, block_height, transaction_index, pre_balance, post_balance) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+	)?;
+	stmt.execute(params![
+		record.account_id,
+		record.transaction_id,
+		record.block_height,
+		record.transaction_index,
+		Amount::from(record.pre_balance),
+		Amount::from(record.post_balance)
+	])?;
+	Ok(())
+}
+
+/// Retrieves the last balance change of an account at or before a given slot.
+///
+/// The post balance of this change is the balance of the account at the given slot, as no other
+/// change was recorded in between. Without a slot, the latest recorded change is returned.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `account_id` - A string slice containing the account ID.
+/// * `at_slot` - An optional slot at which the balance is requested.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing an `Option<BalanceChangeRecord>`.
+/// The `Option` is `None` if no balance change was recorded at or before the slot.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_balance_change_at(
+	conn: &Connection,
+	account_id: &str,
+	at_slot: Option<u64>,
+) -> Result<Option<BalanceChangeRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare("SELECT account_id, transaction_id, block_height, transaction_index, pre_balance, post_balance FROM balance_changes WHERE account_id = ?1 AND (?2 IS NULL OR block_height <= ?2) ORDER BY block_height DESC, transaction_index DESC LIMIT 1")?;
+	let mut rows = stmt.query(params![account_id, at_slot])?;
+
+	if let Some(row) = rows.next()? {
+		Ok(Some(BalanceChangeRecord {
+			account_id: row.get(0)?,
+			transaction_id: row.get(1)?,
+			block_height: row.get(2)?,
+			transaction_index: row.get(3)?,
+			pre_balance: get_amount(row, 4)?,
+			post_balance: get_amount(row, 5)?,
+		}))
+	} else {
+		Ok(None)
+	}
+}
+
+/// Merges the transfers accumulated for an account over a block into its stored record.
+///
+/// Transactions that are not related to the account yet are appended to its related transactions
+/// and their lamport change is applied to the received or sent totals, so re-ingesting a block
+/// does not count its transfers twice. The balance of the account is the one reported by the meta
+/// of its latest transaction; it only replaces the stored balance if no later transaction reported
+/// one, as blocks may be ingested out of order. While no transaction reported a balance, it is
+/// estimated from the transfers and floored at zero since the history of the account may be
+/// incomplete. The first seen and last active slots are widened to the block's slot. The account
+/// is created if it does not exist.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `account_id` - A string slice containing the account ID.
+/// * `slot` - The slot of the block.
+/// * `changes` - The transactions of the block involving the account, with the lamport change of
+///   the account in each.
+/// * `balance` - The balance of the account after its last transaction of the block, as the index
+///   of the transaction within the block and the lamports, if the block reported it.
+/// * `recent` - The states recently written for the accounts; the upsert is skipped when the merged
+///   state was just written.
+///
+/// # Returns
+///
+/// This function returns a `Result` indicating success or failure.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if the estimated balance
+/// overflows.
+pub fn merge_account(
+	conn: &Connection,
+	account_id: &str,
+	slot: u64,
+	changes: &[(String, i128)],
+	balance: Option<(u32, u64)>,
+	recent: &mut RecentWrites,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut record = get_account(conn, account_id)?.unwrap_or_else(|| AccountRecord {
+		account_id: account_id.to_string(),
+		estimated_balance: 0,
+		related_transactions: Vec::new(),
+		total_received: 0,
+		total_sent: 0,
+		first_seen_slot: None,
+		last_active_slot: None,
+		balance_slot: None,
+		balance_transaction_index: None,
+	});
+	record.first_seen_slot = Some(record.first_seen_slot.map_or(slot, |first| first.min(slot)));
+	record.last_active_slot = Some(record.last_active_slot.map_or(slot, |last| last.max(slot)));
+
+	let known: HashSet<String> = record.related_transactions.iter().cloned().collect();
+	let mut estimate = record.estimated_balance as i128;
+	for (transaction_id, delta) in changes {
+		if known.contains(transaction_id) {
+			continue
+		}
+		estimate = estimate.checked_add(*delta).ok_or("Estimated balance overflow")?;
+		let total = if *delta >= 0 { &mut record.total_received } else { &mut record.total_sent };
+		*total = total.checked_add(delta.unsigned_abs()).ok_or("Transfer total overflow")?;
+		if !record.related_transactions.contains(transaction_id) {
+			record.related_transactions.push(transaction_id.clone());
+		}
+	}
+
+	let stored_position = record.balance_slot.zip(record.balance_transaction_index);
+	match balance {
+		Some((index, lamports))
+			if stored_position.is_none_or(|position| position <= (slot, index)) =>
+		{
+			record.estimated_balance = lamports;
+			record.balance_slot = Some(slot);
+			record.balance_transaction_index = Some(index);
+		},
+		_ if stored_position.is_none() =>
+			record.estimated_balance =
+				u64::try_from(estimate.max(0)).map_err(|_| "Estimated balance overflow")?,
+		_ => {},
+	}
+
+	let hash = RecentWrites::hash_state(&record);
+	if recent.contains(account_id, hash) {
+		return Ok(())
+	}
+	insert_or_update_account(conn, &record)?;
+	recent.insert(account_id, hash);
+	Ok(())
+}
+
+/// Inserts or updates an account transaction record in the database.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `record` - A reference to an `AccountTransactionRecord` linking the account to the
+///   transaction.
+///
+/// # Returns
+///
+/// This function returns a `Result` indicating success or failure.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_or_update_account_transaction(
+	conn: &Connection,
+	record: &AccountTransactionRecord,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"INSERT OR REPLACE INTO account_transactions (account_id, transaction_id, block_height, transaction_index) VALUES (?1, ?2, ?3, ?4)",
+	)?;
+	stmt.execute(params![
+		record.account_id,
+		record.transaction_id,
+		record.block_height,
+		record.transaction_index
+	])?;
+	Ok(())
+}
+
+/// Retrieves the signatures of the transactions an account appears in, newest first.
+///
+/// The pagination follows `getSignaturesForAddress`: `before` starts the search right before the
+/// given signature and `until` stops it right before reaching the given signature, both exclusive.
+/// An unknown `before` signature yields no results, an unknown `until` signature is ignored.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `account_id` - A string slice containing the account ID.
+/// * `before` - An optional signature to start searching backwards from.
+/// * `until` - An optional signature to search until.
+/// * `filter` - The slot and block time bounds of the transactions; transactions without a stored
+///   block time are left out of a time range.
+/// * `limit` - The maximum number of signatures to return.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing a `Vec<SignatureInfo>`.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_account_signatures(
+	conn: &Connection,
+	account_id: &str,
+	before: Option<&str>,
+	until: Option<&str>,
+	filter: &TransactionFilter,
+	limit: usize,
+) -> Result<Vec<SignatureInfo>, Box<dyn Error + Send + Sync>> {
+	query_signatures(conn, "a.account_id = ?1", account_id, before, until, filter, limit)
+}
+
+/// Retrieves a page of the signatures of the transactions matching an account condition.
+///
+/// `accounts` is the SQL condition on the `account_transactions` rows (aliased `a`), bound to
+/// `key` as `?1`. Transactions involving several matching accounts are returned once. The block
+/// times of the transactions moved to cold storage are read from the cold database, if attached.
+pub(crate) fn query_signatures(
+	conn: &Connection,
+	accounts: &str,
+	key: &str,
+	before: Option<&str>,
+	until: Option<&str>,
+	filter: &TransactionFilter,
+	limit: usize,
+) -> Result<Vec<SignatureInfo>, Box<dyn Error + Send + Sync>> {
+	let before = match before {
+		Some(signature) => match get_signature_position(conn, signature)? {
+			Some(position) => Some(position),
+			None => return Ok(Vec::new()),
+		},
+		None => None,
+	};
+	let until = match until {
+		Some(signature) => get_signature_position(conn, signature)?,
+		None => None,
+	};
+
+	let (timestamp, cold_join) = if tiering::is_cold_attached(conn)? {
+		(
+			"COALESCE(t.timestamp, c.timestamp)",
+			"LEFT JOIN cold.transactions c ON c.transaction_id = a.transaction_id",
+		)
+	} else {
+		("t.timestamp", "")
+	};
+	let mut stmt = conn.prepare(&format!(
+		"SELECT a.transaction_id, a.block_height, e.error, {timestamp}
+         FROM account_transactions a
+         LEFT JOIN main.transactions t ON t.transaction_id = a.transaction_id
+         {cold_join}
+         LEFT JOIN transaction_errors e ON e.transaction_id = a.transaction_id
+         WHERE {accounts}
+           AND (?2 IS NULL OR a.block_height < ?2 OR (a.block_height = ?2 AND a.transaction_index < ?3))
+           AND (?4 IS NULL OR a.block_height > ?4 OR (a.block_height = ?4 AND a.transaction_index > ?5))
+           AND (?6 IS NULL OR a.block_height >= ?6)
+           AND (?7 IS NULL OR a.block_height <= ?7)
+           AND (?8 IS NULL OR {timestamp} >= ?8)
+           AND (?9 IS NULL OR {timestamp} < ?9)
+         GROUP BY a.transaction_id
+         ORDER BY a.block_height DESC, a.transaction_index DESC
+         LIMIT ?10",
+	))?;
+	let mut rows = stmt.query(params![
+		key,
+		before.map(|(slot, _)| slot),
+		before.map(|(_, index)| index),
+		until.map(|(slot, _)| slot),
+		until.map(|(_, index)| index),
+		filter.from_slot,
+		filter.to_slot,
+		filter.since,
+		filter.until,
+		limit as i64
+	])?;
+
+	let mut signatures = Vec::new();
+	while let Some(row) = rows.next()? {
+		signatures.push(SignatureInfo {
+			signature: row.get(0)?,
+			slot: row.get(1)?,
+			err: row.get(2)?,
+			memo: None,
+			block_time: row.get(3)?,
+			confirmation_status: Some("finalized".to_string()),
+		});
+	}
+	Ok(signatures)
+}
+
+/// Returns the slot and index within the block of a transaction, if it is known.
+fn get_signature_position(
+	conn: &Connection,
+	signature: &str,
+) -> Result<Option<(u64, u32)>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT block_height, transaction_index FROM account_transactions WHERE transaction_id = ?1 LIMIT 1",
+	)?;
+	let mut rows = stmt.query(params![signature])?;
+	match rows.next()? {
+		Some(row) => Ok(Some((row.get(0)?, row.get(1)?))),
+		None => Ok(None),
+	}
+}
+
+/// Retrieves the transfers sent or received by an account.
+///
+/// This function fetches all transfer records where the given account is either the sender or the
+/// receiver, or the wallet owning either of them, ordered by block height. The results can
+/// optionally be restricted to a single `TransferKind`, which allows separating payments from rent
+/// deposits and account closures, and to the transfers carrying a tag.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `account_id` - A string slice containing the account ID.
+/// * `kind` - An optional `TransferKind` to filter the transfers by.
+/// * `tag` - An optional tag the transfers must carry.
+/// * `limit` - The maximum number of transfers to return.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing a `Vec<TransferRecord>`.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if a stored kind is invalid.
+pub fn get_transfers(
+	conn: &Connection,
+	account_id: &str,
+	kind: Option<TransferKind>,
+	tag: Option<&str>,
+	limit: usize,
+) -> Result<Vec<TransferRecord>, Box<dyn Error + Send + Sync>> {
+	let mut transfers = Vec::new();
+	for_each_transfer(conn, account_id, kind, tag, limit, |transfer| {
+		transfers.push(transfer);
+		true
+	})?;
+	Ok(transfers)
+}
+
+/// Reads the transfers of an account one at a time, in the order of `get_transfers`, without
+/// collecting them.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `account_id` - A string slice containing the account ID.
+/// * `kind` - An optional `TransferKind` to filter the transfers by.
+/// * `tag` - An optional tag the transfers must carry.
+/// * `limit` - The maximum number of transfers to read.
+/// * `f` - Called with each transfer; returning `false` stops the reading.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if a stored kind is invalid.
+pub fn for_each_transfer(
+	conn: &Connection,
+	account_id: &str,
+	kind: Option<TransferKind>,
+	tag: Option<&str>,
+	limit: usize,
+	mut f: impl FnMut(TransferRecord) -> bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare("SELECT transaction_id, transfer_index, sender, receiver, amount, timestamp, block_height, kind, sender_owner, receiver_owner, invoked_by, tags FROM transfers WHERE (sender = ?1 OR receiver = ?1 OR sender_owner = ?1 OR receiver_owner = ?1) AND (?2 IS NULL OR kind = ?2) AND (?3 IS NULL OR EXISTS (SELECT 1 FROM json_each(transfers.tags) WHERE value = ?3)) ORDER BY block_height, transaction_id, transfer_index LIMIT ?4")?;
+	let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+	let mut rows = stmt.query(params![account_id, kind.map(|kind| kind.as_str()), tag, limit])?;
+
+	while let Some(row) = rows.next()? {
+		if !f(read_transfer(row)?) {
+			break
+		}
+	}
+	Ok(())
+}
+
+/// Reads a transfer from a row selecting the columns of `for_each_transfer`, in the same order.
+///
+/// # Errors
+///
+/// This function returns an error if a column cannot be read or if the stored kind is invalid.
+pub(crate) fn read_transfer(
+	row: &rusqlite::Row,
+) -> Result<TransferRecord, Box<dyn Error + Send + Sync>> {
+	let kind: String = row.get(7)?;
+	Ok(TransferRecord {
+		transaction_id: row.get(0)?,
+		transfer_index: row.get(1)?,
+		sender: row.get(2)?,
+		receiver: row.get(3)?,
+		amount: get_amount(row, 4)?,
+		timestamp: row.get(5)?,
+		block_height: row.get(6)?,
+		kind: kind.parse()?,
+		sender_owner: row.get(8)?,
+		receiver_owner: row.get(9)?,
+		invoked_by: row.get(10)?,
+		tags: row
+			.get::<_, Option<String>>(11)?
+			.map(|tags| serde_json::from_str(&tags))
+			.transpose()?
+			.unwrap_or_default(),
+	})
+}
+
+/// Retrieves the most recent blocks that have stored transactions.
+///
+/// Blocks are summarized from the `transactions` table, newest first, so blocks without any
+/// stored transaction are not listed.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `limit` - The maximum number of blocks to return.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing a `Vec<BlockSummary>`.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_recent_blocks(
+	conn: &Connection,
+	limit: u32,
+) -> Result<Vec<BlockSummary>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare("SELECT block_height, MAX(timestamp), COUNT(*), SUM(fee) FROM transactions GROUP BY block_height ORDER BY block_height DESC LIMIT ?1")?;
+	let mut rows = stmt.query(params![limit])?;
+
+	let mut blocks = Vec::new();
+	while let Some(row) = rows.next()? {
+		blocks.push(BlockSummary {
+			slot: row.get(0)?,
+			block_time: row.get(1)?,
+			transaction_count: row.get(2)?,
+			total_fee: row.get(3)?,
+		});
+	}
+	Ok(blocks)
+}
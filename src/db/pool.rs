@@ -0,0 +1,117 @@
+//! Pool of SQLite connections shared by the API handlers and the background tasks.
+//!
+//! In WAL mode SQLite serves any number of readers alongside the single writer, so each request
+//! checks out its own connection instead of queueing behind the others on a shared one. `rusqlite`
+//! calls block, so they run through `interact` on the blocking thread pool rather than on the async
+//! executor.
+//!
+//! A blocking call keeps running once its caller stops waiting for it, so `interact` interrupts the
+//! statement running on the connection when its future is dropped, for instance when the client of
+//! a handler disconnects or the request times out. The statement fails with `SQLITE_INTERRUPT`, and
+//! the connection goes back to the pool instead of being held by a query whose result nobody reads.
+use async_trait::async_trait;
+use rusqlite::{Connection, InterruptHandle, OpenFlags};
+use std::{panic, path::PathBuf, sync::Arc};
+use tracing::Span;
+
+/// A pool of connections to the database.
+pub type DbPool = bb8::Pool<SqliteConnectionManager>;
+
+/// A connection checked out of a `DbPool`, returned to the pool when dropped.
+pub type PooledConnection = bb8::PooledConnection<'static, SqliteConnectionManager>;
+
+type ConnectionSetup = dyn Fn(&mut Connection) -> rusqlite::Result<()> + Send + Sync;
+
+/// Opens the connections of a `DbPool`.
+pub struct SqliteConnectionManager {
+	path: PathBuf,
+	flags: OpenFlags,
+	setup: Arc<ConnectionSetup>,
+}
+
+impl SqliteConnectionManager {
+	/// Creates a manager opening connections to a database file.
+	///
+	/// # Arguments
+	///
+	/// * `path` - The path of the database file.
+	/// * `setup` - Runs on every new connection, to set its pragmas and attach other databases.
+	pub fn new<F>(path: impl Into<PathBuf>, setup: F) -> Self
+	where
+		F: Fn(&mut Connection) -> rusqlite::Result<()> + Send + Sync + 'static,
+	{
+		Self { path: path.into(), flags: OpenFlags::default(), setup: Arc::new(setup) }
+	}
+
+	/// Opens the connections read-only, failing if the database file does not exist.
+	pub fn read_only(self) -> Self {
+		Self { flags: OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX, ..self }
+	}
+}
+
+#[async_trait]
+impl bb8::ManageConnection for SqliteConnectionManager {
+	type Connection = Connection;
+	type Error = rusqlite::Error;
+
+	async fn connect(&self) -> Result<Connection, rusqlite::Error> {
+		let mut conn = Connection::open_with_flags(&self.path, self.flags)?;
+		(self.setup)(&mut conn)?;
+		Ok(conn)
+	}
+
+	async fn is_valid(&self, conn: &mut Connection) -> Result<(), rusqlite::Error> {
+		conn.query_row("SELECT 1", [], |_| Ok(()))
+	}
+
+	fn has_broken(&self, _conn: &mut Connection) -> bool {
+		false
+	}
+}
+
+/// Interrupts the statement running on a connection when dropped, unless disarmed.
+struct InterruptOnDrop(Option<InterruptHandle>);
+
+impl Drop for InterruptOnDrop {
+	fn drop(&mut self) {
+		if let Some(handle) = self.0.take() {
+			handle.interrupt();
+		}
+	}
+}
+
+/// Runs a closure over a pooled connection on the blocking thread pool.
+///
+/// The connection goes back to the pool once the closure returns. A panic of the closure is
+/// resumed in the calling task. When the returned future is dropped before the closure returns, the
+/// statement the closure is running is interrupted; the closure itself runs to its end, so a write
+/// interrupted that way is rolled back with its statement or transaction.
+pub async fn interact<F, T>(conn: PooledConnection, f: F) -> T
+where
+	F: FnOnce(&Connection) -> T + Send + 'static,
+	T: Send + 'static,
+{
+	let mut interrupt = InterruptOnDrop(Some(conn.get_interrupt_handle()));
+	// The closure runs within the span of the caller, e.g. that of the request it answers
+	let span = Span::current();
+	let result = tokio::task::spawn_blocking(move || span.in_scope(|| f(&conn))).await;
+	interrupt.0 = None;
+	match result {
+		Ok(result) => result,
+		Err(err) if err.is_panic() => panic::resume_unwind(err.into_panic()),
+		Err(err) => panic!("database task cancelled: {}", err),
+	}
+}
+
+/// Checks a connection out of the pool and runs a closure over it on the blocking thread pool.
+///
+/// # Errors
+///
+/// This function returns an error if no connection can be checked out of the pool.
+pub async fn with_connection<F, T>(pool: &DbPool, f: F) -> Result<T, bb8::RunError<rusqlite::Error>>
+where
+	F: FnOnce(&Connection) -> T + Send + 'static,
+	T: Send + 'static,
+{
+	Ok(interact(pool.get_owned().await?, f).await)
+}
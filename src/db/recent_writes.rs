@@ -0,0 +1,79 @@
+//! Deduplication of identical account upserts.
+//!
+//! Hot accounts, such as fee collectors and programs, are upserted by most blocks, and by every
+//! block ingested again. The writer remembers the hash of the state it last wrote for the most
+//! recently written accounts, and skips the upserts that would write the same state again.
+//!
+//! The remembered states must match the stored rows: the writer clears them whenever a write it
+//! made may have been rolled back, or when the accounts are written without them.
+use std::{
+	collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+	hash::{Hash, Hasher},
+};
+
+/// A least recently used set of `(account_id, state hash)` pairs.
+///
+/// The default set remembers nothing, so it never skips a write.
+#[derive(Debug, Default)]
+pub struct RecentWrites {
+	capacity: usize,
+	/// The hash of the last written state and the recency stamp of each account.
+	entries: HashMap<String, (u64, u64)>,
+	/// The accounts by recency stamp, oldest first.
+	order: BTreeMap<u64, String>,
+	next_stamp: u64,
+}
+
+impl RecentWrites {
+	/// Creates a set remembering up to `capacity` accounts; a capacity of 0 never skips a write.
+	pub fn new(capacity: usize) -> Self {
+		Self { capacity, ..Default::default() }
+	}
+
+	/// Returns the hash identifying a state to remember.
+	pub fn hash_state<T: Hash>(state: &T) -> u64 {
+		let mut hasher = DefaultHasher::new();
+		state.hash(&mut hasher);
+		hasher.finish()
+	}
+
+	/// Returns whether `hash` is the state last written for the account, marking the account as
+	/// recently used if so.
+	pub fn contains(&mut self, account_id: &str, hash: u64) -> bool {
+		match self.entries.get(account_id) {
+			Some(&(written, _)) if written == hash => {
+				self.touch(account_id, hash);
+				true
+			},
+			_ => false,
+		}
+	}
+
+	/// Records the state written for an account, evicting the least recently used account when the
+	/// set is full.
+	pub fn insert(&mut self, account_id: &str, hash: u64) {
+		if self.capacity == 0 {
+			return
+		}
+		self.touch(account_id, hash);
+		while self.entries.len() > self.capacity {
+			let Some((_, evicted)) = self.order.pop_first() else { break };
+			self.entries.remove(&evicted);
+		}
+	}
+
+	/// Forgets every remembered state.
+	pub fn clear(&mut self) {
+		self.entries.clear();
+		self.order.clear();
+	}
+
+	fn touch(&mut self, account_id: &str, hash: u64) {
+		let stamp = self.next_stamp;
+		self.next_stamp += 1;
+		if let Some((_, previous)) = self.entries.insert(account_id.to_string(), (hash, stamp)) {
+			self.order.remove(&previous);
+		}
+		self.order.insert(stamp, account_id.to_string());
+	}
+}
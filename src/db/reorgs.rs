@@ -0,0 +1,147 @@
+//! Rollback of the blocks and transactions the chain no longer holds after a reorg.
+//!
+//! A rolled back transaction leaves the database along with the records derived from it, and what
+//! its ingestion added elsewhere is taken back: its transfers from the totals and the estimated
+//! balances of the accounts, its fee and volume from the rollup of its day, and its landing from
+//! the status of a submission, which is pending again. A balance read from a rolled back
+//! transaction is forgotten, so the next transaction reporting one sets it. Accounts keep their
+//! first and last active slots, and days their active and new accounts.
+use rusqlite::{params, Connection, OptionalExtension};
+use std::error::Error;
+
+use super::{
+	amount::get_amount,
+	get_account, get_block_transaction_ids, insert_or_update_account,
+	rollups::{self, DailyStats},
+	storage::TRANSACTION_TABLES,
+	submissions,
+};
+
+/// Rolls back a stored block along with its transactions.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slot` - The slot of the block.
+///
+/// # Returns
+///
+/// This function returns the IDs of the rolled back transactions.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn roll_back_block(
+	conn: &Connection,
+	slot: u64,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+	let transaction_ids = get_block_transaction_ids(conn, slot)?;
+	roll_back_transactions(conn, &transaction_ids)?;
+	conn.execute("DELETE FROM blocks WHERE slot = ?1", params![slot])?;
+	Ok(transaction_ids)
+}
+
+/// Rolls back stored transactions, leaving their block.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `transaction_ids` - The IDs of the transactions.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or an account cannot be
+/// written.
+pub fn roll_back_transactions(
+	conn: &Connection,
+	transaction_ids: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	for transaction_id in transaction_ids {
+		roll_back_transaction(conn, transaction_id)?;
+	}
+	Ok(())
+}
+
+fn roll_back_transaction(
+	conn: &Connection,
+	transaction_id: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let stored: Option<(u64, i64, u64)> = conn
+		.query_row(
+			"SELECT block_height, timestamp, fee FROM transactions WHERE transaction_id = ?1",
+			params![transaction_id],
+			|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+		)
+		.optional()?;
+	let Some((slot, timestamp, fee)) = stored else { return Ok(()) };
+
+	// The lamport change of each transfer for its sender and its receiver, as merged into them
+	let mut changes: Vec<(String, i128)> = Vec::new();
+	{
+		let mut stmt = conn.prepare_cached(
+			"SELECT sender, receiver, amount FROM transfers WHERE transaction_id = ?1",
+		)?;
+		let mut rows = stmt.query(params![transaction_id])?;
+		while let Some(row) = rows.next()? {
+			let amount: u64 = get_amount(row, 2)?;
+			changes.push((row.get(0)?, -(amount as i128)));
+			changes.push((row.get(1)?, amount as i128));
+		}
+	}
+
+	let account_ids: Vec<String> = conn
+		.prepare_cached("SELECT account_id FROM account_transactions WHERE transaction_id = ?1")?
+		.query_map(params![transaction_id], |row| row.get(0))?
+		.collect::<rusqlite::Result<_>>()?;
+	for account_id in account_ids {
+		let Some(mut record) = get_account(conn, &account_id)? else { continue };
+		// Only the accounts the transaction was merged into hold it
+		let Some(position) =
+			record.related_transactions.iter().position(|related| related == transaction_id)
+		else {
+			continue
+		};
+		record.related_transactions.remove(position);
+		let mut estimate = record.estimated_balance as i128;
+		for (_, delta) in changes.iter().filter(|(changed, _)| *changed == account_id) {
+			estimate -= delta;
+			let total =
+				if *delta >= 0 { &mut record.total_received } else { &mut record.total_sent };
+			*total = total.saturating_sub(delta.unsigned_abs());
+		}
+		if record.balance_slot == Some(slot) {
+			record.balance_slot = None;
+			record.balance_transaction_index = None;
+		} else if record.balance_slot.is_none() {
+			record.estimated_balance = u64::try_from(estimate.max(0)).unwrap_or(u64::MAX);
+		}
+		insert_or_update_account(conn, &record)?;
+	}
+
+	if let Some(day) = rollups::day_of(timestamp) {
+		let volume =
+			changes.iter().filter(|(_, delta)| *delta > 0).map(|(_, delta)| *delta as u128);
+		let delta = DailyStats {
+			day,
+			transaction_count: 1,
+			volume: volume.sum(),
+			total_fee: fee,
+			..Default::default()
+		};
+		rollups::subtract_daily_stats(conn, &delta)?;
+	}
+	submissions::mark_submission_pending(conn, transaction_id)?;
+
+	for table in TRANSACTION_TABLES {
+		conn.execute(
+			&format!("DELETE FROM {} WHERE transaction_id = ?1", table),
+			params![transaction_id],
+		)?;
+	}
+	conn.execute(
+		"DELETE FROM sandwiches WHERE ?1 IN (front_transaction_id, victim_transaction_id, back_transaction_id)",
+		params![transaction_id],
+	)?;
+	conn.execute("DELETE FROM transactions WHERE transaction_id = ?1", params![transaction_id])?;
+	Ok(())
+}
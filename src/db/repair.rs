@@ -0,0 +1,250 @@
+//! Repair of the rows written by older releases.
+//!
+//! Older releases stored every account with a balance of 0, overwrote the related transactions of
+//! an account with those of its latest block, and stored a time of 0 for the transactions of the
+//! blocks the node reported no time for. The repair rebuilds these rows from what the database
+//! still holds:
+//!
+//! - the time of a transaction, and of its transfers and error, from the time stored for its block;
+//! - the related transactions of an account from its links to the stored transactions, completed by
+//!   the account keys of the stored raw transactions;
+//! - the balance of an account from its latest balance change, or else from its transfer totals.
+//!
+//! It runs once, as a schema migration. The rows it cannot rebuild are left as they are and
+//! reported in the log: transactions whose block has no stored time, accounts left with a zero
+//! balance estimate, and transactions whose raw transaction is redacted or unreadable, whose
+//! accounts could not be linked.
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use solana_transaction_status::EncodedTransaction;
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+use super::amount::{get_amount, Amount};
+use crate::aggregator::processor::get_account_keys;
+
+/// Number of the rows not repaired listed in the report, per kind.
+const MAX_EXAMPLES: usize = 10;
+
+/// Rows the repair could not rebuild, counted, with the first ones as examples.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Unrepaired {
+	pub count: usize,
+	pub examples: Vec<String>,
+}
+
+impl Unrepaired {
+	fn push(&mut self, id: String) {
+		self.count += 1;
+		if self.examples.len() < MAX_EXAMPLES {
+			self.examples.push(id);
+		}
+	}
+}
+
+/// What the repair of the legacy rows rebuilt, and what it could not.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RepairReport {
+	/// Transactions, transfers and errors given the time of their block.
+	pub timestamps_repaired: usize,
+	/// Links between accounts and transactions added from the raw transactions.
+	pub links_added: usize,
+	/// Accounts whose related transactions were completed.
+	pub related_transactions_repaired: usize,
+	/// Accounts given the balance of their latest balance change or transfer totals.
+	pub balances_repaired: usize,
+	/// Transactions left with a time of 0, as no time is stored for their block.
+	pub missing_timestamps: Unrepaired,
+	/// Accounts left with a zero balance estimate, as neither a balance change nor a positive
+	/// transfer total of theirs is stored.
+	pub missing_balances: Unrepaired,
+	/// Transactions whose raw transaction is redacted or cannot be read, whose accounts were not
+	/// linked.
+	pub unreadable_transactions: Unrepaired,
+}
+
+/// Rebuilds the rows written by older releases, see the module documentation.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+///
+/// # Returns
+///
+/// This function returns a `RepairReport` of the rows rebuilt and of those left as they are.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn repair_legacy_rows(conn: &Connection) -> Result<RepairReport> {
+	let mut report = RepairReport::default();
+	repair_timestamps(conn, &mut report)?;
+	link_raw_transactions(conn, &mut report)?;
+	repair_accounts(conn, &mut report)?;
+	Ok(report)
+}
+
+/// Repairs the legacy rows and logs the report, the migration running the repair.
+pub(crate) fn apply_legacy_repair(conn: &Connection) -> Result<()> {
+	let report = repair_legacy_rows(conn)?;
+	info!(
+		"Repaired {} timestamps, {} related transaction lists ({} links added) and {} balances of legacy rows",
+		report.timestamps_repaired,
+		report.related_transactions_repaired,
+		report.links_added,
+		report.balances_repaired
+	);
+	let unrepaired = [
+		("transactions have no stored block time", &report.missing_timestamps),
+		("accounts have no stored balance", &report.missing_balances),
+		("transactions have no readable raw transaction", &report.unreadable_transactions),
+	];
+	for (reason, unrepaired) in unrepaired {
+		if unrepaired.count > 0 {
+			warn!(
+				"{} legacy rows left unrepaired, {}: {}",
+				unrepaired.count,
+				reason,
+				unrepaired.examples.join(", ")
+			);
+		}
+	}
+	Ok(())
+}
+
+/// Gives the rows with a time of 0 the time stored for their block.
+fn repair_timestamps(conn: &Connection, report: &mut RepairReport) -> Result<()> {
+	for table in ["transactions", "transfers", "transaction_errors"] {
+		report.timestamps_repaired += conn.execute(
+			&format!(
+				"UPDATE {table} SET timestamp = (SELECT block_time FROM blocks WHERE slot = {table}.block_height)
+                 WHERE timestamp = 0
+                 AND EXISTS (SELECT 1 FROM blocks WHERE slot = {table}.block_height AND block_time > 0)"
+			),
+			[],
+		)?;
+	}
+	let mut stmt = conn.prepare("SELECT transaction_id FROM transactions WHERE timestamp = 0")?;
+	let mut rows = stmt.query([])?;
+	while let Some(row) = rows.next()? {
+		report.missing_timestamps.push(row.get(0)?);
+	}
+	Ok(())
+}
+
+/// Links the stored accounts to the stored transactions listing them in their account keys.
+///
+/// The links take the position of the transaction in its block from its other links, if any.
+fn link_raw_transactions(conn: &Connection, report: &mut RepairReport) -> Result<()> {
+	let mut link = conn.prepare(
+		"INSERT OR IGNORE INTO account_transactions (account_id, transaction_id, block_height, transaction_index)
+         SELECT ?1, ?2, ?3, (SELECT MIN(transaction_index) FROM account_transactions WHERE transaction_id = ?2)
+         WHERE EXISTS (SELECT 1 FROM accounts WHERE account_id = ?1)",
+	)?;
+	let mut stmt =
+		conn.prepare("SELECT transaction_id, block_height, raw_transaction FROM transactions")?;
+	let mut rows = stmt.query([])?;
+	while let Some(row) = rows.next()? {
+		let transaction_id: String = row.get(0)?;
+		let slot: u64 = row.get(1)?;
+		let raw: Option<String> = row.get(2)?;
+		let account_keys = raw
+			.and_then(|raw| serde_json::from_str::<EncodedTransaction>(&raw).ok())
+			.map(|transaction| get_account_keys(&transaction, None))
+			.unwrap_or_default();
+		if account_keys.is_empty() {
+			report.unreadable_transactions.push(transaction_id);
+			continue
+		}
+		for account_id in account_keys {
+			report.links_added += link.execute(params![account_id, transaction_id, slot])?;
+		}
+	}
+	Ok(())
+}
+
+/// Completes the related transactions of the accounts from their links, and gives the accounts
+/// without a balance the one of their latest balance change or transfer totals.
+fn repair_accounts(conn: &Connection, report: &mut RepairReport) -> Result<()> {
+	let account_ids: Vec<String> = conn
+		.prepare("SELECT account_id FROM accounts ORDER BY account_id")?
+		.query_map([], |row| row.get(0))?
+		.collect::<Result<_>>()?;
+	let mut read = conn.prepare(
+		"SELECT estimated_balance, related_transactions, total_received, total_sent, balance_slot
+         FROM accounts WHERE account_id = ?1",
+	)?;
+	let mut links = conn.prepare(
+		"SELECT transaction_id FROM account_transactions WHERE account_id = ?1
+         ORDER BY block_height, transaction_index, transaction_id",
+	)?;
+	let mut latest_balance = conn.prepare(
+		"SELECT post_balance, block_height, transaction_index FROM balance_changes WHERE account_id = ?1
+         ORDER BY block_height DESC, transaction_index DESC LIMIT 1",
+	)?;
+	for account_id in account_ids {
+		let (balance, related, received, sent, balance_slot) =
+			read.query_row(params![account_id], |row| {
+				let related: Option<String> = row.get(1)?;
+				Ok((
+					get_amount::<u64>(row, 0)?,
+					related,
+					get_amount::<u128>(row, 2)?,
+					get_amount::<u128>(row, 3)?,
+					row.get::<_, Option<u64>>(4)?,
+				))
+			})?;
+
+		// An unreadable list is rebuilt from the links alone
+		let related: Vec<String> = related
+			.and_then(|related| serde_json::from_str(&related).ok())
+			.unwrap_or_default();
+		let linked: Vec<String> =
+			links.query_map(params![account_id], |row| row.get(0))?.collect::<Result<_>>()?;
+		let known: HashSet<&String> = related.iter().collect();
+		if linked.iter().any(|transaction_id| !known.contains(transaction_id)) {
+			let linked_ids: HashSet<&String> = linked.iter().collect();
+			let unlinked =
+				related.iter().filter(|transaction_id| !linked_ids.contains(transaction_id));
+			let rebuilt: Vec<&String> = linked.iter().chain(unlinked).collect();
+			conn.execute(
+				"UPDATE accounts SET related_transactions = ?2 WHERE account_id = ?1",
+				params![account_id, serde_json::to_string(&rebuilt).expect("strings serialize")],
+			)?;
+			report.related_transactions_repaired += 1;
+		}
+
+		if balance != 0 || balance_slot.is_some() {
+			continue
+		}
+		let latest = latest_balance
+			.query_row(params![account_id], |row| {
+				Ok((
+					get_amount::<u64>(row, 0)?,
+					row.get::<_, u64>(1)?,
+					row.get::<_, Option<u32>>(2)?,
+				))
+			})
+			.optional()?;
+		if let Some((post_balance, slot, transaction_index)) = latest {
+			conn.execute(
+				"UPDATE accounts SET estimated_balance = ?2, balance_slot = ?3, balance_transaction_index = ?4
+                 WHERE account_id = ?1",
+				params![account_id, Amount::from(post_balance), slot, transaction_index],
+			)?;
+			report.balances_repaired += 1;
+		} else if let Some(estimate) = received
+			.checked_sub(sent)
+			.filter(|estimate| *estimate > 0)
+			.and_then(|estimate| u64::try_from(estimate).ok())
+		{
+			conn.execute(
+				"UPDATE accounts SET estimated_balance = ?2 WHERE account_id = ?1",
+				params![account_id, Amount::from(estimate)],
+			)?;
+			report.balances_repaired += 1;
+		} else {
+			report.missing_balances.push(account_id);
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,250 @@
+//! Per-day rollups of the ingested transactions, maintained incrementally at ingestion time so the
+//! dashboard queries do not scan the transactions.
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error};
+
+use super::amount::{get_amount, Amount};
+
+/// Activity of a UTC day.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailyStats {
+	/// The day, as `YYYY-MM-DD`.
+	pub day: String,
+	pub transaction_count: u64,
+	/// Lamports moved by transfers.
+	pub volume: u128,
+	pub total_fee: u64,
+	/// Accounts involved in at least one transaction of the day.
+	pub active_accounts: u64,
+	/// Accounts involved in a transaction for the first time.
+	pub new_accounts: u64,
+}
+
+/// Initializes the `daily_stats` and `daily_active_accounts` tables.
+///
+/// When the tables are created on an existing database, they are seeded from the stored
+/// transactions, so this must run once the other tables are initialized.
+pub fn initialize_rollups(conn: &Connection) -> rusqlite::Result<()> {
+	let exists: bool = conn.query_row(
+		"SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'daily_stats')",
+		[],
+		|row| row.get(0),
+	)?;
+	if exists {
+		return Ok(())
+	}
+
+	let tx = conn.unchecked_transaction()?;
+	tx.execute(
+		"CREATE TABLE daily_active_accounts (
+            day TEXT,
+            account_id TEXT,
+            PRIMARY KEY (day, account_id)
+        )",
+		[],
+	)?;
+	tx.execute(
+		"CREATE TABLE daily_stats (
+            day TEXT PRIMARY KEY,
+            transaction_count INTEGER NOT NULL,
+            volume TEXT NOT NULL,
+            total_fee INTEGER NOT NULL,
+            active_accounts INTEGER NOT NULL,
+            new_accounts INTEGER NOT NULL
+        )",
+		[],
+	)?;
+
+	tx.execute(
+		"INSERT INTO daily_active_accounts (day, account_id)
+         SELECT DISTINCT date(t.timestamp, 'unixepoch'), a.account_id
+         FROM account_transactions a
+         JOIN transactions t ON t.transaction_id = a.transaction_id
+         WHERE t.timestamp > 0",
+		[],
+	)?;
+	tx.execute(
+		"INSERT INTO daily_stats (day, transaction_count, volume, total_fee, active_accounts, new_accounts)
+         SELECT date(timestamp, 'unixepoch'), COUNT(*), '0', SUM(fee), 0, 0
+         FROM transactions WHERE timestamp > 0 GROUP BY date(timestamp, 'unixepoch')",
+		[],
+	)?;
+	tx.execute(
+		"UPDATE daily_stats SET
+            active_accounts = (SELECT COUNT(*) FROM daily_active_accounts d WHERE d.day = daily_stats.day),
+            new_accounts = (
+                SELECT COUNT(*) FROM (SELECT MIN(day) AS first_day FROM daily_active_accounts GROUP BY account_id)
+                WHERE first_day = daily_stats.day
+            )",
+		[],
+	)?;
+
+	// Amounts are stored as text, so the volumes are summed here rather than in SQL
+	let mut volumes: HashMap<String, u128> = HashMap::new();
+	{
+		let mut stmt = tx.prepare(
+			"SELECT date(timestamp, 'unixepoch'), amount FROM transfers WHERE timestamp > 0",
+		)?;
+		let mut rows = stmt.query([])?;
+		while let Some(row) = rows.next()? {
+			*volumes.entry(row.get(0)?).or_default() += get_amount::<u128>(row, 1)?;
+		}
+	}
+	for (day, volume) in volumes {
+		tx.execute(
+			"UPDATE daily_stats SET volume = ?2 WHERE day = ?1",
+			params![day, Amount::from(volume)],
+		)?;
+	}
+	tx.commit()
+}
+
+/// Returns the UTC day of a unix timestamp, `None` for a missing (zero) block time.
+pub fn day_of(timestamp: i64) -> Option<String> {
+	if timestamp <= 0 {
+		return None
+	}
+	chrono::DateTime::from_timestamp(timestamp, 0).map(|time| time.format("%Y-%m-%d").to_string())
+}
+
+/// Records an account as active on a day.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `day` - The day, as `YYYY-MM-DD`.
+/// * `account_id` - The account involved in a transaction of the day.
+///
+/// # Returns
+///
+/// This function returns `true` if the account was not active on that day yet.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn mark_active_account(
+	conn: &Connection,
+	day: &str,
+	account_id: &str,
+) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"INSERT OR IGNORE INTO daily_active_accounts (day, account_id) VALUES (?1, ?2)",
+	)?;
+	Ok(stmt.execute(params![day, account_id])? == 1)
+}
+
+/// Adds the activity of newly ingested transactions to the rollup of their day.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `delta` - A reference to the `DailyStats` to add to the stored ones.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or a counter overflows.
+pub fn add_daily_stats(
+	conn: &Connection,
+	delta: &DailyStats,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let stored = query_daily_stats(conn, "WHERE day = ?1", params![delta.day])?
+		.pop()
+		.unwrap_or_else(|| DailyStats { day: delta.day.clone(), ..Default::default() });
+	let overflow = || format!("Daily stats overflow on {}", delta.day);
+	conn.execute(
+		"INSERT OR REPLACE INTO daily_stats (day, transaction_count, volume, total_fee, active_accounts, new_accounts)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+		params![
+			delta.day,
+			stored.transaction_count + delta.transaction_count,
+			Amount::from(stored.volume.checked_add(delta.volume).ok_or_else(overflow)?),
+			stored.total_fee.checked_add(delta.total_fee).ok_or_else(overflow)?,
+			stored.active_accounts + delta.active_accounts,
+			stored.new_accounts + delta.new_accounts,
+		],
+	)?;
+	Ok(())
+}
+
+/// Takes the activity of rolled back transactions out of the rollup of their day.
+///
+/// The active and new accounts of the day are kept, as other transactions may involve them.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `delta` - A reference to the `DailyStats` to subtract from the stored ones.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn subtract_daily_stats(
+	conn: &Connection,
+	delta: &DailyStats,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let Some(stored) = query_daily_stats(conn, "WHERE day = ?1", params![delta.day])?.pop() else {
+		return Ok(())
+	};
+	conn.execute(
+		"UPDATE daily_stats SET transaction_count = ?2, volume = ?3, total_fee = ?4 WHERE day = ?1",
+		params![
+			delta.day,
+			stored.transaction_count.saturating_sub(delta.transaction_count),
+			Amount::from(stored.volume.saturating_sub(delta.volume)),
+			stored.total_fee.saturating_sub(delta.total_fee),
+		],
+	)?;
+	Ok(())
+}
+
+/// Retrieves the rollups of the days within a range, oldest first.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `from` - The optional first day, as `YYYY-MM-DD`, inclusive.
+/// * `to` - The optional last day, as `YYYY-MM-DD`, inclusive.
+/// * `limit` - The maximum number of days to return; the most recent days of the range are kept.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_daily_stats(
+	conn: &Connection,
+	from: Option<&str>,
+	to: Option<&str>,
+	limit: u32,
+) -> Result<Vec<DailyStats>, Box<dyn Error + Send + Sync>> {
+	let mut days = query_daily_stats(
+		conn,
+		"WHERE (?1 IS NULL OR day >= ?1) AND (?2 IS NULL OR day <= ?2) ORDER BY day DESC LIMIT ?3",
+		params![from, to, limit],
+	)?;
+	days.reverse();
+	Ok(days)
+}
+
+fn query_daily_stats(
+	conn: &Connection,
+	filter: &str,
+	params: &[&dyn rusqlite::ToSql],
+) -> Result<Vec<DailyStats>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT day, transaction_count, volume, total_fee, active_accounts, new_accounts FROM daily_stats {}",
+		filter
+	))?;
+	let days = stmt
+		.query_map(params, |row| {
+			Ok(DailyStats {
+				day: row.get(0)?,
+				transaction_count: row.get(1)?,
+				volume: get_amount(row, 2)?,
+				total_fee: row.get(3)?,
+				active_accounts: row.get(4)?,
+				new_accounts: row.get(5)?,
+			})
+		})?
+		.collect::<rusqlite::Result<Vec<_>>>()?;
+	Ok(days)
+}
@@ -0,0 +1,118 @@
+//! Visibility scopes of the API keys.
+//!
+//! A key restricted to some account groups or label tags sees the members of its groups and the
+//! accounts whose label carries one of its tags, so one deployment serves several teams without
+//! any of them reading the wallets of the others. The scope is checked by the queries of the
+//! handlers, against the groups and labels stored at the time of the request: adding a wallet to a
+//! group makes it visible to the keys of the group right away.
+use rusqlite::{params, Connection};
+use std::{error::Error, fmt};
+
+/// The accounts visible to the members of the groups bound to `?2` and the label tags bound to
+/// `?3`, both as JSON arrays.
+const SCOPE_ACCOUNTS: &str = "SELECT pubkey FROM group_members
+     WHERE group_name IN (SELECT value FROM json_each(?2))
+     UNION
+     SELECT labels.pubkey FROM labels, json_each(labels.tags) AS tag
+     WHERE tag.value IN (SELECT value FROM json_each(?3))";
+
+/// The accounts an API key may see. A scope without groups or label tags sees every account.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountScope {
+	pub groups: Vec<String>,
+	pub label_tags: Vec<String>,
+}
+
+/// The error of a query reading data outside the scope of the API key of the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutsideScope;
+
+impl fmt::Display for OutsideScope {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Outside the scope of the API key")
+	}
+}
+
+impl Error for OutsideScope {}
+
+impl AccountScope {
+	/// Returns whether the scope sees every account.
+	pub fn is_unrestricted(&self) -> bool {
+		self.groups.is_empty() && self.label_tags.is_empty()
+	}
+
+	/// Returns whether the scope sees a group as a whole.
+	pub fn includes_group(&self, name: &str) -> bool {
+		self.is_unrestricted() || self.groups.iter().any(|group| group == name)
+	}
+
+	/// Checks that the scope sees an account.
+	///
+	/// # Errors
+	///
+	/// This function returns an `OutsideScope` error if the account is not visible, or an error if
+	/// the database operation fails.
+	pub fn check_account(
+		&self,
+		conn: &Connection,
+		account_id: &str,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		if self.is_unrestricted() {
+			return Ok(())
+		}
+		let (groups, label_tags) = self.bindings()?;
+		let visible: bool = conn.query_row(
+			&format!("SELECT ?1 IN ({})", SCOPE_ACCOUNTS),
+			params![account_id, groups, label_tags],
+			|row| row.get(0),
+		)?;
+		if !visible {
+			return Err(OutsideScope.into())
+		}
+		Ok(())
+	}
+
+	/// Checks that the scope sees a transaction, i.e. one of the accounts it is linked to.
+	///
+	/// A transaction that is not stored passes, so that it is reported as not found.
+	///
+	/// # Errors
+	///
+	/// This function returns an `OutsideScope` error if the transaction is not visible, or an
+	/// error if the database operation fails.
+	pub fn check_transaction(
+		&self,
+		conn: &Connection,
+		transaction_id: &str,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		if self.is_unrestricted() {
+			return Ok(())
+		}
+		let (groups, label_tags) = self.bindings()?;
+		// A transaction moved to cold storage keeps its account links in the hot database
+		let cold = if crate::db::tiering::is_cold_attached(conn)? {
+			" OR EXISTS (SELECT 1 FROM cold.transactions WHERE transaction_id = ?1)"
+		} else {
+			""
+		};
+		let (stored, visible): (bool, bool) = conn.query_row(
+			&format!(
+				"SELECT EXISTS (SELECT 1 FROM main.transactions WHERE transaction_id = ?1){},
+                        EXISTS (SELECT 1 FROM account_transactions
+                                WHERE transaction_id = ?1 AND account_id IN ({}))",
+				cold, SCOPE_ACCOUNTS
+			),
+			params![transaction_id, groups, label_tags],
+			|row| Ok((row.get(0)?, row.get(1)?)),
+		)?;
+		if stored && !visible {
+			return Err(OutsideScope.into())
+		}
+		Ok(())
+	}
+
+	/// Returns the groups and label tags of the scope as the JSON arrays bound to the queries.
+	fn bindings(&self) -> Result<(String, String), Box<dyn Error + Send + Sync>> {
+		Ok((serde_json::to_string(&self.groups)?, serde_json::to_string(&self.label_tags)?))
+	}
+}
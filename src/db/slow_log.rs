@@ -0,0 +1,64 @@
+//! Logging of the database queries slower than a configurable threshold.
+//!
+//! SQLite reports the text of a statement with its bound parameters expanded when the statement
+//! starts running, and its duration, without the parameters, once it completes. The expanded texts
+//! are kept on a per-thread stack until the matching completion, since statements are nested while
+//! iterating over rows.
+use rusqlite::Connection;
+use std::{
+	cell::RefCell,
+	sync::atomic::{AtomicU64, Ordering},
+	time::Duration,
+};
+use tracing::warn;
+
+/// Duration above which queries are logged, in microseconds. The SQLite callbacks cannot capture
+/// state, so the threshold is shared by every connection.
+static THRESHOLD_MICROS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Maximum number of running statements tracked per thread.
+const MAX_RUNNING_STATEMENTS: usize = 64;
+
+thread_local! {
+	static RUNNING_STATEMENTS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Logs the queries of a connection taking longer than a threshold, with their parameters.
+///
+/// # Arguments
+///
+/// * `conn` - The connection to instrument.
+/// * `threshold` - The duration above which a query is logged.
+pub fn enable_slow_query_log(conn: &mut Connection, threshold: Duration) {
+	THRESHOLD_MICROS.store(threshold.as_micros().min(u64::MAX as u128) as u64, Ordering::Relaxed);
+	conn.trace(Some(on_statement_start));
+	conn.profile(Some(on_statement_end));
+}
+
+fn on_statement_start(expanded_sql: &str) {
+	RUNNING_STATEMENTS.with(|running| {
+		let mut running = running.borrow_mut();
+		if running.len() == MAX_RUNNING_STATEMENTS {
+			running.remove(0);
+		}
+		running.push(expanded_sql.to_string());
+	});
+}
+
+fn on_statement_end(sql: &str, duration: Duration) {
+	// The expanded text matches the statement text up to its first parameter
+	let prefix = sql.split('?').next().unwrap_or_default();
+	let expanded_sql = RUNNING_STATEMENTS.with(|running| {
+		let mut running = running.borrow_mut();
+		let position = running.iter().rposition(|expanded| expanded.starts_with(prefix))?;
+		Some(running.remove(position))
+	});
+
+	if duration.as_micros() >= THRESHOLD_MICROS.load(Ordering::Relaxed) as u128 {
+		warn!(
+			"Slow query ({} ms): {}",
+			duration.as_millis(),
+			expanded_sql.as_deref().unwrap_or(sql).trim()
+		);
+	}
+}
@@ -0,0 +1,222 @@
+//! Aggregate queries over the stored transactions, used by the statistics endpoints.
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+/// Aggregated fee spending of a single fee payer.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeePayerStats {
+	pub fee_payer: String,
+	pub transaction_count: u64,
+	pub total_fee: u64,
+	pub average_fee: f64,
+}
+
+/// Retrieves the top fee payers since a given timestamp.
+///
+/// This function groups the stored transactions by fee payer and returns the accounts that spent
+/// the most on fees, ordered by total fee in descending order.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `since` - The unix timestamp from which transactions are taken into account.
+/// * `limit` - The maximum number of fee payers to return.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing a `Vec<FeePayerStats>`.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_top_fee_payers(
+	conn: &Connection,
+	since: i64,
+	limit: usize,
+) -> Result<Vec<FeePayerStats>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare("SELECT fee_payer, COUNT(*), SUM(fee), AVG(fee) FROM transactions WHERE timestamp >= ?1 GROUP BY fee_payer ORDER BY SUM(fee) DESC LIMIT ?2")?;
+	let mut rows = stmt.query(params![since, limit])?;
+
+	let mut stats = Vec::new();
+	while let Some(row) = rows.next()? {
+		stats.push(FeePayerStats {
+			fee_payer: row.get(0)?,
+			transaction_count: row.get(1)?,
+			total_fee: row.get(2)?,
+			average_fee: row.get(3)?,
+		});
+	}
+	Ok(stats)
+}
+
+/// Number of occurrences of an error code raised by a program.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorFrequency {
+	pub program_id: Option<String>,
+	pub error: String,
+	pub count: u64,
+}
+
+/// Retrieves the frequencies of the errors failed transactions ended with since a given timestamp.
+///
+/// This function groups the stored transaction errors by program and error code, ordered by
+/// frequency in descending order. Transaction-level errors are reported without a program.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `since` - The unix timestamp from which failed transactions are taken into account.
+/// * `program_id` - An optional program ID to restrict the errors to.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing a `Vec<ErrorFrequency>`.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_error_frequencies(
+	conn: &Connection,
+	since: i64,
+	program_id: Option<&str>,
+) -> Result<Vec<ErrorFrequency>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare("SELECT program_id, error, COUNT(*) FROM transaction_errors WHERE timestamp >= ?1 AND (?2 IS NULL OR program_id = ?2) GROUP BY program_id, error ORDER BY COUNT(*) DESC")?;
+	let mut rows = stmt.query(params![since, program_id])?;
+
+	let mut frequencies = Vec::new();
+	while let Some(row) = rows.next()? {
+		frequencies.push(ErrorFrequency {
+			program_id: row.get(0)?,
+			error: row.get(1)?,
+			count: row.get(2)?,
+		});
+	}
+	Ok(frequencies)
+}
+
+/// Percentiles of the priority fees paid over a window of recent blocks.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PriorityFeeStats {
+	pub from_slot: u64,
+	pub to_slot: u64,
+	pub samples: u64,
+	pub p50: u64,
+	pub p75: u64,
+	pub p95: u64,
+}
+
+/// Computes the percentiles of the compute unit prices paid in the most recent blocks.
+///
+/// The window spans the `blocks` slots up to the most recent stored slot. Only transactions that
+/// set a compute unit price through the ComputeBudget program are sampled, and percentiles use the
+/// nearest-rank method. Prices are in micro-lamports per compute unit.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `blocks` - The number of recent slots to compute the percentiles over.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing an `Option<PriorityFeeStats>`.
+/// The `Option` is `None` if no transaction in the window set a compute unit price.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_priority_fee_percentiles(
+	conn: &Connection,
+	blocks: u64,
+) -> Result<Option<PriorityFeeStats>, Box<dyn Error + Send + Sync>> {
+	let to_slot: Option<u64> =
+		conn.query_row("SELECT MAX(block_height) FROM transactions", [], |row| row.get(0))?;
+	let Some(to_slot) = to_slot else { return Ok(None) };
+	let from_slot = (to_slot + 1).saturating_sub(blocks);
+
+	let mut stmt = conn.prepare("SELECT compute_unit_price FROM transactions WHERE block_height >= ?1 AND compute_unit_price IS NOT NULL ORDER BY compute_unit_price")?;
+	let prices = stmt
+		.query_map(params![from_slot], |row| row.get::<_, u64>(0))?
+		.collect::<Result<Vec<_>, _>>()?;
+	if prices.is_empty() {
+		return Ok(None)
+	}
+
+	Ok(Some(PriorityFeeStats {
+		from_slot,
+		to_slot,
+		samples: prices.len() as u64,
+		p50: percentile(&prices, 50),
+		p75: percentile(&prices, 75),
+		p95: percentile(&prices, 95),
+	}))
+}
+
+/// The transactions of an account bucketed by day of the week and hour of the day, in UTC.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActivityHeatmap {
+	pub account_id: String,
+	/// The unix timestamp from which transactions are counted.
+	pub since: i64,
+	/// The number of transactions counted.
+	pub total: u64,
+	/// The number of transactions per day of the week, Sunday first, and hour of the day.
+	pub counts: [[u64; 24]; 7],
+}
+
+/// Retrieves the activity heat map of an account since a given timestamp.
+///
+/// This function counts the stored transactions linked to the account, grouped by the day of the
+/// week and the hour of the day of their block time in UTC. Transactions without a block time are
+/// left out.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `account_id` - The account whose transactions are counted.
+/// * `since` - The unix timestamp from which transactions are taken into account.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing an `ActivityHeatmap`, whose counts are all zero for
+/// an account without transactions.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_activity_heatmap(
+	conn: &Connection,
+	account_id: &str,
+	since: i64,
+) -> Result<ActivityHeatmap, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT CAST(strftime('%w', t.timestamp, 'unixepoch') AS INTEGER) AS day,
+                CAST(strftime('%H', t.timestamp, 'unixepoch') AS INTEGER) AS hour,
+                COUNT(*)
+         FROM account_transactions a
+         JOIN transactions t ON t.transaction_id = a.transaction_id
+         WHERE a.account_id = ?1 AND t.timestamp >= ?2
+         GROUP BY day, hour",
+	)?;
+	let mut rows = stmt.query(params![account_id, since])?;
+
+	let mut heatmap = ActivityHeatmap {
+		account_id: account_id.to_string(),
+		since,
+		total: 0,
+		counts: [[0; 24]; 7],
+	};
+	while let Some(row) = rows.next()? {
+		let (day, hour, count): (usize, usize, u64) = (row.get(0)?, row.get(1)?, row.get(2)?);
+		heatmap.counts[day][hour] = count;
+		heatmap.total += count;
+	}
+	Ok(heatmap)
+}
+
+/// Returns the nearest-rank percentile of a sorted, non-empty slice.
+fn percentile(sorted: &[u64], percentile: usize) -> u64 {
+	let rank = (percentile * sorted.len()).div_ceil(100);
+	sorted[rank.saturating_sub(1)]
+}
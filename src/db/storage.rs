@@ -0,0 +1,275 @@
+//! Estimation of the space taken by the database, and enforcement of the storage quota.
+//!
+//! The space used by the data is that of the database pages minus the free ones: rows deleted by a
+//! prune free their pages for the next writes rather than shrinking the file. The size of each
+//! table is estimated from its row count and the average size of its most recent rows.
+//!
+//! With a quota configured, a monitor measures the database periodically. Once the data exceeds the
+//! quota, the ingestion either pauses its writes until space is freed, or the transactions of the
+//! oldest slots are pruned until the data is back under 90% of the quota, pausing the ingestion
+//! only if nothing is left to prune.
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, error::Error, time::Duration};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use super::pool::{with_connection, DbPool};
+use crate::types::{QuotaAction, StorageQuotaConfig};
+
+/// Number of the most recent rows of a table sampled to estimate the size of its rows.
+const SAMPLE_ROWS: u32 = 100;
+
+/// Number of slots whose transactions are deleted by a single prune.
+const PRUNE_SLOTS: u64 = 10_000;
+
+/// Share of the quota a prune brings the data back under.
+const PRUNE_TARGET: f64 = 0.9;
+
+/// The tables holding the details of the transactions, deleted along with them.
+pub(crate) const TRANSACTION_TABLES: &[&str] = &[
+	"transaction_errors",
+	"balance_changes",
+	"transfers",
+	"swaps",
+	"program_instructions",
+	"account_transactions",
+];
+
+/// The space taken by the database file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseSize {
+	/// The bytes of the pages holding data.
+	pub used_bytes: u64,
+	/// The bytes of the free pages, reused by the next writes.
+	pub free_bytes: u64,
+}
+
+/// The estimated size of a table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableUsage {
+	pub table: String,
+	pub rows: u64,
+	/// The row count times the average size of the values of the most recent rows.
+	pub estimated_bytes: u64,
+}
+
+/// The space taken by the database and its tables, with the state of the quota.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StorageUsage {
+	pub database: DatabaseSize,
+	pub tables: Vec<TableUsage>,
+	/// `None` when no quota is configured, or the process does not ingest blocks.
+	pub quota: Option<QuotaStatus>,
+}
+
+/// The state of the storage quota, as last measured by the monitor.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct QuotaStatus {
+	pub max_bytes: u64,
+	pub used_bytes: u64,
+	pub exceeded: bool,
+	/// Whether the ingestion waits for space before writing blocks.
+	pub ingestion_paused: bool,
+	/// The unix timestamp of the last measure, `None` until the first one.
+	pub checked_at: Option<i64>,
+}
+
+/// Measures the space taken by the database file.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_database_size(conn: &Connection) -> Result<DatabaseSize, Box<dyn Error + Send + Sync>> {
+	let pragma = |name: &str| -> rusqlite::Result<u64> {
+		conn.query_row(&format!("PRAGMA main.{}", name), [], |row| row.get(0))
+	};
+	let page_size = pragma("page_size")?;
+	let (pages, free_pages) = (pragma("page_count")?, pragma("freelist_count")?);
+	Ok(DatabaseSize {
+		used_bytes: pages.saturating_sub(free_pages) * page_size,
+		free_bytes: free_pages * page_size,
+	})
+}
+
+/// Estimates the size of each table of the database, largest first.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn estimate_table_usage(
+	conn: &Connection,
+) -> Result<Vec<TableUsage>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT name FROM main.sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+	)?;
+	let tables = stmt
+		.query_map([], |row| row.get(0))?
+		.collect::<rusqlite::Result<Vec<String>>>()?;
+	let mut usage = Vec::new();
+	for table in tables {
+		let mut stmt = conn.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?;
+		let columns = stmt
+			.query_map([], |row| row.get(0))?
+			.collect::<rusqlite::Result<Vec<String>>>()?;
+		let row_size = columns
+			.iter()
+			.map(|column| format!("COALESCE(LENGTH(\"{}\"), 0)", column))
+			.collect::<Vec<_>>()
+			.join(" + ");
+		let rows: u64 =
+			conn.query_row(&format!("SELECT COUNT(*) FROM \"{}\"", table), [], |row| row.get(0))?;
+		// Tables without a rowid are sampled in the order of their key
+		let recent = format!("SELECT * FROM \"{}\" ORDER BY rowid DESC LIMIT ?1", table);
+		let sample = format!("SELECT * FROM \"{}\" LIMIT ?1", table);
+		let average = |query: &str| -> rusqlite::Result<f64> {
+			conn.query_row(
+				&format!("SELECT COALESCE(AVG({}), 0) FROM ({})", row_size, query),
+				params![SAMPLE_ROWS],
+				|row| row.get(0),
+			)
+		};
+		let average = average(&recent).or_else(|_| average(&sample))?;
+		usage.push(TableUsage { table, rows, estimated_bytes: (rows as f64 * average) as u64 });
+	}
+	usage.sort_by_key(|usage| Reverse(usage.estimated_bytes));
+	Ok(usage)
+}
+
+/// Deletes the transactions of the oldest stored slots, along with their details and blocks.
+///
+/// The accounts and daily statistics keep the totals of the deleted transactions.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slots` - The number of slots pruned, from the oldest one holding a transaction.
+///
+/// # Returns
+///
+/// This function returns the number of deleted transactions.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn prune_oldest_slots(
+	conn: &Connection,
+	slots: u64,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+	let oldest: Option<u64> =
+		conn.query_row("SELECT MIN(block_height) FROM transactions", [], |row| row.get(0))?;
+	let Some(oldest) = oldest else { return Ok(0) };
+	let before = oldest.saturating_add(slots);
+	let tx = conn.unchecked_transaction()?;
+	for table in TRANSACTION_TABLES {
+		tx.execute(
+			&format!(
+				"DELETE FROM {} WHERE transaction_id IN (SELECT transaction_id FROM transactions WHERE block_height < ?1)",
+				table
+			),
+			params![before],
+		)?;
+	}
+	tx.execute("DELETE FROM sandwiches WHERE block_height < ?1", params![before])?;
+	tx.execute("DELETE FROM blocks WHERE slot < ?1", params![before])?;
+	let deleted =
+		tx.execute("DELETE FROM transactions WHERE block_height < ?1", params![before])?;
+	tx.commit()?;
+	Ok(deleted)
+}
+
+/// Publishes the state of the storage quota to the ingestion and the API.
+#[derive(Clone)]
+pub struct StorageMonitor {
+	status: watch::Receiver<QuotaStatus>,
+}
+
+impl StorageMonitor {
+	/// Spawns the monitor enforcing a quota on the database of the pool.
+	pub fn spawn(pool: DbPool, config: StorageQuotaConfig) -> Self {
+		let status = QuotaStatus { max_bytes: config.max_bytes, ..Default::default() };
+		let (sender, receiver) = watch::channel(status);
+		tokio::spawn(run_storage_monitor(pool, config, sender));
+		Self { status: receiver }
+	}
+
+	/// Returns the state of the quota as last measured.
+	pub fn status(&self) -> QuotaStatus {
+		self.status.borrow().clone()
+	}
+
+	/// Waits until the ingestion may write, returning right away if the monitor stopped.
+	pub async fn wait_for_space(&self) {
+		let mut status = self.status.clone();
+		let _ = status.wait_for(|status| !status.ingestion_paused).await;
+	}
+}
+
+/// Measures the database every `check_interval_secs`, prunes it or pauses the ingestion while it
+/// exceeds the quota, and publishes the state of the quota, until the last receiver is dropped.
+async fn run_storage_monitor(
+	pool: DbPool,
+	config: StorageQuotaConfig,
+	status: watch::Sender<QuotaStatus>,
+) {
+	let mut ticker = tokio::time::interval(Duration::from_secs(config.check_interval_secs.max(1)));
+	while !status.is_closed() {
+		ticker.tick().await;
+		let max_bytes = config.max_bytes;
+		let action = config.on_exceeded;
+		let measure = with_connection(&pool, move |conn| enforce_quota(conn, max_bytes, action));
+		match measure.await {
+			Ok(Ok(measured)) => {
+				status.send_if_modified(|current| {
+					let changed = (current.exceeded, current.ingestion_paused) !=
+						(measured.exceeded, measured.ingestion_paused);
+					if changed && measured.ingestion_paused {
+						warn!(
+							"Database uses {} bytes, above its quota of {}: pausing the ingestion",
+							measured.used_bytes, max_bytes
+						);
+					} else if changed && current.ingestion_paused {
+						info!("Database back under its storage quota, resuming the ingestion");
+					}
+					*current = measured;
+					changed
+				});
+			},
+			Ok(Err(err)) => error!("Failed to measure the database: {:?}", err),
+			Err(err) => error!("Failed to check out a database connection: {:?}", err),
+		}
+	}
+}
+
+/// Measures the database against a quota, pruning it while it exceeds the quota with the `prune`
+/// action.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn enforce_quota(
+	conn: &Connection,
+	max_bytes: u64,
+	action: QuotaAction,
+) -> Result<QuotaStatus, Box<dyn Error + Send + Sync>> {
+	let mut used_bytes = get_database_size(conn)?.used_bytes;
+	if action == QuotaAction::Prune && used_bytes > max_bytes {
+		let target = (max_bytes as f64 * PRUNE_TARGET) as u64;
+		while used_bytes > target {
+			let deleted = prune_oldest_slots(conn, PRUNE_SLOTS)?;
+			if deleted == 0 {
+				break
+			}
+			used_bytes = get_database_size(conn)?.used_bytes;
+			info!("Pruned {} transactions, the database now uses {} bytes", deleted, used_bytes);
+		}
+	}
+	let exceeded = used_bytes > max_bytes;
+	Ok(QuotaStatus {
+		max_bytes,
+		used_bytes,
+		exceeded,
+		ingestion_paused: exceeded,
+		checked_at: Some(chrono::Utc::now().timestamp()),
+	})
+}
@@ -0,0 +1,152 @@
+//! Tracking of the transactions submitted through the service.
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+/// Status of a submitted transaction that has not been seen in an ingested block yet.
+pub const STATUS_PENDING: &str = "pending";
+/// Status of a submitted transaction that landed in an ingested block and succeeded.
+pub const STATUS_CONFIRMED: &str = "confirmed";
+/// Status of a submitted transaction that landed in an ingested block and failed.
+pub const STATUS_FAILED: &str = "failed";
+
+/// A record representing a transaction submitted through the service.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubmissionRecord {
+	pub signature: String,
+	/// The unix timestamp of the submission.
+	pub submitted_at: i64,
+	/// One of `pending`, `confirmed` or `failed`.
+	pub status: String,
+	/// The slot the transaction landed in, once ingested.
+	pub slot: Option<u64>,
+	/// The error the transaction failed with, if any.
+	pub error: Option<String>,
+}
+
+/// Initializes the `submissions` table.
+pub fn initialize_submissions(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS submissions (
+            signature TEXT PRIMARY KEY,
+            submitted_at INTEGER,
+            status TEXT,
+            slot INTEGER,
+            error TEXT
+        )",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Records a submitted transaction as pending.
+///
+/// Submitting the same transaction again keeps its current status.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `signature` - The signature of the submitted transaction.
+/// * `submitted_at` - The unix timestamp of the submission.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn insert_submission(
+	conn: &Connection,
+	signature: &str,
+	submitted_at: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	conn.execute(
+		"INSERT OR IGNORE INTO submissions (signature, submitted_at, status) VALUES (?1, ?2, ?3)",
+		params![signature, submitted_at, STATUS_PENDING],
+	)?;
+	Ok(())
+}
+
+/// Marks a submitted transaction as landed in an ingested block.
+///
+/// Transactions that were not submitted through the service are left untouched.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `signature` - The signature of the ingested transaction.
+/// * `slot` - The slot of the block the transaction landed in.
+/// * `error` - The error the transaction failed with, if any.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn mark_submission_landed(
+	conn: &Connection,
+	signature: &str,
+	slot: u64,
+	error: Option<&str>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let status = if error.is_some() { STATUS_FAILED } else { STATUS_CONFIRMED };
+	let mut stmt = conn.prepare_cached(
+		"UPDATE submissions SET status = ?2, slot = ?3, error = ?4 WHERE signature = ?1",
+	)?;
+	stmt.execute(params![signature, status, slot, error])?;
+	Ok(())
+}
+
+/// Marks a submitted transaction as pending again, once the block it landed in is rolled back.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `signature` - The signature of the rolled back transaction.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn mark_submission_pending(
+	conn: &Connection,
+	signature: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	conn.execute(
+		"UPDATE submissions SET status = ?2, slot = NULL, error = NULL WHERE signature = ?1",
+		params![signature, STATUS_PENDING],
+	)?;
+	Ok(())
+}
+
+/// Retrieves a submitted transaction by signature.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `signature` - The signature of the submitted transaction.
+///
+/// # Returns
+///
+/// This function returns a `Result` containing an `Option<SubmissionRecord>`.
+/// The `Option` is `None` if the transaction was not submitted through the service.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_submission(
+	conn: &Connection,
+	signature: &str,
+) -> Result<Option<SubmissionRecord>, Box<dyn Error + Send + Sync>> {
+	let submission = conn
+		.query_row(
+			"SELECT signature, submitted_at, status, slot, error FROM submissions WHERE signature = ?1",
+			params![signature],
+			|row| {
+				Ok(SubmissionRecord {
+					signature: row.get(0)?,
+					submitted_at: row.get(1)?,
+					status: row.get(2)?,
+					slot: row.get(3)?,
+					error: row.get(4)?,
+				})
+			},
+		)
+		.optional()?;
+	Ok(submission)
+}
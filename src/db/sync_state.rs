@@ -0,0 +1,80 @@
+//! Progress of the block ingestion, persisted so a restart resumes where the previous run stopped.
+use rusqlite::{params, Connection};
+use std::error::Error;
+
+/// Initializes the `sync_state` table.
+///
+/// When the table is created on an existing database, the progress is seeded from the last stored
+/// block, so this must run once the `blocks` table is initialized.
+pub fn initialize_sync_state(conn: &Connection) -> rusqlite::Result<()> {
+	let exists: bool = conn.query_row(
+		"SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'sync_state')",
+		[],
+		|row| row.get(0),
+	)?;
+	if exists {
+		return Ok(())
+	}
+
+	conn.execute(
+		"CREATE TABLE sync_state (
+            id INTEGER PRIMARY KEY CHECK (id = 0),
+            last_processed_slot INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+		[],
+	)?;
+	conn.execute(
+		"INSERT INTO sync_state (id, last_processed_slot, updated_at)
+         SELECT 0, MAX(slot), strftime('%s', 'now') FROM blocks HAVING MAX(slot) IS NOT NULL",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Retrieves the last slot processed by the ingestion.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+///
+/// # Returns
+///
+/// This function returns `None` if no slot was processed yet.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_last_processed_slot(
+	conn: &Connection,
+) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+	Ok(conn.query_row("SELECT MAX(last_processed_slot) FROM sync_state", [], |row| row.get(0))?)
+}
+
+/// Records that the ingestion processed every slot up to a given one.
+///
+/// The recorded slot never moves backwards.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `slot` - The last processed slot.
+/// * `updated_at` - The current unix timestamp.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn set_last_processed_slot(
+	conn: &Connection,
+	slot: u64,
+	updated_at: i64,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	conn.execute(
+		"INSERT INTO sync_state (id, last_processed_slot, updated_at) VALUES (0, ?1, ?2)
+         ON CONFLICT (id) DO UPDATE SET
+            last_processed_slot = MAX(last_processed_slot, excluded.last_processed_slot),
+            updated_at = excluded.updated_at",
+		params![slot, updated_at],
+	)?;
+	Ok(())
+}
@@ -0,0 +1,124 @@
+//! Tiering of old transactions into a separate "cold" SQLite file.
+//!
+//! The cold file is attached to every connection under the `cold` schema, and its tables are
+//! created once at startup, alongside the migrations of the main database. Transactions older than
+//! the configured age are moved there periodically, keeping the hot database small, while lookups
+//! by signature and the account histories transparently fall back to the cold copy. The links
+//! between accounts and transactions stay in the hot database.
+//!
+//! The other readers of the `transactions` table only cover the hot database: the statistics and
+//! priority fee windows, the group statistics, the block summaries, the transactions listing, the
+//! table exports and snapshots, the repair jobs and the storage pruning.
+use rusqlite::{params, Connection, Result};
+use std::time::Duration;
+use tracing::{error, info};
+
+use super::pool::{with_connection, DbPool};
+
+/// The schema name the cold database is attached under.
+pub const COLD_SCHEMA: &str = "cold";
+
+/// Attaches the cold database file under the `cold` schema.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the main `Connection`.
+/// * `path` - The path of the cold SQLite file.
+///
+/// # Errors
+///
+/// This function returns an error if the file cannot be attached.
+pub fn attach_cold_storage(conn: &Connection, path: &str) -> Result<()> {
+	conn.execute("ATTACH DATABASE ?1 AS cold", params![path])?;
+	Ok(())
+}
+
+/// Creates the tables of the attached cold database, if they do not exist.
+///
+/// # Errors
+///
+/// This function returns an error if the cold database is not attached or a table cannot be
+/// created.
+pub fn initialize_cold_storage(conn: &Connection) -> Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS cold.transactions (
+            transaction_id TEXT PRIMARY KEY,
+            timestamp INTEGER,
+            block_height INTEGER,
+            raw_transaction TEXT,
+            fee INTEGER,
+            fee_payer TEXT,
+            compute_unit_limit INTEGER,
+            compute_unit_price INTEGER
+        )",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Returns whether a cold database is attached to the connection.
+pub fn is_cold_attached(conn: &Connection) -> Result<bool> {
+	let mut stmt = conn.prepare("PRAGMA database_list")?;
+	let mut rows = stmt.query([])?;
+	while let Some(row) = rows.next()? {
+		let name: String = row.get(1)?;
+		if name == COLD_SCHEMA {
+			return Ok(true)
+		}
+	}
+	Ok(false)
+}
+
+/// Moves the transactions older than a timestamp from the hot to the cold database.
+///
+/// Both the copy and the deletion run in a single database transaction, so a transaction is never
+/// missing from both databases.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the main `Connection`, with the cold database attached.
+/// * `before` - The unix timestamp before which transactions are moved.
+///
+/// # Returns
+///
+/// This function returns the number of moved transactions.
+///
+/// # Errors
+///
+/// This function returns an error if the cold database is not attached or a statement fails.
+pub fn move_to_cold(conn: &Connection, before: i64) -> Result<usize> {
+	let tx = conn.unchecked_transaction()?;
+	tx.execute(
+		"INSERT OR REPLACE INTO cold.transactions
+         SELECT transaction_id, timestamp, block_height, raw_transaction, fee, fee_payer,
+                compute_unit_limit, compute_unit_price
+         FROM main.transactions WHERE timestamp < ?1",
+		params![before],
+	)?;
+	let moved =
+		tx.execute("DELETE FROM main.transactions WHERE timestamp < ?1", params![before])?;
+	tx.commit()?;
+	Ok(moved)
+}
+
+/// Periodically moves the transactions older than `max_age` seconds to the cold database.
+///
+/// # Arguments
+///
+/// * `pool` - The `DbPool`, whose connections have the cold database attached.
+/// * `max_age` - The age in seconds after which transactions are moved.
+/// * `interval` - The time between two tiering runs.
+pub async fn run_tiering(pool: DbPool, max_age: i64, interval: Duration) {
+	let mut ticker = tokio::time::interval(interval);
+	loop {
+		ticker.tick().await;
+		let before = chrono::Utc::now().timestamp() - max_age;
+		match with_connection(&pool, move |conn| move_to_cold(conn, before)).await {
+			Ok(Ok(0)) => {},
+			Ok(Ok(moved)) =>
+				info!("Moved {} transactions older than {} to cold storage", moved, before),
+			Ok(Err(err)) => error!("Failed to move transactions to cold storage: {:?}", err),
+			Err(err) => error!("Failed to check out a database connection: {:?}", err),
+		}
+	}
+}
@@ -0,0 +1,517 @@
+//! Storage of the registered webhooks and of the deliveries of their notifications.
+//!
+//! A webhook is a URL notified of the transfers touching one of its addresses. Each notification is
+//! recorded as a delivery before it is sent, keyed by the webhook and the event it notifies, so an
+//! event replayed by the ingestion is not delivered twice and a delivery the endpoint did not
+//! acknowledge is attempted again with an exponential backoff starting at `MIN_RETRY_DELAY_SECS`
+//! and capped at `MAX_RETRY_DELAY_SECS`. After `MAX_ATTEMPTS` failures a delivery is marked
+//! `failed`, and is only attempted again once redelivered. Deliveries are kept once delivered, so a
+//! consumer can audit them and request the ones it missed again.
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::contention;
+use std::{collections::HashMap, error::Error};
+
+/// Delay before the first re-attempt of a delivery, in seconds.
+pub const MIN_RETRY_DELAY_SECS: i64 = 10;
+
+/// Maximum delay between two re-attempts of a delivery, in seconds.
+pub const MAX_RETRY_DELAY_SECS: i64 = 60 * 60;
+
+/// Number of failed attempts after which a delivery is marked `failed`.
+pub const MAX_ATTEMPTS: u32 = 8;
+
+/// The watched addresses, each with the id and URL of the webhooks watching it.
+pub type WatchedAddresses = HashMap<String, Vec<(i64, String)>>;
+
+/// A record representing a registered webhook.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookRecord {
+	pub id: i64,
+	/// The URL the notifications are posted to.
+	pub url: String,
+	/// The watched addresses, in ascending order.
+	pub addresses: Vec<String>,
+	/// The unix timestamp of the registration.
+	pub created_at: i64,
+}
+
+/// Where a delivery stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+	/// Waiting for its next attempt.
+	Pending,
+	/// Acknowledged by the endpoint with a 2xx status.
+	Delivered,
+	/// Given up on after `MAX_ATTEMPTS` attempts.
+	Failed,
+}
+
+impl DeliveryStatus {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			DeliveryStatus::Pending => "pending",
+			DeliveryStatus::Delivered => "delivered",
+			DeliveryStatus::Failed => "failed",
+		}
+	}
+}
+
+impl std::str::FromStr for DeliveryStatus {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"pending" => Ok(DeliveryStatus::Pending),
+			"delivered" => Ok(DeliveryStatus::Delivered),
+			"failed" => Ok(DeliveryStatus::Failed),
+			_ => Err(format!("Unknown delivery status: {}", s)),
+		}
+	}
+}
+
+/// A record representing the delivery of a notification to a webhook.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeliveryRecord {
+	pub id: i64,
+	pub webhook_id: i64,
+	/// The event notified, `<signature>:<transfer index>` for a transfer.
+	pub event_id: String,
+	/// The URL the notification is posted to.
+	pub target: String,
+	/// The JSON body posted.
+	pub payload: Value,
+	pub status: DeliveryStatus,
+	/// The number of attempts made so far.
+	pub attempts: u32,
+	/// The error of the last failed attempt.
+	pub last_error: Option<String>,
+	/// The unix timestamp the delivery was recorded at.
+	pub created_at: i64,
+	/// The unix timestamp of the last attempt.
+	pub last_attempt_at: Option<i64>,
+	/// The unix timestamp of the next attempt, `None` unless the delivery is pending.
+	pub next_attempt_at: Option<i64>,
+}
+
+/// A notification to record for a webhook.
+#[derive(Debug, Clone)]
+pub struct NewDelivery {
+	pub webhook_id: i64,
+	pub event_id: String,
+	pub target: String,
+	pub payload: Value,
+}
+
+/// Initializes the `webhooks`, `webhook_addresses` and `deliveries` tables.
+pub fn initialize_webhooks(conn: &Connection) -> rusqlite::Result<()> {
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS webhooks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            url TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+		[],
+	)?;
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS webhook_addresses (
+            webhook_id INTEGER NOT NULL,
+            address TEXT NOT NULL,
+            PRIMARY KEY (webhook_id, address)
+        )",
+		[],
+	)?;
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS deliveries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            webhook_id INTEGER NOT NULL,
+            event_id TEXT NOT NULL,
+            target TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL,
+            attempts INTEGER NOT NULL,
+            last_error TEXT,
+            created_at INTEGER NOT NULL,
+            last_attempt_at INTEGER,
+            next_attempt_at INTEGER,
+            UNIQUE (webhook_id, event_id)
+        )",
+		[],
+	)?;
+	conn.execute(
+		"CREATE INDEX IF NOT EXISTS idx_deliveries_next_attempt_at ON deliveries (next_attempt_at)",
+		[],
+	)?;
+	Ok(())
+}
+
+/// Returns the delay before the next attempt of a delivery that failed `attempts` times, `None`
+/// once the delivery is to be marked `failed`.
+pub fn retry_delay(attempts: u32) -> Option<i64> {
+	if attempts >= MAX_ATTEMPTS {
+		return None
+	}
+	let exponent = attempts.saturating_sub(1).min(16);
+	Some((MIN_RETRY_DELAY_SECS << exponent).min(MAX_RETRY_DELAY_SECS))
+}
+
+/// Registers a webhook.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `url` - The URL the notifications are posted to.
+/// * `addresses` - The watched addresses.
+/// * `created_at` - The unix timestamp of the registration.
+///
+/// # Returns
+///
+/// This function returns the record of the new webhook.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn create_webhook(
+	conn: &Connection,
+	url: &str,
+	addresses: &[String],
+	created_at: i64,
+) -> Result<WebhookRecord, Box<dyn Error + Send + Sync>> {
+	contention::with_retry(|| {
+		let tx = conn.unchecked_transaction()?;
+		tx.execute(
+			"INSERT INTO webhooks (url, created_at) VALUES (?1, ?2)",
+			params![url, created_at],
+		)?;
+		let id = tx.last_insert_rowid();
+		for address in addresses {
+			tx.execute(
+				"INSERT OR IGNORE INTO webhook_addresses (webhook_id, address) VALUES (?1, ?2)",
+				params![id, address],
+			)?;
+		}
+		let webhook = get_webhook(&tx, id)?.ok_or("Registered webhook missing")?;
+		tx.commit()?;
+		Ok(webhook)
+	})
+}
+
+/// Retrieves a webhook along with its addresses.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn get_webhook(
+	conn: &Connection,
+	id: i64,
+) -> Result<Option<WebhookRecord>, Box<dyn Error + Send + Sync>> {
+	let webhook = conn
+		.query_row("SELECT url, created_at FROM webhooks WHERE id = ?1", params![id], |row| {
+			Ok((row.get(0)?, row.get(1)?))
+		})
+		.optional()?;
+	let Some((url, created_at)) = webhook else { return Ok(None) };
+	let mut stmt = conn
+		.prepare("SELECT address FROM webhook_addresses WHERE webhook_id = ?1 ORDER BY address")?;
+	let addresses = stmt
+		.query_map(params![id], |row| row.get(0))?
+		.collect::<rusqlite::Result<_>>()?;
+	Ok(Some(WebhookRecord { id, url, addresses, created_at }))
+}
+
+/// Retrieves the webhooks along with their addresses, ordered by id.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn list_webhooks(
+	conn: &Connection,
+) -> Result<Vec<WebhookRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(
+		"SELECT w.id, w.url, w.created_at, a.address
+         FROM webhooks w LEFT JOIN webhook_addresses a ON a.webhook_id = w.id
+         ORDER BY w.id, a.address",
+	)?;
+	let mut rows = stmt.query([])?;
+	let mut webhooks: Vec<WebhookRecord> = Vec::new();
+	while let Some(row) = rows.next()? {
+		let id: i64 = row.get(0)?;
+		if webhooks.last().is_none_or(|webhook| webhook.id != id) {
+			webhooks.push(WebhookRecord {
+				id,
+				url: row.get(1)?,
+				addresses: Vec::new(),
+				created_at: row.get(2)?,
+			});
+		}
+		if let (Some(webhook), Some(address)) = (webhooks.last_mut(), row.get(3)?) {
+			webhook.addresses.push(address);
+		}
+	}
+	Ok(webhooks)
+}
+
+/// Deletes a webhook, along with its addresses and deliveries.
+///
+/// # Returns
+///
+/// This function returns `true` if the webhook was deleted, `false` if it did not exist.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn delete_webhook(conn: &Connection, id: i64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+	contention::with_retry(|| {
+		let tx = conn.unchecked_transaction()?;
+		tx.execute("DELETE FROM webhook_addresses WHERE webhook_id = ?1", params![id])?;
+		tx.execute("DELETE FROM deliveries WHERE webhook_id = ?1", params![id])?;
+		let deleted = tx.execute("DELETE FROM webhooks WHERE id = ?1", params![id])?;
+		tx.commit()?;
+		Ok(deleted > 0)
+	})
+}
+
+/// Retrieves the watched addresses, each with the webhooks watching it.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn load_watched_addresses(
+	conn: &Connection,
+) -> Result<WatchedAddresses, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare_cached(
+		"SELECT a.address, w.id, w.url
+         FROM webhook_addresses a JOIN webhooks w ON w.id = a.webhook_id
+         ORDER BY w.id",
+	)?;
+	let mut rows = stmt.query([])?;
+	let mut watched = WatchedAddresses::new();
+	while let Some(row) = rows.next()? {
+		watched.entry(row.get(0)?).or_default().push((row.get(1)?, row.get(2)?));
+	}
+	Ok(watched)
+}
+
+/// Records deliveries to attempt right away, skipping the events already recorded for their
+/// webhook.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `deliveries` - The notifications to record.
+/// * `now` - The current unix timestamp.
+///
+/// # Returns
+///
+/// This function returns the number of deliveries recorded.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn enqueue_deliveries(
+	conn: &Connection,
+	deliveries: &[NewDelivery],
+	now: i64,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+	contention::with_retry(|| {
+		let tx = conn.unchecked_transaction()?;
+		let mut recorded = 0;
+		{
+			let mut stmt = tx.prepare_cached(
+				"INSERT OR IGNORE INTO deliveries
+                    (webhook_id, event_id, target, payload, status, attempts, created_at,
+                     next_attempt_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, 0, ?6, ?6)",
+			)?;
+			for delivery in deliveries {
+				recorded += stmt.execute(params![
+					delivery.webhook_id,
+					delivery.event_id,
+					delivery.target,
+					delivery.payload.to_string(),
+					DeliveryStatus::Pending.as_str(),
+					now
+				])?;
+			}
+		}
+		tx.commit()?;
+		Ok(recorded)
+	})
+}
+
+/// Retrieves the pending deliveries whose next attempt is due, oldest schedule first.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `now` - The current unix timestamp.
+/// * `limit` - The maximum number of deliveries to return.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if a stored delivery is
+/// invalid.
+pub fn get_due_deliveries(
+	conn: &Connection,
+	now: i64,
+	limit: usize,
+) -> Result<Vec<DeliveryRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {} FROM deliveries WHERE status = 'pending' AND next_attempt_at <= ?1
+         ORDER BY next_attempt_at, id LIMIT ?2",
+		DELIVERY_COLUMNS
+	))?;
+	let mut rows = stmt.query(params![now, limit])?;
+	let mut deliveries = Vec::new();
+	while let Some(row) = rows.next()? {
+		deliveries.push(read_delivery(row)?);
+	}
+	Ok(deliveries)
+}
+
+/// Records the outcome of an attempt at a delivery, and schedules the next one if it failed.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `id` - The delivery attempted.
+/// * `error` - The error of the attempt, `None` if the endpoint acknowledged it.
+/// * `attempted_at` - The unix timestamp of the attempt.
+///
+/// # Returns
+///
+/// This function returns the updated record, `None` if the delivery no longer exists.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn record_attempt(
+	conn: &Connection,
+	id: i64,
+	error: Option<&str>,
+	attempted_at: i64,
+) -> Result<Option<DeliveryRecord>, Box<dyn Error + Send + Sync>> {
+	let Some(delivery) = get_delivery(conn, id)? else { return Ok(None) };
+	let attempts = delivery.attempts + 1;
+	let (status, next_attempt_at) = match error {
+		None => (DeliveryStatus::Delivered, None),
+		Some(_) => match retry_delay(attempts) {
+			Some(delay) => (DeliveryStatus::Pending, Some(attempted_at + delay)),
+			None => (DeliveryStatus::Failed, None),
+		},
+	};
+	conn.execute(
+		"UPDATE deliveries
+         SET status = ?2, attempts = ?3, last_error = COALESCE(?4, last_error),
+             last_attempt_at = ?5, next_attempt_at = ?6
+         WHERE id = ?1",
+		params![id, status.as_str(), attempts, error, attempted_at, next_attempt_at],
+	)?;
+	get_delivery(conn, id)
+}
+
+/// Schedules a delivery of a webhook again right away, whatever its status.
+///
+/// The attempts made so far are kept, so a failed delivery is marked `failed` again if the attempt
+/// fails.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `webhook_id` - The webhook of the delivery.
+/// * `id` - The delivery to send again.
+/// * `now` - The current unix timestamp.
+///
+/// # Returns
+///
+/// This function returns the updated record, `None` if the webhook has no such delivery.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails.
+pub fn redeliver(
+	conn: &Connection,
+	webhook_id: i64,
+	id: i64,
+	now: i64,
+) -> Result<Option<DeliveryRecord>, Box<dyn Error + Send + Sync>> {
+	let updated = conn.execute(
+		"UPDATE deliveries SET status = ?3, next_attempt_at = ?4 WHERE id = ?1 AND webhook_id = ?2",
+		params![id, webhook_id, DeliveryStatus::Pending.as_str(), now],
+	)?;
+	if updated == 0 {
+		return Ok(None)
+	}
+	get_delivery(conn, id)
+}
+
+/// Retrieves a delivery.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if the stored delivery is
+/// invalid.
+pub fn get_delivery(
+	conn: &Connection,
+	id: i64,
+) -> Result<Option<DeliveryRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt =
+		conn.prepare(&format!("SELECT {} FROM deliveries WHERE id = ?1", DELIVERY_COLUMNS))?;
+	let mut rows = stmt.query(params![id])?;
+	rows.next()?.map(read_delivery).transpose()
+}
+
+/// Retrieves the deliveries of a webhook, newest first.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to a `Connection` object representing the database connection.
+/// * `webhook_id` - The webhook.
+/// * `status` - Restricts the deliveries to those with a status.
+/// * `limit` - The maximum number of deliveries to return.
+///
+/// # Errors
+///
+/// This function returns an error if the database operation fails or if a stored delivery is
+/// invalid.
+pub fn get_deliveries(
+	conn: &Connection,
+	webhook_id: i64,
+	status: Option<DeliveryStatus>,
+	limit: usize,
+) -> Result<Vec<DeliveryRecord>, Box<dyn Error + Send + Sync>> {
+	let mut stmt = conn.prepare(&format!(
+		"SELECT {} FROM deliveries WHERE webhook_id = ?1 AND (?2 IS NULL OR status = ?2)
+         ORDER BY id DESC LIMIT ?3",
+		DELIVERY_COLUMNS
+	))?;
+	let mut rows = stmt.query(params![webhook_id, status.map(|status| status.as_str()), limit])?;
+	let mut deliveries = Vec::new();
+	while let Some(row) = rows.next()? {
+		deliveries.push(read_delivery(row)?);
+	}
+	Ok(deliveries)
+}
+
+const DELIVERY_COLUMNS: &str = "id, webhook_id, event_id, target, payload, status, attempts, last_error, created_at, last_attempt_at, next_attempt_at";
+
+fn read_delivery(row: &Row) -> Result<DeliveryRecord, Box<dyn Error + Send + Sync>> {
+	let payload: String = row.get(4)?;
+	let status: String = row.get(5)?;
+	Ok(DeliveryRecord {
+		id: row.get(0)?,
+		webhook_id: row.get(1)?,
+		event_id: row.get(2)?,
+		target: row.get(3)?,
+		payload: serde_json::from_str(&payload)?,
+		status: status.parse()?,
+		attempts: row.get(6)?,
+		last_error: row.get(7)?,
+		created_at: row.get(8)?,
+		last_attempt_at: row.get(9)?,
+		next_attempt_at: row.get(10)?,
+	})
+}
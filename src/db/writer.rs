@@ -0,0 +1,669 @@
+//! Single-writer actor owning the ingestion connection.
+//!
+//! The aggregator sends write commands over a bounded channel instead of locking a shared
+//! connection. The actor runs on a dedicated thread, drains the queued commands and commits them in
+//! one SQLite transaction, so bursts of blocks are written in batches and the async runtime never
+//! blocks on SQLite. The per-row statements of the ingestion are prepared once and reused from the
+//! statement cache of the connection for every transaction of every block. The writer remembers
+//! the states it recently wrote for the accounts, so blocks leaving a hot account unchanged do not
+//! upsert it again. Blocks that fail to be stored join the failed slots queue, which a block
+//! leaves once stored. A batch that cannot be committed is rolled back whole: its slots are queued
+//! as failed slots in a transaction of their own, along with its progress, and the checkpoints
+//! waiting for it get its error. The blocks and transfers stored by a batch are published on the
+//! event bus once the batch is committed. A whole block stored at a slot already holding other
+//! transactions is reported as a reorg, and those transactions are rolled back, as are the blocks
+//! the ingestion finds on an abandoned fork. The ingestion reads the stored chain through the
+//! writer, so it sees the blocks still queued. While the database exceeds its storage quota, the
+//! handles wait before queueing blocks and transactions. Blocks and transactions are written within
+//! the span they were queued from, so the logs of their writes are tied to the slot they were
+//! fetched for.
+use rusqlite::{Connection, TransactionBehavior};
+use std::{
+	collections::{HashSet, VecDeque},
+	error::Error,
+	ops::RangeInclusive,
+	thread,
+};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, warn, Span};
+
+use super::{
+	blocks::{self, BlockRecord, UnavailableSlotsRecord},
+	failed_slots,
+	filters::{self, ProgramFilters},
+	get_block_transaction_ids,
+	recent_writes::RecentWrites,
+	reorgs,
+	storage::StorageMonitor,
+	sync_state,
+};
+use crate::{
+	aggregator::{
+		passes_filters, processor::ParsedTransaction, replace_transaction, store_parsed_block,
+		tagging::TransferTagger, transfer_records,
+	},
+	events::{Event, EventBus},
+	types::StoragePolicy,
+};
+
+/// Capacity of the command channel; the aggregator waits when the writer falls this far behind.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Maximum number of commands committed in a single database transaction.
+const MAX_BATCH_SIZE: usize = 32;
+
+/// Capacity of the prepared statement cache of the writer connection, above the number of
+/// distinct statements issued while storing a block.
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// Number of accounts whose last written state is remembered to skip identical upserts.
+const RECENT_WRITES_CAPACITY: usize = 4096;
+
+/// A write request handled by the writer actor.
+pub enum WriteCommand {
+	/// Stores the transactions parsed from a block, along with the block itself when it was
+	/// fetched whole.
+	WriteBlock {
+		slot: u64,
+		block_time: Option<i64>,
+		block: Option<BlockRecord>,
+		transactions: Vec<ParsedTransaction>,
+		span: Span,
+	},
+	/// Replaces a stored transaction with a freshly parsed copy.
+	ReplaceTransaction {
+		slot: u64,
+		block_time: Option<i64>,
+		transaction: Box<ParsedTransaction>,
+		span: Span,
+	},
+	/// Rolls back the blocks stored at some slots, which the chain no longer holds.
+	RollBack { slots: Vec<u64>, span: Span },
+	/// Reads the blockhashes of the blocks stored within a range of slots, once the previously
+	/// sent commands are applied.
+	ReadBlockhashes {
+		slots: RangeInclusive<u64>,
+		reply: oneshot::Sender<Result<Vec<(u64, String)>, String>>,
+	},
+	/// Records a range of slots that could not be ingested.
+	MarkUnavailable(UnavailableSlotsRecord),
+	/// Queues a slot whose block could not be fetched or parsed for a later attempt.
+	MarkFailed { slot: u64, error: String },
+	/// Records that the ingestion processed every slot up to `slot`.
+	AdvanceSyncState { slot: u64 },
+	/// Acknowledges once every previously sent command has been committed, or replies with the
+	/// error of the batch that could not be.
+	Checkpoint { reply: oneshot::Sender<Result<(), String>> },
+}
+
+/// A cloneable handle sending commands to the writer actor.
+#[derive(Clone)]
+pub struct DbWriter {
+	sender: mpsc::Sender<WriteCommand>,
+	/// The storage quota the blocks and transactions wait for space under, if any.
+	storage: Option<StorageMonitor>,
+}
+
+impl DbWriter {
+	/// Spawns the writer actor on a dedicated thread, taking ownership of the connection.
+	///
+	/// The storage `policy` selects which of the accounts touched by the written transactions get
+	/// an account row and how much of the raw transactions is stored. The committed writes are
+	/// published on `events`. The thread exits once every handle has been dropped and the queued
+	/// commands are written.
+	pub fn spawn(
+		conn: Connection,
+		policy: StoragePolicy,
+		events: EventBus,
+	) -> (Self, thread::JoinHandle<()>) {
+		let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+		conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+		let handle = thread::spawn(move || run(conn, receiver, policy, events));
+		(Self { sender, storage: None }, handle)
+	}
+
+	/// Makes the blocks and transactions written through the handle wait while the storage monitor
+	/// pauses the ingestion.
+	pub fn with_storage_monitor(self, storage: StorageMonitor) -> Self {
+		Self { storage: Some(storage), ..self }
+	}
+
+	/// Returns the number of commands waiting for the writer actor.
+	pub fn queued_commands(&self) -> usize {
+		self.sender.max_capacity() - self.sender.capacity()
+	}
+
+	/// Queues a block fetched whole and the transactions parsed from it for writing.
+	///
+	/// # Arguments
+	///
+	/// * `block` - The header of the block.
+	/// * `transactions` - The transactions parsed from the block, in block order.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the writer actor has stopped.
+	pub async fn write_block(
+		&self,
+		block: BlockRecord,
+		transactions: Vec<ParsedTransaction>,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.wait_for_space().await;
+		self.send(WriteCommand::WriteBlock {
+			slot: block.slot,
+			block_time: block.block_time,
+			block: Some(block),
+			transactions,
+			span: Span::current(),
+		})
+		.await
+	}
+
+	/// Queues some of the transactions of a block for writing, without the block itself.
+	///
+	/// # Arguments
+	///
+	/// * `slot` - The slot of the block.
+	/// * `block_time` - The optional timestamp of the block.
+	/// * `transactions` - The transactions parsed from the block, in block order.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the writer actor has stopped.
+	pub async fn write_transactions(
+		&self,
+		slot: u64,
+		block_time: Option<i64>,
+		transactions: Vec<ParsedTransaction>,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.wait_for_space().await;
+		self.send(WriteCommand::WriteBlock {
+			slot,
+			block_time,
+			block: None,
+			transactions,
+			span: Span::current(),
+		})
+		.await
+	}
+
+	/// Queues the replacement of a stored transaction with a freshly parsed copy.
+	///
+	/// # Arguments
+	///
+	/// * `slot` - The slot of the transaction.
+	/// * `block_time` - The optional timestamp of the block.
+	/// * `transaction` - The parsed transaction.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the writer actor has stopped.
+	pub async fn replace_transaction(
+		&self,
+		slot: u64,
+		block_time: Option<i64>,
+		transaction: ParsedTransaction,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.wait_for_space().await;
+		self.send(WriteCommand::ReplaceTransaction {
+			slot,
+			block_time,
+			transaction: Box::new(transaction),
+			span: Span::current(),
+		})
+		.await
+	}
+
+	/// Queues the rollback of the blocks stored at some slots, along with their transactions.
+	///
+	/// # Arguments
+	///
+	/// * `slots` - The slots of the blocks the chain no longer holds.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the writer actor has stopped.
+	pub async fn roll_back(&self, slots: Vec<u64>) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.send(WriteCommand::RollBack { slots, span: Span::current() }).await
+	}
+
+	/// Returns the blockhashes of the blocks stored within a range of slots, in slot order, the
+	/// blocks queued before included.
+	///
+	/// # Arguments
+	///
+	/// * `slots` - The range of slots, inclusive.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the writer actor has stopped or the blocks cannot be read.
+	pub async fn stored_blockhashes(
+		&self,
+		slots: RangeInclusive<u64>,
+	) -> Result<Vec<(u64, String)>, Box<dyn Error + Send + Sync>> {
+		let (reply, blockhashes) = oneshot::channel();
+		self.send(WriteCommand::ReadBlockhashes { slots, reply }).await?;
+		Ok(blockhashes.await.map_err(|_| "Database writer stopped")??)
+	}
+
+	/// Queues the record of a range of slots that could not be ingested.
+	///
+	/// # Arguments
+	///
+	/// * `start_slot` - The first unavailable slot.
+	/// * `end_slot` - The last unavailable slot, inclusive.
+	/// * `reason` - Why the slots are unavailable.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the writer actor has stopped.
+	pub async fn mark_unavailable(
+		&self,
+		start_slot: u64,
+		end_slot: u64,
+		reason: &str,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.send(WriteCommand::MarkUnavailable(UnavailableSlotsRecord {
+			start_slot,
+			end_slot,
+			reason: reason.to_string(),
+			recorded_at: chrono::Utc::now().timestamp(),
+		}))
+		.await
+	}
+
+	/// Queues the record of a slot whose block could not be fetched or parsed, scheduling another
+	/// attempt.
+	///
+	/// # Arguments
+	///
+	/// * `slot` - The slot that failed.
+	/// * `error` - The error of the attempt.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the writer actor has stopped.
+	pub async fn mark_failed(
+		&self,
+		slot: u64,
+		error: &str,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.send(WriteCommand::MarkFailed { slot, error: error.to_string() }).await
+	}
+
+	/// Queues the record of the ingestion progress, committed along with the blocks queued before.
+	///
+	/// # Arguments
+	///
+	/// * `slot` - The last slot processed by the ingestion.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the writer actor has stopped.
+	pub async fn advance_sync_state(&self, slot: u64) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.send(WriteCommand::AdvanceSyncState { slot }).await
+	}
+
+	/// Waits until every previously queued command has been committed.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the writer actor has stopped or the batch of the
+	/// checkpoint could not be committed, its slots being queued for another attempt.
+	pub async fn checkpoint(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+		let (reply, done) = oneshot::channel();
+		self.send(WriteCommand::Checkpoint { reply }).await?;
+		done.await.map_err(|_| "Database writer stopped")??;
+		Ok(())
+	}
+
+	async fn wait_for_space(&self) {
+		if let Some(storage) = &self.storage {
+			storage.wait_for_space().await;
+		}
+	}
+
+	async fn send(&self, command: WriteCommand) -> Result<(), Box<dyn Error + Send + Sync>> {
+		self.sender.send(command).await.map_err(|_| "Database writer stopped")?;
+		Ok(())
+	}
+}
+
+/// Consumes the commands until every sender is dropped, committing them in batches.
+fn run(
+	mut conn: Connection,
+	mut receiver: mpsc::Receiver<WriteCommand>,
+	policy: StoragePolicy,
+	events: EventBus,
+) {
+	let mut recent = RecentWrites::new(RECENT_WRITES_CAPACITY);
+	while let Some(command) = receiver.blocking_recv() {
+		let mut batch = VecDeque::from([command]);
+		while batch.len() < MAX_BATCH_SIZE {
+			match receiver.try_recv() {
+				Ok(command) => batch.push_back(command),
+				Err(_) => break,
+			}
+		}
+		let outline = BatchOutline::of(&batch);
+		let mut replies = Vec::new();
+		let outcome =
+			match write_batch(&mut conn, &mut batch, &mut replies, &policy, &mut recent, &events) {
+				Ok(()) => Ok(()),
+				Err(err) => {
+					// The states written by the batch were rolled back
+					recent.clear();
+					error!(
+						error_kind = "write",
+						error = %err,
+						slots = ?outline.slots,
+						"Failed to commit write batch, queueing its slots for another attempt"
+					);
+					if let Err(err) = outline.record(&mut conn, &err.to_string()) {
+						error!(
+							error_kind = "write",
+							error = %err,
+							"Failed to queue the slots of a failed write batch"
+						);
+					}
+					Err(err.to_string())
+				},
+			};
+		// The commands a failed batch did not reach are answered with its error
+		if let Err(error) = &outcome {
+			for command in batch {
+				match command {
+					WriteCommand::Checkpoint { reply } => replies.push(reply),
+					WriteCommand::ReadBlockhashes { reply, .. } => {
+						let answered = reply.send(Err(error.clone())).is_ok();
+						if !answered {
+							warn!("Blockhash requester dropped before the reply");
+						}
+					},
+					_ => {},
+				}
+			}
+		}
+		for reply in replies {
+			if reply.send(outcome.clone()).is_err() {
+				warn!("Checkpoint requester dropped before the acknowledgement");
+			}
+		}
+	}
+}
+
+/// What a batch records besides the writes of its blocks, kept to be recorded on its own if the
+/// batch cannot be committed.
+#[derive(Default)]
+struct BatchOutline {
+	/// The slots whose blocks the batch writes or rolls back.
+	slots: Vec<u64>,
+	/// The slots the batch queues as failed slots, with their errors.
+	failed: Vec<(u64, String)>,
+	unavailable: Vec<UnavailableSlotsRecord>,
+	/// The last processed slot the batch records.
+	synced: Option<u64>,
+}
+
+impl BatchOutline {
+	fn of(batch: &VecDeque<WriteCommand>) -> Self {
+		let mut outline = Self::default();
+		for command in batch {
+			match command {
+				WriteCommand::WriteBlock { slot, .. } => outline.slots.push(*slot),
+				WriteCommand::RollBack { slots, .. } => outline.slots.extend(slots),
+				WriteCommand::MarkFailed { slot, error } =>
+					outline.failed.push((*slot, error.clone())),
+				WriteCommand::MarkUnavailable(record) => outline.unavailable.push(record.clone()),
+				WriteCommand::AdvanceSyncState { slot } => outline.synced = Some(*slot),
+				_ => {},
+			}
+		}
+		outline
+	}
+
+	/// Queues the slots of a batch that could not be committed for another attempt, with `error`,
+	/// and records the unavailable slots and the progress of the batch, in a transaction of their
+	/// own.
+	fn record(
+		&self,
+		conn: &mut Connection,
+		error: &str,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+		let failed_at = chrono::Utc::now().timestamp();
+		let failed = self.slots.iter().map(|slot| (*slot, error));
+		for (slot, error) in
+			failed.chain(self.failed.iter().map(|(slot, error)| (*slot, error.as_str())))
+		{
+			failed_slots::record_failed_slot(&tx, slot, error, failed_at)?;
+		}
+		for record in &self.unavailable {
+			blocks::insert_or_update_unavailable_slots(&tx, record)?;
+		}
+		if let Some(slot) = self.synced {
+			sync_state::set_last_processed_slot(&tx, slot, failed_at)?;
+		}
+		tx.commit()?;
+		Ok(())
+	}
+}
+
+/// Applies a batch of commands in a single transaction.
+///
+/// Each block or replaced transaction is written under its own savepoint, so a failing one is
+/// rolled back and logged without discarding the rest of the batch. The commands are taken from
+/// `batch` as they are applied, and the checkpoints collected into `replies`, to be acknowledged
+/// by the caller. The events of the stored blocks are published after the commit.
+fn write_batch(
+	conn: &mut Connection,
+	batch: &mut VecDeque<WriteCommand>,
+	replies: &mut Vec<oneshot::Sender<Result<(), String>>>,
+	policy: &StoragePolicy,
+	recent: &mut RecentWrites,
+	events: &EventBus,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut stored = Vec::new();
+	// Taking the write lock up front, a batch waits for it in the busy handler rather than failing
+	// once it has read, as the batch cannot be replayed
+	let mut tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+	// The program filters are read once per batch, so rules changed through the API apply from
+	// the next batch on
+	let filters = filters::load_program_filters(&tx)?;
+	// Likewise, the labels the tagging rules look up are read once per batch
+	let mut tagger = TransferTagger::new(&policy.tagging_rules);
+	while let Some(command) = batch.pop_front() {
+		match command {
+			WriteCommand::WriteBlock { slot, block_time, block, mut transactions, span } => {
+				let _span = span.entered();
+				let savepoint = tx.savepoint()?;
+				// Only a whole block tells which stored transactions the slot no longer holds
+				let orphaned = match &block {
+					Some(block) => get_orphaned_transactions(&savepoint, slot, &transactions)
+						.and_then(|orphaned| {
+							reorgs::roll_back_transactions(&savepoint, &orphaned)?;
+							blocks::insert_or_update_block_record(&savepoint, block)?;
+							Ok(orphaned)
+						}),
+					None => Ok(Vec::new()),
+				};
+				match orphaned.and_then(|orphaned| {
+					for transaction in transactions
+						.iter_mut()
+						.filter(|transaction| passes_filters(policy, &filters, transaction))
+					{
+						tagger.tag(&savepoint, transaction)?;
+					}
+					store_parsed_block(
+						&savepoint,
+						slot,
+						block_time,
+						&transactions,
+						policy,
+						&filters,
+						recent,
+					)?;
+					failed_slots::remove_failed_slot(&savepoint, slot)?;
+					Ok(orphaned)
+				}) {
+					Ok(orphaned) => {
+						savepoint.commit()?;
+						if !orphaned.is_empty() {
+							// The accounts of the rolled back transactions were written over
+							recent.clear();
+							warn!(
+								slot,
+								orphaned_transactions = orphaned.len(),
+								"Block no longer holds some stored transactions, rolled them back"
+							);
+							stored.push(Event::ReorgDetected {
+								slot,
+								orphaned_transactions: orphaned,
+							});
+						}
+						stored.extend(block_events(
+							slot,
+							block_time,
+							&transactions,
+							policy,
+							&filters,
+						));
+					},
+					Err(err) => {
+						recent.clear();
+						error!(slot, error_kind = "write", error = %err, "Failed to store block");
+						drop(savepoint);
+						failed_slots::record_failed_slot(
+							&tx,
+							slot,
+							&err.to_string(),
+							chrono::Utc::now().timestamp(),
+						)?;
+					},
+				}
+			},
+			WriteCommand::ReplaceTransaction { slot, block_time, mut transaction, span } => {
+				let _span = span.entered();
+				let savepoint = tx.savepoint()?;
+				// The accounts of the transaction are written without the remembered states
+				recent.clear();
+				let replaced = tagger.tag(&savepoint, &mut transaction).and_then(|()| {
+					replace_transaction(&savepoint, slot, block_time, &transaction, policy)
+				});
+				match replaced {
+					Ok(()) => savepoint.commit()?,
+					Err(err) => error!(
+						slot,
+						signature = %transaction.signature,
+						error_kind = "write",
+						error = %err,
+						"Failed to replace transaction"
+					),
+				}
+			},
+			WriteCommand::RollBack { slots, span } => {
+				let _span = span.entered();
+				let savepoint = tx.savepoint()?;
+				recent.clear();
+				let rolled_back: Result<Vec<_>, Box<dyn Error + Send + Sync>> = slots
+					.iter()
+					.map(|slot| Ok((*slot, reorgs::roll_back_block(&savepoint, *slot)?)))
+					.collect();
+				match rolled_back {
+					Ok(rolled_back) => {
+						savepoint.commit()?;
+						for (slot, orphaned) in rolled_back {
+							warn!(
+								slot,
+								orphaned_transactions = orphaned.len(),
+								"Rolled back block of an abandoned fork"
+							);
+							stored.push(Event::ReorgDetected {
+								slot,
+								orphaned_transactions: orphaned,
+							});
+						}
+					},
+					Err(err) => error!(
+						error_kind = "write",
+						error = %err,
+						"Failed to roll back blocks of an abandoned fork"
+					),
+				}
+			},
+			WriteCommand::ReadBlockhashes { slots, reply } => {
+				let blockhashes =
+					blocks::get_blockhashes(&tx, slots).map_err(|err| err.to_string());
+				if reply.send(blockhashes).is_err() {
+					warn!("Blockhash requester dropped before the reply");
+				}
+			},
+			WriteCommand::MarkUnavailable(record) =>
+				blocks::insert_or_update_unavailable_slots(&tx, &record)?,
+			WriteCommand::MarkFailed { slot, error } => {
+				failed_slots::record_failed_slot(
+					&tx,
+					slot,
+					&error,
+					chrono::Utc::now().timestamp(),
+				)?;
+			},
+			WriteCommand::AdvanceSyncState { slot } =>
+				sync_state::set_last_processed_slot(&tx, slot, chrono::Utc::now().timestamp())?,
+			WriteCommand::Checkpoint { reply } => replies.push(reply),
+		}
+	}
+	tx.commit()?;
+
+	for event in stored {
+		events.publish(event);
+	}
+	Ok(())
+}
+
+/// Returns the transactions stored at a slot that are missing from the block about to be stored
+/// there, which the chain no longer holds.
+fn get_orphaned_transactions(
+	conn: &Connection,
+	slot: u64,
+	transactions: &[ParsedTransaction],
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+	let signatures: HashSet<&str> =
+		transactions.iter().map(|transaction| transaction.signature.as_str()).collect();
+	let mut orphaned = get_block_transaction_ids(conn, slot)?;
+	orphaned.retain(|transaction_id| !signatures.contains(transaction_id.as_str()));
+	Ok(orphaned)
+}
+
+/// Returns the events of a stored block: its transactions the storage policy and the program
+/// filters let through, each followed by its transfers, in block order, then the block itself.
+fn block_events<'a>(
+	slot: u64,
+	block_time: Option<i64>,
+	transactions: &'a [ParsedTransaction],
+	policy: &StoragePolicy,
+	filters: &ProgramFilters,
+) -> impl Iterator<Item = Event> + 'a {
+	let transactions: Vec<_> = transactions
+		.iter()
+		.filter(|transaction| passes_filters(policy, filters, transaction))
+		.collect();
+	let transaction_count = transactions.len();
+	transactions
+		.into_iter()
+		.flat_map(move |transaction| {
+			let stored = Event::TransactionStored {
+				signature: transaction.signature.clone(),
+				slot,
+				block_time,
+				fee: transaction.fee,
+				fee_payer: transaction.fee_payer.clone(),
+				error: transaction.error.clone(),
+			};
+			[stored].into_iter().chain(
+				transfer_records(slot, block_time.unwrap_or_default(), transaction)
+					.map(Event::TransferStored),
+			)
+		})
+		.chain([Event::BlockIngested { slot, block_time, transaction_count }])
+}
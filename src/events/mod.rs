@@ -0,0 +1,64 @@
+//! Internal event bus between the ingestion and the features reacting to it.
+//!
+//! The database writer publishes what it committed as typed events on a broadcast channel, and the
+//! consumers (metrics, streaming, notifications, ...) subscribe to it instead of being called from
+//! the ingestion path. Events are only published once the writes they describe are committed, so a
+//! subscriber reading the database on an event sees the new rows. Features deriving alerts from
+//! the ingestion, such as the anomaly detector, publish them on the same bus. A subscriber falling
+//! more than `EVENT_BUFFER` events behind skips the oldest ones.
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::{anomaly::Anomaly, db::TransferRecord, types::TransactionFailure};
+
+/// Number of events buffered for the slowest subscriber.
+const EVENT_BUFFER: usize = 1024;
+
+/// An event of the ingestion.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+	/// The transactions of a block were stored.
+	BlockIngested { slot: u64, block_time: Option<i64>, transaction_count: usize },
+	/// A transaction was stored, published before the events of its transfers.
+	TransactionStored {
+		signature: String,
+		slot: u64,
+		block_time: Option<i64>,
+		fee: u64,
+		fee_payer: String,
+		/// The error the transaction failed with, `None` if it succeeded.
+		error: Option<TransactionFailure>,
+	},
+	/// A transfer was stored, published before the `BlockIngested` event of its block.
+	TransferStored(TransferRecord),
+	/// A whole block was stored at a slot that already held other transactions, which are no
+	/// longer part of the block.
+	ReorgDetected { slot: u64, orphaned_transactions: Vec<String> },
+	/// The transfer volume of an account or the failure rate of a program spiked.
+	AnomalyDetected(Anomaly),
+}
+
+/// A cloneable handle publishing and subscribing to the events.
+#[derive(Clone)]
+pub struct EventBus {
+	sender: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+	fn default() -> Self {
+		Self { sender: broadcast::channel(EVENT_BUFFER).0 }
+	}
+}
+
+impl EventBus {
+	/// Publishes an event to the current subscribers; the event is dropped if there are none.
+	pub fn publish(&self, event: Event) {
+		let _ = self.sender.send(event);
+	}
+
+	/// Subscribes to the events published from now on.
+	pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+		self.sender.subscribe()
+	}
+}
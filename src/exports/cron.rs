@@ -0,0 +1,126 @@
+//! Cron expressions scheduling the exports.
+//!
+//! An expression has the five standard fields (minute, hour, day of month, month, day of week),
+//! each a `*`, a value, a range `a-b` or a comma-separated list of those, optionally stepped with
+//! `/n`, and is evaluated in UTC. As with cron, a day matches if either its day of month or its day
+//! of week does when both fields are restricted. The `@hourly`, `@daily`, `@weekly` and `@monthly`
+//! shorthands are accepted too.
+use chrono::{DateTime, Datelike, Duration, NaiveTime, TimeZone, Timelike, Utc};
+
+/// Number of years searched for the next occurrence of a schedule, which never runs past them.
+const MAX_SEARCH_YEARS: i32 = 5;
+
+/// A parsed cron expression, each field held as a bit set of the values it matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+	minutes: u64,
+	hours: u64,
+	days_of_month: u64,
+	months: u64,
+	/// Days of week from Sunday (0) to Saturday (6).
+	days_of_week: u64,
+	/// Whether both the day of month and the day of week fields are restricted.
+	either_day: bool,
+}
+
+impl Schedule {
+	/// Parses a cron expression.
+	///
+	/// # Errors
+	///
+	/// This function returns an error message if the expression does not have five valid fields.
+	pub fn parse(expression: &str) -> Result<Self, String> {
+		let invalid =
+			|reason: String| format!("Invalid cron expression {}: {}", expression, reason);
+		let fields: Vec<&str> = match expression.trim() {
+			"@hourly" => vec!["0", "*", "*", "*", "*"],
+			"@daily" | "@midnight" => vec!["0", "0", "*", "*", "*"],
+			"@weekly" => vec!["0", "0", "*", "*", "0"],
+			"@monthly" => vec!["0", "0", "1", "*", "*"],
+			expression => expression.split_whitespace().collect(),
+		};
+		if fields.len() != 5 {
+			return Err(invalid(format!("expected 5 fields, got {}", fields.len())))
+		}
+		let mut days_of_week = parse_field(fields[4], 0, 7).map_err(invalid)?;
+		// Sunday is both 0 and 7
+		if days_of_week & 1 << 7 != 0 {
+			days_of_week = (days_of_week | 1) & !(1 << 7);
+		}
+		Ok(Self {
+			minutes: parse_field(fields[0], 0, 59).map_err(invalid)?,
+			hours: parse_field(fields[1], 0, 23).map_err(invalid)?,
+			days_of_month: parse_field(fields[2], 1, 31).map_err(invalid)?,
+			months: parse_field(fields[3], 1, 12).map_err(invalid)?,
+			days_of_week,
+			either_day: !fields[2].starts_with('*') && !fields[4].starts_with('*'),
+		})
+	}
+
+	/// Returns the first time the schedule matches strictly after `after`, to the minute, or `None`
+	/// if it does not match within the next years (e.g. on February 30th).
+	pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+		let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+		let last_year = after.year() + MAX_SEARCH_YEARS;
+		while time.year() <= last_year {
+			if !has(self.months, time.month()) {
+				let (year, month) = if time.month() == 12 {
+					(time.year() + 1, 1)
+				} else {
+					(time.year(), time.month() + 1)
+				};
+				time = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+			} else if !self.matches_day(time) {
+				time = (time.date_naive() + Duration::days(1)).and_time(NaiveTime::MIN).and_utc();
+			} else if !has(self.hours, time.hour()) {
+				time = time.with_minute(0)? + Duration::hours(1);
+			} else if !has(self.minutes, time.minute()) {
+				time += Duration::minutes(1);
+			} else {
+				return Some(time)
+			}
+		}
+		None
+	}
+
+	fn matches_day(&self, time: DateTime<Utc>) -> bool {
+		let day_of_month = has(self.days_of_month, time.day());
+		let day_of_week = has(self.days_of_week, time.weekday().num_days_from_sunday());
+		if self.either_day {
+			day_of_month || day_of_week
+		} else {
+			day_of_month && day_of_week
+		}
+	}
+}
+
+fn has(set: u64, value: u32) -> bool {
+	set & 1 << value != 0
+}
+
+/// Parses a field into the set of values between `min` and `max` it matches.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+	let mut set = 0;
+	for part in field.split(',') {
+		let invalid = || format!("invalid field {}", field);
+		let (range, step) = match part.split_once('/') {
+			Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+			None => (part, 1),
+		};
+		let value = |value: &str| value.parse::<u32>().map_err(|_| invalid());
+		let (start, end) = match range.split_once('-') {
+			_ if range == "*" => (min, max),
+			Some((start, end)) => (value(start)?, value(end)?),
+			// A stepped value runs up to the maximum, e.g. `5/15`
+			None if step > 1 => (value(range)?, max),
+			None => (value(range)?, value(range)?),
+		};
+		if step == 0 || start < min || start > end || end > max {
+			return Err(invalid())
+		}
+		for value in (start..=end).step_by(step as usize) {
+			set |= 1 << value;
+		}
+	}
+	Ok(set)
+}
@@ -0,0 +1,239 @@
+//! Scheduled exports of the stored transfers to CSV.
+//!
+//! Each configured export runs a saved filter (the transfers of an account, or of any member of a
+//! group, optionally limited to a window before the run) on its cron schedule, and writes the
+//! result as a CSV file to a local directory or an S3-compatible bucket. Every run is recorded in
+//! the `export_runs` table, listed by the `/admin/exports` endpoint. A run missed while the
+//! service was down is not caught up.
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{collections::HashSet, error::Error, path::PathBuf};
+use tracing::{error, info, warn};
+
+pub mod cron;
+pub mod snapshot;
+pub mod tables;
+
+use crate::{
+	archive::s3::{S3Client, S3Credentials},
+	db::{
+		exports,
+		pool::{with_connection, DbPool},
+	},
+	server::{dto::TransferResponse, stats::parse_window},
+	types::{ExportConfig, ExportDestination, ExportFilter, TransferKind},
+};
+use cron::Schedule;
+
+/// A row of an export file: a transfer, its tags joined with `;` as CSV has no lists.
+#[derive(Serialize)]
+struct CsvTransfer {
+	transaction_id: String,
+	transfer_index: u32,
+	sender: String,
+	receiver: String,
+	amount: u64,
+	timestamp: i64,
+	block_height: u64,
+	kind: TransferKind,
+	sender_owner: Option<String>,
+	receiver_owner: Option<String>,
+	invoked_by: Option<String>,
+	tags: String,
+}
+
+impl From<TransferResponse> for CsvTransfer {
+	fn from(transfer: TransferResponse) -> Self {
+		Self {
+			transaction_id: transfer.transaction_id,
+			transfer_index: transfer.transfer_index,
+			sender: transfer.sender,
+			receiver: transfer.receiver,
+			amount: transfer.amount,
+			timestamp: transfer.timestamp,
+			block_height: transfer.block_height,
+			kind: transfer.kind,
+			sender_owner: transfer.sender_owner,
+			receiver_owner: transfer.receiver_owner,
+			invoked_by: transfer.invoked_by,
+			tags: transfer.tags.join(";"),
+		}
+	}
+}
+
+/// Where the files of an export are written.
+enum Destination {
+	Directory(PathBuf),
+	S3 { client: Box<S3Client>, bucket: String, prefix: String },
+}
+
+/// An export ready to run, with its configuration validated.
+pub struct ScheduledExport {
+	name: String,
+	schedule: Schedule,
+	filter: ExportFilter,
+	/// The window before each run the transfers are exported from, in seconds.
+	window: Option<i64>,
+	destination: Destination,
+}
+
+impl ScheduledExport {
+	/// Validates the configuration of an export.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the name, schedule, window or destination is invalid, or
+	/// if the S3 credentials are missing.
+	pub fn new(config: ExportConfig) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let valid_name = !config.name.is_empty() &&
+			config.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+		if !valid_name {
+			return Err(format!("Invalid export name: {:?}", config.name).into())
+		}
+		let schedule = Schedule::parse(&config.schedule)?;
+		let window = config.window.as_deref().map(parse_window).transpose()?;
+		let destination = match config.destination {
+			ExportDestination::Directory(path) => Destination::Directory(path.into()),
+			ExportDestination::S3(s3) => {
+				let credentials = S3Credentials::resolve(s3.access_key_id, s3.secret_access_key)?;
+				Destination::S3 {
+					client: Box::new(S3Client::new(
+						&s3.endpoint,
+						&s3.bucket,
+						&s3.region,
+						credentials,
+					)?),
+					bucket: s3.bucket,
+					prefix: s3.prefix.trim_matches('/').to_string(),
+				}
+			},
+		};
+		Ok(Self { name: config.name, schedule, filter: config.filter, window, destination })
+	}
+
+	/// Runs the export on its schedule, until no run is scheduled anymore.
+	pub async fn run(self, pool: DbPool) {
+		loop {
+			let Some(next) = self.schedule.next_after(Utc::now()) else {
+				warn!("Export {} is not scheduled to run anymore", self.name);
+				return
+			};
+			tokio::time::sleep((next - Utc::now()).to_std().unwrap_or_default()).await;
+			if let Err(err) = self.run_once(&pool, next).await {
+				error!("Failed to record the run of export {}: {:?}", self.name, err);
+			}
+		}
+	}
+
+	/// Runs the export once and records the run.
+	///
+	/// # Arguments
+	///
+	/// * `pool` - The `DbPool` the transfers are read from and the run is recorded in.
+	/// * `scheduled_at` - The time the run was scheduled at, ending the exported window.
+	///
+	/// # Returns
+	///
+	/// This function returns the id of the recorded run, which failed if the transfers could not
+	/// be read or written.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the run cannot be recorded.
+	pub async fn run_once(
+		&self,
+		pool: &DbPool,
+		scheduled_at: DateTime<Utc>,
+	) -> Result<i64, Box<dyn Error + Send + Sync>> {
+		let (name, scheduled) = (self.name.clone(), scheduled_at.timestamp());
+		let id = with_connection(pool, move |conn| {
+			exports::start_export_run(conn, &name, scheduled, Utc::now().timestamp())
+		})
+		.await??;
+
+		let outcome = self.export(pool, scheduled_at).await.map_err(|err| err.to_string());
+		match &outcome {
+			Ok((rows, location)) =>
+				info!("Export {} wrote {} transfers to {}", self.name, rows, location),
+			Err(err) => error!("Export {} failed: {}", self.name, err),
+		}
+		with_connection(pool, move |conn| {
+			exports::finish_export_run(conn, id, Utc::now().timestamp(), outcome)
+		})
+		.await??;
+		Ok(id)
+	}
+
+	/// Writes the transfers matching the filter to the destination, returning their number and
+	/// the location of the file.
+	async fn export(
+		&self,
+		pool: &DbPool,
+		scheduled_at: DateTime<Utc>,
+	) -> Result<(u64, String), Box<dyn Error + Send + Sync>> {
+		let filter = self.filter.clone();
+		let until = scheduled_at.timestamp();
+		let since = self.window.map(|window| until - window);
+		let (rows, csv) = with_connection(pool, move |conn| {
+			let mut rows = 0;
+			let mut writer = csv::Writer::from_writer(Vec::new());
+			exports::for_each_exported_transfer(conn, &filter, since, until, |transfer| {
+				rows += 1;
+				Ok(writer.serialize(CsvTransfer::from(TransferResponse::from(transfer)))?)
+			})?;
+			Ok::<_, Box<dyn Error + Send + Sync>>((rows, writer.into_inner()?))
+		})
+		.await??;
+
+		let file_name = format!("{}-{}.csv", self.name, scheduled_at.format("%Y%m%dT%H%MZ"));
+		let location = match &self.destination {
+			Destination::Directory(directory) => {
+				tokio::fs::create_dir_all(directory).await?;
+				let path = directory.join(file_name);
+				tokio::fs::write(&path, csv).await?;
+				path.display().to_string()
+			},
+			Destination::S3 { client, bucket, prefix } => {
+				let key =
+					if prefix.is_empty() { file_name } else { format!("{}/{}", prefix, file_name) };
+				client.put_object(&key, csv, "text/csv", None).await?;
+				format!("s3://{}/{}", bucket, key)
+			},
+		};
+		Ok((rows, location))
+	}
+}
+
+/// Validates the configured exports and starts running them on their schedules.
+///
+/// The runs left running by a previous process are marked as failed first.
+///
+/// # Errors
+///
+/// This function returns an error if an export is invalid, if two exports share a name, or if the
+/// interrupted runs cannot be updated.
+pub async fn spawn_scheduled_exports(
+	pool: DbPool,
+	configs: &[ExportConfig],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+	let mut names = HashSet::new();
+	let mut scheduled = Vec::new();
+	for config in configs {
+		if !names.insert(config.name.clone()) {
+			return Err(format!("Duplicate export name: {}", config.name).into())
+		}
+		scheduled.push(ScheduledExport::new(config.clone())?);
+	}
+	let now = Utc::now().timestamp();
+	let interrupted =
+		with_connection(&pool, move |conn| exports::fail_interrupted_export_runs(conn, now))
+			.await??;
+	if interrupted > 0 {
+		warn!("Marked {} interrupted export runs as failed", interrupted);
+	}
+	for export in scheduled {
+		info!("Scheduling export {}", export.name);
+		tokio::spawn(export.run(pool.clone()));
+	}
+	Ok(())
+}
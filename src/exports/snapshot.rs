@@ -0,0 +1,332 @@
+//! Snapshots of the database, run by the `export-snapshot` and `import-snapshot` subcommands, so
+//! a new instance is seeded from an existing one instead of ingesting weeks of blocks again.
+//!
+//! A snapshot is a gzipped stream of MessagePack entries: a header with the version of the
+//! snapshot format, the schema version and the genesis hash of the exported database and the range
+//! of slots, then each table with its `CREATE` statement and columns, followed by its rows, and an
+//! end marker counting the rows, so a truncated file is refused. The tables of the ingested data
+//! are limited to the rows of the range; the accounts, labels, groups, filters, epochs and rollups
+//! are exported whole, as they summarize every slot. The operational state (webhooks, submissions,
+//! idempotency keys, export runs) stays with the exporting instance, and so do the transactions
+//! moved to cold storage.
+//!
+//! A snapshot is imported in a single transaction, its rows replacing those with the same key, and
+//! moves the ingestion progress to the last slot of the range, so the imported instance resumes
+//! from the next one. It is refused by a database of another network, and by a binary of another
+//! schema version, as the columns of the tables would not match.
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use rusqlite::{
+	types::{Value, ValueRef},
+	Connection, TransactionBehavior,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+	error::Error,
+	fs::File,
+	io::{BufReader, BufWriter, Write},
+	path::PathBuf,
+};
+
+use crate::db::{compat, sync_state};
+
+/// Version of the snapshot format, bumped when the layout of the entries changes.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The tables of a snapshot, along with the slot column limiting their rows to the range, `None`
+/// for the tables exported whole.
+const SNAPSHOT_TABLES: &[(&str, Option<&str>)] = &[
+	("blocks", Some("slot")),
+	("transactions", Some("block_height")),
+	("transfers", Some("block_height")),
+	("transaction_errors", Some("block_height")),
+	("swaps", Some("block_height")),
+	("sandwiches", Some("block_height")),
+	("balance_changes", Some("block_height")),
+	("account_transactions", Some("block_height")),
+	("program_instructions", Some("block_height")),
+	("account_snapshots", Some("slot")),
+	("failed_slots", Some("slot")),
+	("unavailable_slots", None),
+	("accounts", None),
+	("account_info", None),
+	("labels", None),
+	("groups", None),
+	("group_members", None),
+	("program_filters", None),
+	("epochs", None),
+	("daily_stats", None),
+	("daily_active_accounts", None),
+];
+
+/// The first entry of a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotHeader {
+	pub version: u32,
+	/// The schema version of the exported database, see `compat::SCHEMA_VERSION`.
+	pub schema_version: u32,
+	/// The genesis hash of the network of the exported database, if recorded.
+	pub genesis_hash: Option<String>,
+	pub from_slot: u64,
+	pub to_slot: u64,
+	/// The unix timestamp of the export.
+	pub created_at: i64,
+}
+
+/// A value of a row, as stored by SQLite.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum SnapshotValue {
+	Null,
+	Integer(i64),
+	Real(f64),
+	Text(String),
+	Blob(Vec<u8>),
+}
+
+impl From<ValueRef<'_>> for SnapshotValue {
+	fn from(value: ValueRef) -> Self {
+		match value {
+			ValueRef::Null => SnapshotValue::Null,
+			ValueRef::Integer(value) => SnapshotValue::Integer(value),
+			ValueRef::Real(value) => SnapshotValue::Real(value),
+			ValueRef::Text(value) =>
+				SnapshotValue::Text(String::from_utf8_lossy(value).into_owned()),
+			ValueRef::Blob(value) => SnapshotValue::Blob(value.to_vec()),
+		}
+	}
+}
+
+impl From<SnapshotValue> for Value {
+	fn from(value: SnapshotValue) -> Self {
+		match value {
+			SnapshotValue::Null => Value::Null,
+			SnapshotValue::Integer(value) => Value::Integer(value),
+			SnapshotValue::Real(value) => Value::Real(value),
+			SnapshotValue::Text(value) => Value::Text(value),
+			SnapshotValue::Blob(value) => Value::Blob(value),
+		}
+	}
+}
+
+/// An entry of the stream of a snapshot.
+#[derive(Debug, Serialize, Deserialize)]
+enum SnapshotEntry {
+	Header(SnapshotHeader),
+	/// A table, whose rows follow.
+	Table {
+		name: String,
+		/// The `CREATE` statement of the table, so the snapshot describes itself.
+		sql: String,
+		columns: Vec<String>,
+	},
+	/// A row of the last table, its values in the order of the columns.
+	Row(Vec<SnapshotValue>),
+	/// The end of the snapshot, with the number of rows of every table.
+	End {
+		rows: u64,
+	},
+}
+
+/// A table written to or read from a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotTable {
+	pub name: String,
+	/// The number of rows of the table in the snapshot.
+	pub rows: u64,
+}
+
+/// An export of the database to a snapshot file, as requested on the command line.
+pub struct SnapshotExport {
+	/// The file the snapshot is written to, replaced if it exists.
+	pub path: PathBuf,
+	/// The first slot of the range, the first stored slot by default.
+	pub from_slot: Option<u64>,
+	/// The last slot of the range, inclusive, the last processed slot by default.
+	pub to_slot: Option<u64>,
+}
+
+impl SnapshotExport {
+	/// Writes the snapshot of the range of slots to its file.
+	///
+	/// The tables are read in a single transaction, so the snapshot is consistent while the service
+	/// keeps ingesting blocks.
+	///
+	/// # Arguments
+	///
+	/// * `conn` - A reference to a `Connection` object representing the database connection.
+	///
+	/// # Returns
+	///
+	/// This function returns the header of the snapshot and each of its tables along with its
+	/// number of rows.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the range is empty, if no range is given and no slot was
+	/// processed yet, if the database operation fails, or if the file cannot be written.
+	pub fn run(
+		&self,
+		conn: &Connection,
+	) -> Result<(SnapshotHeader, Vec<SnapshotTable>), Box<dyn Error + Send + Sync>> {
+		let tx = conn.unchecked_transaction()?;
+		let to_slot = match self.to_slot {
+			Some(slot) => slot,
+			None => sync_state::get_last_processed_slot(&tx)?
+				.ok_or("No slot was processed yet, set the last slot of the snapshot")?,
+		};
+		let from_slot = self.from_slot.unwrap_or_default();
+		if from_slot > to_slot {
+			return Err(format!("Slot {} is after slot {}", from_slot, to_slot).into())
+		}
+		let header = SnapshotHeader {
+			version: SNAPSHOT_VERSION,
+			schema_version: compat::SCHEMA_VERSION,
+			genesis_hash: compat::recorded_genesis_hash(&tx)?,
+			from_slot,
+			to_slot,
+			created_at: chrono::Utc::now().timestamp(),
+		};
+
+		let mut writer =
+			GzEncoder::new(BufWriter::new(File::create(&self.path)?), Compression::default());
+		rmp_serde::encode::write(&mut writer, &SnapshotEntry::Header(header.clone()))?;
+		let mut tables = Vec::new();
+		let mut total = 0;
+		for &(name, slot_column) in SNAPSHOT_TABLES {
+			let sql: String = tx.query_row(
+				"SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+				[name],
+				|row| row.get(0),
+			)?;
+			let condition = slot_column
+				.map(|column| format!(" WHERE \"{}\" BETWEEN ?1 AND ?2", column))
+				.unwrap_or_default();
+			let mut stmt = tx.prepare(&format!("SELECT * FROM \"{}\"{}", name, condition))?;
+			let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+			let entry = SnapshotEntry::Table { name: name.to_string(), sql, columns };
+			rmp_serde::encode::write(&mut writer, &entry)?;
+
+			let count = stmt.column_count();
+			let mut selected = match slot_column {
+				Some(_) => stmt.query([from_slot, to_slot])?,
+				None => stmt.query([])?,
+			};
+			let mut rows = 0;
+			while let Some(row) = selected.next()? {
+				let values = (0..count)
+					.map(|index| row.get_ref(index).map(SnapshotValue::from))
+					.collect::<Result<_, _>>()?;
+				rmp_serde::encode::write(&mut writer, &SnapshotEntry::Row(values))?;
+				rows += 1;
+			}
+			tables.push(SnapshotTable { name: name.to_string(), rows });
+			total += rows;
+		}
+		rmp_serde::encode::write(&mut writer, &SnapshotEntry::End { rows: total })?;
+		writer.finish()?.flush()?;
+		Ok((header, tables))
+	}
+}
+
+/// An import of a snapshot file into the database, as requested on the command line.
+pub struct SnapshotImport {
+	/// The file the snapshot is read from.
+	pub path: PathBuf,
+}
+
+impl SnapshotImport {
+	/// Writes the rows of the snapshot to the database and moves the ingestion progress to the last
+	/// slot of its range, or leaves the database untouched on error.
+	///
+	/// The database must be initialized at the schema version of the running binary.
+	///
+	/// # Arguments
+	///
+	/// * `conn` - A mutable reference to a `Connection` object representing the database
+	///   connection.
+	///
+	/// # Returns
+	///
+	/// This function returns the header of the snapshot and each of its tables along with its
+	/// number of rows.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the file cannot be read or is not a complete snapshot, if
+	/// its format or schema version differs from those of the binary, if the database indexes
+	/// another network, if a table or a column of the snapshot is unknown, or if the database
+	/// operation fails.
+	pub fn run(
+		&self,
+		conn: &mut Connection,
+	) -> Result<(SnapshotHeader, Vec<SnapshotTable>), Box<dyn Error + Send + Sync>> {
+		let reader = BufReader::new(GzDecoder::new(BufReader::new(File::open(&self.path)?)));
+		let mut entries = rmp_serde::Deserializer::new(reader);
+		let mut next_entry = || -> Result<SnapshotEntry, Box<dyn Error + Send + Sync>> {
+			SnapshotEntry::deserialize(&mut entries)
+				.map_err(|err| format!("Invalid or truncated snapshot: {}", err).into())
+		};
+
+		let SnapshotEntry::Header(header) = next_entry()? else {
+			return Err("The file does not start with a snapshot header".into())
+		};
+		if header.version != SNAPSHOT_VERSION {
+			return Err(format!(
+				"The snapshot format version {} is not supported by this binary (version {})",
+				header.version, SNAPSHOT_VERSION
+			)
+			.into())
+		}
+		if header.schema_version != compat::SCHEMA_VERSION {
+			return Err(format!(
+				"The snapshot was exported at schema version {}, but this binary is at version {}. \
+				 Import it with the version of the service that exported it.",
+				header.schema_version,
+				compat::SCHEMA_VERSION
+			)
+			.into())
+		}
+
+		let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+		if let Some(genesis_hash) = &header.genesis_hash {
+			compat::check_genesis_hash(&tx, genesis_hash)?;
+		}
+		let mut tables: Vec<SnapshotTable> = Vec::new();
+		let mut insert = None;
+		let total = loop {
+			match next_entry()? {
+				SnapshotEntry::Table { name, columns, .. } => {
+					if !SNAPSHOT_TABLES.iter().any(|(known, _)| *known == name) {
+						return Err(format!("Unknown table in the snapshot: {}", name).into())
+					}
+					let quoted: Vec<String> =
+						columns.iter().map(|column| format!("\"{}\"", column)).collect();
+					let placeholders = vec!["?"; columns.len()].join(", ");
+					let sql = format!(
+						"INSERT OR REPLACE INTO \"{}\" ({}) VALUES ({})",
+						name,
+						quoted.join(", "),
+						placeholders
+					);
+					insert = Some(tx.prepare(&sql)?);
+					tables.push(SnapshotTable { name, rows: 0 });
+				},
+				SnapshotEntry::Row(values) => {
+					let (Some(stmt), Some(table)) = (insert.as_mut(), tables.last_mut()) else {
+						return Err("A row of the snapshot precedes its table".into())
+					};
+					stmt.execute(rusqlite::params_from_iter(values.into_iter().map(Value::from)))?;
+					table.rows += 1;
+				},
+				SnapshotEntry::End { rows } => break rows,
+				SnapshotEntry::Header(_) => return Err("The snapshot has a second header".into()),
+			}
+		};
+		drop(insert);
+		if total != tables.iter().map(|table| table.rows).sum::<u64>() {
+			return Err("The snapshot is missing rows".into())
+		}
+		sync_state::set_last_processed_slot(&tx, header.to_slot, chrono::Utc::now().timestamp())?;
+		tx.commit()?;
+		Ok((header, tables))
+	}
+}
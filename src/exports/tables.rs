@@ -0,0 +1,388 @@
+//! Exports of the stored tables to CSV or Parquet files, run by the `export` subcommand.
+//!
+//! The transactions, transfers and accounts are written to `<table>.csv` or `<table>.parquet`
+//! files in a directory, so they can be loaded by dataframe libraries without reading SQLite. The
+//! transactions and transfers are limited to a range of slots and block times; the accounts, when
+//! a range is set, to those linked to a transaction of the range.
+//!
+//! Every column is nullable. Slots, block times, fees and lamport amounts are 64-bit integers, the
+//! block times in unix seconds; the other columns are strings. Parquet files are compressed with
+//! Snappy and hold `ROW_GROUP_SIZE` rows per row group.
+use parquet::{
+	basic::{Compression, LogicalType, Repetition, Type as PhysicalType},
+	data_type::{ByteArray, ByteArrayType, Int64Type},
+	file::{properties::WriterProperties, writer::SerializedFileWriter},
+	schema::types::Type,
+};
+use rusqlite::{params, Connection, Row};
+use std::{
+	error::Error,
+	fs,
+	path::{Path, PathBuf},
+	str::FromStr,
+	sync::Arc,
+};
+
+use crate::db::{amount::Amount, TransactionFilter};
+
+/// Number of rows of a row group of the Parquet files.
+const ROW_GROUP_SIZE: usize = 65536;
+
+/// The format of the exported files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+	Csv,
+	Parquet,
+}
+
+impl ExportFormat {
+	fn extension(self) -> &'static str {
+		match self {
+			ExportFormat::Csv => "csv",
+			ExportFormat::Parquet => "parquet",
+		}
+	}
+}
+
+impl FromStr for ExportFormat {
+	type Err = String;
+
+	fn from_str(format: &str) -> Result<Self, Self::Err> {
+		match format {
+			"csv" => Ok(ExportFormat::Csv),
+			"parquet" => Ok(ExportFormat::Parquet),
+			_ => Err(format!("Unknown export format: {}", format)),
+		}
+	}
+}
+
+/// A table of the database that can be exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportTable {
+	Transactions,
+	Transfers,
+	Accounts,
+}
+
+/// How an exported column is read from the database and written to the files.
+#[derive(Clone, Copy)]
+enum ColumnType {
+	Integer,
+	/// A decimal `TEXT` amount, see the `amount` module, exported as an integer.
+	Amount,
+	Text,
+}
+
+/// A value of an exported row.
+enum Field {
+	Integer(Option<i64>),
+	Text(Option<String>),
+}
+
+/// The bounds of a range of transactions, over the columns of a table, see `TransactionFilter`.
+fn range_condition(table: &str) -> String {
+	format!(
+		"(?1 IS NULL OR {table}.block_height >= ?1) AND (?2 IS NULL OR {table}.block_height <= ?2)
+         AND (?3 IS NULL OR {table}.timestamp >= ?3) AND (?4 IS NULL OR {table}.timestamp < ?4)"
+	)
+}
+
+impl ExportTable {
+	/// The tables exported by default.
+	pub const ALL: [ExportTable; 3] =
+		[ExportTable::Transactions, ExportTable::Transfers, ExportTable::Accounts];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			ExportTable::Transactions => "transactions",
+			ExportTable::Transfers => "transfers",
+			ExportTable::Accounts => "accounts",
+		}
+	}
+
+	/// The exported columns, in the order the query selects them.
+	fn columns(self) -> &'static [(&'static str, ColumnType)] {
+		use ColumnType::*;
+		match self {
+			ExportTable::Transactions => &[
+				("transaction_id", Text),
+				("slot", Integer),
+				("block_time", Integer),
+				("fee", Integer),
+				("fee_payer", Text),
+				("compute_unit_limit", Integer),
+				("compute_unit_price", Integer),
+				("raw_transaction", Text),
+			],
+			ExportTable::Transfers => &[
+				("transaction_id", Text),
+				("transfer_index", Integer),
+				("slot", Integer),
+				("block_time", Integer),
+				("sender", Text),
+				("receiver", Text),
+				("amount", Amount),
+				("kind", Text),
+				("sender_owner", Text),
+				("receiver_owner", Text),
+				("invoked_by", Text),
+				("tags", Text),
+			],
+			ExportTable::Accounts => &[
+				("account_id", Text),
+				("balance", Amount),
+				("balance_slot", Integer),
+				("total_received", Amount),
+				("total_sent", Amount),
+				("first_seen_slot", Integer),
+				("last_active_slot", Integer),
+			],
+		}
+	}
+
+	/// The query selecting the exported rows, bound to the parameters of a `TransactionFilter`.
+	fn query(self) -> String {
+		match self {
+			ExportTable::Transactions => format!(
+				"SELECT transaction_id, block_height, timestamp, fee, fee_payer, compute_unit_limit, compute_unit_price, raw_transaction
+                 FROM transactions WHERE {} ORDER BY block_height, transaction_id",
+				range_condition("transactions")
+			),
+			ExportTable::Transfers => format!(
+				"SELECT transaction_id, transfer_index, block_height, timestamp, sender, receiver, amount, kind, sender_owner, receiver_owner, invoked_by, tags
+                 FROM transfers WHERE {} ORDER BY block_height, transaction_id, transfer_index",
+				range_condition("transfers")
+			),
+			ExportTable::Accounts => format!(
+				"SELECT account_id, estimated_balance, balance_slot, total_received, total_sent, first_seen_slot, last_active_slot
+                 FROM accounts
+                 WHERE (?1 IS NULL AND ?2 IS NULL AND ?3 IS NULL AND ?4 IS NULL)
+                 OR account_id IN (
+                     SELECT account_transactions.account_id FROM account_transactions
+                     JOIN transactions ON transactions.transaction_id = account_transactions.transaction_id
+                     WHERE {}
+                 )
+                 ORDER BY account_id",
+				range_condition("transactions")
+			),
+		}
+	}
+}
+
+impl FromStr for ExportTable {
+	type Err = String;
+
+	fn from_str(table: &str) -> Result<Self, Self::Err> {
+		ExportTable::ALL
+			.into_iter()
+			.find(|known| known.name() == table)
+			.ok_or_else(|| format!("Unknown export table: {}", table))
+	}
+}
+
+/// A table written by an export.
+#[derive(Debug)]
+pub struct ExportedTable {
+	pub table: ExportTable,
+	/// The number of rows written.
+	pub rows: u64,
+	pub path: PathBuf,
+}
+
+/// An export of stored tables to files, as requested on the command line.
+pub struct TableExport {
+	/// The directory the files are written to, created if missing.
+	pub directory: PathBuf,
+	pub format: ExportFormat,
+	pub tables: Vec<ExportTable>,
+	/// The range of the exported transactions and transfers.
+	pub filter: TransactionFilter,
+}
+
+impl TableExport {
+	/// Writes the rows of the tables in the range to their files, replacing existing files.
+	///
+	/// # Arguments
+	///
+	/// * `conn` - A reference to a `Connection` object representing the database connection.
+	///
+	/// # Returns
+	///
+	/// This function returns each exported table along with its number of rows and the path of its
+	/// file, as an `ExportedTable`.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the database operation fails, if an amount does not fit a
+	/// 64-bit integer, or if a file cannot be written.
+	pub fn run(
+		&self,
+		conn: &Connection,
+	) -> Result<Vec<ExportedTable>, Box<dyn Error + Send + Sync>> {
+		fs::create_dir_all(&self.directory)?;
+		let mut exported = Vec::new();
+		for &table in &self.tables {
+			let path = self.directory.join(format!("{}.{}", table.name(), self.format.extension()));
+			let mut stmt = conn.prepare(&table.query())?;
+			let filter = &self.filter;
+			let mut selected =
+				stmt.query(params![filter.from_slot, filter.to_slot, filter.since, filter.until])?;
+			let columns = table.columns();
+			let mut next_row = || -> Result<_, Box<dyn Error + Send + Sync>> {
+				selected.next()?.map(|row| read_fields(row, columns)).transpose()
+			};
+			let rows = match self.format {
+				ExportFormat::Csv => write_csv(&path, columns, &mut next_row)?,
+				ExportFormat::Parquet =>
+					write_parquet(&path, table.name(), columns, &mut next_row)?,
+			};
+			exported.push(ExportedTable { table, rows, path });
+		}
+		Ok(exported)
+	}
+}
+
+/// Reads the fields of a row selected by the query of a table.
+fn read_fields(
+	row: &Row,
+	columns: &[(&str, ColumnType)],
+) -> Result<Vec<Field>, Box<dyn Error + Send + Sync>> {
+	let mut fields = Vec::with_capacity(columns.len());
+	for (index, (name, column_type)) in columns.iter().enumerate() {
+		fields.push(match column_type {
+			ColumnType::Integer => Field::Integer(row.get(index)?),
+			ColumnType::Amount => {
+				let amount: Option<Amount> = row.get(index)?;
+				let amount = amount
+					.map(|Amount(amount)| i64::try_from(amount))
+					.transpose()
+					.map_err(|_| format!("The {} of a row does not fit a 64-bit integer", name))?;
+				Field::Integer(amount)
+			},
+			ColumnType::Text => Field::Text(row.get(index)?),
+		});
+	}
+	Ok(fields)
+}
+
+/// Writes rows to a CSV file with a header, an empty field standing for a null.
+///
+/// # Returns
+///
+/// This function returns the number of rows written.
+fn write_csv(
+	path: &Path,
+	columns: &[(&str, ColumnType)],
+	next_row: &mut dyn FnMut() -> Result<Option<Vec<Field>>, Box<dyn Error + Send + Sync>>,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+	let mut writer = csv::Writer::from_path(path)?;
+	writer.write_record(columns.iter().map(|(name, _)| name))?;
+	let mut count = 0;
+	while let Some(fields) = next_row()? {
+		let record = fields.into_iter().map(|field| match field {
+			Field::Integer(value) => value.map(|value| value.to_string()).unwrap_or_default(),
+			Field::Text(value) => value.unwrap_or_default(),
+		});
+		writer.write_record(record)?;
+		count += 1;
+	}
+	writer.flush()?;
+	Ok(count)
+}
+
+/// The values of a column of a row group, the nulls left out, along with the definition level of
+/// each row: 1 for a value, 0 for a null.
+enum ColumnBuffer {
+	Integer(Vec<i64>, Vec<i16>),
+	Text(Vec<ByteArray>, Vec<i16>),
+}
+
+/// Writes rows to a Parquet file, in row groups of `ROW_GROUP_SIZE` rows.
+///
+/// # Returns
+///
+/// This function returns the number of rows written.
+fn write_parquet(
+	path: &Path,
+	name: &str,
+	columns: &[(&str, ColumnType)],
+	next_row: &mut dyn FnMut() -> Result<Option<Vec<Field>>, Box<dyn Error + Send + Sync>>,
+) -> Result<u64, Box<dyn Error + Send + Sync>> {
+	let fields = columns
+		.iter()
+		.map(|(name, column_type)| {
+			let column = match column_type {
+				ColumnType::Integer | ColumnType::Amount =>
+					Type::primitive_type_builder(name, PhysicalType::INT64),
+				ColumnType::Text => Type::primitive_type_builder(name, PhysicalType::BYTE_ARRAY)
+					.with_logical_type(Some(LogicalType::String)),
+			};
+			Ok(Arc::new(column.with_repetition(Repetition::OPTIONAL).build()?))
+		})
+		.collect::<Result<Vec<_>, Box<dyn Error + Send + Sync>>>()?;
+	let schema = Arc::new(Type::group_type_builder(name).with_fields(fields).build()?);
+	let properties =
+		Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+	let mut writer = SerializedFileWriter::new(fs::File::create(path)?, schema, properties)?;
+
+	let new_buffers = || {
+		columns
+			.iter()
+			.map(|(_, column_type)| match column_type {
+				ColumnType::Integer | ColumnType::Amount =>
+					ColumnBuffer::Integer(Vec::new(), Vec::new()),
+				ColumnType::Text => ColumnBuffer::Text(Vec::new(), Vec::new()),
+			})
+			.collect::<Vec<_>>()
+	};
+	let mut buffers = new_buffers();
+	let mut buffered = 0;
+	let mut count = 0;
+	loop {
+		let row = next_row()?;
+		let done = row.is_none();
+		if let Some(fields) = row {
+			for (buffer, field) in buffers.iter_mut().zip(fields) {
+				match (buffer, field) {
+					(ColumnBuffer::Integer(values, levels), Field::Integer(value)) => {
+						levels.push(value.is_some() as i16);
+						values.extend(value);
+					},
+					(ColumnBuffer::Text(values, levels), Field::Text(value)) => {
+						levels.push(value.is_some() as i16);
+						values.extend(value.map(|value| ByteArray::from(value.into_bytes())));
+					},
+					_ => unreachable!("the fields follow the columns of the table"),
+				}
+			}
+			buffered += 1;
+			count += 1;
+		}
+		if buffered == ROW_GROUP_SIZE || (done && buffered > 0) {
+			let mut row_group = writer.next_row_group()?;
+			for buffer in std::mem::replace(&mut buffers, new_buffers()) {
+				let mut column = row_group.next_column()?.expect("a writer per column");
+				match buffer {
+					ColumnBuffer::Integer(values, levels) => {
+						column.typed::<Int64Type>().write_batch(&values, Some(&levels), None)?;
+					},
+					ColumnBuffer::Text(values, levels) => {
+						column.typed::<ByteArrayType>().write_batch(
+							&values,
+							Some(&levels),
+							None,
+						)?;
+					},
+				}
+				column.close()?;
+			}
+			row_group.close()?;
+			buffered = 0;
+		}
+		if done {
+			break
+		}
+	}
+	writer.close()?;
+	Ok(count)
+}
@@ -0,0 +1,44 @@
+//! Output of the logs, as free text or as one JSON object per line.
+//!
+//! The events of the ingestion carry what log aggregation systems index them by as fields rather
+//! than in their message: the `slot` of a block, the `signature` of a transaction, and the
+//! `error_kind` of a failure (`fetch`, `parse`, `transaction_parse`, `archive`, `write`),
+//! `skipped_slot` for a slot the leader skipped or `reorg` for a fork rolled back. In JSON, the
+//! fields of an event sit at the top level of its object, next to `timestamp`, `level`, `target`
+//! and `message`, and the spans it happened in are listed under `spans`, outermost first, each with
+//! its `name` and fields.
+use tracing::Subscriber;
+use tracing_subscriber::{fmt::MakeWriter, util::SubscriberInitExt, EnvFilter};
+
+use crate::types::LogFormat;
+
+/// Builds the subscriber writing the events enabled by `filter` to `writer`, in `format`.
+pub fn subscriber<W>(
+	format: LogFormat,
+	filter: EnvFilter,
+	writer: W,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+	W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+	let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(writer);
+	match format {
+		LogFormat::Text => Box::new(builder.finish()),
+		LogFormat::Json => Box::new(
+			builder
+				.json()
+				.flatten_event(true)
+				.with_current_span(false)
+				.with_span_list(true)
+				.finish(),
+		),
+	}
+}
+
+/// Writes the logs to the standard output in `format` for the rest of the process.
+///
+/// Levels and targets are filtered by `RUST_LOG`; the records of the dependencies logging through
+/// `log` are forwarded to the subscriber.
+pub fn init(format: LogFormat) {
+	subscriber(format, EnvFilter::from_default_env(), std::io::stdout).init();
+}
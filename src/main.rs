@@ -1,60 +1,519 @@
 mod aggregator;
+mod anomaly;
+mod archive;
+mod cli;
 mod db;
+mod events;
+mod exports;
+mod logging;
+mod pubsub;
+mod rpc;
 mod server;
+mod shutdown;
+mod sinks;
 mod tests;
 pub mod types;
+mod webhooks;
 
-use axum::{routing::get, Extension, Router};
-use db::{initialize_db, TransactionRecord};
-use log::{error, info};
-use rusqlite::Connection;
+use axum::{
+	middleware,
+	routing::{get, post},
+	Extension, Router,
+};
+use db::{
+	compat, contention, initialize_db,
+	pool::{DbPool, SqliteConnectionManager},
+	slow_log,
+	storage::StorageMonitor,
+	tiering,
+	writer::DbWriter,
+};
+use rusqlite::{Connection, OpenFlags};
+use tracing::{error, info};
 
-use solana_client::rpc_client::RpcClient;
+use rpc::RpcGateway;
 
-use crate::aggregator::aggregate_blocks;
-use server::{get_account_handler, get_transaction_handler};
-use std::{error::Error, sync::Arc};
-use tokio::sync::Mutex;
-use types::Config;
+use crate::aggregator::{
+	aggregate_blocks, backfill::Backfiller, backfill_range, enrichment, epochs,
+	retrieval::get_genesis_hash, retry, snapshots::AccountSnapshotter,
+};
+use events::EventBus;
+use pubsub::{pubsub_url, PubsubHub};
+use server::{
+	admin::{
+		backfill_account_handler, delete_program_filter_handler, get_export_runs_handler,
+		get_failed_slots_handler, get_program_filter_handler, list_program_filters_handler,
+		put_program_filter_handler, refetch_transaction_handler, requeue_failed_slot_handler,
+	},
+	auth::{self, ApiKeys},
+	consistency::ReadConsistency,
+	degraded::{get_degraded_status_handler, DegradedMode},
+	explorer, get_account_handler, get_account_snapshots_handler, get_account_transactions_handler,
+	get_accounts_batch_handler, get_balance_handler, get_block_handler, get_recent_blocks_handler,
+	get_transaction_handler, get_transaction_instructions_handler, get_transactions_handler,
+	get_transfers_handler,
+	groups::{
+		delete_group_handler, get_group_balance_handler, get_group_handler,
+		get_group_stats_handler, get_group_transactions_handler, list_groups_handler,
+		put_group_handler,
+	},
+	health_handler,
+	idempotency::idempotency,
+	labels::{get_label_handler, import_labels_handler},
+	listener,
+	metrics::{get_http_stats_handler, get_ingestion_stats_handler, IngestionMetrics},
+	mev::get_sandwiches_handler,
+	runtime::{get_runtime_status_handler, RuntimeInfo},
+	signing::ResponseSigner,
+	slots::{
+		get_epoch_handler, get_epochs_handler, get_slot_at_handler, get_slot_time_handler,
+		get_unavailable_slots_handler,
+	},
+	spans,
+	state::AppState,
+	stats::{
+		get_activity_heatmap_handler, get_daily_stats_handler, get_db_stats_handler,
+		get_errors_handler, get_fee_payers_handler, get_priority_fees_handler,
+		get_rpc_stats_handler, get_storage_stats_handler, parse_window,
+	},
+	submissions::{get_submission_handler, send_transaction_handler},
+	timeout::RequestTimeout,
+	webhooks::{
+		create_webhook_handler, delete_webhook_handler, get_webhook_deliveries_handler,
+		get_webhook_handler, list_webhooks_handler, redeliver_handler,
+	},
+	ws::{transactions_ws_handler, ws_handler},
+};
+use shutdown::Shutdown;
+use sinks::MessageBus;
+use std::{error::Error, sync::Arc, time::Duration};
+use types::{Config, ListenerConfig, RunMode};
+use webhooks::WebhookDispatcher;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-	env_logger::init();
+	// Load the configuration file, overridden by the environment and the command line
+	let matches = cli::command().get_matches();
+	let config = cli::load_config(&matches, std::env::vars())?;
+	logging::init(config.log_format);
+	let backfill = cli::backfill_range(&matches)?;
+	let table_export = cli::table_export(&matches);
+	let snapshot_export = cli::snapshot_export(&matches);
+	let snapshot_import = cli::snapshot_import(&matches);
+	let runtime = RuntimeInfo::new(&config);
+	runtime.log_banner();
 
-	// Load the configuration file
-	let config = Config::from_file("config.toml")?;
-	info!("Starting Solana Aggregator with config: {:?}", config);
+	let mode = config.mode;
+	info!("Running in {:?} mode", mode);
 
-	let client = Arc::new(RpcClient::new(config.rpc_url.to_string()));
-
-	// Initialize SQLite database
-	let conn = Arc::new(Mutex::new(Connection::open("solana.db")?));
-	{
-		let conn = conn.lock().await;
+	// Initialize SQLite database, refusing a schema written by a newer binary. Serve-only replicas
+	// never write to the database, so they only check that it is already up to date
+	if mode == RunMode::ServeOnly {
+		let conn = open_read_only_database(&config)?;
+		compat::check_schema_is_current(&conn).map_err(|err| err.to_string())?;
+	} else {
+		let conn = open_database(&config)?;
+		compat::check_schema_version(&conn).map_err(|err| err.to_string())?;
 		initialize_db(&conn)?;
+		if config.tiering.is_some() {
+			tiering::initialize_cold_storage(&conn)?;
+		}
+		compat::record_schema_version(&conn).map_err(|err| err.to_string())?;
 	}
 
-	// Start the block aggregation process
-	let client_clone = Arc::clone(&client);
-	let conn_clone = Arc::clone(&conn);
-	let config_clone = config.clone();
+	// A backfill writes its range of slots alongside the live ingestion, if any, then exits
+	if let Some(slots) = backfill {
+		let gateway = Arc::new(RpcGateway::new(&config));
+		let conn = open_database(&config)?;
+		let genesis_hash = get_genesis_hash(&gateway).await.map_err(|err| err.to_string())?;
+		compat::check_genesis_hash(&conn, &genesis_hash).map_err(|err| err.to_string())?;
+		let (writer, _) = DbWriter::spawn(conn, config.storage_policy(), EventBus::default());
+		backfill_range(gateway, writer, &config, slots)
+			.await
+			.map_err(|err| err.to_string())?;
+		return Ok(())
+	}
 
-	tokio::spawn(async move {
-		if let Err(e) = aggregate_blocks(client_clone, conn_clone, config_clone).await {
-			error!("Block aggregation process failed: {:?}", e);
+	// An export writes the stored tables to files, then exits
+	if let Some(export) = table_export {
+		let conn = open_database(&config)?;
+		let exported = export.run(&conn).map_err(|err| err.to_string())?;
+		for exported in exported {
+			let (name, path) = (exported.table.name(), exported.path.display());
+			info!("Exported {} {} to {}", exported.rows, name, path);
 		}
-	});
+		return Ok(())
+	}
+
+	// A snapshot is written to a file, or read into the database, then the process exits
+	if let Some(export) = snapshot_export {
+		let conn = open_database(&config)?;
+		let (header, tables) = export.run(&conn).map_err(|err| err.to_string())?;
+		let rows: u64 = tables.iter().map(|table| table.rows).sum();
+		let (from_slot, to_slot, path) = (header.from_slot, header.to_slot, export.path.display());
+		info!("Exported {} rows of slots {} to {} to {}", rows, from_slot, to_slot, path);
+		return Ok(())
+	}
+	if let Some(import) = snapshot_import {
+		let mut conn = open_database(&config)?;
+		let (header, tables) = import.run(&mut conn).map_err(|err| err.to_string())?;
+		for table in tables {
+			info!("Imported {} rows of {}", table.rows, table.name);
+		}
+		info!("Imported slots {} to {}", header.from_slot, header.to_slot);
+		return Ok(())
+	}
+
+	// A signal stops the ingestion at a block boundary and the API once its requests are answered
+	let shutdown = Shutdown::new();
+	tokio::spawn(shutdown.clone().request_on_signal());
+	run_service(config, runtime, shutdown).await
+}
+
+/// Runs the parts of the service selected by the mode of the configuration over the initialized
+/// database, until the shutdown is requested.
+///
+/// Serve-only replicas only start the API: they never talk to the RPC node and run no ingestion
+/// or background job writing to the database.
+///
+/// # Errors
+///
+/// This function returns an error if the service cannot be started, a listener fails, or the
+/// ingestion task panics.
+async fn run_service(
+	config: Config,
+	mut runtime: RuntimeInfo,
+	shutdown: Shutdown,
+) -> Result<(), Box<dyn Error>> {
+	let mode = config.mode;
+	let pool = open_pool(&config).await?;
+
+	// Start moving old transactions to cold storage
+	if let Some(tiering_config) = &config.tiering {
+		let max_age = parse_window(&tiering_config.max_age)?;
+		let interval = parse_window(&tiering_config.interval)?;
+		if mode != RunMode::ServeOnly {
+			tokio::spawn(tiering::run_tiering(
+				pool.clone(),
+				max_age,
+				Duration::from_secs(interval.max(1) as u64),
+			));
+		}
+	}
+
+	// Start the scheduled exports, which record their runs
+	if mode != RunMode::ServeOnly {
+		exports::spawn_scheduled_exports(pool.clone(), &config.exports)
+			.await
+			.map_err(|err| err.to_string())?;
+	}
+
+	// The features reacting to the ingestion subscribe to the events of the writer
+	let events = EventBus::default();
+	let ingestion_metrics = IngestionMetrics::default();
+	tokio::spawn(ingestion_metrics.clone().follow(events.subscribe()));
+	if let Some(anomaly_config) = &config.anomaly_detection {
+		tokio::spawn(anomaly::run_anomaly_detection(anomaly_config.clone(), events.clone()));
+	}
+
+	// Start the block aggregation process, serve-only replicas never talk to the RPC node
+	let mut backfiller = None;
+	let mut rpc_gateway = None;
+	let mut pubsub_hub = None;
+	let mut storage = None;
+	let mut db_writer = None;
+	let ingestion = if mode != RunMode::ServeOnly {
+		let gateway = Arc::new(RpcGateway::new(&config));
+		// Refuse to mix the transactions of several networks in the database
+		let genesis_hash = get_genesis_hash(&gateway).await.map_err(|err| err.to_string())?;
+		compat::check_genesis_hash(&*pool.get().await?, &genesis_hash)
+			.map_err(|err| err.to_string())?;
+		runtime.set_genesis_hash(genesis_hash);
+		rpc_gateway = Some(Arc::clone(&gateway));
+		if let Some(account_info_config) = &config.account_info {
+			let interval = parse_window(&account_info_config.interval)?;
+			tokio::spawn(enrichment::run_account_enrichment(
+				Arc::clone(&gateway),
+				pool.clone(),
+				account_info_config.batch_size,
+				Duration::from_secs(interval.max(1) as u64),
+			));
+		}
+		for snapshot_config in &config.account_snapshots {
+			let snapshotter =
+				AccountSnapshotter::new(snapshot_config).map_err(|err| err.to_string())?;
+			tokio::spawn(snapshotter.run(Arc::clone(&gateway), pool.clone()));
+		}
+		tokio::spawn(epochs::run_epoch_catalog(
+			Arc::clone(&gateway),
+			pool.clone(),
+			events.subscribe(),
+		));
+		if let Some(bus_config) = &config.message_bus {
+			let bus = MessageBus::new(bus_config).await.map_err(|err| err.to_string())?;
+			tokio::spawn(sinks::run_message_sink(bus, events.subscribe()));
+		}
+		let dispatcher = WebhookDispatcher::new(pool.clone());
+		tokio::spawn(dispatcher.clone().record_deliveries(events.subscribe()));
+		tokio::spawn(dispatcher.run_deliveries());
+		pubsub_hub = Some(PubsubHub::spawn(
+			config.pubsub_url.clone().unwrap_or_else(|| pubsub_url(&config.rpc_url)),
+		));
+		let (mut writer, _) =
+			DbWriter::spawn(open_database(&config)?, config.storage_policy(), events.clone());
+		if let Some(quota_config) = &config.storage_quota {
+			let monitor = StorageMonitor::spawn(pool.clone(), quota_config.clone());
+			writer = writer.with_storage_monitor(monitor.clone());
+			storage = Some(monitor);
+		}
+		backfiller = Some(Backfiller::new(Arc::clone(&gateway), pool.clone(), writer.clone()));
+		db_writer = Some(writer.clone());
+		tokio::spawn(retry::run_failed_slot_retries(
+			Arc::clone(&gateway),
+			pool.clone(),
+			writer.clone(),
+			config.archive.clone(),
+		));
+		let config_clone = config.clone();
+		let pool_clone = pool.clone();
+		let shutdown = shutdown.clone();
+
+		Some(tokio::spawn(async move {
+			let aggregation = aggregate_blocks(gateway, pool_clone, writer, config_clone, shutdown);
+			if let Err(e) = aggregation.await {
+				error!("Block aggregation process failed: {:?}", e);
+			}
+		}))
+	} else {
+		// The network of a replica is the one recorded by its ingesting instance, if any
+		let recorded = compat::recorded_genesis_hash(&*pool.get().await?);
+		if let Some(genesis_hash) = recorded.map_err(|err| err.to_string())? {
+			runtime.set_genesis_hash(genesis_hash);
+		}
+		None
+	};
+
+	// Headless ingestion only exposes the health check, if configured
+	if mode == RunMode::IngestOnly {
+		if let Some(health_address) = &config.health_address {
+			let app = Router::new()
+				.route("/health", get(health_handler))
+				.layer(Extension(AppState { storage, ..AppState::new(pool) }));
+			listener::serve_all(&[ListenerConfig::new(health_address)], app, &shutdown).await?;
+		}
+		if let Some(ingestion) = ingestion {
+			ingestion.await?;
+		}
+		return Ok(())
+	}
 
 	// Build the API service with Axum
-	let app = Router::new()
+	let state = AppState {
+		gateway: rpc_gateway,
+		backfiller,
+		pubsub: pubsub_hub,
+		events: (mode != RunMode::ServeOnly).then_some(events),
+		max_rows: config.max_rows_per_request,
+		amount_unit: config.amount_unit,
+		transaction_passthrough: config.transaction_passthrough,
+		ingestion_metrics,
+		storage,
+		degraded: config.degraded_mode.as_ref().map(|degraded_config| {
+			DegradedMode::spawn(pool.clone(), db_writer, degraded_config.clone())
+		}),
+		runtime: Some(Arc::new(runtime)),
+		..AppState::new(pool)
+	};
+	// Routes over the data of every account are closed to the API keys restricted to some accounts
+	let mut app = Router::new()
+		.route("/transactions", get(get_transactions_handler))
+		.route("/stats/fee-payers", get(get_fee_payers_handler))
+		.route("/stats/errors", get(get_errors_handler))
+		.route("/stats/priority-fees", get(get_priority_fees_handler))
+		.route("/stats/rpc", get(get_rpc_stats_handler))
+		.route("/stats/daily", get(get_daily_stats_handler))
+		.route("/stats/storage", get(get_storage_stats_handler))
+		.route("/stats/db", get(get_db_stats_handler))
+		.route("/stats/degraded", get(get_degraded_status_handler))
+		.route("/stats/http", get(get_http_stats_handler))
+		.route("/stats/ingestion", get(get_ingestion_stats_handler))
+		.route("/status/runtime", get(get_runtime_status_handler))
+		.route("/mev/sandwiches", get(get_sandwiches_handler))
+		.route(
+			"/labels/import",
+			post(import_labels_handler).route_layer(middleware::from_fn(idempotency)),
+		)
+		.route("/transactions/send", post(send_transaction_handler))
+		.route("/transactions/submitted/:signature", get(get_submission_handler))
+		.route(
+			"/admin/backfill/:pubkey",
+			post(backfill_account_handler).route_layer(middleware::from_fn(idempotency)),
+		)
+		.route(
+			"/admin/transactions/:signature/refetch",
+			post(refetch_transaction_handler).route_layer(middleware::from_fn(idempotency)),
+		)
+		.route("/admin/exports", get(get_export_runs_handler))
+		.route("/admin/failed-slots", get(get_failed_slots_handler))
+		.route(
+			"/admin/failed-slots/:slot/requeue",
+			post(requeue_failed_slot_handler).route_layer(middleware::from_fn(idempotency)),
+		)
+		.route("/admin/filters", get(list_program_filters_handler))
+		.route(
+			"/admin/filters/:program_id",
+			get(get_program_filter_handler)
+				.put(put_program_filter_handler)
+				.delete(delete_program_filter_handler),
+		)
+		.route(
+			"/webhooks",
+			get(list_webhooks_handler).merge(
+				post(create_webhook_handler).route_layer(middleware::from_fn(idempotency)),
+			),
+		)
+		.route("/webhooks/:id", get(get_webhook_handler).delete(delete_webhook_handler))
+		.route("/webhooks/:id/deliveries", get(get_webhook_deliveries_handler))
+		.route("/webhooks/:id/deliveries/:delivery_id/redeliver", post(redeliver_handler))
+		.route("/block/:slot", get(get_block_handler))
+		.route("/blocks/recent", get(get_recent_blocks_handler))
+		.route("/ws", get(ws_handler))
+		.route("/ws/transactions", get(transactions_ws_handler))
+		.route_layer(middleware::from_fn(auth::require_unrestricted))
+		.route("/health", get(health_handler))
 		.route("/transaction", get(get_transaction_handler))
+		.route("/transaction/instructions", get(get_transaction_instructions_handler))
 		.route("/accountid", get(get_account_handler))
-		.layer(Extension(Arc::clone(&conn)));
+		.route("/accounts/batch", post(get_accounts_batch_handler))
+		.route("/transfers", get(get_transfers_handler))
+		.route("/accounts/:pubkey/balance", get(get_balance_handler))
+		.route("/accounts/:pubkey/snapshots", get(get_account_snapshots_handler))
+		.route("/accounts/:pubkey/activity-heatmap", get(get_activity_heatmap_handler))
+		.route("/account/:id/transactions", get(get_account_transactions_handler))
+		.route("/labels/:pubkey", get(get_label_handler))
+		.route("/groups", get(list_groups_handler))
+		.route(
+			"/groups/:name",
+			get(get_group_handler).put(put_group_handler).delete(delete_group_handler),
+		)
+		.route("/groups/:name/transactions", get(get_group_transactions_handler))
+		.route("/groups/:name/balance", get(get_group_balance_handler))
+		.route("/groups/:name/stats", get(get_group_stats_handler))
+		.route("/slots/at", get(get_slot_at_handler))
+		.route("/slots/:slot/time", get(get_slot_time_handler))
+		.route("/slots/unavailable", get(get_unavailable_slots_handler))
+		.route("/epochs", get(get_epochs_handler))
+		.route("/epochs/:epoch", get(get_epoch_handler))
+		.route("/explorer", get(explorer::index_handler))
+		.route("/explorer/app.js", get(explorer::script_handler))
+		.route("/explorer/style.css", get(explorer::style_handler));
+	// Handlers running past the request timeout are dropped, which interrupts their queries
+	if let Some(timeout_ms) = config.request_timeout_ms {
+		let timeout = RequestTimeout::new(Duration::from_millis(timeout_ms));
+		app = app.layer(middleware::from_fn(move |request, next| timeout.handle(request, next)));
+	}
+	app = app.layer(middleware::from_fn({
+		let consistency =
+			ReadConsistency::new(state.db.clone(), Duration::from_millis(config.max_slot_wait_ms));
+		move |request, next| consistency.clone().handle(request, next)
+	}));
+	// Reads are served from a cache while the ingestion is behind, if configured
+	if let Some(degraded) = state.degraded.clone() {
+		app = app.layer(middleware::from_fn(move |request, next| {
+			degraded.clone().handle(request, next)
+		}));
+	}
+	app = app
+		.layer(middleware::from_fn({
+			let http_metrics = state.http_metrics.clone();
+			move |request, next| http_metrics.clone().track(request, next)
+		}))
+		.layer(Extension(state));
+	// Requests must carry an API key once keys are configured
+	if !config.api_keys.is_empty() {
+		let api_keys = ApiKeys::new(&config.api_keys).map_err(|err| err.to_string())?;
+		info!("Requiring one of {} API keys", config.api_keys.len());
+		app = app.layer(middleware::from_fn(move |request, next| {
+			api_keys.clone().authenticate(request, next)
+		}));
+	}
+	if let Some(keypair_path) = &config.signing_keypair {
+		let signer =
+			Arc::new(ResponseSigner::from_file(keypair_path).map_err(|err| err.to_string())?);
+		info!("Signing API responses with {}", signer.pubkey());
+		app = app.layer(middleware::from_fn(move |request, next| {
+			Arc::clone(&signer).sign_response(request, next)
+		}));
+	}
+	// Every request, rejected ones included, is answered within its span
+	app = app.layer(middleware::from_fn(spans::request_span));
+
+	// Run the Axum server on every listener, then wait for the ingestion to flush its writes
+	listener::serve_all(&config.api_listeners(), app, &shutdown).await?;
+	if let Some(ingestion) = ingestion {
+		ingestion.await?;
+	}
+	info!("Shut down");
+
+	Ok(())
+}
+
+/// Opens a connection to the database file, used by the writer and the startup checks.
+fn open_database(config: &Config) -> Result<Connection, Box<dyn Error>> {
+	let mut conn = Connection::open(&config.db_path)?;
+	configure_connection(&mut conn, Duration::from_millis(config.slow_query_ms), false)?;
+	if let Some(tiering) = &config.tiering {
+		tiering::attach_cold_storage(&conn, &tiering.cold_path)?;
+	}
+	Ok(conn)
+}
+
+/// Opens a read-only connection to the existing database file, used by the startup checks of
+/// serve-only replicas.
+fn open_read_only_database(config: &Config) -> Result<Connection, Box<dyn Error>> {
+	let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+	let mut conn = Connection::open_with_flags(&config.db_path, flags)?;
+	configure_connection(&mut conn, Duration::from_millis(config.slow_query_ms), true)?;
+	Ok(conn)
+}
 
-	// Run the Axum server
-	axum::Server::bind(&config.server_address.parse()?)
-		.serve(app.into_make_service())
-		.await?;
+/// Opens the pool of connections serving the API handlers and the background tasks.
+///
+/// Each connection has the cold database attached when tiering is configured, its tables being
+/// created by the startup migrations. The connections of serve-only replicas are read-only.
+async fn open_pool(config: &Config) -> Result<DbPool, Box<dyn Error>> {
+	let slow_query = Duration::from_millis(config.slow_query_ms);
+	let read_only = config.mode == RunMode::ServeOnly;
+	let cold_path = config.tiering.as_ref().map(|tiering| tiering.cold_path.clone());
+	let mut manager = SqliteConnectionManager::new(&config.db_path, move |conn| {
+		configure_connection(conn, slow_query, read_only)?;
+		if let Some(cold_path) = &cold_path {
+			tiering::attach_cold_storage(conn, cold_path)?;
+		}
+		Ok(())
+	});
+	if read_only {
+		manager = manager.read_only();
+	}
+	Ok(bb8::Pool::builder().max_size(config.db_pool_size).build(manager).await?)
+}
 
+/// Sets up a new connection to the database file.
+///
+/// The database runs in WAL mode so the pooled connections keep reading while the writer commits,
+/// and retries a statement finding the database locked through the busy handler of
+/// `db::contention` instead of failing right away. Queries slower than `slow_query` are logged
+/// with their parameters. A read-only connection keeps the journal mode set by the ingesting
+/// instance.
+fn configure_connection(
+	conn: &mut Connection,
+	slow_query: Duration,
+	read_only: bool,
+) -> rusqlite::Result<()> {
+	if !read_only {
+		conn.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(()))?;
+	}
+	contention::install_busy_handler(conn)?;
+	slow_log::enable_slow_query_log(conn, slow_query);
 	Ok(())
 }
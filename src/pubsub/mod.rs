@@ -0,0 +1,199 @@
+//! Live account updates proxied from a single upstream PubSub connection.
+//!
+//! Every `/ws` client subscribing to an account shares the same upstream `accountSubscribe`
+//! subscription: notifications are fanned out through a broadcast channel per account. The
+//! upstream connection is re-established with an exponential backoff when it drops, and the
+//! accounts that still have listeners are subscribed again.
+use futures::{
+	future::{self, Either},
+	stream::{self, BoxStream, SelectAll},
+	StreamExt,
+};
+use serde_json::json;
+use solana_client::{nonblocking::pubsub_client::PubsubClient, rpc_config::RpcAccountInfoConfig};
+use solana_program::pubkey::Pubkey;
+use std::{collections::HashMap, error::Error, time::Duration};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{error, info, warn};
+
+/// Number of notifications buffered per account before slow listeners start missing updates.
+const NOTIFICATION_BUFFER: usize = 128;
+
+/// Upper bound of the delay between two reconnection attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// A request sent to the hub task.
+enum HubCommand {
+	Subscribe { pubkey: Pubkey, reply: oneshot::Sender<broadcast::Receiver<String>> },
+}
+
+/// A cloneable handle to the task managing the upstream PubSub connection.
+#[derive(Clone)]
+pub struct PubsubHub {
+	commands: mpsc::UnboundedSender<HubCommand>,
+}
+
+impl PubsubHub {
+	/// Spawns the hub task connecting to the given PubSub endpoint.
+	pub fn spawn(url: String) -> Self {
+		let (commands, receiver) = mpsc::unbounded_channel();
+		tokio::spawn(run(url, receiver));
+		Self { commands }
+	}
+
+	/// Subscribes to the updates of an account.
+	///
+	/// Each update is an `accountSubscribe` notification result, serialized as JSON:
+	/// `{"context": {"slot": ..}, "value": <account>}` with base64 encoded data.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the hub task has stopped.
+	pub async fn subscribe(
+		&self,
+		pubkey: Pubkey,
+	) -> Result<broadcast::Receiver<String>, Box<dyn Error + Send + Sync>> {
+		let (reply, receiver) = oneshot::channel();
+		self.commands
+			.send(HubCommand::Subscribe { pubkey, reply })
+			.map_err(|_| "PubSub hub stopped")?;
+		Ok(receiver.await.map_err(|_| "PubSub hub stopped")?)
+	}
+}
+
+/// Derives the PubSub endpoint from an HTTP RPC endpoint (`http` to `ws`, `https` to `wss`).
+pub fn pubsub_url(rpc_url: &str) -> String {
+	if let Some(rest) = rpc_url.strip_prefix("https://") {
+		format!("wss://{}", rest)
+	} else if let Some(rest) = rpc_url.strip_prefix("http://") {
+		format!("ws://{}", rest)
+	} else {
+		rpc_url.to_string()
+	}
+}
+
+/// Keeps the upstream connection alive until every hub handle is dropped.
+async fn run(url: String, mut commands: mpsc::UnboundedReceiver<HubCommand>) {
+	let mut topics: HashMap<Pubkey, broadcast::Sender<String>> = HashMap::new();
+	let mut delay = Duration::from_secs(1);
+
+	loop {
+		match PubsubClient::new(&url).await {
+			Ok(client) => {
+				info!("Connected to PubSub endpoint {}", url);
+				delay = Duration::from_secs(1);
+				if serve_connection(&client, &mut topics, &mut commands).await {
+					return
+				}
+				warn!("PubSub connection to {} lost, reconnecting", url);
+			},
+			Err(err) => error!("Failed to connect to PubSub endpoint {}: {:?}", url, err),
+		}
+
+		// Keep accepting subscriptions while waiting to reconnect
+		let sleep = tokio::time::sleep(delay);
+		tokio::pin!(sleep);
+		loop {
+			tokio::select! {
+				_ = &mut sleep => break,
+				command = commands.recv() => match command {
+					Some(command) => {
+						add_listener(&mut topics, command);
+					},
+					None => return,
+				},
+			}
+		}
+		delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+	}
+}
+
+/// Forwards the notifications of a connection until it drops.
+///
+/// Returns `true` once every hub handle is dropped, `false` when the connection is lost.
+async fn serve_connection(
+	client: &PubsubClient,
+	topics: &mut HashMap<Pubkey, broadcast::Sender<String>>,
+	commands: &mut mpsc::UnboundedReceiver<HubCommand>,
+) -> bool {
+	let mut notifications: SelectAll<BoxStream<'_, (Pubkey, Option<String>)>> = SelectAll::new();
+
+	// Resubscribe the accounts that still have listeners
+	topics.retain(|_, sender| sender.receiver_count() > 0);
+	let pubkeys: Vec<Pubkey> = topics.keys().copied().collect();
+	for pubkey in pubkeys {
+		match subscribe_upstream(client, pubkey).await {
+			Some(stream) => notifications.push(stream),
+			None => return false,
+		}
+	}
+
+	loop {
+		let next = if notifications.is_empty() {
+			Either::Left(future::pending())
+		} else {
+			Either::Right(notifications.next())
+		};
+		tokio::select! {
+			command = commands.recv() => match command {
+				Some(command) =>
+					if let Some(pubkey) = add_listener(topics, command) {
+						match subscribe_upstream(client, pubkey).await {
+							Some(stream) => notifications.push(stream),
+							None => return false,
+						}
+					},
+				None => return true,
+			},
+			notification = next => match notification {
+				Some((pubkey, Some(update))) => {
+					// Sending only fails when the account has no listener left
+					if let Some(sender) = topics.get(&pubkey) {
+						let _ = sender.send(update);
+					}
+				},
+				// An upstream subscription ended, the connection is gone
+				Some((_, None)) | None => return false,
+			},
+		}
+	}
+}
+
+/// Registers a listener, returning the account if it needs an upstream subscription.
+fn add_listener(
+	topics: &mut HashMap<Pubkey, broadcast::Sender<String>>,
+	command: HubCommand,
+) -> Option<Pubkey> {
+	let HubCommand::Subscribe { pubkey, reply } = command;
+	let mut is_new = false;
+	let sender = topics.entry(pubkey).or_insert_with(|| {
+		is_new = true;
+		broadcast::channel(NOTIFICATION_BUFFER).0
+	});
+	// A listener that gave up waiting is not an error
+	let _ = reply.send(sender.subscribe());
+	is_new.then_some(pubkey)
+}
+
+/// Opens the upstream subscription of an account, tagging its end with `None`.
+async fn subscribe_upstream(
+	client: &PubsubClient,
+	pubkey: Pubkey,
+) -> Option<BoxStream<'_, (Pubkey, Option<String>)>> {
+	let config: RpcAccountInfoConfig =
+		serde_json::from_value(json!({ "encoding": "base64" })).expect("valid account config");
+	match client.account_subscribe(&pubkey, Some(config)).await {
+		Ok((updates, _unsubscribe)) => Some(
+			updates
+				.filter_map(move |update| {
+					future::ready(serde_json::to_string(&update).ok().map(|u| (pubkey, Some(u))))
+				})
+				.chain(stream::once(future::ready((pubkey, None))))
+				.boxed(),
+		),
+		Err(err) => {
+			error!("Failed to subscribe to account {}: {:?}", pubkey, err);
+			None
+		},
+	}
+}
@@ -0,0 +1,400 @@
+//! Gateway shared by every subsystem talking to the RPC node.
+//!
+//! `RpcGateway` combines in one place what callers used to reimplement around `RpcClient`: a
+//! token-bucket rate limit, retries with exponential backoff, request timeouts, failover across
+//! endpoints and per-method metrics. It also sends JSON-RPC batches, which `RpcClient` does not
+//! support, over its own HTTP client.
+
+// The solana client error is large, but boxing it at every call site would only add noise.
+#![allow(clippy::result_large_err)]
+use serde::Serialize;
+use solana_client::{
+	client_error::{ClientError, ClientErrorKind},
+	rpc_client::RpcClient,
+	rpc_custom_error::{
+		JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE,
+		JSON_RPC_SERVER_ERROR_BLOCK_STATUS_NOT_AVAILABLE_YET,
+		JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED, JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+	},
+	rpc_request::{RpcError, RpcRequest},
+};
+use std::{
+	collections::BTreeMap,
+	error::Error,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Mutex,
+	},
+	time::{Duration, Instant},
+};
+use tracing::{info, instrument, warn};
+
+use crate::types::{Commitment, Config};
+use serde_json::{json, Value};
+
+/// Counters of the calls made to a single RPC method.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MethodMetrics {
+	/// Requests sent, including retries.
+	pub requests: u64,
+	/// Requests that failed.
+	pub failures: u64,
+	/// Cumulated latency of the requests, in milliseconds.
+	pub total_latency_ms: u64,
+}
+
+/// A snapshot of the gateway metrics.
+#[derive(Debug, Clone, Serialize)]
+pub struct GatewayMetrics {
+	/// The endpoint currently in use.
+	pub active_endpoint: String,
+	/// Number of switches to another endpoint after a failure.
+	pub failovers: u64,
+	pub methods: BTreeMap<String, MethodMetrics>,
+}
+
+/// The error returned for one call of a JSON-RPC batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchCallError {
+	pub code: i64,
+	pub message: String,
+}
+
+impl BatchCallError {
+	/// Returns whether the node may answer the call on a later attempt.
+	pub fn is_transient(&self) -> bool {
+		is_transient_code(self.code)
+	}
+}
+
+impl std::fmt::Display for BatchCallError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "RPC error {}: {}", self.code, self.message)
+	}
+}
+
+impl Error for BatchCallError {}
+
+/// A token bucket refilled continuously at `rate` tokens per second, holding at most `burst`.
+struct TokenBucket {
+	rate: f64,
+	burst: f64,
+	state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+	fn new(rate: f64, burst: f64) -> Self {
+		Self { rate, burst, state: Mutex::new((burst, Instant::now())) }
+	}
+
+	/// Waits until a token is available and takes it.
+	async fn acquire(&self) {
+		loop {
+			let wait = {
+				let mut state = self.state.lock().expect("token bucket lock poisoned");
+				let (tokens, last) = &mut *state;
+				let now = Instant::now();
+				*tokens =
+					(*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.burst);
+				*last = now;
+				if *tokens >= 1.0 {
+					*tokens -= 1.0;
+					return
+				}
+				Duration::from_secs_f64((1.0 - *tokens) / self.rate)
+			};
+			tokio::time::sleep(wait).await;
+		}
+	}
+}
+
+/// The single entry point to the RPC node(s).
+pub struct RpcGateway {
+	endpoints: Vec<(String, RpcClient)>,
+	http: reqwest::Client,
+	active: AtomicUsize,
+	limiter: Option<TokenBucket>,
+	retries: u8,
+	batch_size: usize,
+	commitment: Commitment,
+	failovers: AtomicUsize,
+	metrics: Mutex<BTreeMap<String, MethodMetrics>>,
+}
+
+impl RpcGateway {
+	/// Creates a gateway over `rpc_url` and the fallback endpoints of the configuration.
+	pub fn new(config: &Config) -> Self {
+		let timeout = Duration::from_secs(config.rpc.timeout_secs);
+		let endpoints = std::iter::once(&config.rpc_url)
+			.chain(&config.rpc.fallback_urls)
+			.map(|url| {
+				let client = RpcClient::new_with_timeout_and_commitment(
+					url.clone(),
+					timeout,
+					config.commitment.config(),
+				);
+				(url.clone(), client)
+			})
+			.collect();
+		let limiter = config
+			.rpc
+			.requests_per_second
+			.map(|rate| TokenBucket::new(rate, config.rpc.burst.unwrap_or(rate).max(1.0)));
+		let http = reqwest::Client::builder()
+			.timeout(timeout)
+			.build()
+			.expect("valid HTTP client configuration");
+		Self {
+			endpoints,
+			http,
+			active: AtomicUsize::new(0),
+			limiter,
+			retries: config.retry_attempts,
+			batch_size: config.rpc.batch_size.max(1),
+			commitment: config.commitment,
+			failovers: AtomicUsize::new(0),
+			metrics: Mutex::new(BTreeMap::new()),
+		}
+	}
+
+	/// Runs an RPC call through the rate limit, with retries and failover.
+	///
+	/// Transport errors and transient node errors (block not available yet, node unhealthy, ...)
+	/// are retried up to `retry_attempts` times with an exponential backoff starting at two
+	/// seconds, switching to the next endpoint after each failure. Other JSON-RPC errors, such as
+	/// a skipped slot or a failed preflight, are returned right away.
+	///
+	/// # Arguments
+	///
+	/// * `method` - The name of the RPC method, used in the logs and metrics.
+	/// * `call` - The call to run against the client of the active endpoint.
+	///
+	/// # Errors
+	///
+	/// This function returns the error of the last attempt.
+	#[instrument(name = "rpc", skip_all, fields(method = method))]
+	pub async fn call<T, F>(&self, method: &str, call: F) -> Result<T, Box<dyn Error + Send + Sync>>
+	where
+		F: Fn(&RpcClient) -> Result<T, ClientError>,
+	{
+		let mut attempts = 0;
+		let mut wait_time = 2; // initial wait time in seconds
+
+		loop {
+			if let Some(limiter) = &self.limiter {
+				limiter.acquire().await;
+			}
+			let index = self.active.load(Ordering::Relaxed) % self.endpoints.len();
+			let (url, client) = &self.endpoints[index];
+
+			let started = Instant::now();
+			let result = call(client);
+			self.record(method, started.elapsed(), result.is_err());
+
+			match result {
+				Ok(value) => return Ok(value),
+				Err(err) if attempts < self.retries && is_retryable(&err) => {
+					attempts += 1;
+					info!("Retry {}/{} for {} on {}: {}", attempts, self.retries, method, url, err);
+					self.fail_over(index);
+					tokio::time::sleep(Duration::from_secs(wait_time)).await;
+					wait_time *= 2; // exponential backoff
+				},
+				Err(err) => return Err(format!("{} failed: {}", method, err).into()),
+			}
+		}
+	}
+
+	/// Sends several calls of a method in a single JSON-RPC batch request.
+	///
+	/// The batch goes through the rate limit as one token per call, and is retried with failover
+	/// on transport errors like `call`. The errors of individual calls are returned in their slot
+	/// of the result, so the caller can retry only those.
+	///
+	/// # Arguments
+	///
+	/// * `method` - The name of the RPC method; the batch is recorded in the metrics as
+	///   `<method>[batch]`.
+	/// * `params` - The parameters of each call.
+	///
+	/// # Returns
+	///
+	/// This function returns the result of each call, in the order of `params`.
+	///
+	/// # Errors
+	///
+	/// This function returns the error of the last attempt if the batch request itself fails.
+	#[instrument(name = "rpc", skip_all, fields(method = method, calls = params.len()))]
+	pub async fn batch_call(
+		&self,
+		method: &str,
+		params: &[Value],
+	) -> Result<Vec<Result<Value, BatchCallError>>, Box<dyn Error + Send + Sync>> {
+		let metric = format!("{}[batch]", method);
+		let body: Vec<Value> = params
+			.iter()
+			.enumerate()
+			.map(
+				|(id, params)| json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }),
+			)
+			.collect();
+		let mut attempts = 0;
+		let mut wait_time = 2; // initial wait time in seconds
+
+		loop {
+			if let Some(limiter) = &self.limiter {
+				for _ in 0..params.len() {
+					limiter.acquire().await;
+				}
+			}
+			let index = self.active.load(Ordering::Relaxed) % self.endpoints.len();
+			let url = &self.endpoints[index].0;
+
+			let started = Instant::now();
+			let result = self.send_batch(url, &body, params.len()).await;
+			self.record(&metric, started.elapsed(), result.is_err());
+
+			match result {
+				Ok(results) => return Ok(results),
+				Err(err) if attempts < self.retries => {
+					attempts += 1;
+					info!("Retry {}/{} for {} on {}: {}", attempts, self.retries, metric, url, err);
+					self.fail_over(index);
+					tokio::time::sleep(Duration::from_secs(wait_time)).await;
+					wait_time *= 2; // exponential backoff
+				},
+				Err(err) => return Err(format!("{} failed: {}", metric, err).into()),
+			}
+		}
+	}
+
+	async fn send_batch(
+		&self,
+		url: &str,
+		body: &[Value],
+		len: usize,
+	) -> Result<Vec<Result<Value, BatchCallError>>, Box<dyn Error + Send + Sync>> {
+		let response = self
+			.http
+			.post(url)
+			.header(reqwest::header::CONTENT_TYPE, "application/json")
+			.body(serde_json::to_vec(body)?)
+			.send()
+			.await?
+			.error_for_status()?;
+		let responses: Vec<Value> = serde_json::from_slice(&response.bytes().await?)?;
+
+		let mut results: Vec<Option<Result<Value, BatchCallError>>> = vec![None; len];
+		for mut response in responses {
+			let Some(id) = response["id"].as_u64().map(|id| id as usize).filter(|id| *id < len)
+			else {
+				return Err(format!("Unexpected batch response: {}", response).into())
+			};
+			results[id] = Some(match response.get_mut("error") {
+				Some(error) => Err(BatchCallError {
+					code: error["code"].as_i64().unwrap_or_default(),
+					message: error["message"].as_str().unwrap_or_default().to_string(),
+				}),
+				None => Ok(response["result"].take()),
+			});
+		}
+		results
+			.into_iter()
+			.map(|result| result.ok_or_else(|| "Incomplete batch response".into()))
+			.collect()
+	}
+
+	/// Submits a signed, serialized transaction through `sendTransaction` as is.
+	///
+	/// # Arguments
+	///
+	/// * `transaction` - The serialized transaction.
+	/// * `encoding` - The encoding of the transaction, `base64` or `base58`.
+	/// * `skip_preflight` - Whether the node skips the preflight simulation.
+	///
+	/// # Returns
+	///
+	/// This function returns the signature of the transaction.
+	pub async fn send_raw_transaction(
+		&self,
+		transaction: &str,
+		encoding: &str,
+		skip_preflight: bool,
+	) -> Result<String, Box<dyn Error + Send + Sync>> {
+		let params = json!([
+			transaction,
+			{ "encoding": encoding, "skipPreflight": skip_preflight }
+		]);
+		self.call("sendTransaction", |client| {
+			client.send::<String>(RpcRequest::SendTransaction, params.clone())
+		})
+		.await
+	}
+
+	/// Returns the commitment level of the ingestion.
+	pub fn commitment(&self) -> Commitment {
+		self.commitment
+	}
+
+	/// Returns the number of calls to group in a JSON-RPC batch, one when batching is disabled.
+	pub fn batch_size(&self) -> usize {
+		self.batch_size
+	}
+
+	/// Returns a snapshot of the metrics.
+	pub fn metrics(&self) -> GatewayMetrics {
+		let index = self.active.load(Ordering::Relaxed) % self.endpoints.len();
+		GatewayMetrics {
+			active_endpoint: self.endpoints[index].0.clone(),
+			failovers: self.failovers.load(Ordering::Relaxed) as u64,
+			methods: self.metrics.lock().expect("metrics lock poisoned").clone(),
+		}
+	}
+
+	/// Switches to the next endpoint, unless another caller already did.
+	fn fail_over(&self, failed: usize) {
+		if self.endpoints.len() < 2 {
+			return
+		}
+		let next = (failed + 1) % self.endpoints.len();
+		if self
+			.active
+			.compare_exchange(failed, next, Ordering::Relaxed, Ordering::Relaxed)
+			.is_ok()
+		{
+			self.failovers.fetch_add(1, Ordering::Relaxed);
+			warn!("Failing over from {} to {}", self.endpoints[failed].0, self.endpoints[next].0);
+		}
+	}
+
+	fn record(&self, method: &str, latency: Duration, failed: bool) {
+		let mut metrics = self.metrics.lock().expect("metrics lock poisoned");
+		let entry = metrics.entry(method.to_string()).or_default();
+		entry.requests += 1;
+		entry.total_latency_ms += latency.as_millis() as u64;
+		if failed {
+			entry.failures += 1;
+		}
+	}
+}
+
+/// Returns whether an error is worth retrying: transport errors and transient node errors.
+fn is_retryable(err: &ClientError) -> bool {
+	match err.kind() {
+		ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) =>
+			is_transient_code(*code),
+		ClientErrorKind::SigningError(_) | ClientErrorKind::TransactionError(_) => false,
+		_ => true,
+	}
+}
+
+/// Returns whether a JSON-RPC error code reports a transient node condition.
+fn is_transient_code(code: i64) -> bool {
+	matches!(
+		code,
+		JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE |
+			JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY |
+			JSON_RPC_SERVER_ERROR_BLOCK_STATUS_NOT_AVAILABLE_YET |
+			JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED
+	)
+}
@@ -0,0 +1,357 @@
+//! Handlers of the administrative operations.
+use axum::{
+	extract::{Path, Query},
+	http::StatusCode,
+	response::{IntoResponse, Json},
+};
+use serde::Deserialize;
+use serde_json::json;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::str::FromStr;
+use tracing::{error, info};
+
+use super::{build_error_response, row_limit, state::AppState, DbConn};
+use crate::db::{
+	exports, failed_slots,
+	filters::{self, FilterRule, ProgramFilterRecord},
+};
+
+/// Handler starting the backfill of the full history of an account.
+///
+/// This asynchronous function validates the account and starts pulling its signature history from
+/// the RPC node in the background, ingesting every transaction not stored yet. The outcome of the
+/// backfill is logged once it completes.
+///
+/// # Arguments
+///
+/// * `pubkey` - A `Path` extractor containing the account to backfill.
+/// * `state` - The `AppState`, whose `Backfiller` is missing when the service does not ingest
+///   blocks.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - An `ACCEPTED` response once the backfill has started.
+/// - An error response with a `BAD_REQUEST` status if the account is not a valid pubkey.
+/// - An error response with a `SERVICE_UNAVAILABLE` status if the service does not ingest blocks.
+pub async fn backfill_account_handler(
+	Path(pubkey): Path<String>,
+	state: AppState,
+) -> impl IntoResponse {
+	let Some(backfiller) = state.backfiller else {
+		return build_error_response(
+			StatusCode::SERVICE_UNAVAILABLE,
+			"Backfill requires the ingestion to be enabled",
+		)
+		.into_response()
+	};
+	let address = match Pubkey::from_str(&pubkey) {
+		Ok(address) => address,
+		Err(_) =>
+			return build_error_response(StatusCode::BAD_REQUEST, "Invalid pubkey").into_response(),
+	};
+
+	tokio::spawn(async move {
+		match backfiller.backfill_account(&address).await {
+			Ok(summary) => info!("Backfill of {} completed: {:?}", address, summary),
+			Err(err) => error!("Backfill of {} failed: {:?}", address, err),
+		}
+	});
+
+	(StatusCode::ACCEPTED, Json(json!({ "pubkey": pubkey, "status": "started" }))).into_response()
+}
+
+/// Handler re-fetching a transaction from the RPC node and replacing the stored copy.
+///
+/// This asynchronous function pulls the transaction with `getTransaction`, parses it again and
+/// overwrites the stored records, repairing rows written by a parser bug or before a parser
+/// existed. It responds once the transaction has been written.
+///
+/// # Arguments
+///
+/// * `signature` - A `Path` extractor containing the signature of the transaction.
+/// * `state` - The `AppState`, whose `Backfiller` is missing when the service does not ingest
+///   blocks.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<RefetchSummary>` once the transaction has been written.
+/// - An error response with a `BAD_REQUEST` status if the signature is invalid.
+/// - An error response with a `BAD_GATEWAY` status if the transaction cannot be fetched, parsed or
+///   written.
+/// - An error response with a `SERVICE_UNAVAILABLE` status if the service does not ingest blocks.
+pub async fn refetch_transaction_handler(
+	Path(signature): Path<String>,
+	state: AppState,
+) -> impl IntoResponse {
+	let Some(backfiller) = state.backfiller else {
+		return build_error_response(
+			StatusCode::SERVICE_UNAVAILABLE,
+			"Re-fetch requires the ingestion to be enabled",
+		)
+		.into_response()
+	};
+	let signature = match Signature::from_str(&signature) {
+		Ok(signature) => signature,
+		Err(_) =>
+			return build_error_response(StatusCode::BAD_REQUEST, "Invalid signature")
+				.into_response(),
+	};
+
+	match backfiller.refetch_transaction(&signature).await {
+		Ok(summary) => {
+			info!("Re-fetched transaction {}: {:?}", signature, summary);
+			Json(summary).into_response()
+		},
+		Err(err) => {
+			error!("Re-fetch of transaction {} failed: {:?}", signature, err);
+			build_error_response(StatusCode::BAD_GATEWAY, "Failed to re-fetch the transaction")
+				.into_response()
+		},
+	}
+}
+
+/// Handler for listing the slots queued for another attempt after their block could not be fetched
+/// or parsed.
+///
+/// # Arguments
+///
+/// * `state` - The `AppState`, providing the maximum number of rows.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<FailedSlotRecord>>` with the queued slots, ordered by slot.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_failed_slots_handler(state: AppState, conn: DbConn) -> impl IntoResponse {
+	let limit = state.max_rows;
+	match conn.interact(move |conn| failed_slots::get_failed_slots(conn, limit)).await {
+		Ok(slots) => Json(slots).into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Handler scheduling the next attempt of a queued slot right away.
+///
+/// This asynchronous function also requeues a slot parked after too many failed attempts. The
+/// ingestion picks the slot up on its next scan of the queue, within a minute.
+///
+/// # Arguments
+///
+/// * `slot` - A `Path` extractor containing the slot to requeue.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<FailedSlotRecord>` with the rescheduled slot.
+/// - An error response with a `NOT_FOUND` status if the slot is not queued.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database error.
+pub async fn requeue_failed_slot_handler(Path(slot): Path<u64>, conn: DbConn) -> impl IntoResponse {
+	let now = chrono::Utc::now().timestamp();
+	match conn
+		.interact(move |conn| failed_slots::requeue_failed_slot(conn, slot, now))
+		.await
+	{
+		Ok(Some(record)) => {
+			info!("Requeued failed slot {}", slot);
+			Json(record).into_response()
+		},
+		Ok(None) => build_error_response(StatusCode::NOT_FOUND, "Slot not queued").into_response(),
+		Err(err) => {
+			error!("Database error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Query parameters for listing the runs of the scheduled exports.
+#[derive(Deserialize)]
+pub struct ExportRunsQuery {
+	name: Option<String>,
+	limit: Option<usize>,
+}
+
+/// Handler for listing the runs of the scheduled exports, newest first.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `ExportRunsQuery`, `name` restricting the runs
+///   to those of an export and `limit` defaulting to the maximum number of rows.
+/// * `state` - The `AppState`, providing the maximum number of rows.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<ExportRunRecord>>` with the runs, newest first.
+/// - An error response with a `BAD_REQUEST` status if the limit is out of range.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_export_runs_handler(
+	Query(params): Query<ExportRunsQuery>,
+	state: AppState,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let limit = match row_limit(params.limit, state.max_rows) {
+		Ok(limit) => limit,
+		Err(message) =>
+			return build_error_response(StatusCode::BAD_REQUEST, &message).into_response(),
+	};
+	match conn
+		.interact(move |conn| exports::get_export_runs(conn, params.name.as_deref(), limit))
+		.await
+	{
+		Ok(runs) => Json(runs).into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Body of a program filter creation or update.
+#[derive(Deserialize)]
+pub struct ProgramFilterRequest {
+	rule: FilterRule,
+}
+
+/// Handler for listing the program filters of the ingestion.
+///
+/// # Arguments
+///
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<ProgramFilterRecord>>` with the filters, ordered by program.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn list_program_filters_handler(conn: DbConn) -> impl IntoResponse {
+	match conn.interact(filters::list_program_filters).await {
+		Ok(filters) => Json(filters).into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Handler for retrieving the filter of a program.
+///
+/// # Arguments
+///
+/// * `program_id` - A `Path` extractor containing the program.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<ProgramFilterRecord>` if the program has a filter.
+/// - An error response with a `NOT_FOUND` status if the program has none.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_program_filter_handler(
+	Path(program_id): Path<String>,
+	conn: DbConn,
+) -> impl IntoResponse {
+	match conn.interact(move |conn| filters::get_program_filter(conn, &program_id)).await {
+		Ok(Some(filter)) => Json(filter).into_response(),
+		Ok(None) => build_error_response(StatusCode::NOT_FOUND, "Filter not found").into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Handler for creating the filter of a program or replacing its rule.
+///
+/// The rule applies to the blocks the ingestion writes from its next batch on.
+///
+/// # Arguments
+///
+/// * `program_id` - A `Path` extractor containing the program.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+/// * `request` - A `Json` extractor containing the `ProgramFilterRequest` with the rule, `allow` or
+///   `deny`.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<ProgramFilterRecord>` with a `CREATED` status if the filter was created, `OK` if it was
+///   replaced.
+/// - An error response with a `BAD_REQUEST` status if the program is not a valid pubkey.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database error.
+pub async fn put_program_filter_handler(
+	Path(program_id): Path<String>,
+	conn: DbConn,
+	Json(request): Json<ProgramFilterRequest>,
+) -> impl IntoResponse {
+	if Pubkey::from_str(&program_id).is_err() {
+		return build_error_response(StatusCode::BAD_REQUEST, "Invalid program id").into_response()
+	}
+	let filter = ProgramFilterRecord {
+		program_id,
+		rule: request.rule,
+		updated_at: chrono::Utc::now().timestamp(),
+	};
+	match conn
+		.interact(move |conn| {
+			filters::put_program_filter(conn, &filter).map(|created| (created, filter))
+		})
+		.await
+	{
+		Ok((created, filter)) => {
+			info!("Set the {} filter of program {}", filter.rule.as_str(), filter.program_id);
+			let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+			(status, Json(filter)).into_response()
+		},
+		Err(err) => {
+			error!("Database error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Handler for deleting the filter of a program.
+///
+/// # Arguments
+///
+/// * `program_id` - A `Path` extractor containing the program.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - A `NO_CONTENT` response if the filter was deleted.
+/// - An error response with a `NOT_FOUND` status if the program has none.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database error.
+pub async fn delete_program_filter_handler(
+	Path(program_id): Path<String>,
+	conn: DbConn,
+) -> impl IntoResponse {
+	match conn
+		.interact(move |conn| filters::delete_program_filter(conn, &program_id))
+		.await
+	{
+		Ok(true) => StatusCode::NO_CONTENT.into_response(),
+		Ok(false) =>
+			build_error_response(StatusCode::NOT_FOUND, "Filter not found").into_response(),
+		Err(err) => {
+			error!("Database error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
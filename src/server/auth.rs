@@ -0,0 +1,143 @@
+//! API key authentication and visibility scopes.
+//!
+//! Once `[[api_keys]]` are configured, every request but the health check and the explorer assets
+//! must carry one of the keys, in an `Authorization: Bearer <key>` or an `X-API-Key` header, and is
+//! rejected with an `UNAUTHORIZED` status otherwise. A key restricted to some account groups or
+//! label tags only reads the accounts of its scope (see `db::scopes`): the handlers serving the
+//! data of an account, a transaction or a group check it in their queries and answer data outside
+//! the scope with a `FORBIDDEN` status, and the routes serving the data of every account, such as
+//! the statistics, the blocks, the live streams and the administration, are closed to it.
+use axum::{
+	async_trait,
+	extract::{FromRequest, RequestParts},
+	http::{header::AUTHORIZATION, Request, StatusCode},
+	middleware::Next,
+	response::Response,
+};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, error::Error, ops::Deref, sync::Arc};
+
+use super::build_error_response;
+use crate::{db::scopes::AccountScope, types::ApiKeyConfig};
+
+/// Header carrying the API key of a request, as an alternative to `Authorization`.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// The API key of a request and the accounts it sees, taken by the handlers as an extractor.
+///
+/// Requests carry no key when none is configured, and then see every account.
+#[derive(Clone, Default)]
+pub struct ApiScope {
+	/// The name of the key.
+	key: Option<Arc<str>>,
+	accounts: Arc<AccountScope>,
+}
+
+impl ApiScope {
+	/// Returns the name of the API key of the request, if any.
+	pub fn key_name(&self) -> Option<&str> {
+		self.key.as_deref()
+	}
+}
+
+impl Deref for ApiScope {
+	type Target = AccountScope;
+
+	fn deref(&self) -> &AccountScope {
+		&self.accounts
+	}
+}
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for ApiScope {
+	type Rejection = Response;
+
+	async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+		Ok(request.extensions().get::<ApiScope>().cloned().unwrap_or_default())
+	}
+}
+
+/// The configured API keys, by the SHA-256 digest of the key.
+#[derive(Clone)]
+pub struct ApiKeys {
+	keys: Arc<HashMap<[u8; 32], ApiScope>>,
+}
+
+impl ApiKeys {
+	/// Validates the configured keys.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if a key or its name is empty or used twice.
+	pub fn new(configs: &[ApiKeyConfig]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let mut keys = HashMap::new();
+		for config in configs {
+			if config.name.is_empty() || config.key.is_empty() {
+				return Err("API keys need a name and a key".into())
+			}
+			if keys
+				.values()
+				.any(|scope: &ApiScope| scope.key_name() == Some(config.name.as_str()))
+			{
+				return Err(format!("Duplicate API key name {}", config.name).into())
+			}
+			let scope = ApiScope {
+				key: Some(config.name.as_str().into()),
+				accounts: Arc::new(AccountScope {
+					groups: config.groups.clone(),
+					label_tags: config.label_tags.clone(),
+				}),
+			};
+			if keys.insert(digest(&config.key), scope).is_some() {
+				return Err(format!("API key {} is used by another key", config.name).into())
+			}
+		}
+		Ok(Self { keys: Arc::new(keys) })
+	}
+
+	/// Middleware rejecting the requests without a configured API key with an `UNAUTHORIZED`
+	/// status, and giving the others the scope of their key.
+	pub async fn authenticate<B>(self, mut request: Request<B>, next: Next<B>) -> Response {
+		let path = request.uri().path();
+		if path == "/health" || path.starts_with("/explorer") {
+			return next.run(request).await
+		}
+		let headers = request.headers();
+		let key = match headers.get(API_KEY_HEADER) {
+			Some(key) => key.to_str().ok(),
+			None => headers
+				.get(AUTHORIZATION)
+				.and_then(|value| value.to_str().ok())
+				.and_then(|value| value.strip_prefix("Bearer ")),
+		};
+		let Some(key) = key else {
+			return build_error_response(StatusCode::UNAUTHORIZED, "Missing API key")
+		};
+		let Some(scope) = self.keys.get(&digest(key.trim())) else {
+			return build_error_response(StatusCode::UNAUTHORIZED, "Invalid API key")
+		};
+		request.extensions_mut().insert(scope.clone());
+		next.run(request).await
+	}
+}
+
+/// Middleware closing a route to the API keys restricted to some accounts.
+pub async fn require_unrestricted<B>(request: Request<B>, next: Next<B>) -> Response {
+	let restricted = request
+		.extensions()
+		.get::<ApiScope>()
+		.is_some_and(|scope| !scope.is_unrestricted());
+	if restricted {
+		return build_error_response(
+			StatusCode::FORBIDDEN,
+			"Not available to API keys restricted to some accounts",
+		)
+	}
+	next.run(request).await
+}
+
+/// Returns the SHA-256 digest of a key, so the keys are compared without their timing telling
+/// how much of a guess matches.
+fn digest(key: &str) -> [u8; 32] {
+	Sha256::digest(key.as_bytes()).into()
+}
@@ -0,0 +1,122 @@
+//! Consistency markers and slot-conditional reads of the API.
+//!
+//! Every response carries the last slot fully ingested when the request came in, read from the
+//! ingestion checkpoint (`sync_state`) before the handler runs. The data of the response is at
+//! least as fresh as that slot, so a client waiting for a slot can retry until the marker reaches
+//! it. The marker is a header rather than a field, so the response bodies keep their format.
+//!
+//! A read can also ask to be answered only once the ingestion reached a slot, with a `min_slot`
+//! query parameter or an `X-Wait-For-Slot` header. The request then waits, up to a configured
+//! bound, polling the checkpoint, so a client that just submitted a transaction reads its own
+//! writes without a retry loop.
+use axum::{
+	http::{header::RETRY_AFTER, HeaderValue, Method, Request, StatusCode},
+	middleware::Next,
+	response::{IntoResponse, Response},
+};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::error;
+
+use super::build_error_response;
+use crate::db::{
+	pool::{with_connection, DbPool},
+	sync_state,
+};
+
+/// Header carrying the last slot fully ingested when the request came in, missing until the
+/// ingestion processed a first slot.
+pub const AS_OF_SLOT_HEADER: &str = "x-aggregator-as-of-slot";
+
+/// Header asking a read to wait until the ingestion reached a slot, like `min_slot`.
+pub const WAIT_FOR_SLOT_HEADER: &str = "x-wait-for-slot";
+
+/// Delay between two reads of the checkpoint while a request waits for a slot.
+const SLOT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Adds the consistency marker to the responses and holds the slot-conditional reads.
+#[derive(Clone)]
+pub struct ReadConsistency {
+	pool: DbPool,
+	/// How long a read waits for its slot at most.
+	max_wait: Duration,
+}
+
+impl ReadConsistency {
+	pub fn new(pool: DbPool, max_wait: Duration) -> Self {
+		Self { pool, max_wait }
+	}
+
+	/// Middleware waiting for the slot a `GET` request asks for, if any, then adding the
+	/// `X-Aggregator-As-Of-Slot` header to the response.
+	///
+	/// Requests whose slot is not reached within the bound are answered with a
+	/// `SERVICE_UNAVAILABLE` status and a `Retry-After` header, and requests with an invalid slot
+	/// with a `BAD_REQUEST` status.
+	pub async fn handle<B>(self, request: Request<B>, next: Next<B>) -> Response {
+		let min_slot = match requested_slot(&request) {
+			Ok(min_slot) => min_slot,
+			Err(message) => return build_error_response(StatusCode::BAD_REQUEST, &message),
+		};
+		let deadline = Instant::now() + self.max_wait;
+		let mut slot = self.last_processed_slot().await;
+		if let Some(min_slot) = min_slot {
+			while slot.is_none_or(|slot| slot < min_slot) {
+				if Instant::now() + SLOT_POLL_INTERVAL > deadline {
+					let message = format!("Slot {} not ingested yet", min_slot);
+					let mut response =
+						build_error_response(StatusCode::SERVICE_UNAVAILABLE, &message);
+					response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(1));
+					return with_as_of_slot(response, slot)
+				}
+				tokio::time::sleep(SLOT_POLL_INTERVAL).await;
+				slot = self.last_processed_slot().await;
+			}
+		}
+		with_as_of_slot(next.run(request).await.into_response(), slot)
+	}
+
+	async fn last_processed_slot(&self) -> Option<u64> {
+		match with_connection(&self.pool, sync_state::get_last_processed_slot).await {
+			Ok(Ok(slot)) => slot,
+			Ok(Err(err)) => {
+				error!("Failed to read the last processed slot: {:?}", err);
+				None
+			},
+			Err(err) => {
+				error!("Failed to check out a database connection: {:?}", err);
+				None
+			},
+		}
+	}
+}
+
+/// Returns the slot a read request waits for, the highest of its `min_slot` query parameter and
+/// `X-Wait-For-Slot` header; other requests never wait.
+pub(crate) fn requested_slot<B>(request: &Request<B>) -> Result<Option<u64>, String> {
+	if request.method() != Method::GET {
+		return Ok(None)
+	}
+	let parse = |value: &str| {
+		value.parse::<u64>().map_err(|_| format!("Invalid slot to wait for: {}", value))
+	};
+	let query = request
+		.uri()
+		.query()
+		.into_iter()
+		.flat_map(|query| query.split('&'))
+		.filter_map(|pair| pair.strip_prefix("min_slot="))
+		.map(parse);
+	let header = request
+		.headers()
+		.get(WAIT_FOR_SLOT_HEADER)
+		.map(|value| parse(value.to_str().unwrap_or_default()));
+	query.chain(header).try_fold(None, |max, slot| Ok(max.max(Some(slot?))))
+}
+
+fn with_as_of_slot(mut response: Response, slot: Option<u64>) -> Response {
+	if let Some(slot) = slot {
+		response.headers_mut().insert(AS_OF_SLOT_HEADER, HeaderValue::from(slot));
+	}
+	response
+}
@@ -0,0 +1,246 @@
+//! Degraded mode of the API, serving cached reads while the ingestion falls behind.
+//!
+//! A monitor checks the queue of the database writer and the age of the last stored block every
+//! second. While either is above its threshold, the service is degraded: the reads answered before
+//! are served from a cache of their last response instead of the database, which is busy catching
+//! up. Reads that exceed their latency budget are answered from the cache as well, degraded or
+//! not. Cached responses carry an `X-Aggregator-Degraded: true` header and their `Age`, so clients
+//! tell them apart from fresh ones; the `X-Aggregator-As-Of-Slot` header they were cached with
+//! still tells how fresh their data is.
+//!
+//! Only `GET` requests answered with an `OK` status and a body of known size are cached, so
+//! streamed listings and WebSocket upgrades always run. Reads waiting for a slot never get a cached
+//! response.
+use axum::{
+	body::{boxed, Bytes, Full, HttpBody},
+	extract::Json,
+	http::{
+		header::{ACCEPT, AGE, UPGRADE},
+		HeaderMap, HeaderValue, Method, Request, StatusCode,
+	},
+	middleware::Next,
+	response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use super::{auth::ApiScope, consistency::requested_slot, state::AppState};
+use crate::{
+	db::{
+		blocks,
+		pool::{with_connection, DbPool},
+		writer::DbWriter,
+	},
+	types::DegradedModeConfig,
+};
+
+/// Header flagging a response served from the cache of the degraded mode.
+pub const DEGRADED_HEADER: &str = "x-aggregator-degraded";
+
+/// Delay between two checks of the ingestion.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Largest response body cached, in bytes.
+const MAX_CACHED_BODY_BYTES: u64 = 1 << 20;
+
+/// The state of the ingestion, as last checked by the monitor.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DegradedStatus {
+	pub degraded: bool,
+	/// The commands waiting for the database writer, `None` when the process does not ingest.
+	pub queued_writes: Option<usize>,
+	/// The age of the last stored block in seconds, `None` while no block with a time is stored.
+	pub lag_secs: Option<u64>,
+	/// The unix timestamp of the last check, `None` until the first one.
+	pub checked_at: Option<i64>,
+}
+
+/// A response kept to be served while the service is degraded.
+struct CachedResponse {
+	status: StatusCode,
+	headers: HeaderMap,
+	body: Bytes,
+	cached_at: Instant,
+}
+
+/// Serves cached reads while the ingestion is behind, shared by the middleware and the monitor.
+#[derive(Clone)]
+pub struct DegradedMode {
+	config: Arc<DegradedModeConfig>,
+	status: watch::Receiver<DegradedStatus>,
+	cache: Arc<Mutex<HashMap<String, CachedResponse>>>,
+}
+
+impl DegradedMode {
+	/// Spawns the monitor of the ingestion, watching the queue of the writer if the process
+	/// ingests blocks, and the age of the blocks stored in the database of the pool.
+	pub fn spawn(pool: DbPool, writer: Option<DbWriter>, config: DegradedModeConfig) -> Self {
+		let config = Arc::new(config);
+		let (sender, receiver) = watch::channel(DegradedStatus::default());
+		tokio::spawn(run_degraded_monitor(pool, writer, Arc::clone(&config), sender));
+		Self { config, status: receiver, cache: Arc::default() }
+	}
+
+	/// Returns the state of the ingestion as last checked.
+	pub fn status(&self) -> DegradedStatus {
+		self.status.borrow().clone()
+	}
+
+	/// Middleware caching the responses of the reads, and serving them from the cache while the
+	/// service is degraded or once they exceed their latency budget.
+	pub async fn handle<B>(self, request: Request<B>, next: Next<B>) -> Response {
+		let Some(key) = cache_key(&request) else { return next.run(request).await };
+		if self.status.borrow().degraded {
+			if let Some(response) = self.cached(&key) {
+				return response
+			}
+		}
+		let budget = Duration::from_millis(self.config.latency_budget_ms);
+		let response = next.run(request);
+		tokio::pin!(response);
+		let response = match tokio::time::timeout(budget, &mut response).await {
+			Ok(response) => response,
+			Err(_) => match self.cached(&key) {
+				Some(cached) => {
+					warn!("{} exceeded its latency budget, serving it from the cache", key);
+					return cached
+				},
+				None => response.await,
+			},
+		};
+		self.store(key, response).await
+	}
+
+	/// Returns the cached response of a read, flagged as degraded, unless it is too old.
+	fn cached(&self, key: &str) -> Option<Response> {
+		let max_staleness = Duration::from_secs(self.config.max_staleness_secs);
+		let cache = self.cache.lock().expect("response cache lock poisoned");
+		let cached = cache.get(key).filter(|cached| cached.cached_at.elapsed() <= max_staleness)?;
+		let mut response = Response::new(boxed(Full::from(cached.body.clone())));
+		*response.status_mut() = cached.status;
+		*response.headers_mut() = cached.headers.clone();
+		let headers = response.headers_mut();
+		headers.insert(DEGRADED_HEADER, HeaderValue::from_static("true"));
+		headers.insert(AGE, HeaderValue::from(cached.cached_at.elapsed().as_secs()));
+		Some(response)
+	}
+
+	/// Caches a successful response of known size, evicting the oldest response when the cache is
+	/// full, and returns it.
+	async fn store(&self, key: String, response: Response) -> Response {
+		let cacheable = response.status() == StatusCode::OK &&
+			response
+				.body()
+				.size_hint()
+				.exact()
+				.is_some_and(|size| size <= MAX_CACHED_BODY_BYTES);
+		if !cacheable || self.config.cache_entries == 0 {
+			return response
+		}
+		let (parts, body) = response.into_parts();
+		let body = match hyper::body::to_bytes(body).await {
+			Ok(body) => body,
+			Err(err) => {
+				error!("Failed to read the response of {}: {:?}", key, err);
+				let mut response = Response::new(boxed(Full::from("Internal server error")));
+				*response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+				return response
+			},
+		};
+		let mut cache = self.cache.lock().expect("response cache lock poisoned");
+		if cache.len() >= self.config.cache_entries && !cache.contains_key(&key) {
+			let oldest = cache
+				.iter()
+				.min_by_key(|(_, cached)| cached.cached_at)
+				.map(|(key, _)| key.clone());
+			if let Some(oldest) = oldest {
+				cache.remove(&oldest);
+			}
+		}
+		let cached = CachedResponse {
+			status: parts.status,
+			headers: parts.headers.clone(),
+			body: body.clone(),
+			cached_at: Instant::now(),
+		};
+		cache.insert(key, cached);
+		Response::from_parts(parts, boxed(Full::from(body)))
+	}
+}
+
+/// Handler for the `/stats/degraded` endpoint, reporting the state of the ingestion as last checked
+/// by the degraded mode.
+///
+/// # Arguments
+///
+/// * `state` - The `AppState`, holding the degraded mode if one is configured.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` with the `DegradedStatus`, `null` when the degraded
+/// mode is not configured.
+pub async fn get_degraded_status_handler(state: AppState) -> impl IntoResponse {
+	Json(state.degraded.map(|degraded| degraded.status()))
+}
+
+/// Returns the key a read is cached under, `None` for the requests never served from the cache.
+fn cache_key<B>(request: &Request<B>) -> Option<String> {
+	let waits = !matches!(requested_slot(request), Ok(None));
+	if request.method() != Method::GET || request.headers().contains_key(UPGRADE) || waits {
+		return None
+	}
+	let accept = request.headers().get(ACCEPT).and_then(|value| value.to_str().ok());
+	// Responses are cached per API key, whose scope may differ
+	let key = request.extensions().get::<ApiScope>().and_then(ApiScope::key_name);
+	Some(format!("{} {} {}", request.uri(), accept.unwrap_or_default(), key.unwrap_or_default()))
+}
+
+/// Checks the writer queue and the age of the last stored block every second, and publishes the
+/// state of the ingestion, until the last receiver is dropped.
+async fn run_degraded_monitor(
+	pool: DbPool,
+	writer: Option<DbWriter>,
+	config: Arc<DegradedModeConfig>,
+	status: watch::Sender<DegradedStatus>,
+) {
+	let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+	while !status.is_closed() {
+		ticker.tick().await;
+		let latest =
+			with_connection(&pool, |conn| blocks::get_blocks_around_slot(conn, i64::MAX as u64))
+				.await;
+		let latest_time = match latest {
+			Ok(Ok((latest, _))) => latest.map(|(_, block_time)| block_time),
+			Ok(Err(err)) => {
+				error!("Failed to read the last stored block: {:?}", err);
+				continue
+			},
+			Err(err) => {
+				error!("Failed to check out a database connection: {:?}", err);
+				continue
+			},
+		};
+		let now = chrono::Utc::now().timestamp();
+		let queued_writes = writer.as_ref().map(DbWriter::queued_commands);
+		let lag_secs = latest_time.map(|block_time| now.saturating_sub(block_time).max(0) as u64);
+		let degraded = queued_writes.is_some_and(|queued| queued > config.max_queued_writes) ||
+			lag_secs.is_some_and(|lag| lag > config.max_lag_secs);
+		status.send_modify(|current| {
+			if degraded && !current.degraded {
+				warn!(
+					"Ingestion behind ({:?} queued writes, last block {:?}s old), serving cached reads",
+					queued_writes, lag_secs
+				);
+			} else if !degraded && current.degraded {
+				info!("Ingestion caught up, serving fresh reads");
+			}
+			*current = DegradedStatus { degraded, queued_writes, lag_secs, checked_at: Some(now) };
+		});
+	}
+}
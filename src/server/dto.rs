@@ -0,0 +1,211 @@
+//! Response bodies of the public API, separate from the database records.
+//!
+//! The handlers of the transaction, account, transfer and block endpoints convert the records they
+//! read into these structs before encoding them, so a storage change (a new column, a renamed
+//! field) only touches the conversions below and the JSON contract stays as documented until it is
+//! changed on purpose.
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	db::{
+		account_info::{AccountInfoRecord, AccountKind},
+		blocks::BlockRecord,
+		AccountRecord, TransactionRecord, TransferRecord,
+	},
+	types::{TransactionFailure, TransferKind},
+};
+
+/// A stored transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionResponse {
+	pub transaction_id: String,
+	/// The block time, 0 when the node did not report it.
+	pub timestamp: i64,
+	/// The slot of the transaction.
+	pub block_height: u64,
+	/// The transaction as stored under the `raw_transactions` policy of the service.
+	pub raw_transaction: String,
+	pub fee: u64,
+	pub fee_payer: String,
+	pub compute_unit_limit: Option<u32>,
+	pub compute_unit_price: Option<u64>,
+	/// `rpc` when the transaction was missing from the database and fetched from the RPC node.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub source: Option<TransactionSource>,
+}
+
+/// Where a transaction not read from the database came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionSource {
+	/// Fetched from the RPC node with `getTransaction`, then stored.
+	Rpc,
+}
+
+impl TransactionSource {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			TransactionSource::Rpc => "rpc",
+		}
+	}
+}
+
+impl From<TransactionRecord> for TransactionResponse {
+	fn from(record: TransactionRecord) -> Self {
+		Self {
+			transaction_id: record.transaction_id,
+			timestamp: record.timestamp,
+			block_height: record.block_height,
+			raw_transaction: record.raw_transaction,
+			fee: record.fee,
+			fee_payer: record.fee_payer,
+			compute_unit_limit: record.compute_unit_limit,
+			compute_unit_price: record.compute_unit_price,
+			source: None,
+		}
+	}
+}
+
+/// An account, with its balance and transfer totals.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountResponse {
+	pub account_id: String,
+	pub estimated_balance: u64,
+	pub related_transactions: Vec<String>,
+	pub total_received: u128,
+	pub total_sent: u128,
+	pub first_seen_slot: Option<u64>,
+	pub last_active_slot: Option<u64>,
+	pub balance_slot: Option<u64>,
+	pub balance_transaction_index: Option<u32>,
+}
+
+impl From<AccountRecord> for AccountResponse {
+	fn from(record: AccountRecord) -> Self {
+		Self {
+			account_id: record.account_id,
+			estimated_balance: record.estimated_balance,
+			related_transactions: record.related_transactions,
+			total_received: record.total_received,
+			total_sent: record.total_sent,
+			first_seen_slot: record.first_seen_slot,
+			last_active_slot: record.last_active_slot,
+			balance_slot: record.balance_slot,
+			balance_transaction_index: record.balance_transaction_index,
+		}
+	}
+}
+
+/// The on-chain metadata of an account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountInfoResponse {
+	pub kind: AccountKind,
+	pub owner: Option<String>,
+	pub executable: bool,
+	pub data_length: Option<u64>,
+	pub fetched_slot: u64,
+	pub fetched_at: i64,
+}
+
+impl From<AccountInfoRecord> for AccountInfoResponse {
+	fn from(record: AccountInfoRecord) -> Self {
+		Self {
+			kind: record.kind,
+			owner: record.owner,
+			executable: record.executable,
+			data_length: record.data_length,
+			fetched_slot: record.fetched_slot,
+			fetched_at: record.fetched_at,
+		}
+	}
+}
+
+/// A lamport movement of a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferResponse {
+	pub transaction_id: String,
+	pub transfer_index: u32,
+	pub sender: String,
+	pub receiver: String,
+	pub amount: u64,
+	pub timestamp: i64,
+	pub block_height: u64,
+	pub kind: TransferKind,
+	pub sender_owner: Option<String>,
+	pub receiver_owner: Option<String>,
+	pub invoked_by: Option<String>,
+	/// The tags the tagging rules gave the transfer when it was stored.
+	pub tags: Vec<String>,
+}
+
+impl From<TransferRecord> for TransferResponse {
+	fn from(record: TransferRecord) -> Self {
+		Self {
+			transaction_id: record.transaction_id,
+			transfer_index: record.transfer_index,
+			sender: record.sender,
+			receiver: record.receiver,
+			amount: record.amount,
+			timestamp: record.timestamp,
+			block_height: record.block_height,
+			kind: record.kind,
+			sender_owner: record.sender_owner,
+			receiver_owner: record.receiver_owner,
+			invoked_by: record.invoked_by,
+			tags: record.tags,
+		}
+	}
+}
+
+/// A newly stored transaction, with its transfers, as streamed by `/ws/transactions`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamedTransaction {
+	pub transaction_id: String,
+	/// The slot of the transaction.
+	pub block_height: u64,
+	/// The block time, `None` when the node did not report it.
+	pub block_time: Option<i64>,
+	pub fee: u64,
+	pub fee_payer: String,
+	/// The error the transaction failed with, `None` if it succeeded.
+	pub error: Option<TransactionFailure>,
+	pub transfers: Vec<TransferResponse>,
+}
+
+/// A stored block, with the signatures of its stored transactions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockResponse {
+	pub slot: u64,
+	/// The block time, `None` when the node did not report it.
+	pub block_time: Option<i64>,
+	/// The header fields are `None` for the blocks stored before headers were kept.
+	pub blockhash: Option<String>,
+	pub previous_blockhash: Option<String>,
+	pub parent_slot: Option<u64>,
+	pub block_height: Option<u64>,
+	/// The number of transactions of the block, the ones filtered out of storage included.
+	pub transaction_count: Option<u32>,
+	/// The number of transactions stored at the slot.
+	pub stored_transaction_count: u64,
+	/// A page of the signatures of the stored transactions, in block order; empty in summary mode.
+	pub signatures: Vec<String>,
+	/// The cursor of the next page of signatures, `None` once the listing is exhausted.
+	pub next_cursor: Option<String>,
+}
+
+impl BlockResponse {
+	pub fn new(record: BlockRecord, stored_transaction_count: u64) -> Self {
+		Self {
+			slot: record.slot,
+			block_time: record.block_time,
+			blockhash: record.blockhash,
+			previous_blockhash: record.previous_blockhash,
+			parent_slot: record.parent_slot,
+			block_height: record.block_height,
+			transaction_count: record.transaction_count,
+			stored_transaction_count,
+			signatures: Vec::new(),
+			next_cursor: None,
+		}
+	}
+}
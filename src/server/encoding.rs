@@ -0,0 +1,81 @@
+//! Content negotiation between JSON and the binary wire formats of the REST endpoints.
+use axum::{
+	body::{boxed, Full},
+	http::{header, HeaderMap, HeaderValue, StatusCode},
+	response::{IntoResponse, Json, Response},
+};
+use prost::Message;
+use serde::Serialize;
+use tracing::error;
+
+use super::{build_error_response, proto::ToProto};
+
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+/// The wire format of a response body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireFormat {
+	Json,
+	Protobuf,
+	MessagePack,
+}
+
+impl WireFormat {
+	/// Picks the wire format requested by the `Accept` header.
+	///
+	/// The first supported media type listed wins; JSON is used when the header is missing or
+	/// lists no binary format.
+	pub fn from_headers(headers: &HeaderMap) -> Self {
+		let accept = headers.get(header::ACCEPT).and_then(|value| value.to_str().ok());
+		accept
+			.into_iter()
+			.flat_map(|accept| accept.split(','))
+			.map(|media_type| media_type.split(';').next().unwrap_or_default().trim())
+			.find_map(|media_type| match media_type {
+				"application/json" => Some(WireFormat::Json),
+				PROTOBUF_CONTENT_TYPE | "application/protobuf" => Some(WireFormat::Protobuf),
+				MSGPACK_CONTENT_TYPE | "application/x-msgpack" => Some(WireFormat::MessagePack),
+				_ => None,
+			})
+			.unwrap_or(WireFormat::Json)
+	}
+}
+
+/// Encodes a response body in the wire format requested by the `Accept` header.
+///
+/// # Arguments
+///
+/// * `headers` - The headers of the request.
+/// * `value` - The response body.
+///
+/// # Returns
+///
+/// This function returns the encoded `Response`, or an `INTERNAL_SERVER_ERROR` response if the
+/// body cannot be encoded.
+pub fn negotiate<T: Serialize + ToProto>(headers: &HeaderMap, value: T) -> Response {
+	let (content_type, body) = match WireFormat::from_headers(headers) {
+		WireFormat::Json => return with_vary(Json(value).into_response()),
+		WireFormat::Protobuf => (PROTOBUF_CONTENT_TYPE, value.to_proto().encode_to_vec()),
+		WireFormat::MessagePack => match rmp_serde::to_vec_named(&value) {
+			Ok(body) => (MSGPACK_CONTENT_TYPE, body),
+			Err(err) => {
+				error!("MessagePack encoding error: {:?}", err);
+				return build_error_response(
+					StatusCode::INTERNAL_SERVER_ERROR,
+					"Internal server error",
+				)
+			},
+		},
+	};
+	let mut response = Response::new(boxed(Full::from(body)));
+	response
+		.headers_mut()
+		.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+	with_vary(response)
+}
+
+pub(crate) fn with_vary(mut response: Response) -> Response {
+	response.headers_mut().insert(header::VARY, HeaderValue::from_static("accept"));
+	response
+}
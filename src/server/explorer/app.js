@@ -0,0 +1,136 @@
+// Hash based routing over the JSON endpoints:
+//   #/                    recent blocks
+//   #/tx/<signature>      transaction lookup
+//   #/account/<pubkey>    account history, paged with `before`
+"use strict";
+
+const content = document.getElementById("content");
+const PAGE_SIZE = 25;
+
+function escape(value) {
+	return String(value ?? "").replace(/[&<>"']/g, (c) => `&#${c.charCodeAt(0)};`);
+}
+
+function formatTime(timestamp) {
+	return timestamp ? new Date(timestamp * 1000).toISOString().replace(".000Z", "Z") : "";
+}
+
+function txLink(signature) {
+	return `<a href="#/tx/${encodeURIComponent(signature)}">${escape(signature)}</a>`;
+}
+
+function accountLink(pubkey) {
+	return `<a href="#/account/${encodeURIComponent(pubkey)}">${escape(pubkey)}</a>`;
+}
+
+// Fetches a JSON endpoint, resolving to `null` on 404.
+async function fetchJson(path) {
+	const response = await fetch(path, { headers: { Accept: "application/json" } });
+	if (response.status === 404) {
+		return null;
+	}
+	if (!response.ok) {
+		throw new Error(`${response.status}: ${await response.text()}`);
+	}
+	return response.json();
+}
+
+function table(headers, rows) {
+	const head = headers.map((header) => `<th>${escape(header)}</th>`).join("");
+	const body = rows.map((row) => `<tr>${row.map((cell) => `<td>${cell}</td>`).join("")}</tr>`);
+	return `<table><thead><tr>${head}</tr></thead><tbody>${body.join("")}</tbody></table>`;
+}
+
+async function showBlocks() {
+	const blocks = await fetchJson("/blocks/recent?limit=25");
+	content.innerHTML = `<h2>Recent blocks</h2>` + table(
+		["Slot", "Time", "Transactions", "Fees (lamports)"],
+		blocks.map((block) => [
+			escape(block.slot),
+			escape(formatTime(block.block_time)),
+			escape(block.transaction_count),
+			escape(block.total_fee),
+		]),
+	);
+}
+
+async function showTransaction(signature) {
+	const transaction = await fetchJson(`/transaction?tx-id=${encodeURIComponent(signature)}`);
+	if (!transaction) {
+		content.innerHTML = `<p class="error">Transaction ${escape(signature)} not found</p>`;
+		return;
+	}
+	let raw = transaction.raw_transaction;
+	try {
+		raw = JSON.stringify(JSON.parse(raw), null, 2);
+	} catch (_) {
+		// Keep the raw transaction as stored
+	}
+	content.innerHTML = `<h2>Transaction</h2>` + table(["Field", "Value"], [
+		["Signature", escape(transaction.transaction_id)],
+		["Slot", escape(transaction.block_height)],
+		["Time", escape(formatTime(transaction.timestamp))],
+		["Fee payer", accountLink(transaction.fee_payer)],
+		["Fee (lamports)", escape(transaction.fee)],
+		["Compute unit limit", escape(transaction.compute_unit_limit)],
+		["Compute unit price", escape(transaction.compute_unit_price)],
+	]) + `<h3>Raw transaction</h3><pre>${escape(raw)}</pre>`;
+}
+
+async function showAccount(pubkey, before) {
+	const path = encodeURIComponent(pubkey);
+	const query = `limit=${PAGE_SIZE}` + (before ? `&before=${encodeURIComponent(before)}` : "");
+	const [balance, label, signatures] = await Promise.all([
+		fetchJson(`/accounts/${path}/balance`),
+		fetchJson(`/labels/${path}`).catch(() => null),
+		fetchJson(`/account/${path}/transactions?${query}`),
+	]);
+
+	let html = `<h2>Account</h2>` + table(["Field", "Value"], [
+		["Address", escape(pubkey)],
+		["Label", escape(label ? label.label : "")],
+		["Balance (lamports)", escape(balance ? balance.balance : "unknown")],
+		["Last change slot", escape(balance ? balance.last_change_slot : "")],
+	]);
+	html += `<h3>Transactions</h3>` + table(
+		["Signature", "Slot", "Time", "Error"],
+		signatures.map((info) => [
+			txLink(info.signature),
+			escape(info.slot),
+			escape(formatTime(info.blockTime)),
+			escape(info.err),
+		]),
+	);
+	if (signatures.length === PAGE_SIZE) {
+		const last = signatures[signatures.length - 1].signature;
+		html += `<p class="pager"><a href="#/account/${path}/before/${encodeURIComponent(last)}">Older transactions</a></p>`;
+	}
+	content.innerHTML = html;
+}
+
+async function route() {
+	const parts = location.hash.replace(/^#\/?/, "").split("/").map(decodeURIComponent);
+	try {
+		if (parts[0] === "tx" && parts[1]) {
+			await showTransaction(parts[1]);
+		} else if (parts[0] === "account" && parts[1]) {
+			await showAccount(parts[1], parts[2] === "before" ? parts[3] : null);
+		} else {
+			await showBlocks();
+		}
+	} catch (err) {
+		content.innerHTML = `<p class="error">${escape(err.message)}</p>`;
+	}
+}
+
+// Signatures are 64 bytes and addresses 32 bytes, so the base58 length tells them apart.
+document.getElementById("search").addEventListener("submit", (event) => {
+	event.preventDefault();
+	const query = document.getElementById("query").value.trim();
+	if (query) {
+		location.hash = query.length > 50 ? `#/tx/${query}` : `#/account/${query}`;
+	}
+});
+
+window.addEventListener("hashchange", route);
+route();
@@ -0,0 +1,24 @@
+//! A minimal built-in explorer, served as static files over the JSON endpoints.
+//!
+//! The pages are plain HTML/JS compiled into the binary, so the service can be browsed at
+//! `/explorer` without building or deploying a separate frontend.
+use axum::{http::header, response::IntoResponse};
+
+const INDEX_HTML: &str = include_str!("index.html");
+const APP_JS: &str = include_str!("app.js");
+const STYLE_CSS: &str = include_str!("style.css");
+
+/// Handler serving the explorer page.
+pub async fn index_handler() -> impl IntoResponse {
+	([(header::CONTENT_TYPE, "text/html; charset=utf-8")], INDEX_HTML)
+}
+
+/// Handler serving the explorer script.
+pub async fn script_handler() -> impl IntoResponse {
+	([(header::CONTENT_TYPE, "application/javascript; charset=utf-8")], APP_JS)
+}
+
+/// Handler serving the explorer stylesheet.
+pub async fn style_handler() -> impl IntoResponse {
+	([(header::CONTENT_TYPE, "text/css; charset=utf-8")], STYLE_CSS)
+}
@@ -0,0 +1,361 @@
+//! Handlers for managing account groups and querying them as a whole.
+use axum::{
+	extract::{Path, Query},
+	http::StatusCode,
+	response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use solana_program::pubkey::Pubkey;
+use std::{error::Error, str::FromStr};
+use tracing::error;
+
+use super::{
+	auth::ApiScope, build_error_response, state::AppState, stats::window_start, units,
+	AccountTransactionsQuery, BalanceQuery, DbConn, BALANCE_AMOUNTS, MAX_SIGNATURES_LIMIT,
+};
+use crate::db::{
+	self,
+	groups::{self, GroupRecord},
+	scopes::OutsideScope,
+};
+
+/// Maximum number of members of a group.
+const MAX_GROUP_MEMBERS: usize = 100;
+
+/// Body of a group creation or update.
+#[derive(Deserialize)]
+pub struct GroupRequest {
+	members: Vec<String>,
+}
+
+/// Query parameters for retrieving the statistics of a group.
+#[derive(Deserialize)]
+pub struct GroupStatsQuery {
+	window: Option<String>,
+}
+
+/// The balance of a member of a group.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberBalance {
+	pub account_id: String,
+	/// The balance of the member, `None` if no balance change was recorded.
+	pub balance: Option<u64>,
+}
+
+/// The combined lamport balance of the members of a group at a slot.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GroupBalance {
+	pub name: String,
+	/// The requested slot, or `None` for the latest known balances.
+	pub at_slot: Option<u64>,
+	pub balance: u128,
+	pub members: Vec<MemberBalance>,
+}
+
+/// Handler for listing the groups along with their members.
+///
+/// # Arguments
+///
+/// * `scope` - The `ApiScope` of the request, restricting the listed groups to its own.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<GroupRecord>>` with the groups, ordered by name.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn list_groups_handler(scope: ApiScope, conn: DbConn) -> impl IntoResponse {
+	match conn.interact(groups::list_groups).await {
+		Ok(mut groups) => {
+			groups.retain(|group| scope.includes_group(&group.name));
+			Json(groups).into_response()
+		},
+		Err(err) => internal_error(err),
+	}
+}
+
+/// Handler for retrieving a group along with its members.
+///
+/// # Arguments
+///
+/// * `name` - A `Path` extractor containing the name of the group.
+/// * `scope` - The `ApiScope` of the request, which must include the group.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<GroupRecord>` if the group exists.
+/// - An error response with a `NOT_FOUND` status if the group does not exist.
+/// - An error response with a `FORBIDDEN` status if the group is outside the scope of the key.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_group_handler(
+	Path(name): Path<String>,
+	scope: ApiScope,
+	conn: DbConn,
+) -> impl IntoResponse {
+	if !scope.includes_group(&name) {
+		return group_outside_scope()
+	}
+	match conn.interact(move |conn| groups::get_group(conn, &name)).await {
+		Ok(Some(group)) => Json(group).into_response(),
+		Ok(None) => group_not_found(),
+		Err(err) => internal_error(err),
+	}
+}
+
+/// Handler for creating a group or replacing its members.
+///
+/// # Arguments
+///
+/// * `name` - A `Path` extractor containing the name of the group.
+/// * `scope` - The `ApiScope` of the request, which must see every account.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+/// * `request` - A `Json` extractor containing the `GroupRequest` with the members of the group.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<GroupRecord>` with a `CREATED` status if the group was created, `OK` if it was replaced.
+/// - An error response with a `BAD_REQUEST` status if a member is not a valid pubkey or if there
+///   are no or too many members.
+/// - An error response with a `FORBIDDEN` status for an API key restricted to some accounts, as the
+///   groups define the scopes.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database error.
+pub async fn put_group_handler(
+	Path(name): Path<String>,
+	scope: ApiScope,
+	conn: DbConn,
+	Json(request): Json<GroupRequest>,
+) -> impl IntoResponse {
+	if !scope.is_unrestricted() {
+		return group_outside_scope()
+	}
+	let mut members = request.members;
+	members.sort();
+	members.dedup();
+	if members.is_empty() || members.len() > MAX_GROUP_MEMBERS {
+		return build_error_response(StatusCode::BAD_REQUEST, "A group has 1 to 100 members")
+			.into_response()
+	}
+	if let Some(member) = members.iter().find(|member| Pubkey::from_str(member).is_err()) {
+		return build_error_response(
+			StatusCode::BAD_REQUEST,
+			&format!("Invalid member pubkey: {}", member),
+		)
+		.into_response()
+	}
+
+	let group = GroupRecord { name, members };
+	match conn
+		.interact(move |conn| groups::put_group(conn, &group).map(|created| (created, group)))
+		.await
+	{
+		Ok((true, group)) => (StatusCode::CREATED, Json(group)).into_response(),
+		Ok((false, group)) => Json(group).into_response(),
+		Err(err) => internal_error(err),
+	}
+}
+
+/// Handler for deleting a group.
+///
+/// # Arguments
+///
+/// * `name` - A `Path` extractor containing the name of the group.
+/// * `scope` - The `ApiScope` of the request, which must see every account.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - A `NO_CONTENT` response if the group was deleted.
+/// - An error response with a `NOT_FOUND` status if the group does not exist.
+/// - An error response with a `FORBIDDEN` status for an API key restricted to some accounts.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database error.
+pub async fn delete_group_handler(
+	Path(name): Path<String>,
+	scope: ApiScope,
+	conn: DbConn,
+) -> impl IntoResponse {
+	if !scope.is_unrestricted() {
+		return group_outside_scope()
+	}
+	match conn.interact(move |conn| groups::delete_group(conn, &name)).await {
+		Ok(true) => StatusCode::NO_CONTENT.into_response(),
+		Ok(false) => group_not_found(),
+		Err(err) => internal_error(err),
+	}
+}
+
+/// Handler for retrieving the transaction signatures involving any member of a group, newest first.
+///
+/// Pagination follows `GET /account/:id/transactions`, and transactions involving several members
+/// are listed once.
+///
+/// # Arguments
+///
+/// * `name` - A `Path` extractor containing the name of the group.
+/// * `params` - A `Query` extractor containing the `AccountTransactionsQuery`.
+/// * `scope` - The `ApiScope` of the request, which must include the group.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<SignatureInfo>>` with the signatures of the page.
+/// - An error response with a `BAD_REQUEST` status if the limit is out of range.
+/// - An error response with a `NOT_FOUND` status if the group does not exist.
+/// - An error response with a `FORBIDDEN` status if the group is outside the scope of the key.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_group_transactions_handler(
+	Path(name): Path<String>,
+	Query(params): Query<AccountTransactionsQuery>,
+	scope: ApiScope,
+	conn: DbConn,
+) -> impl IntoResponse {
+	if !scope.includes_group(&name) {
+		return group_outside_scope()
+	}
+	let limit = params.limit.unwrap_or(MAX_SIGNATURES_LIMIT);
+	if limit == 0 || limit > MAX_SIGNATURES_LIMIT {
+		return build_error_response(StatusCode::BAD_REQUEST, "Invalid limit; max 1000")
+			.into_response()
+	}
+
+	let signatures = conn
+		.interact(move |conn| -> Result<_, Box<dyn Error + Send + Sync>> {
+			if groups::get_group(conn, &name)?.is_none() {
+				return Ok(None)
+			}
+			groups::get_group_signatures(
+				conn,
+				&name,
+				params.before.as_deref(),
+				params.until.as_deref(),
+				&params.filter(),
+				limit,
+			)
+			.map(Some)
+		})
+		.await;
+	match signatures {
+		Ok(Some(signatures)) => Json(signatures).into_response(),
+		Ok(None) => group_not_found(),
+		Err(err) => internal_error(err),
+	}
+}
+
+/// Handler for retrieving the combined lamport balance of the members of a group at a past slot.
+///
+/// Each member balance is reconstructed as in `GET /accounts/:pubkey/balance`; members without
+/// recorded balance changes do not count towards the total. The balances are in lamports, or in SOL
+/// with `unit=sol`.
+///
+/// # Arguments
+///
+/// * `name` - A `Path` extractor containing the name of the group.
+/// * `params` - A `Query` extractor containing the `BalanceQuery`.
+/// * `state` - The `AppState`, holding the default amount unit.
+/// * `scope` - The `ApiScope` of the request, which must include the group.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<GroupBalance>` with the total and the balance of each member.
+/// - An error response with a `NOT_FOUND` status if the group does not exist.
+/// - An error response with a `FORBIDDEN` status if the group is outside the scope of the key.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_group_balance_handler(
+	Path(name): Path<String>,
+	Query(params): Query<BalanceQuery>,
+	state: AppState,
+	scope: ApiScope,
+	conn: DbConn,
+) -> impl IntoResponse {
+	if !scope.includes_group(&name) {
+		return group_outside_scope()
+	}
+	let unit = params.unit.unwrap_or(state.amount_unit);
+	let group_balance = conn
+		.interact(move |conn| -> Result<_, Box<dyn Error + Send + Sync>> {
+			let Some(group) = groups::get_group(conn, &name)? else { return Ok(None) };
+
+			let mut balance = 0u128;
+			let mut members = Vec::with_capacity(group.members.len());
+			for account_id in group.members {
+				let member_balance = db::get_balance_change_at(conn, &account_id, params.at_slot)?
+					.map(|change| change.post_balance);
+				balance += member_balance.unwrap_or_default() as u128;
+				members.push(MemberBalance { account_id, balance: member_balance });
+			}
+			let group_balance = GroupBalance { name, at_slot: params.at_slot, balance, members };
+			Ok(Some(units::with_unit(group_balance, unit, BALANCE_AMOUNTS)?))
+		})
+		.await;
+	match group_balance {
+		Ok(Some(group_balance)) => Json(group_balance).into_response(),
+		Ok(None) => group_not_found(),
+		Err(err) => internal_error(err),
+	}
+}
+
+/// Handler for retrieving the fee and failure statistics of the transactions paid by the members of
+/// a group within a time window.
+///
+/// # Arguments
+///
+/// * `name` - A `Path` extractor containing the name of the group.
+/// * `params` - A `Query` extractor containing the `GroupStatsQuery`, the window defaults to `24h`.
+/// * `scope` - The `ApiScope` of the request, which must include the group.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<GroupStats>` with the statistics of the group.
+/// - An error response with a `BAD_REQUEST` status if the window is invalid.
+/// - An error response with a `NOT_FOUND` status if the group does not exist.
+/// - An error response with a `FORBIDDEN` status if the group is outside the scope of the key.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_group_stats_handler(
+	Path(name): Path<String>,
+	Query(params): Query<GroupStatsQuery>,
+	scope: ApiScope,
+	conn: DbConn,
+) -> impl IntoResponse {
+	if !scope.includes_group(&name) {
+		return group_outside_scope()
+	}
+	let since = match window_start(params.window.as_deref()) {
+		Ok(since) => since,
+		Err(err) => return build_error_response(StatusCode::BAD_REQUEST, &err).into_response(),
+	};
+
+	let stats = conn
+		.interact(move |conn| -> Result<_, Box<dyn Error + Send + Sync>> {
+			if groups::get_group(conn, &name)?.is_none() {
+				return Ok(None)
+			}
+			groups::get_group_stats(conn, &name, since).map(Some)
+		})
+		.await;
+	match stats {
+		Ok(Some(stats)) => Json(stats).into_response(),
+		Ok(None) => group_not_found(),
+		Err(err) => internal_error(err),
+	}
+}
+
+fn group_not_found() -> Response {
+	build_error_response(StatusCode::NOT_FOUND, "Group not found").into_response()
+}
+
+fn group_outside_scope() -> Response {
+	build_error_response(StatusCode::FORBIDDEN, &OutsideScope.to_string()).into_response()
+}
+
+fn internal_error(err: Box<dyn Error + Send + Sync>) -> Response {
+	error!("Database query error: {:?}", err);
+	build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+}
@@ -0,0 +1,197 @@
+//! `Idempotency-Key` support for the mutating endpoints.
+//!
+//! A client retrying a request with the same key gets the recorded response instead of running the
+//! request again, so a retried call does not start a duplicate job. The key is bound to a
+//! fingerprint of the request, and reusing it for a different request is rejected.
+use axum::{
+	body::{self, Body, Bytes, HttpBody},
+	http::{header::CONTENT_TYPE, HeaderValue, Request, StatusCode},
+	middleware::Next,
+	response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use super::{build_error_response, state::AppState};
+use crate::db::{
+	idempotency::{self, Reservation, StoredResponse},
+	pool::{self, DbPool, PooledConnection},
+};
+
+/// Header carrying the idempotency key of a request.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Header set on the responses replayed from a previous request.
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Maximum length of an idempotency key.
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Middleware replaying the recorded response of a request carrying a known `Idempotency-Key`.
+///
+/// Requests without the header run as usual. Otherwise the key is reserved before the request runs
+/// and the response is recorded once it completes; server errors release the key so the request can
+/// be retried. The route needs the `AppState` as an `Extension`.
+///
+/// # Returns
+///
+/// This function returns the response of the handler, or:
+/// - The recorded response, with an `Idempotent-Replayed` header, for a retried request.
+/// - An error response with a `BAD_REQUEST` status if the key is empty or too long.
+/// - An error response with a `CONFLICT` status if the same request is still running.
+/// - An error response with an `UNPROCESSABLE_ENTITY` status if the key was used for a different
+///   request.
+/// - An error response with a `SERVICE_UNAVAILABLE` status if no database connection is available.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database error.
+pub async fn idempotency(request: Request<Body>, next: Next<Body>) -> Response {
+	let Some(key) = request.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+		return next.run(request).await
+	};
+	let key = match key.to_str() {
+		Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => key.to_string(),
+		_ =>
+			return build_error_response(StatusCode::BAD_REQUEST, "Invalid Idempotency-Key")
+				.into_response(),
+	};
+	let Some(pool) = request.extensions().get::<AppState>().map(|state| state.db.clone()) else {
+		error!("Idempotency middleware installed without the application state");
+		return internal_error()
+	};
+
+	let (parts, request_body) = request.into_parts();
+	let request_body = match collect_body(request_body).await {
+		Ok(bytes) => bytes,
+		Err(_) =>
+			return build_error_response(StatusCode::BAD_REQUEST, "Failed to read the request body")
+				.into_response(),
+	};
+	let mut hasher = Sha256::new();
+	hasher.update(parts.method.as_str());
+	hasher.update(b" ");
+	hasher.update(parts.uri.to_string());
+	hasher.update(b"\n");
+	hasher.update(&request_body);
+	let fingerprint: String =
+		hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+
+	// The connection is given back before the handler runs, which checks out its own
+	let reservation = match checkout(&pool).await {
+		Ok(conn) => {
+			let key = key.clone();
+			let now = chrono::Utc::now().timestamp();
+			pool::interact(conn, move |conn| {
+				idempotency::reserve_key(conn, &key, &fingerprint, now)
+			})
+			.await
+		},
+		Err(response) => return response,
+	};
+	match reservation {
+		Ok(Reservation::Reserved) => {},
+		Ok(Reservation::Completed(stored)) => return replay(stored),
+		Ok(Reservation::InProgress) =>
+			return build_error_response(
+				StatusCode::CONFLICT,
+				"A request with this Idempotency-Key is still running",
+			)
+			.into_response(),
+		Ok(Reservation::Mismatch) =>
+			return build_error_response(
+				StatusCode::UNPROCESSABLE_ENTITY,
+				"The Idempotency-Key was used for a different request",
+			)
+			.into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			return internal_error()
+		},
+	}
+
+	let response = next.run(Request::from_parts(parts, Body::from(request_body))).await;
+	let (parts, response_body) = response.into_parts();
+	let response_body = match collect_body(response_body).await {
+		Ok(bytes) => bytes,
+		Err(err) => {
+			error!("Failed to buffer the response of idempotent request {}: {:?}", key, err);
+			release(&pool, &key).await;
+			return internal_error()
+		},
+	};
+
+	if parts.status.is_server_error() {
+		release(&pool, &key).await;
+	} else {
+		let stored = StoredResponse {
+			status: parts.status.as_u16(),
+			content_type: parts
+				.headers
+				.get(CONTENT_TYPE)
+				.and_then(|value| value.to_str().ok())
+				.map(str::to_string),
+			body: response_body.to_vec(),
+		};
+		let completed = {
+			let key = key.clone();
+			pool::with_connection(&pool, move |conn| idempotency::complete_key(conn, &key, &stored))
+				.await
+		};
+		match completed {
+			Ok(Ok(())) => {},
+			Ok(Err(err)) =>
+				error!("Failed to record the response of idempotent request {}: {:?}", key, err),
+			Err(err) =>
+				error!("Failed to record the response of idempotent request {}: {:?}", key, err),
+		}
+	}
+	Response::from_parts(parts, body::boxed(body::Full::from(response_body)))
+}
+
+/// Rebuilds a recorded response.
+fn replay(stored: StoredResponse) -> Response {
+	let mut response = Response::new(body::boxed(body::Full::from(stored.body)));
+	*response.status_mut() = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+	if let Some(content_type) =
+		stored.content_type.and_then(|value| HeaderValue::from_str(&value).ok())
+	{
+		response.headers_mut().insert(CONTENT_TYPE, content_type);
+	}
+	response
+		.headers_mut()
+		.insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+	response
+}
+
+async fn release(pool: &DbPool, key: &str) {
+	let released = {
+		let key = key.to_string();
+		pool::with_connection(pool, move |conn| idempotency::release_key(conn, &key)).await
+	};
+	match released {
+		Ok(Ok(())) => {},
+		Ok(Err(err)) => error!("Failed to release idempotency key {}: {:?}", key, err),
+		Err(err) => error!("Failed to release idempotency key {}: {:?}", key, err),
+	}
+}
+
+async fn checkout(pool: &DbPool) -> Result<PooledConnection, Response> {
+	pool.get_owned().await.map_err(|err| {
+		error!("Failed to check out a database connection: {:?}", err);
+		build_error_response(StatusCode::SERVICE_UNAVAILABLE, "Database unavailable")
+			.into_response()
+	})
+}
+
+pub(crate) async fn collect_body<B>(mut body: B) -> Result<Bytes, B::Error>
+where
+	B: HttpBody<Data = Bytes> + Unpin,
+{
+	let mut bytes = Vec::new();
+	while let Some(chunk) = body.data().await {
+		bytes.extend_from_slice(&chunk?);
+	}
+	Ok(Bytes::from(bytes))
+}
+
+fn internal_error() -> Response {
+	build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+}
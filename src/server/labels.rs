@@ -0,0 +1,122 @@
+//! Handlers for the account label endpoints.
+use axum::{
+	body::Bytes,
+	extract::Path,
+	http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+	response::{IntoResponse, Json},
+};
+use serde::Deserialize;
+use tracing::error;
+
+use super::{auth::ApiScope, build_error_response, query_error_response, DbConn};
+use crate::db::labels::{self, LabelRecord};
+
+/// A row of a CSV label import, with tags separated by `;`.
+#[derive(Deserialize)]
+struct CsvLabelRow {
+	pubkey: String,
+	label: String,
+	#[serde(default)]
+	tags: String,
+}
+
+/// Handler for importing account labels in bulk.
+///
+/// This asynchronous function accepts either a JSON array of `{ "pubkey", "label", "tags" }`
+/// objects, or a CSV document (`Content-Type: text/csv`) with a `pubkey,label,tags` header where
+/// tags are separated by `;`. Rows are deduplicated by pubkey and merged with the existing labels:
+/// the last label wins and tags are merged.
+///
+/// # Arguments
+///
+/// * `headers` - The request headers, used to detect the body format.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+/// * `body` - The request body containing the labels to import.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<LabelImportSummary>` with the number of received, inserted and updated labels.
+/// - An error response with a `BAD_REQUEST` status if the body cannot be parsed.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database error.
+pub async fn import_labels_handler(
+	headers: HeaderMap,
+	conn: DbConn,
+	body: Bytes,
+) -> impl IntoResponse {
+	let is_csv = headers
+		.get(CONTENT_TYPE)
+		.and_then(|value| value.to_str().ok())
+		.is_some_and(|value| value.starts_with("text/csv"));
+	let records = if is_csv { parse_csv_labels(&body) } else { parse_json_labels(&body) };
+	let records = match records {
+		Ok(records) => records,
+		Err(err) => return build_error_response(StatusCode::BAD_REQUEST, &err).into_response(),
+	};
+
+	match conn.interact(move |conn| labels::import_labels(conn, records)).await {
+		Ok(summary) => Json(summary).into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Handler for retrieving the label of an account.
+///
+/// # Arguments
+///
+/// * `pubkey` - A `Path` extractor containing the account ID.
+/// * `scope` - The `ApiScope` of the request, which must see the account.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<LabelRecord>` if the account has a label.
+/// - An error response with a `NOT_FOUND` status if the account has no label.
+/// - An error response with a `FORBIDDEN` status if the account is outside the scope of the key.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_label_handler(
+	Path(pubkey): Path<String>,
+	scope: ApiScope,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let label = conn
+		.interact(move |conn| {
+			scope.check_account(conn, &pubkey)?;
+			labels::get_label(conn, &pubkey)
+		})
+		.await;
+	match label {
+		Ok(Some(label)) => Json(label).into_response(),
+		Ok(None) => build_error_response(StatusCode::NOT_FOUND, "Label not found").into_response(),
+		Err(err) => query_error_response(err),
+	}
+}
+
+/// Parses a JSON array of labels.
+fn parse_json_labels(body: &[u8]) -> Result<Vec<LabelRecord>, String> {
+	serde_json::from_slice(body).map_err(|e| format!("Invalid JSON labels: {}", e))
+}
+
+/// Parses a CSV document of labels with a `pubkey,label,tags` header.
+fn parse_csv_labels(body: &[u8]) -> Result<Vec<LabelRecord>, String> {
+	let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(body);
+	reader
+		.deserialize::<CsvLabelRow>()
+		.map(|row| {
+			let row = row.map_err(|e| format!("Invalid CSV labels: {}", e))?;
+			let tags = row
+				.tags
+				.split(';')
+				.map(str::trim)
+				.filter(|tag| !tag.is_empty())
+				.map(str::to_string)
+				.collect();
+			Ok(LabelRecord { pubkey: row.pubkey, label: row.label, tags })
+		})
+		.collect()
+}
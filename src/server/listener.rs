@@ -0,0 +1,131 @@
+//! Listeners of the API server.
+//!
+//! The API is served on `server_address` and on each `[[listeners]]` address of the configuration,
+//! all at once. An address is either a TCP socket address, IPv4 or IPv6, or the path of a Unix
+//! domain socket prefixed with `unix:`, for sidecars reaching the service through a shared volume.
+//! TCP listeners can set `SO_REUSEPORT`, so several processes bind the same address and the kernel
+//! spreads the connections between them. On shutdown, the listeners stop accepting connections
+//! and the server returns once the requests in flight are answered.
+use axum::{Router, Server};
+use hyper::server::{accept, conn::AddrIncoming};
+use std::{
+	error::Error,
+	fs::{self, Permissions},
+	io,
+	net::SocketAddr,
+	os::unix::fs::{FileTypeExt, PermissionsExt},
+	path::{Path, PathBuf},
+};
+use tokio::net::{TcpListener, TcpSocket, UnixListener};
+use tracing::info;
+
+use crate::{shutdown::Shutdown, types::ListenerConfig};
+
+/// Prefix of the addresses of Unix domain sockets.
+const UNIX_PREFIX: &str = "unix:";
+
+/// Maximum number of connections waiting to be accepted by a TCP listener.
+const TCP_BACKLOG: u32 = 1024;
+
+/// A bound listener of the API server.
+pub enum Listener {
+	Tcp(TcpListener),
+	Unix { listener: UnixListener, path: PathBuf },
+}
+
+impl Listener {
+	/// Binds the address of a listener.
+	///
+	/// A Unix domain socket left at the path by a previous run is replaced, any other file is not.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the address is invalid or cannot be bound, or if an option
+	/// does not apply to the kind of listener.
+	pub fn bind(config: &ListenerConfig) -> Result<Self, Box<dyn Error>> {
+		if let Some(path) = config.address.strip_prefix(UNIX_PREFIX) {
+			if config.reuse_port {
+				return Err(format!("reuse_port does not apply to {}", config.address).into())
+			}
+			let path = PathBuf::from(path);
+			remove_stale_socket(&path)?;
+			let listener = UnixListener::bind(&path)?;
+			if let Some(mode) = config.socket_mode {
+				fs::set_permissions(&path, Permissions::from_mode(mode))?;
+			}
+			return Ok(Self::Unix { listener, path })
+		}
+		if config.socket_mode.is_some() {
+			return Err(format!("socket_mode does not apply to {}", config.address).into())
+		}
+		let address: SocketAddr = config
+			.address
+			.parse()
+			.map_err(|_| format!("Invalid listener address: {}", config.address))?;
+		let socket = if address.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+		socket.set_reuseaddr(true)?;
+		socket.set_reuseport(config.reuse_port)?;
+		socket.bind(address)?;
+		Ok(Self::Tcp(socket.listen(TCP_BACKLOG)?))
+	}
+
+	/// Serves the API on the listener, until accepting connections fails or the shutdown is
+	/// requested.
+	pub async fn serve(self, app: Router, shutdown: Shutdown) -> Result<(), hyper::Error> {
+		match self {
+			Self::Tcp(listener) => {
+				let incoming = AddrIncoming::from_listener(listener)?;
+				info!("API listening on {}", incoming.local_addr());
+				Server::builder(incoming)
+					.serve(app.into_make_service())
+					.with_graceful_shutdown(shutdown.requested())
+					.await
+			},
+			Self::Unix { listener, path } => {
+				info!("API listening on {}{}", UNIX_PREFIX, path.display());
+				let incoming = accept::poll_fn(move |cx| {
+					listener
+						.poll_accept(cx)
+						.map(|accepted| Some(accepted.map(|(stream, _)| stream)))
+				});
+				Server::builder(incoming)
+					.serve(app.into_make_service())
+					.with_graceful_shutdown(shutdown.requested())
+					.await
+			},
+		}
+	}
+}
+
+/// Binds all the listeners, then serves the API on each of them until one fails or the shutdown is
+/// requested.
+///
+/// # Errors
+///
+/// This function returns an error if a listener cannot be bound, before the API is served on any,
+/// or once one of the listeners fails.
+pub async fn serve_all(
+	listeners: &[ListenerConfig],
+	app: Router,
+	shutdown: &Shutdown,
+) -> Result<(), Box<dyn Error>> {
+	let listeners = listeners.iter().map(Listener::bind).collect::<Result<Vec<_>, _>>()?;
+	let served = listeners
+		.into_iter()
+		.map(|listener| listener.serve(app.clone(), shutdown.clone()));
+	futures::future::try_join_all(served).await?;
+	Ok(())
+}
+
+/// Removes the socket file at a path, if there is one.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+	match fs::symlink_metadata(path) {
+		Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+		Ok(_) => Err(io::Error::new(
+			io::ErrorKind::AlreadyExists,
+			format!("{} exists and is not a socket", path.display()),
+		)),
+		Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+		Err(err) => Err(err),
+	}
+}
@@ -0,0 +1,187 @@
+//! Per-route latency histograms of the HTTP API, and counters of the ingestion.
+use axum::{
+	extract::MatchedPath,
+	http::Request,
+	middleware::Next,
+	response::{IntoResponse, Json, Response},
+};
+use serde::Serialize;
+use std::{
+	collections::BTreeMap,
+	sync::{Arc, Mutex},
+	time::Instant,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+use super::state::AppState;
+use crate::events::Event;
+
+/// Upper bounds of the latency histogram buckets, in milliseconds.
+const LATENCY_BUCKETS_MS: [u64; 11] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// Metrics of a single route.
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteMetrics {
+	pub requests: u64,
+	/// Requests answered with a 5xx status.
+	pub server_errors: u64,
+	/// Cumulated latency of the requests, in milliseconds.
+	pub total_latency_ms: u64,
+	/// Number of requests per latency bucket, the last bucket holding the slower requests.
+	pub histogram: Vec<LatencyBucket>,
+}
+
+/// A bucket of the latency histogram.
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyBucket {
+	/// Upper bound of the bucket in milliseconds, `None` for the overflow bucket.
+	pub le_ms: Option<u64>,
+	pub count: u64,
+}
+
+impl Default for RouteMetrics {
+	fn default() -> Self {
+		let histogram = LATENCY_BUCKETS_MS
+			.iter()
+			.map(|bound| Some(*bound))
+			.chain([None])
+			.map(|le_ms| LatencyBucket { le_ms, count: 0 })
+			.collect();
+		Self { requests: 0, server_errors: 0, total_latency_ms: 0, histogram }
+	}
+}
+
+/// Records the latency of the requests per route, shared between the middleware and the handler.
+#[derive(Clone, Default)]
+pub struct HttpMetrics {
+	routes: Arc<Mutex<BTreeMap<String, RouteMetrics>>>,
+}
+
+impl HttpMetrics {
+	/// Middleware recording the latency and status of each request under its route pattern (e.g.
+	/// `/groups/:name`), so the metrics do not grow with the requested paths.
+	pub async fn track<B>(self, request: Request<B>, next: Next<B>) -> Response {
+		let route = match request.extensions().get::<MatchedPath>() {
+			Some(path) => path.as_str().to_string(),
+			None => "unmatched".to_string(),
+		};
+		let start = Instant::now();
+		let response = next.run(request).await;
+		let latency_ms = start.elapsed().as_millis() as u64;
+
+		let mut routes = self.routes.lock().expect("metrics lock poisoned");
+		let metrics = routes.entry(route).or_default();
+		metrics.requests += 1;
+		if response.status().is_server_error() {
+			metrics.server_errors += 1;
+		}
+		metrics.total_latency_ms += latency_ms;
+		let bucket = LATENCY_BUCKETS_MS
+			.iter()
+			.position(|bound| latency_ms <= *bound)
+			.unwrap_or(LATENCY_BUCKETS_MS.len());
+		metrics.histogram[bucket].count += 1;
+		response
+	}
+
+	/// Returns a snapshot of the metrics, per route.
+	pub fn snapshot(&self) -> BTreeMap<String, RouteMetrics> {
+		self.routes.lock().expect("metrics lock poisoned").clone()
+	}
+}
+
+/// Handler for retrieving the request count, server errors and latency histogram of each route.
+///
+/// # Arguments
+///
+/// * `state` - The `AppState` holding the `HttpMetrics`.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` with the `RouteMetrics` of each route, keyed by
+/// route pattern.
+pub async fn get_http_stats_handler(state: AppState) -> impl IntoResponse {
+	Json(state.http_metrics.snapshot())
+}
+
+/// Counters of the blocks and transfers stored by the ingestion.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IngestionCounters {
+	pub blocks_ingested: u64,
+	pub transactions_stored: u64,
+	pub transfers_stored: u64,
+	pub reorgs_detected: u64,
+	/// Stored transactions found missing from a block stored again at their slot.
+	pub orphaned_transactions: u64,
+	/// Spikes reported by the anomaly detection.
+	pub anomalies_detected: u64,
+	/// The slot of the last stored block.
+	pub last_slot: Option<u64>,
+	/// The timestamp of the last stored block.
+	pub last_block_time: Option<i64>,
+	/// Events missed because the counters fell behind the event bus.
+	pub missed_events: u64,
+}
+
+/// Counts the events of the ingestion, shared between the subscriber task and the handler.
+#[derive(Clone, Default)]
+pub struct IngestionMetrics {
+	counters: Arc<Mutex<IngestionCounters>>,
+}
+
+impl IngestionMetrics {
+	/// Counts the events received from the event bus until it is closed.
+	pub async fn follow(self, mut events: broadcast::Receiver<Event>) {
+		loop {
+			let event = match events.recv().await {
+				Ok(event) => event,
+				Err(RecvError::Lagged(missed)) => {
+					warn!("Ingestion metrics lagged, {} events skipped", missed);
+					self.counters.lock().expect("metrics lock poisoned").missed_events += missed;
+					continue
+				},
+				Err(RecvError::Closed) => break,
+			};
+			self.record(&event);
+		}
+	}
+
+	/// Updates the counters with an event.
+	pub fn record(&self, event: &Event) {
+		let mut counters = self.counters.lock().expect("metrics lock poisoned");
+		match event {
+			Event::BlockIngested { slot, block_time, .. } => {
+				counters.blocks_ingested += 1;
+				counters.last_slot = Some(*slot);
+				counters.last_block_time = *block_time;
+			},
+			Event::TransactionStored { .. } => counters.transactions_stored += 1,
+			Event::TransferStored(_) => counters.transfers_stored += 1,
+			Event::ReorgDetected { orphaned_transactions, .. } => {
+				counters.reorgs_detected += 1;
+				counters.orphaned_transactions += orphaned_transactions.len() as u64;
+			},
+			Event::AnomalyDetected(_) => counters.anomalies_detected += 1,
+		}
+	}
+
+	/// Returns a snapshot of the counters.
+	pub fn snapshot(&self) -> IngestionCounters {
+		self.counters.lock().expect("metrics lock poisoned").clone()
+	}
+}
+
+/// Handler for retrieving the counters of the blocks and transfers stored by the ingestion.
+///
+/// # Arguments
+///
+/// * `state` - The `AppState` holding the `IngestionMetrics`.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` with the `IngestionCounters`, all zero when the
+/// process does not ingest blocks.
+pub async fn get_ingestion_stats_handler(state: AppState) -> impl IntoResponse {
+	Json(state.ingestion_metrics.snapshot())
+}
@@ -0,0 +1,60 @@
+//! Handlers for the MEV analysis endpoints.
+use axum::{
+	extract::Query,
+	http::StatusCode,
+	response::{IntoResponse, Json},
+};
+use serde::Deserialize;
+use tracing::error;
+
+use super::{build_error_response, DbConn};
+use crate::db;
+
+/// Number of sandwiches returned when the query does not specify a limit.
+const DEFAULT_LIMIT: u32 = 100;
+
+/// Query parameters for retrieving flagged sandwiches.
+#[derive(Deserialize)]
+pub struct SandwichesQuery {
+	attacker: Option<String>,
+	limit: Option<u32>,
+}
+
+/// Handler for retrieving the most recent probable sandwiches.
+///
+/// This asynchronous function takes an optional attacker account ID and an optional limit
+/// (defaults to 100), and returns the flagged front-run/victim/back-run triplets as JSON, most
+/// recent first.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `SandwichesQuery`.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<SandwichRecord>>` with the flagged sandwiches.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_sandwiches_handler(
+	Query(params): Query<SandwichesQuery>,
+	conn: DbConn,
+) -> impl IntoResponse {
+	match conn
+		.interact(move |conn| {
+			db::get_sandwiches(
+				conn,
+				params.attacker.as_deref(),
+				params.limit.unwrap_or(DEFAULT_LIMIT),
+			)
+		})
+		.await
+	{
+		Ok(sandwiches) => Json(sandwiches).into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
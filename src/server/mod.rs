@@ -1,17 +1,61 @@
 use axum::{
+	async_trait,
 	body::{boxed, Full},
-	extract::Query,
-	http::StatusCode,
+	extract::{FromRequest, Path, Query, RequestParts},
+	http::{HeaderMap, StatusCode},
 	response::{IntoResponse, Json, Response},
-	Extension,
 };
-use log::error;
 use rusqlite::Connection;
-use serde::Deserialize;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use solana_sdk::signature::Signature;
+use std::{collections::HashMap, error::Error, str::FromStr};
+use tracing::{error, info};
 
-use crate::{db, TransactionRecord};
-use tokio::sync::Mutex;
+use crate::{
+	aggregator::backfill::Backfiller,
+	db::{
+		self, account_info, account_snapshots,
+		pool::{self, PooledConnection},
+		scopes::OutsideScope,
+		AccountRecord,
+	},
+	types::{AmountUnit, ProgramInstruction, TransferKind},
+};
+
+pub mod admin;
+pub mod auth;
+pub mod consistency;
+pub mod degraded;
+pub mod dto;
+pub mod encoding;
+pub mod explorer;
+pub mod groups;
+pub mod idempotency;
+pub mod labels;
+pub mod listener;
+pub mod metrics;
+pub mod mev;
+pub mod proto;
+pub mod runtime;
+pub mod signing;
+pub mod slots;
+pub mod spans;
+pub mod state;
+pub mod stats;
+pub mod streaming;
+pub mod submissions;
+pub mod timeout;
+pub mod units;
+pub mod webhooks;
+pub mod ws;
+
+use auth::ApiScope;
+use dto::{
+	AccountInfoResponse, AccountResponse, BlockResponse, TransactionResponse, TransactionSource,
+	TransferResponse,
+};
+use encoding::{negotiate, with_vary, WireFormat};
+use state::AppState;
 
 /// Query parameters for retrieving a transaction.
 #[derive(Deserialize)]
@@ -23,41 +67,254 @@ pub struct TransactionQuery {
 /// Handler for retrieving a transaction record from the database.
 ///
 /// This asynchronous function takes a transaction query with a transaction ID, and retrieves the
-/// corresponding transaction record from the database. It returns the transaction record as JSON
-/// or an appropriate error response.
+/// corresponding transaction record from the database. It returns the transaction record as JSON,
+/// protobuf or MessagePack depending on the `Accept` header, or an appropriate error response.
+///
+/// With `transaction_passthrough` enabled on a service ingesting blocks, a transaction missing from
+/// the database is fetched from the RPC node with `getTransaction`, stored, then returned with a
+/// `source` of `rpc`. Only keys seeing every account go through the passthrough: the transaction is
+/// not known to be in the scope of a restricted key before it is fetched, so it is reported as not
+/// found to them rather than fetched and stored on their behalf.
 ///
 /// # Arguments
 ///
 /// * `params` - A `Query` extractor containing the `TransactionQuery`.
-/// * `conn` - An `Extension` extractor providing an `Arc<Mutex<Connection>>` to the database.
+/// * `headers` - The request headers, used for content negotiation.
+/// * `scope` - The `ApiScope` of the request, which must see an account of the transaction.
+/// * `state` - The `AppState`, whose `Backfiller` fetches the missing transactions.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
 ///
 /// # Returns
 ///
 /// This function returns a `Result` containing:
-/// - `Ok(Json<TransactionRecord>)` if the transaction is found.
+/// - `Ok(Response)` with the encoded `TransactionResponse` if the transaction is found.
 /// - `Err(Response)` with a `NOT_FOUND` status if the transaction is not found.
+/// - `Err(Response)` with a `FORBIDDEN` status if the transaction is outside the scope of the key.
+/// - `Err(Response)` with a `BAD_GATEWAY` status if a missing transaction cannot be fetched from
+///   the RPC node.
 /// - `Err(Response)` with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
 ///
 /// # Errors
 ///
-/// This function returns an error response if the transaction is not found or if there is a
-/// database query error.
+/// This function returns an error response if the transaction is not found or visible, or if there
+/// is a database query error.
 pub async fn get_transaction_handler(
 	Query(params): Query<TransactionQuery>,
-	axum::extract::Extension(conn): axum::extract::Extension<Arc<Mutex<Connection>>>,
-) -> Result<Json<TransactionRecord>, Response> {
+	headers: HeaderMap,
+	scope: ApiScope,
+	state: AppState,
+	conn: DbConn,
+) -> Result<Response, Response> {
 	let tx_id = params.tx_id;
-	let conn = conn.lock().await;
-	match db::get_transaction(&conn, &tx_id) {
-		Ok(Some(transaction)) => Ok(Json(transaction)),
-		Ok(None) => Err(build_error_response(StatusCode::NOT_FOUND, "Transaction not found")),
+	let (lookup, lookup_scope) = (tx_id.clone(), scope.clone());
+	let transaction = conn
+		.interact(move |conn| {
+			lookup_scope.check_transaction(conn, &lookup)?;
+			db::get_transaction(conn, &lookup)
+		})
+		.await;
+	match transaction {
+		Ok(Some(transaction)) => Ok(negotiate(&headers, TransactionResponse::from(transaction))),
+		Ok(None) => match state
+			.backfiller
+			.as_ref()
+			.filter(|_| state.transaction_passthrough && scope.is_unrestricted())
+		{
+			Some(backfiller) => {
+				let transaction = fetch_missing_transaction(&state.db, backfiller, tx_id).await?;
+				let response = TransactionResponse {
+					source: Some(TransactionSource::Rpc),
+					..TransactionResponse::from(transaction)
+				};
+				Ok(negotiate(&headers, response))
+			},
+			None => Err(build_error_response(StatusCode::NOT_FOUND, "Transaction not found")),
+		},
+		Err(err) => Err(query_error_response(err)),
+	}
+}
+
+/// Fetches a transaction missing from the database from the RPC node, then reads it back once
+/// stored.
+///
+/// The transaction is written before its accounts are known, so this is only called for requests
+/// seeing every account.
+///
+/// # Errors
+///
+/// This function returns a `NOT_FOUND` response if the RPC node does not know the transaction or
+/// the writer dropped it, a `BAD_GATEWAY` response if it cannot be fetched, and the response of
+/// `query_error_response` if it cannot be read back, or a `SERVICE_UNAVAILABLE` one if no database
+/// connection is available.
+async fn fetch_missing_transaction(
+	pool: &pool::DbPool,
+	backfiller: &Backfiller,
+	tx_id: String,
+) -> Result<db::TransactionRecord, Response> {
+	let not_found = || build_error_response(StatusCode::NOT_FOUND, "Transaction not found");
+	let Ok(signature) = Signature::from_str(&tx_id) else { return Err(not_found()) };
+	match backfiller.fetch_missing_transaction(&signature).await {
+		Ok(true) => info!("Fetched missing transaction {} from the RPC node", tx_id),
+		Ok(false) => return Err(not_found()),
+		Err(err) => {
+			error!("Failed to fetch missing transaction {}: {:?}", tx_id, err);
+			return Err(build_error_response(
+				StatusCode::BAD_GATEWAY,
+				"Failed to fetch the transaction",
+			))
+		},
+	}
+	let transaction =
+		pool::with_connection(pool, move |conn| db::get_transaction(conn, &tx_id)).await;
+	match transaction {
+		Ok(Ok(Some(transaction))) => Ok(transaction),
+		Ok(Ok(None)) => Err(not_found()),
+		Ok(Err(err)) => Err(query_error_response(err)),
+		Err(err) => {
+			error!("Failed to check out a database connection: {:?}", err);
+			Err(build_error_response(StatusCode::SERVICE_UNAVAILABLE, "Database unavailable"))
+		},
+	}
+}
+
+/// Query parameters for listing the stored transactions.
+#[derive(Deserialize)]
+pub struct TransactionsQuery {
+	limit: Option<usize>,
+	/// The `next_cursor` of the previous page.
+	cursor: Option<String>,
+	from_slot: Option<u64>,
+	to_slot: Option<u64>,
+	since: Option<i64>,
+	until: Option<i64>,
+}
+
+/// A page of the transactions listing.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransactionPage {
+	pub transactions: Vec<TransactionResponse>,
+	/// The cursor of the next page, `None` once the listing is exhausted.
+	pub next_cursor: Option<String>,
+}
+
+/// Handler for listing the stored transactions, newest first.
+///
+/// The listing can be bounded by slot (`from_slot` and `to_slot`, inclusive) and by block time
+/// (`since`, inclusive, and `until`, exclusive, as unix timestamps). Each page carries the cursor
+/// of the next one, to pass back as `cursor` along with the same bounds. The limit defaults to and
+/// cannot exceed the row cap of the service.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `TransactionsQuery`.
+/// * `state` - The `AppState`, holding the row cap.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<TransactionPage>` with the transactions of the page.
+/// - An error response with a `BAD_REQUEST` status if the limit is out of range or the cursor is
+///   invalid.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_transactions_handler(
+	Query(params): Query<TransactionsQuery>,
+	state: AppState,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let limit = match row_limit(params.limit, state.max_rows) {
+		Ok(limit) => limit,
+		Err(err) => return build_error_response(StatusCode::BAD_REQUEST, &err).into_response(),
+	};
+	let after = match params.cursor.as_deref().map(parse_transaction_cursor).transpose() {
+		Ok(after) => after,
+		Err(err) => return build_error_response(StatusCode::BAD_REQUEST, &err).into_response(),
+	};
+	let filter = db::TransactionFilter {
+		from_slot: params.from_slot,
+		to_slot: params.to_slot,
+		since: params.since,
+		until: params.until,
+	};
+
+	match conn
+		.interact(move |conn| {
+			let after = after.as_ref().map(|(slot, signature)| (*slot, signature.as_str()));
+			db::get_transactions_page(conn, &filter, after, limit)
+		})
+		.await
+	{
+		Ok(transactions) => {
+			let next_cursor = match transactions.last() {
+				Some(last) if transactions.len() == limit =>
+					Some(format!("{}:{}", last.block_height, last.transaction_id)),
+				_ => None,
+			};
+			let transactions = transactions.into_iter().map(TransactionResponse::from).collect();
+			Json(TransactionPage { transactions, next_cursor }).into_response()
+		},
 		Err(err) => {
 			error!("Database query error: {:?}", err);
-			Err(build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"))
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
 		},
 	}
 }
 
+/// Parses a cursor of the transactions listing into the slot and signature it resumes after.
+fn parse_transaction_cursor(cursor: &str) -> Result<(u64, String), String> {
+	cursor
+		.split_once(':')
+		.and_then(|(slot, signature)| Some((slot.parse().ok()?, signature.to_string())))
+		.ok_or_else(|| "Invalid cursor".to_string())
+}
+
+/// Parses a cursor of the signatures of a block into the position and signature it resumes after.
+fn parse_block_cursor(cursor: &str) -> Result<(i64, String), String> {
+	cursor
+		.split_once(':')
+		.and_then(|(position, signature)| Some((position.parse().ok()?, signature.to_string())))
+		.ok_or_else(|| "Invalid cursor".to_string())
+}
+
+/// Handler for retrieving the instructions recorded for a transaction.
+///
+/// Only the top level instructions the RPC node could not parse are recorded, with their program
+/// id, accounts and base58 data, in execution order.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `TransactionQuery`.
+/// * `scope` - The `ApiScope` of the request, which must see an account of the transaction.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Errors
+///
+/// This function returns a `NOT_FOUND` response if the transaction is not stored, a `FORBIDDEN`
+/// response if it is outside the scope of the API key, or an `INTERNAL_SERVER_ERROR` response if
+/// there is a database query error.
+pub async fn get_transaction_instructions_handler(
+	Query(params): Query<TransactionQuery>,
+	scope: ApiScope,
+	conn: DbConn,
+) -> Result<Json<Vec<ProgramInstruction>>, Response> {
+	let tx_id = params.tx_id;
+	let result = conn
+		.interact(move |conn| {
+			scope.check_transaction(conn, &tx_id)?;
+			if db::get_transaction(conn, &tx_id)?.is_none() {
+				return Ok(None)
+			}
+			db::instructions::get_program_instructions(conn, &tx_id).map(Some)
+		})
+		.await;
+	match result {
+		Ok(Some(instructions)) => Ok(Json(instructions)),
+		Ok(None) => Err(build_error_response(StatusCode::NOT_FOUND, "Transaction not found")),
+		Err(err) => Err(query_error_response(err)),
+	}
+}
+
 /// Query parameters for retrieving an account.
 #[derive(Deserialize)]
 pub struct AccountQuery {
@@ -65,38 +322,553 @@ pub struct AccountQuery {
 	account_id: String,
 }
 
+/// An account, along with its on-chain metadata.
+#[derive(Debug, Serialize)]
+pub struct AccountDetails {
+	#[serde(flatten)]
+	pub account: AccountResponse,
+	/// The on-chain metadata of the account, `None` until it is fetched.
+	pub info: Option<AccountInfoResponse>,
+}
+
 /// Handler for retrieving an account record from the database.
 ///
 /// This asynchronous function takes an account query with an account ID, and retrieves the
-/// corresponding account record from the database. It returns the account record as JSON
-/// or an appropriate error response.
+/// corresponding account record from the database. It returns the account record as JSON, along
+/// with its on-chain metadata (owner, executable flag, data length and kind) once the enrichment
+/// job has fetched it, or an appropriate error response.
 ///
 /// # Arguments
 ///
 /// * `params` - A `Query` extractor containing the `AccountQuery`.
-/// * `conn` - An `Extension` extractor providing an `Arc<Mutex<Connection>>` to the database.
+/// * `scope` - The `ApiScope` of the request, which must see the account.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
 ///
 /// # Returns
 ///
 /// This function returns an `impl IntoResponse` which can be either:
-/// - `Json<AccountRecord>` if the account is found.
+/// - `Json<AccountDetails>` if the account is found.
 /// - An error response with a `NOT_FOUND` status if the account is not found.
+/// - An error response with a `FORBIDDEN` status if the account is outside the scope of the key.
 /// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
 ///
 /// # Errors
 ///
-/// This function returns an error response if the account is not found or if there is a database
-/// query error.
+/// This function returns an error response if the account is not found or visible, or if there is
+/// a database query error.
 pub async fn get_account_handler(
 	Query(params): Query<AccountQuery>,
-	Extension(conn): Extension<Arc<Mutex<Connection>>>,
+	scope: ApiScope,
+	conn: DbConn,
 ) -> impl IntoResponse {
 	let account_id = params.account_id;
-	let conn = conn.lock().await;
-	match db::get_account(&conn, &account_id) {
-		Ok(Some(account)) => Json(account).into_response(),
+	let details = conn
+		.interact(move |conn| -> Result<_, Box<dyn Error + Send + Sync>> {
+			scope.check_account(conn, &account_id)?;
+			let Some(account) = db::get_account(conn, &account_id)? else { return Ok(None) };
+			let info = account_info::get_account_info(conn, &account_id)?;
+			Ok(Some(AccountDetails { account: account.into(), info: info.map(Into::into) }))
+		})
+		.await;
+	match details {
+		Ok(Some(details)) => Json(details).into_response(),
 		Ok(None) =>
 			build_error_response(StatusCode::NOT_FOUND, "Account not found").into_response(),
+		Err(err) => query_error_response(err),
+	}
+}
+
+/// Maximum number of accounts looked up by a single batch request.
+const MAX_BATCH_ACCOUNTS: usize = 100;
+
+/// Body of a batch account lookup.
+#[derive(Deserialize)]
+pub struct AccountBatchRequest {
+	account_ids: Vec<String>,
+}
+
+/// The outcome of the lookup of an account in a batch.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountLookup {
+	pub account_id: String,
+	pub found: bool,
+	/// The account, `None` if it is not stored.
+	pub account: Option<AccountResponse>,
+}
+
+/// Handler for retrieving several account records at once.
+///
+/// The accounts are read with a single query, and returned in the order of the request, each with
+/// a marker telling whether it is stored. Account IDs repeated in the request are looked up once.
+///
+/// # Arguments
+///
+/// * `scope` - The `ApiScope` of the request, which must see every requested account.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+/// * `request` - A `Json` extractor containing the `AccountBatchRequest` with the account IDs.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<AccountLookup>>` with an entry per distinct account ID.
+/// - An error response with a `BAD_REQUEST` status if there are no or too many account IDs.
+/// - An error response with a `FORBIDDEN` status if an account is outside the scope of the key.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_accounts_batch_handler(
+	scope: ApiScope,
+	conn: DbConn,
+	Json(request): Json<AccountBatchRequest>,
+) -> impl IntoResponse {
+	let mut account_ids = Vec::new();
+	for account_id in request.account_ids {
+		if !account_ids.contains(&account_id) {
+			account_ids.push(account_id);
+		}
+	}
+	if account_ids.is_empty() || account_ids.len() > MAX_BATCH_ACCOUNTS {
+		let message = format!("A batch holds 1 to {} account IDs", MAX_BATCH_ACCOUNTS);
+		return build_error_response(StatusCode::BAD_REQUEST, &message).into_response()
+	}
+
+	let lookups = conn
+		.interact(move |conn| -> Result<_, Box<dyn Error + Send + Sync>> {
+			for account_id in &account_ids {
+				scope.check_account(conn, account_id)?;
+			}
+			let mut accounts: HashMap<String, AccountRecord> =
+				db::get_accounts(conn, &account_ids)?
+					.into_iter()
+					.map(|account| (account.account_id.clone(), account))
+					.collect();
+			let lookups: Vec<AccountLookup> = account_ids
+				.into_iter()
+				.map(|account_id| {
+					let account = accounts.remove(&account_id).map(AccountResponse::from);
+					AccountLookup { account_id, found: account.is_some(), account }
+				})
+				.collect();
+			Ok(lookups)
+		})
+		.await;
+	match lookups {
+		Ok(lookups) => Json(lookups).into_response(),
+		Err(err) => query_error_response(err),
+	}
+}
+
+/// Query parameters for retrieving the balance of an account.
+#[derive(Deserialize)]
+pub struct BalanceQuery {
+	at_slot: Option<u64>,
+	/// The unit of the balance, the `amount_unit` of the service by default.
+	unit: Option<AmountUnit>,
+}
+
+/// The lamport fields of the balance responses.
+const BALANCE_AMOUNTS: &[&str] = &["balance"];
+
+/// The lamport balance of an account at a slot.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountBalance {
+	pub account_id: String,
+	/// The requested slot, or `None` for the latest known balance.
+	pub at_slot: Option<u64>,
+	pub balance: u64,
+	/// The slot of the last balance change at or before the requested slot.
+	pub last_change_slot: u64,
+	/// The transaction of the last balance change at or before the requested slot.
+	pub last_change_transaction_id: String,
+}
+
+/// Handler for retrieving the lamport balance of an account at a past slot.
+///
+/// This asynchronous function reconstructs the balance of the account at the requested slot from
+/// its stored balance change history: the post balance of the last change at or before the slot
+/// is the balance at that slot. Without a slot, the latest known balance is returned. The balance
+/// is in lamports, or in SOL with `unit=sol`.
+///
+/// # Arguments
+///
+/// * `pubkey` - A `Path` extractor containing the account ID.
+/// * `params` - A `Query` extractor containing the `BalanceQuery`.
+/// * `state` - The `AppState`, holding the default amount unit.
+/// * `scope` - The `ApiScope` of the request, which must see the account.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<AccountBalance>` if a balance change was recorded at or before the slot.
+/// - An error response with a `NOT_FOUND` status if no balance change was recorded.
+/// - An error response with a `FORBIDDEN` status if the account is outside the scope of the key.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_balance_handler(
+	Path(pubkey): Path<String>,
+	Query(params): Query<BalanceQuery>,
+	state: AppState,
+	scope: ApiScope,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let unit = params.unit.unwrap_or(state.amount_unit);
+	let balance = conn
+		.interact(move |conn| -> Result<_, Box<dyn Error + Send + Sync>> {
+			scope.check_account(conn, &pubkey)?;
+			let Some(change) = db::get_balance_change_at(conn, &pubkey, params.at_slot)? else {
+				return Ok(None)
+			};
+			let balance = AccountBalance {
+				account_id: change.account_id,
+				at_slot: params.at_slot,
+				balance: change.post_balance,
+				last_change_slot: change.block_height,
+				last_change_transaction_id: change.transaction_id,
+			};
+			Ok(Some(units::with_unit(balance, unit, BALANCE_AMOUNTS)?))
+		})
+		.await;
+	match balance {
+		Ok(Some(balance)) => Json(balance).into_response(),
+		Ok(None) =>
+			build_error_response(StatusCode::NOT_FOUND, "No balance history found").into_response(),
+		Err(err) => query_error_response(err),
+	}
+}
+
+/// Query parameters for retrieving the snapshots of an account.
+#[derive(Deserialize)]
+pub struct AccountSnapshotsQuery {
+	from_slot: Option<u64>,
+	to_slot: Option<u64>,
+	limit: Option<usize>,
+}
+
+/// Handler for retrieving the snapshots of an account of a tracked program, newest first.
+///
+/// # Arguments
+///
+/// * `pubkey` - A `Path` extractor containing the account ID.
+/// * `params` - A `Query` extractor containing the `AccountSnapshotsQuery`: `from_slot` and
+///   `to_slot` bound the slots the snapshots were taken at, inclusive.
+/// * `state` - The `AppState` holding the maximum number of rows per response.
+/// * `scope` - The `ApiScope` of the request, which must see the account.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<AccountSnapshotRecord>>` with the snapshots, empty for an account never snapshotted.
+/// - An error response with a `BAD_REQUEST` status if the limit is out of range.
+/// - An error response with a `FORBIDDEN` status if the account is outside the scope of the key.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_account_snapshots_handler(
+	Path(pubkey): Path<String>,
+	Query(params): Query<AccountSnapshotsQuery>,
+	state: AppState,
+	scope: ApiScope,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let limit = match row_limit(params.limit, state.max_rows) {
+		Ok(limit) => limit,
+		Err(message) =>
+			return build_error_response(StatusCode::BAD_REQUEST, &message).into_response(),
+	};
+	match conn
+		.interact(move |conn| {
+			scope.check_account(conn, &pubkey)?;
+			account_snapshots::get_account_snapshots(
+				conn,
+				&pubkey,
+				params.from_slot,
+				params.to_slot,
+				limit,
+			)
+		})
+		.await
+	{
+		Ok(snapshots) => Json(snapshots).into_response(),
+		Err(err) => query_error_response(err),
+	}
+}
+
+/// The default and maximum number of signatures returned, as in `getSignaturesForAddress`.
+const MAX_SIGNATURES_LIMIT: usize = 1000;
+
+/// Query parameters for paginating the transactions of an account.
+#[derive(Deserialize)]
+pub struct AccountTransactionsQuery {
+	before: Option<String>,
+	until: Option<String>,
+	limit: Option<usize>,
+	from_slot: Option<u64>,
+	to_slot: Option<u64>,
+	/// The unix timestamp from which transactions are listed.
+	start_time: Option<i64>,
+	/// The unix timestamp before which transactions are listed.
+	end_time: Option<i64>,
+}
+
+impl AccountTransactionsQuery {
+	/// Returns the slot and block time bounds of the query.
+	pub(crate) fn filter(&self) -> db::TransactionFilter {
+		db::TransactionFilter {
+			from_slot: self.from_slot,
+			to_slot: self.to_slot,
+			since: self.start_time,
+			until: self.end_time,
+		}
+	}
+}
+
+/// Handler for retrieving the transaction signatures of an account, newest first.
+///
+/// This asynchronous function mirrors Solana's `getSignaturesForAddress`: `before` and `until` are
+/// exclusive signature bounds and `limit` defaults to and cannot exceed 1000, so wallet sync code
+/// can page through the history the same way. The history can also be bounded by slot
+/// (`from_slot` and `to_slot`, inclusive) and by block time (`start_time`, inclusive, and
+/// `end_time`, exclusive, as unix timestamps).
+///
+/// # Arguments
+///
+/// * `account_id` - A `Path` extractor containing the account ID.
+/// * `params` - A `Query` extractor containing the `AccountTransactionsQuery`.
+/// * `scope` - The `ApiScope` of the request, which must see the account.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<SignatureInfo>>` with the signatures of the page.
+/// - An error response with a `BAD_REQUEST` status if the limit is out of range.
+/// - An error response with a `FORBIDDEN` status if the account is outside the scope of the key.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_account_transactions_handler(
+	Path(account_id): Path<String>,
+	Query(params): Query<AccountTransactionsQuery>,
+	scope: ApiScope,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let limit = params.limit.unwrap_or(MAX_SIGNATURES_LIMIT);
+	if limit == 0 || limit > MAX_SIGNATURES_LIMIT {
+		return build_error_response(StatusCode::BAD_REQUEST, "Invalid limit; max 1000")
+			.into_response()
+	}
+
+	match conn
+		.interact(move |conn| {
+			scope.check_account(conn, &account_id)?;
+			db::get_account_signatures(
+				conn,
+				&account_id,
+				params.before.as_deref(),
+				params.until.as_deref(),
+				&params.filter(),
+				limit,
+			)
+		})
+		.await
+	{
+		Ok(signatures) => Json(signatures).into_response(),
+		Err(err) => query_error_response(err),
+	}
+}
+
+/// The direction of a transfer relative to the queried account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+	/// The account received the lamports.
+	In,
+	/// The account sent the lamports.
+	Out,
+	/// The account sent the lamports to itself.
+	#[serde(rename = "self")]
+	SelfTransfer,
+}
+
+/// A transfer of an account, with its direction and the running net flow of the account.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountTransfer {
+	#[serde(flatten)]
+	pub transfer: TransferResponse,
+	pub direction: TransferDirection,
+	/// Lamports received minus lamports sent over the listed transfers, up to this one included.
+	pub net_flow: i64,
+}
+
+/// Annotates the transfers of an account, oldest first, with their direction and running net flow.
+pub fn account_transfers(
+	account_id: &str,
+	transfers: Vec<db::TransferRecord>,
+) -> Vec<AccountTransfer> {
+	let mut net_flow: i64 = 0;
+	transfers
+		.into_iter()
+		.map(|transfer| account_transfer(account_id, &mut net_flow, transfer))
+		.collect()
+}
+
+/// Annotates the next transfer of an account, updating the running net flow of the account.
+fn account_transfer(
+	account_id: &str,
+	net_flow: &mut i64,
+	transfer: db::TransferRecord,
+) -> AccountTransfer {
+	let amount = i64::try_from(transfer.amount).unwrap_or(i64::MAX);
+	// Transfers of the token accounts of a wallet are transfers of the wallet
+	let holds = |address: &str, owner: &Option<String>| {
+		address == account_id || owner.as_deref() == Some(account_id)
+	};
+	let sent = holds(&transfer.sender, &transfer.sender_owner);
+	let received = holds(&transfer.receiver, &transfer.receiver_owner);
+	let direction = if transfer.sender == transfer.receiver || (sent && received) {
+		TransferDirection::SelfTransfer
+	} else if received {
+		*net_flow = net_flow.saturating_add(amount);
+		TransferDirection::In
+	} else {
+		*net_flow = net_flow.saturating_sub(amount);
+		TransferDirection::Out
+	};
+	AccountTransfer { transfer: transfer.into(), direction, net_flow: *net_flow }
+}
+
+/// Query parameters for retrieving the transfers of an account.
+#[derive(Deserialize)]
+pub struct TransferQuery {
+	#[serde(rename = "account-id")]
+	account_id: String,
+	kind: Option<TransferKind>,
+	/// Only the transfers the tagging rules gave this tag.
+	tag: Option<String>,
+	limit: Option<usize>,
+	/// The unit of the amounts of JSON responses, the `amount_unit` of the service by default.
+	unit: Option<AmountUnit>,
+}
+
+/// The lamport fields of the transfer responses.
+const TRANSFER_AMOUNTS: &[&str] = &["amount", "net_flow"];
+
+/// Handler for retrieving the transfers of an account from the database.
+///
+/// This asynchronous function takes a transfer query with an account ID, an optional transfer
+/// kind (`transfer`, `rent_deposit` or `account_close`), an optional tag given by the tagging rules
+/// and an optional limit, and returns the matching transfer records, oldest first, as JSON,
+/// protobuf or MessagePack depending on the `Accept` header, or an appropriate error response. Each
+/// transfer carries its `direction` relative to the account and the running `net_flow` of the
+/// account over the returned transfers. The limit defaults to and cannot exceed the row cap of the
+/// service; JSON responses are streamed as the rows are read, with their amounts in lamports or in
+/// SOL with `unit=sol`. Binary responses always carry lamports.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `TransferQuery`.
+/// * `headers` - The request headers, used for content negotiation.
+/// * `state` - The `AppState`, holding the row cap and the default amount unit.
+/// * `scope` - The `ApiScope` of the request, which must see the account.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - The encoded `Vec<AccountTransfer>` with the transfers of the account.
+/// - An error response with a `BAD_REQUEST` status if the limit is out of range.
+/// - An error response with a `FORBIDDEN` status if the account is outside the scope of the key.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_transfers_handler(
+	Query(params): Query<TransferQuery>,
+	headers: HeaderMap,
+	state: AppState,
+	scope: ApiScope,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let limit = match row_limit(params.limit, state.max_rows) {
+		Ok(limit) => limit,
+		Err(err) => return build_error_response(StatusCode::BAD_REQUEST, &err),
+	};
+	let account_id = params.account_id.clone();
+	let unit = params.unit.unwrap_or(state.amount_unit);
+	if WireFormat::from_headers(&headers) == WireFormat::Json {
+		let response = streaming::stream_json_array(conn, move |conn, sink| {
+			scope.check_account(conn, &account_id)?;
+			let mut net_flow = 0;
+			let mut failure = None;
+			let tag = params.tag.as_deref();
+			db::for_each_transfer(conn, &account_id, params.kind, tag, limit, |transfer| {
+				let transfer = account_transfer(&account_id, &mut net_flow, transfer);
+				match units::with_unit(transfer, unit, TRANSFER_AMOUNTS) {
+					Ok(transfer) => sink(transfer),
+					Err(err) => {
+						failure = Some(err);
+						false
+					},
+				}
+			})?;
+			failure.map_or(Ok(()), |err| Err(err.into()))
+		});
+		return with_vary(response.await)
+	}
+	match conn
+		.interact(move |conn| {
+			scope.check_account(conn, &account_id)?;
+			db::get_transfers(conn, &account_id, params.kind, params.tag.as_deref(), limit)
+		})
+		.await
+	{
+		Ok(transfers) => negotiate(&headers, account_transfers(&params.account_id, transfers)),
+		Err(err) => query_error_response(err),
+	}
+}
+
+/// Validates the limit of a listing against the row cap of the service.
+///
+/// # Returns
+///
+/// This function returns the limit, or the row cap when none was given.
+///
+/// # Errors
+///
+/// This function returns an error message if the limit is zero or above the cap.
+pub(crate) fn row_limit(limit: Option<usize>, max_rows: usize) -> Result<usize, String> {
+	match limit.unwrap_or(max_rows) {
+		limit if limit == 0 || limit > max_rows => Err(format!("Invalid limit; max {}", max_rows)),
+		limit => Ok(limit),
+	}
+}
+
+/// Maximum number of blocks returned by the recent blocks endpoint.
+const MAX_RECENT_BLOCKS: u32 = 100;
+
+/// Query parameters for retrieving the recent blocks.
+#[derive(Deserialize)]
+pub struct RecentBlocksQuery {
+	limit: Option<u32>,
+}
+
+/// Handler for retrieving a summary of the most recently ingested blocks.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `RecentBlocksQuery`, `limit` defaults to 20.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<BlockSummary>>` with the blocks, newest first.
+/// - An error response with a `BAD_REQUEST` status if the limit is out of range.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_recent_blocks_handler(
+	Query(params): Query<RecentBlocksQuery>,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let limit = params.limit.unwrap_or(20);
+	if limit == 0 || limit > MAX_RECENT_BLOCKS {
+		return build_error_response(StatusCode::BAD_REQUEST, "Invalid limit; max 100")
+			.into_response()
+	}
+
+	match conn.interact(move |conn| db::get_recent_blocks(conn, limit)).await {
+		Ok(blocks) => Json(blocks).into_response(),
 		Err(err) => {
 			error!("Database query error: {:?}", err);
 			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
@@ -105,6 +877,143 @@ pub async fn get_account_handler(
 	}
 }
 
+/// Query parameters for retrieving a block.
+#[derive(Deserialize)]
+pub struct BlockQuery {
+	limit: Option<usize>,
+	/// The `next_cursor` of the previous page.
+	cursor: Option<String>,
+	/// Return the header and counts of the block without its signatures.
+	#[serde(default)]
+	summary: bool,
+}
+
+/// Handler for retrieving a stored block and the signatures of its transactions.
+///
+/// The signatures are paginated: each response carries the cursor of the next page, to pass back
+/// as `cursor`. The limit defaults to and cannot exceed the row cap of the service. In summary
+/// mode, only the header and the transaction counts of the block are returned. The blocks stored
+/// before headers were kept only have their slot, block time and signatures.
+///
+/// # Arguments
+///
+/// * `slot` - A `Path` extractor containing the slot of the block.
+/// * `params` - A `Query` extractor containing the `BlockQuery`.
+/// * `state` - The `AppState`, holding the row cap.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Errors
+///
+/// This function returns a `BAD_REQUEST` response if the limit is out of range or the cursor is
+/// invalid, a `NOT_FOUND` response if nothing is stored at the slot, or an `INTERNAL_SERVER_ERROR`
+/// response if there is a database query error.
+pub async fn get_block_handler(
+	Path(slot): Path<u64>,
+	Query(params): Query<BlockQuery>,
+	state: AppState,
+	conn: DbConn,
+) -> Result<Json<BlockResponse>, Response> {
+	let limit = row_limit(params.limit, state.max_rows)
+		.map_err(|err| build_error_response(StatusCode::BAD_REQUEST, &err))?;
+	let after = params
+		.cursor
+		.as_deref()
+		.map(parse_block_cursor)
+		.transpose()
+		.map_err(|err| build_error_response(StatusCode::BAD_REQUEST, &err))?;
+	let result = conn
+		.interact(move |conn| {
+			let record = db::blocks::get_block(conn, slot)?;
+			let stored_transaction_count = db::blocks::count_block_transactions(conn, slot)?;
+			if record.is_none() && stored_transaction_count == 0 {
+				return Ok(None)
+			}
+			let record = record.unwrap_or(db::blocks::BlockRecord { slot, ..Default::default() });
+			let mut block = BlockResponse::new(record, stored_transaction_count);
+			if !params.summary {
+				let after =
+					after.as_ref().map(|(position, signature)| (*position, signature.as_str()));
+				let signatures = db::blocks::get_block_signatures(conn, slot, after, limit)?;
+				block.next_cursor = match signatures.last() {
+					Some((position, signature)) if signatures.len() == limit =>
+						Some(format!("{}:{}", position, signature)),
+					_ => None,
+				};
+				block.signatures = signatures.into_iter().map(|(_, signature)| signature).collect();
+			}
+			Ok::<_, Box<dyn Error + Send + Sync>>(Some(block))
+		})
+		.await;
+	match result {
+		Ok(Some(block)) => Ok(Json(block)),
+		Ok(None) => Err(build_error_response(StatusCode::NOT_FOUND, "Block not found")),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			Err(build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"))
+		},
+	}
+}
+
+/// Handler for the liveness check of the service.
+///
+/// # Arguments
+///
+/// * `state` - The `AppState`, holding the storage quota of the ingestion if one is configured.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `ok` with an `OK` status.
+/// - An error response with a `SERVICE_UNAVAILABLE` status while the storage quota pauses the
+///   ingestion.
+pub async fn health_handler(state: Option<AppState>) -> impl IntoResponse {
+	let paused = state
+		.and_then(|state| state.storage)
+		.is_some_and(|storage| storage.status().ingestion_paused);
+	if paused {
+		return build_error_response(
+			StatusCode::SERVICE_UNAVAILABLE,
+			"Storage quota exceeded, ingestion paused",
+		)
+		.into_response()
+	}
+	"ok".into_response()
+}
+
+/// Extractor checking a connection out of the `DbPool` of the `AppState`.
+///
+/// Requests are rejected with a `SERVICE_UNAVAILABLE` status when no connection frees up within the
+/// pool timeout.
+pub struct DbConn(PooledConnection);
+
+impl DbConn {
+	/// Runs the queries of a handler on the blocking thread pool, then gives the connection back to
+	/// the pool.
+	pub async fn interact<F, T>(self, f: F) -> T
+	where
+		F: FnOnce(&Connection) -> T + Send + 'static,
+		T: Send + 'static,
+	{
+		pool::interact(self.0, f).await
+	}
+}
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for DbConn {
+	type Rejection = Response;
+
+	async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+		let state = AppState::from_request(request).await?;
+		match state.db.get_owned().await {
+			Ok(conn) => Ok(DbConn(conn)),
+			Err(err) => {
+				error!("Failed to check out a database connection: {:?}", err);
+				Err(build_error_response(StatusCode::SERVICE_UNAVAILABLE, "Database unavailable"))
+			},
+		}
+	}
+}
+
 /// Builds an error response with a given status code and message.
 ///
 /// This function takes a status code and a message, and constructs an HTTP response with
@@ -118,7 +1027,22 @@ pub async fn get_account_handler(
 /// # Returns
 ///
 /// This function returns a `Response` with the given status and message.
-fn build_error_response(status: StatusCode, message: &str) -> Response {
+pub(crate) fn build_error_response(status: StatusCode, message: &str) -> Response {
 	let message = message.to_string();
 	Response::builder().status(status).body(boxed(Full::from(message))).unwrap()
 }
+
+/// Builds the response of a failed query.
+///
+/// # Returns
+///
+/// This function returns a response with a `FORBIDDEN` status for data outside the scope of the API
+/// key of the request, or with an `INTERNAL_SERVER_ERROR` status for the other errors, which are
+/// logged.
+pub(crate) fn query_error_response(err: Box<dyn Error + Send + Sync>) -> Response {
+	if err.is::<OutsideScope>() {
+		return build_error_response(StatusCode::FORBIDDEN, &err.to_string())
+	}
+	error!("Database query error: {:?}", err);
+	build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+}
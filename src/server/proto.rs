@@ -0,0 +1,84 @@
+//! Protobuf message types of the binary wire format, generated from `proto/aggregator.proto` by
+//! the build script.
+use crate::{
+	server::{self, dto::TransactionResponse, AccountTransfer},
+	types,
+};
+
+include!(concat!(env!("OUT_DIR"), "/solana_aggregator.rs"));
+
+/// Conversion of a response body to its protobuf message.
+pub trait ToProto {
+	type Message: prost::Message;
+
+	fn to_proto(&self) -> Self::Message;
+}
+
+impl ToProto for TransactionResponse {
+	type Message = Transaction;
+
+	fn to_proto(&self) -> Transaction {
+		Transaction {
+			transaction_id: self.transaction_id.clone(),
+			timestamp: self.timestamp,
+			block_height: self.block_height,
+			raw_transaction: self.raw_transaction.clone(),
+			fee: self.fee,
+			fee_payer: self.fee_payer.clone(),
+			compute_unit_limit: self.compute_unit_limit,
+			compute_unit_price: self.compute_unit_price,
+			source: self.source.map(|source| source.as_str().to_string()),
+		}
+	}
+}
+
+impl From<types::TransferKind> for TransferKind {
+	fn from(kind: types::TransferKind) -> Self {
+		match kind {
+			types::TransferKind::Transfer => TransferKind::Transfer,
+			types::TransferKind::RentDeposit => TransferKind::RentDeposit,
+			types::TransferKind::AccountClose => TransferKind::AccountClose,
+		}
+	}
+}
+
+impl From<server::TransferDirection> for TransferDirection {
+	fn from(direction: server::TransferDirection) -> Self {
+		match direction {
+			server::TransferDirection::In => TransferDirection::In,
+			server::TransferDirection::Out => TransferDirection::Out,
+			server::TransferDirection::SelfTransfer => TransferDirection::SelfTransfer,
+		}
+	}
+}
+
+impl ToProto for AccountTransfer {
+	type Message = Transfer;
+
+	fn to_proto(&self) -> Transfer {
+		let transfer = &self.transfer;
+		Transfer {
+			transaction_id: transfer.transaction_id.clone(),
+			sender: transfer.sender.clone(),
+			receiver: transfer.receiver.clone(),
+			amount: transfer.amount,
+			timestamp: transfer.timestamp,
+			block_height: transfer.block_height,
+			kind: TransferKind::from(transfer.kind) as i32,
+			direction: TransferDirection::from(self.direction) as i32,
+			net_flow: self.net_flow,
+			sender_owner: transfer.sender_owner.clone(),
+			receiver_owner: transfer.receiver_owner.clone(),
+			invoked_by: transfer.invoked_by.clone(),
+			tags: transfer.tags.clone(),
+		}
+	}
+}
+
+impl ToProto for Vec<AccountTransfer> {
+	type Message = TransferList;
+
+	fn to_proto(&self) -> TransferList {
+		TransferList { transfers: self.iter().map(ToProto::to_proto).collect() }
+	}
+}
@@ -0,0 +1,232 @@
+//! What a running instance is and does, logged as a banner on startup and served by
+//! `GET /status/runtime`.
+//!
+//! The information is gathered once, from the configuration and the build, so operators can check
+//! the version, the network and the effective settings of an instance without shell access. The
+//! configuration is sanitized first: the values of the settings naming a secret are redacted, and
+//! so are the credentials, paths and query strings of the URLs, where RPC providers put their API
+//! keys.
+use axum::{
+	http::StatusCode,
+	response::{IntoResponse, Json},
+};
+use reqwest::Url;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Instant;
+use tracing::info;
+
+use super::{build_error_response, state::AppState};
+use crate::{
+	aggregator::processor::PARSED_INSTRUCTIONS,
+	types::{Commitment, Config, RunMode},
+};
+
+/// Text replacing the redacted values.
+const REDACTED: &str = "<redacted>";
+
+/// Parts of the setting names whose values are redacted, e.g. `secret_access_key` or the
+/// `sasl.password` property of Kafka.
+const SECRET_SETTINGS: [&str; 4] = ["secret", "password", "token", "credential"];
+
+/// Genesis hashes of the public clusters.
+const CLUSTERS: [(&str, &str); 3] = [
+	("5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d", "mainnet-beta"),
+	("EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG", "devnet"),
+	("4uhcVJyU9pJkvQyS88uRDiswHXSCkY3zQawwpjk2NsNY", "testnet"),
+];
+
+/// The network an instance indexes.
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkInfo {
+	/// The RPC endpoint, sanitized.
+	pub rpc_url: String,
+	pub commitment: Commitment,
+	/// The genesis hash of the network, once read from the RPC node or the database.
+	pub genesis_hash: Option<String>,
+	/// The public cluster with that genesis hash, if any.
+	pub cluster: Option<&'static str>,
+}
+
+/// The instructions decoded for a program.
+#[derive(Debug, Clone, Serialize)]
+pub struct ParserInfo {
+	pub program_id: &'static str,
+	pub instructions: &'static [&'static str],
+}
+
+/// What a running instance is and does.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeInfo {
+	/// The version of the binary.
+	pub version: &'static str,
+	/// The commit the binary was built from, `unknown` outside of a checkout.
+	pub git_commit: &'static str,
+	pub mode: RunMode,
+	/// The unix timestamp the instance started at.
+	pub started_at: i64,
+	#[serde(skip)]
+	started: Instant,
+	/// The optional features enabled by the configuration, named after their settings.
+	pub features: Vec<&'static str>,
+	pub parsers: Vec<ParserInfo>,
+	pub network: NetworkInfo,
+	/// The effective configuration, defaults included, sanitized.
+	pub config: Value,
+}
+
+/// The runtime information along with the time the instance has been running for.
+#[derive(Debug, Serialize)]
+pub struct RuntimeStatus {
+	#[serde(flatten)]
+	pub info: RuntimeInfo,
+	pub uptime_secs: u64,
+}
+
+impl RuntimeInfo {
+	/// Gathers the information of an instance starting with `config`.
+	pub fn new(config: &Config) -> Self {
+		let mut settings = serde_json::to_value(config).unwrap_or_default();
+		sanitize(&mut settings);
+		Self {
+			version: env!("CARGO_PKG_VERSION"),
+			git_commit: env!("GIT_COMMIT"),
+			mode: config.mode,
+			started_at: chrono::Utc::now().timestamp(),
+			started: Instant::now(),
+			features: enabled_features(config),
+			parsers: PARSED_INSTRUCTIONS
+				.iter()
+				.map(|&(program_id, instructions)| ParserInfo { program_id, instructions })
+				.collect(),
+			network: NetworkInfo {
+				rpc_url: sanitize_url(&config.rpc_url),
+				commitment: config.commitment,
+				genesis_hash: None,
+				cluster: None,
+			},
+			config: settings,
+		}
+	}
+
+	/// Records the genesis hash of the indexed network, naming its cluster if it is a public one.
+	pub fn set_genesis_hash(&mut self, genesis_hash: String) {
+		self.network.cluster = CLUSTERS
+			.iter()
+			.find(|(hash, _)| *hash == genesis_hash)
+			.map(|(_, cluster)| *cluster);
+		self.network.genesis_hash = Some(genesis_hash);
+	}
+
+	/// Logs the startup banner, with the information as fields.
+	pub fn log_banner(&self) {
+		info!(
+			version = self.version,
+			git_commit = self.git_commit,
+			mode = ?self.mode,
+			rpc_url = %self.network.rpc_url,
+			commitment = ?self.network.commitment,
+			features = %self.features.join(","),
+			config = %self.config,
+			"Starting Solana Aggregator"
+		);
+	}
+
+	/// Returns the information along with the current uptime.
+	pub fn status(&self) -> RuntimeStatus {
+		RuntimeStatus { info: self.clone(), uptime_secs: self.started.elapsed().as_secs() }
+	}
+}
+
+/// Returns the optional features enabled by a configuration.
+fn enabled_features(config: &Config) -> Vec<&'static str> {
+	[
+		("continuous", config.continuous),
+		("block_subscription", config.block_subscription),
+		("transaction_passthrough", config.transaction_passthrough),
+		("watched_addresses", !config.watched_addresses.is_empty()),
+		("index_programs", !config.index_programs.is_empty()),
+		("request_timeout", config.request_timeout_ms.is_some()),
+		("archive", config.archive.is_some()),
+		("tiering", config.tiering.is_some()),
+		("block_cache", config.block_cache.is_some()),
+		("account_info", config.account_info.is_some()),
+		("anomaly_detection", config.anomaly_detection.is_some()),
+		("storage_quota", config.storage_quota.is_some()),
+		("degraded_mode", config.degraded_mode.is_some()),
+		("message_bus", config.message_bus.is_some()),
+		("signing_keypair", config.signing_keypair.is_some()),
+		("exports", !config.exports.is_empty()),
+		("account_snapshots", !config.account_snapshots.is_empty()),
+		("tagging_rules", !config.tagging_rules.is_empty()),
+		("api_keys", !config.api_keys.is_empty()),
+	]
+	.into_iter()
+	.filter_map(|(name, enabled)| enabled.then_some(name))
+	.collect()
+}
+
+/// Redacts the secrets of serialized settings: the values of the settings named `key` or naming
+/// a secret, and the credentials, path and query of the URLs.
+pub fn sanitize(settings: &mut Value) {
+	match settings {
+		Value::Object(fields) =>
+			for (name, value) in fields.iter_mut() {
+				let name = name.to_lowercase();
+				if !value.is_null() &&
+					(name == "key" || SECRET_SETTINGS.iter().any(|secret| name.contains(secret)))
+				{
+					*value = Value::String(REDACTED.to_string());
+				} else {
+					sanitize(value);
+				}
+			},
+		Value::Array(values) => values.iter_mut().for_each(sanitize),
+		Value::String(value) => *value = sanitize_url(value),
+		_ => {},
+	}
+}
+
+/// Returns a URL without its credentials, path and query, which are replaced with `<redacted>`;
+/// other strings are returned as is.
+pub fn sanitize_url(value: &str) -> String {
+	let Ok(url) = Url::parse(value) else { return value.to_string() };
+	let Some(host) = url.host_str() else { return value.to_string() };
+	let revealing = !url.username().is_empty() ||
+		url.password().is_some() ||
+		url.query().is_some() ||
+		!matches!(url.path(), "" | "/");
+	if !revealing {
+		return value.to_string()
+	}
+	match url.port() {
+		Some(port) => format!("{}://{}:{}/{}", url.scheme(), host, port, REDACTED),
+		None => format!("{}://{}/{}", url.scheme(), host, REDACTED),
+	}
+}
+
+/// Handler for retrieving what the instance is and does.
+///
+/// This asynchronous function returns the version and commit of the binary, its run mode, the
+/// optional features enabled by the configuration, the instructions the parser decodes, the network
+/// indexed, the uptime, and the effective configuration with its secrets redacted.
+///
+/// # Arguments
+///
+/// * `state` - The `AppState`, holding the runtime information gathered on startup.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<RuntimeStatus>` with the information.
+/// - An error response with a `SERVICE_UNAVAILABLE` status if the information was not gathered.
+pub async fn get_runtime_status_handler(state: AppState) -> impl IntoResponse {
+	match state.runtime {
+		Some(runtime) => Json(runtime.status()).into_response(),
+		None => build_error_response(
+			StatusCode::SERVICE_UNAVAILABLE,
+			"Runtime information unavailable",
+		)
+		.into_response(),
+	}
+}
@@ -0,0 +1,149 @@
+//! Signed response mode.
+//!
+//! When a service keypair is configured, every response carries a detached Ed25519 signature of
+//! its payload, so consumers relaying the aggregator data can prove where it came from. JSON
+//! payloads are signed in a canonical form, with sorted keys and no whitespace, which lets the
+//! relays re-serialize them freely; other payloads are signed as is.
+use axum::{
+	body::{self, Bytes},
+	http::{header::CONTENT_TYPE, HeaderValue, Request, StatusCode},
+	middleware::Next,
+	response::{IntoResponse, Response},
+};
+use serde_json::Value;
+use solana_sdk::{
+	pubkey::Pubkey,
+	signature::{read_keypair_file, Keypair, Signature},
+	signer::Signer,
+};
+use std::{error::Error, fmt::Write, sync::Arc};
+use tracing::error;
+
+use super::{build_error_response, idempotency::collect_body};
+
+/// Header carrying the base58 signature of the response payload.
+pub const SIGNATURE_HEADER: &str = "x-aggregator-signature";
+
+/// Header carrying the base58 public key the response was signed with.
+pub const PUBLIC_KEY_HEADER: &str = "x-aggregator-public-key";
+
+/// Signs the API responses with the service keypair.
+pub struct ResponseSigner {
+	keypair: Keypair,
+}
+
+impl ResponseSigner {
+	pub fn new(keypair: Keypair) -> Self {
+		Self { keypair }
+	}
+
+	/// Loads the service keypair from a Solana keypair file.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the file cannot be read or does not hold a keypair.
+	pub fn from_file(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		let keypair = read_keypair_file(path)
+			.map_err(|err| format!("Failed to read signing keypair {}: {}", path, err))?;
+		Ok(Self::new(keypair))
+	}
+
+	pub fn pubkey(&self) -> Pubkey {
+		self.keypair.pubkey()
+	}
+
+	/// Signs the canonical form of a payload.
+	///
+	/// # Arguments
+	///
+	/// * `content_type` - The `Content-Type` of the payload, telling whether it is JSON.
+	/// * `payload` - The payload as sent.
+	pub fn sign(&self, content_type: Option<&str>, payload: &[u8]) -> Signature {
+		self.keypair.sign_message(&canonical_payload(content_type, payload))
+	}
+
+	/// Middleware adding the signature of the payload and the public key to each response.
+	///
+	/// Protocol upgrades are passed through unsigned.
+	pub async fn sign_response<B>(self: Arc<Self>, request: Request<B>, next: Next<B>) -> Response {
+		let response = next.run(request).await;
+		if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+			return response
+		}
+
+		let (mut parts, response_body) = response.into_parts();
+		let payload = match collect_body(response_body).await {
+			Ok(bytes) => bytes,
+			Err(err) => {
+				error!("Failed to buffer the response to sign: {:?}", err);
+				return build_error_response(
+					StatusCode::INTERNAL_SERVER_ERROR,
+					"Internal server error",
+				)
+				.into_response()
+			},
+		};
+		let content_type = parts.headers.get(CONTENT_TYPE).and_then(|value| value.to_str().ok());
+		let signature = self.sign(content_type, &payload);
+
+		for (name, value) in [
+			(SIGNATURE_HEADER, signature.to_string()),
+			(PUBLIC_KEY_HEADER, self.pubkey().to_string()),
+		] {
+			let value = HeaderValue::from_str(&value).expect("base58 is a valid header value");
+			parts.headers.insert(name, value);
+		}
+		Response::from_parts(parts, body::boxed(body::Full::from(payload)))
+	}
+}
+
+/// Returns the bytes a payload is signed over: the canonical JSON of JSON payloads, or the payload
+/// itself.
+///
+/// # Arguments
+///
+/// * `content_type` - The `Content-Type` of the payload.
+/// * `payload` - The payload as sent.
+pub fn canonical_payload(content_type: Option<&str>, payload: &[u8]) -> Bytes {
+	let is_json = content_type.is_some_and(|content_type| {
+		content_type.split(';').next().unwrap_or_default().trim() == "application/json"
+	});
+	match is_json.then(|| serde_json::from_slice::<Value>(payload).ok()).flatten() {
+		Some(value) => {
+			let mut canonical = String::with_capacity(payload.len());
+			write_canonical_json(&value, &mut canonical);
+			Bytes::from(canonical)
+		},
+		None => Bytes::copy_from_slice(payload),
+	}
+}
+
+/// Writes a JSON value with the object keys sorted and no whitespace.
+fn write_canonical_json(value: &Value, out: &mut String) {
+	match value {
+		Value::Array(items) => {
+			out.push('[');
+			for (index, item) in items.iter().enumerate() {
+				if index > 0 {
+					out.push(',');
+				}
+				write_canonical_json(item, out);
+			}
+			out.push(']');
+		},
+		Value::Object(fields) => {
+			let mut fields: Vec<_> = fields.iter().collect();
+			fields.sort_by_key(|(key, _)| *key);
+			out.push('{');
+			for (index, (key, item)) in fields.into_iter().enumerate() {
+				if index > 0 {
+					out.push(',');
+				}
+				write!(out, "{}:", Value::String(key.clone())).expect("writing to a String");
+				write_canonical_json(item, out);
+			}
+			out.push('}');
+		},
+		scalar => write!(out, "{}", scalar).expect("writing to a String"),
+	}
+}
@@ -0,0 +1,241 @@
+//! Handlers mapping between slots, epochs and wall-clock time.
+use axum::{
+	extract::{Path, Query},
+	http::StatusCode,
+	response::{IntoResponse, Json},
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use super::{build_error_response, row_limit, state::AppState, DbConn};
+use crate::db::{
+	blocks::{self, BlockTime},
+	epochs,
+};
+
+/// Nominal duration of a slot, used past the first and last stored blocks.
+const SLOT_DURATION_SECS: f64 = 0.4;
+
+/// A slot along with its time.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SlotTime {
+	pub slot: u64,
+	pub timestamp: i64,
+	/// Whether the mapping is interpolated rather than read from a stored block.
+	pub estimated: bool,
+}
+
+/// Query parameters for retrieving the slot at a time.
+#[derive(Deserialize)]
+pub struct SlotAtQuery {
+	timestamp: i64,
+}
+
+/// Handler for retrieving the first slot produced at or after a unix timestamp.
+///
+/// The slot is interpolated between the stored blocks surrounding the timestamp, or extrapolated
+/// with the nominal slot duration past the first or last stored block.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `SlotAtQuery`.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<SlotTime>` with the slot.
+/// - An error response with a `NOT_FOUND` status if no block time is stored.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_slot_at_handler(
+	Query(params): Query<SlotAtQuery>,
+	conn: DbConn,
+) -> impl IntoResponse {
+	match conn
+		.interact(move |conn| blocks::get_blocks_around_time(conn, params.timestamp))
+		.await
+	{
+		Ok((before, after)) => match estimate_slot(params.timestamp, before, after) {
+			Some(slot_time) => Json(slot_time).into_response(),
+			None =>
+				build_error_response(StatusCode::NOT_FOUND, "No block times stored").into_response(),
+		},
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Handler for retrieving the time of a slot.
+///
+/// The time is read from the stored block of the slot, or interpolated between the stored blocks
+/// surrounding it, or extrapolated with the nominal slot duration past the first or last stored
+/// block.
+///
+/// # Arguments
+///
+/// * `slot` - A `Path` extractor containing the slot.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<SlotTime>` with the time of the slot.
+/// - An error response with a `NOT_FOUND` status if no block time is stored.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_slot_time_handler(Path(slot): Path<u64>, conn: DbConn) -> impl IntoResponse {
+	match conn.interact(move |conn| blocks::get_blocks_around_slot(conn, slot)).await {
+		Ok((before, after)) => match estimate_time(slot, before, after) {
+			Some(slot_time) => Json(slot_time).into_response(),
+			None =>
+				build_error_response(StatusCode::NOT_FOUND, "No block times stored").into_response(),
+		},
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Handler for listing the ranges of slots skipped because the RPC node had already purged them.
+///
+/// # Arguments
+///
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<UnavailableSlotsRecord>>` with the ranges, ordered by start slot.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_unavailable_slots_handler(conn: DbConn) -> impl IntoResponse {
+	match conn.interact(blocks::get_unavailable_slots).await {
+		Ok(ranges) => Json(ranges).into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Query parameters for listing the catalogued epochs.
+#[derive(Deserialize)]
+pub struct EpochsQuery {
+	slot: Option<u64>,
+	timestamp: Option<i64>,
+	limit: Option<usize>,
+}
+
+/// Handler for listing the catalogued epochs, newest first.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `EpochsQuery`: `slot` keeps the epoch holding
+///   the slot, and `timestamp` the epochs whose stored blocks span the unix timestamp.
+/// * `state` - The `AppState` holding the maximum number of rows per response.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<EpochRecord>>` with the epochs.
+/// - An error response with a `BAD_REQUEST` status if the limit is out of range.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_epochs_handler(
+	Query(params): Query<EpochsQuery>,
+	state: AppState,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let limit = match row_limit(params.limit, state.max_rows) {
+		Ok(limit) => limit,
+		Err(message) =>
+			return build_error_response(StatusCode::BAD_REQUEST, &message).into_response(),
+	};
+	match conn
+		.interact(move |conn| epochs::get_epochs(conn, params.slot, params.timestamp, limit))
+		.await
+	{
+		Ok(epochs) => Json(epochs).into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Handler for retrieving the slots and times of a catalogued epoch.
+///
+/// # Arguments
+///
+/// * `epoch` - A `Path` extractor containing the epoch number.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<EpochRecord>` with the epoch.
+/// - An error response with a `NOT_FOUND` status if the epoch is not catalogued.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_epoch_handler(Path(epoch): Path<u64>, conn: DbConn) -> impl IntoResponse {
+	match conn.interact(move |conn| epochs::get_epoch(conn, epoch)).await {
+		Ok(Some(record)) => Json(record).into_response(),
+		Ok(None) => build_error_response(StatusCode::NOT_FOUND, "Epoch not found").into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Estimates the time of a slot from the last block at or before it and the first block after it.
+fn estimate_time(
+	slot: u64,
+	before: Option<BlockTime>,
+	after: Option<BlockTime>,
+) -> Option<SlotTime> {
+	let timestamp = match (before, after) {
+		(Some((before_slot, before_time)), _) if before_slot == slot =>
+			return Some(SlotTime { slot, timestamp: before_time, estimated: false }),
+		(Some((before_slot, before_time)), Some((after_slot, after_time))) => {
+			let progress = (slot - before_slot) as f64 / (after_slot - before_slot) as f64;
+			before_time + ((after_time - before_time) as f64 * progress).round() as i64
+		},
+		(Some((before_slot, before_time)), None) =>
+			before_time + ((slot - before_slot) as f64 * SLOT_DURATION_SECS).round() as i64,
+		(None, Some((after_slot, after_time))) =>
+			after_time - ((after_slot - slot) as f64 * SLOT_DURATION_SECS).round() as i64,
+		(None, None) => return None,
+	};
+	Some(SlotTime { slot, timestamp, estimated: true })
+}
+
+/// Estimates the first slot at or after a timestamp from the last block produced before it and the
+/// first block produced at or after it.
+fn estimate_slot(
+	timestamp: i64,
+	before: Option<BlockTime>,
+	after: Option<BlockTime>,
+) -> Option<SlotTime> {
+	let slot = match (before, after) {
+		(Some((before_slot, before_time)), Some((after_slot, after_time)))
+			if after_slot > before_slot =>
+		{
+			let progress = (timestamp - before_time) as f64 / (after_time - before_time) as f64;
+			before_slot + ((after_slot - before_slot) as f64 * progress).ceil() as u64
+		},
+		(_, Some((after_slot, after_time))) if after_time == timestamp => after_slot,
+		(_, Some((after_slot, after_time))) => after_slot
+			.saturating_sub(((after_time - timestamp) as f64 / SLOT_DURATION_SECS).floor() as u64),
+		(Some((before_slot, before_time)), None) =>
+			before_slot + ((timestamp - before_time) as f64 / SLOT_DURATION_SECS).ceil() as u64,
+		(None, None) => return None,
+	};
+	let estimated = after != Some((slot, timestamp));
+	Some(SlotTime { slot, timestamp, estimated })
+}
@@ -0,0 +1,28 @@
+//! Per-request spans of the HTTP API.
+//!
+//! Every request is answered within a `request` span holding a sequential ID, its method and its
+//! path, so the events logged while answering it, down to the slow queries of its database reads,
+//! are tied to it. The status and latency of the response are logged at the `debug` level.
+use axum::{http::Request, middleware::Next, response::Response};
+use std::{
+	sync::atomic::{AtomicU64, Ordering},
+	time::Instant,
+};
+use tracing::{debug, info_span, Instrument};
+
+/// ID of the next request, counted from the start of the process.
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Middleware running the rest of the stack within the span of the request.
+pub async fn request_span<B>(request: Request<B>, next: Next<B>) -> Response {
+	let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+	let span = info_span!("request", id, method = %request.method(), path = request.uri().path());
+	async move {
+		let started = Instant::now();
+		let response = next.run(request).await;
+		debug!("Answered with {} in {} ms", response.status(), started.elapsed().as_millis());
+		response
+	}
+	.instrument(span)
+	.await
+}
@@ -0,0 +1,91 @@
+//! State shared by the API handlers.
+use axum::{
+	async_trait,
+	extract::{FromRequest, RequestParts},
+	http::StatusCode,
+	response::Response,
+};
+use std::sync::Arc;
+use tracing::error;
+
+use super::{
+	build_error_response,
+	degraded::DegradedMode,
+	metrics::{HttpMetrics, IngestionMetrics},
+	runtime::RuntimeInfo,
+};
+use crate::{
+	aggregator::backfill::Backfiller,
+	db::{pool::DbPool, storage::StorageMonitor},
+	events::EventBus,
+	pubsub::PubsubHub,
+	rpc::RpcGateway,
+	types::{AmountUnit, DEFAULT_MAX_ROWS_PER_REQUEST},
+};
+
+/// The services the API handlers run on, provided to the router as a single `Extension`.
+///
+/// Cloning the state only clones handles to the shared services. Handlers take it as an extractor,
+/// and the optional services are missing when the process does not ingest blocks.
+#[derive(Clone)]
+pub struct AppState {
+	/// Pool of connections to the database.
+	pub db: DbPool,
+	/// Gateway to the RPC node.
+	pub gateway: Option<Arc<RpcGateway>>,
+	/// Targeted backfills and re-fetches.
+	pub backfiller: Option<Backfiller>,
+	/// Shared upstream PubSub connection of the WebSocket clients.
+	pub pubsub: Option<PubsubHub>,
+	/// Events of the ingestion, streamed to the WebSocket clients of the live transactions.
+	pub events: Option<EventBus>,
+	/// Latency metrics of the routes.
+	pub http_metrics: HttpMetrics,
+	/// Counters of the ingestion, fed by the event bus.
+	pub ingestion_metrics: IngestionMetrics,
+	/// Maximum number of rows a listing endpoint returns in a single response.
+	pub max_rows: usize,
+	/// Unit of the lamport amounts of the responses when the request does not pick one.
+	pub amount_unit: AmountUnit,
+	/// Storage quota of the ingestion, when one is configured.
+	pub storage: Option<StorageMonitor>,
+	/// Degraded mode of the API, when one is configured.
+	pub degraded: Option<DegradedMode>,
+	/// Whether the transactions missing from the database are fetched from the RPC node.
+	pub transaction_passthrough: bool,
+	/// What the instance is and does, gathered on startup.
+	pub runtime: Option<Arc<RuntimeInfo>>,
+}
+
+impl AppState {
+	/// Creates the state of a process serving the database only.
+	pub fn new(db: DbPool) -> Self {
+		Self {
+			db,
+			gateway: None,
+			backfiller: None,
+			pubsub: None,
+			events: None,
+			http_metrics: HttpMetrics::default(),
+			ingestion_metrics: IngestionMetrics::default(),
+			max_rows: DEFAULT_MAX_ROWS_PER_REQUEST,
+			amount_unit: AmountUnit::default(),
+			storage: None,
+			degraded: None,
+			transaction_passthrough: false,
+			runtime: None,
+		}
+	}
+}
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for AppState {
+	type Rejection = Response;
+
+	async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+		request.extensions().get::<AppState>().cloned().ok_or_else(|| {
+			error!("Application state missing from the request extensions");
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+		})
+	}
+}
@@ -0,0 +1,371 @@
+//! Handlers for the statistics endpoints.
+use axum::{
+	extract::{Path, Query},
+	http::StatusCode,
+	response::{IntoResponse, Json},
+};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+use super::{
+	auth::ApiScope, build_error_response, query_error_response, row_limit, state::AppState, DbConn,
+};
+use crate::db::{contention, rollups, stats, storage};
+
+/// Window used when a statistics query does not specify one.
+const DEFAULT_WINDOW: &str = "24h";
+
+/// Window of the activity heat map when the query does not specify one.
+const DEFAULT_HEATMAP_WINDOW: &str = "30d";
+
+/// Number of entries returned when a statistics query does not specify a limit.
+const DEFAULT_LIMIT: usize = 10;
+
+/// Number of recent blocks priority fees are sampled from, matching the window of the
+/// `getRecentPrioritizationFees` RPC method.
+const DEFAULT_PRIORITY_FEE_BLOCKS: u64 = 150;
+
+/// Query parameters for retrieving the top fee payers.
+#[derive(Deserialize)]
+pub struct FeePayersQuery {
+	window: Option<String>,
+	limit: Option<usize>,
+}
+
+/// Handler for retrieving the accounts that spent the most on fees within a time window.
+///
+/// This asynchronous function takes an optional window (e.g. `30m`, `24h`, `7d`, defaults to
+/// `24h`) and an optional limit (defaults to 10, up to the row cap of the service), and returns
+/// the top fee payers with their total and average fee as JSON.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `FeePayersQuery`.
+/// * `state` - The `AppState`, holding the row cap.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<FeePayerStats>>` with the top fee payers.
+/// - An error response with a `BAD_REQUEST` status if the window or the limit is invalid.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_fee_payers_handler(
+	Query(params): Query<FeePayersQuery>,
+	state: AppState,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let since = match window_start(params.window.as_deref()) {
+		Ok(since) => since,
+		Err(err) => return build_error_response(StatusCode::BAD_REQUEST, &err).into_response(),
+	};
+	let limit = match row_limit(Some(params.limit.unwrap_or(DEFAULT_LIMIT)), state.max_rows) {
+		Ok(limit) => limit,
+		Err(err) => return build_error_response(StatusCode::BAD_REQUEST, &err).into_response(),
+	};
+	match conn.interact(move |conn| stats::get_top_fee_payers(conn, since, limit)).await {
+		Ok(fee_payers) => Json(fee_payers).into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Query parameters for retrieving error frequencies.
+#[derive(Deserialize)]
+pub struct ErrorsQuery {
+	program: Option<String>,
+	window: Option<String>,
+}
+
+/// Handler for retrieving the error code frequencies of failed transactions within a time window.
+///
+/// This asynchronous function takes an optional program ID and an optional window (defaults to
+/// `24h`), and returns how often each error code was raised, per program, as JSON.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `ErrorsQuery`.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<ErrorFrequency>>` with the error frequencies.
+/// - An error response with a `BAD_REQUEST` status if the window is invalid.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_errors_handler(
+	Query(params): Query<ErrorsQuery>,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let since = match window_start(params.window.as_deref()) {
+		Ok(since) => since,
+		Err(err) => return build_error_response(StatusCode::BAD_REQUEST, &err).into_response(),
+	};
+	match conn
+		.interact(move |conn| stats::get_error_frequencies(conn, since, params.program.as_deref()))
+		.await
+	{
+		Ok(errors) => Json(errors).into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Query parameters for retrieving the activity heat map of an account.
+#[derive(Deserialize)]
+pub struct ActivityHeatmapQuery {
+	window: Option<String>,
+}
+
+/// Handler for retrieving the activity heat map of an account within a time window.
+///
+/// This asynchronous function takes an optional window (defaults to `30d`) and returns the number
+/// of transactions of the account per day of the week and hour of the day, in UTC, as JSON, for a
+/// contribution graph style visualization.
+///
+/// # Arguments
+///
+/// * `pubkey` - A `Path` extractor containing the account ID.
+/// * `params` - A `Query` extractor containing the `ActivityHeatmapQuery`.
+/// * `scope` - The `ApiScope` of the request, which must see the account.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<ActivityHeatmap>` with the counts, all zero for an account without transactions.
+/// - An error response with a `BAD_REQUEST` status if the window is invalid.
+/// - An error response with a `FORBIDDEN` status if the account is outside the scope of the key.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_activity_heatmap_handler(
+	Path(pubkey): Path<String>,
+	Query(params): Query<ActivityHeatmapQuery>,
+	scope: ApiScope,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let window = params.window.as_deref().unwrap_or(DEFAULT_HEATMAP_WINDOW);
+	let since = match window_start(Some(window)) {
+		Ok(since) => since,
+		Err(err) => return build_error_response(StatusCode::BAD_REQUEST, &err).into_response(),
+	};
+	match conn
+		.interact(move |conn| {
+			scope.check_account(conn, &pubkey)?;
+			stats::get_activity_heatmap(conn, &pubkey, since)
+		})
+		.await
+	{
+		Ok(heatmap) => Json(heatmap).into_response(),
+		Err(err) => query_error_response(err),
+	}
+}
+
+/// Query parameters for retrieving priority fee percentiles.
+#[derive(Deserialize)]
+pub struct PriorityFeesQuery {
+	blocks: Option<u64>,
+}
+
+/// Handler for retrieving the percentiles of the priority fees paid in recent blocks.
+///
+/// This asynchronous function takes an optional number of recent blocks (defaults to 150) and
+/// returns the p50/p75/p95 compute unit prices, in micro-lamports, paid over these blocks as JSON.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `PriorityFeesQuery`.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<PriorityFeeStats>` with the priority fee percentiles.
+/// - An error response with a `NOT_FOUND` status if no priority fee was paid in the window.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_priority_fees_handler(
+	Query(params): Query<PriorityFeesQuery>,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let blocks = params.blocks.unwrap_or(DEFAULT_PRIORITY_FEE_BLOCKS);
+	match conn
+		.interact(move |conn| stats::get_priority_fee_percentiles(conn, blocks))
+		.await
+	{
+		Ok(Some(priority_fees)) => Json(priority_fees).into_response(),
+		Ok(None) =>
+			build_error_response(StatusCode::NOT_FOUND, "No priority fees found").into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Maximum number of days returned by the daily statistics endpoint.
+const MAX_DAILY_STATS_DAYS: u32 = 366;
+
+/// Query parameters for retrieving the daily statistics.
+#[derive(Deserialize)]
+pub struct DailyStatsQuery {
+	from: Option<String>,
+	to: Option<String>,
+}
+
+/// Handler for retrieving the per-day rollups of the ingested transactions.
+///
+/// This asynchronous function takes an optional range of UTC days (`YYYY-MM-DD`, inclusive) and
+/// returns, oldest first, the transaction count, transfer volume, fees, active and new accounts
+/// of each day with ingested transactions. At most 366 days are returned, the most recent ones of
+/// the range.
+///
+/// # Arguments
+///
+/// * `params` - A `Query` extractor containing the `DailyStatsQuery`.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<DailyStats>>` with the rollups.
+/// - An error response with a `BAD_REQUEST` status if a day is invalid.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_daily_stats_handler(
+	Query(params): Query<DailyStatsQuery>,
+	conn: DbConn,
+) -> impl IntoResponse {
+	for day in [&params.from, &params.to].into_iter().flatten() {
+		if NaiveDate::parse_from_str(day, "%Y-%m-%d").is_err() {
+			return build_error_response(
+				StatusCode::BAD_REQUEST,
+				&format!("Invalid day: {}; expected YYYY-MM-DD", day),
+			)
+			.into_response()
+		}
+	}
+	match conn
+		.interact(move |conn| {
+			rollups::get_daily_stats(
+				conn,
+				params.from.as_deref(),
+				params.to.as_deref(),
+				MAX_DAILY_STATS_DAYS,
+			)
+		})
+		.await
+	{
+		Ok(days) => Json(days).into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Handler for retrieving the metrics of the RPC gateway.
+///
+/// This asynchronous function returns the endpoint in use, the number of failovers, and the
+/// requests, failures and cumulated latency of each RPC method called since startup.
+///
+/// # Arguments
+///
+/// * `state` - The `AppState`, whose `RpcGateway` is missing when the service does not talk to an
+///   RPC node.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<GatewayMetrics>` with the metrics.
+/// - An error response with a `SERVICE_UNAVAILABLE` status if no RPC node is configured.
+pub async fn get_rpc_stats_handler(state: AppState) -> impl IntoResponse {
+	match state.gateway {
+		Some(gateway) => Json(gateway.metrics()).into_response(),
+		None => build_error_response(StatusCode::SERVICE_UNAVAILABLE, "No RPC node configured")
+			.into_response(),
+	}
+}
+
+/// Handler for retrieving the contention on the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which is `Json<ContentionStats>` with the number of
+/// statements that waited for the write lock, of transactions started over, and of those still
+/// failing on contention once their retries ran out, since the process started.
+pub async fn get_db_stats_handler() -> impl IntoResponse {
+	Json(contention::contention_stats())
+}
+
+/// Handler for measuring the space taken by the database.
+///
+/// The database and its tables are measured on each request, while the state of the storage quota
+/// is the one last measured by its monitor.
+///
+/// # Arguments
+///
+/// * `state` - The `AppState`, holding the storage quota of the ingestion if one is configured.
+/// * `conn` - A connection to the SQLite database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<StorageUsage>` with the size of the database, the estimated size of each table, largest
+///   first, and the state of the quota.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_storage_stats_handler(state: AppState, conn: DbConn) -> impl IntoResponse {
+	let measure = conn.interact(|conn| {
+		Ok::<_, Box<dyn std::error::Error + Send + Sync>>((
+			storage::get_database_size(conn)?,
+			storage::estimate_table_usage(conn)?,
+		))
+	});
+	match measure.await {
+		Ok((database, tables)) => Json(storage::StorageUsage {
+			database,
+			tables,
+			quota: state.storage.map(|storage| storage.status()),
+		})
+		.into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
+
+/// Returns the unix timestamp at which a window ending now starts.
+pub(crate) fn window_start(window: Option<&str>) -> Result<i64, String> {
+	let window = parse_window(window.unwrap_or(DEFAULT_WINDOW))?;
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?;
+	Ok(now.as_secs() as i64 - window)
+}
+
+/// Parses a window such as `90s`, `30m`, `24h` or `7d` into a number of seconds.
+///
+/// A plain number is interpreted as seconds.
+pub fn parse_window(window: &str) -> Result<i64, String> {
+	let invalid = || format!("Invalid window: {}", window);
+	let (value, unit) = match window.find(|c: char| !c.is_ascii_digit()) {
+		Some(index) => window.split_at(index),
+		None => (window, "s"),
+	};
+	let value: i64 = value.parse().map_err(|_| invalid())?;
+	let multiplier = match unit {
+		"s" => 1,
+		"m" => 60,
+		"h" => 60 * 60,
+		"d" => 24 * 60 * 60,
+		_ => return Err(invalid()),
+	};
+	value.checked_mul(multiplier).ok_or_else(invalid)
+}
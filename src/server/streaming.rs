@@ -0,0 +1,101 @@
+//! Streaming of large listings.
+//!
+//! A listing matching many rows is not collected into a `Vec` before being serialized: the query
+//! runs on the blocking thread pool and hands the rows over in chunks of serialized JSON through a
+//! bounded channel, so a response only holds a few chunks in memory while the client reads it. The
+//! query stops as soon as the client goes away.
+use axum::{
+	body::{boxed, Bytes, StreamBody},
+	http::{header, HeaderValue, StatusCode},
+	response::{IntoResponse, Response},
+};
+use futures::{stream, StreamExt};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{error::Error, io};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::error;
+
+use super::{build_error_response, query_error_response, DbConn};
+use crate::db::pool;
+
+/// Number of rows serialized into each chunk of the response body.
+const CHUNK_ROWS: usize = 256;
+
+/// Number of chunks buffered ahead of the client.
+const CHANNEL_CAPACITY: usize = 4;
+
+type Chunk = Result<Bytes, Box<dyn Error + Send + Sync>>;
+
+/// Streams the rows produced by a query as a JSON array.
+///
+/// The query calls the given sink with each row, and stops when the sink returns `false` because
+/// the client went away. An error raised before the first chunk is sent yields the error response
+/// of the query (see `query_error_response`); once the response has started, it aborts the body
+/// instead.
+///
+/// # Arguments
+///
+/// * `conn` - The pooled connection the query runs on, given back once the query returns.
+/// * `query` - The query, feeding its rows to the sink in response order.
+pub async fn stream_json_array<T, F>(conn: DbConn, query: F) -> Response
+where
+	T: Serialize,
+	F: FnOnce(&Connection, &mut dyn FnMut(T) -> bool) -> Result<(), Box<dyn Error + Send + Sync>>
+		+ Send
+		+ 'static,
+{
+	let (sender, mut receiver) = mpsc::channel::<Chunk>(CHANNEL_CAPACITY);
+	tokio::spawn(pool::interact(conn.0, move |conn| {
+		let mut chunk = b"[".to_vec();
+		let mut rows = 0;
+		let mut failure = None;
+		let mut sink = |row: T| {
+			if rows > 0 {
+				chunk.push(b',');
+			}
+			if let Err(err) = serde_json::to_writer(&mut chunk, &row) {
+				failure = Some(err.into());
+				return false
+			}
+			rows += 1;
+			if rows % CHUNK_ROWS == 0 {
+				return sender.blocking_send(Ok(Bytes::from(std::mem::take(&mut chunk)))).is_ok()
+			}
+			true
+		};
+		let result = query(conn, &mut sink);
+		match failure.map_or(result, Err) {
+			Ok(()) => {
+				chunk.push(b']');
+				let _ = sender.blocking_send(Ok(Bytes::from(chunk)));
+			},
+			Err(err) => {
+				let _ = sender.blocking_send(Err(err));
+			},
+		}
+	}));
+
+	let first = match receiver.recv().await {
+		Some(Ok(chunk)) => chunk,
+		Some(Err(err)) => return query_error_response(err),
+		None => {
+			error!("Streaming query stopped without a response");
+			return build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	};
+	let rest = ReceiverStream::new(receiver).map(|chunk| {
+		chunk.map_err(|err| {
+			error!("Database query error while streaming: {:?}", err);
+			io::Error::other(err.to_string())
+		})
+	});
+	let body = StreamBody::new(stream::once(async { Ok(first) }).chain(rest));
+	let mut response = Response::new(boxed(body));
+	response
+		.headers_mut()
+		.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+	response
+}
@@ -0,0 +1,125 @@
+//! Handlers for submitting transactions and tracking their status.
+use axum::{
+	extract::Path,
+	http::StatusCode,
+	response::{IntoResponse, Json},
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::error;
+
+use super::{build_error_response, state::AppState, DbConn};
+use crate::db::{pool, submissions};
+
+/// Body of a transaction submission.
+#[derive(Deserialize)]
+pub struct SendTransactionRequest {
+	/// The signed, serialized transaction.
+	transaction: String,
+	/// The encoding of the transaction, `base64` (default) or `base58`.
+	#[serde(default = "default_encoding")]
+	encoding: String,
+	#[serde(default)]
+	skip_preflight: bool,
+}
+
+fn default_encoding() -> String {
+	"base64".to_string()
+}
+
+/// Handler submitting a signed transaction through the RPC node and tracking it locally.
+///
+/// This asynchronous function forwards the transaction to `sendTransaction` as is and records the
+/// returned signature as `pending`. The status becomes `confirmed` or `failed` once the
+/// transaction appears in an ingested block.
+///
+/// # Arguments
+///
+/// * `state` - The `AppState`, whose `RpcGateway` is missing when the service does not talk to an
+///   RPC node. A database connection is only checked out once the RPC node accepted the
+///   transaction.
+/// * `request` - A `Json` extractor containing the `SendTransactionRequest`.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json` with the signature of the submitted transaction.
+/// - An error response with a `BAD_REQUEST` status if the encoding is not supported.
+/// - An error response with a `BAD_GATEWAY` status if the RPC node rejects the transaction.
+/// - An error response with a `SERVICE_UNAVAILABLE` status if no RPC node is configured.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database error.
+pub async fn send_transaction_handler(
+	state: AppState,
+	Json(request): Json<SendTransactionRequest>,
+) -> impl IntoResponse {
+	let Some(gateway) = state.gateway else {
+		return build_error_response(
+			StatusCode::SERVICE_UNAVAILABLE,
+			"Transaction submission requires an RPC node",
+		)
+		.into_response()
+	};
+	if request.encoding != "base64" && request.encoding != "base58" {
+		return build_error_response(StatusCode::BAD_REQUEST, "Unsupported encoding").into_response()
+	}
+
+	let signature = match gateway
+		.send_raw_transaction(&request.transaction, &request.encoding, request.skip_preflight)
+		.await
+	{
+		Ok(signature) => signature,
+		Err(err) =>
+			return build_error_response(StatusCode::BAD_GATEWAY, &err.to_string()).into_response(),
+	};
+
+	let recorded = {
+		let signature = signature.clone();
+		let submitted_at = chrono::Utc::now().timestamp();
+		pool::with_connection(&state.db, move |conn| {
+			submissions::insert_submission(conn, &signature, submitted_at)
+		})
+		.await
+	};
+	match recorded {
+		Ok(Ok(())) => Json(json!({ "signature": signature })).into_response(),
+		Ok(Err(err)) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+		Err(err) => {
+			error!("Failed to check out a database connection: {:?}", err);
+			build_error_response(StatusCode::SERVICE_UNAVAILABLE, "Database unavailable")
+				.into_response()
+		},
+	}
+}
+
+/// Handler for retrieving the status of a transaction submitted through the service.
+///
+/// # Arguments
+///
+/// * `signature` - A `Path` extractor containing the signature of the transaction.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<SubmissionRecord>` if the transaction was submitted through the service.
+/// - An error response with a `NOT_FOUND` status if the transaction is unknown.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_submission_handler(
+	Path(signature): Path<String>,
+	conn: DbConn,
+) -> impl IntoResponse {
+	match conn.interact(move |conn| submissions::get_submission(conn, &signature)).await {
+		Ok(Some(submission)) => Json(submission).into_response(),
+		Ok(None) =>
+			build_error_response(StatusCode::NOT_FOUND, "Submission not found").into_response(),
+		Err(err) => {
+			error!("Database query error: {:?}", err);
+			build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+				.into_response()
+		},
+	}
+}
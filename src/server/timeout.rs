@@ -0,0 +1,41 @@
+//! Time limit of the API handlers.
+//!
+//! A handler that has not responded within the configured limit is dropped, and the request is
+//! answered with a `SERVICE_UNAVAILABLE` status. Dropping the handler interrupts the database query
+//! it was waiting for (see `db::pool::interact`), so an expensive filter frees its connection
+//! instead of running to completion for a response nobody reads. Only the time to the response
+//! head is limited: streamed bodies and WebSocket sessions keep running once they started.
+use axum::{
+	http::{Request, StatusCode},
+	middleware::Next,
+	response::Response,
+};
+use std::time::Duration;
+use tracing::warn;
+
+use super::build_error_response;
+
+/// Bounds the time the handlers take to respond.
+#[derive(Clone, Copy)]
+pub struct RequestTimeout {
+	limit: Duration,
+}
+
+impl RequestTimeout {
+	pub fn new(limit: Duration) -> Self {
+		Self { limit }
+	}
+
+	/// Middleware answering the requests whose handler exceeds the limit with a
+	/// `SERVICE_UNAVAILABLE` status, dropping the handler.
+	pub async fn handle<B>(self, request: Request<B>, next: Next<B>) -> Response {
+		let (method, uri) = (request.method().clone(), request.uri().clone());
+		match tokio::time::timeout(self.limit, next.run(request)).await {
+			Ok(response) => response,
+			Err(_) => {
+				warn!("{} {} timed out after {:?}", method, uri, self.limit);
+				build_error_response(StatusCode::SERVICE_UNAVAILABLE, "Request timed out")
+			},
+		}
+	}
+}
@@ -0,0 +1,67 @@
+//! Units of the lamport amounts returned by the transfer and balance endpoints.
+//!
+//! Amounts are returned as integer lamports by default. In SOL, they are returned as exact decimal
+//! strings instead, formatted from the integer amounts, so clients neither lose precision to
+//! floating point nor reimplement the conversion and rounding.
+use serde::Serialize;
+use serde_json::Value;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+
+use crate::types::AmountUnit;
+
+/// Number of decimals of an amount of SOL.
+const SOL_DECIMALS: usize = 9;
+
+/// Formats an amount of lamports as a decimal string of SOL, without trailing zeros.
+pub fn format_sol(lamports: i128) -> String {
+	let sign = if lamports < 0 { "-" } else { "" };
+	let lamports = lamports.unsigned_abs();
+	let whole = lamports / LAMPORTS_PER_SOL as u128;
+	let fraction = lamports % LAMPORTS_PER_SOL as u128;
+	if fraction == 0 {
+		return format!("{}{}", sign, whole)
+	}
+	let fraction = format!("{:0width$}", fraction, width = SOL_DECIMALS);
+	format!("{}{}.{}", sign, whole, fraction.trim_end_matches('0'))
+}
+
+/// Serializes a response with its lamport fields in the requested unit.
+///
+/// # Arguments
+///
+/// * `value` - The response, with its amounts in lamports.
+/// * `unit` - The unit to return the amounts in.
+/// * `fields` - The names of the lamport fields, converted at any depth of the response.
+///
+/// # Errors
+///
+/// This function returns an error if the response cannot be serialized.
+pub fn with_unit<T: Serialize>(
+	value: T,
+	unit: AmountUnit,
+	fields: &[&str],
+) -> Result<Value, serde_json::Error> {
+	let mut value = serde_json::to_value(value)?;
+	if unit == AmountUnit::Sol {
+		convert_fields(&mut value, fields);
+	}
+	Ok(value)
+}
+
+fn convert_fields(value: &mut Value, fields: &[&str]) {
+	match value {
+		Value::Object(map) =>
+			for (key, field) in map.iter_mut() {
+				match field.as_i64().map(i128::from).or_else(|| field.as_u64().map(i128::from)) {
+					Some(lamports) if fields.contains(&key.as_str()) =>
+						*field = Value::String(format_sol(lamports)),
+					_ => convert_fields(field, fields),
+				}
+			},
+		Value::Array(items) =>
+			for item in items {
+				convert_fields(item, fields);
+			},
+		_ => {},
+	}
+}
@@ -0,0 +1,233 @@
+//! Handlers for registering webhooks and inspecting their deliveries.
+use axum::{
+	extract::{Path, Query},
+	http::StatusCode,
+	response::{IntoResponse, Json, Response},
+};
+use serde::Deserialize;
+use solana_program::pubkey::Pubkey;
+use std::{error::Error, str::FromStr};
+use tracing::{error, info};
+
+use super::{build_error_response, row_limit, state::AppState, DbConn};
+use crate::db::webhooks::{self, DeliveryStatus};
+
+/// Maximum number of addresses watched by a webhook.
+const MAX_WEBHOOK_ADDRESSES: usize = 100;
+
+/// Body of a webhook registration.
+#[derive(Deserialize)]
+pub struct WebhookRequest {
+	url: String,
+	addresses: Vec<String>,
+}
+
+/// Query parameters for listing the deliveries of a webhook.
+#[derive(Deserialize)]
+pub struct DeliveriesQuery {
+	status: Option<DeliveryStatus>,
+	limit: Option<usize>,
+}
+
+/// Handler for listing the registered webhooks along with their addresses.
+///
+/// # Arguments
+///
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<WebhookRecord>>` with the webhooks, ordered by id.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn list_webhooks_handler(conn: DbConn) -> impl IntoResponse {
+	match conn.interact(webhooks::list_webhooks).await {
+		Ok(webhooks) => Json(webhooks).into_response(),
+		Err(err) => internal_error(err),
+	}
+}
+
+/// Handler for registering a webhook.
+///
+/// The webhook is notified of the transfers of the blocks ingested from then on.
+///
+/// # Arguments
+///
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+/// * `request` - A `Json` extractor containing the `WebhookRequest` with the URL of the webhook and
+///   the addresses it watches.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<WebhookRecord>` with a `CREATED` status.
+/// - An error response with a `BAD_REQUEST` status if the URL is not an HTTP(S) URL, if an address
+///   is not a valid pubkey or if there are no or too many addresses.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database error.
+pub async fn create_webhook_handler(
+	conn: DbConn,
+	Json(request): Json<WebhookRequest>,
+) -> impl IntoResponse {
+	let valid_url = reqwest::Url::parse(&request.url)
+		.is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+	if !valid_url {
+		return build_error_response(StatusCode::BAD_REQUEST, "Invalid webhook URL").into_response()
+	}
+	let mut addresses = request.addresses;
+	addresses.sort();
+	addresses.dedup();
+	if addresses.is_empty() || addresses.len() > MAX_WEBHOOK_ADDRESSES {
+		return build_error_response(
+			StatusCode::BAD_REQUEST,
+			"A webhook watches 1 to 100 addresses",
+		)
+		.into_response()
+	}
+	if let Some(address) = addresses.iter().find(|address| Pubkey::from_str(address).is_err()) {
+		return build_error_response(
+			StatusCode::BAD_REQUEST,
+			&format!("Invalid address: {}", address),
+		)
+		.into_response()
+	}
+
+	let (url, created_at) = (request.url, chrono::Utc::now().timestamp());
+	match conn
+		.interact(move |conn| webhooks::create_webhook(conn, &url, &addresses, created_at))
+		.await
+	{
+		Ok(webhook) => {
+			info!("Registered webhook {} posting to {}", webhook.id, webhook.url);
+			(StatusCode::CREATED, Json(webhook)).into_response()
+		},
+		Err(err) => internal_error(err),
+	}
+}
+
+/// Handler for retrieving a webhook along with its addresses.
+///
+/// # Arguments
+///
+/// * `id` - A `Path` extractor containing the id of the webhook.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<WebhookRecord>` if the webhook exists.
+/// - An error response with a `NOT_FOUND` status if it does not.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_webhook_handler(Path(id): Path<i64>, conn: DbConn) -> impl IntoResponse {
+	match conn.interact(move |conn| webhooks::get_webhook(conn, id)).await {
+		Ok(Some(webhook)) => Json(webhook).into_response(),
+		Ok(None) => webhook_not_found(),
+		Err(err) => internal_error(err),
+	}
+}
+
+/// Handler for deleting a webhook along with its deliveries.
+///
+/// # Arguments
+///
+/// * `id` - A `Path` extractor containing the id of the webhook.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - A `NO_CONTENT` response if the webhook was deleted.
+/// - An error response with a `NOT_FOUND` status if the webhook does not exist.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database error.
+pub async fn delete_webhook_handler(Path(id): Path<i64>, conn: DbConn) -> impl IntoResponse {
+	match conn.interact(move |conn| webhooks::delete_webhook(conn, id)).await {
+		Ok(true) => {
+			info!("Deleted webhook {}", id);
+			StatusCode::NO_CONTENT.into_response()
+		},
+		Ok(false) => webhook_not_found(),
+		Err(err) => internal_error(err),
+	}
+}
+
+/// Handler for listing the deliveries of a webhook, newest first.
+///
+/// # Arguments
+///
+/// * `id` - A `Path` extractor containing the id of the webhook.
+/// * `params` - A `Query` extractor containing the `DeliveriesQuery`, `status` restricting the
+///   deliveries to the `pending`, `delivered` or `failed` ones and `limit` defaulting to the
+///   maximum number of rows.
+/// * `state` - The `AppState`, providing the maximum number of rows.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<Vec<DeliveryRecord>>` with the deliveries, newest first.
+/// - An error response with a `BAD_REQUEST` status if the limit is out of range.
+/// - An error response with a `NOT_FOUND` status if the webhook does not exist.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database query error.
+pub async fn get_webhook_deliveries_handler(
+	Path(id): Path<i64>,
+	Query(params): Query<DeliveriesQuery>,
+	state: AppState,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let limit = match row_limit(params.limit, state.max_rows) {
+		Ok(limit) => limit,
+		Err(message) =>
+			return build_error_response(StatusCode::BAD_REQUEST, &message).into_response(),
+	};
+	match conn
+		.interact(move |conn| {
+			if webhooks::get_webhook(conn, id)?.is_none() {
+				return Ok(None)
+			}
+			webhooks::get_deliveries(conn, id, params.status, limit).map(Some)
+		})
+		.await
+	{
+		Ok(Some(deliveries)) => Json(deliveries).into_response(),
+		Ok(None) => webhook_not_found(),
+		Err(err) => internal_error(err),
+	}
+}
+
+/// Handler scheduling a delivery of a webhook again right away, whatever its status, so a consumer
+/// can request a notification it missed.
+///
+/// # Arguments
+///
+/// * `id` - A `Path` extractor containing the id of the webhook and of the delivery.
+/// * `conn` - A `DbConn` extractor providing a pooled connection to the database.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - `Json<DeliveryRecord>` with the rescheduled delivery.
+/// - An error response with a `NOT_FOUND` status if the webhook has no such delivery.
+/// - An error response with an `INTERNAL_SERVER_ERROR` status if there is a database error.
+pub async fn redeliver_handler(
+	Path((id, delivery_id)): Path<(i64, i64)>,
+	conn: DbConn,
+) -> impl IntoResponse {
+	let now = chrono::Utc::now().timestamp();
+	match conn.interact(move |conn| webhooks::redeliver(conn, id, delivery_id, now)).await {
+		Ok(Some(delivery)) => {
+			info!("Scheduled delivery {} of webhook {} again", delivery_id, id);
+			Json(delivery).into_response()
+		},
+		Ok(None) =>
+			build_error_response(StatusCode::NOT_FOUND, "Delivery not found").into_response(),
+		Err(err) => internal_error(err),
+	}
+}
+
+fn webhook_not_found() -> Response {
+	build_error_response(StatusCode::NOT_FOUND, "Webhook not found").into_response()
+}
+
+fn internal_error(err: Box<dyn Error + Send + Sync>) -> Response {
+	error!("Database query error: {:?}", err);
+	build_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+}
@@ -0,0 +1,246 @@
+//! WebSocket endpoints: `accountSubscribe` style subscriptions and the live transactions.
+//!
+//! On `/ws`, clients speak a subset of the Solana PubSub JSON-RPC protocol: `accountSubscribe`
+//! with the account as first parameter, and `accountUnsubscribe` with the subscription id, which is
+//! the account itself. Notifications follow the `accountNotification` shape.
+//!
+//! `/ws/transactions` pushes every transaction the ingestion stores, with its transfers, once its
+//! block is committed. The messages are assembled from the events of the writer, so a client
+//! reading the API on a message finds the transaction stored. A client falling behind the event bus
+//! gets a `lagged` message with the number of events it missed instead.
+use axum::{
+	extract::ws::{Message, WebSocket, WebSocketUpgrade},
+	http::StatusCode,
+	response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use solana_program::pubkey::Pubkey;
+use std::str::FromStr;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio_stream::{
+	wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+	StreamExt, StreamMap,
+};
+use tracing::warn;
+
+use super::{build_error_response, dto::StreamedTransaction, state::AppState};
+use crate::{events::Event, pubsub::PubsubHub};
+
+/// A JSON-RPC request sent by a WebSocket client.
+#[derive(Deserialize)]
+struct WsRequest {
+	#[serde(default)]
+	id: Value,
+	method: String,
+	#[serde(default)]
+	params: Vec<Value>,
+}
+
+/// Handler upgrading a connection to the WebSocket subscription protocol.
+///
+/// # Arguments
+///
+/// * `ws` - The `WebSocketUpgrade` extractor of the connection.
+/// * `state` - The `AppState`, whose `PubsubHub` is missing when the service does not talk to an
+///   RPC node.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - The protocol switch response, after which subscriptions are served on the socket.
+/// - An error response with a `SERVICE_UNAVAILABLE` status if no PubSub endpoint is available.
+pub async fn ws_handler(ws: WebSocketUpgrade, state: AppState) -> impl IntoResponse {
+	match state.pubsub {
+		Some(hub) => ws.on_upgrade(move |socket| serve_socket(socket, hub)),
+		None => build_error_response(
+			StatusCode::SERVICE_UNAVAILABLE,
+			"Subscriptions require an RPC node",
+		),
+	}
+}
+
+/// Serves the requests and notifications of a socket until it closes.
+async fn serve_socket(mut socket: WebSocket, hub: PubsubHub) {
+	let mut subscriptions: StreamMap<String, BroadcastStream<String>> = StreamMap::new();
+
+	loop {
+		tokio::select! {
+			message = socket.recv() => match message {
+				Some(Ok(Message::Text(text))) => {
+					let reply = handle_request(&text, &hub, &mut subscriptions).await;
+					if socket.send(Message::Text(reply.to_string())).await.is_err() {
+						break
+					}
+				},
+				Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+				Some(Ok(_)) => {},
+			},
+			Some((subscription, update)) = subscriptions.next(), if !subscriptions.is_empty() => {
+				let update = match update {
+					Ok(update) => update,
+					Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+						warn!("Subscriber of {} lagged, {} updates skipped", subscription, skipped);
+						continue
+					},
+				};
+				let notification = format!(
+					r#"{{"jsonrpc":"2.0","method":"accountNotification","params":{{"subscription":"{}","result":{}}}}}"#,
+					subscription, update
+				);
+				if socket.send(Message::Text(notification)).await.is_err() {
+					break
+				}
+			},
+		}
+	}
+}
+
+/// Handles a JSON-RPC request of a client, returning the response to send back.
+async fn handle_request(
+	text: &str,
+	hub: &PubsubHub,
+	subscriptions: &mut StreamMap<String, BroadcastStream<String>>,
+) -> Value {
+	let request: WsRequest = match serde_json::from_str(text) {
+		Ok(request) => request,
+		Err(_) => return error_response(Value::Null, -32700, "Parse error"),
+	};
+	let account = request.params.first().and_then(Value::as_str).map(str::to_string);
+
+	match (request.method.as_str(), account) {
+		("accountSubscribe", Some(account)) => {
+			let Ok(pubkey) = Pubkey::from_str(&account) else {
+				return error_response(request.id, -32602, "Invalid param: Invalid pubkey")
+			};
+			match hub.subscribe(pubkey).await {
+				Ok(receiver) => {
+					subscriptions.insert(account.clone(), BroadcastStream::new(receiver));
+					json!({ "jsonrpc": "2.0", "id": request.id, "result": account })
+				},
+				Err(_) => error_response(request.id, -32603, "Internal error"),
+			}
+		},
+		("accountUnsubscribe", Some(subscription)) => {
+			let removed = subscriptions.remove(&subscription).is_some();
+			json!({ "jsonrpc": "2.0", "id": request.id, "result": removed })
+		},
+		("accountSubscribe", None) | ("accountUnsubscribe", None) =>
+			error_response(request.id, -32602, "Invalid params"),
+		_ => error_response(request.id, -32601, "Method not found"),
+	}
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+	json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// A message of the live transaction stream.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamMessage {
+	Transaction(StreamedTransaction),
+	/// The client fell behind and missed some events, hence transactions.
+	Lagged {
+		skipped: u64,
+	},
+}
+
+/// Handler upgrading a connection to the stream of the newly stored transactions.
+///
+/// # Arguments
+///
+/// * `ws` - The `WebSocketUpgrade` extractor of the connection.
+/// * `state` - The `AppState`, whose `EventBus` is missing when the service does not ingest blocks.
+///
+/// # Returns
+///
+/// This function returns an `impl IntoResponse` which can be either:
+/// - The protocol switch response, after which the transactions are streamed on the socket.
+/// - An error response with a `SERVICE_UNAVAILABLE` status if the service does not ingest blocks.
+pub async fn transactions_ws_handler(ws: WebSocketUpgrade, state: AppState) -> impl IntoResponse {
+	match state.events {
+		Some(events) => {
+			let events = events.subscribe();
+			ws.on_upgrade(move |socket| stream_transactions(socket, events))
+		},
+		None => build_error_response(
+			StatusCode::SERVICE_UNAVAILABLE,
+			"Live transactions require the ingestion",
+		),
+	}
+}
+
+/// Sends the transactions stored from now on to a socket, until it closes.
+async fn stream_transactions(mut socket: WebSocket, mut events: Receiver<Event>) {
+	let mut assembler = TransactionAssembler::default();
+
+	loop {
+		let message = tokio::select! {
+			message = socket.recv() => match message {
+				Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+				// The stream takes no requests
+				Some(Ok(_)) => continue,
+			},
+			event = events.recv() => match event {
+				Ok(event) => match assembler.push(event) {
+					Some(transaction) => StreamMessage::Transaction(transaction),
+					None => continue,
+				},
+				Err(RecvError::Lagged(skipped)) => {
+					// The transfers of the pending transaction may be among the missed events
+					assembler.pending = None;
+					StreamMessage::Lagged { skipped }
+				},
+				Err(RecvError::Closed) => break,
+			},
+		};
+		let text = serde_json::to_string(&message).unwrap_or_default();
+		if socket.send(Message::Text(text)).await.is_err() {
+			break
+		}
+	}
+}
+
+/// Assembles the transactions of the stream from the events of the writer.
+///
+/// A transaction is complete once the next transaction or its block is stored, as the writer
+/// publishes the events of its transfers in between.
+#[derive(Default)]
+pub struct TransactionAssembler {
+	pending: Option<StreamedTransaction>,
+}
+
+impl TransactionAssembler {
+	/// Adds an event to the pending transaction.
+	///
+	/// # Returns
+	///
+	/// This function returns the transaction the event completes, if any.
+	pub fn push(&mut self, event: Event) -> Option<StreamedTransaction> {
+		match event {
+			Event::TransactionStored { signature, slot, block_time, fee, fee_payer, error } =>
+				self.pending.replace(StreamedTransaction {
+					transaction_id: signature,
+					block_height: slot,
+					block_time,
+					fee,
+					fee_payer,
+					error,
+					transfers: Vec::new(),
+				}),
+			Event::TransferStored(transfer) => {
+				if let Some(pending) = self
+					.pending
+					.as_mut()
+					.filter(|pending| pending.transaction_id == transfer.transaction_id)
+				{
+					pending.transfers.push(transfer.into());
+				}
+				None
+			},
+			Event::BlockIngested { .. } => self.pending.take(),
+			Event::ReorgDetected { .. } | Event::AnomalyDetected(_) => None,
+		}
+	}
+}
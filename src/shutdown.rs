@@ -0,0 +1,75 @@
+//! Graceful shutdown of the service on `SIGINT` or `SIGTERM`.
+//!
+//! A signal requests the shutdown once, through a `Shutdown` handle shared by the tasks that must
+//! not be cut off mid-write. The ingestion stops at a block boundary: the block being written is
+//! handed to the writer, the ingestion progress is recorded up to it, and the queued writes are
+//! committed before the ingestion returns. The API listeners stop accepting connections and finish
+//! the requests in flight. Once both are done, the process exits; the other background tasks are
+//! dropped, as their writes are either idempotent or retried on the next run.
+use std::{io, sync::Arc};
+use tokio::{
+	signal::unix::{signal, SignalKind},
+	sync::watch,
+};
+use tracing::{error, info};
+
+/// A handle to request the shutdown of the service, or to wait until it is requested.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+	requested: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Shutdown {
+	/// Creates a handle on which no shutdown is requested yet.
+	pub fn new() -> Self {
+		let (requested, _) = watch::channel(false);
+		Self { requested: Arc::new(requested) }
+	}
+
+	/// Requests the shutdown, waking every task waiting for it.
+	pub fn request(&self) {
+		self.requested.send_replace(true);
+	}
+
+	/// Returns whether the shutdown has been requested.
+	pub fn is_requested(&self) -> bool {
+		*self.requested.borrow()
+	}
+
+	/// Waits until the shutdown is requested, returning right away if it already is.
+	pub async fn requested(&self) {
+		let mut requested = self.requested.subscribe();
+		// The sender lives as long as `self`, so the channel cannot close while waiting
+		let _ = requested.wait_for(|requested| *requested).await;
+	}
+
+	/// Requests the shutdown on the first `SIGINT` or `SIGTERM` received by the process.
+	///
+	/// If the signals cannot be listened to, the error is logged and the shutdown is only requested
+	/// through the handle.
+	pub async fn request_on_signal(self) {
+		match wait_for_signal().await {
+			Ok(name) => {
+				info!("Received {}, shutting down", name);
+				self.request();
+			},
+			Err(err) => error!("Failed to listen for shutdown signals: {:?}", err),
+		}
+	}
+}
+
+/// Waits for a `SIGINT` or a `SIGTERM`, and returns its name.
+async fn wait_for_signal() -> io::Result<&'static str> {
+	let mut interrupt = signal(SignalKind::interrupt())?;
+	let mut terminate = signal(SignalKind::terminate())?;
+	Ok(tokio::select! {
+		_ = interrupt.recv() => "SIGINT",
+		_ = terminate.recv() => "SIGTERM",
+	})
+}
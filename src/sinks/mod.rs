@@ -0,0 +1,166 @@
+//! Publication of the stored transactions to a message bus.
+//!
+//! With a `[message_bus]` section, every transaction the ingestion stores is published, with its
+//! transfers, to a Kafka topic or a NATS subject, so downstream consumers do not have to poll the
+//! API. The messages are the JSON objects streamed by `/ws/transactions`, assembled from the events
+//! of the writer once the block of the transaction is committed; Kafka messages are keyed by the
+//! signature. Publication is best effort and never slows the ingestion down: the sink waits for
+//! room in the queue of the client while the bus is slow or unreachable, up to `QUEUE_TIMEOUT`,
+//! and falls behind the event bus meanwhile, skipping the events it lagged past. A message the
+//! bus rejects or that times out is logged and dropped.
+use async_nats::{Client, ConnectOptions, Event as NatsEvent, ServerAddr};
+use rdkafka::{
+	error::{KafkaError, RDKafkaErrorCode},
+	producer::{FutureProducer, FutureRecord},
+	util::Timeout,
+	ClientConfig,
+};
+use std::{error::Error, time::Duration};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::{error, info, warn};
+
+use crate::{
+	events::Event,
+	server::{dto::StreamedTransaction, ws::TransactionAssembler},
+	types::MessageBusConfig,
+};
+
+/// How long a message waits for room in the queue of the client before it is dropped.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The client of a message bus.
+pub enum MessageBus {
+	Kafka { producer: FutureProducer, topic: String },
+	Nats { client: Client, subject: String },
+}
+
+impl MessageBus {
+	/// Creates the client of the configured bus.
+	///
+	/// Both clients connect in the background and keep reconnecting, so an unavailable bus does not
+	/// prevent the service from starting.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the configuration is invalid.
+	pub async fn new(config: &MessageBusConfig) -> Result<Self, Box<dyn Error + Send + Sync>> {
+		match config {
+			MessageBusConfig::Kafka(kafka) => {
+				let mut client_config = ClientConfig::new();
+				client_config.set("bootstrap.servers", &kafka.brokers);
+				for (key, value) in &kafka.properties {
+					client_config.set(key, value);
+				}
+				let producer = client_config.create()?;
+				info!("Publishing the stored transactions to the Kafka topic {}", kafka.topic);
+				Ok(Self::Kafka { producer, topic: kafka.topic.clone() })
+			},
+			MessageBusConfig::Nats(nats) => {
+				if nats.subject.is_empty() || nats.subject.contains(char::is_whitespace) {
+					return Err(format!("Invalid NATS subject {:?}", nats.subject).into())
+				}
+				// The client ignores the credentials of the URL, passed as options instead
+				let server: ServerAddr = nats.url.parse()?;
+				let options = match (server.username(), server.password()) {
+					(Some(user), Some(password)) => ConnectOptions::with_user_and_password(
+						user.to_string(),
+						password.to_string(),
+					),
+					(Some(token), None) => ConnectOptions::with_token(token.to_string()),
+					_ => ConnectOptions::new(),
+				};
+				let client = options
+					.name(env!("CARGO_PKG_NAME"))
+					.retry_on_initial_connect()
+					.event_callback(|event| async move {
+						match event {
+							NatsEvent::Connected => info!("Connected to the NATS server"),
+							event => warn!("NATS connection event: {}", event),
+						}
+					})
+					.connect(server)
+					.await?;
+				info!("Publishing the stored transactions to the NATS subject {}", nats.subject);
+				Ok(Self::Nats { client, subject: nats.subject.clone() })
+			},
+		}
+	}
+
+	/// Publishes a stored transaction.
+	///
+	/// The message is queued by the client of the bus; Kafka delivery failures are logged once the
+	/// broker answers. A full queue is waited on, so the sink falls behind the event bus rather
+	/// than dropping messages while the bus is slow.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the message cannot be queued, or the queue stays full for
+	/// `QUEUE_TIMEOUT`.
+	pub async fn publish(
+		&self,
+		transaction: &StreamedTransaction,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		let payload = serde_json::to_vec(transaction)?;
+		match self {
+			Self::Kafka { producer, topic } => {
+				let key = transaction.transaction_id.as_str();
+				let record = FutureRecord::to(topic).key(key).payload(&payload);
+				match producer.send_result(record) {
+					Ok(delivery) => {
+						let key = key.to_string();
+						tokio::spawn(async move {
+							match delivery.await {
+								Ok(Ok(_)) => {},
+								Ok(Err((err, _))) => error!(
+									"Failed to deliver transaction {} to Kafka: {:?}",
+									key, err
+								),
+								Err(_) => error!("Kafka producer dropped transaction {}", key),
+							}
+						});
+					},
+					// Wait for room in the queue, which also waits for the delivery
+					Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), record)) => {
+						producer
+							.send(record, Timeout::After(QUEUE_TIMEOUT))
+							.await
+							.map_err(|(err, _)| err)?;
+					},
+					Err((err, _)) => return Err(err.into()),
+				}
+			},
+			Self::Nats { client, subject } => {
+				let publish = client.publish(subject.clone(), payload.into());
+				tokio::time::timeout(QUEUE_TIMEOUT, publish)
+					.await
+					.map_err(|_| "Timed out waiting for room in the NATS client queue")??;
+			},
+		}
+		Ok(())
+	}
+}
+
+/// Publishes the transactions stored from now on to the bus, until the event bus is closed.
+pub async fn run_message_sink(bus: MessageBus, mut events: Receiver<Event>) {
+	let mut assembler = TransactionAssembler::default();
+	loop {
+		let event = match events.recv().await {
+			Ok(event) => event,
+			Err(RecvError::Lagged(skipped)) => {
+				// The transfers of the pending transaction may be among the missed events
+				warn!("Message bus publication lagged, {} events skipped", skipped);
+				assembler = TransactionAssembler::default();
+				continue
+			},
+			Err(RecvError::Closed) => break,
+		};
+		if let Some(transaction) = assembler.push(event) {
+			if let Err(err) = bus.publish(&transaction).await {
+				error!(
+					"Failed to publish transaction {} to the message bus: {:?}",
+					transaction.transaction_id, err
+				);
+			}
+		}
+	}
+}
@@ -1,5 +1,17 @@
 use super::*;
-use crate::aggregator::processor::{get_transaction_signature, parse_block};
+use crate::{
+	aggregator::{
+		epochs::{catalog_block, catalog_stored_epochs},
+		idl::{account_discriminator, Idl},
+		processor::{discover_accounts, get_block_record, get_transaction_signature, parse_block},
+		replace_transaction,
+		retrieval::ProgramAccount,
+		snapshots::snapshot_account,
+		store_parsed_block,
+	},
+	db::{epochs::EpochRecord, recent_writes::RecentWrites},
+};
+use solana_sdk::epoch_schedule::EpochSchedule;
 use solana_transaction_status::{
 	EncodedTransaction, EncodedTransactionWithStatusMeta, TransactionStatusMeta, UiConfirmedBlock,
 	UiInnerInstructions, UiInstruction, UiMessage, UiParsedInstruction, UiTransaction,
@@ -111,15 +123,2858 @@ fn test_parse_block() {
 	assert!(result.is_ok());
 	let transactions = result.unwrap();
 	assert_eq!(transactions.len(), 1);
-	let (signature, encoded_tx, details) = &transactions[0];
+	let parsed = &transactions[0];
+	let (signature, encoded_tx, details) =
+		(&parsed.signature, &parsed.transaction, &parsed.details);
 	assert_eq!(
 		signature,
 		"2xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1"
 	);
 	assert!(matches!(encoded_tx, EncodedTransaction::Json(_)));
-	let details = details.as_ref().unwrap();
+	assert_eq!(details.len(), 1);
+	let details = &details[0];
 	assert_eq!(details.sender, "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g");
 	assert_eq!(details.receiver, "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ");
 	assert_eq!(details.amount, 967);
 	assert_eq!(details.timestamp, Some(1720421680));
+	assert_eq!(details.kind, TransferKind::Transfer);
+	assert_eq!(parsed.fee, 5040);
+	assert_eq!(parsed.fee_payer, "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g");
+	assert_eq!(parsed.compute_unit_limit, Some(500));
+	assert_eq!(parsed.compute_unit_price, Some(80000));
+}
+
+#[test]
+fn test_parse_block_classifies_rent_deposit() {
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	block["transactions"][0]["transaction"]["message"]["instructions"][2]["parsed"] = json!({
+		"info": {
+			"lamports": 2039280,
+			"newAccount": "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ",
+			"owner": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+			"source": "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g",
+			"space": 165
+		},
+		"type": "createAccount"
+	});
+	let block: UiConfirmedBlock = serde_json::from_value(block).unwrap();
+
+	let transactions = parse_block(&block).unwrap();
+	let details = &transactions[0].details[0];
+	assert_eq!(details.sender, "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g");
+	assert_eq!(details.receiver, "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ");
+	assert_eq!(details.amount, 2039280);
+	assert_eq!(details.kind, TransferKind::RentDeposit);
+}
+
+#[test]
+fn test_parse_block_keeps_every_top_level_transfer() {
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let instructions = &mut block["transactions"][0]["transaction"]["message"]["instructions"];
+	let mut second = instructions[2].clone();
+	second["parsed"]["info"]["lamports"] = json!(1000);
+	instructions.as_array_mut().unwrap().push(second);
+	let block: UiConfirmedBlock = serde_json::from_value(block).unwrap();
+
+	let transactions = parse_block(&block).unwrap();
+	let amounts: Vec<_> = transactions[0].details.iter().map(|details| details.amount).collect();
+	assert_eq!(amounts, [967, 1000]);
+}
+
+#[test]
+fn test_json_logs_carry_fields_and_spans() {
+	use crate::{logging, types::LogFormat};
+	use std::{
+		io,
+		sync::{Arc, Mutex},
+	};
+	use tracing_subscriber::EnvFilter;
+
+	#[derive(Clone, Default)]
+	struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+	impl io::Write for Buffer {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.0.lock().unwrap().write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	// A transaction in an encoding the parser does not support
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	block["transactions"][0]["transaction"] = json!(["AQ==", "base64"]);
+	let block: UiConfirmedBlock = serde_json::from_value(block).unwrap();
+
+	let buffer = Buffer::default();
+	let subscriber = logging::subscriber(LogFormat::Json, EnvFilter::new("info"), {
+		let buffer = buffer.clone();
+		move || buffer.clone()
+	});
+	let transactions = tracing::subscriber::with_default(subscriber, || {
+		let _span = tracing::info_span!("slot", slot = 310176000).entered();
+		parse_block(&block).unwrap()
+	});
+	assert!(transactions.is_empty());
+
+	let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+	let lines: Vec<serde_json::Value> =
+		output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+	assert_eq!(lines.len(), 1);
+	assert_eq!(lines[0]["level"], "ERROR");
+	assert_eq!(lines[0]["message"], "Failed to parse transaction");
+	assert_eq!(lines[0]["error_kind"], "transaction_parse");
+	assert_eq!(lines[0]["error"], "Unsupported transaction encoding");
+	assert_eq!(
+		lines[0]["spans"],
+		json!([{ "name": "slot", "slot": 310176000 }, { "name": "transaction", "index": 0 }])
+	);
+}
+
+#[test]
+fn test_store_parsed_block_resolves_token_account_owners() {
+	let token_account = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+	let wallet = "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM";
+	// Lamports sent to a wrapped SOL account owned by the wallet
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let balance = |amount: &str| {
+		json!([{
+			"accountIndex": 1,
+			"mint": "So11111111111111111111111111111111111111112",
+			"owner": wallet,
+			"programId": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+			"uiTokenAmount": { "amount": amount, "decimals": 9, "uiAmount": null, "uiAmountString": amount }
+		}])
+	};
+	block["transactions"][0]["meta"]["preTokenBalances"] = balance("0");
+	block["transactions"][0]["meta"]["postTokenBalances"] = balance("967");
+	let transactions = parse_block(&serde_json::from_value(block).unwrap()).unwrap();
+	let details = &transactions[0].details[0];
+	assert_eq!(
+		(details.sender_owner.as_deref(), details.receiver_owner.as_deref()),
+		(None, Some(wallet))
+	);
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+
+	// The transfer and the transaction are listed for the wallet
+	let transfers = db::get_transfers(&conn, wallet, None, None, 10).unwrap();
+	assert_eq!(transfers.len(), 1);
+	assert_eq!(transfers[0].receiver, token_account);
+	let transfers = crate::server::account_transfers(wallet, transfers);
+	assert_eq!((transfers[0].direction, transfers[0].net_flow), (TransferDirection::In, 967));
+	let signatures =
+		db::get_account_signatures(&conn, wallet, None, None, &Default::default(), 10).unwrap();
+	assert_eq!(signatures.len(), 1);
+
+	// Closed token accounts are resolved from the instruction
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	block["transactions"][0]["transaction"]["message"]["instructions"][2] = json!({
+		"parsed": {
+			"info": { "account": token_account, "destination": wallet, "owner": wallet },
+			"type": "closeAccount"
+		},
+		"program": "spl-token",
+		"programId": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+		"stackHeight": null
+	});
+	let transactions = parse_block(&serde_json::from_value(block).unwrap()).unwrap();
+	let details = &transactions[0].details[0];
+	assert_eq!(details.kind, TransferKind::AccountClose);
+	assert_eq!(details.sender_owner.as_deref(), Some(wallet));
+
+	// Token transfers are not lamport transfers, but their transaction is listed for the wallet
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	block["transactions"][0]["meta"]["preTokenBalances"] = balance("967");
+	block["transactions"][0]["meta"]["postTokenBalances"] = balance("0");
+	block["transactions"][0]["transaction"]["message"]["instructions"][2] = json!({
+		"parsed": {
+			"info": {
+				"authority": wallet,
+				"destination": "5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1",
+				"mint": "So11111111111111111111111111111111111111112",
+				"source": token_account,
+				"tokenAmount": {
+					"amount": "967",
+					"decimals": 9,
+					"uiAmount": null,
+					"uiAmountString": "0.000000967"
+				}
+			},
+			"type": "transferChecked"
+		},
+		"program": "spl-token",
+		"programId": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+		"stackHeight": null
+	});
+	let transactions = parse_block(&serde_json::from_value(block).unwrap()).unwrap();
+	assert!(transactions[0].details.is_empty());
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	assert!(db::get_transfers(&conn, wallet, None, None, 10).unwrap().is_empty());
+	let signatures =
+		db::get_account_signatures(&conn, wallet, None, None, &Default::default(), 10).unwrap();
+	assert_eq!(signatures.len(), 1);
+}
+
+#[test]
+fn test_store_parsed_block_stores_inner_transfers() {
+	let sender = "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g";
+	let receiver = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+	let compute_budget = "ComputeBudget111111111111111111111111111111";
+	let jupiter = "JUP6LkbZbjS1jKKwapdHNy74zcZ3tQUkcPGMzq6Uqp4";
+	let transfer = |lamports: u64, stack_height: u32| {
+		json!({
+			"parsed": {
+				"info": { "destination": receiver, "lamports": lamports, "source": sender },
+				"type": "transfer"
+			},
+			"program": "system",
+			"programId": "11111111111111111111111111111111",
+			"stackHeight": stack_height
+		})
+	};
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	block["transactions"][0]["meta"]["innerInstructions"] = json!([{
+		"index": 0,
+		"instructions": [
+			transfer(100, 2),
+			{ "accounts": [], "data": "", "programId": jupiter, "stackHeight": 2 },
+			transfer(200, 3),
+		]
+	}]);
+	let transactions = parse_block(&serde_json::from_value(block.clone()).unwrap()).unwrap();
+	let inner = &transactions[0].inner_transfers;
+	assert_eq!(inner.len(), 2);
+	assert_eq!((inner[0].amount, inner[0].invoked_by.as_deref()), (100, Some(compute_budget)));
+	assert_eq!((inner[1].amount, inner[1].invoked_by.as_deref()), (200, Some(jupiter)));
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	let transfers = db::get_transfers(&conn, receiver, None, None, 10).unwrap();
+	let transfers: Vec<_> = transfers
+		.iter()
+		.map(|transfer| (transfer.transfer_index, transfer.amount, transfer.invoked_by.as_deref()))
+		.collect();
+	assert_eq!(
+		transfers,
+		vec![(0, 967, None), (1, 100, Some(compute_budget)), (2, 200, Some(jupiter))]
+	);
+	let account = db::get_account(&conn, receiver).unwrap().unwrap();
+	assert_eq!(account.total_received, 967 + 100 + 200);
+
+	// The transfers of failed transactions were rolled back, top level ones included
+	block["transactions"][0]["meta"]["err"] = json!({ "InstructionError": [2, { "Custom": 1 }] });
+	let transactions = parse_block(&serde_json::from_value(block).unwrap()).unwrap();
+	assert_eq!(transactions[0].transfers().count(), 0);
+}
+
+#[test]
+fn test_parse_block_extracts_instruction_error() {
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	block["transactions"][0]["meta"]["err"] = json!({ "InstructionError": [2, { "Custom": 1 }] });
+	let block: UiConfirmedBlock = serde_json::from_value(block).unwrap();
+
+	let transactions = parse_block(&block).unwrap();
+	let failure = transactions[0].error.as_ref().unwrap();
+	assert_eq!(failure.program_id.as_deref(), Some("11111111111111111111111111111111"));
+	assert_eq!(failure.error, "Custom(1)");
+
+	let block = create_mock_ui_confirmed_block();
+	let transactions = parse_block(&block).unwrap();
+	assert!(transactions[0].error.is_none());
+}
+
+fn token_balance(account_index: u8, mint: &str, owner: &str, amount: u64) -> serde_json::Value {
+	json!({
+		"accountIndex": account_index,
+		"mint": mint,
+		"owner": owner,
+		"programId": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+		"uiTokenAmount": {
+			"amount": amount.to_string(),
+			"decimals": 6,
+			"uiAmount": null,
+			"uiAmountString": "0"
+		}
+	})
+}
+
+#[test]
+fn test_parse_block_extracts_swap() {
+	let trader = "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g";
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let meta = &mut block["transactions"][0]["meta"];
+	meta["preTokenBalances"] = json!([
+		token_balance(4, "MintA", trader, 1000),
+		token_balance(5, "MintB", trader, 0),
+		token_balance(6, "MintA", "PoolAuthority", 50000),
+		token_balance(7, "MintB", "PoolAuthority", 80000),
+	]);
+	meta["postTokenBalances"] = json!([
+		token_balance(4, "MintA", trader, 400),
+		token_balance(5, "MintB", trader, 900),
+		token_balance(6, "MintA", "PoolAuthority", 50600),
+		token_balance(7, "MintB", "PoolAuthority", 79100),
+	]);
+	let block: UiConfirmedBlock = serde_json::from_value(block).unwrap();
+
+	let transactions = parse_block(&block).unwrap();
+	let swap = transactions[0].swap.as_ref().unwrap();
+	assert_eq!(swap.trader, trader);
+	assert_eq!(swap.pool, "PoolAuthority");
+	assert_eq!(swap.mint_in, "MintA");
+	assert_eq!(swap.amount_in, 600);
+	assert_eq!(swap.mint_out, "MintB");
+	assert_eq!(swap.amount_out, 900);
+
+	// A plain transfer is not a swap
+	let block = create_mock_ui_confirmed_block();
+	let transactions = parse_block(&block).unwrap();
+	assert!(transactions[0].swap.is_none());
+}
+
+#[test]
+fn test_detect_sandwiches() {
+	let swap = |index: u32, trader: &str, mint_in: &str, mint_out: &str| SwapRecord {
+		transaction_id: format!("tx{}", index),
+		block_height: 100,
+		transaction_index: index,
+		trader: trader.to_string(),
+		pool: "pool".to_string(),
+		mint_in: mint_in.to_string(),
+		amount_in: 1000,
+		mint_out: mint_out.to_string(),
+		amount_out: 1000,
+	};
+	let swaps = vec![
+		swap(0, "attacker", "SOL", "BONK"),
+		swap(1, "victim", "SOL", "BONK"),
+		swap(2, "bystander", "BONK", "SOL"),
+		swap(3, "attacker", "BONK", "SOL"),
+		swap(4, "victim", "BONK", "SOL"),
+	];
+
+	let sandwiches = crate::aggregator::mev::detect_sandwiches(&swaps);
+	assert_eq!(sandwiches.len(), 1);
+	assert_eq!(sandwiches[0].attacker, "attacker");
+	assert_eq!(sandwiches[0].victim, "victim");
+	assert_eq!(sandwiches[0].front_transaction_id, "tx0");
+	assert_eq!(sandwiches[0].victim_transaction_id, "tx1");
+	assert_eq!(sandwiches[0].back_transaction_id, "tx3");
+}
+
+#[test]
+fn test_parse_block_extracts_balance_changes() {
+	let block = create_mock_ui_confirmed_block();
+
+	let transactions = parse_block(&block).unwrap();
+	let changes = &transactions[0].balance_changes;
+	assert_eq!(changes.len(), 2);
+	assert_eq!(changes[0].account, "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g");
+	assert_eq!(changes[0].pre_balance, 771523253933);
+	assert_eq!(changes[0].post_balance, 771523247926);
+	assert_eq!(changes[1].account, "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ");
+	assert_eq!(changes[1].post_balance, 1798951577);
+}
+
+#[tokio::test]
+async fn test_db_writer_commits_blocks_before_checkpoint() {
+	let path = std::env::temp_dir().join(format!("aggregator-writer-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let conn = Connection::open(&path).unwrap();
+	initialize_db(&conn).unwrap();
+
+	let (writer, handle) = DbWriter::spawn(
+		Connection::open(&path).unwrap(),
+		StoragePolicy::default(),
+		EventBus::default(),
+	);
+	let block = create_mock_ui_confirmed_block();
+	let transactions = parse_block(&block).unwrap();
+	writer
+		.write_block(get_block_record(310176000, &block), transactions)
+		.await
+		.unwrap();
+	writer.checkpoint().await.unwrap();
+
+	let transaction = db::get_transaction(
+		&conn,
+		"2xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1",
+	)
+	.unwrap()
+	.unwrap();
+	assert_eq!(transaction.block_height, 310176000);
+	let transfers =
+		db::get_transfers(&conn, "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g", None, None, 100)
+			.unwrap();
+	assert_eq!(transfers.len(), 1);
+
+	// The header of the block is stored along with its transactions
+	let record = blocks::get_block(&conn, 310176000).unwrap().unwrap();
+	assert_eq!(
+		record,
+		BlockRecord {
+			slot: 310176000,
+			block_time: Some(1720421680),
+			blockhash: Some("AZ8jzQjcgFSKYZ47sUVGTn7nR3FowHoyszEo2Nwq8vae".to_string()),
+			previous_blockhash: Some("6RbXYJiJa8V7K5YJyS8YjkWsWf6Vuh5vGEzww7xSWigf".to_string()),
+			parent_slot: Some(310175999),
+			block_height: Some(298414228),
+			transaction_count: Some(1),
+		}
+	);
+	assert_eq!(
+		blocks::get_block_signatures(&conn, 310176000, None, 10).unwrap(),
+		vec![(0, transaction.transaction_id)]
+	);
+
+	// Updating the block time of the slot keeps its header
+	blocks::insert_or_update_block(&conn, 310176000, 1720421681).unwrap();
+	assert_eq!(
+		blocks::get_block(&conn, 310176000).unwrap(),
+		Some(BlockRecord { block_time: Some(1720421681), ..record })
+	);
+
+	drop(writer);
+	handle.join().unwrap();
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn test_db_writer_publishes_committed_blocks() {
+	let path = std::env::temp_dir().join(format!("aggregator-events-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	initialize_db(&Connection::open(&path).unwrap()).unwrap();
+	let events = EventBus::default();
+	let mut receiver = events.subscribe();
+	let (writer, handle) =
+		DbWriter::spawn(Connection::open(&path).unwrap(), StoragePolicy::default(), events);
+	let metrics = IngestionMetrics::default();
+	let mut received = Vec::new();
+
+	let block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let header = get_block_record(310176000, &serde_json::from_value(block.clone()).unwrap());
+	let transactions = parse_block(&serde_json::from_value(block.clone()).unwrap()).unwrap();
+	writer.write_block(header.clone(), transactions).await.unwrap();
+	writer.checkpoint().await.unwrap();
+	while let Ok(event) = receiver.try_recv() {
+		metrics.record(&event);
+		received.push(event);
+	}
+	assert_eq!(received.len(), 3);
+	match &received[0] {
+		Event::TransactionStored { signature, slot, error, .. } => {
+			assert_eq!(
+				signature,
+				"2xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1"
+			);
+			assert_eq!((*slot, error), (310176000, &None));
+		},
+		event => panic!("unexpected event {:?}", event),
+	}
+	match &received[1] {
+		Event::TransferStored(transfer) => {
+			assert_eq!(transfer.receiver, "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ");
+			assert_eq!(transfer.amount, 967);
+		},
+		event => panic!("unexpected event {:?}", event),
+	}
+	assert!(matches!(
+		received[2],
+		Event::BlockIngested {
+			slot: 310176000,
+			block_time: Some(1720421680),
+			transaction_count: 1
+		}
+	));
+
+	// Storing another block at the slot orphans the transactions it no longer holds, but storing
+	// part of a block does not
+	let mut other = block;
+	other["transactions"][0]["transaction"]["signatures"] = json!([
+		"3wBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1"
+	]);
+	let transactions = parse_block(&serde_json::from_value(other.clone()).unwrap()).unwrap();
+	writer
+		.write_transactions(310176000, Some(1720421680), transactions)
+		.await
+		.unwrap();
+	writer.checkpoint().await.unwrap();
+	received.clear();
+	while let Ok(event) = receiver.try_recv() {
+		metrics.record(&event);
+		received.push(event);
+	}
+	assert!(!received.iter().any(|event| matches!(event, Event::ReorgDetected { .. })));
+	let transactions = parse_block(&serde_json::from_value(other).unwrap()).unwrap();
+	writer.write_block(header, transactions).await.unwrap();
+	writer.checkpoint().await.unwrap();
+	received.clear();
+	while let Ok(event) = receiver.try_recv() {
+		metrics.record(&event);
+		received.push(event);
+	}
+	match &received[0] {
+		Event::ReorgDetected { slot, orphaned_transactions } => {
+			assert_eq!(*slot, 310176000);
+			assert_eq!(
+				orphaned_transactions,
+				&["2xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1"]
+			);
+		},
+		event => panic!("unexpected event {:?}", event),
+	}
+	// The orphaned transaction is rolled back, the one the block holds is kept
+	let conn = Connection::open(&path).unwrap();
+	assert!(db::get_transaction(
+		&conn,
+		"2xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1"
+	)
+	.unwrap()
+	.is_none());
+	assert!(db::get_transaction(
+		&conn,
+		"3wBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1"
+	)
+	.unwrap()
+	.is_some());
+	drop(conn);
+
+	let counters = metrics.snapshot();
+	assert_eq!(
+		(
+			counters.blocks_ingested,
+			counters.transactions_stored,
+			counters.transfers_stored,
+			counters.reorgs_detected
+		),
+		(3, 3, 3, 1)
+	);
+	assert_eq!(counters.last_slot, Some(310176000));
+
+	drop(writer);
+	handle.join().unwrap();
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_db_writer_queues_the_slots_of_a_failed_batch() {
+	let path = std::env::temp_dir().join(format!("aggregator-failed-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let conn = Connection::open(&path).unwrap();
+	initialize_db(&conn).unwrap();
+	// Storing a block breaks a deferred foreign key, so the batch storing it fails on commit
+	conn.execute_batch(
+		"CREATE TABLE guard_parents (id INTEGER PRIMARY KEY);
+         CREATE TABLE guards (
+             parent INTEGER REFERENCES guard_parents (id) DEFERRABLE INITIALLY DEFERRED
+         );
+         CREATE TRIGGER guard_blocks AFTER INSERT ON blocks BEGIN
+             INSERT INTO guards VALUES (NEW.slot);
+         END;",
+	)
+	.unwrap();
+	let writer_conn = Connection::open(&path).unwrap();
+	writer_conn.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+	let (writer, _) = DbWriter::spawn(writer_conn, StoragePolicy::default(), EventBus::default());
+
+	// While the write lock is held, the commands queue up behind a first batch and are written
+	// together
+	conn.execute_batch("BEGIN IMMEDIATE").unwrap();
+	writer.mark_unavailable(1, 2, "Slot was skipped").await.unwrap();
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	let block: UiConfirmedBlock = serde_json::from_str(MOCK_JSON).unwrap();
+	let header = get_block_record(310176000, &block);
+	writer.write_block(header.clone(), parse_block(&block).unwrap()).await.unwrap();
+	writer.advance_sync_state(310176000).await.unwrap();
+	let checkpoint = tokio::spawn({
+		let writer = writer.clone();
+		async move { writer.checkpoint().await.map_err(|err| err.to_string()) }
+	});
+	tokio::time::sleep(Duration::from_millis(100)).await;
+	conn.execute_batch("COMMIT").unwrap();
+
+	let error = checkpoint.await.unwrap().unwrap_err();
+	assert!(error.contains("FOREIGN KEY"), "{}", error);
+	// The block is not stored, but its slot is queued for another attempt and the progress kept
+	assert!(blocks::get_block(&conn, 310176000).unwrap().is_none());
+	let failed = db::failed_slots::get_failed_slot(&conn, 310176000).unwrap().unwrap();
+	assert!(failed.error.contains("FOREIGN KEY"));
+	assert_eq!(failed.attempts, 1);
+	assert_eq!(db::sync_state::get_last_processed_slot(&conn).unwrap(), Some(310176000));
+
+	// The writer goes on with the next batches, which store the block and dequeue its slot
+	conn.execute_batch("DROP TRIGGER guard_blocks").unwrap();
+	writer.write_block(header, parse_block(&block).unwrap()).await.unwrap();
+	writer.checkpoint().await.unwrap();
+	assert!(blocks::get_block(&conn, 310176000).unwrap().is_some());
+	assert!(db::failed_slots::get_failed_slot(&conn, 310176000).unwrap().is_none());
+
+	drop(conn);
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_anomaly_detector_flags_spikes() {
+	let mut detector = AnomalyDetector::new(AnomalyConfig {
+		bucket_slots: 10,
+		window: 20,
+		threshold: 4.0,
+		min_samples: 5,
+		..AnomalyConfig::default()
+	});
+	let transaction = |slot: u64, failing: bool| Event::TransactionStored {
+		signature: format!("tx{}", slot),
+		slot,
+		block_time: None,
+		fee: 5000,
+		fee_payer: "payer".to_string(),
+		error: failing.then(|| TransactionFailure {
+			program_id: Some("program".to_string()),
+			error: "Custom(1)".to_string(),
+		}),
+	};
+	let transfer = |slot: u64, amount: u64| {
+		Event::TransferStored(TransferRecord {
+			transaction_id: format!("tx{}", slot),
+			transfer_index: 0,
+			sender: "whale".to_string(),
+			receiver: "exchange".to_string(),
+			amount,
+			timestamp: 1720000000,
+			block_height: slot,
+			kind: TransferKind::Transfer,
+			sender_owner: None,
+			receiver_owner: None,
+			invoked_by: None,
+			tags: Vec::new(),
+		})
+	};
+
+	// Ten quiet samples: a steady volume and one or two failures out of four transactions
+	let mut anomalies = Vec::new();
+	for bucket in 0..10u64 {
+		let slot = bucket * 10;
+		for offset in 0..4 {
+			anomalies.extend(detector.observe(&transaction(slot + offset, offset <= bucket % 2)));
+		}
+		anomalies.extend(detector.observe(&transfer(slot, 1000 + bucket * 10)));
+	}
+	// A sample with a spike of volume and every transaction failing
+	for offset in 0..4 {
+		anomalies.extend(detector.observe(&transaction(100 + offset, true)));
+	}
+	anomalies.extend(detector.observe(&transfer(100, 50_000)));
+	assert!(anomalies.is_empty());
+	// Late events of a past sample are ignored
+	assert!(detector.observe(&transfer(15, 1_000_000)).is_empty());
+
+	let mut anomalies = detector.observe(&Event::BlockIngested {
+		slot: 110,
+		block_time: None,
+		transaction_count: 0,
+	});
+	anomalies.sort_by(|a, b| (a.metric as u8, &a.subject).cmp(&(b.metric as u8, &b.subject)));
+	let flagged: Vec<_> = anomalies
+		.iter()
+		.map(|anomaly| (anomaly.metric, anomaly.subject.as_str()))
+		.collect();
+	assert_eq!(
+		flagged,
+		[
+			(AnomalyMetric::TransferVolume, "exchange"),
+			(AnomalyMetric::TransferVolume, "whale"),
+			(AnomalyMetric::FailureRate, "program"),
+		]
+	);
+	assert_eq!((anomalies[0].start_slot, anomalies[0].end_slot), (100, 109));
+	assert_eq!(anomalies[0].value, 50_000.0);
+	assert_eq!(anomalies[0].mean, 1045.0);
+	assert_eq!(anomalies[2].value, 1.0);
+}
+
+#[test]
+fn test_store_parsed_block_marks_submission_landed() {
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	let signature =
+		"2xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1";
+	db::submissions::insert_submission(&conn, signature, 1720421600).unwrap();
+
+	let transactions = parse_block(&create_mock_ui_confirmed_block()).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+
+	let submission = db::submissions::get_submission(&conn, signature).unwrap().unwrap();
+	assert_eq!(submission.status, "confirmed");
+	assert_eq!(submission.slot, Some(310176000));
+	assert_eq!(submission.error, None);
+}
+
+#[test]
+fn test_store_parsed_block_applies_program_filters() {
+	let (memo, system, compute_budget) = (
+		"MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr",
+		"11111111111111111111111111111111",
+		"ComputeBudget111111111111111111111111111111",
+	);
+	// A memo, followed by the transfer of the mock block
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let mut memo_transaction = block["transactions"][0].clone();
+	memo_transaction["transaction"]["signatures"][0] = json!("memo");
+	memo_transaction["transaction"]["message"]["instructions"] = json!([{
+		"program": "spl-memo",
+		"programId": memo,
+		"parsed": "hello",
+		"stackHeight": null
+	}]);
+	block["transactions"].as_array_mut().unwrap().insert(0, memo_transaction);
+	let transactions = parse_block(&serde_json::from_value(block).unwrap()).unwrap();
+	let transfer = transactions[1].signature.clone();
+
+	let stored = |rules: &[(&str, FilterRule)]| {
+		let conn = Connection::open_in_memory().unwrap();
+		initialize_db(&conn).unwrap();
+		for (program_id, rule) in rules {
+			let filter = ProgramFilterRecord {
+				program_id: program_id.to_string(),
+				rule: *rule,
+				updated_at: 0,
+			};
+			filters::put_program_filter(&conn, &filter).unwrap();
+		}
+		store_parsed_block(
+			&conn,
+			310176000,
+			Some(1720421680),
+			&transactions,
+			&StoragePolicy::default(),
+			&filters::load_program_filters(&conn).unwrap(),
+			&mut RecentWrites::default(),
+		)
+		.unwrap();
+		let mut stmt = conn
+			.prepare(
+				"SELECT transaction_id, MIN(transaction_index) FROM account_transactions
+                 GROUP BY transaction_id ORDER BY 2",
+			)
+			.unwrap();
+		stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+			.unwrap()
+			.collect::<Result<Vec<(String, u32)>, _>>()
+			.unwrap()
+	};
+
+	assert_eq!(stored(&[]), [("memo".to_string(), 0), (transfer.clone(), 1)]);
+	// A denied program drops its transactions, the others keep their position in the block
+	assert_eq!(stored(&[(memo, FilterRule::Deny)]), [(transfer.clone(), 1)]);
+	// Allowed programs restrict the stored transactions, a denied program winning
+	assert_eq!(stored(&[(system, FilterRule::Allow)]), [(transfer, 1)]);
+	assert!(stored(&[(system, FilterRule::Allow), (compute_budget, FilterRule::Deny)]).is_empty());
+}
+
+#[test]
+fn test_store_parsed_block_keeps_transactions_touching_watched_addresses() {
+	use crate::types::Config;
+
+	let parse = |addresses: &str| {
+		toml::from_str::<Config>(&format!(
+			"rpc_url = \"http://localhost:8899\"\nretry_attempts = 1\nserver_address = \"127.0.0.1:0\"\nwatched_addresses = {}",
+			addresses
+		))
+	};
+	assert!(parse("[\"not-a-pubkey\"]").is_err());
+
+	let transactions = parse_block(&create_mock_ui_confirmed_block()).unwrap();
+	let stored = |addresses: &str| {
+		let conn = Connection::open_in_memory().unwrap();
+		initialize_db(&conn).unwrap();
+		store_parsed_block(
+			&conn,
+			310176000,
+			Some(1720421680),
+			&transactions,
+			&parse(addresses).unwrap().storage_policy(),
+			&ProgramFilters::default(),
+			&mut RecentWrites::default(),
+		)
+		.unwrap();
+		conn.query_row("SELECT COUNT(*) FROM transactions", [], |row| row.get::<_, u32>(0))
+			.unwrap()
+	};
+
+	assert_eq!(stored("[]"), 1);
+	// The receiver of the transfer is an account of the message
+	assert_eq!(stored("[\"84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ\"]"), 1);
+	assert_eq!(stored("[\"Vote111111111111111111111111111111111111111\"]"), 0);
+}
+
+#[test]
+fn test_store_parsed_block_keeps_transactions_of_indexed_programs() {
+	let (memo, system) =
+		("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr", "11111111111111111111111111111111");
+	// A memo, followed by the transfer of the mock block
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let mut memo_transaction = block["transactions"][0].clone();
+	memo_transaction["transaction"]["signatures"][0] = json!("memo");
+	memo_transaction["transaction"]["message"]["instructions"] = json!([{
+		"program": "spl-memo",
+		"programId": memo,
+		"parsed": "hello",
+		"stackHeight": null
+	}]);
+	block["transactions"].as_array_mut().unwrap().insert(0, memo_transaction);
+	let transactions = parse_block(&serde_json::from_value(block).unwrap()).unwrap();
+	let transfer = transactions[1].signature.clone();
+
+	let stored = |programs: &[&str], denied: Option<&str>| {
+		let conn = Connection::open_in_memory().unwrap();
+		initialize_db(&conn).unwrap();
+		if let Some(program_id) = denied {
+			let filter = ProgramFilterRecord {
+				program_id: program_id.to_string(),
+				rule: FilterRule::Deny,
+				updated_at: 0,
+			};
+			filters::put_program_filter(&conn, &filter).unwrap();
+		}
+		let policy = StoragePolicy {
+			index_programs: Arc::new(programs.iter().map(|program| program.to_string()).collect()),
+			..Default::default()
+		};
+		store_parsed_block(
+			&conn,
+			310176000,
+			Some(1720421680),
+			&transactions,
+			&policy,
+			&filters::load_program_filters(&conn).unwrap(),
+			&mut RecentWrites::default(),
+		)
+		.unwrap();
+		let mut stmt = conn
+			.prepare("SELECT transaction_id FROM transactions ORDER BY transaction_id")
+			.unwrap();
+		stmt.query_map([], |row| row.get(0))
+			.unwrap()
+			.collect::<Result<Vec<String>, _>>()
+			.unwrap()
+	};
+
+	assert_eq!(stored(&[], None), [transfer.clone(), "memo".to_string()]);
+	assert_eq!(stored(&[memo], None), ["memo"]);
+	assert_eq!(stored(&[system, memo], None), [transfer.clone(), "memo".to_string()]);
+	// The program filters still apply to the indexed programs
+	assert_eq!(stored(&[system, memo], Some(memo)), [transfer]);
+}
+
+#[test]
+fn test_store_parsed_block_redacts_raw_transactions() {
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	block["transactions"][0]["transaction"]["message"]["instructions"]
+		.as_array_mut()
+		.unwrap()
+		.push(json!({
+			"program": "spl-memo",
+			"programId": "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr",
+			"parsed": "invoice 1234 for alice",
+			"stackHeight": null
+		}));
+	let block: UiConfirmedBlock = serde_json::from_value(block).unwrap();
+	let transactions = parse_block(&block).unwrap();
+	let signature = transactions[0].signature.clone();
+	let sender = transactions[0].details[0].sender.clone();
+
+	let stored = |raw_transactions| {
+		let conn = Connection::open_in_memory().unwrap();
+		initialize_db(&conn).unwrap();
+		let policy = StoragePolicy { raw_transactions, ..Default::default() };
+		store_parsed_block(
+			&conn,
+			310176000,
+			Some(1720421680),
+			&transactions,
+			&policy,
+			&ProgramFilters::default(),
+			&mut RecentWrites::default(),
+		)
+		.unwrap();
+		// The parsed records are kept whatever the policy
+		assert!(!db::get_transfers(&conn, &sender, None, None, 100).unwrap().is_empty());
+		db::get_transaction(&conn, &signature).unwrap().unwrap().raw_transaction
+	};
+
+	let full = stored(RawTransactionPolicy::Full);
+	assert!(full.contains("invoice 1234 for alice"));
+
+	let stripped = stored(RawTransactionPolicy::StripMemos);
+	assert!(!stripped.contains("invoice 1234"));
+	assert!(stripped.contains("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr"));
+	assert_eq!(stripped.len() + "\"invoice 1234 for alice\"".len(), full.len() + "null".len());
+
+	let hashed = stored(RawTransactionPolicy::HashOnly);
+	let hash: String = <sha2::Sha256 as sha2::Digest>::digest(full.as_bytes())
+		.iter()
+		.map(|byte| format!("{:02x}", byte))
+		.collect();
+	assert_eq!(hashed, format!("sha256:{}", hash));
+}
+
+#[tokio::test]
+async fn test_db_writer_tags_transfers_with_rules() {
+	use crate::types::Config;
+
+	let sender = "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g";
+	let receiver = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+	let parse = |rules: &str| {
+		toml::from_str::<Config>(&format!(
+			"rpc_url = \"http://localhost:8899\"\nretry_attempts = 1\nserver_address = \"127.0.0.1:0\"\n{}",
+			rules
+		))
+	};
+	assert!(parse("[[tagging_rules]]\ntag = \"payroll\"\nmemo = \"(unclosed\"").is_err());
+	let config = parse(
+		r#"
+		[[tagging_rules]]
+		tag = "payroll"
+		min_amount = 100
+		memo = "(?i)^payroll"
+
+		[[tagging_rules]]
+		tag = "whale"
+		min_amount = 1000000000
+
+		[[tagging_rules]]
+		tag = "exchange-deposit"
+		receiver_label = "exchange"
+
+		[[tagging_rules]]
+		tag = "exchange-withdrawal"
+		sender_label = "exchange"
+
+		[[tagging_rules]]
+		tag = "dust"
+		max_amount = 1000
+		kind = "transfer"
+		"#,
+	)
+	.unwrap();
+
+	let path = std::env::temp_dir().join(format!("aggregator-tags-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let conn = Connection::open(&path).unwrap();
+	initialize_db(&conn).unwrap();
+	let label = LabelRecord {
+		pubkey: receiver.to_string(),
+		label: "Kraken hot wallet".to_string(),
+		tags: vec!["exchange".to_string()],
+	};
+	db::labels::import_labels(&conn, vec![label]).unwrap();
+
+	let events = EventBus::default();
+	let mut receiver_events = events.subscribe();
+	let (writer, handle) =
+		DbWriter::spawn(Connection::open(&path).unwrap(), config.storage_policy(), events);
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	block["transactions"][0]["transaction"]["message"]["instructions"]
+		.as_array_mut()
+		.unwrap()
+		.push(json!({
+			"program": "spl-memo",
+			"programId": "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr",
+			"parsed": "Payroll March",
+			"stackHeight": null
+		}));
+	let block: UiConfirmedBlock = serde_json::from_value(block).unwrap();
+	let transactions = parse_block(&block).unwrap();
+	writer
+		.write_block(get_block_record(310176000, &block), transactions)
+		.await
+		.unwrap();
+	writer.checkpoint().await.unwrap();
+
+	// The tags follow the order of the rules
+	let tags = vec!["payroll".to_string(), "exchange-deposit".to_string(), "dust".to_string()];
+	let transfers = db::get_transfers(&conn, sender, None, None, 100).unwrap();
+	assert_eq!(transfers.len(), 1);
+	assert_eq!(transfers[0].tags, tags);
+	assert_eq!(db::get_transfers(&conn, sender, None, Some("dust"), 100).unwrap().len(), 1);
+	assert!(db::get_transfers(&conn, sender, None, Some("whale"), 100).unwrap().is_empty());
+	let published = std::iter::from_fn(|| receiver_events.try_recv().ok())
+		.find_map(|event| match event {
+			Event::TransferStored(transfer) => Some(transfer),
+			_ => None,
+		})
+		.unwrap();
+	assert_eq!(published.tags, tags);
+
+	drop(writer);
+	handle.join().unwrap();
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_store_parsed_block_keeps_amounts_above_i64() {
+	let trader = "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g";
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let meta = &mut block["transactions"][0]["meta"];
+	meta["preTokenBalances"] = json!([
+		token_balance(4, "MintA", trader, u64::MAX),
+		token_balance(5, "MintB", trader, 0),
+		token_balance(6, "MintA", "PoolAuthority", 0),
+		token_balance(7, "MintB", "PoolAuthority", u64::MAX),
+	]);
+	meta["postTokenBalances"] = json!([
+		token_balance(4, "MintA", trader, 0),
+		token_balance(5, "MintB", trader, u64::MAX),
+		token_balance(6, "MintA", "PoolAuthority", u64::MAX),
+		token_balance(7, "MintB", "PoolAuthority", 0),
+	]);
+	let block: UiConfirmedBlock = serde_json::from_value(block).unwrap();
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	let transactions = parse_block(&block).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+
+	let (amount_in, amount_out): (db::amount::Amount, db::amount::Amount) = conn
+		.query_row("SELECT amount_in, amount_out FROM swaps", [], |row| {
+			Ok((row.get(0)?, row.get(1)?))
+		})
+		.unwrap();
+	assert_eq!(amount_in.0, u64::MAX as u128);
+	assert_eq!(amount_out.0, u64::MAX as u128);
+}
+
+#[test]
+fn test_initialize_db_migrates_integer_amount_columns() {
+	let conn = Connection::open_in_memory().unwrap();
+	conn.execute_batch(
+		"CREATE TABLE balance_changes (
+            account_id TEXT,
+            transaction_id TEXT,
+            block_height INTEGER,
+            transaction_index INTEGER,
+            pre_balance INTEGER,
+            post_balance INTEGER,
+            PRIMARY KEY (account_id, transaction_id)
+        );
+        CREATE INDEX balance_changes_by_slot ON balance_changes (account_id, block_height, transaction_index);
+        INSERT INTO balance_changes VALUES ('acc1', 'tx1', 10, 0, 5000, 7000);",
+	)
+	.unwrap();
+
+	initialize_db(&conn).unwrap();
+
+	let column_type: String = conn
+		.query_row(
+			"SELECT type FROM pragma_table_info('balance_changes') WHERE name = 'post_balance'",
+			[],
+			|row| row.get(0),
+		)
+		.unwrap();
+	assert_eq!(column_type, "TEXT");
+	let change = db::get_balance_change_at(&conn, "acc1", None).unwrap().unwrap();
+	assert_eq!(change.pre_balance, 5000);
+	assert_eq!(change.post_balance, 7000);
+
+	// Running the initialization again leaves the migrated table as is
+	initialize_db(&conn).unwrap();
+	assert!(db::get_balance_change_at(&conn, "acc1", None).unwrap().is_some());
+}
+
+#[test]
+fn test_account_balances_follow_the_latest_transaction() {
+	let receiver = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+	let block_with_balance = |receiver_balance: u64| {
+		let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+		block["transactions"][0]["meta"]["postBalances"][1] = json!(receiver_balance);
+		parse_block(&serde_json::from_value(block).unwrap()).unwrap()
+	};
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	let policy = StoragePolicy::default();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&block_with_balance(5000),
+		&policy,
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	let account = db::get_account(&conn, receiver).unwrap().unwrap();
+	assert_eq!((account.estimated_balance, account.balance_slot), (5000, Some(310176000)));
+
+	// A later block updates the balance, an older block ingested afterwards does not
+	store_parsed_block(
+		&conn,
+		310176010,
+		Some(1720421690),
+		&block_with_balance(7000),
+		&policy,
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	store_parsed_block(
+		&conn,
+		310175990,
+		Some(1720421670),
+		&block_with_balance(3000),
+		&policy,
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	let account = db::get_account(&conn, receiver).unwrap().unwrap();
+	assert_eq!((account.estimated_balance, account.balance_slot), (7000, Some(310176010)));
+	assert_eq!(account.total_received, 967);
+
+	// Transactions without a meta leave the balance estimated from the transfers
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	block["transactions"][0]["meta"] = serde_json::Value::Null;
+	let transactions = parse_block(&serde_json::from_value(block).unwrap()).unwrap();
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&policy,
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	let account = db::get_account(&conn, receiver).unwrap().unwrap();
+	assert_eq!((account.estimated_balance, account.balance_slot), (967, None));
+}
+
+#[test]
+fn test_recent_writes_skip_identical_account_upserts() {
+	let mut recent = RecentWrites::new(2);
+	recent.insert("acc1", 1);
+	recent.insert("acc2", 2);
+	assert!(recent.contains("acc1", 1));
+	assert!(!recent.contains("acc1", 3));
+	// acc2 is the least recently used account
+	recent.insert("acc3", 3);
+	assert!(!recent.contains("acc2", 2));
+	assert!(recent.contains("acc1", 1) && recent.contains("acc3", 3));
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	conn.execute_batch(
+		"CREATE TABLE account_upserts (count INTEGER);
+        INSERT INTO account_upserts VALUES (0);
+        CREATE TRIGGER count_account_upserts AFTER INSERT ON accounts
+        BEGIN UPDATE account_upserts SET count = count + 1; END;",
+	)
+	.unwrap();
+	let upserts = || -> u32 {
+		conn.query_row("SELECT count FROM account_upserts", [], |row| row.get(0))
+			.unwrap()
+	};
+	let transactions = parse_block(&create_mock_ui_confirmed_block()).unwrap();
+	let policy = StoragePolicy::default();
+
+	// Ingesting the block again leaves the sender and receiver unchanged
+	let mut recent = RecentWrites::new(16);
+	for _ in 0..2 {
+		store_parsed_block(
+			&conn,
+			310176000,
+			Some(1720421680),
+			&transactions,
+			&policy,
+			&ProgramFilters::default(),
+			&mut recent,
+		)
+		.unwrap();
+	}
+	assert_eq!(upserts(), 2);
+	// Without the remembered states, the accounts are written again
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&policy,
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	assert_eq!(upserts(), 4);
+}
+
+#[test]
+fn test_store_parsed_block_coalesces_account_writes() {
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let mut second = block["transactions"][0].clone();
+	second["transaction"]["signatures"][0] = json!(
+		"3xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1"
+	);
+	block["transactions"].as_array_mut().unwrap().push(second);
+	let block: UiConfirmedBlock = serde_json::from_value(block).unwrap();
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	let transactions = parse_block(&block).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+
+	let receiver = db::get_account(&conn, "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ")
+		.unwrap()
+		.unwrap();
+	// The balance is the post balance of the last transaction of the block
+	assert_eq!(receiver.estimated_balance, 1798951577);
+	assert_eq!(
+		(receiver.balance_slot, receiver.balance_transaction_index),
+		(Some(310176000), Some(1))
+	);
+	assert_eq!(receiver.related_transactions.len(), 2);
+
+	// Re-ingesting the block does not count its transfers twice
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	let receiver = db::get_account(&conn, "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ")
+		.unwrap()
+		.unwrap();
+	assert_eq!(receiver.estimated_balance, 1798951577);
+	assert_eq!(receiver.related_transactions.len(), 2);
+	assert_eq!(receiver.total_received, 2 * 967);
+	assert_eq!(receiver.total_sent, 0);
+
+	let sender = db::get_account(&conn, "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g")
+		.unwrap()
+		.unwrap();
+	assert_eq!(sender.estimated_balance, 771523247926);
+	assert_eq!(sender.total_sent, 2 * 967);
+}
+
+#[test]
+fn test_initialize_db_computes_totals_of_existing_accounts() {
+	let conn = Connection::open_in_memory().unwrap();
+	conn.execute_batch(
+		"CREATE TABLE accounts (
+            account_id TEXT PRIMARY KEY,
+            estimated_balance TEXT,
+            related_transactions TEXT
+        );
+        CREATE TABLE transfers (
+            transaction_id TEXT PRIMARY KEY,
+            sender TEXT,
+            receiver TEXT,
+            amount TEXT,
+            timestamp INTEGER,
+            block_height INTEGER,
+            kind TEXT
+        );
+        INSERT INTO accounts VALUES ('acc1', '0', '[\"tx1\",\"tx2\"]');
+        INSERT INTO transfers VALUES ('tx1', 'acc2', 'acc1', '500', 0, 10, 'transfer');
+        INSERT INTO transfers VALUES ('tx2', 'acc1', 'acc2', '200', 0, 11, 'transfer');",
+	)
+	.unwrap();
+
+	initialize_db(&conn).unwrap();
+
+	let account = db::get_account(&conn, "acc1").unwrap().unwrap();
+	assert_eq!(account.total_received, 500);
+	assert_eq!(account.total_sent, 200);
+}
+
+#[test]
+fn test_config_precedence() {
+	use crate::{
+		cli,
+		types::{Config, LogFormat, RunMode},
+	};
+
+	let path = std::env::temp_dir().join(format!("aggregator-config-{}.toml", std::process::id()));
+	std::fs::write(
+		&path,
+		"rpc_url = \"http://file:8899\"\nretry_attempts = 1\nserver_address = \"127.0.0.1:0\"\nmode = \"ingest_only\"\n[rpc]\ntimeout_secs = 10",
+	)
+	.unwrap();
+	let path = path.to_str().unwrap().to_string();
+	let load = |args: &[&str], vars: &[(&str, &str)]| {
+		let args = ["aggregator", "--config", &path].into_iter().chain(args.iter().copied());
+		let matches = cli::command().try_get_matches_from(args).unwrap();
+		let vars = vars.iter().map(|(name, value)| (name.to_string(), value.to_string()));
+		cli::load_config(&matches, vars)
+	};
+
+	// Defaults, then the file
+	let config = load(&[], &[("PATH", "/usr/bin")]).unwrap();
+	assert_eq!(
+		(config.rpc_url.as_str(), config.db_path.as_str()),
+		("http://file:8899", "solana.db")
+	);
+	assert_eq!((config.mode, config.rpc.timeout_secs), (RunMode::IngestOnly, 10));
+	assert_eq!(config.log_format, LogFormat::Text);
+
+	// The environment overrides the file, sections and other types included
+	let vars = [
+		("AGG_RPC_URL", "http://env:8899"),
+		("AGG_RPC__TIMEOUT_SECS", "30"),
+		("AGG_RPC__FALLBACK_URLS", "[\"http://backup:8899\"]"),
+		("AGG_CONTINUOUS", "true"),
+		("AGG_MODE", "serve_only"),
+		("AGG_DB_PATH", "/data/env.db"),
+		("AGG_ANOMALY_DETECTION__THRESHOLD", "3.5"),
+		("AGG_LOG_FORMAT", "json"),
+	];
+	let config = load(&[], &vars).unwrap();
+	assert_eq!(
+		(config.rpc_url.as_str(), config.db_path.as_str()),
+		("http://env:8899", "/data/env.db")
+	);
+	assert_eq!((config.rpc.timeout_secs, config.rpc.fallback_urls.len()), (30, 1));
+	assert_eq!((config.continuous, config.mode), (true, RunMode::ServeOnly));
+	assert_eq!(config.anomaly_detection.unwrap().threshold, 3.5);
+	assert_eq!(config.log_format, LogFormat::Json);
+
+	// The flags override both
+	let args = [
+		"--rpc-url",
+		"http://cli:8899",
+		"--server-address",
+		"0.0.0.0:3030",
+		"--db-path",
+		"/data/cli.db",
+		"--retry-attempts",
+		"5",
+		"--ingest-only",
+	];
+	let config = load(&args, &vars).unwrap();
+	assert_eq!(
+		(config.rpc_url.as_str(), config.server_address.as_str()),
+		("http://cli:8899", "0.0.0.0:3030")
+	);
+	assert_eq!((config.db_path.as_str(), config.retry_attempts), ("/data/cli.db", 5));
+	assert_eq!((config.mode, config.rpc.timeout_secs), (RunMode::IngestOnly, 30));
+
+	// Values reading as another type are read as strings when their settings are strings
+	let vars = [
+		("AGG_DB_PATH", "2024"),
+		("AGG_RPC__TIMEOUT_SECS", "30"),
+		("AGG_TIERING__COLD_PATH", "true"),
+		("AGG_TIERING__MAX_AGE", "30d"),
+	];
+	let config = load(&[], &vars).unwrap();
+	assert_eq!((config.db_path.as_str(), config.rpc.timeout_secs), ("2024", 30));
+	assert_eq!(config.tiering.unwrap().cold_path, "true");
+	let config = load(&["--db-path", "/data/cli.db"], &vars).unwrap();
+	assert_eq!(config.db_path, "/data/cli.db");
+
+	assert!(load(&[], &[("AGG_RETRY_ATTEMPTS", "many")]).is_err());
+	assert!(load(&[], &[("AGG_RPC_URL__PATH", "x")]).is_err());
+	std::fs::remove_file(&path).unwrap();
+
+	// A file named on the command line is required, the default one is not
+	let vars = [
+		("AGG_RPC_URL", "http://env:8899"),
+		("AGG_RETRY_ATTEMPTS", "2"),
+		("AGG_SERVER_ADDRESS", "127.0.0.1:0"),
+	];
+	assert!(load(&[], &vars).is_err());
+	let matches = cli::command().try_get_matches_from(["aggregator"]).unwrap();
+	assert_eq!(matches.get_one::<String>("config").unwrap(), "config.toml");
+	let vars = vars.iter().map(|(name, value)| (name.to_string(), value.to_string()));
+	let config = Config::load(&path, false, vars, Default::default()).unwrap();
+	assert_eq!((config.rpc_url.as_str(), config.retry_attempts), ("http://env:8899", 2));
+
+	let parse = |args: &[&str]| {
+		cli::command().try_get_matches_from(["aggregator"].into_iter().chain(args.iter().copied()))
+	};
+	assert!(parse(&["--retry-attempts", "many"]).is_err());
+	assert!(parse(&["--serve-only", "--ingest-only"]).is_err());
+
+	// The backfill subcommand takes an inclusive range of slots
+	assert_eq!(cli::backfill_range(&parse(&[]).unwrap()).unwrap(), None);
+	let backfill = parse(&["backfill", "--from-slot", "10", "--to-slot", "20"]).unwrap();
+	assert_eq!(cli::backfill_range(&backfill).unwrap(), Some(10..=20));
+	let backfill = parse(&["backfill", "--from-slot", "20", "--to-slot", "10"]).unwrap();
+	assert!(cli::backfill_range(&backfill).is_err());
+	assert!(parse(&["backfill", "--from-slot", "10"]).is_err());
+}
+
+#[test]
+fn test_start_slot_strategies() {
+	use crate::{
+		aggregator::start_slot,
+		types::{Config, EpochInfo, StartFrom},
+	};
+
+	let parse = |start_from: &str| {
+		let config: Config = toml::from_str(&format!(
+			"rpc_url = \"http://localhost:8899\"\nretry_attempts = 1\nserver_address = \"127.0.0.1:0\"\n{}",
+			start_from
+		))
+		.unwrap();
+		config.start_from
+	};
+	let config: Config = toml::from_str(
+		"rpc_url = \"http://localhost:8899\"\nretry_attempts = 1\nserver_address = \"127.0.0.1:0\"",
+	)
+	.unwrap();
+	assert!(!config.continuous);
+	assert_eq!(config.poll_interval_ms, 1000);
+	assert_eq!(parse(""), StartFrom::Checkpoint);
+	assert_eq!(parse("start_from = \"epoch_start\""), StartFrom::EpochStart);
+	assert_eq!(parse("start_from = \"latest\""), StartFrom::Latest);
+	assert_eq!(parse("start_from = \"checkpoint\""), StartFrom::Checkpoint);
+	assert_eq!(parse("start_from = { slot = 1234 }"), StartFrom::Slot(1234));
+
+	let epoch_info =
+		EpochInfo { epoch: 0, absolute_slot: 1500, slot_index: 500, slots_in_epoch: 432000 };
+	assert_eq!(start_slot(StartFrom::Latest, &epoch_info, None), 1500);
+	assert_eq!(start_slot(StartFrom::EpochStart, &epoch_info, Some(1200)), 1000);
+	assert_eq!(start_slot(StartFrom::Checkpoint, &epoch_info, Some(1200)), 1201);
+	assert_eq!(start_slot(StartFrom::Checkpoint, &epoch_info, None), 1000);
+	assert_eq!(start_slot(StartFrom::Slot(42), &epoch_info, None), 42);
+}
+
+#[test]
+fn test_commitment_levels() {
+	use crate::types::{Commitment, Config};
+	use solana_sdk::commitment_config::CommitmentConfig;
+
+	let parse = |commitment: &str| {
+		toml::from_str::<Config>(&format!(
+			"rpc_url = \"http://localhost:8899\"\nretry_attempts = 1\nserver_address = \"127.0.0.1:0\"\n{}",
+			commitment
+		))
+		.map(|config| config.commitment)
+	};
+	assert_eq!(parse("").unwrap(), Commitment::Finalized);
+	assert_eq!(parse("commitment = \"confirmed\"").unwrap(), Commitment::Confirmed);
+	assert!(parse("commitment = \"recent\"").is_err());
+
+	// `getBlock` does not serve processed blocks
+	let processed = parse("commitment = \"processed\"").unwrap();
+	assert_eq!(processed.config(), CommitmentConfig::processed());
+	assert_eq!(processed.block_config(), CommitmentConfig::confirmed());
+	assert_eq!(Commitment::Confirmed.block_config(), CommitmentConfig::confirmed());
+	assert_eq!(Commitment::Finalized.block_config(), CommitmentConfig::finalized());
+}
+
+#[test]
+fn test_replace_transaction_repairs_derived_records() {
+	let signature =
+		"3xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1";
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let mut second = block["transactions"][0].clone();
+	second["transaction"]["signatures"][0] = json!(signature);
+	block["transactions"].as_array_mut().unwrap().push(second);
+	let block: UiConfirmedBlock = serde_json::from_value(block).unwrap();
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	let transactions = parse_block(&block).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+
+	// Simulate rows written by a buggy parser
+	conn.execute("UPDATE transfers SET amount = '1' WHERE transaction_id = ?1", [signature])
+		.unwrap();
+	conn.execute(
+		"INSERT INTO swaps (transaction_id, block_height, transaction_index, trader, pool, mint_in, amount_in, mint_out, amount_out)
+         VALUES (?1, 310176000, 1, 'trader', 'pool', 'MintA', '1', 'MintB', '1')",
+		[signature],
+	)
+	.unwrap();
+
+	replace_transaction(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions[1],
+		&StoragePolicy::default(),
+	)
+	.unwrap();
+
+	let amount: db::amount::Amount = conn
+		.query_row("SELECT amount FROM transfers WHERE transaction_id = ?1", [signature], |row| {
+			row.get(0)
+		})
+		.unwrap();
+	assert_eq!(amount.0, 967);
+	let swaps: u32 = conn.query_row("SELECT COUNT(*) FROM swaps", [], |row| row.get(0)).unwrap();
+	assert_eq!(swaps, 0);
+	// The transaction keeps its position within the block
+	assert_eq!(db::get_transaction_index(&conn, signature).unwrap(), Some(1));
+	// Transfers already merged into the accounts are not counted twice
+	let receiver = db::get_account(&conn, "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ")
+		.unwrap()
+		.unwrap();
+	assert_eq!(receiver.total_received, 2 * 967);
+}
+
+#[test]
+fn test_daily_rollups_are_maintained_incrementally() {
+	let block: UiConfirmedBlock = serde_json::from_str(MOCK_JSON).unwrap();
+	let transactions = parse_block(&block).unwrap();
+	let mut later: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	later["transactions"][0]["transaction"]["signatures"][0] = json!(
+		"3xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1"
+	);
+	let later = parse_block(&serde_json::from_value(later).unwrap()).unwrap();
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	// Re-ingesting a block does not count its transactions twice
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	store_parsed_block(
+		&conn,
+		310400000,
+		Some(1720421680 + 86400),
+		&later,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+
+	let incremental = db::rollups::get_daily_stats(&conn, None, None, 10).unwrap();
+	assert_eq!(
+		incremental,
+		vec![
+			DailyStats {
+				day: "2024-07-08".to_string(),
+				transaction_count: 1,
+				volume: 967,
+				total_fee: 5040,
+				active_accounts: 4,
+				new_accounts: 4,
+			},
+			DailyStats {
+				day: "2024-07-09".to_string(),
+				transaction_count: 1,
+				volume: 967,
+				total_fee: 5040,
+				active_accounts: 4,
+				new_accounts: 0,
+			},
+		]
+	);
+	let days = db::rollups::get_daily_stats(&conn, Some("2024-07-09"), None, 10).unwrap();
+	assert_eq!(days.len(), 1);
+
+	// Rollups created on an existing database are seeded with the same figures
+	conn.execute_batch(
+		"DROP TABLE daily_stats; DROP TABLE daily_active_accounts; DROP TABLE schema_version;",
+	)
+	.unwrap();
+	initialize_db(&conn).unwrap();
+	assert_eq!(db::rollups::get_daily_stats(&conn, None, None, 10).unwrap(), incremental);
+}
+
+#[test]
+fn test_tiered_transactions_keep_their_account_history_and_rollups() {
+	let block: UiConfirmedBlock = serde_json::from_str(MOCK_JSON).unwrap();
+	let transactions = parse_block(&block).unwrap();
+	let signature = transactions[0].signature.clone();
+	let receiver = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	db::tiering::attach_cold_storage(&conn, ":memory:").unwrap();
+	db::tiering::initialize_cold_storage(&conn).unwrap();
+	let store = |conn: &Connection| {
+		store_parsed_block(
+			conn,
+			310176000,
+			Some(1720421680),
+			&transactions,
+			&StoragePolicy::default(),
+			&ProgramFilters::default(),
+			&mut RecentWrites::default(),
+		)
+		.unwrap()
+	};
+	store(&conn);
+	assert_eq!(db::tiering::move_to_cold(&conn, 1720421681).unwrap(), 1);
+	assert!(db::is_transaction_stored(&conn, &signature).unwrap());
+
+	// The account history still lists the transaction, with its block time
+	let filter = db::TransactionFilter { since: Some(1720421680), ..Default::default() };
+	let signatures = db::get_account_signatures(&conn, receiver, None, None, &filter, 10).unwrap();
+	assert_eq!(signatures.len(), 1);
+	assert_eq!(signatures[0].signature, signature);
+	assert_eq!(signatures[0].block_time, Some(1720421680));
+
+	// The block summaries only cover the hot database
+	assert_eq!(db::blocks::count_block_transactions(&conn, 310176000).unwrap(), 0);
+
+	// Re-ingesting the block does not count the tiered transaction twice
+	store(&conn);
+	let days = db::rollups::get_daily_stats(&conn, None, None, 10).unwrap();
+	assert_eq!(days.len(), 1);
+	assert_eq!((days[0].transaction_count, days[0].total_fee), (1, 5040));
+}
+
+#[test]
+fn test_store_parsed_block_follows_account_discovery_policy() {
+	let sender = "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g";
+	let receiver = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+	let compute_budget = "ComputeBudget111111111111111111111111111111";
+	let transactions = parse_block(&create_mock_ui_confirmed_block()).unwrap();
+
+	for (discovery, expected) in [
+		(AccountDiscovery::TransferParties, [true, true, false]),
+		(AccountDiscovery::Signers, [true, false, false]),
+		(AccountDiscovery::Writable, [true, true, false]),
+		(AccountDiscovery::All, [true, true, true]),
+	] {
+		let conn = Connection::open_in_memory().unwrap();
+		initialize_db(&conn).unwrap();
+		let policy = StoragePolicy { account_discovery: discovery, ..Default::default() };
+		store_parsed_block(
+			&conn,
+			310176000,
+			Some(1720421680),
+			&transactions,
+			&policy,
+			&ProgramFilters::default(),
+			&mut RecentWrites::default(),
+		)
+		.unwrap();
+
+		let discovered = [sender, receiver, compute_budget]
+			.map(|account_id| db::get_account(&conn, account_id).unwrap().is_some());
+		assert_eq!(discovered, expected, "{:?}", discovery);
+	}
+
+	// Accounts discovered without a transfer are linked to the transaction only
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	let policy = StoragePolicy { account_discovery: AccountDiscovery::All, ..Default::default() };
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&policy,
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	let program = db::get_account(&conn, compute_budget).unwrap().unwrap();
+	assert_eq!(program.related_transactions.len(), 1);
+	assert_eq!((program.estimated_balance, program.total_received, program.total_sent), (1, 0, 0));
+	// Transfer parties keep their transfer totals
+	let receiver = db::get_account(&conn, receiver).unwrap().unwrap();
+	assert_eq!(receiver.total_received, 967);
+}
+
+#[test]
+fn test_versioned_transactions_index_lookup_table_accounts() {
+	let sender = "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g";
+	let receiver = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+	let lookup_table = "4sKLJ1Qoudh8PJyqBeuKocYdsZvxTcRShUt9aKqwhgvC";
+
+	// The node lists the looked up accounts in the parsed messages of version 0 transactions
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let transaction = &mut block["transactions"][0];
+	transaction["version"] = json!(0);
+	transaction["meta"]["loadedAddresses"] = json!({ "writable": [receiver], "readonly": [] });
+	transaction["transaction"]["message"]["accountKeys"][1]["source"] = json!("lookupTable");
+	transaction["transaction"]["message"]["addressTableLookups"] =
+		json!([{ "accountKey": lookup_table, "writableIndexes": [0], "readonlyIndexes": [] }]);
+	let transactions = parse_block(&serde_json::from_value(block.clone()).unwrap()).unwrap();
+	assert_eq!(transactions[0].details[0].receiver, receiver);
+	assert_eq!(
+		discover_accounts(&transactions[0], AccountDiscovery::Writable),
+		vec![receiver.to_string(), sender.to_string()]
+	);
+
+	// Raw messages only list their static keys, followed by the loaded ones in the balances
+	let transaction = &mut block["transactions"][0];
+	transaction["meta"]["preBalances"] = json!([771523253933u64, 1, 1, 1798950610u64]);
+	transaction["meta"]["postBalances"] = json!([771523247926u64, 1, 1, 1798951577u64]);
+	transaction["transaction"]["message"] = json!({
+		"header": {
+			"numRequiredSignatures": 1,
+			"numReadonlySignedAccounts": 0,
+			"numReadonlyUnsignedAccounts": 2
+		},
+		"accountKeys": [sender, "11111111111111111111111111111111", "ComputeBudget111111111111111111111111111111"],
+		"recentBlockhash": "FF2Z9QfmsehPeSoSC3ekupHCNt3VvxkLrcAZagAUXU85",
+		"instructions": [
+			{ "programIdIndex": 1, "accounts": [0, 3], "data": "3Bxs4NN8M2Yn4TLb", "stackHeight": null }
+		],
+		"addressTableLookups": [
+			{ "accountKey": lookup_table, "writableIndexes": [0], "readonlyIndexes": [] }
+		]
+	});
+	let transactions = parse_block(&serde_json::from_value(block).unwrap()).unwrap();
+	let transaction = &transactions[0];
+	assert!(transaction.details.is_empty());
+	assert_eq!(transaction.balance_changes.len(), 2);
+	assert_eq!(transaction.balance_changes[1].account, receiver);
+	assert_eq!(transaction.post_balances[3], (receiver.to_string(), 1798951577));
+	assert_eq!(discover_accounts(transaction, AccountDiscovery::Signers), vec![sender.to_string()]);
+	assert_eq!(
+		discover_accounts(transaction, AccountDiscovery::Writable),
+		vec![receiver.to_string(), sender.to_string()]
+	);
+	assert_eq!(discover_accounts(transaction, AccountDiscovery::All).len(), 4);
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy { account_discovery: AccountDiscovery::Writable, ..Default::default() },
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	let signatures =
+		db::get_account_signatures(&conn, receiver, None, None, &Default::default(), 10).unwrap();
+	assert_eq!(signatures.len(), 1);
+	let account = db::get_account(&conn, receiver).unwrap();
+	assert_eq!(account.map(|account| account.estimated_balance), Some(1798951577));
+}
+
+#[test]
+fn test_store_parsed_block_records_instructions_of_unparsed_programs() {
+	let sender = "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g";
+	let receiver = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+	let program = "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4";
+	let signature =
+		"2xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1";
+
+	let mut block: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	block["transactions"][0]["transaction"]["message"]["instructions"]
+		.as_array_mut()
+		.unwrap()
+		.push(json!({
+			"accounts": [sender, receiver],
+			"data": "3Bxs4NN8M2Yn4TLb",
+			"programId": program,
+			"stackHeight": null
+		}));
+	let create_account = json!({
+		"info": { "account": receiver, "mint": "So11111111111111111111111111111111111111112", "source": sender },
+		"type": "create"
+	});
+	let instructions = block["transactions"][0]["transaction"]["message"]["instructions"]
+		.as_array_mut()
+		.unwrap();
+	instructions.push(json!({
+		"parsed": create_account,
+		"program": "spl-associated-token-account",
+		"programId": "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL",
+		"stackHeight": null
+	}));
+	instructions.push(json!({
+		"parsed": "gm",
+		"program": "spl-memo",
+		"programId": "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr",
+		"stackHeight": null
+	}));
+	let transactions = parse_block(&serde_json::from_value(block).unwrap()).unwrap();
+	// The compute budget and memo instructions are left out, the parsed transfer is kept as such,
+	// and the JSON of the other parsed instructions is kept verbatim
+	let expected = vec![
+		ProgramInstruction {
+			index: 3,
+			program_id: program.to_string(),
+			accounts: vec![sender.to_string(), receiver.to_string()],
+			data: "3Bxs4NN8M2Yn4TLb".to_string(),
+			parsed: None,
+		},
+		ProgramInstruction {
+			index: 4,
+			program_id: "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL".to_string(),
+			accounts: vec![],
+			data: String::new(),
+			parsed: Some(create_account),
+		},
+	];
+	assert_eq!(transactions[0].program_instructions, expected);
+	assert_eq!(transactions[0].details[0].amount, 967);
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	assert_eq!(db::instructions::get_program_instructions(&conn, signature).unwrap(), expected);
+	let mint: String = conn
+		.query_row(
+			"SELECT json_extract(parsed, '$.info.mint') FROM program_instructions WHERE parsed IS NOT NULL",
+			[],
+			|row| row.get(0),
+		)
+		.unwrap();
+	assert_eq!(mint, "So11111111111111111111111111111111111111112");
+
+	db::delete_transaction_details(&conn, signature).unwrap();
+	assert!(db::instructions::get_program_instructions(&conn, signature).unwrap().is_empty());
+}
+
+#[test]
+fn test_compat_guard_rejects_newer_schema_and_other_network() {
+	let conn = Connection::open_in_memory().unwrap();
+	// A database without metadata predates the guard, but cannot be served without a migration
+	db::compat::check_schema_version(&conn).unwrap();
+	assert!(db::compat::check_schema_is_current(&conn).is_err());
+	initialize_db(&conn).unwrap();
+	db::compat::record_schema_version(&conn).unwrap();
+	db::compat::check_schema_version(&conn).unwrap();
+	db::compat::check_schema_is_current(&conn).unwrap();
+
+	conn.execute(
+		"UPDATE metadata SET value = ?1 WHERE key = 'schema_version'",
+		[(db::compat::SCHEMA_VERSION - 1).to_string()],
+	)
+	.unwrap();
+	db::compat::check_schema_version(&conn).unwrap();
+	let err = db::compat::check_schema_is_current(&conn).unwrap_err();
+	assert!(err.to_string().contains("older than this binary expects"));
+
+	conn.execute(
+		"UPDATE metadata SET value = ?1 WHERE key = 'schema_version'",
+		[(db::compat::SCHEMA_VERSION + 1).to_string()],
+	)
+	.unwrap();
+	let err = db::compat::check_schema_version(&conn).unwrap_err();
+	assert!(err.to_string().contains("newer than this binary supports"));
+	assert!(db::compat::check_schema_is_current(&conn).is_err());
+
+	let testnet = "4uhcVJyU9pJkvQyS88uRDiswHXSCkY3zQawwpjk2NsNY";
+	db::compat::check_genesis_hash(&conn, testnet).unwrap();
+	db::compat::check_genesis_hash(&conn, testnet).unwrap();
+	let err = db::compat::check_genesis_hash(&conn, "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d")
+		.unwrap_err();
+	assert!(err.to_string().contains("separate database for each network"));
+}
+
+#[test]
+fn test_migrations_evolve_an_existing_schema() {
+	use db::migrations::{apply_migrations, get_schema_version, Migration, MIGRATIONS};
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	assert_eq!(get_schema_version(&conn).unwrap(), db::migrations::LATEST_VERSION);
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&parse_block(&create_mock_ui_confirmed_block()).unwrap(),
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+
+	// A later release adds a column to the populated database
+	let next = db::migrations::LATEST_VERSION + 1;
+	let migrations: Vec<Migration> = MIGRATIONS
+		.iter()
+		.map(|migration| Migration { ..*migration })
+		.chain([Migration {
+			version: next,
+			description: "add transaction notes",
+			apply: |conn| conn.execute_batch("ALTER TABLE transactions ADD COLUMN note TEXT"),
+		}])
+		.collect();
+	assert_eq!(apply_migrations(&conn, &migrations).unwrap(), vec![next]);
+	assert_eq!(get_schema_version(&conn).unwrap(), next);
+	let (count, notes): (u64, u64) = conn
+		.query_row("SELECT COUNT(*), COUNT(note) FROM transactions", [], |row| {
+			Ok((row.get(0)?, row.get(1)?))
+		})
+		.unwrap();
+	assert_eq!((count, notes), (1, 0));
+	assert!(apply_migrations(&conn, &migrations).unwrap().is_empty());
+
+	// A failed migration is rolled back and left pending
+	let failing = [Migration {
+		version: next + 1,
+		description: "broken",
+		apply: |conn| {
+			conn.execute_batch("CREATE TABLE partial (id INTEGER)")?;
+			conn.execute_batch("ALTER TABLE missing ADD COLUMN id INTEGER")
+		},
+	}];
+	assert!(apply_migrations(&conn, &failing).is_err());
+	assert_eq!(get_schema_version(&conn).unwrap(), next);
+	assert!(conn.prepare("SELECT * FROM partial").is_err());
+}
+
+#[test]
+fn test_legacy_rows_are_repaired_from_the_stored_data() {
+	use db::repair::{repair_legacy_rows, Unrepaired};
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	// A legacy transaction of the payer to the receiver, as stored raw
+	let raw = json!({
+		"signatures": ["sig"],
+		"message": {
+			"header": {
+				"numRequiredSignatures": 1,
+				"numReadonlySignedAccounts": 0,
+				"numReadonlyUnsignedAccounts": 0
+			},
+			"accountKeys": ["payer", "receiver"],
+			"recentBlockhash": "11111111111111111111111111111111",
+			"instructions": []
+		}
+	})
+	.to_string();
+	conn.execute_batch(&format!(
+		"INSERT INTO blocks (slot, block_time) VALUES (10, 1700000010);
+         INSERT INTO transactions (transaction_id, timestamp, block_height, raw_transaction, fee, fee_payer)
+         VALUES ('tx1', 0, 10, '{raw}', 5000, 'payer'),
+                ('tx2', 0, 11, '{raw}', 5000, 'payer'),
+                ('tx3', 1700000012, 12, 'sha256:00', 5000, 'payer');
+         INSERT INTO transfers (transaction_id, transfer_index, sender, receiver, amount, timestamp, block_height, kind)
+         VALUES ('tx1', 0, 'payer', 'receiver', '700', 0, 10, 'system');
+         INSERT INTO account_transactions (account_id, transaction_id, block_height, transaction_index)
+         VALUES ('payer', 'tx1', 10, 0), ('payer', 'tx2', 11, 3), ('payer', 'tx3', 12, 1);
+         INSERT INTO balance_changes (account_id, transaction_id, block_height, transaction_index, pre_balance, post_balance)
+         VALUES ('payer', 'tx2', 11, 3, '10000', '4300');
+         INSERT INTO accounts (account_id, estimated_balance, related_transactions, total_received, total_sent)
+         VALUES ('payer', '0', '[\"tx3\"]', '0', '700'),
+                ('receiver', '0', '[\"tx2\"]', '700', '0'),
+                ('idle', '0', '[]', '0', '0');"
+	))
+	.unwrap();
+
+	let report = repair_legacy_rows(&conn).unwrap();
+	assert_eq!(report.timestamps_repaired, 2);
+	assert_eq!(report.links_added, 2);
+	assert_eq!(report.related_transactions_repaired, 2);
+	assert_eq!(report.balances_repaired, 2);
+	assert_eq!(report.missing_timestamps, Unrepaired { count: 1, examples: vec!["tx2".into()] });
+	assert_eq!(report.missing_balances, Unrepaired { count: 1, examples: vec!["idle".into()] });
+	assert_eq!(
+		report.unreadable_transactions,
+		Unrepaired { count: 1, examples: vec!["tx3".into()] }
+	);
+
+	// The transaction and its transfer take the time of their block
+	assert_eq!(db::get_transaction(&conn, "tx1").unwrap().unwrap().timestamp, 1700000010);
+	let transfer_time: i64 = conn
+		.query_row("SELECT timestamp FROM transfers WHERE transaction_id = 'tx1'", [], |row| {
+			row.get(0)
+		})
+		.unwrap();
+	assert_eq!(transfer_time, 1700000010);
+
+	// The payer takes its latest balance and keeps the order of its transactions
+	let payer = db::get_account(&conn, "payer").unwrap().unwrap();
+	assert_eq!(payer.related_transactions, vec!["tx1", "tx2", "tx3"]);
+	assert_eq!((payer.estimated_balance, payer.balance_slot), (4300, Some(11)));
+	assert_eq!(payer.balance_transaction_index, Some(3));
+
+	// The receiver is linked from the raw transactions and estimated from its transfers
+	let receiver = db::get_account(&conn, "receiver").unwrap().unwrap();
+	assert_eq!(receiver.related_transactions, vec!["tx1", "tx2"]);
+	assert_eq!((receiver.estimated_balance, receiver.balance_slot), (700, None));
+
+	// The repaired rows are left alone by another run
+	let report = repair_legacy_rows(&conn).unwrap();
+	assert_eq!(
+		(report.timestamps_repaired, report.links_added, report.related_transactions_repaired),
+		(0, 0, 0)
+	);
+	assert_eq!(report.balances_repaired, 0);
+}
+
+#[test]
+fn test_account_seen_slots_follow_ingested_blocks() {
+	let receiver = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+	let transactions = parse_block(&create_mock_ui_confirmed_block()).unwrap();
+	let mut earlier: serde_json::Value = serde_json::from_str(MOCK_JSON).unwrap();
+	earlier["transactions"][0]["transaction"]["signatures"][0] = json!(
+		"3xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1"
+	);
+	let earlier = parse_block(&serde_json::from_value(earlier).unwrap()).unwrap();
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	store_parsed_block(
+		&conn,
+		310176000,
+		Some(1720421680),
+		&transactions,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	let account = db::get_account(&conn, receiver).unwrap().unwrap();
+	assert_eq!(
+		(account.first_seen_slot, account.last_active_slot),
+		(Some(310176000), Some(310176000))
+	);
+
+	// A backfilled older block moves the first seen slot back only
+	store_parsed_block(
+		&conn,
+		310100000,
+		Some(1720390000),
+		&earlier,
+		&StoragePolicy::default(),
+		&ProgramFilters::default(),
+		&mut RecentWrites::default(),
+	)
+	.unwrap();
+	let account = db::get_account(&conn, receiver).unwrap().unwrap();
+	assert_eq!(
+		(account.first_seen_slot, account.last_active_slot),
+		(Some(310100000), Some(310176000))
+	);
+}
+
+#[test]
+fn test_initialize_db_backfills_account_seen_slots() {
+	let conn = Connection::open_in_memory().unwrap();
+	conn.execute_batch(
+		"CREATE TABLE accounts (
+            account_id TEXT PRIMARY KEY,
+            estimated_balance TEXT,
+            related_transactions TEXT,
+            total_received TEXT NOT NULL DEFAULT '0',
+            total_sent TEXT NOT NULL DEFAULT '0'
+        );
+        CREATE TABLE account_transactions (
+            account_id TEXT,
+            transaction_id TEXT,
+            block_height INTEGER,
+            transaction_index INTEGER,
+            PRIMARY KEY (account_id, transaction_id)
+        );
+        INSERT INTO accounts VALUES ('acc1', '10', '[\"tx1\",\"tx2\"]', '10', '0');
+        INSERT INTO account_transactions VALUES ('acc1', 'tx1', 50, 0), ('acc1', 'tx2', 80, 3);",
+	)
+	.unwrap();
+
+	initialize_db(&conn).unwrap();
+
+	let account = db::get_account(&conn, "acc1").unwrap().unwrap();
+	assert_eq!((account.first_seen_slot, account.last_active_slot), (Some(50), Some(80)));
+	assert_eq!(account.total_received, 10);
+}
+
+#[test]
+fn test_block_cache_serves_and_evicts_blocks() {
+	use crate::{aggregator::cache::BlockCache, types::BlockCacheConfig};
+
+	let path = std::env::temp_dir().join(format!("block-cache-test-{}", std::process::id()));
+	let _ = std::fs::remove_dir_all(&path);
+	let config = |max_size_mb| BlockCacheConfig {
+		path: path.to_string_lossy().into_owned(),
+		max_size_mb,
+		ttl: None,
+	};
+	let block = create_mock_ui_confirmed_block();
+
+	let cache = BlockCache::open(&config(1)).unwrap();
+	assert!(cache.get(310176000).is_none());
+	cache.put(310176000, &block);
+	assert_eq!(cache.get(310176000).unwrap().blockhash, block.blockhash);
+
+	// The blocks on disk are indexed when the cache is opened again
+	let cache = BlockCache::open(&config(1)).unwrap();
+	assert_eq!(cache.get(310176000).unwrap().previous_blockhash, block.previous_blockhash);
+
+	// A cache too small for any block evicts it right away
+	let cache = BlockCache::open(&config(0)).unwrap();
+	assert!(cache.get(310176000).is_none());
+	cache.put(310176001, &block);
+	assert!(cache.get(310176001).is_none());
+	assert_eq!(std::fs::read_dir(&path).unwrap().count(), 0);
+
+	std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_sync_state_records_processed_slots() {
+	use crate::db::sync_state::get_last_processed_slot;
+
+	let path = std::env::temp_dir().join(format!("sync-state-test-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let conn = Connection::open(&path).unwrap();
+	initialize_db(&conn).unwrap();
+	assert_eq!(get_last_processed_slot(&conn).unwrap(), None);
+
+	let (writer, handle) = DbWriter::spawn(
+		Connection::open(&path).unwrap(),
+		StoragePolicy::default(),
+		EventBus::default(),
+	);
+	writer.advance_sync_state(1200).await.unwrap();
+	// A backfill of older slots does not move the checkpoint back
+	writer.advance_sync_state(1100).await.unwrap();
+	writer.checkpoint().await.unwrap();
+	drop(writer);
+	handle.join().unwrap();
+	assert_eq!(get_last_processed_slot(&conn).unwrap(), Some(1200));
+
+	// Databases predating the table resume after their last stored block
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	conn.execute_batch(
+		"DROP TABLE sync_state; DROP TABLE schema_version; INSERT INTO blocks (slot, block_time) VALUES (900, 1), (950, 2);",
+	)
+	.unwrap();
+	initialize_db(&conn).unwrap();
+	assert_eq!(get_last_processed_slot(&conn).unwrap(), Some(950));
+
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_failed_slots_are_retried_with_backoff_until_stored() {
+	use crate::db::failed_slots::{
+		get_due_failed_slots, get_failed_slot, record_failed_slot, requeue_failed_slot,
+		MAX_ATTEMPTS, MAX_RETRY_DELAY_SECS, MIN_RETRY_DELAY_SECS,
+	};
+
+	let path = std::env::temp_dir().join(format!("failed-slots-test-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let conn = Connection::open(&path).unwrap();
+	initialize_db(&conn).unwrap();
+
+	let (writer, handle) = DbWriter::spawn(
+		Connection::open(&path).unwrap(),
+		StoragePolicy::default(),
+		EventBus::default(),
+	);
+	writer.mark_failed(310176000, "getBlock failed: timed out").await.unwrap();
+	writer.checkpoint().await.unwrap();
+	let record = get_failed_slot(&conn, 310176000).unwrap().unwrap();
+	assert_eq!(record.attempts, 1);
+	assert_eq!(record.next_attempt_at, Some(record.last_failed_at + MIN_RETRY_DELAY_SECS));
+	assert!(get_due_failed_slots(&conn, record.last_failed_at, 10).unwrap().is_empty());
+
+	// Each failure doubles the delay, up to the cap, and the slot is parked after the last attempt
+	let record = record_failed_slot(&conn, 310176000, "parse failed", 1000).unwrap();
+	assert_eq!(
+		(record.attempts, record.next_attempt_at),
+		(2, Some(1000 + 2 * MIN_RETRY_DELAY_SECS))
+	);
+	assert_eq!(
+		get_due_failed_slots(&conn, 1000 + 2 * MIN_RETRY_DELAY_SECS, 10).unwrap(),
+		vec![310176000]
+	);
+	for _ in 2..MAX_ATTEMPTS - 1 {
+		record_failed_slot(&conn, 310176000, "parse failed", 1000).unwrap();
+	}
+	let record = get_failed_slot(&conn, 310176000).unwrap().unwrap();
+	assert_eq!(record.next_attempt_at, Some(1000 + MAX_RETRY_DELAY_SECS));
+	let record = record_failed_slot(&conn, 310176000, "parse failed", 2000).unwrap();
+	assert_eq!((record.attempts, record.next_attempt_at), (MAX_ATTEMPTS, None));
+	assert!(get_due_failed_slots(&conn, i64::MAX, 10).unwrap().is_empty());
+
+	// A requeued slot is due right away, and leaves the queue once its block is stored
+	let record = requeue_failed_slot(&conn, 310176000, 3000).unwrap().unwrap();
+	assert_eq!((record.attempts, record.next_attempt_at), (MAX_ATTEMPTS, Some(3000)));
+	assert!(requeue_failed_slot(&conn, 1, 3000).unwrap().is_none());
+	let block = create_mock_ui_confirmed_block();
+	let transactions = parse_block(&block).unwrap();
+	writer
+		.write_block(get_block_record(310176000, &block), transactions)
+		.await
+		.unwrap();
+	writer.checkpoint().await.unwrap();
+	assert_eq!(get_failed_slot(&conn, 310176000).unwrap(), None);
+
+	drop(writer);
+	handle.join().unwrap();
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_epoch_catalog_follows_stored_blocks() {
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	// Epochs of 32 slots from slot 0
+	let schedule = EpochSchedule::custom(32, 32, false);
+	let epoch = |epoch: u64, start_time: Option<i64>, end_time: Option<i64>| EpochRecord {
+		epoch,
+		first_slot: epoch * 32,
+		last_slot: epoch * 32 + 31,
+		start_time,
+		end_time,
+	};
+
+	assert_eq!(catalog_stored_epochs(&conn, &schedule, 0).unwrap(), 0);
+	for (slot, block_time) in [(10, 100), (20, 104), (40, 112)] {
+		db::blocks::insert_or_update_block(&conn, slot, block_time).unwrap();
+	}
+	assert_eq!(catalog_stored_epochs(&conn, &schedule, 0).unwrap(), 2);
+	assert_eq!(catalog_stored_epochs(&conn, &schedule, 35).unwrap(), 1);
+	assert_eq!(
+		db::epochs::get_epochs(&conn, None, None, 10).unwrap(),
+		[epoch(1, Some(112), Some(112)), epoch(0, Some(100), Some(104))]
+	);
+
+	// The ingested blocks extend the times of their epoch, or catalog it, without a time if the
+	// node did not report one
+	catalog_block(&conn, &schedule, 50, Some(116)).unwrap();
+	catalog_block(&conn, &schedule, 45, None).unwrap();
+	catalog_block(&conn, &schedule, 70, None).unwrap();
+	assert_eq!(
+		db::epochs::get_epochs(&conn, None, None, 10).unwrap(),
+		[epoch(2, None, None), epoch(1, Some(112), Some(116)), epoch(0, Some(100), Some(104))]
+	);
+	catalog_block(&conn, &schedule, 71, Some(130)).unwrap();
+	assert_eq!(db::epochs::get_epoch(&conn, 2).unwrap(), Some(epoch(2, Some(130), Some(130))));
+}
+
+#[test]
+fn test_storage_quota_prunes_the_oldest_slots() {
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	for (transaction_id, block_height) in [("old", 100), ("older", 50), ("recent", 20_100)] {
+		let record = TransactionRecord {
+			transaction_id: transaction_id.to_string(),
+			timestamp: 1622556000 + block_height as i64,
+			block_height,
+			raw_transaction: "r".repeat(50_000),
+			fee: 5000,
+			fee_payer: "acc1".to_string(),
+			compute_unit_limit: None,
+			compute_unit_price: None,
+		};
+		db::insert_or_update_transaction(&conn, &record).unwrap();
+	}
+	let stored = |conn: &Connection| {
+		let mut stmt = conn
+			.prepare("SELECT transaction_id FROM transactions ORDER BY block_height")
+			.unwrap();
+		stmt.query_map([], |row| row.get(0))
+			.unwrap()
+			.collect::<Result<Vec<String>, _>>()
+			.unwrap()
+	};
+	let used_bytes = storage::get_database_size(&conn).unwrap().used_bytes;
+
+	// Under the quota, or exceeding it with the pause action, nothing is pruned
+	let status = storage::enforce_quota(&conn, used_bytes * 2, QuotaAction::Prune).unwrap();
+	assert!(!status.exceeded && !status.ingestion_paused);
+	let status = storage::enforce_quota(&conn, used_bytes / 2, QuotaAction::Pause).unwrap();
+	assert!(status.exceeded && status.ingestion_paused);
+	assert_eq!(stored(&conn), ["older", "old", "recent"]);
+
+	// The prune action deletes the oldest slots until the data is back under the quota
+	let status = storage::enforce_quota(&conn, used_bytes - 10_000, QuotaAction::Prune).unwrap();
+	assert!(!status.exceeded && !status.ingestion_paused);
+	assert_eq!(stored(&conn), ["recent"]);
+	assert!(status.used_bytes < used_bytes);
+
+	// Nothing left to prune pauses the ingestion
+	let status = storage::enforce_quota(&conn, 1, QuotaAction::Prune).unwrap();
+	assert!(status.exceeded && status.ingestion_paused);
+	assert!(stored(&conn).is_empty());
+	assert_eq!(storage::prune_oldest_slots(&conn, 10).unwrap(), 0);
+}
+
+#[test]
+fn test_account_snapshots_decode_with_idl_and_skip_unchanged_accounts() {
+	let idl = Idl::parse(&json!({
+		"accounts": [{
+			"name": "Counter",
+			"type": {
+				"kind": "struct",
+				"fields": [
+					{ "name": "authority", "type": "publicKey" },
+					{ "name": "count", "type": "u64" },
+					{ "name": "label", "type": { "option": "string" } },
+					{ "name": "state", "type": { "defined": "State" } }
+				]
+			}
+		}],
+		"types": [{
+			"name": "State",
+			"type": { "kind": "enum", "variants": [{ "name": "Open" }, { "name": "Closed" }] }
+		}]
+	}))
+	.unwrap();
+	let authority = solana_program::pubkey::Pubkey::new_unique();
+	let counter = |count: u64| {
+		let mut data = account_discriminator("Counter").to_vec();
+		data.extend_from_slice(authority.as_ref());
+		data.extend_from_slice(&count.to_le_bytes());
+		data.extend_from_slice(&[1, 2, 0, 0, 0]);
+		data.extend_from_slice(b"hi");
+		data.push(1);
+		ProgramAccount { pubkey: "counter".to_string(), lamports: 1_000_000, data }
+	};
+
+	let snapshot = snapshot_account("program", 100, &counter(7), Some(&idl), 1720421680);
+	assert_eq!(snapshot.account_type.as_deref(), Some("Counter"));
+	assert_eq!(
+		snapshot.decoded,
+		Some(json!({
+			"authority": authority.to_string(),
+			"count": 7,
+			"label": "hi",
+			"state": "Closed"
+		}))
+	);
+	assert_eq!(snapshot.data_length, 8 + 32 + 8 + 7 + 1);
+	// Data the IDL does not describe is stored undecoded
+	let unknown = ProgramAccount { data: vec![0; 16], ..counter(7) };
+	let undecoded = snapshot_account("program", 100, &unknown, Some(&idl), 1720421680);
+	assert_eq!((undecoded.account_type, undecoded.decoded), (None, None));
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	assert!(account_snapshots::insert_account_snapshot(&conn, &snapshot).unwrap());
+	// An unchanged account is not stored again, a changed one is
+	let unchanged = snapshot_account("program", 200, &counter(7), Some(&idl), 1720421690);
+	assert!(!account_snapshots::insert_account_snapshot(&conn, &unchanged).unwrap());
+	let changed = snapshot_account("program", 300, &counter(8), Some(&idl), 1720421700);
+	assert!(account_snapshots::insert_account_snapshot(&conn, &changed).unwrap());
+	let snapshots =
+		account_snapshots::get_account_snapshots(&conn, "counter", None, None, 10).unwrap();
+	assert_eq!(snapshots, vec![changed, snapshot]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_transaction_lookup_falls_back_to_the_rpc_node() {
+	use crate::{
+		aggregator::backfill::Backfiller,
+		rpc::RpcGateway,
+		server::auth::ApiKeys,
+		types::{ApiKeyConfig, Config},
+	};
+	use axum::Json;
+	use serde_json::Value;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	// A node knowing the transaction of the mock block only
+	let block: Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let mut transaction = block["transactions"][0].clone();
+	transaction["slot"] = json!(310176000);
+	transaction["blockTime"] = json!(1720421680);
+	let signature = transaction["transaction"]["signatures"][0].as_str().unwrap().to_string();
+	let requests = Arc::new(AtomicUsize::new(0));
+	let app = Router::new().route(
+		"/",
+		post({
+			let requests = Arc::clone(&requests);
+			move |Json(request): Json<Value>| {
+				requests.fetch_add(1, Ordering::SeqCst);
+				let transaction = transaction.clone();
+				async move {
+					assert_eq!(request["method"], "getTransaction");
+					let known = request["params"][0] == transaction["transaction"]["signatures"][0];
+					let result = if known { transaction } else { Value::Null };
+					Json(json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+				}
+			}
+		}),
+	);
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = listener.local_addr().unwrap();
+	tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+	let config: Config = toml::from_str(&format!(
+		"rpc_url = \"http://{}\"\nretry_attempts = 0\nserver_address = \"127.0.0.1:0\"",
+		address
+	))
+	.unwrap();
+
+	let path =
+		std::env::temp_dir().join(format!("aggregator-passthrough-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let pool = bb8::Pool::builder()
+		.max_size(2)
+		.build(SqliteConnectionManager::new(&path, |_| Ok(())))
+		.await
+		.unwrap();
+	initialize_db(&pool.get().await.unwrap()).unwrap();
+	let (writer, _) = DbWriter::spawn(
+		Connection::open(&path).unwrap(),
+		StoragePolicy::default(),
+		EventBus::default(),
+	);
+	let backfiller = Backfiller::new(Arc::new(RpcGateway::new(&config)), pool.clone(), writer);
+	let state = AppState {
+		backfiller: Some(backfiller),
+		transaction_passthrough: true,
+		..AppState::new(pool.clone())
+	};
+	let key = |name: &str, groups: &[&str]| ApiKeyConfig {
+		name: name.to_string(),
+		key: format!("{}-secret", name),
+		groups: groups.iter().map(|group| group.to_string()).collect(),
+		label_tags: Vec::new(),
+	};
+	let api_keys = ApiKeys::new(&[key("admin", &[]), key("team", &["treasury"])]).unwrap();
+	let router = Router::new()
+		.route("/transaction", get(get_transaction_handler))
+		.layer(Extension(state))
+		.layer(middleware::from_fn(move |request, next| {
+			api_keys.clone().authenticate(request, next)
+		}));
+	let lookup_with = |key: &str, signature: &str| {
+		let request = Request::builder()
+			.uri(format!("/transaction?tx-id={}", signature))
+			.header("authorization", format!("Bearer {}-secret", key))
+			.body(Body::empty())
+			.unwrap();
+		let router = router.clone();
+		async move {
+			let response = router.oneshot(request).await.unwrap();
+			let status = response.status();
+			let body = to_bytes(response.into_body()).await.unwrap();
+			// Errors come with a plain text message
+			(status, serde_json::from_slice::<Value>(&body).unwrap_or_default())
+		}
+	};
+	let lookup = |signature: &str| lookup_with("admin", signature);
+
+	// A restricted key does not make the service fetch and store the missing transaction
+	let (status, _) = lookup_with("team", &signature).await;
+	assert_eq!(status, StatusCode::NOT_FOUND);
+	assert_eq!(requests.load(Ordering::SeqCst), 0);
+	assert!(db::get_transaction(&pool.get().await.unwrap(), &signature).unwrap().is_none());
+
+	// The missing transaction is fetched, stored and marked as such
+	let (status, body) = lookup(&signature).await;
+	assert_eq!(status, StatusCode::OK);
+	assert_eq!(body["source"], "rpc");
+	assert_eq!(body["block_height"], 310176000);
+	assert_eq!(body["fee"], 5040);
+	// From then on it is read from the database
+	let (status, body) = lookup(&signature).await;
+	assert_eq!(status, StatusCode::OK);
+	assert!(body.get("source").is_none());
+
+	let unknown =
+		"5VERv8NMvzbJMEkV8xnrLkEaWRtSz9CosKDYjCJjBRnbJLgp8uirBgmQpjKhoR4tjF3ZpRzrFmBV6UjKdiSZkQUW";
+	let (status, _) = lookup(unknown).await;
+	assert_eq!(status, StatusCode::NOT_FOUND);
+	let (status, _) = lookup("not-a-signature").await;
+	assert_eq!(status, StatusCode::NOT_FOUND);
+
+	drop(pool);
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_ingestion_stops_at_a_block_boundary_on_shutdown() {
+	use crate::{aggregator::Ingestion, db::sync_state, rpc::RpcGateway, types::Config};
+	use axum::Json;
+	use serde_json::Value;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	// A node serving the mock block at every slot, whose first request comes with a shutdown
+	let shutdown = Shutdown::new();
+	let requests = Arc::new(AtomicUsize::new(0));
+	let block: Value = serde_json::from_str(MOCK_JSON).unwrap();
+	let app = Router::new().route(
+		"/",
+		post({
+			let (shutdown, requests) = (shutdown.clone(), Arc::clone(&requests));
+			move |Json(request): Json<Value>| {
+				shutdown.request();
+				requests.fetch_add(1, Ordering::SeqCst);
+				let block = block.clone();
+				async move {
+					assert_eq!(request["method"], "getBlock");
+					Json(json!({ "jsonrpc": "2.0", "id": request["id"], "result": block }))
+				}
+			}
+		}),
+	);
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = listener.local_addr().unwrap();
+	tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+	let config: Config = toml::from_str(&format!(
+		"rpc_url = \"http://{}\"\nretry_attempts = 0\nserver_address = \"127.0.0.1:0\"",
+		address
+	))
+	.unwrap();
+	let gateway = RpcGateway::new(&config);
+
+	let path = std::env::temp_dir().join(format!("aggregator-shutdown-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let conn = Connection::open(&path).unwrap();
+	initialize_db(&conn).unwrap();
+	let (writer, _) = DbWriter::spawn(conn, StoragePolicy::default(), EventBus::default());
+	let ingestion = Ingestion {
+		gateway: &gateway,
+		writer: &writer,
+		archiver: None,
+		cache: None,
+		shutdown: Some(&shutdown),
+		chain: Default::default(),
+	};
+
+	// The block being fetched is written, and the progress is recorded up to it only
+	ingestion.ingest_slots(310176000..=310176005).await.unwrap();
+	writer.checkpoint().await.unwrap();
+	assert_eq!(requests.load(Ordering::SeqCst), 1);
+	let conn = Connection::open(&path).unwrap();
+	assert_eq!(sync_state::get_last_processed_slot(&conn).unwrap(), Some(310176000));
+	let signature =
+		"2xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1";
+	assert!(db::get_transaction(&conn, signature).unwrap().is_some());
+	// Nothing more is fetched once stopped
+	ingestion.ingest_slots(310176001..=310176005).await.unwrap();
+	assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+	drop(conn);
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_continuous_ingestion_follows_the_tip_until_shutdown() {
+	use crate::{aggregator::aggregate_blocks, db::sync_state, rpc::RpcGateway, types::Config};
+	use axum::Json;
+	use serde_json::Value;
+	use std::sync::atomic::{AtomicU64, Ordering};
+
+	// A node at slot 100, whose next block holds the mock transaction
+	let signature =
+		"2xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1";
+	let block = |blockhash: &str, parent_slot: u64, previous_blockhash: &str, holds: bool| {
+		let mut block: Value = serde_json::from_str(MOCK_JSON).unwrap();
+		block["blockhash"] = json!(blockhash);
+		block["parentSlot"] = json!(parent_slot);
+		block["previousBlockhash"] = json!(previous_blockhash);
+		if !holds {
+			block["transactions"] = json!([]);
+		}
+		block
+	};
+	let blocks = [
+		(100, block("hash100", 99, "hash99", false)),
+		(101, block("hash101", 100, "hash100", true)),
+	];
+	let tip = Arc::new(AtomicU64::new(100));
+	let app = Router::new().route(
+		"/",
+		post({
+			let tip = Arc::clone(&tip);
+			move |Json(request): Json<Value>| {
+				let tip = tip.load(Ordering::SeqCst);
+				let result = match request["method"].as_str().unwrap() {
+					"getEpochInfo" => json!({
+						"absoluteSlot": tip,
+						"blockHeight": tip,
+						"epoch": 0,
+						"slotIndex": tip,
+						"slotsInEpoch": 432000,
+						"transactionCount": null
+					}),
+					"getSlot" => json!(tip),
+					"minimumLedgerSlot" | "getFirstAvailableBlock" => json!(0),
+					"getBlock" => {
+						let slot = request["params"][0].as_u64().unwrap();
+						assert!(slot <= tip);
+						blocks.iter().find(|(block_slot, _)| *block_slot == slot).unwrap().1.clone()
+					},
+					method => panic!("Unexpected {} request", method),
+				};
+				let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+				async move { Json(response) }
+			}
+		}),
+	);
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = listener.local_addr().unwrap();
+	tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+	let config: Config = toml::from_str(&format!(
+		"rpc_url = \"http://{}\"\nretry_attempts = 0\nserver_address = \"127.0.0.1:0\"\n\
+		 continuous = true\npoll_interval_ms = 50\nstart_from = {{ slot = 100 }}",
+		address
+	))
+	.unwrap();
+
+	let path =
+		std::env::temp_dir().join(format!("aggregator-continuous-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let pool = bb8::Pool::builder()
+		.max_size(2)
+		.build(SqliteConnectionManager::new(&path, |_| Ok(())))
+		.await
+		.unwrap();
+	initialize_db(&pool.get().await.unwrap()).unwrap();
+	let (writer, _) = DbWriter::spawn(
+		Connection::open(&path).unwrap(),
+		StoragePolicy::default(),
+		EventBus::default(),
+	);
+	let shutdown = Shutdown::new();
+	let ingestion = tokio::spawn(aggregate_blocks(
+		Arc::new(RpcGateway::new(&config)),
+		pool.clone(),
+		writer,
+		config,
+		shutdown.clone(),
+	));
+	let conn = Connection::open(&path).unwrap();
+	let wait_for_slot = |slot: u64| {
+		let conn = &conn;
+		async move {
+			for _ in 0..50 {
+				if sync_state::get_last_processed_slot(conn).unwrap() == Some(slot) {
+					return
+				}
+				tokio::time::sleep(Duration::from_millis(100)).await;
+			}
+			panic!("Slot {} was not ingested", slot);
+		}
+	};
+
+	// The ingestion catches up to the tip, and keeps running
+	wait_for_slot(100).await;
+	assert!(db::get_transaction(&conn, signature).unwrap().is_none());
+	assert!(!ingestion.is_finished());
+	// A block produced since is picked up by the next poll
+	tip.store(101, Ordering::SeqCst);
+	wait_for_slot(101).await;
+	assert!(db::get_transaction(&conn, signature).unwrap().is_some());
+
+	// The shutdown stops the loop
+	shutdown.request();
+	let result = tokio::time::timeout(Duration::from_secs(5), ingestion).await.unwrap().unwrap();
+	assert!(result.is_ok());
+
+	drop((conn, pool));
+	std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_ingestion_rolls_back_abandoned_fork() {
+	use crate::{aggregator::Ingestion, rpc::RpcGateway, types::Config};
+	use axum::Json;
+	use serde_json::Value;
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	// Slot 101 holds the mock transaction until the cluster switches to a fork skipping it
+	let signature =
+		"2xBbzb1SjzSw5VjY92bjRYUB49Exnn45xE7RXRdbgR4XuyKQzJKFkA5kyy98MEDHDCUaQe1qEN4YbyY6jNpUqm1";
+	let block = |blockhash: &str, parent_slot: u64, previous_blockhash: &str, holds: bool| {
+		let mut block: Value = serde_json::from_str(MOCK_JSON).unwrap();
+		block["blockhash"] = json!(blockhash);
+		block["parentSlot"] = json!(parent_slot);
+		block["previousBlockhash"] = json!(previous_blockhash);
+		if !holds {
+			block["transactions"] = json!([]);
+		}
+		block
+	};
+	let abandoned = [
+		(100, block("hash100", 99, "hash99", false)),
+		(101, block("hash101a", 100, "hash100", true)),
+		(102, block("hash102a", 101, "hash101a", false)),
+	];
+	let canonical = [
+		(100, block("hash100", 99, "hash99", false)),
+		(102, block("hash102b", 100, "hash100", false)),
+		(103, block("hash103b", 102, "hash102b", false)),
+	];
+	let forked = Arc::new(AtomicBool::new(false));
+	let app = Router::new().route(
+		"/",
+		post({
+			let forked = Arc::clone(&forked);
+			move |Json(request): Json<Value>| {
+				let slot = request["params"][0].as_u64().unwrap();
+				let chain = if forked.load(Ordering::SeqCst) { &canonical } else { &abandoned };
+				let response = match chain.iter().find(|(block_slot, _)| *block_slot == slot) {
+					Some((_, block)) => {
+						json!({ "jsonrpc": "2.0", "id": request["id"], "result": block })
+					},
+					None => json!({
+						"jsonrpc": "2.0",
+						"id": request["id"],
+						"error": { "code": -32007, "message": format!("Slot {} was skipped", slot) },
+					}),
+				};
+				async move { Json(response) }
+			}
+		}),
+	);
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = listener.local_addr().unwrap();
+	tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+	let config: Config = toml::from_str(&format!(
+		"rpc_url = \"http://{}\"\nretry_attempts = 0\nserver_address = \"127.0.0.1:0\"",
+		address
+	))
+	.unwrap();
+	let gateway = RpcGateway::new(&config);
+
+	let path = std::env::temp_dir().join(format!("aggregator-reorg-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let conn = Connection::open(&path).unwrap();
+	initialize_db(&conn).unwrap();
+	let events = EventBus::default();
+	let mut receiver = events.subscribe();
+	let (writer, _) =
+		DbWriter::spawn(Connection::open(&path).unwrap(), StoragePolicy::default(), events);
+	let ingestion = Ingestion {
+		gateway: &gateway,
+		writer: &writer,
+		archiver: None,
+		cache: None,
+		shutdown: None,
+		chain: Default::default(),
+	};
+	ingestion.ingest_slots(100..=102).await.unwrap();
+	writer.checkpoint().await.unwrap();
+	assert!(db::get_transaction(&conn, signature).unwrap().is_some());
+	let receiver_account = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+	assert_eq!(db::get_account(&conn, receiver_account).unwrap().unwrap().total_received, 967);
+	while receiver.try_recv().is_ok() {}
+
+	// The block at 103 descends from another block at 102, whose parent is the stored block at 100:
+	// the blocks stored at 101 and 102 are rolled back, and the canonical one written at 102
+	forked.store(true, Ordering::SeqCst);
+	ingestion.ingest_slots(103..=103).await.unwrap();
+	writer.checkpoint().await.unwrap();
+	let blockhash =
+		|slot| blocks::get_block(&conn, slot).unwrap().and_then(|block| block.blockhash);
+	assert_eq!(
+		[blockhash(100), blockhash(101), blockhash(102), blockhash(103)],
+		[
+			Some("hash100".to_string()),
+			None,
+			Some("hash102b".to_string()),
+			Some("hash103b".to_string())
+		]
+	);
+	let mut reorgs = Vec::new();
+	while let Ok(event) = receiver.try_recv() {
+		if let Event::ReorgDetected { slot, orphaned_transactions } = event {
+			reorgs.push((slot, orphaned_transactions));
+		}
+	}
+	assert_eq!(reorgs, [(101, vec![signature.to_string()]), (102, vec![])]);
+
+	// Along with the transaction, what it added to its accounts and its day is taken back
+	assert!(db::get_transaction(&conn, signature).unwrap().is_none());
+	let account = db::get_account(&conn, receiver_account).unwrap().unwrap();
+	assert_eq!((account.total_received, account.balance_slot), (0, None));
+	assert!(account.related_transactions.is_empty());
+	let days = db::rollups::get_daily_stats(&conn, None, None, 10).unwrap();
+	assert!(days.iter().all(|day| day.transaction_count == 0 && day.volume == 0));
+
+	// The next block descends from the new tip without reading the stored chain
+	assert!(ingestion.chain.is_extended_by(
+		&serde_json::from_value(block("hash104b", 103, "hash103b", false)).unwrap()
+	));
+
+	drop(conn);
+	std::fs::remove_file(&path).unwrap();
 }
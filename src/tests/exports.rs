@@ -0,0 +1,325 @@
+use super::*;
+use crate::{
+	db::exports::{get_export_runs, ExportRunStatus},
+	exports::{cron::Schedule, ScheduledExport},
+	types::{ExportConfig, ExportDestination, ExportFilter},
+};
+use chrono::{DateTime, TimeZone, Utc};
+
+fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+	Utc.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+}
+
+#[test]
+fn test_schedule_next_after() {
+	let daily = Schedule::parse("0 2 * * *").unwrap();
+	assert_eq!(daily.next_after(at(2024, 7, 8, 1, 59)), Some(at(2024, 7, 8, 2, 0)));
+	assert_eq!(daily.next_after(at(2024, 7, 8, 2, 0)), Some(at(2024, 7, 9, 2, 0)));
+	assert_eq!(
+		Schedule::parse("@daily").unwrap().next_after(at(2024, 12, 31, 12, 0)),
+		Some(at(2025, 1, 1, 0, 0))
+	);
+
+	let quarter = Schedule::parse("*/15 9-17 * * 1-5").unwrap();
+	assert_eq!(quarter.next_after(at(2024, 7, 8, 10, 7)), Some(at(2024, 7, 8, 10, 15)));
+	// From Friday evening to Monday morning
+	assert_eq!(quarter.next_after(at(2024, 7, 12, 17, 45)), Some(at(2024, 7, 15, 9, 0)));
+
+	// With both day fields restricted, either matches; Sunday is both 0 and 7
+	let days = Schedule::parse("30 6 1,15 * 7").unwrap();
+	assert_eq!(days.next_after(at(2024, 7, 2, 0, 0)), Some(at(2024, 7, 7, 6, 30)));
+	assert_eq!(days.next_after(at(2024, 7, 8, 0, 0)), Some(at(2024, 7, 14, 6, 30)));
+	assert_eq!(days.next_after(at(2024, 7, 14, 7, 0)), Some(at(2024, 7, 15, 6, 30)));
+
+	assert_eq!(Schedule::parse("0 0 30 2 *").unwrap().next_after(at(2024, 1, 1, 0, 0)), None);
+	for invalid in ["0 2 * *", "60 * * * *", "0 0 0 * *", "5-1 * * * *", "*/0 * * * *", "a * * * *"]
+	{
+		assert!(Schedule::parse(invalid).is_err(), "{}", invalid);
+	}
+}
+
+#[tokio::test]
+async fn test_scheduled_export_writes_csv_and_records_runs() {
+	let pool = bb8::Pool::builder()
+		.max_size(1)
+		.idle_timeout(None)
+		.max_lifetime(None)
+		.build(SqliteConnectionManager::new(":memory:", |_| Ok(())))
+		.await
+		.unwrap();
+	{
+		let conn = pool.get().await.unwrap();
+		initialize_db(&conn).unwrap();
+		let group = GroupRecord {
+			name: "treasury".to_string(),
+			members: vec!["vault1".to_string(), "vault2".to_string()],
+		};
+		db::groups::put_group(&conn, &group).unwrap();
+		for (index, (sender, receiver, timestamp)) in [
+			("vault1", "acc1", 1720000000),
+			("acc2", "vault2", 1720400000),
+			("acc1", "acc2", 1720400000),
+			("vault1", "vault2", 1720500000),
+		]
+		.into_iter()
+		.enumerate()
+		{
+			let transfer = TransferRecord {
+				transaction_id: format!("tx{}", index),
+				transfer_index: 0,
+				sender: sender.to_string(),
+				receiver: receiver.to_string(),
+				amount: 1000,
+				timestamp,
+				block_height: 100 + index as u64,
+				kind: TransferKind::Transfer,
+				sender_owner: None,
+				receiver_owner: None,
+				invoked_by: None,
+				tags: vec!["deposit".to_string(), "payroll".to_string()],
+			};
+			db::insert_or_update_transfer(&conn, &transfer).unwrap();
+		}
+	}
+
+	let directory = std::env::temp_dir().join(format!("aggregator-exports-{}", std::process::id()));
+	let _ = std::fs::remove_dir_all(&directory);
+	let export = ScheduledExport::new(ExportConfig {
+		name: "treasury-daily".to_string(),
+		schedule: "0 2 * * *".to_string(),
+		filter: ExportFilter::Group("treasury".to_string()),
+		window: Some("1d".to_string()),
+		destination: ExportDestination::Directory(directory.display().to_string()),
+	})
+	.unwrap();
+
+	// Only the transfers of the members within the window before the run are exported
+	let scheduled_at = Utc.timestamp_opt(1720450000, 0).unwrap();
+	export.run_once(&pool, scheduled_at).await.unwrap();
+	let path = directory.join("treasury-daily-20240708T1446Z.csv");
+	let csv = std::fs::read_to_string(&path).unwrap();
+	let mut lines = csv.lines();
+	assert_eq!(
+		lines.next(),
+		Some("transaction_id,transfer_index,sender,receiver,amount,timestamp,block_height,kind,sender_owner,receiver_owner,invoked_by,tags")
+	);
+	assert_eq!(
+		lines.next(),
+		Some("tx1,0,acc2,vault2,1000,1720400000,101,transfer,,,,deposit;payroll")
+	);
+	assert_eq!(lines.next(), None);
+
+	// A run that cannot write its file is recorded as failed
+	std::fs::remove_file(&path).unwrap();
+	std::fs::create_dir(&path).unwrap();
+	export.run_once(&pool, scheduled_at).await.unwrap();
+
+	let runs = with_connection(&pool, |conn| get_export_runs(conn, Some("treasury-daily"), 10))
+		.await
+		.unwrap()
+		.unwrap();
+	assert_eq!(runs.len(), 2);
+	assert_eq!(runs[0].status, ExportRunStatus::Failed);
+	assert!(runs[0].error.is_some());
+	assert_eq!(
+		(runs[1].status, runs[1].scheduled_at, runs[1].row_count),
+		(ExportRunStatus::Succeeded, 1720450000, Some(1))
+	);
+	assert_eq!(runs[1].location, Some(path.display().to_string()));
+
+	assert!(ScheduledExport::new(ExportConfig {
+		name: "../treasury".to_string(),
+		schedule: "@daily".to_string(),
+		filter: ExportFilter::Account("vault1".to_string()),
+		window: None,
+		destination: ExportDestination::Directory(directory.display().to_string()),
+	})
+	.is_err());
+	std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_table_export_writes_csv_and_parquet() {
+	use crate::{cli, exports::tables::ExportTable};
+	use parquet::{
+		file::reader::{FileReader, SerializedFileReader},
+		record::RowAccessor,
+	};
+
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	for (index, (slot, timestamp, account_id)) in
+		[(100, 1720000000, "acc1"), (101, 1720400000, "acc2")].into_iter().enumerate()
+	{
+		let transaction_id = format!("tx{}", index);
+		let transaction = TransactionRecord {
+			transaction_id: transaction_id.clone(),
+			timestamp,
+			block_height: slot,
+			raw_transaction: "raw_data".to_string(),
+			fee: 5000,
+			fee_payer: account_id.to_string(),
+			compute_unit_limit: None,
+			compute_unit_price: Some(10),
+		};
+		db::insert_or_update_transaction(&conn, &transaction).unwrap();
+		let transfer = TransferRecord {
+			transaction_id: transaction_id.clone(),
+			transfer_index: 0,
+			sender: account_id.to_string(),
+			receiver: "vault".to_string(),
+			amount: 1000,
+			timestamp,
+			block_height: slot,
+			kind: TransferKind::Transfer,
+			sender_owner: None,
+			receiver_owner: None,
+			invoked_by: None,
+			tags: Vec::new(),
+		};
+		db::insert_or_update_transfer(&conn, &transfer).unwrap();
+		let account = AccountRecord {
+			account_id: account_id.to_string(),
+			estimated_balance: 2000,
+			related_transactions: vec![transaction_id.clone()],
+			total_received: 0,
+			total_sent: 1000,
+			first_seen_slot: Some(slot),
+			last_active_slot: Some(slot),
+			balance_slot: Some(slot),
+			balance_transaction_index: Some(0),
+		};
+		db::insert_or_update_account(&conn, &account).unwrap();
+		let link = AccountTransactionRecord {
+			account_id: account_id.to_string(),
+			transaction_id,
+			block_height: slot,
+			transaction_index: 0,
+		};
+		db::insert_or_update_account_transaction(&conn, &link).unwrap();
+	}
+
+	let directory =
+		std::env::temp_dir().join(format!("aggregator-table-exports-{}", std::process::id()));
+	let _ = std::fs::remove_dir_all(&directory);
+	let export = |args: &[&str]| {
+		let output = directory.display().to_string();
+		let base = ["aggregator", "export", "--output", output.as_str()];
+		let matches = cli::command()
+			.try_get_matches_from(base.into_iter().chain(args.iter().copied()))
+			.unwrap();
+		cli::table_export(&matches).unwrap().run(&conn).unwrap()
+	};
+
+	// Only the tables given are exported, with all their rows without a range
+	let exported = export(&["--tables", "transfers"]);
+	assert_eq!(exported.len(), 1);
+	assert_eq!((exported[0].table, exported[0].rows), (ExportTable::Transfers, 2));
+	let csv = std::fs::read_to_string(directory.join("transfers.csv")).unwrap();
+	let mut lines = csv.lines();
+	assert_eq!(
+		lines.next(),
+		Some("transaction_id,transfer_index,slot,block_time,sender,receiver,amount,kind,sender_owner,receiver_owner,invoked_by,tags")
+	);
+	assert_eq!(lines.next(), Some("tx0,0,100,1720000000,acc1,vault,1000,transfer,,,,"));
+	assert_eq!(lines.next(), Some("tx1,0,101,1720400000,acc2,vault,1000,transfer,,,,"));
+	assert_eq!(lines.next(), None);
+
+	// A time range limits the transactions, and the accounts to those linked to them
+	let exported = export(&["--format", "parquet", "--start-time", "1720400000"]);
+	let exported: Vec<(ExportTable, u64)> =
+		exported.iter().map(|exported| (exported.table, exported.rows)).collect();
+	assert_eq!(exported, ExportTable::ALL.map(|table| (table, 1)));
+	let read = |name: &str| {
+		let file = std::fs::File::open(directory.join(name)).unwrap();
+		let reader = SerializedFileReader::new(file).unwrap();
+		reader.get_row_iter(None).unwrap().map(|row| row.unwrap()).collect::<Vec<_>>()
+	};
+	let transactions = read("transactions.parquet");
+	assert_eq!(transactions.len(), 1);
+	assert_eq!(transactions[0].get_string(0).unwrap(), "tx1");
+	assert_eq!(transactions[0].get_long(1).unwrap(), 101);
+	assert_eq!(transactions[0].get_long(6).unwrap(), 10);
+	assert!(transactions[0].get_long(5).is_err());
+	let accounts = read("accounts.parquet");
+	assert_eq!(accounts[0].get_string(0).unwrap(), "acc2");
+	assert_eq!(accounts[0].get_long(1).unwrap(), 2000);
+
+	// A slot range past the stored transactions leaves empty files
+	let exported = export(&["--from-slot", "102", "--tables", "transactions,accounts"]);
+	assert!(exported.iter().all(|exported| exported.rows == 0));
+	assert_eq!(std::fs::read_to_string(directory.join("accounts.csv")).unwrap().lines().count(), 1);
+	std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn test_snapshot_seeds_another_database() {
+	use crate::{cli, db::sync_state, exports::snapshot::SnapshotTable};
+
+	let source = Connection::open_in_memory().unwrap();
+	initialize_db(&source).unwrap();
+	db::compat::check_genesis_hash(&source, "testnet").unwrap();
+	for (index, slot) in [100, 200].into_iter().enumerate() {
+		let transaction = TransactionRecord {
+			transaction_id: format!("tx{}", index),
+			timestamp: 1720000000,
+			block_height: slot,
+			raw_transaction: "raw_data".to_string(),
+			fee: 5000,
+			fee_payer: "acc1".to_string(),
+			compute_unit_limit: None,
+			compute_unit_price: Some(10),
+		};
+		db::insert_or_update_transaction(&source, &transaction).unwrap();
+	}
+	let label = LabelRecord {
+		pubkey: "acc1".to_string(),
+		label: "Treasury".to_string(),
+		tags: vec!["dao".to_string()],
+	};
+	db::labels::import_labels(&source, vec![label.clone()]).unwrap();
+	sync_state::set_last_processed_slot(&source, 200, 1720000000).unwrap();
+
+	let path = std::env::temp_dir().join(format!("aggregator-{}.snapshot", std::process::id()));
+	let parse = |args: &[&str]| cli::command().try_get_matches_from(args).unwrap();
+	let output = path.display().to_string();
+	let matches =
+		parse(&["aggregator", "export-snapshot", "--output", &output, "--to-slot", "150"]);
+	let (header, tables) = cli::snapshot_export(&matches).unwrap().run(&source).unwrap();
+	assert_eq!((header.from_slot, header.to_slot), (0, 150));
+	assert_eq!(header.genesis_hash.as_deref(), Some("testnet"));
+	let rows = |tables: &[SnapshotTable], name: &str| {
+		tables.iter().find(|table| table.name == name).map(|table| table.rows)
+	};
+	assert_eq!(rows(&tables, "transactions"), Some(1));
+	assert_eq!(rows(&tables, "labels"), Some(1));
+
+	// The rows of the range and the whole tables are imported, and the ingestion resumes after it
+	let mut target = Connection::open_in_memory().unwrap();
+	initialize_db(&target).unwrap();
+	let matches = parse(&["aggregator", "import-snapshot", "--input", &output]);
+	let import = cli::snapshot_import(&matches).unwrap();
+	let (_, imported) = import.run(&mut target).unwrap();
+	assert_eq!(imported, tables);
+	assert!(db::get_transaction(&target, "tx0").unwrap().is_some());
+	assert!(db::get_transaction(&target, "tx1").unwrap().is_none());
+	assert_eq!(db::labels::get_label(&target, "acc1").unwrap(), Some(label));
+	assert_eq!(sync_state::get_last_processed_slot(&target).unwrap(), Some(150));
+	assert_eq!(db::compat::recorded_genesis_hash(&target).unwrap().as_deref(), Some("testnet"));
+
+	// A database of another network refuses the snapshot, and is left untouched
+	let mut other = Connection::open_in_memory().unwrap();
+	initialize_db(&other).unwrap();
+	db::compat::check_genesis_hash(&other, "mainnet").unwrap();
+	assert!(import.run(&mut other).is_err());
+	assert_eq!(sync_state::get_last_processed_slot(&other).unwrap(), None);
+
+	// So does a truncated file
+	let bytes = std::fs::read(&path).unwrap();
+	std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+	let mut empty = Connection::open_in_memory().unwrap();
+	initialize_db(&empty).unwrap();
+	assert!(import.run(&mut empty).is_err());
+	assert!(db::get_transaction(&empty, "tx0").unwrap().is_none());
+	std::fs::remove_file(&path).unwrap();
+}
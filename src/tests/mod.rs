@@ -1,23 +1,105 @@
 #![cfg(test)]
 #![allow(unused_imports)]
 use crate::{
-	db, db::AccountRecord, get_account_handler, get_transaction_handler, initialize_db,
-	TransactionRecord,
+	anomaly::{AnomalyDetector, AnomalyMetric},
+	db,
+	db::{
+		account_info::{AccountInfoRecord, AccountKind},
+		account_snapshots::{self, AccountSnapshotRecord},
+		blocks::{self, BlockRecord},
+		filters::{self, FilterRule, ProgramFilterRecord, ProgramFilters},
+		groups::{GroupRecord, GroupStats},
+		labels::{LabelImportSummary, LabelRecord},
+		pool::{with_connection, DbPool, SqliteConnectionManager},
+		rollups::DailyStats,
+		stats::{ActivityHeatmap, ErrorFrequency, FeePayerStats, PriorityFeeStats},
+		storage::{self, StorageMonitor, StorageUsage},
+		submissions::SubmissionRecord,
+		webhooks::{DeliveryRecord, DeliveryStatus, NewDelivery, WebhookRecord},
+		writer::DbWriter,
+		AccountRecord, AccountTransactionRecord, BalanceChangeRecord, BlockSummary, SandwichRecord,
+		SignatureInfo, SwapRecord, TransactionRecord, TransferRecord,
+	},
+	events::{Event, EventBus},
+	get_account_handler, get_account_transactions_handler, get_accounts_batch_handler,
+	get_balance_handler, get_daily_stats_handler, get_errors_handler, get_fee_payers_handler,
+	get_label_handler, get_priority_fees_handler, get_rpc_stats_handler, get_sandwiches_handler,
+	get_transaction_handler, get_transaction_instructions_handler, get_transactions_handler,
+	get_transfers_handler, health_handler, import_labels_handler, initialize_db,
+	server::{
+		admin::{
+			backfill_account_handler, delete_program_filter_handler, get_export_runs_handler,
+			get_failed_slots_handler, get_program_filter_handler, list_program_filters_handler,
+			put_program_filter_handler, refetch_transaction_handler, requeue_failed_slot_handler,
+		},
+		consistency::{ReadConsistency, AS_OF_SLOT_HEADER, WAIT_FOR_SLOT_HEADER},
+		degraded::{get_degraded_status_handler, DegradedMode, DegradedStatus, DEGRADED_HEADER},
+		dto::{AccountResponse, BlockResponse, TransactionResponse, TransferResponse},
+		explorer, get_account_snapshots_handler, get_block_handler, get_recent_blocks_handler,
+		groups::{
+			delete_group_handler, get_group_balance_handler, get_group_handler,
+			get_group_stats_handler, get_group_transactions_handler, list_groups_handler,
+			put_group_handler,
+		},
+		idempotency::idempotency,
+		listener::Listener,
+		metrics::{get_http_stats_handler, HttpMetrics, IngestionMetrics},
+		proto,
+		runtime::{get_runtime_status_handler, sanitize_url, RuntimeInfo},
+		signing::{canonical_payload, ResponseSigner, PUBLIC_KEY_HEADER, SIGNATURE_HEADER},
+		slots::{
+			get_epoch_handler, get_epochs_handler, get_slot_at_handler, get_slot_time_handler,
+			get_unavailable_slots_handler, SlotTime,
+		},
+		state::AppState,
+		stats::{get_activity_heatmap_handler, get_storage_stats_handler},
+		submissions::{get_submission_handler, send_transaction_handler},
+		timeout::RequestTimeout,
+		webhooks::{
+			create_webhook_handler, delete_webhook_handler, get_webhook_deliveries_handler,
+			get_webhook_handler, list_webhooks_handler, redeliver_handler,
+		},
+		ws::TransactionAssembler,
+		AccountBalance, AccountLookup, AccountTransfer, DbConn, TransactionPage, TransferDirection,
+	},
+	shutdown::Shutdown,
+	types::{
+		AccountDiscovery, AnomalyConfig, DegradedModeConfig, ListenerConfig, ProgramInstruction,
+		QuotaAction, RawTransactionPolicy, StoragePolicy, StorageQuotaConfig, TransactionFailure,
+		TransferKind,
+	},
 };
 use axum::{
 	body::Body,
 	http::{Request, StatusCode},
+	middleware,
 	response::Response,
-	routing::get,
+	routing::{get, post},
 	Extension, Router,
 };
 use hyper::body::to_bytes;
+use prost::Message;
 use rusqlite::Connection;
 use serde_json::json;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use tokio::sync::Mutex;
 use tower::ServiceExt;
 
 mod aggregator;
 
+mod archive;
+
 mod api;
+
+mod exports;
+
+mod pubsub;
+
+mod rpc;
+
+mod sinks;
+
+mod webhooks;
+
+#[cfg(feature = "test-validator")]
+mod validator;
@@ -0,0 +1,47 @@
+use crate::{
+	aggregator::{subscription::ingest_subscribed_blocks, Ingestion},
+	db::writer::DbWriter,
+	events::EventBus,
+	initialize_db,
+	pubsub::pubsub_url,
+	rpc::RpcGateway,
+	types::StoragePolicy,
+};
+use rusqlite::Connection;
+
+#[test]
+fn test_pubsub_url_from_rpc_url() {
+	assert_eq!(pubsub_url("https://api.testnet.solana.com"), "wss://api.testnet.solana.com");
+	assert_eq!(pubsub_url("http://127.0.0.1:8899"), "ws://127.0.0.1:8899");
+	assert_eq!(pubsub_url("ws://127.0.0.1:8900"), "ws://127.0.0.1:8900");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_block_subscription_reports_unreachable_endpoint() {
+	let config = toml::from_str(
+		"rpc_url = \"http://127.0.0.1:1\"\nretry_attempts = 0\nserver_address = \"127.0.0.1:0\"",
+	)
+	.unwrap();
+	let gateway = RpcGateway::new(&config);
+	let conn = Connection::open_in_memory().unwrap();
+	initialize_db(&conn).unwrap();
+	let (writer, _) = DbWriter::spawn(conn, StoragePolicy::default(), EventBus::default());
+
+	let ingestion = Ingestion {
+		gateway: &gateway,
+		writer: &writer,
+		archiver: None,
+		cache: None,
+		shutdown: None,
+		chain: Default::default(),
+	};
+
+	let mut next_slot = 100;
+	let connected = ingest_subscribed_blocks("ws://127.0.0.1:1", &ingestion, &mut next_slot)
+		.await
+		.unwrap();
+
+	// The caller falls back to polling, from the same slot
+	assert!(!connected);
+	assert_eq!(next_slot, 100);
+}
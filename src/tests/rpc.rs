@@ -0,0 +1,281 @@
+// The solana client error is large, but boxing it in the test closures would only add noise.
+#![allow(clippy::result_large_err)]
+use crate::{rpc::RpcGateway, types::Config};
+use std::time::Instant;
+
+fn gateway_config(retry_attempts: u8, rpc: &str) -> Config {
+	toml::from_str(&format!(
+		"rpc_url = \"http://127.0.0.1:1\"\nretry_attempts = {}\nserver_address = \"127.0.0.1:0\"\n[rpc]\n{}",
+		retry_attempts, rpc
+	))
+	.unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_retries_and_fails_over() {
+	let config = gateway_config(1, "fallback_urls = [\"http://127.0.0.1:2\"]\ntimeout_secs = 1");
+	let gateway = RpcGateway::new(&config);
+
+	assert!(gateway.call("getSlot", |client| client.get_slot()).await.is_err());
+
+	let metrics = gateway.metrics();
+	assert_eq!(metrics.failovers, 1);
+	assert_eq!(metrics.active_endpoint, "http://127.0.0.1:2");
+	assert_eq!(metrics.methods["getSlot"].requests, 2);
+	assert_eq!(metrics.methods["getSlot"].failures, 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_rate_limits_requests() {
+	let config = gateway_config(0, "requests_per_second = 10.0\nburst = 1.0");
+	let gateway = RpcGateway::new(&config);
+
+	let started = Instant::now();
+	for _ in 0..3 {
+		let _ = gateway.call("getSlot", |client| client.get_slot()).await;
+	}
+	// The first request uses the burst, the next two wait for a token each
+	assert!(started.elapsed().as_millis() >= 180);
+
+	let config = gateway_config(0, "max_rps = 5.0");
+	assert_eq!(config.rpc.requests_per_second, Some(5.0));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_batch_call_matches_responses_by_id() {
+	use axum::{routing::post, Json, Router};
+	use serde_json::{json, Value};
+
+	// Answers in reverse order, failing the calls with an odd parameter
+	let app = Router::new().route(
+		"/",
+		post(|Json(requests): Json<Vec<Value>>| async move {
+			let responses: Vec<Value> = requests
+				.iter()
+				.rev()
+				.map(|request| match request["params"][0].as_u64().unwrap() {
+					slot if slot % 2 == 1 => json!({
+						"jsonrpc": "2.0",
+						"id": request["id"],
+						"error": { "code": -32007, "message": format!("Slot {} was skipped", slot) },
+					}),
+					slot => json!({ "jsonrpc": "2.0", "id": request["id"], "result": slot * 10 }),
+				})
+				.collect();
+			Json(responses)
+		}),
+	);
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = listener.local_addr().unwrap();
+	tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+	let config: Config = toml::from_str(&format!(
+		"rpc_url = \"http://{}\"\nretry_attempts = 0\nserver_address = \"127.0.0.1:0\"\n[rpc]\nbatch_size = 3",
+		address
+	))
+	.unwrap();
+	let gateway = RpcGateway::new(&config);
+	assert_eq!(gateway.batch_size(), 3);
+
+	let results = gateway
+		.batch_call("getBlock", &[json!([10]), json!([11]), json!([12])])
+		.await
+		.unwrap();
+	assert_eq!(results[0], Ok(json!(100)));
+	assert_eq!(results[1].as_ref().unwrap_err().code, -32007);
+	assert!(!results[1].as_ref().unwrap_err().is_transient());
+	assert_eq!(results[2], Ok(json!(120)));
+
+	let metrics = gateway.metrics();
+	assert_eq!(metrics.methods["getBlock[batch]"].requests, 1);
+	assert_eq!(metrics.methods["getBlock[batch]"].failures, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_account_enrichment_classifies_discovered_accounts() {
+	use crate::{
+		aggregator::enrichment::enrich_accounts,
+		db::{
+			self,
+			account_info::{get_account_info, AccountKind},
+			initialize_db,
+			pool::SqliteConnectionManager,
+			AccountRecord,
+		},
+	};
+	use axum::{routing::post, Json, Router};
+	use serde_json::{json, Value};
+
+	let wallet = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+	let token_account = "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g";
+	let program = "ComputeBudget111111111111111111111111111111";
+	let closed = "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d";
+	let app = Router::new().route(
+		"/",
+		post(move |Json(request): Json<Value>| async move {
+			assert_eq!(request["method"], "getMultipleAccounts");
+			assert_eq!(request["params"][1]["dataSlice"]["length"], 0);
+			let value: Vec<Value> = request["params"][0]
+				.as_array()
+				.unwrap()
+				.iter()
+				.map(|pubkey| match pubkey.as_str().unwrap() {
+					key if key == wallet => json!({
+						"owner": "11111111111111111111111111111111", "executable": false,
+						"lamports": 1, "data": ["", "base64"], "rentEpoch": 0, "space": 0,
+					}),
+					key if key == token_account => json!({
+						"owner": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA", "executable": false,
+						"lamports": 1, "data": ["", "base64"], "rentEpoch": 0, "space": 165,
+					}),
+					key if key == program => json!({
+						"owner": "NativeLoader1111111111111111111111111111111", "executable": true,
+						"lamports": 1, "data": ["", "base64"], "rentEpoch": 0, "space": 14,
+					}),
+					_ => Value::Null,
+				})
+				.collect();
+			Json(json!({
+				"jsonrpc": "2.0",
+				"id": request["id"],
+				"result": { "context": { "slot": 42 }, "value": value },
+			}))
+		}),
+	);
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = listener.local_addr().unwrap();
+	tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+	let config: Config = toml::from_str(&format!(
+		"rpc_url = \"http://{}\"\nretry_attempts = 0\nserver_address = \"127.0.0.1:0\"",
+		address
+	))
+	.unwrap();
+	let gateway = RpcGateway::new(&config);
+
+	let pool = bb8::Pool::builder()
+		.max_size(1)
+		.idle_timeout(None)
+		.max_lifetime(None)
+		.build(SqliteConnectionManager::new(":memory:", |_| Ok(())))
+		.await
+		.unwrap();
+	{
+		let conn = pool.get().await.unwrap();
+		initialize_db(&conn).unwrap();
+		for account_id in [wallet, token_account, program, closed, "not-a-pubkey"] {
+			let record = AccountRecord {
+				account_id: account_id.to_string(),
+				estimated_balance: 0,
+				related_transactions: Vec::new(),
+				total_received: 0,
+				total_sent: 0,
+				first_seen_slot: Some(1),
+				last_active_slot: Some(1),
+				balance_slot: None,
+				balance_transaction_index: None,
+			};
+			db::insert_or_update_account(&conn, &record).unwrap();
+		}
+	}
+
+	assert_eq!(enrich_accounts(&gateway, &pool, 100).await.unwrap(), 5);
+	// Every account is looked up once
+	assert_eq!(enrich_accounts(&gateway, &pool, 100).await.unwrap(), 0);
+
+	let conn = pool.get().await.unwrap();
+	let kinds = [wallet, token_account, program, closed, "not-a-pubkey"]
+		.map(|account_id| get_account_info(&conn, account_id).unwrap().unwrap().kind);
+	assert_eq!(
+		kinds,
+		[
+			AccountKind::Wallet,
+			AccountKind::TokenAccount,
+			AccountKind::Program,
+			AccountKind::Closed,
+			AccountKind::Closed,
+		]
+	);
+	let info = get_account_info(&conn, token_account).unwrap().unwrap();
+	assert_eq!(
+		(info.owner.as_deref(), info.data_length, info.fetched_slot),
+		(Some("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"), Some(165), 42)
+	);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_backfill_range_leaves_the_checkpoint_alone() {
+	use crate::{
+		aggregator::backfill_range,
+		db::{
+			blocks, initialize_db,
+			sync_state::{get_last_processed_slot, set_last_processed_slot},
+			writer::DbWriter,
+		},
+		events::EventBus,
+		types::StoragePolicy,
+	};
+	use axum::{routing::post, Json, Router};
+	use rusqlite::Connection;
+	use serde_json::{json, Value};
+	use std::sync::Arc;
+
+	// Serves an empty block at every even slot, the odd ones were skipped
+	let app = Router::new().route(
+		"/",
+		post(|Json(request): Json<Value>| async move {
+			// The client checks the version of the node before its first block request
+			if request["method"] == "getVersion" {
+				let version = json!({ "solana-core": "1.18.26", "feature-set": 0 });
+				return Json(json!({ "jsonrpc": "2.0", "id": request["id"], "result": version }))
+			}
+			assert_eq!(request["method"], "getBlock");
+			assert_eq!(request["params"][1]["commitment"], "finalized");
+			let slot = request["params"][0].as_u64().unwrap();
+			let response = if slot % 2 == 1 {
+				json!({ "code": -32007, "message": format!("Slot {} was skipped", slot) })
+			} else {
+				json!({
+					"blockHeight": slot,
+					"blockTime": 1720421680,
+					"blockhash": "AZ8jzQjcgFSKYZ47sUVGTn7nR3FowHoyszEo2Nwq8vae",
+					"parentSlot": slot - 1,
+					"previousBlockhash": "6RbXYJiJa8V7K5YJyS8YjkWsWf6Vuh5vGEzww7xSWigf",
+					"transactions": [],
+				})
+			};
+			let key = if slot % 2 == 1 { "error" } else { "result" };
+			Json(json!({ "jsonrpc": "2.0", "id": request["id"], key: response }))
+		}),
+	);
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = listener.local_addr().unwrap();
+	tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+	let config: Config = toml::from_str(&format!(
+		"rpc_url = \"http://{}\"\nretry_attempts = 0\nserver_address = \"127.0.0.1:0\"",
+		address
+	))
+	.unwrap();
+
+	let path = std::env::temp_dir().join(format!("aggregator-backfill-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let conn = Connection::open(&path).unwrap();
+	initialize_db(&conn).unwrap();
+	set_last_processed_slot(&conn, 500, 0).unwrap();
+	let (writer, handle) = DbWriter::spawn(
+		Connection::open(&path).unwrap(),
+		StoragePolicy::default(),
+		EventBus::default(),
+	);
+
+	backfill_range(Arc::new(RpcGateway::new(&config)), writer, &config, 10..=13)
+		.await
+		.unwrap();
+	handle.join().unwrap();
+
+	let stored: Vec<u64> = (10..=13)
+		.filter(|slot| blocks::get_block(&conn, *slot).unwrap().is_some())
+		.collect();
+	assert_eq!(stored, vec![10, 12]);
+	assert_eq!(get_last_processed_slot(&conn).unwrap(), Some(500));
+	std::fs::remove_file(&path).unwrap();
+}
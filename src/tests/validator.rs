@@ -0,0 +1,180 @@
+//! End-to-end tests of the whole pipeline against a local `solana-test-validator`: transfers are
+//! submitted with a local keypair, ingested by the aggregator and read back through the API.
+//!
+//! They only build with the `test-validator` feature, and need the validator on the `PATH`:
+//!
+//! ```sh
+//! cargo test --features test-validator validator
+//! ```
+use super::*;
+use crate::{aggregator::aggregate_blocks, rpc::RpcGateway, shutdown::Shutdown, types::Config};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::{
+	commitment_config::CommitmentConfig,
+	native_token::LAMPORTS_PER_SOL,
+	pubkey::Pubkey,
+	signature::{Keypair, Signature, Signer},
+	system_transaction,
+};
+use std::{
+	net::TcpListener,
+	path::PathBuf,
+	process::{Child, Command, Stdio},
+};
+
+/// How long the validator has to start and the aggregator to ingest the transfers.
+const TIMEOUT: Duration = Duration::from_secs(90);
+
+/// A `solana-test-validator` process over a fresh ledger, stopped when dropped.
+struct TestValidator {
+	process: Child,
+	ledger: PathBuf,
+	rpc_url: String,
+}
+
+impl TestValidator {
+	/// Starts a validator on free ports and waits until its RPC endpoint is healthy.
+	async fn start() -> Self {
+		let ledger = std::env::temp_dir().join(format!("aggregator-ledger-{}", std::process::id()));
+		let (rpc_port, faucet_port) = (free_port(), free_port());
+		let process = Command::new("solana-test-validator")
+			.args(["--reset", "--quiet", "--ledger"])
+			.arg(&ledger)
+			.args(["--rpc-port", &rpc_port.to_string(), "--faucet-port", &faucet_port.to_string()])
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.spawn()
+			.expect("solana-test-validator must be on the PATH");
+		let validator = Self { process, ledger, rpc_url: format!("http://127.0.0.1:{}", rpc_port) };
+
+		let client = validator.client();
+		let deadline = tokio::time::Instant::now() + TIMEOUT;
+		while client.get_health().await.is_err() {
+			assert!(tokio::time::Instant::now() < deadline, "the validator did not start");
+			tokio::time::sleep(Duration::from_millis(500)).await;
+		}
+		validator
+	}
+
+	fn client(&self) -> RpcClient {
+		RpcClient::new_with_commitment(self.rpc_url.clone(), CommitmentConfig::finalized())
+	}
+}
+
+impl Drop for TestValidator {
+	fn drop(&mut self) {
+		let _ = self.process.kill();
+		let _ = self.process.wait();
+		let _ = std::fs::remove_dir_all(&self.ledger);
+	}
+}
+
+fn free_port() -> u16 {
+	TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Sends lamports from the payer and waits for the transfer to be finalized.
+async fn transfer(client: &RpcClient, payer: &Keypair, to: &Pubkey, lamports: u64) -> Signature {
+	let blockhash = client.get_latest_blockhash().await.unwrap();
+	let transaction = system_transaction::transfer(payer, to, lamports, blockhash);
+	client.send_and_confirm_transaction(&transaction).await.unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_ingested_transfers_are_served_by_the_api() {
+	let validator = TestValidator::start().await;
+	let client = validator.client();
+
+	let payer = Keypair::new();
+	let receiver = Keypair::new().pubkey();
+	let airdrop = client.request_airdrop(&payer.pubkey(), 10 * LAMPORTS_PER_SOL).await.unwrap();
+	client
+		.poll_for_signature_with_commitment(&airdrop, CommitmentConfig::finalized())
+		.await
+		.unwrap();
+	let first = transfer(&client, &payer, &receiver, 1_000_000).await;
+	let second = transfer(&client, &payer, &receiver, 2_500_000).await;
+	let statuses = client.get_signature_statuses(&[first, second]).await.unwrap().value;
+	let slots: Vec<u64> = statuses.into_iter().map(|status| status.unwrap().slot).collect();
+
+	// The aggregator follows the validator from the slot of the first transfer
+	let path = std::env::temp_dir().join(format!("aggregator-e2e-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let configure = |conn: &mut Connection| {
+		conn.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(()))?;
+		conn.busy_timeout(Duration::from_secs(5))
+	};
+	let mut conn = Connection::open(&path).unwrap();
+	configure(&mut conn).unwrap();
+	initialize_db(&conn).unwrap();
+	let pool = bb8::Pool::builder()
+		.build(SqliteConnectionManager::new(&path, configure))
+		.await
+		.unwrap();
+	let (writer, _) = DbWriter::spawn(conn, StoragePolicy::default(), EventBus::default());
+	let config: Config = toml::from_str(&format!(
+		"rpc_url = \"{}\"\nserver_address = \"127.0.0.1:0\"\ncontinuous = true\npoll_interval_ms = 200\nstart_from = {{ slot = {} }}",
+		validator.rpc_url, slots[0]
+	))
+	.unwrap();
+	let gateway = Arc::new(RpcGateway::new(&config));
+	let aggregation =
+		tokio::spawn(aggregate_blocks(gateway, pool.clone(), writer, config, Shutdown::new()));
+
+	let router = Router::new()
+		.route("/transaction", get(get_transaction_handler))
+		.route("/accountid", get(get_account_handler))
+		.route("/transfers", get(get_transfers_handler))
+		.layer(Extension(AppState::new(pool)));
+	let get = |uri: String| {
+		let router = router.clone();
+		async move {
+			let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+			let response = router.oneshot(request).await.unwrap();
+			let status = response.status();
+			(status, to_bytes(response.into_body()).await.unwrap())
+		}
+	};
+
+	let deadline = tokio::time::Instant::now() + TIMEOUT;
+	let body = loop {
+		match get(format!("/transaction?tx-id={}", second)).await {
+			(StatusCode::OK, body) => break body,
+			_ => {
+				assert!(tokio::time::Instant::now() < deadline, "the transfers were not ingested");
+				tokio::time::sleep(Duration::from_millis(500)).await;
+			},
+		}
+	};
+	let transaction: TransactionResponse = serde_json::from_slice(&body).unwrap();
+	assert_eq!(transaction.fee_payer, payer.pubkey().to_string());
+	assert_eq!(transaction.block_height, slots[1]);
+	assert_eq!(transaction.fee, 5000);
+
+	let (status, body) = get(format!("/transfers?account-id={}", receiver)).await;
+	assert_eq!(status, StatusCode::OK);
+	let transfers: Vec<AccountTransfer> = serde_json::from_slice(&body).unwrap();
+	let received: Vec<_> = transfers
+		.iter()
+		.map(|transfer| {
+			(transfer.transfer.transaction_id.clone(), transfer.transfer.amount, transfer.net_flow)
+		})
+		.collect();
+	assert_eq!(
+		received,
+		[(first.to_string(), 1_000_000, 1_000_000), (second.to_string(), 2_500_000, 3_500_000)]
+	);
+	assert!(transfers.iter().all(|transfer| transfer.direction == TransferDirection::In));
+
+	let (status, body) = get(format!("/accountid?account-id={}", receiver)).await;
+	assert_eq!(status, StatusCode::OK);
+	let account: AccountResponse = serde_json::from_slice(&body).unwrap();
+	assert_eq!((account.total_received, account.total_sent), (3_500_000, 0));
+	assert_eq!(account.estimated_balance, 3_500_000);
+
+	aggregation.abort();
+	drop(router);
+	for suffix in ["", "-wal", "-shm"] {
+		let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+	}
+}
@@ -0,0 +1,262 @@
+use crate::{
+	db::{
+		initialize_db,
+		pool::{with_connection, DbPool, SqliteConnectionManager},
+		webhooks::{self, DeliveryStatus},
+		TransferRecord,
+	},
+	events::{Event, EventBus},
+	types::TransferKind,
+	webhooks::{transfer_deliveries, TransferNotification, WebhookDispatcher},
+};
+use axum::{
+	http::{HeaderMap, StatusCode},
+	routing::post,
+	Json, Router,
+};
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+async fn memory_pool() -> DbPool {
+	// A single, never recycled connection, as every in-memory connection is a separate database
+	let pool = bb8::Pool::builder()
+		.max_size(1)
+		.idle_timeout(None)
+		.max_lifetime(None)
+		.build(SqliteConnectionManager::new(":memory:", |_| Ok(())))
+		.await
+		.unwrap();
+	initialize_db(&pool.get().await.unwrap()).unwrap();
+	pool
+}
+
+fn transfer(sender: &str, receiver: &str, receiver_owner: Option<&str>) -> TransferRecord {
+	TransferRecord {
+		transaction_id: "tx1".to_string(),
+		transfer_index: 0,
+		sender: sender.to_string(),
+		receiver: receiver.to_string(),
+		amount: 1000,
+		timestamp: 1720000000,
+		block_height: 100,
+		kind: TransferKind::Transfer,
+		sender_owner: None,
+		receiver_owner: receiver_owner.map(str::to_string),
+		invoked_by: None,
+		tags: Vec::new(),
+	}
+}
+
+#[test]
+fn test_transfer_deliveries_match_the_watched_parties() {
+	let watched = HashMap::from([
+		("payer".to_string(), vec![(1, "http://a".to_string()), (2, "http://b".to_string())]),
+		("wallet".to_string(), vec![(1, "http://a".to_string())]),
+	]);
+	let transfers =
+		[transfer("payer", "token-account", Some("wallet")), transfer("someone", "else", None)];
+
+	let deliveries = transfer_deliveries(&transfers, &watched);
+	assert_eq!(deliveries.len(), 2);
+	assert_eq!((deliveries[0].webhook_id, deliveries[0].target.as_str()), (1, "http://a"));
+	assert_eq!(deliveries[0].event_id, "tx1:0");
+	let notification: TransferNotification =
+		serde_json::from_value(deliveries[0].payload.clone()).unwrap();
+	assert_eq!(notification.addresses, ["payer", "wallet"]);
+	assert_eq!(notification.transfer.receiver, "token-account");
+	let notification: TransferNotification =
+		serde_json::from_value(deliveries[1].payload.clone()).unwrap();
+	assert_eq!((notification.webhook_id, notification.addresses), (2, vec!["payer".to_string()]));
+}
+
+#[test]
+fn test_delivery_retry_delay_backs_off_then_gives_up() {
+	assert_eq!(webhooks::retry_delay(1), Some(webhooks::MIN_RETRY_DELAY_SECS));
+	assert_eq!(webhooks::retry_delay(2), Some(2 * webhooks::MIN_RETRY_DELAY_SECS));
+	assert_eq!(webhooks::retry_delay(webhooks::MAX_ATTEMPTS - 1), Some(640));
+	assert_eq!(webhooks::retry_delay(webhooks::MAX_ATTEMPTS), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_stored_transfers_are_delivered_to_webhooks() {
+	// An endpoint failing its first request, recording the event header and body of the others
+	let received = Arc::new(Mutex::new(Vec::new()));
+	let app = Router::new().route(
+		"/hook",
+		post({
+			let received = Arc::clone(&received);
+			move |headers: HeaderMap, Json(body): Json<TransferNotification>| {
+				let received = Arc::clone(&received);
+				async move {
+					let mut received = received.lock().unwrap();
+					let event = headers["x-webhook-event"].to_str().unwrap().to_string();
+					received.push((event, body));
+					if received.len() == 1 {
+						StatusCode::INTERNAL_SERVER_ERROR
+					} else {
+						StatusCode::OK
+					}
+				}
+			}
+		}),
+	);
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/hook", listener.local_addr().unwrap());
+	tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+	let pool = memory_pool().await;
+	let watched = ["payer".to_string()];
+	let webhook = with_connection(&pool, move |conn| {
+		webhooks::create_webhook(conn, &url, &watched, 1720000000)
+	})
+	.await
+	.unwrap()
+	.unwrap();
+
+	let dispatcher = WebhookDispatcher::new(pool.clone());
+	let events = EventBus::default();
+	tokio::spawn(dispatcher.clone().record_deliveries(events.subscribe()));
+	events.publish(Event::TransferStored(transfer("payer", "acc1", None)));
+	events.publish(Event::TransferStored(transfer("other", "acc2", None)));
+	events.publish(Event::BlockIngested {
+		slot: 100,
+		block_time: Some(1720000000),
+		transaction_count: 1,
+	});
+	let id = webhook.id;
+	let deliveries = tokio::time::timeout(Duration::from_secs(5), async {
+		loop {
+			let deliveries =
+				with_connection(&pool, move |conn| webhooks::get_deliveries(conn, id, None, 10))
+					.await
+					.unwrap()
+					.unwrap();
+			if !deliveries.is_empty() {
+				break deliveries
+			}
+			tokio::time::sleep(Duration::from_millis(20)).await;
+		}
+	})
+	.await
+	.unwrap();
+	assert_eq!(deliveries.len(), 1);
+	assert_eq!(deliveries[0].event_id, "tx1:0");
+	// A replayed transfer is not delivered twice
+	let replayed = with_connection(&pool, |conn| {
+		let watched = webhooks::load_watched_addresses(conn)?;
+		let transfers = [transfer("payer", "acc1", None)];
+		webhooks::enqueue_deliveries(conn, &transfer_deliveries(&transfers, &watched), 1720000000)
+	})
+	.await
+	.unwrap()
+	.unwrap();
+	assert_eq!(replayed, 0);
+
+	// The failed attempt is scheduled again later
+	assert_eq!(dispatcher.deliver_due().await.unwrap(), 1);
+	let delivery_id = deliveries[0].id;
+	let delivery = with_connection(&pool, move |conn| webhooks::get_delivery(conn, delivery_id))
+		.await
+		.unwrap()
+		.unwrap()
+		.unwrap();
+	assert_eq!((delivery.status, delivery.attempts), (DeliveryStatus::Pending, 1));
+	assert_eq!(delivery.last_error.as_deref(), Some("HTTP status 500 Internal Server Error"));
+	assert!(delivery.next_attempt_at.unwrap() > delivery.last_attempt_at.unwrap());
+	assert_eq!(dispatcher.deliver_due().await.unwrap(), 0);
+
+	// Redelivered right away, the next attempt is acknowledged
+	let now = chrono::Utc::now().timestamp();
+	with_connection(&pool, move |conn| webhooks::redeliver(conn, id, delivery_id, now))
+		.await
+		.unwrap()
+		.unwrap()
+		.unwrap();
+	assert_eq!(dispatcher.deliver_due().await.unwrap(), 1);
+	let delivery = with_connection(&pool, move |conn| webhooks::get_delivery(conn, delivery_id))
+		.await
+		.unwrap()
+		.unwrap()
+		.unwrap();
+	assert_eq!((delivery.status, delivery.attempts), (DeliveryStatus::Delivered, 2));
+	assert_eq!(delivery.next_attempt_at, None);
+
+	let received = received.lock().unwrap();
+	assert_eq!(received.len(), 2);
+	assert_eq!(received[1].0, "tx1:0");
+	assert_eq!((received[1].1.webhook_id, received[1].1.transfer.receiver.as_str()), (id, "acc1"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_delivery_worker_retries_failed_deliveries_until_max_attempts() {
+	// An endpoint failing every request
+	let requests = Arc::new(Mutex::new(0));
+	let app = Router::new().route(
+		"/hook",
+		post({
+			let requests = Arc::clone(&requests);
+			move || {
+				*requests.lock().unwrap() += 1;
+				async { StatusCode::SERVICE_UNAVAILABLE }
+			}
+		}),
+	);
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}/hook", listener.local_addr().unwrap());
+	tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(app.into_make_service()));
+
+	let pool = memory_pool().await;
+	let delivery_id = with_connection(&pool, move |conn| {
+		webhooks::create_webhook(conn, &url, &["payer".to_string()], 1720000000)?;
+		let watched = webhooks::load_watched_addresses(conn)?;
+		let transfers = [transfer("payer", "acc1", None)];
+		webhooks::enqueue_deliveries(conn, &transfer_deliveries(&transfers, &watched), 1720000000)?;
+		Ok::<_, Box<dyn std::error::Error + Send + Sync>>(
+			webhooks::get_due_deliveries(conn, 1720000000, 1)?[0].id,
+		)
+	})
+	.await
+	.unwrap()
+	.unwrap();
+	let get_delivery = || {
+		let pool = pool.clone();
+		async move {
+			with_connection(&pool, move |conn| webhooks::get_delivery(conn, delivery_id))
+				.await
+				.unwrap()
+				.unwrap()
+				.unwrap()
+		}
+	};
+
+	// Each failed attempt is scheduled again, until the last one
+	let dispatcher = WebhookDispatcher::new(pool.clone());
+	for attempt in 1..=webhooks::MAX_ATTEMPTS {
+		assert_eq!(dispatcher.deliver_due().await.unwrap(), 1);
+		let delivery = get_delivery().await;
+		assert_eq!(delivery.attempts, attempt);
+		if attempt < webhooks::MAX_ATTEMPTS {
+			assert_eq!(delivery.status, DeliveryStatus::Pending);
+			// The retry delay elapses
+			with_connection(&pool, move |conn| {
+				conn.execute(
+					"UPDATE deliveries SET next_attempt_at = 0 WHERE id = ?1",
+					[delivery_id],
+				)
+			})
+			.await
+			.unwrap()
+			.unwrap();
+		}
+	}
+
+	// The delivery is given up on and no longer attempted
+	let delivery = get_delivery().await;
+	assert_eq!((delivery.status, delivery.next_attempt_at), (DeliveryStatus::Failed, None));
+	assert_eq!(delivery.last_error.as_deref(), Some("HTTP status 503 Service Unavailable"));
+	assert_eq!(dispatcher.deliver_due().await.unwrap(), 0);
+	assert_eq!(*requests.lock().unwrap(), webhooks::MAX_ATTEMPTS);
+}
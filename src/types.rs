@@ -1,8 +1,17 @@
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::fs;
+use serde_json::Value;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::{
+	collections::{HashMap, HashSet},
+	fmt, fs,
+	str::FromStr,
+	sync::Arc,
+};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct EpochInfo {
+	pub epoch: u64,
 	pub absolute_slot: u64,
 	pub slot_index: u64,
 	pub slots_in_epoch: u64,
@@ -14,6 +23,105 @@ pub struct TransactionDetails {
 	pub receiver: String,
 	pub amount: u64,
 	pub timestamp: Option<i64>,
+	#[serde(default)]
+	pub kind: TransferKind,
+	/// The wallet owning the sender, if the sender is a token account.
+	#[serde(default)]
+	pub sender_owner: Option<String>,
+	/// The wallet owning the receiver, if the receiver is a token account.
+	#[serde(default)]
+	pub receiver_owner: Option<String>,
+	/// The program that made the transfer through a cross-program invocation, `None` for a top
+	/// level instruction.
+	#[serde(default)]
+	pub invoked_by: Option<String>,
+	/// The tags of the tagging rules the transfer meets, given by the writer before it is stored.
+	#[serde(default)]
+	pub tags: Vec<String>,
+}
+
+/// Classification of a lamport movement between two accounts.
+///
+/// Plain transfers are payments, while rent deposits (funding a newly created account) and account
+/// closures (reclaiming the lamports of a closed account) are operational flows that should not be
+/// mistaken for payments when reconciling balances.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferKind {
+	#[default]
+	Transfer,
+	RentDeposit,
+	AccountClose,
+}
+
+impl TransferKind {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			TransferKind::Transfer => "transfer",
+			TransferKind::RentDeposit => "rent_deposit",
+			TransferKind::AccountClose => "account_close",
+		}
+	}
+}
+
+impl FromStr for TransferKind {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"transfer" => Ok(TransferKind::Transfer),
+			"rent_deposit" => Ok(TransferKind::RentDeposit),
+			"account_close" => Ok(TransferKind::AccountClose),
+			other => Err(format!("Unknown transfer kind: {}", other)),
+		}
+	}
+}
+
+/// The error a failed transaction ended with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionFailure {
+	/// The program of the failing instruction, if the error was raised by an instruction.
+	pub program_id: Option<String>,
+	/// The error code, e.g. `Custom(6001)` or `InsufficientFundsForFee`.
+	pub error: String,
+}
+
+/// The lamport balance change of an account within a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceChange {
+	pub account: String,
+	pub pre_balance: u64,
+	pub post_balance: u64,
+}
+
+/// A top level instruction the service does not decode, as the program id, the accounts passed to
+/// it and its base58 encoded data, or the JSON of the RPC node for the programs it parses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgramInstruction {
+	/// The position of the instruction among the top level instructions of the transaction.
+	pub index: u32,
+	pub program_id: String,
+	/// The accounts of the instruction, in instruction order, empty if the node parsed it.
+	pub accounts: Vec<String>,
+	/// The base58 encoded data, empty if the node parsed the instruction.
+	pub data: String,
+	/// The `parsed` JSON of the node, verbatim, if it parsed the instruction.
+	pub parsed: Option<Value>,
+}
+
+/// A token swap performed by the fee payer of a transaction, derived from its balance changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Swap {
+	/// The account that signed and paid for the swap.
+	pub trader: String,
+	/// The owner of the pool vaults the trader swapped against.
+	pub pool: String,
+	/// The mint sold by the trader.
+	pub mint_in: String,
+	pub amount_in: u128,
+	/// The mint bought by the trader.
+	pub mint_out: String,
+	pub amount_out: u128,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,24 +131,897 @@ pub struct TransferInfo {
 	pub lamports: u64,
 }
 
+/// Info of a system program `createAccount`/`createAccountWithSeed` instruction.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateAccountInfo {
+	pub source: String,
+	pub new_account: String,
+	pub lamports: u64,
+}
+
+/// Info of a token program `closeAccount` instruction.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CloseAccountInfo {
+	pub account: String,
+	pub destination: String,
+	/// The owner of the closed account, absent when it is closed by a multisig.
+	pub owner: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ParsedInstruction {
-	pub info: TransferInfo,
+pub struct ParsedInstruction<T = TransferInfo> {
+	pub info: T,
 	#[serde(rename = "type")]
 	pub instruction_type: String,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
 	pub rpc_url: String,
+	/// PubSub endpoint of the RPC node, derived from `rpc_url` by default.
+	pub pubsub_url: Option<String>,
 	pub retry_attempts: u8,
+	/// The main address of the API, with the syntax of the `[[listeners]]` addresses.
 	pub server_address: String,
+	/// Additional addresses the API listens on, written `[[listeners]]`.
+	#[serde(default)]
+	pub listeners: Vec<ListenerConfig>,
+	/// Path of the SQLite database file.
+	#[serde(default = "default_db_path")]
+	pub db_path: String,
+	/// The slot ingestion starts from.
+	#[serde(default)]
+	pub start_from: StartFrom,
+	/// Rate limit, timeout and failover settings of the RPC gateway.
+	#[serde(default)]
+	pub rpc: RpcConfig,
+	/// The commitment level the epoch information and the blocks are read at.
+	#[serde(default)]
+	pub commitment: Commitment,
+	/// Keep following the tip once the current epoch is ingested, instead of exiting.
+	#[serde(default)]
+	pub continuous: bool,
+	/// Receive the new blocks through a `blockSubscribe` subscription in continuous mode, polling
+	/// only while the subscription is down.
+	#[serde(default)]
+	pub block_subscription: bool,
+	/// Delay between two polls of the tip in continuous mode, in milliseconds.
+	#[serde(default = "default_poll_interval_ms")]
+	pub poll_interval_ms: u64,
+	/// Format of the logs: free text, or one JSON object per line for log aggregation systems.
+	#[serde(default)]
+	pub log_format: LogFormat,
+	/// Duration above which database queries are logged, in milliseconds.
+	#[serde(default = "default_slow_query_ms")]
+	pub slow_query_ms: u64,
+	/// Maximum number of database connections shared by the API handlers and background tasks.
+	#[serde(default = "default_db_pool_size")]
+	pub db_pool_size: u32,
+	/// Maximum number of rows a listing endpoint returns in a single response.
+	#[serde(default = "default_max_rows_per_request")]
+	pub max_rows_per_request: usize,
+	/// How long a read asking for a `min_slot` waits for the ingestion to reach it, in
+	/// milliseconds.
+	#[serde(default = "default_max_slot_wait_ms")]
+	pub max_slot_wait_ms: u64,
+	/// How long a handler gets to respond, in milliseconds, before the request is answered with a
+	/// `SERVICE_UNAVAILABLE` status and its database query interrupted; unbounded by default.
+	pub request_timeout_ms: Option<u64>,
+	/// Unit of the lamport amounts returned by the transfer and balance endpoints, overridden by
+	/// their `unit` query parameter.
+	#[serde(default)]
+	pub amount_unit: AmountUnit,
+	/// Which of the accounts touched by a transaction get an account row.
+	#[serde(default)]
+	pub account_discovery: AccountDiscovery,
+	/// How much of the raw transactions is stored, for privacy-sensitive deployments.
+	#[serde(default)]
+	pub raw_transactions: RawTransactionPolicy,
+	/// Fetch the transactions `/transaction` does not find from the RPC node, storing them; only
+	/// when the service ingests blocks.
+	#[serde(default)]
+	pub transaction_passthrough: bool,
+	/// Addresses of interest; when set, only the transactions touching one of them are stored.
+	#[serde(default)]
+	pub watched_addresses: Vec<WatchedAddress>,
+	/// Programs of interest; when set, only the transactions with a top level instruction of one of
+	/// them are stored.
+	#[serde(default)]
+	pub index_programs: Vec<String>,
+	/// Which parts of the service run, overridden by the `--serve-only` and `--ingest-only` flags.
+	#[serde(default)]
+	pub mode: RunMode,
+	/// Address of the health check listener in ingest-only mode, none by default.
+	pub health_address: Option<String>,
+	/// Optional archival of the raw blocks to S3-compatible storage.
+	pub archive: Option<ArchiveConfig>,
+	/// Optional tiering of old transactions into a cold SQLite file.
+	pub tiering: Option<TieringConfig>,
+	/// Optional on-disk cache of the raw blocks fetched from the RPC node.
+	pub block_cache: Option<BlockCacheConfig>,
+	/// Optional enrichment of the discovered accounts with their on-chain metadata.
+	pub account_info: Option<AccountInfoConfig>,
+	/// Optional detection of spikes in the transfer volume of accounts and the failure rate of
+	/// programs.
+	pub anomaly_detection: Option<AnomalyConfig>,
+	/// Optional quota on the space taken by the database.
+	pub storage_quota: Option<StorageQuotaConfig>,
+	/// Optional serving of cached reads while the ingestion falls behind.
+	pub degraded_mode: Option<DegradedModeConfig>,
+	/// Optional publication of the stored transactions to a Kafka topic or a NATS subject.
+	pub message_bus: Option<MessageBusConfig>,
+	/// Path of a Solana keypair file signing the API responses, which are unsigned by default.
+	pub signing_keypair: Option<String>,
+	/// Exports of stored transfers run on a schedule, written `[[exports]]`.
+	#[serde(default)]
+	pub exports: Vec<ExportConfig>,
+	/// Programs whose accounts are snapshotted periodically, written `[[account_snapshots]]`.
+	#[serde(default)]
+	pub account_snapshots: Vec<AccountSnapshotConfig>,
+	/// Rules tagging the transfers as they are stored, written `[[tagging_rules]]`.
+	#[serde(default)]
+	pub tagging_rules: Vec<TaggingRule>,
+	/// Keys required by the API, written `[[api_keys]]`; the API is open without any.
+	#[serde(default)]
+	pub api_keys: Vec<ApiKeyConfig>,
+}
+
+/// An address the API listens on.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ListenerConfig {
+	/// A TCP socket address, IPv4 (`0.0.0.0:3030`) or IPv6 (`[::]:3030`), or the path of a Unix
+	/// domain socket prefixed with `unix:`.
+	pub address: String,
+	/// Set `SO_REUSEPORT` on a TCP listener, so several processes can bind the same address.
+	#[serde(default)]
+	pub reuse_port: bool,
+	/// Permissions of a Unix domain socket, e.g. `0o660`; those of the umask by default.
+	pub socket_mode: Option<u32>,
+}
+
+impl ListenerConfig {
+	/// A listener on an address, with the default options.
+	pub fn new(address: &str) -> Self {
+		Self { address: address.to_string(), reuse_port: false, socket_mode: None }
+	}
+}
+
+/// Configuration of the RPC gateway shared by every subsystem.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RpcConfig {
+	/// Endpoints to fail over to, in order, when `rpc_url` keeps failing.
+	#[serde(default)]
+	pub fallback_urls: Vec<String>,
+	/// Maximum sustained request rate, unlimited by default. Also accepted as `max_rps`.
+	#[serde(alias = "max_rps")]
+	pub requests_per_second: Option<f64>,
+	/// Number of requests that can be sent at once, defaults to `requests_per_second`.
+	pub burst: Option<f64>,
+	/// Timeout of a single request, in seconds.
+	#[serde(default = "default_rpc_timeout")]
+	pub timeout_secs: u64,
+	/// Number of blocks fetched per JSON-RPC batch request during ingestion, one (no batching) by
+	/// default.
+	#[serde(default = "default_batch_size")]
+	pub batch_size: usize,
+}
+
+impl Default for RpcConfig {
+	fn default() -> Self {
+		Self {
+			fallback_urls: Vec::new(),
+			requests_per_second: None,
+			burst: None,
+			timeout_secs: default_rpc_timeout(),
+			batch_size: default_batch_size(),
+		}
+	}
+}
+
+fn default_rpc_timeout() -> u64 {
+	30
+}
+
+fn default_batch_size() -> usize {
+	1
+}
+
+fn default_poll_interval_ms() -> u64 {
+	1000
+}
+
+fn default_slow_query_ms() -> u64 {
+	250
+}
+
+fn default_db_pool_size() -> u32 {
+	8
+}
+
+/// Row cap of the listing endpoints when none is configured.
+pub const DEFAULT_MAX_ROWS_PER_REQUEST: usize = 100_000;
+
+fn default_db_path() -> String {
+	"solana.db".to_string()
+}
+
+fn default_max_rows_per_request() -> usize {
+	DEFAULT_MAX_ROWS_PER_REQUEST
+}
+
+fn default_max_slot_wait_ms() -> u64 {
+	10_000
+}
+
+/// Where the ingestion starts; it runs up to the end of the current epoch, or keeps following the
+/// chain in continuous mode.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartFrom {
+	/// The current slot, to tail the chain without indexing the past.
+	Latest,
+	/// The first slot of the current epoch.
+	EpochStart,
+	/// The slot after the last slot processed by a previous run (recorded in `sync_state`), or
+	/// the epoch start on an empty database.
+	#[default]
+	Checkpoint,
+	/// A given slot, written `{ slot = N }`.
+	Slot(u64),
+}
+
+/// The commitment level of the ingestion, trading the latency of the indexed blocks for the risk
+/// of indexing a block the cluster later drops.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Commitment {
+	/// The blocks processed by the node, read at `confirmed` since `getBlock` does not serve
+	/// processed ones.
+	Processed,
+	/// The blocks voted on by a supermajority of the cluster.
+	Confirmed,
+	/// The blocks rooted by a supermajority of the cluster, which are never rolled back.
+	#[default]
+	Finalized,
+}
+
+impl Commitment {
+	/// Returns the commitment of the epoch information.
+	pub fn config(self) -> CommitmentConfig {
+		match self {
+			Commitment::Processed => CommitmentConfig::processed(),
+			Commitment::Confirmed => CommitmentConfig::confirmed(),
+			Commitment::Finalized => CommitmentConfig::finalized(),
+		}
+	}
+
+	/// Returns the commitment of the blocks and of the tip they are fetched up to.
+	pub fn block_config(self) -> CommitmentConfig {
+		match self {
+			Commitment::Processed | Commitment::Confirmed => CommitmentConfig::confirmed(),
+			Commitment::Finalized => CommitmentConfig::finalized(),
+		}
+	}
+}
+
+/// Which of the accounts touched by a transaction get an account row, trading index size for
+/// completeness.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountDiscovery {
+	/// Every account of the message, including programs and sysvars.
+	All,
+	/// The writable accounts of the message.
+	Writable,
+	/// The signers of the message.
+	Signers,
+	/// The sender and receiver of parsed transfers.
+	#[default]
+	TransferParties,
+}
+
+/// The format of the logs written to the standard output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+	/// Human readable lines, the fields of the events following their message.
+	#[default]
+	Text,
+	/// One JSON object per event, with its fields and the spans it happened in.
+	Json,
+}
+
+/// The unit of the lamport amounts of the API responses.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AmountUnit {
+	/// Integer lamports.
+	#[default]
+	Lamports,
+	/// Exact decimal strings of SOL.
+	Sol,
+}
+
+/// How much of a raw transaction is stored; the records parsed from it are kept either way.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RawTransactionPolicy {
+	/// The encoded transaction as received.
+	#[default]
+	Full,
+	/// The encoded transaction with the content of its memo instructions removed.
+	StripMemos,
+	/// Only the SHA-256 hash of the encoded transaction.
+	HashOnly,
+}
+
+/// What the database writer keeps of the ingested transactions.
+#[derive(Debug, Default, Clone)]
+pub struct StoragePolicy {
+	/// Which of the accounts touched by a transaction get an account row.
+	pub account_discovery: AccountDiscovery,
+	/// How much of the raw transaction is stored.
+	pub raw_transactions: RawTransactionPolicy,
+	/// The rules tagging the transfers, in the order of the configuration.
+	pub tagging_rules: Arc<Vec<TaggingRule>>,
+	/// The addresses a transaction must touch to be stored; every transaction is stored when empty.
+	pub watched_addresses: Arc<HashSet<String>>,
+	/// The programs a transaction must invoke to be stored; every transaction is stored when empty.
+	pub index_programs: Arc<HashSet<String>>,
+}
+
+/// An address of interest of the ingestion, checked to be a valid pubkey when the configuration is
+/// read.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String")]
+pub struct WatchedAddress(pub String);
+
+impl TryFrom<String> for WatchedAddress {
+	type Error = String;
+
+	fn try_from(address: String) -> Result<Self, Self::Error> {
+		match Pubkey::from_str(&address) {
+			Ok(_) => Ok(WatchedAddress(address)),
+			Err(_) => Err(format!("Invalid watched address: {}", address)),
+		}
+	}
+}
+
+/// A rule giving its tag to the transfers meeting all of its conditions, written
+/// `[[tagging_rules]]`. A rule without conditions tags every transfer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TaggingRule {
+	/// The tag given to the matching transfers, e.g. `payroll`.
+	pub tag: String,
+	/// Smallest amount of a matching transfer, in lamports.
+	pub min_amount: Option<u64>,
+	/// Largest amount of a matching transfer, in lamports.
+	pub max_amount: Option<u64>,
+	pub kind: Option<TransferKind>,
+	/// A label, or a tag of the label, of the sender or of the wallet owning it.
+	pub sender_label: Option<String>,
+	/// A label, or a tag of the label, of the receiver or of the wallet owning it.
+	pub receiver_label: Option<String>,
+	/// A regular expression one of the memos of the transaction must match.
+	pub memo: Option<MemoPattern>,
+}
+
+/// A regular expression matched against the memos, compiled when the configuration is read.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct MemoPattern(pub Regex);
+
+impl TryFrom<String> for MemoPattern {
+	type Error = regex::Error;
+
+	fn try_from(pattern: String) -> Result<Self, Self::Error> {
+		Regex::new(&pattern).map(MemoPattern)
+	}
+}
+
+impl From<MemoPattern> for String {
+	fn from(pattern: MemoPattern) -> Self {
+		pattern.0.as_str().to_string()
+	}
+}
+
+/// An API key, with the accounts it sees.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiKeyConfig {
+	/// Name of the key, e.g. the team using it.
+	pub name: String,
+	/// The secret sent by the clients.
+	pub key: String,
+	/// Groups whose members the key sees.
+	#[serde(default)]
+	pub groups: Vec<String>,
+	/// Label tags of the accounts the key sees. A key without groups or label tags sees every
+	/// account.
+	#[serde(default)]
+	pub label_tags: Vec<String>,
+}
+
+/// The parts of the service a process runs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunMode {
+	/// Ingest blocks and serve the API.
+	#[default]
+	Full,
+	/// Serve the API over an existing database, without ingesting blocks.
+	ServeOnly,
+	/// Ingest blocks without serving the API, exposing at most a health check.
+	IngestOnly,
+}
+
+/// Configuration of the cold-storage tiering job.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TieringConfig {
+	/// Path of the cold SQLite file.
+	pub cold_path: String,
+	/// Age after which transactions are moved to cold storage, e.g. `30d`.
+	pub max_age: String,
+	/// Time between two tiering runs, e.g. `1h`.
+	#[serde(default = "default_tiering_interval")]
+	pub interval: String,
+}
+
+fn default_tiering_interval() -> String {
+	"1h".to_string()
+}
+
+/// Configuration of the account metadata enrichment job.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AccountInfoConfig {
+	/// Number of accounts looked up per `getMultipleAccounts` call, at most 100.
+	#[serde(default = "default_account_info_batch_size")]
+	pub batch_size: usize,
+	/// Pause between two passes once every discovered account is enriched, e.g. `10s`.
+	#[serde(default = "default_account_info_interval")]
+	pub interval: String,
+}
+
+fn default_account_info_batch_size() -> usize {
+	100
+}
+
+fn default_account_info_interval() -> String {
+	"10s".to_string()
+}
+
+/// Configuration of the periodic snapshots of the accounts of a program.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AccountSnapshotConfig {
+	/// The program whose accounts are snapshotted.
+	pub program_id: String,
+	/// Pause between two snapshots of the accounts, e.g. `1h`.
+	#[serde(default = "default_account_snapshot_interval")]
+	pub interval: String,
+	/// Path of the Anchor IDL of the program, to store the decoded fields of the accounts.
+	pub idl: Option<String>,
+}
+
+fn default_account_snapshot_interval() -> String {
+	"1h".to_string()
+}
+
+/// Configuration of the storage quota.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StorageQuotaConfig {
+	/// Maximum number of bytes of data in the database.
+	pub max_bytes: u64,
+	/// What happens once the data exceeds the quota.
+	#[serde(default)]
+	pub on_exceeded: QuotaAction,
+	/// Delay between two measures of the database, in seconds.
+	#[serde(default = "default_quota_check_interval_secs")]
+	pub check_interval_secs: u64,
+}
+
+fn default_quota_check_interval_secs() -> u64 {
+	60
+}
+
+/// Configuration of the degraded mode of the API.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DegradedModeConfig {
+	/// Number of commands queued for the database writer above which the service is degraded.
+	#[serde(default = "default_degraded_max_queued_writes")]
+	pub max_queued_writes: usize,
+	/// Age of the last stored block, in seconds, above which the service is degraded.
+	#[serde(default = "default_degraded_max_lag_secs")]
+	pub max_lag_secs: u64,
+	/// Time a read gets to answer, in milliseconds, before a cached response is served instead.
+	#[serde(default = "default_degraded_latency_budget_ms")]
+	pub latency_budget_ms: u64,
+	/// Age of the oldest cached response served, in seconds.
+	#[serde(default = "default_degraded_max_staleness_secs")]
+	pub max_staleness_secs: u64,
+	/// Maximum number of responses cached.
+	#[serde(default = "default_degraded_cache_entries")]
+	pub cache_entries: usize,
+}
+
+fn default_degraded_max_queued_writes() -> usize {
+	48
+}
+
+fn default_degraded_max_lag_secs() -> u64 {
+	120
+}
+
+fn default_degraded_latency_budget_ms() -> u64 {
+	1000
+}
+
+fn default_degraded_max_staleness_secs() -> u64 {
+	300
+}
+
+fn default_degraded_cache_entries() -> usize {
+	1000
+}
+
+/// The message bus the stored transactions are published to.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageBusConfig {
+	/// A Kafka topic, written `kafka = { brokers = "...", topic = "..." }`.
+	Kafka(KafkaConfig),
+	/// A NATS subject, written `nats = { url = "...", subject = "..." }`.
+	Nats(NatsConfig),
+}
+
+/// Configuration of the Kafka producer publishing the stored transactions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KafkaConfig {
+	/// Comma separated `host:port` addresses of the bootstrap brokers.
+	pub brokers: String,
+	pub topic: String,
+	/// Additional librdkafka producer properties, e.g. `"security.protocol" = "SASL_SSL"`.
+	#[serde(default)]
+	pub properties: HashMap<String, String>,
+}
+
+/// Configuration of the NATS client publishing the stored transactions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NatsConfig {
+	/// The server URL, e.g. `nats://localhost:4222` (`tls://` for TLS), with credentials if the
+	/// server requires them.
+	pub url: String,
+	pub subject: String,
+}
+
+/// What happens once the database exceeds its storage quota.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+	/// Pause the ingestion until space is freed.
+	#[default]
+	Pause,
+	/// Delete the transactions of the oldest slots, pausing the ingestion if nothing is left.
+	Prune,
+}
+
+/// Configuration of the anomaly detection.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnomalyConfig {
+	/// Number of consecutive slots aggregated into a sample, about a minute by default.
+	#[serde(default = "default_anomaly_bucket_slots")]
+	pub bucket_slots: u64,
+	/// Number of previous samples a sample is compared with.
+	#[serde(default = "default_anomaly_window")]
+	pub window: usize,
+	/// Number of standard deviations above the mean from which a sample is an anomaly.
+	#[serde(default = "default_anomaly_threshold")]
+	pub threshold: f64,
+	/// Number of previous samples a subject needs before its samples are judged.
+	#[serde(default = "default_anomaly_min_samples")]
+	pub min_samples: usize,
+	/// Maximum number of accounts and programs tracked at once.
+	#[serde(default = "default_anomaly_max_subjects")]
+	pub max_subjects: usize,
+}
+
+impl Default for AnomalyConfig {
+	fn default() -> Self {
+		Self {
+			bucket_slots: default_anomaly_bucket_slots(),
+			window: default_anomaly_window(),
+			threshold: default_anomaly_threshold(),
+			min_samples: default_anomaly_min_samples(),
+			max_subjects: default_anomaly_max_subjects(),
+		}
+	}
+}
+
+fn default_anomaly_bucket_slots() -> u64 {
+	150
+}
+
+fn default_anomaly_window() -> usize {
+	60
+}
+
+fn default_anomaly_threshold() -> f64 {
+	4.0
+}
+
+fn default_anomaly_min_samples() -> usize {
+	10
+}
+
+fn default_anomaly_max_subjects() -> usize {
+	10_000
+}
+
+/// Configuration of the on-disk cache of raw blocks.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockCacheConfig {
+	/// Directory holding the cached blocks.
+	pub path: String,
+	/// Size above which the oldest blocks are evicted, in megabytes.
+	#[serde(default = "default_block_cache_max_size_mb")]
+	pub max_size_mb: u64,
+	/// Age after which a cached block is evicted, e.g. `7d`; blocks do not expire by default.
+	pub ttl: Option<String>,
+}
+
+fn default_block_cache_max_size_mb() -> u64 {
+	10 * 1024
+}
+
+/// Configuration of the raw block archival to S3-compatible object storage.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ArchiveConfig {
+	/// The bucket the blocks are written to.
+	pub bucket: String,
+	/// The S3-compatible endpoint, e.g. `https://s3.us-east-1.amazonaws.com`.
+	pub endpoint: String,
+	#[serde(default = "default_archive_region")]
+	pub region: String,
+	/// A prefix prepended to every object key.
+	#[serde(default)]
+	pub prefix: String,
+	#[serde(default)]
+	pub compression: ArchiveCompression,
+	#[serde(default)]
+	pub layout: ArchiveLayout,
+	/// Access key, defaults to the `AWS_ACCESS_KEY_ID` environment variable.
+	pub access_key_id: Option<String>,
+	/// Secret key, defaults to the `AWS_SECRET_ACCESS_KEY` environment variable.
+	pub secret_access_key: Option<String>,
+}
+
+impl fmt::Debug for ArchiveConfig {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("ArchiveConfig")
+			.field("bucket", &self.bucket)
+			.field("endpoint", &self.endpoint)
+			.field("region", &self.region)
+			.field("prefix", &self.prefix)
+			.field("compression", &self.compression)
+			.field("layout", &self.layout)
+			.field("access_key_id", &self.access_key_id)
+			.field("secret_access_key", &self.secret_access_key.as_ref().map(|_| "<redacted>"))
+			.finish()
+	}
+}
+
+fn default_archive_region() -> String {
+	"us-east-1".to_string()
+}
+
+/// Compression applied to archived blocks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveCompression {
+	None,
+	#[default]
+	Gzip,
+}
+
+/// Layout of the archived block keys under the prefix.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveLayout {
+	/// `<prefix>/<slot>.json[.gz]`
+	Flat,
+	/// `<prefix>/<slot rounded down to 100k>/<slot>.json[.gz]`, keeping listings small.
+	#[default]
+	Partitioned,
+}
+
+/// Configuration of a scheduled export of transfers to CSV.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportConfig {
+	/// The unique name of the export, naming its files and its runs in the history.
+	pub name: String,
+	/// When the export runs, as a five-field cron expression in UTC, e.g. `0 2 * * *`.
+	pub schedule: String,
+	/// The transfers exported.
+	pub filter: ExportFilter,
+	/// Only export the transfers of the given window before each run, e.g. `1d`; all the stored
+	/// transfers by default.
+	pub window: Option<String>,
+	/// Where the CSV files are written.
+	pub destination: ExportDestination,
+}
+
+/// The saved filter of a scheduled export.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFilter {
+	/// The transfers of an account, written `{ account = "..." }`.
+	Account(String),
+	/// The transfers of any member of a group, written `{ group = "..." }`.
+	Group(String),
+}
+
+/// Where the files of a scheduled export are written.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportDestination {
+	/// A local directory, written `{ directory = "..." }`.
+	Directory(String),
+	/// A bucket of S3-compatible storage, written `{ s3 = { bucket = "...", ... } }`.
+	S3(ExportS3Config),
+}
+
+/// Configuration of the S3-compatible bucket receiving the files of an export.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExportS3Config {
+	pub bucket: String,
+	/// The S3-compatible endpoint, e.g. `https://s3.us-east-1.amazonaws.com`.
+	pub endpoint: String,
+	#[serde(default = "default_archive_region")]
+	pub region: String,
+	/// A prefix prepended to every object key.
+	#[serde(default)]
+	pub prefix: String,
+	/// Access key, defaults to the `AWS_ACCESS_KEY_ID` environment variable.
+	pub access_key_id: Option<String>,
+	/// Secret key, defaults to the `AWS_SECRET_ACCESS_KEY` environment variable.
+	pub secret_access_key: Option<String>,
+}
+
+impl fmt::Debug for ExportS3Config {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("ExportS3Config")
+			.field("bucket", &self.bucket)
+			.field("endpoint", &self.endpoint)
+			.field("region", &self.region)
+			.field("prefix", &self.prefix)
+			.field("access_key_id", &self.access_key_id)
+			.field("secret_access_key", &self.secret_access_key.as_ref().map(|_| "<redacted>"))
+			.finish()
+	}
+}
+
+/// Prefix of the environment variables setting the configuration.
+const ENV_PREFIX: &str = "AGG_";
+
+/// The most environment values read as another type than a string that may fall back to strings,
+/// bounding the combinations tried.
+const MAX_STRING_FALLBACKS: usize = 8;
+
+/// Reads the value of an environment variable as a TOML value, or as a string if it is not one.
+fn parse_env_value(value: &str) -> toml::Value {
+	toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+		.ok()
+		.and_then(|mut table| table.remove("value"))
+		.unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+/// Sets a setting at a path of fields, creating the sections on the way.
+fn set_setting(
+	settings: &mut toml::value::Table,
+	path: &[String],
+	value: toml::Value,
+) -> Result<(), String> {
+	match path {
+		[] => Err("no setting named".to_string()),
+		[field] => {
+			settings.insert(field.clone(), value);
+			Ok(())
+		},
+		[section, rest @ ..] => match settings
+			.entry(section.clone())
+			.or_insert_with(|| toml::Value::Table(toml::value::Table::new()))
+		{
+			toml::Value::Table(section) => set_setting(section, rest, value),
+			_ => Err(format!("{} is not a section", section)),
+		},
+	}
 }
 
 impl Config {
-	pub fn from_file(file_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-		let config_content = fs::read_to_string(file_path)?;
-		let config: Config = toml::from_str(&config_content)?;
-		Ok(config)
+	/// Reads the configuration from a file, with the settings of the environment variables taking
+	/// precedence over it, and the overrides over both.
+	///
+	/// Each `AGG_` variable sets the field named by the rest of its name in lower case, with `__`
+	/// separating the fields of a section: `AGG_RPC_URL` sets `rpc_url`, and
+	/// `AGG_RPC__TIMEOUT_SECS` sets `timeout_secs` in the `[rpc]` section. Values are read as TOML
+	/// values, e.g. `5`, `true` or `["a", "b"]`, and as strings otherwise or when their setting is
+	/// a string, e.g. `AGG_DB_PATH=2024`.
+	///
+	/// # Arguments
+	///
+	/// * `file_path` - The path of the configuration file.
+	/// * `required` - Whether the file must exist, or the configuration may come from the
+	///   environment alone.
+	/// * `vars` - The environment variables.
+	/// * `overrides` - Settings taking precedence over the file and the environment, e.g. those of
+	///   the command line.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the file cannot be read or parsed, or if a required
+	/// setting is missing or has an invalid value.
+	pub fn load(
+		file_path: &str,
+		required: bool,
+		vars: impl IntoIterator<Item = (String, String)>,
+		overrides: toml::value::Table,
+	) -> Result<Self, Box<dyn std::error::Error>> {
+		let mut settings = match fs::read_to_string(file_path) {
+			Ok(content) => toml::from_str(&content)?,
+			Err(err) if !required && err.kind() == std::io::ErrorKind::NotFound =>
+				toml::value::Table::new(),
+			Err(err) => return Err(format!("Failed to read {}: {}", file_path, err).into()),
+		};
+		// The values read as another type than a string, which may be meant as strings
+		let mut typed = Vec::new();
+		for (name, value) in vars {
+			let Some(key) = name.strip_prefix(ENV_PREFIX) else { continue };
+			let path: Vec<String> = key.to_lowercase().split("__").map(str::to_string).collect();
+			let parsed = parse_env_value(&value);
+			if !parsed.is_str() && typed.len() < MAX_STRING_FALLBACKS {
+				typed.push((path.clone(), value));
+			}
+			set_setting(&mut settings, &path, parsed)
+				.map_err(|err| format!("Invalid {}: {}", name, err))?;
+		}
+
+		// Fall back to reading the fewest of them as strings, e.g. `AGG_DB_PATH=2024`, when the
+		// configuration does not fit their types
+		let mut fallbacks: Vec<u32> = (0..1 << typed.len()).collect();
+		fallbacks.sort_by_key(|fallback| fallback.count_ones());
+		let mut typed_err = None;
+		for fallback in fallbacks {
+			let mut attempt = settings.clone();
+			for (index, (path, value)) in typed.iter().enumerate() {
+				if fallback & (1 << index) != 0 {
+					set_setting(&mut attempt, path, toml::Value::String(value.clone()))?;
+				}
+			}
+			attempt.extend(overrides.clone());
+			match toml::Value::Table(attempt).try_into() {
+				Ok(config) => return Ok(config),
+				Err(err) => typed_err = typed_err.or(Some(err)),
+			}
+		}
+		Err(typed_err.expect("the values are tried with their types first").into())
+	}
+
+	/// Returns the listeners of the API: `server_address` followed by the `[[listeners]]`.
+	pub fn api_listeners(&self) -> Vec<ListenerConfig> {
+		let mut listeners = vec![ListenerConfig::new(&self.server_address)];
+		listeners.extend(self.listeners.iter().cloned());
+		listeners
+	}
+
+	/// Returns the storage policy of the database writer.
+	pub fn storage_policy(&self) -> StoragePolicy {
+		StoragePolicy {
+			account_discovery: self.account_discovery,
+			raw_transactions: self.raw_transactions,
+			tagging_rules: Arc::new(self.tagging_rules.clone()),
+			watched_addresses: Arc::new(
+				self.watched_addresses.iter().map(|address| address.0.clone()).collect(),
+			),
+			index_programs: Arc::new(self.index_programs.iter().cloned().collect()),
+		}
 	}
 }
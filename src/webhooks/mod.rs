@@ -0,0 +1,247 @@
+//! Webhook notifications of the transfers touching watched addresses.
+//!
+//! Webhooks are registered through the API with a URL and the addresses they watch. The dispatcher
+//! follows the event bus and, once a block is committed, records a delivery for every transfer of
+//! the block whose sender or receiver, or the wallet owning either, is watched by a webhook. A
+//! worker then posts the due deliveries to their webhook, one JSON notification per transfer, and
+//! records the outcome of each attempt: a 2xx status acknowledges the delivery, anything else
+//! schedules another attempt with the backoff of `db::webhooks`. Deliveries are at least once: the
+//! `X-Webhook-Delivery` header carries the id of the delivery and `X-Webhook-Event` the event it
+//! notifies, so an endpoint can recognize a notification it already processed.
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, error::Error, sync::Arc, time::Duration};
+use tokio::sync::{
+	broadcast::{error::RecvError, Receiver},
+	Notify,
+};
+use tracing::{error, warn};
+
+use crate::{
+	db::{
+		pool::{with_connection, DbPool},
+		webhooks::{self, DeliveryRecord, NewDelivery, WatchedAddresses},
+		TransferRecord,
+	},
+	events::Event,
+	server::dto::TransferResponse,
+};
+
+/// Timeout of a single notification request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause between two scans of the deliveries for due ones, unless new deliveries are recorded.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum number of deliveries attempted per scan.
+const BATCH_SIZE: usize = 64;
+
+/// Header carrying the id of the delivery.
+pub const DELIVERY_HEADER: &str = "x-webhook-delivery";
+
+/// Header carrying the id of the notified event.
+pub const EVENT_HEADER: &str = "x-webhook-event";
+
+/// The body posted to a webhook for a transfer touching its addresses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferNotification {
+	/// The id of the event, `<signature>:<transfer index>`.
+	pub event_id: String,
+	pub webhook_id: i64,
+	/// The watched addresses the transfer touches.
+	pub addresses: Vec<String>,
+	/// The transfer, in the format of `/transfers`.
+	pub transfer: TransferResponse,
+}
+
+/// Returns the id of the event of a transfer.
+pub fn transfer_event_id(transfer: &TransferRecord) -> String {
+	format!("{}:{}", transfer.transaction_id, transfer.transfer_index)
+}
+
+/// Returns the deliveries of transfers to the webhooks watching the addresses they touch.
+///
+/// # Arguments
+///
+/// * `transfers` - The stored transfers.
+/// * `watched` - The watched addresses, each with the id and URL of the webhooks watching it.
+pub fn transfer_deliveries(
+	transfers: &[TransferRecord],
+	watched: &WatchedAddresses,
+) -> Vec<NewDelivery> {
+	let mut deliveries = Vec::new();
+	for transfer in transfers {
+		let parties = [
+			Some(&transfer.sender),
+			Some(&transfer.receiver),
+			transfer.sender_owner.as_ref(),
+			transfer.receiver_owner.as_ref(),
+		];
+		// The addresses each webhook watches among the parties of the transfer
+		let mut matches: Vec<(i64, &String, BTreeSet<&String>)> = Vec::new();
+		for address in parties.into_iter().flatten() {
+			for (webhook_id, url) in watched.get(address).into_iter().flatten() {
+				match matches.iter_mut().find(|(id, ..)| id == webhook_id) {
+					Some((.., addresses)) => {
+						addresses.insert(address);
+					},
+					None => matches.push((*webhook_id, url, BTreeSet::from([address]))),
+				}
+			}
+		}
+		for (webhook_id, url, addresses) in matches {
+			let notification = TransferNotification {
+				event_id: transfer_event_id(transfer),
+				webhook_id,
+				addresses: addresses.into_iter().cloned().collect(),
+				transfer: transfer.clone().into(),
+			};
+			deliveries.push(NewDelivery {
+				webhook_id,
+				event_id: notification.event_id.clone(),
+				target: url.clone(),
+				payload: serde_json::to_value(&notification).unwrap_or_default(),
+			});
+		}
+	}
+	deliveries
+}
+
+/// Records and sends the notifications of the webhooks.
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+	pool: DbPool,
+	http: reqwest::Client,
+	/// Wakes the delivery worker up once new deliveries are recorded.
+	recorded: Arc<Notify>,
+}
+
+impl WebhookDispatcher {
+	/// Creates a dispatcher recording its deliveries in the database.
+	pub fn new(pool: DbPool) -> Self {
+		let http = reqwest::Client::builder()
+			.timeout(REQUEST_TIMEOUT)
+			.build()
+			.expect("valid HTTP client configuration");
+		Self { pool, http, recorded: Arc::new(Notify::new()) }
+	}
+
+	/// Records the deliveries of the transfers stored from now on, until the event bus is closed.
+	///
+	/// The transfers of a block are matched against the webhooks once its `BlockIngested` event
+	/// arrives, so a webhook registered while the block is written is notified of the whole block.
+	pub async fn record_deliveries(self, mut events: Receiver<Event>) {
+		let mut transfers = Vec::new();
+		loop {
+			match events.recv().await {
+				Ok(Event::TransferStored(transfer)) => transfers.push(transfer),
+				Ok(Event::BlockIngested { slot, .. }) => {
+					let block_transfers = std::mem::take(&mut transfers);
+					if let Err(err) = self.enqueue(block_transfers).await {
+						error!(
+							"Failed to record the webhook deliveries of slot {}: {:?}",
+							slot, err
+						);
+					}
+				},
+				Ok(_) => {},
+				Err(RecvError::Lagged(skipped)) => {
+					warn!("Webhook dispatcher lagged, {} events skipped", skipped);
+				},
+				Err(RecvError::Closed) => break,
+			}
+		}
+	}
+
+	/// Keeps sending the due deliveries, scanning them every `POLL_INTERVAL` or as soon as new ones
+	/// are recorded.
+	pub async fn run_deliveries(self) {
+		loop {
+			match self.deliver_due().await {
+				Ok(attempted) if attempted >= BATCH_SIZE => continue,
+				Ok(_) => {},
+				Err(err) => error!("Failed to send the webhook deliveries: {:?}", err),
+			}
+			let _ = tokio::time::timeout(POLL_INTERVAL, self.recorded.notified()).await;
+		}
+	}
+
+	/// Records the deliveries of the transfers of a block.
+	async fn enqueue(
+		&self,
+		transfers: Vec<TransferRecord>,
+	) -> Result<(), Box<dyn Error + Send + Sync>> {
+		if transfers.is_empty() {
+			return Ok(())
+		}
+		let now = chrono::Utc::now().timestamp();
+		let recorded = with_connection(&self.pool, move |conn| {
+			let watched = webhooks::load_watched_addresses(conn)?;
+			if watched.is_empty() {
+				return Ok(0)
+			}
+			webhooks::enqueue_deliveries(conn, &transfer_deliveries(&transfers, &watched), now)
+		})
+		.await??;
+		if recorded > 0 {
+			self.recorded.notify_one();
+		}
+		Ok(())
+	}
+
+	/// Sends the deliveries whose next attempt is due.
+	///
+	/// # Returns
+	///
+	/// This function returns the number of deliveries attempted.
+	///
+	/// # Errors
+	///
+	/// This function returns an error if the deliveries cannot be read or updated.
+	pub async fn deliver_due(&self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+		let now = chrono::Utc::now().timestamp();
+		let due = with_connection(&self.pool, move |conn| {
+			webhooks::get_due_deliveries(conn, now, BATCH_SIZE)
+		})
+		.await??;
+		let attempted = due.len();
+		for delivery in due {
+			let error = self.send(&delivery).await.err().map(|err| err.to_string());
+			let attempted_at = chrono::Utc::now().timestamp();
+			let id = delivery.id;
+			let outcome = error.clone();
+			let record = with_connection(&self.pool, move |conn| {
+				webhooks::record_attempt(conn, id, outcome.as_deref(), attempted_at)
+			})
+			.await??;
+			match (error, record) {
+				(Some(error), Some(record)) if record.next_attempt_at.is_none() => warn!(
+					"Giving up on the delivery {} of {} to webhook {} after {} attempts: {}",
+					id, delivery.event_id, delivery.webhook_id, record.attempts, error
+				),
+				(Some(error), _) => warn!(
+					"Delivery {} of {} to webhook {} failed: {}",
+					id, delivery.event_id, delivery.webhook_id, error
+				),
+				(None, _) => {},
+			}
+		}
+		Ok(attempted)
+	}
+
+	/// Posts a delivery to its webhook.
+	async fn send(&self, delivery: &DeliveryRecord) -> Result<(), Box<dyn Error + Send + Sync>> {
+		let response = self
+			.http
+			.post(&delivery.target)
+			.header(DELIVERY_HEADER, delivery.id.to_string())
+			.header(EVENT_HEADER, &delivery.event_id)
+			.header(reqwest::header::CONTENT_TYPE, "application/json")
+			.body(delivery.payload.to_string())
+			.send()
+			.await?;
+		if !response.status().is_success() {
+			return Err(format!("HTTP status {}", response.status()).into())
+		}
+		Ok(())
+	}
+}